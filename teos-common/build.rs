@@ -4,7 +4,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .type_attribute("AppointmentData.appointment_data", "#[serde(untagged)]")
         .field_attribute("AppointmentData.appointment_data", "#[serde(flatten)]")
         .field_attribute("appointment_data", "#[serde(rename = \"appointment\")]")
+        .type_attribute("AddAppointmentResult.result", "#[serde(untagged)]")
+        .field_attribute("AddAppointmentResult.result", "#[serde(flatten)]")
         .field_attribute("user_id", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("tower_id", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("old_tower_id", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("new_tower_id", "#[serde(with = \"hex::serde\")]")
         .field_attribute("locator", "#[serde(with = \"hex::serde\")]")
         .field_attribute(
             "locators",
@@ -22,6 +27,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[
                 "proto/common/teos/v2/appointment.proto",
                 "proto/common/teos/v2/user.proto",
+                "proto/common/teos/v2/tower.proto",
             ],
             &["proto/common/teos/v2"],
         )?;
@@ -0,0 +1,261 @@
+//! Announcements issued by towers so users can learn about them before registering.
+
+use std::convert::TryFrom;
+
+use serde::Serialize;
+
+use bitcoin::secp256k1::SecretKey;
+
+use crate::constants::{ENCRYPTED_BLOB_MAX_SIZE, PROTOCOL_VERSION};
+use crate::protos as msgs;
+use crate::{cryptography, TowerId};
+
+/// Public information about a tower, signed by the tower itself.
+///
+/// Announcements let users check whether a tower is compatible with them (protocol version, supported features,
+/// appointment constraints and subscription terms) before registering. Since they are signed, they can also be
+/// relayed by third parties without the user having to trust them.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct TowerAnnouncement {
+    tower_id: TowerId,
+    protocol_version: u32,
+    addresses: Vec<String>,
+    features: Vec<String>,
+    min_to_self_delay: u32,
+    max_blob_size: u32,
+    subscription_slots: u32,
+    subscription_duration: u32,
+    signature: Option<String>,
+}
+
+impl TowerAnnouncement {
+    /// Creates a new (unsigned) announcement for the current protocol version.
+    pub fn new(
+        tower_id: TowerId,
+        addresses: Vec<String>,
+        features: Vec<String>,
+        min_to_self_delay: u32,
+        subscription_slots: u32,
+        subscription_duration: u32,
+    ) -> Self {
+        TowerAnnouncement {
+            tower_id,
+            protocol_version: PROTOCOL_VERSION,
+            addresses,
+            features,
+            min_to_self_delay,
+            max_blob_size: ENCRYPTED_BLOB_MAX_SIZE as u32,
+            subscription_slots,
+            subscription_duration,
+            signature: None,
+        }
+    }
+
+    pub fn tower_id(&self) -> TowerId {
+        self.tower_id
+    }
+
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    pub fn addresses(&self) -> &[String] {
+        &self.addresses
+    }
+
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    pub fn min_to_self_delay(&self) -> u32 {
+        self.min_to_self_delay
+    }
+
+    pub fn max_blob_size(&self) -> u32 {
+        self.max_blob_size
+    }
+
+    pub fn subscription_slots(&self) -> u32 {
+        self.subscription_slots
+    }
+
+    pub fn subscription_duration(&self) -> u32 {
+        self.subscription_duration
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    /// Whether the tower supports a given feature.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Whether the announcement was issued for the protocol version this crate implements.
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = Vec::new();
+        ser.extend_from_slice(&self.tower_id.to_vec());
+        ser.extend_from_slice(&self.protocol_version.to_be_bytes());
+        // Lists and strings are length prefixed so different announcements cannot serialize to the same data.
+        for list in [&self.addresses, &self.features] {
+            ser.extend_from_slice(&(list.len() as u32).to_be_bytes());
+            for item in list.iter() {
+                ser.extend_from_slice(&(item.len() as u32).to_be_bytes());
+                ser.extend_from_slice(item.as_bytes());
+            }
+        }
+        ser.extend_from_slice(&self.min_to_self_delay.to_be_bytes());
+        ser.extend_from_slice(&self.max_blob_size.to_be_bytes());
+        ser.extend_from_slice(&self.subscription_slots.to_be_bytes());
+        ser.extend_from_slice(&self.subscription_duration.to_be_bytes());
+
+        ser
+    }
+
+    pub fn sign(&mut self, sk: &SecretKey) {
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    /// Verifies the announcement was signed by the tower it claims to come from.
+    pub fn verify(&self) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &self.tower_id.0)
+        } else {
+            false
+        }
+    }
+}
+
+impl TryFrom<msgs::TowerAnnouncement> for TowerAnnouncement {
+    type Error = String;
+
+    fn try_from(x: msgs::TowerAnnouncement) -> Result<Self, Self::Error> {
+        let tower_id = TowerId::from_slice(&x.tower_id)
+            .map_err(|_| "Tower id does not match expected format".to_owned())?;
+        let signature = if x.signature.is_empty() {
+            None
+        } else {
+            Some(x.signature)
+        };
+
+        Ok(TowerAnnouncement {
+            tower_id,
+            protocol_version: x.protocol_version,
+            addresses: x.addresses,
+            features: x.features,
+            min_to_self_delay: x.min_to_self_delay,
+            max_blob_size: x.max_blob_size,
+            subscription_slots: x.subscription_slots,
+            subscription_duration: x.subscription_duration,
+            signature,
+        })
+    }
+}
+
+impl From<TowerAnnouncement> for msgs::TowerAnnouncement {
+    fn from(a: TowerAnnouncement) -> Self {
+        Self {
+            tower_id: a.tower_id.to_vec(),
+            protocol_version: a.protocol_version,
+            addresses: a.addresses,
+            features: a.features,
+            min_to_self_delay: a.min_to_self_delay,
+            max_blob_size: a.max_blob_size,
+            subscription_slots: a.subscription_slots,
+            subscription_duration: a.subscription_duration,
+            signature: a.signature.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_signed_announcement() -> (TowerAnnouncement, SecretKey) {
+        let (sk, pk) = cryptography::get_random_keypair();
+        let mut announcement = TowerAnnouncement::new(
+            TowerId(pk),
+            vec!["localhost:9814".to_owned()],
+            vec!["add_appointments".to_owned()],
+            20,
+            10000,
+            4320,
+        );
+        announcement.sign(&sk);
+
+        (announcement, sk)
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let (announcement, _) = get_signed_announcement();
+        assert!(announcement.verify());
+        assert!(announcement.is_compatible());
+        assert!(announcement.supports("add_appointments"));
+        assert!(!announcement.supports("delete_appointment"));
+
+        // Unsigned announcements do not verify
+        let mut unsigned = announcement.clone();
+        unsigned.signature = None;
+        assert!(!unsigned.verify());
+
+        // Neither do announcements that have been tampered with
+        let mut tampered = announcement.clone();
+        tampered.subscription_slots += 1;
+        assert!(!tampered.verify());
+
+        // Nor announcements signed by someone else
+        let (sk, _) = cryptography::get_random_keypair();
+        let mut impersonated = announcement;
+        impersonated.sign(&sk);
+        assert!(!impersonated.verify());
+    }
+
+    #[test]
+    fn test_to_vec_is_unambiguous() {
+        let (announcement, sk) = get_signed_announcement();
+
+        // Moving data between lists must change the serialization
+        let mut moved = announcement.clone();
+        moved.addresses = vec![];
+        moved.features = vec!["localhost:9814".to_owned(), "add_appointments".to_owned()];
+        assert_ne!(announcement.to_vec(), moved.to_vec());
+
+        let mut split = announcement.clone();
+        split.addresses = vec!["localhost".to_owned(), ":9814".to_owned()];
+        assert_ne!(announcement.to_vec(), split.to_vec());
+
+        // Signatures are deterministic
+        let mut resigned = announcement.clone();
+        resigned.sign(&sk);
+        assert_eq!(announcement, resigned);
+    }
+
+    #[test]
+    fn test_proto_roundtrip() {
+        let (announcement, _) = get_signed_announcement();
+        let proto = msgs::TowerAnnouncement::from(announcement.clone());
+        assert_eq!(TowerAnnouncement::try_from(proto).unwrap(), announcement);
+
+        // An empty signature maps to no signature at all
+        let proto = msgs::TowerAnnouncement {
+            signature: String::new(),
+            ..announcement.clone().into()
+        };
+        let unsigned = TowerAnnouncement::try_from(proto).unwrap();
+        assert_eq!(unsigned.signature(), None);
+
+        // Wrong tower ids are rejected
+        let proto = msgs::TowerAnnouncement {
+            tower_id: vec![0; 33],
+            ..announcement.into()
+        };
+        assert!(TowerAnnouncement::try_from(proto).is_err());
+    }
+}
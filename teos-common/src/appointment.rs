@@ -75,6 +75,7 @@ pub enum AppointmentStatus {
     NotFound = 0,
     BeingWatched = 1,
     DisputeResponded = 2,
+    Resolved = 3,
 }
 
 impl From<i32> for AppointmentStatus {
@@ -82,6 +83,7 @@ impl From<i32> for AppointmentStatus {
         match x {
             1 => AppointmentStatus::BeingWatched,
             2 => AppointmentStatus::DisputeResponded,
+            3 => AppointmentStatus::Resolved,
             _ => AppointmentStatus::NotFound,
         }
     }
@@ -94,6 +96,7 @@ impl std::str::FromStr for AppointmentStatus {
         match s {
             "being_watched" => Ok(AppointmentStatus::BeingWatched),
             "dispute_responded" => Ok(AppointmentStatus::DisputeResponded),
+            "resolved" => Ok(AppointmentStatus::Resolved),
             "not_found" => Ok(AppointmentStatus::NotFound),
             _ => Err(format!("Unknown status: {s}")),
         }
@@ -105,6 +108,7 @@ impl fmt::Display for AppointmentStatus {
         let s = match self {
             AppointmentStatus::BeingWatched => "being_watched",
             AppointmentStatus::DisputeResponded => "dispute_responded",
+            AppointmentStatus::Resolved => "resolved",
             AppointmentStatus::NotFound => "not_found",
         };
         write!(f, "{s}")
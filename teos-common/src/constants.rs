@@ -4,6 +4,9 @@
 /// Number of blocks required to consider a transaction irrevocable.
 pub const IRREVOCABLY_RESOLVED: u32 = 100;
 
+/// Version of the protocol spoken between users and towers. Announced by towers so users can check compatibility.
+pub const PROTOCOL_VERSION: u32 = 2;
+
 // Temporary constants, may be changed
 /// Maximum size of encrypted blobs in appointments.
 pub const ENCRYPTED_BLOB_MAX_SIZE: usize = 2048;
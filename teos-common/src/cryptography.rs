@@ -1,9 +1,11 @@
 //! Cryptography module, used in the interaction between users and towers.
 
+use std::fmt;
+
 use rand::distributions::Uniform;
 use rand::Rng;
 
-use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 
 use bitcoin::consensus;
@@ -12,6 +14,18 @@ use bitcoin::secp256k1::{Error, PublicKey, Secp256k1, SecretKey};
 use bitcoin::{Transaction, Txid};
 use lightning::util::message_signing;
 
+/// Prefix of the secret keys encrypted with a passphrase.
+const ENCRYPTED_KEY_PREFIX: &str = "scrypt:";
+/// Cost parameter (log2) of the scrypt key derivation used to encrypt secret keys. Stored along with the key.
+#[cfg(not(test))]
+const KEY_SCRYPT_LOG_N: u8 = 15;
+#[cfg(test)]
+const KEY_SCRYPT_LOG_N: u8 = 4;
+/// Highest scrypt cost accepted when decrypting, so a tampered key cannot make the key derivation take forever.
+pub const MAX_SCRYPT_LOG_N: u8 = 20;
+const KEY_SALT_LEN: usize = 16;
+const KEY_NONCE_LEN: usize = 12;
+
 /// Enum representing the possible errors when decrypting an encrypted blob.
 #[derive(Debug)]
 pub enum DecryptingError {
@@ -19,7 +33,30 @@ pub enum DecryptingError {
     Encode(bitcoin::consensus::encode::Error),
 }
 
+/// Enum representing the possible errors when decrypting a secret key encrypted with a passphrase.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyDecryptionError {
+    MissingPassphrase,
+    WrongPassphrase,
+    InvalidKey,
+}
+
+impl fmt::Display for KeyDecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyDecryptionError::MissingPassphrase => {
+                write!(f, "The key is encrypted. A passphrase is required")
+            }
+            KeyDecryptionError::WrongPassphrase => write!(f, "Wrong passphrase"),
+            KeyDecryptionError::InvalidKey => write!(f, "The stored key is malformed"),
+        }
+    }
+}
+
 /// Shadows [message_signing::sign].
+///
+/// Signing only fails for digests that are not 32 bytes long, and the signed digest is always a sha256d hash, so this
+/// can be safely unwrapped.
 pub fn sign(msg: &[u8], sk: &SecretKey) -> Result<String, Error> {
     message_signing::sign(msg, sk)
 }
@@ -75,6 +112,93 @@ pub fn decrypt(encrypted_blob: &[u8], secret: &Txid) -> Result<Transaction, Decr
     }
 }
 
+/// Derives an encryption key from a passphrase using scrypt, with a cost of `2^log_n`.
+pub fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    log_n: u8,
+) -> Result<[u8; 32], scrypt::errors::InvalidParams> {
+    let params = scrypt::Params::new(log_n, 8, 1)?;
+    let mut key = [0; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key).unwrap();
+    Ok(key)
+}
+
+/// Encrypts a secret key under a passphrase using `chacha20poly1305`, so it can be stored at rest.
+///
+/// The output is the hex encoded scrypt cost, salt, nonce and ciphertext, prefixed so encrypted keys can be told apart
+/// from plain ones.
+pub fn encrypt_secret_key(sk: &SecretKey, passphrase: &str) -> String {
+    let mut data = vec![KEY_SCRYPT_LOG_N];
+    data.extend(get_random_bytes(KEY_SALT_LEN));
+    let key = derive_key(passphrase, &data[1..], KEY_SCRYPT_LOG_N).unwrap();
+    let nonce = get_random_bytes(KEY_NONCE_LEN);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &sk.secret_bytes(),
+                aad: &data,
+            },
+        )
+        .unwrap();
+    data.extend(nonce);
+    data.extend(ciphertext);
+
+    format!("{ENCRYPTED_KEY_PREFIX}{}", hex::encode(data))
+}
+
+/// Encodes a secret key so it can be stored at rest. The key is encrypted if a passphrase is given, and hex encoded
+/// otherwise.
+pub fn encode_secret_key(sk: &SecretKey, passphrase: Option<&str>) -> String {
+    match passphrase {
+        Some(passphrase) => encrypt_secret_key(sk, passphrase),
+        None => sk.display_secret().to_string(),
+    }
+}
+
+/// Checks whether a stored secret key is encrypted.
+pub fn is_encrypted_secret_key(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_KEY_PREFIX)
+}
+
+/// Decodes a stored secret key, decrypting it if it was encrypted using [encrypt_secret_key].
+///
+/// Keys that are not encrypted (hex encoded) are loaded as they are, no matter the passphrase.
+pub fn decode_secret_key(
+    stored: &str,
+    passphrase: Option<&str>,
+) -> Result<SecretKey, KeyDecryptionError> {
+    let data = match stored.strip_prefix(ENCRYPTED_KEY_PREFIX) {
+        Some(data) => hex::decode(data).map_err(|_| KeyDecryptionError::InvalidKey)?,
+        None => {
+            return stored
+                .parse::<SecretKey>()
+                .map_err(|_| KeyDecryptionError::InvalidKey)
+        }
+    };
+    let passphrase = passphrase.ok_or(KeyDecryptionError::MissingPassphrase)?;
+
+    let header_len = 1 + KEY_SALT_LEN;
+    if data.len() < header_len + KEY_NONCE_LEN || data[0] > MAX_SCRYPT_LOG_N {
+        return Err(KeyDecryptionError::InvalidKey);
+    }
+    let key = derive_key(passphrase, &data[1..header_len], data[0])
+        .map_err(|_| KeyDecryptionError::InvalidKey)?;
+    let sk = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(
+            Nonce::from_slice(&data[header_len..header_len + KEY_NONCE_LEN]),
+            Payload {
+                msg: &data[header_len + KEY_NONCE_LEN..],
+                aad: &data[..header_len],
+            },
+        )
+        .map_err(|_| KeyDecryptionError::WrongPassphrase)?;
+
+    SecretKey::from_slice(&sk).map_err(|_| KeyDecryptionError::InvalidKey)
+}
+
 /// Utility function to create a vector of pseudo random bytes.
 ///
 /// Mainly used for testing purposes.
@@ -125,4 +249,57 @@ mod tests {
         let txid = Txid::from_hex(HEX_TXID).unwrap();
         assert_eq!(decrypt(&encrypted_blob, &txid).unwrap(), expected_tx);
     }
+
+    #[test]
+    fn test_encrypt_decode_secret_key() {
+        let (sk, _) = get_random_keypair();
+        let passphrase = "correct horse battery staple";
+
+        let encrypted = encrypt_secret_key(&sk, passphrase);
+        assert!(is_encrypted_secret_key(&encrypted));
+        assert!(!encrypted.contains(&sk.display_secret().to_string()));
+        assert_eq!(decode_secret_key(&encrypted, Some(passphrase)), Ok(sk));
+
+        // Encrypting twice gives different outputs (random salt and nonce)
+        assert_ne!(encrypt_secret_key(&sk, passphrase), encrypted);
+
+        assert_eq!(
+            decode_secret_key(&encrypted, None),
+            Err(KeyDecryptionError::MissingPassphrase)
+        );
+        assert_eq!(
+            decode_secret_key(&encrypted, Some("wrong passphrase")),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+
+        // Tampering with the stored data (including the key derivation parameters) is detected
+        let mut tampered =
+            hex::decode(encrypted.strip_prefix(ENCRYPTED_KEY_PREFIX).unwrap()).unwrap();
+        tampered[0] += 1;
+        assert_eq!(
+            decode_secret_key(
+                &format!("{ENCRYPTED_KEY_PREFIX}{}", hex::encode(&tampered)),
+                Some(passphrase)
+            ),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+        assert_eq!(
+            decode_secret_key(&encrypted[..encrypted.len() / 4], Some(passphrase)),
+            Err(KeyDecryptionError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn test_decrypt_plain_secret_key() {
+        // Keys that are not encrypted are loaded no matter the passphrase
+        let (sk, _) = get_random_keypair();
+        let stored = encode_secret_key(&sk, None);
+        assert!(!is_encrypted_secret_key(&stored));
+        assert_eq!(decode_secret_key(&stored, None), Ok(sk));
+        assert_eq!(decode_secret_key(&stored, Some("passphrase")), Ok(sk));
+        assert_eq!(
+            decode_secret_key("not a key", None),
+            Err(KeyDecryptionError::InvalidKey)
+        );
+    }
 }
@@ -2,9 +2,18 @@
 //! that can be used by both clients and towers.
 //!
 
-use rusqlite::ffi::{SQLITE_CONSTRAINT_FOREIGNKEY, SQLITE_CONSTRAINT_PRIMARYKEY};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use rusqlite::ffi::{self, SQLITE_CONSTRAINT_FOREIGNKEY, SQLITE_CONSTRAINT_PRIMARYKEY};
+#[cfg(feature = "sqlcipher")]
+use rusqlite::params;
 use rusqlite::{Connection, Error as SqliteError, ErrorCode, Params};
 
+/// The header every plain text SQLite database starts with. Encrypted databases are indistinguishable from random data.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
 /// Packs the errors than can raise when interacting with the underlying database.
 #[derive(Debug)]
 pub enum Error {
@@ -70,3 +79,122 @@ impl<T: DatabaseConnection> DatabaseManager for T {
         self.remove_data(query, params)
     }
 }
+
+/// Opens a connection to the database at `db_path`, unlocking it with `db_key` if given.
+///
+/// Fails if the database cannot be read with the given key (or without one if it is encrypted), or if a key is given
+/// but database encryption is not supported (that is, the `sqlcipher` feature is not enabled).
+pub fn open_connection(db_path: &Path, db_key: Option<&str>) -> Result<Connection, SqliteError> {
+    let connection = Connection::open(db_path)?;
+    if let Some(db_key) = db_key {
+        if !cfg!(feature = "sqlcipher") {
+            return Err(SqliteError::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_MISUSE),
+                Some("database encryption requires the sqlcipher feature".to_owned()),
+            ));
+        }
+        connection.pragma_update(None, "key", db_key)?;
+    }
+    // The key is not checked until the database is read, so read the schema to fail early if it is wrong
+    connection.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+
+    Ok(connection)
+}
+
+/// Checks whether the database at `db_path` is encrypted. Missing and empty databases are not.
+pub fn is_encrypted_database(db_path: &Path) -> bool {
+    let mut header = [0; SQLITE_HEADER.len()];
+    match File::open(db_path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(_) => &header != SQLITE_HEADER,
+        Err(_) => false,
+    }
+}
+
+/// Exports the database at `src` (unlocked with `src_key`, if any) to a new database at `dst`, encrypted with `dst_key`.
+///
+/// Exporting without a `dst_key` decrypts the database, and exporting with both keys re-encrypts it. `dst` must not
+/// exist already.
+#[cfg(feature = "sqlcipher")]
+pub fn export_database(
+    src: &Path,
+    src_key: Option<&str>,
+    dst: &Path,
+    dst_key: Option<&str>,
+) -> Result<(), SqliteError> {
+    if dst.exists() {
+        return Err(SqliteError::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_CANTOPEN),
+            Some(format!("{} already exists", dst.display())),
+        ));
+    }
+
+    let connection = open_connection(src, src_key)?;
+    connection.execute(
+        "ATTACH DATABASE ?1 AS export KEY ?2",
+        params![dst.to_string_lossy(), dst_key.unwrap_or_default()],
+    )?;
+    connection.query_row("SELECT sqlcipher_export('export')", [], |_| Ok(()))?;
+    connection.execute("DETACH DATABASE export", [])?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_is_encrypted_database() {
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("db.sql3");
+        assert!(!is_encrypted_database(&db_path));
+
+        let connection = open_connection(&db_path, None).unwrap();
+        assert!(!is_encrypted_database(&db_path));
+        connection
+            .execute("CREATE TABLE data (value INTEGER)", [])
+            .unwrap();
+        assert!(!is_encrypted_database(&db_path));
+
+        std::fs::write(&db_path, [42; 32]).unwrap();
+        assert!(is_encrypted_database(&db_path));
+        assert!(open_connection(&db_path, None).is_err());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_export_database() {
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let plain_path = tmp_dir.path().join("plain.sql3");
+        let encrypted_path = tmp_dir.path().join("encrypted.sql3");
+        let decrypted_path = tmp_dir.path().join("decrypted.sql3");
+        let load_value = |path: &Path, key: Option<&str>| -> Result<u32, SqliteError> {
+            open_connection(path, key)?.query_row("SELECT value FROM data", [], |row| row.get(0))
+        };
+
+        let connection = open_connection(&plain_path, None).unwrap();
+        connection
+            .execute("CREATE TABLE data (value INTEGER)", [])
+            .unwrap();
+        connection
+            .execute("INSERT INTO data (value) VALUES (42)", [])
+            .unwrap();
+
+        // Encrypt the database
+        export_database(&plain_path, None, &encrypted_path, Some("key")).unwrap();
+        assert!(is_encrypted_database(&encrypted_path));
+        assert!(load_value(&encrypted_path, None).is_err());
+        assert!(load_value(&encrypted_path, Some("wrong key")).is_err());
+        assert_eq!(load_value(&encrypted_path, Some("key")).unwrap(), 42);
+
+        // Existing databases are never overwritten
+        assert!(export_database(&plain_path, None, &encrypted_path, Some("key")).is_err());
+
+        // And decrypt it back
+        assert!(export_database(&encrypted_path, None, &decrypted_path, None).is_err());
+        export_database(&encrypted_path, Some("key"), &decrypted_path, None).unwrap();
+        assert!(!is_encrypted_database(&decrypted_path));
+        assert_eq!(load_value(&decrypted_path, None).unwrap(), 42);
+    }
+}
@@ -0,0 +1,294 @@
+//! Key handoffs issued by towers when rotating their keys.
+
+use std::convert::TryFrom;
+
+use serde::Serialize;
+
+use bitcoin::secp256k1::SecretKey;
+
+use crate::protos as msgs;
+use crate::{cryptography, TowerId};
+
+/// Prefix of the serialized handoffs, so their signatures cannot be mistaken for signatures of any other data.
+const HANDOFF_TAG: &[u8] = b"teos:key_handoff";
+
+/// Statement signed by a tower key handing the tower identity over to a new key.
+///
+/// Receipts issued from `activation_height` onwards are signed with the new key, whereas the ones issued before are
+/// signed with the old one. Receipts issued at `activation_height` may have been signed by either of them, given
+/// the tower may have issued some before rotating its key.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct KeyHandoff {
+    old_tower_id: TowerId,
+    new_tower_id: TowerId,
+    activation_height: u32,
+    signature: Option<String>,
+}
+
+impl KeyHandoff {
+    /// Creates a new (unsigned) handoff.
+    pub fn new(old_tower_id: TowerId, new_tower_id: TowerId, activation_height: u32) -> Self {
+        KeyHandoff {
+            old_tower_id,
+            new_tower_id,
+            activation_height,
+            signature: None,
+        }
+    }
+
+    /// Creates a handoff with a given signature.
+    pub fn with_signature(
+        old_tower_id: TowerId,
+        new_tower_id: TowerId,
+        activation_height: u32,
+        signature: String,
+    ) -> Self {
+        KeyHandoff {
+            old_tower_id,
+            new_tower_id,
+            activation_height,
+            signature: Some(signature),
+        }
+    }
+
+    pub fn old_tower_id(&self) -> TowerId {
+        self.old_tower_id
+    }
+
+    pub fn new_tower_id(&self) -> TowerId {
+        self.new_tower_id
+    }
+
+    pub fn activation_height(&self) -> u32 {
+        self.activation_height
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = HANDOFF_TAG.to_vec();
+        ser.extend_from_slice(&self.old_tower_id.to_vec());
+        ser.extend_from_slice(&self.new_tower_id.to_vec());
+        ser.extend_from_slice(&self.activation_height.to_be_bytes());
+
+        ser
+    }
+
+    /// Signs the handoff. `sk` must be the secret key matching the old tower id.
+    pub fn sign(&mut self, sk: &SecretKey) {
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    /// Verifies the handoff was signed by the key being handed over.
+    pub fn verify(&self) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &self.old_tower_id.0)
+        } else {
+            false
+        }
+    }
+}
+
+impl TryFrom<msgs::KeyHandoff> for KeyHandoff {
+    type Error = String;
+
+    fn try_from(x: msgs::KeyHandoff) -> Result<Self, Self::Error> {
+        let old_tower_id = TowerId::from_slice(&x.old_tower_id)
+            .map_err(|_| "Old tower id does not match expected format".to_owned())?;
+        let new_tower_id = TowerId::from_slice(&x.new_tower_id)
+            .map_err(|_| "New tower id does not match expected format".to_owned())?;
+        let signature = if x.signature.is_empty() {
+            None
+        } else {
+            Some(x.signature)
+        };
+
+        Ok(KeyHandoff {
+            old_tower_id,
+            new_tower_id,
+            activation_height: x.activation_height,
+            signature,
+        })
+    }
+}
+
+impl From<KeyHandoff> for msgs::KeyHandoff {
+    fn from(h: KeyHandoff) -> Self {
+        Self {
+            old_tower_id: h.old_tower_id.to_vec(),
+            new_tower_id: h.new_tower_id.to_vec(),
+            activation_height: h.activation_height,
+            signature: h.signature.unwrap_or_default(),
+        }
+    }
+}
+
+/// Follows the handoffs of a tower starting at `tower_id`, returning the ones leading to its latest key (oldest first).
+///
+/// Only properly signed handoffs are followed, and their activation heights must not go backwards. Any handoff not
+/// in the chain starting at `tower_id` is ignored.
+pub fn follow(tower_id: TowerId, handoffs: &[KeyHandoff]) -> Vec<KeyHandoff> {
+    let mut chain: Vec<KeyHandoff> = Vec::new();
+    let mut current = tower_id;
+
+    // Every handoff can be followed at most once, so cycles are not an issue.
+    while chain.len() < handoffs.len() {
+        let min_height = chain.last().map_or(0, |h| h.activation_height);
+        match handoffs.iter().find(|h| {
+            h.old_tower_id == current
+                && h.activation_height >= min_height
+                && h.verify()
+                && !chain.contains(h)
+        }) {
+            Some(handoff) => {
+                current = handoff.new_tower_id;
+                chain.push(handoff.clone());
+            }
+            None => break,
+        }
+    }
+
+    chain
+}
+
+/// Checks whether `signer` was a valid key of the tower at a given height.
+///
+/// `tower_id` is the current id of the tower and `chain` the handoffs that lead to it (oldest first), as returned by
+/// [follow]. Receipts issued at a given height must be signed by a key that was valid at that height.
+pub fn is_valid_signer(
+    tower_id: TowerId,
+    chain: &[KeyHandoff],
+    height: u32,
+    signer: TowerId,
+) -> bool {
+    let mut valid_from = 0;
+    for handoff in chain {
+        if handoff.old_tower_id == signer
+            && valid_from <= height
+            && height <= handoff.activation_height
+        {
+            return true;
+        }
+        valid_from = handoff.activation_height;
+    }
+
+    signer == tower_id && height >= valid_from
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates a chain of signed handoffs (with the given activation heights), returning them along with all the ids.
+    fn get_handoff_chain(heights: &[u32]) -> (Vec<KeyHandoff>, Vec<TowerId>) {
+        let (mut sk, pk) = cryptography::get_random_keypair();
+        let mut ids = vec![TowerId(pk)];
+        let mut handoffs = Vec::new();
+        for height in heights {
+            let (new_sk, new_pk) = cryptography::get_random_keypair();
+            let mut handoff = KeyHandoff::new(*ids.last().unwrap(), TowerId(new_pk), *height);
+            handoff.sign(&sk);
+            handoffs.push(handoff);
+            ids.push(TowerId(new_pk));
+            sk = new_sk;
+        }
+
+        (handoffs, ids)
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let (handoffs, _) = get_handoff_chain(&[100]);
+        let handoff = handoffs[0].clone();
+        assert!(handoff.verify());
+
+        // Unsigned handoffs do not verify
+        let mut unsigned = handoff.clone();
+        unsigned.signature = None;
+        assert!(!unsigned.verify());
+
+        // Neither do handoffs that have been tampered with
+        let mut tampered = handoff.clone();
+        tampered.activation_height += 1;
+        assert!(!tampered.verify());
+
+        // Nor handoffs signed by the new key (or anyone else but the old key)
+        let (sk, pk) = cryptography::get_random_keypair();
+        let mut forged = KeyHandoff::new(handoff.old_tower_id, TowerId(pk), 100);
+        forged.sign(&sk);
+        assert!(!forged.verify());
+    }
+
+    #[test]
+    fn test_proto_roundtrip() {
+        let (handoffs, _) = get_handoff_chain(&[100]);
+        let proto = msgs::KeyHandoff::from(handoffs[0].clone());
+        assert_eq!(KeyHandoff::try_from(proto).unwrap(), handoffs[0]);
+
+        // Wrong tower ids are rejected
+        let proto = msgs::KeyHandoff {
+            new_tower_id: vec![0; 33],
+            ..handoffs[0].clone().into()
+        };
+        assert!(KeyHandoff::try_from(proto).is_err());
+    }
+
+    #[test]
+    fn test_follow() {
+        let (handoffs, ids) = get_handoff_chain(&[100, 200, 300]);
+
+        // The chain is followed from any of the ids, no matter the order handoffs are given in
+        let mut shuffled = handoffs.clone();
+        shuffled.reverse();
+        assert_eq!(follow(ids[0], &shuffled), handoffs);
+        assert_eq!(follow(ids[2], &handoffs), handoffs[2..]);
+        assert!(follow(ids[3], &handoffs).is_empty());
+
+        // Unrelated handoffs are ignored
+        let (other, other_ids) = get_handoff_chain(&[150]);
+        assert_eq!(
+            follow(ids[0], &[handoffs.clone(), other].concat()),
+            handoffs
+        );
+        assert!(follow(other_ids[1], &handoffs).is_empty());
+
+        // The chain stops at the first handoff that does not verify
+        let mut tampered = handoffs.clone();
+        tampered[1].activation_height += 1;
+        assert_eq!(follow(ids[0], &tampered), handoffs[..1]);
+
+        // As well as if activation heights go backwards
+        let (backwards, ids) = get_handoff_chain(&[200, 100]);
+        assert_eq!(follow(ids[0], &backwards), backwards[..1]);
+    }
+
+    #[test]
+    fn test_is_valid_signer() {
+        let (chain, ids) = get_handoff_chain(&[100, 200]);
+        let tower_id = ids[2];
+
+        assert!(is_valid_signer(tower_id, &chain, 50, ids[0]));
+        assert!(!is_valid_signer(tower_id, &chain, 50, ids[1]));
+        assert!(!is_valid_signer(tower_id, &chain, 50, tower_id));
+
+        // Receipts issued at the activation height may be signed by either key
+        assert!(is_valid_signer(tower_id, &chain, 100, ids[0]));
+        assert!(is_valid_signer(tower_id, &chain, 100, ids[1]));
+        assert!(!is_valid_signer(tower_id, &chain, 101, ids[0]));
+        assert!(is_valid_signer(tower_id, &chain, 150, ids[1]));
+
+        assert!(is_valid_signer(tower_id, &chain, 200, tower_id));
+        assert!(is_valid_signer(tower_id, &chain, 1000, tower_id));
+        assert!(!is_valid_signer(tower_id, &chain, 1000, ids[1]));
+
+        // Without handoffs, only the tower id is valid
+        assert!(is_valid_signer(tower_id, &[], 0, tower_id));
+        assert!(!is_valid_signer(tower_id, &[], 0, ids[0]));
+
+        // Other keys are never valid
+        let other = crate::test_utils::get_random_user_id();
+        assert!(!is_valid_signer(tower_id, &chain, 100, other));
+    }
+}
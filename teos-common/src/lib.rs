@@ -8,11 +8,13 @@ pub mod protos {
     tonic::include_proto!("common.teos.v2");
 }
 
+pub mod announcement;
 pub mod appointment;
 pub mod constants;
 pub mod cryptography;
 pub mod dbm;
 pub mod errors;
+pub mod handoff;
 pub mod net;
 pub mod receipts;
 pub mod ser;
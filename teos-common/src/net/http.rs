@@ -1,9 +1,19 @@
+/// Version prefix of the HTTP API. Endpoints are also reachable without it for backwards compatibility.
+pub const API_VERSION: &str = "v2";
+
+#[derive(Clone, Copy)]
 pub enum Endpoint {
     Register,
     AddAppointment,
+    AddAppointments,
     GetAppointment,
     GetSubscriptionInfo,
+    DeleteAppointment,
+    RegisterWebhook,
+    GetTowerInfo,
+    GetKeyHandoffs,
     Ping,
+    OpenApi,
 }
 
 impl std::fmt::Display for Endpoint {
@@ -14,9 +24,15 @@ impl std::fmt::Display for Endpoint {
             match self {
                 Endpoint::Register => "register",
                 Endpoint::AddAppointment => "add_appointment",
+                Endpoint::AddAppointments => "add_appointments",
                 Endpoint::GetAppointment => "get_appointment",
                 Endpoint::GetSubscriptionInfo => "get_subscription_info",
+                Endpoint::DeleteAppointment => "delete_appointment",
+                Endpoint::RegisterWebhook => "register_webhook",
+                Endpoint::GetTowerInfo => "get_tower_info",
+                Endpoint::GetKeyHandoffs => "get_key_handoffs",
                 Endpoint::Ping => "ping",
+                Endpoint::OpenApi => "openapi.json",
             }
         )
     }
@@ -26,4 +42,8 @@ impl Endpoint {
     pub fn path(&self) -> String {
         format!("/{self}")
     }
+
+    pub fn versioned_path(&self) -> String {
+        format!("/{API_VERSION}/{self}")
+    }
 }
@@ -0,0 +1,195 @@
+//! Custom Lightning messages used to reach the tower public API over Lightning peer connections.
+//!
+//! Each request / response of the public API is mapped to a custom message type. The payload of a message is the
+//! protobuf encoding of the corresponding request / response type, so the same data is exchanged regardless of the
+//! transport. Message types are odd, so peers that do not understand them can safely ignore them.
+
+use std::io::{self, Read};
+
+use lightning::ln::msgs::DecodeError;
+use lightning::ln::wire::{CustomMessageReader, Type};
+use lightning::util::ser::{Writeable, Writer};
+use prost::Message;
+
+use crate::protos as msgs;
+
+pub const REGISTER: u16 = 48849;
+pub const REGISTER_RESPONSE: u16 = 48851;
+pub const ADD_APPOINTMENT: u16 = 48853;
+pub const ADD_APPOINTMENT_RESPONSE: u16 = 48855;
+pub const GET_APPOINTMENT: u16 = 48857;
+pub const GET_APPOINTMENT_RESPONSE: u16 = 48859;
+pub const GET_SUBSCRIPTION_INFO: u16 = 48861;
+pub const GET_SUBSCRIPTION_INFO_RESPONSE: u16 = 48863;
+pub const TOWER_ERROR: u16 = 48865;
+
+/// Error sent back by the tower when a request cannot be served. Error codes match `teos_common::errors`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TowerError {
+    pub error: String,
+    pub error_code: u8,
+}
+
+impl TowerError {
+    pub fn new(error: String, error_code: u8) -> Self {
+        TowerError { error, error_code }
+    }
+}
+
+/// Messages exchanged between users and towers over Lightning peer connections.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TowerMessage {
+    Register(msgs::RegisterRequest),
+    RegisterResponse(msgs::RegisterResponse),
+    AddAppointment(msgs::AddAppointmentRequest),
+    AddAppointmentResponse(msgs::AddAppointmentResponse),
+    GetAppointment(msgs::GetAppointmentRequest),
+    GetAppointmentResponse(msgs::GetAppointmentResponse),
+    GetSubscriptionInfo(msgs::GetSubscriptionInfoRequest),
+    GetSubscriptionInfoResponse(msgs::GetSubscriptionInfoResponse),
+    Error(TowerError),
+}
+
+impl TowerMessage {
+    /// Decodes a message of a given type from its payload.
+    ///
+    /// Returns `Ok(None)` if the message type is not a tower message.
+    pub fn read<R: Read>(message_type: u16, buffer: &mut R) -> Result<Option<Self>, DecodeError> {
+        let mut payload = Vec::new();
+        buffer.read_to_end(&mut payload)?;
+
+        fn decode<M: Message + Default>(payload: &[u8]) -> Result<M, DecodeError> {
+            M::decode(payload).map_err(|_| DecodeError::InvalidValue)
+        }
+
+        let message = match message_type {
+            REGISTER => TowerMessage::Register(decode(&payload)?),
+            REGISTER_RESPONSE => TowerMessage::RegisterResponse(decode(&payload)?),
+            ADD_APPOINTMENT => TowerMessage::AddAppointment(decode(&payload)?),
+            ADD_APPOINTMENT_RESPONSE => TowerMessage::AddAppointmentResponse(decode(&payload)?),
+            GET_APPOINTMENT => TowerMessage::GetAppointment(decode(&payload)?),
+            GET_APPOINTMENT_RESPONSE => TowerMessage::GetAppointmentResponse(decode(&payload)?),
+            GET_SUBSCRIPTION_INFO => TowerMessage::GetSubscriptionInfo(decode(&payload)?),
+            GET_SUBSCRIPTION_INFO_RESPONSE => {
+                TowerMessage::GetSubscriptionInfoResponse(decode(&payload)?)
+            }
+            TOWER_ERROR => {
+                let (error_code, error) = payload.split_first().ok_or(DecodeError::ShortRead)?;
+                TowerMessage::Error(TowerError::new(
+                    String::from_utf8(error.to_vec()).map_err(|_| DecodeError::InvalidValue)?,
+                    *error_code,
+                ))
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(message))
+    }
+}
+
+impl Type for TowerMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            TowerMessage::Register(_) => REGISTER,
+            TowerMessage::RegisterResponse(_) => REGISTER_RESPONSE,
+            TowerMessage::AddAppointment(_) => ADD_APPOINTMENT,
+            TowerMessage::AddAppointmentResponse(_) => ADD_APPOINTMENT_RESPONSE,
+            TowerMessage::GetAppointment(_) => GET_APPOINTMENT,
+            TowerMessage::GetAppointmentResponse(_) => GET_APPOINTMENT_RESPONSE,
+            TowerMessage::GetSubscriptionInfo(_) => GET_SUBSCRIPTION_INFO,
+            TowerMessage::GetSubscriptionInfoResponse(_) => GET_SUBSCRIPTION_INFO_RESPONSE,
+            TowerMessage::Error(_) => TOWER_ERROR,
+        }
+    }
+}
+
+impl Writeable for TowerMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let payload = match self {
+            TowerMessage::Register(m) => m.encode_to_vec(),
+            TowerMessage::RegisterResponse(m) => m.encode_to_vec(),
+            TowerMessage::AddAppointment(m) => m.encode_to_vec(),
+            TowerMessage::AddAppointmentResponse(m) => m.encode_to_vec(),
+            TowerMessage::GetAppointment(m) => m.encode_to_vec(),
+            TowerMessage::GetAppointmentResponse(m) => m.encode_to_vec(),
+            TowerMessage::GetSubscriptionInfo(m) => m.encode_to_vec(),
+            TowerMessage::GetSubscriptionInfoResponse(m) => m.encode_to_vec(),
+            TowerMessage::Error(e) => {
+                let mut payload = vec![e.error_code];
+                payload.extend_from_slice(e.error.as_bytes());
+                payload
+            }
+        };
+
+        writer.write_all(&payload)
+    }
+}
+
+/// Reads [TowerMessage]s out of the custom messages received from a peer.
+pub struct TowerMessageReader;
+
+impl CustomMessageReader for TowerMessageReader {
+    type CustomMessage = TowerMessage;
+
+    fn read<R: Read>(
+        &self,
+        message_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        TowerMessage::read(message_type, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_utils::{get_random_locator, get_random_user_id};
+
+    fn roundtrip(message: TowerMessage) -> TowerMessage {
+        TowerMessageReader
+            .read(message.type_id(), &mut message.encode().as_slice())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for message in [
+            TowerMessage::Register(msgs::RegisterRequest {
+                user_id: get_random_user_id().to_vec(),
+            }),
+            TowerMessage::GetAppointment(msgs::GetAppointmentRequest {
+                locator: get_random_locator().to_vec(),
+                signature: "signature".to_owned(),
+            }),
+            TowerMessage::GetSubscriptionInfoResponse(msgs::GetSubscriptionInfoResponse {
+                available_slots: 21,
+                subscription_expiry: 42,
+                locators: vec![get_random_locator().to_vec()],
+            }),
+            TowerMessage::Error(TowerError::new("error".to_owned(), 1)),
+            TowerMessage::Error(TowerError::new(String::new(), 2)),
+        ] {
+            assert_eq!(roundtrip(message.clone()), message);
+        }
+    }
+
+    #[test]
+    fn test_read_unknown_type() {
+        assert_eq!(TowerMessageReader.read(48848, &mut [].as_slice()), Ok(None));
+    }
+
+    #[test]
+    fn test_read_invalid_payload() {
+        // Errors need at least the error code
+        assert_eq!(
+            TowerMessageReader.read(TOWER_ERROR, &mut [].as_slice()),
+            Err(DecodeError::ShortRead)
+        );
+        assert_eq!(
+            TowerMessageReader.read(REGISTER, &mut [0xff].as_slice()),
+            Err(DecodeError::InvalidValue)
+        );
+    }
+}
@@ -1,13 +1,22 @@
 pub mod http;
+pub mod lightning;
 
 use serde::Serialize;
 use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Maximum length of a DNS name.
+const MAX_DNS_NAME_LEN: usize = 253;
+/// Maximum length of each label of a DNS name.
+const MAX_DNS_LABEL_LEN: usize = 63;
 
 /// Represents all types of teos network addresses
 #[derive(Clone, Serialize, Debug, PartialEq, Eq)]
 pub enum AddressType {
     IpV4 = 0,
     TorV3 = 1,
+    IpV6 = 2,
+    Dns = 3,
 }
 
 impl From<i32> for AddressType {
@@ -15,6 +24,8 @@ impl From<i32> for AddressType {
         match x {
             0 => AddressType::IpV4,
             1 => AddressType::TorV3,
+            2 => AddressType::IpV6,
+            3 => AddressType::Dns,
             x => panic!("Unknown address type {}", x),
         }
     }
@@ -27,6 +38,8 @@ impl std::str::FromStr for AddressType {
         match s {
             "ipv4" => Ok(AddressType::IpV4),
             "torv3" => Ok(AddressType::TorV3),
+            "ipv6" => Ok(AddressType::IpV6),
+            "dns" => Ok(AddressType::Dns),
             _ => Err(format!("Unknown type: {s}")),
         }
     }
@@ -37,26 +50,81 @@ impl fmt::Display for AddressType {
         let s = match self {
             AddressType::IpV4 => "ipv4",
             AddressType::TorV3 => "torv3",
+            AddressType::IpV6 => "ipv6",
+            AddressType::Dns => "dns",
         };
         write!(f, "{s}")
     }
 }
 
 impl AddressType {
-    pub fn get_type(net_addr: &str) -> AddressType {
-        if net_addr.contains(".onion:") {
-            AddressType::TorV3
+    /// Gets the type of a given host (with no scheme nor port). IPv6 hosts may be enclosed in brackets.
+    ///
+    /// Fails if the host is neither an IP address, an onion address nor a valid DNS name.
+    pub fn from_host(host: &str) -> Result<AddressType, String> {
+        let unbracketed = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+
+        if unbracketed.parse::<Ipv6Addr>().is_ok() {
+            Ok(AddressType::IpV6)
+        } else if host.parse::<Ipv4Addr>().is_ok() {
+            Ok(AddressType::IpV4)
+        } else if !is_dns_name(host) {
+            Err(format!("Invalid host: {host}"))
+        } else if host.ends_with(".onion") {
+            Ok(AddressType::TorV3)
         } else {
-            AddressType::IpV4
+            Ok(AddressType::Dns)
         }
     }
 
+    /// Gets the type of a given network address. The address may include a scheme and a port.
+    ///
+    /// Addresses whose host cannot be recognized are considered DNS names.
+    pub fn get_type(net_addr: &str) -> AddressType {
+        let addr = net_addr.split("://").last().unwrap();
+        let host = if addr.parse::<Ipv6Addr>().is_ok() {
+            addr
+        } else if addr.starts_with('[') {
+            addr.split(']').next().unwrap().trim_start_matches('[')
+        } else {
+            addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+        };
+
+        AddressType::from_host(host).unwrap_or(AddressType::Dns)
+    }
+
     pub fn is_tor(&self) -> bool {
         self == &AddressType::TorV3
     }
 
     pub fn is_clearnet(&self) -> bool {
-        self == &AddressType::IpV4
+        !self.is_tor()
+    }
+}
+
+/// Checks whether a given host is a valid DNS name.
+fn is_dns_name(host: &str) -> bool {
+    let name = host.strip_suffix('.').unwrap_or(host);
+    !name.is_empty()
+        && name.len() <= MAX_DNS_NAME_LEN
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_DNS_LABEL_LEN
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Joins a host and a port into a network address, enclosing IPv6 hosts in brackets.
+pub fn join_host_port(host: &str, port: u16) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
     }
 }
 
@@ -94,12 +162,68 @@ pub mod tests {
 
     pub const TORV3_ADDR: &str =
         "recnedb7xfhzjdrcgxongzli3a6qyrv5jwgowoho3v5g3rwk7kkglrid.onion:9814";
-    pub const IPV4_ADDR: &str = "teos.talaia.watch:9814";
+    pub const IPV4_ADDR: &str = "http://172.16.0.1:9814";
+    pub const IPV6_ADDR: &str = "http://[2001:db8::1]:9814";
+    pub const DNS_ADDR: &str = "teos.talaia.watch:9814";
+
+    #[test]
+    fn test_from_host() {
+        assert_eq!(AddressType::from_host("172.16.0.1"), Ok(AddressType::IpV4));
+        assert_eq!(AddressType::from_host("2001:db8::1"), Ok(AddressType::IpV6));
+        assert_eq!(AddressType::from_host("[::1]"), Ok(AddressType::IpV6));
+        assert_eq!(AddressType::from_host("localhost"), Ok(AddressType::Dns));
+        assert_eq!(
+            AddressType::from_host("teos.talaia.watch"),
+            Ok(AddressType::Dns)
+        );
+        assert_eq!(
+            AddressType::from_host(TORV3_ADDR.split(':').next().unwrap()),
+            Ok(AddressType::TorV3)
+        );
+
+        for host in [
+            "",
+            "my host",
+            "-teos.watch",
+            "teos..watch",
+            "teos_watch",
+            "[172.16.0.1]",
+            &format!("{}.watch", "a".repeat(MAX_DNS_LABEL_LEN + 1)),
+        ] {
+            assert!(AddressType::from_host(host).is_err(), "{}", host);
+        }
+    }
 
     #[test]
     fn test_get_type() {
         assert_eq!(AddressType::get_type(TORV3_ADDR), AddressType::TorV3);
         assert_eq!(AddressType::get_type(IPV4_ADDR), AddressType::IpV4);
+        assert_eq!(AddressType::get_type(IPV6_ADDR), AddressType::IpV6);
+        assert_eq!(AddressType::get_type(DNS_ADDR), AddressType::Dns);
+
+        // Scheme and port are optional
+        assert_eq!(AddressType::get_type("172.16.0.1"), AddressType::IpV4);
+        assert_eq!(AddressType::get_type("::1"), AddressType::IpV6);
+        assert_eq!(AddressType::get_type("[::1]"), AddressType::IpV6);
+        assert_eq!(AddressType::get_type("http://localhost"), AddressType::Dns);
+    }
+
+    #[test]
+    fn test_join_host_port() {
+        assert_eq!(join_host_port("172.16.0.1", 9814), "172.16.0.1:9814");
+        assert_eq!(join_host_port("2001:db8::1", 9814), "[2001:db8::1]:9814");
+        assert_eq!(
+            join_host_port("teos.talaia.watch", 9814),
+            "teos.talaia.watch:9814"
+        );
+
+        // Joined addresses keep their type
+        for host in ["172.16.0.1", "2001:db8::1", "teos.talaia.watch"] {
+            assert_eq!(
+                AddressType::get_type(&join_host_port(host, 9814)),
+                AddressType::from_host(host).unwrap()
+            );
+        }
     }
 
     #[test]
@@ -111,6 +235,8 @@ pub mod tests {
     #[test]
     fn test_is_clearnet() {
         assert!(!NetAddr::new(TORV3_ADDR.to_owned()).addr_type.is_clearnet());
-        assert!(NetAddr::new(IPV4_ADDR.to_owned()).addr_type.is_clearnet());
+        for addr in [IPV4_ADDR, IPV6_ADDR, DNS_ADDR] {
+            assert!(NetAddr::new(addr.to_owned()).addr_type.is_clearnet());
+        }
     }
 }
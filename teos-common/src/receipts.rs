@@ -3,7 +3,10 @@
 use serde::Serialize;
 
 use bitcoin::secp256k1::SecretKey;
+use bitcoin::Txid;
 
+use crate::appointment::Locator;
+use crate::protos as msgs;
 use crate::{cryptography, UserId};
 
 /// Proof that a user has registered with a tower. This serves two purposes:
@@ -92,7 +95,6 @@ impl RegistrationReceipt {
     }
 
     pub fn sign(&mut self, sk: &SecretKey) {
-        // TODO: Check if there's any case where this can actually fail. Don't unwrap if so.
         self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
     }
 
@@ -153,7 +155,6 @@ impl AppointmentReceipt {
     }
 
     pub fn sign(&mut self, sk: &SecretKey) {
-        // TODO: Check if there's any case where this can actually fail. Don't unwrap if so.
         self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
     }
 
@@ -165,3 +166,273 @@ impl AppointmentReceipt {
         }
     }
 }
+
+/// Proof that a tower agreed on deleting a batch of appointments on behalf of a user.
+///
+/// Deletion receipts cover the user request (through the user signature) and the slots the user has available after
+/// the deleted appointments have been refunded. They can be used to prove the tower is no longer responsible for the
+/// deleted appointments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeletionReceipt {
+    user_signature: String,
+    #[serde(serialize_with = "crate::ser::serialize_locator_list")]
+    locators: Vec<Locator>,
+    available_slots: u32,
+    signature: Option<String>,
+}
+
+impl DeletionReceipt {
+    pub fn new(user_signature: String, locators: Vec<Locator>, available_slots: u32) -> Self {
+        DeletionReceipt {
+            user_signature,
+            locators,
+            available_slots,
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(
+        user_signature: String,
+        locators: Vec<Locator>,
+        available_slots: u32,
+        signature: String,
+    ) -> Self {
+        DeletionReceipt {
+            user_signature,
+            locators,
+            available_slots,
+            signature: Some(signature),
+        }
+    }
+
+    pub fn user_signature(&self) -> &str {
+        &self.user_signature
+    }
+
+    pub fn locators(&self) -> &[Locator] {
+        &self.locators
+    }
+
+    pub fn available_slots(&self) -> u32 {
+        self.available_slots
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = Vec::new();
+        ser.extend_from_slice(self.user_signature.as_bytes());
+        for locator in self.locators.iter() {
+            ser.extend_from_slice(locator.as_ref());
+        }
+        ser.extend_from_slice(&self.available_slots.to_be_bytes());
+
+        ser
+    }
+
+    pub fn sign(&mut self, sk: &SecretKey) {
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    pub fn verify(&self, id: &UserId) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &id.0)
+        } else {
+            false
+        }
+    }
+}
+
+/// Proof of how a tower responded to the breach of an appointment.
+///
+/// Appointment summaries are handed to users once the tower has finished responding to a breach and the appointment
+/// data is gone (e.g. the penalty got irrevocably resolved or it was rejected), so users can still check what the
+/// tower did on their behalf. The `outcome` names the event that closed the response (e.g. `completed`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppointmentSummary {
+    #[serde(with = "hex::serde")]
+    locator: Locator,
+    dispute_txid: Txid,
+    penalty_txid: Option<Txid>,
+    breach_height: u32,
+    confirmation_height: Option<u32>,
+    outcome: String,
+    resolution_height: u32,
+    signature: Option<String>,
+}
+
+impl AppointmentSummary {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        locator: Locator,
+        dispute_txid: Txid,
+        penalty_txid: Option<Txid>,
+        breach_height: u32,
+        confirmation_height: Option<u32>,
+        outcome: String,
+        resolution_height: u32,
+    ) -> Self {
+        AppointmentSummary {
+            locator,
+            dispute_txid,
+            penalty_txid,
+            breach_height,
+            confirmation_height,
+            outcome,
+            resolution_height,
+            signature: None,
+        }
+    }
+
+    pub fn locator(&self) -> Locator {
+        self.locator
+    }
+
+    pub fn dispute_txid(&self) -> Txid {
+        self.dispute_txid
+    }
+
+    pub fn penalty_txid(&self) -> Option<Txid> {
+        self.penalty_txid
+    }
+
+    pub fn breach_height(&self) -> u32 {
+        self.breach_height
+    }
+
+    pub fn confirmation_height(&self) -> Option<u32> {
+        self.confirmation_height
+    }
+
+    pub fn outcome(&self) -> &str {
+        &self.outcome
+    }
+
+    pub fn resolution_height(&self) -> u32 {
+        self.resolution_height
+    }
+
+    pub fn signature(&self) -> Option<String> {
+        self.signature.clone()
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut ser = Vec::new();
+        ser.extend_from_slice(self.locator.as_ref());
+        ser.extend_from_slice(&self.dispute_txid);
+        // Optional fields are preceded by a presence byte and variable length ones by their length so no two
+        // different summaries serialize (and therefore sign) the same
+        match self.penalty_txid {
+            Some(penalty_txid) => {
+                ser.push(1);
+                ser.extend_from_slice(&penalty_txid);
+            }
+            None => ser.push(0),
+        }
+        ser.extend_from_slice(&self.breach_height.to_be_bytes());
+        match self.confirmation_height {
+            Some(confirmation_height) => {
+                ser.push(1);
+                ser.extend_from_slice(&confirmation_height.to_be_bytes());
+            }
+            None => ser.push(0),
+        }
+        ser.extend_from_slice(&(self.outcome.len() as u32).to_be_bytes());
+        ser.extend_from_slice(self.outcome.as_bytes());
+        ser.extend_from_slice(&self.resolution_height.to_be_bytes());
+
+        ser
+    }
+
+    pub fn sign(&mut self, sk: &SecretKey) {
+        self.signature = Some(cryptography::sign(&self.to_vec(), sk).unwrap());
+    }
+
+    pub fn verify(&self, id: &UserId) -> bool {
+        if let Some(signature) = self.signature() {
+            cryptography::verify(&self.to_vec(), &signature, &id.0)
+        } else {
+            false
+        }
+    }
+}
+
+impl From<AppointmentSummary> for msgs::AppointmentSummary {
+    fn from(s: AppointmentSummary) -> Self {
+        Self {
+            locator: s.locator.to_vec(),
+            dispute_txid: s.dispute_txid.to_vec(),
+            penalty_txid: s.penalty_txid.map_or_else(Vec::new, |txid| txid.to_vec()),
+            breach_height: s.breach_height,
+            confirmation_height: s.confirmation_height.unwrap_or_default(),
+            outcome: s.outcome,
+            resolution_height: s.resolution_height,
+            signature: s.signature.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    use crate::test_utils::get_random_locator;
+
+    #[test]
+    fn test_appointment_summary_to_vec() {
+        let locator = get_random_locator();
+        let dispute_txid = Txid::from_slice(&[1; 32]).unwrap();
+        let penalty_txid = Txid::from_slice(&[b'a'; 32]).unwrap();
+        let breach_height = u32::from_be_bytes(*b"bbbb");
+        let confirmation_height = u32::from_be_bytes(*b"cccc");
+
+        // Optional fields are prefixed with a presence byte and the outcome with its length, so a summary without a
+        // penalty txid cannot be crafted to serialize (and therefore be signed) like one that has it
+        let with_penalty = AppointmentSummary::new(
+            locator,
+            dispute_txid,
+            Some(penalty_txid),
+            breach_height,
+            Some(confirmation_height),
+            "completed".to_owned(),
+            42,
+        );
+        let without_penalty = AppointmentSummary::new(
+            locator,
+            dispute_txid,
+            None,
+            u32::from_be_bytes(*b"aaaa"),
+            Some(u32::from_be_bytes(*b"aaaa")),
+            format!("{}bbbbcccccompleted", "a".repeat(24)),
+            42,
+        );
+        assert_ne!(with_penalty.to_vec(), without_penalty.to_vec());
+
+        // Likewise, a missing confirmation height does not serialize like a confirmation at height zero
+        let confirmed_at = |confirmation_height| {
+            AppointmentSummary::new(
+                locator,
+                dispute_txid,
+                Some(penalty_txid),
+                breach_height,
+                confirmation_height,
+                "completed".to_owned(),
+                42,
+            )
+        };
+        assert_ne!(confirmed_at(None).to_vec(), confirmed_at(Some(0)).to_vec());
+
+        // Signatures do not carry over from one summary to the other
+        let (sk, pk) = cryptography::get_random_keypair();
+        let mut signed = with_penalty.clone();
+        signed.sign(&sk);
+        assert!(signed.verify(&UserId(pk)));
+        let mut forged = without_penalty;
+        forged.signature = signed.signature();
+        assert!(!forged.verify(&UserId(pk)));
+    }
+}
@@ -15,6 +15,17 @@ where
     seq.end()
 }
 
+pub fn serialize_locator_list<S>(v: &[Locator], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut seq = s.serialize_seq(Some(v.len()))?;
+    for element in v.iter() {
+        seq.serialize_element(&hex::encode(element))?;
+    }
+    seq.end()
+}
+
 pub mod serde_be {
     use super::*;
     use serde::de::{self, Deserializer};
@@ -0,0 +1,196 @@
+//! Benchmarks the time it takes the Watcher to process a block depending on the number of appointments it holds.
+//!
+//! Run with `cargo bench -p teos --bench block_processing`. Populating the larger databases takes a while.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{Rng, RngCore};
+use rusqlite::{params, params_from_iter, Connection};
+use tempdir::TempDir;
+
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, BlockHeader, OutPoint, Script, Transaction, TxIn, TxOut, Txid, Witness};
+use bitcoincore_rpc::{Auth, Client};
+use lightning::chain::Listen;
+
+use teos::carrier::Carrier;
+use teos::dbm::DBM;
+use teos::feed::Feed;
+use teos::gatekeeper::Gatekeeper;
+use teos::notifier::Notifier;
+use teos::responder::Responder;
+use teos::watcher::Watcher;
+use teos_common::appointment::Locator;
+use teos_common::cryptography::get_random_keypair;
+use teos_common::TowerId;
+
+/// The number of appointments held by the tower in each run.
+const APPOINTMENTS: [usize; 3] = [10_000, 1_000_000, 3_000_000];
+/// The number of transactions per block, roughly the one of a full block.
+const TXS_PER_BLOCK: usize = 3000;
+const HEIGHT: u32 = 800_000;
+
+/// Creates a database holding `n` appointments (all from the same user).
+///
+/// Data is inserted straight into the database, since going through the Watcher would take too long.
+fn populate_db(n: usize) -> TempDir {
+    let tmp_dir = TempDir::new("teos-bench").unwrap();
+    let db_path = tmp_dir.path().join("teos_db.sql3");
+    // Create the tables
+    DBM::new(db_path.clone(), None).unwrap();
+
+    let mut rng = rand::thread_rng();
+    let mut connection = Connection::open(db_path).unwrap();
+    let tx = connection.transaction().unwrap();
+    let user_id = get_random_keypair().1.serialize().to_vec();
+    tx.execute(
+        "INSERT INTO users (user_id, available_slots, subscription_start, subscription_expiry) VALUES (?1, ?2, ?3, ?4)",
+        params![user_id, u32::MAX, 0, u32::MAX],
+    )
+    .unwrap();
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )
+        .unwrap();
+        for _ in 0..n {
+            stmt.execute(params![
+                rng.gen::<[u8; 20]>().to_vec(),
+                rng.gen::<[u8; 16]>().to_vec(),
+                rng.gen::<[u8; 32]>().to_vec(),
+                42,
+                "user_signature",
+                HEIGHT,
+                user_id,
+            ])
+            .unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    tmp_dir
+}
+
+/// Creates a [Watcher] on top of the given database.
+fn create_watcher(dbm: Arc<Mutex<DBM>>) -> Watcher {
+    // Nothing is ever broadcast, since no breach is found
+    let rpc = Arc::new(Client::new("http://localhost:1", Auth::None).unwrap());
+    let carrier = Carrier::new(rpc, Arc::new((Mutex::new(true), Condvar::new())), HEIGHT);
+    let feed = Arc::new(Feed::new());
+    let gatekeeper = Arc::new(Gatekeeper::new(
+        HEIGHT,
+        10000,
+        4320,
+        6,
+        100_000,
+        dbm.clone(),
+        feed.clone(),
+    ));
+    let (sk, pk) = get_random_keypair();
+    let notifier = Arc::new(Notifier::new(sk, dbm.clone()));
+    let responder = Arc::new(Responder::new(
+        &[],
+        HEIGHT,
+        carrier,
+        gatekeeper.clone(),
+        dbm.clone(),
+        notifier.clone(),
+        feed.clone(),
+        None,
+    ));
+
+    Watcher::new(
+        gatekeeper,
+        responder,
+        &[],
+        HEIGHT,
+        sk,
+        TowerId(pk),
+        dbm,
+        notifier,
+        feed,
+        None,
+    )
+}
+
+fn get_random_block() -> (BlockHeader, Vec<Transaction>) {
+    let mut rng = rand::thread_rng();
+    let header = BlockHeader {
+        version: 1,
+        prev_blockhash: BlockHash::from_slice(&rng.gen::<[u8; 32]>()).unwrap(),
+        merkle_root: TxMerkleNode::from_slice(&rng.gen::<[u8; 32]>()).unwrap(),
+        time: 0,
+        bits: 0,
+        nonce: rng.next_u32(),
+    };
+    let txs = (0..TXS_PER_BLOCK)
+        .map(|_| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(
+                    Txid::from_slice(&rng.gen::<[u8; 32]>()).unwrap(),
+                    0,
+                ),
+                script_sig: Script::new(),
+                witness: Witness::new(),
+                sequence: 0,
+            }],
+            output: vec![TxOut {
+                script_pubkey: Script::new(),
+                value: rng.gen_range(0..21000000000),
+            }],
+        })
+        .collect();
+
+    (header, txs)
+}
+
+fn bench_block_processing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_connected");
+    group.sample_size(20);
+    let (header, txs) = get_random_block();
+    let txdata: Vec<_> = txs.iter().enumerate().collect();
+    let locators: Vec<Vec<u8>> = txs
+        .iter()
+        .map(|tx| Locator::new(tx.txid()).to_vec())
+        .collect();
+
+    for n in APPOINTMENTS {
+        let tmp_dir = populate_db(n);
+        let db_path = tmp_dir.path().join("teos_db.sql3");
+
+        // Looking all the block locators up in the database, which is what had to be done for every block before
+        // having the locator filter in front of it.
+        let connection = Connection::open(&db_path).unwrap();
+        group.bench_with_input(BenchmarkId::new("db_lookup", n), &n, |b, _| {
+            b.iter(|| {
+                let placeholders = format!("(?{})", (", ?").repeat(locators.len() - 1));
+                let mut stmt = connection
+                    .prepare(&format!(
+                        "SELECT locator FROM appointments WHERE locator IN {placeholders}"
+                    ))
+                    .unwrap();
+                stmt.query_map(params_from_iter(locators.iter()), |row| {
+                    row.get::<_, Vec<u8>>(0)
+                })
+                .unwrap()
+                .count()
+            })
+        });
+
+        // The filter is built from the stored appointments when the database is loaded.
+        let dbm = Arc::new(Mutex::new(DBM::new(db_path, None).unwrap()));
+        let watcher = create_watcher(dbm);
+        group.bench_with_input(BenchmarkId::new("watcher", n), &n, |b, _| {
+            b.iter(|| watcher.filtered_block_connected(&header, &txdata, HEIGHT + 1))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_block_processing);
+criterion_main!(benches);
@@ -1,9 +1,37 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The descriptor set is used to generate the OpenAPI spec of the HTTP API.
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR")?).join("teos_descriptor.bin");
+
     tonic_build::configure()
+        .file_descriptor_set_path(descriptor_path)
         .extern_path(".common.teos.v2", "::teos-common::protos")
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .field_attribute("user_id", "#[serde(with = \"hex::serde\")]")
         .field_attribute("tower_id", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("secret_key", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("Event.uuid", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("Event.locator", "#[serde(with = \"hex::serde\")]")
+        .field_attribute(
+            "Event.txid",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
+        .field_attribute("TowerEvent.uuid", "#[serde(with = \"hex::serde\")]")
+        .field_attribute("TowerEvent.locator", "#[serde(with = \"hex::serde\")]")
+        .field_attribute(
+            "TowerEvent.txid",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
+        .field_attribute(
+            "TowerEvent.block_hash",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
+        .field_attribute(
+            "CreateBackupResponse.last_known_block",
+            "#[serde(with = \"teos_common::ser::serde_be\")]",
+        )
         .field_attribute(
             "user_ids",
             "#[serde(serialize_with = \"teos_common::ser::serde_vec_bytes::serialize\")]",
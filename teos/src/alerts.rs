@@ -0,0 +1,599 @@
+//! Logic related to the Alerter, the component in charge of paging the tower operator when something needs attention.
+//!
+//! The Alerter losslessly listens to the [live event feed](crate::feed) for critical conditions (`bitcoind` going unreachable,
+//! penalties being rejected or missing confirmations, trackers being reorged out of the chain) and periodically checks
+//! the disk usage of the data directory. Alerts are delivered as a JSON payload to a webhook and/or to the stdin of an
+//! executable script. Repeated alerts for the same condition are only delivered once per cooldown period.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::sys::statvfs::statvfs;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedReceiver;
+use triggered::Listener;
+
+use crate::events::EventKind;
+use crate::feed::{Feed, FeedEvent, FeedEventKind};
+use crate::notifier::now;
+
+/// Time (in seconds) to wait for the alert webhook or script to finish.
+const DELIVERY_TIMEOUT: u64 = 10;
+/// Time (in seconds) between two disk usage checks.
+const DISK_CHECK_INTERVAL: u64 = 60;
+
+/// The conditions the operator can be alerted about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// `bitcoind` cannot be reached.
+    BitcoindUnreachable,
+    /// A penalty transaction has been rejected by `bitcoind`.
+    PenaltyRejected,
+    /// A penalty transaction has been sitting in mempool for too long.
+    MissedConfirmations,
+    /// A confirmed penalty transaction has been reorged out of the chain.
+    ReorgedTracker,
+    /// The disk holding the data directory is filling up.
+    DiskUsage,
+}
+
+/// The payload delivered to the operator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    /// The condition that triggered the alert.
+    pub kind: AlertKind,
+    /// A human readable description of the condition.
+    pub message: String,
+    /// The UNIX timestamp (in seconds) the alert was raised at.
+    pub timestamp: u64,
+    /// The hex encoded [UUID](crate::extended_appointment::UUID) of the affected tracker, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    /// The penalty transaction id of the affected tracker, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    /// The block height the condition was detected at, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl Alert {
+    fn new(kind: AlertKind, message: String) -> Self {
+        Alert {
+            kind,
+            message,
+            timestamp: now(),
+            uuid: None,
+            txid: None,
+            height: None,
+        }
+    }
+
+    /// Creates an alert from a feed event, copying the fields that identify the affected tracker.
+    fn from_event(kind: AlertKind, message: String, event: &FeedEvent) -> Self {
+        Alert {
+            uuid: event.uuid.map(|uuid| uuid.to_string()),
+            txid: event.txid.map(|txid| txid.to_string()),
+            height: event.height,
+            ..Alert::new(kind, message)
+        }
+    }
+}
+
+/// Returns the percentage of the disk holding `path` that is in use, computed from `statvfs` (so blocks reserved for the
+/// superuser count as neither used nor available, like `df` does).
+#[cfg(unix)]
+fn disk_usage(path: &Path) -> Option<u8> {
+    let stats = statvfs(path).ok()?;
+    let used = (stats.blocks() - stats.blocks_free()) as u64;
+    let total = used + stats.blocks_available() as u64;
+    (total > 0).then(|| (used * 100 / total) as u8)
+}
+
+/// Disk usage cannot be checked on non-unix targets, so no disk usage alert is ever raised there.
+#[cfg(not(unix))]
+fn disk_usage(_: &Path) -> Option<u8> {
+    None
+}
+
+/// Component in charge of raising alerts for the tower operator.
+pub struct Alerter {
+    /// The URL alerts are POSTed to, if any.
+    webhook: Option<String>,
+    /// The executable alerts are piped to, if any.
+    script: Option<PathBuf>,
+    /// Number of missed confirmations after which a penalty in mempool triggers an alert.
+    missed_confirmations: u32,
+    /// Disk usage (in percent) after which an alert is triggered.
+    disk_usage: u8,
+    /// Time (in seconds) during which repeated alerts for the same condition are suppressed.
+    cooldown: u64,
+    /// The directory whose disk usage is monitored.
+    data_dir: PathBuf,
+    /// The last time (UNIX timestamp) an alert was delivered for a given condition. Entries are pruned once their
+    /// cooldown expires.
+    last_fired: Mutex<HashMap<String, u64>>,
+    /// The critical events published to the live [Feed] of the tower. Subscribed to on creation so none are missed.
+    events: tokio::sync::Mutex<UnboundedReceiver<FeedEvent>>,
+}
+
+impl Alerter {
+    /// Creates a new [Alerter] instance.
+    pub fn new(
+        webhook: Option<String>,
+        script: Option<PathBuf>,
+        missed_confirmations: u32,
+        disk_usage: u8,
+        cooldown: u64,
+        data_dir: PathBuf,
+        feed: Arc<Feed>,
+    ) -> Self {
+        Alerter {
+            webhook,
+            script,
+            missed_confirmations,
+            disk_usage,
+            cooldown,
+            data_dir,
+            last_fired: Mutex::new(HashMap::new()),
+            events: tokio::sync::Mutex::new(feed.subscribe_lossless(Alerter::is_critical)),
+        }
+    }
+
+    /// Checks whether a kind of feed event may trigger an alert.
+    fn is_critical(kind: FeedEventKind) -> bool {
+        matches!(
+            kind,
+            FeedEventKind::BitcoindReachable
+                | FeedEventKind::BitcoindUnreachable
+                | FeedEventKind::Appointment(EventKind::PenaltyRejected)
+                | FeedEventKind::MissedConfirmation
+                | FeedEventKind::TrackerReorged
+        )
+    }
+
+    /// Checks whether an alert for the given condition should be delivered, updating the last time it fired if so.
+    /// Conditions whose cooldown has expired are forgotten along the way.
+    fn should_fire(&self, key: String, timestamp: u64) -> bool {
+        let mut last_fired = self.last_fired.lock().unwrap();
+        last_fired.retain(|_, t| timestamp < *t + self.cooldown);
+        match last_fired.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(e) => {
+                e.insert(timestamp);
+                true
+            }
+        }
+    }
+
+    /// Marks a condition as resolved, so the next time it happens it is alerted straightaway.
+    fn resolve(&self, key: &str) {
+        self.last_fired.lock().unwrap().remove(key);
+    }
+
+    /// Checks whether a feed event should trigger an alert.
+    fn check_event(&self, event: &FeedEvent) -> Option<Alert> {
+        let uuid = event.uuid.map(|uuid| uuid.to_string()).unwrap_or_default();
+        let (key, alert) = match event.kind {
+            FeedEventKind::BitcoindReachable => {
+                self.resolve("bitcoind_unreachable");
+                return None;
+            }
+            FeedEventKind::BitcoindUnreachable => (
+                "bitcoind_unreachable".to_owned(),
+                Alert::new(
+                    AlertKind::BitcoindUnreachable,
+                    "bitcoind is unreachable".to_owned(),
+                ),
+            ),
+            FeedEventKind::Appointment(EventKind::PenaltyRejected) => (
+                format!("penalty_rejected:{uuid}"),
+                Alert::from_event(
+                    AlertKind::PenaltyRejected,
+                    format!(
+                        "Penalty transaction rejected (rejection_reason={})",
+                        event.rejection_reason.unwrap_or_default()
+                    ),
+                    event,
+                ),
+            ),
+            FeedEventKind::MissedConfirmation => {
+                let missed_confirmations = event.missed_confirmations.unwrap_or_default();
+                if missed_confirmations < self.missed_confirmations {
+                    return None;
+                }
+                (
+                    format!("missed_confirmations:{uuid}"),
+                    Alert::from_event(
+                        AlertKind::MissedConfirmations,
+                        format!(
+                            "Penalty transaction has missed {missed_confirmations} confirmations"
+                        ),
+                        event,
+                    ),
+                )
+            }
+            FeedEventKind::TrackerReorged => (
+                format!("reorged_tracker:{uuid}"),
+                Alert::from_event(
+                    AlertKind::ReorgedTracker,
+                    "Penalty transaction has been reorged out of the chain".to_owned(),
+                    event,
+                ),
+            ),
+            _ => return None,
+        };
+
+        self.should_fire(key, alert.timestamp).then_some(alert)
+    }
+
+    /// Checks whether the given disk usage (in percent) should trigger an alert.
+    fn check_disk_usage(&self, usage: u8) -> Option<Alert> {
+        if usage < self.disk_usage {
+            self.resolve("disk_usage");
+            return None;
+        }
+
+        let alert = Alert::new(
+            AlertKind::DiskUsage,
+            format!("Disk holding {} is {usage}% full", self.data_dir.display()),
+        );
+        self.should_fire("disk_usage".to_owned(), alert.timestamp)
+            .then_some(alert)
+    }
+
+    /// Runs the alert script, writing the payload to its stdin.
+    async fn run_script(script: &Path, payload: &str) -> Result<(), String> {
+        let mut child = Command::new(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(payload.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        drop(stdin);
+
+        match tokio::time::timeout(Duration::from_secs(DELIVERY_TIMEOUT), child.wait()).await {
+            Ok(Ok(status)) if status.success() => Ok(()),
+            Ok(Ok(status)) => Err(format!("script exited with {status}")),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("script timed out".to_owned()),
+        }
+    }
+
+    /// Delivers an alert to the webhook and the script, if set. Failures are logged but not retried.
+    async fn deliver(&self, client: &reqwest::Client, alert: &Alert) {
+        tracing::warn!(kind = ?alert.kind, "{}", alert.message);
+        let payload = serde_json::to_string(alert).unwrap();
+
+        if let Some(webhook) = &self.webhook {
+            match client
+                .post(webhook)
+                .header(CONTENT_TYPE, "application/json")
+                .body(payload.clone())
+                .send()
+                .await
+            {
+                Ok(r) if r.status().is_success() => (),
+                Ok(r) => tracing::error!("Alert webhook replied with status {}", r.status()),
+                Err(e) => tracing::error!("Cannot reach alert webhook. Error: {e}"),
+            }
+        }
+
+        if let Some(script) = &self.script {
+            if let Err(e) = Alerter::run_script(script, &payload).await {
+                tracing::error!("Cannot run alert script {}. Error: {e}", script.display());
+            }
+        }
+    }
+
+    /// Watches the tower for critical conditions and delivers alerts until the shutdown signal is received.
+    pub async fn run(&self, shutdown_signal: Listener) {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(DELIVERY_TIMEOUT))
+            .build()
+            .unwrap();
+        let mut events = self.events.lock().await;
+        let mut disk_check = tokio::time::interval(Duration::from_secs(DISK_CHECK_INTERVAL));
+
+        loop {
+            let alert = tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.check_event(&event),
+                    None => break,
+                },
+                _ = disk_check.tick() => match disk_usage(&self.data_dir) {
+                    Some(usage) => self.check_disk_usage(usage),
+                    None => {
+                        // Disk usage is never available on non-unix targets, so there is nothing to report there.
+                        #[cfg(unix)]
+                        tracing::error!("Cannot check disk usage of {}", self.data_dir.display());
+                        None
+                    }
+                },
+                _ = shutdown_signal.clone() => break,
+            };
+
+            if let Some(alert) = alert {
+                self.deliver(&client, &alert).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+    use warp::Filter;
+
+    use teos_common::test_utils::{get_random_locator, get_random_user_id};
+
+    use crate::extended_appointment::UUID;
+    use crate::test_utils::get_random_tx;
+
+    const MISSED_CONFIRMATIONS: u32 = 6;
+    const DISK_USAGE: u8 = 90;
+    const COOLDOWN: u64 = 3600;
+
+    /// Runs a local HTTP server that forwards every alert it receives through a channel.
+    async fn run_receiver() -> (SocketAddr, mpsc::UnboundedReceiver<Alert>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let route = warp::post()
+            .and(warp::body::json())
+            .map(move |alert: Alert| {
+                tx.send(alert).unwrap();
+                warp::reply()
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, rx)
+    }
+
+    fn init_alerter(webhook: Option<String>, script: Option<PathBuf>) -> Alerter {
+        Alerter::new(
+            webhook,
+            script,
+            MISSED_CONFIRMATIONS,
+            DISK_USAGE,
+            COOLDOWN,
+            std::env::temp_dir(),
+            Arc::new(Feed::new()),
+        )
+    }
+
+    fn get_random_uuid() -> UUID {
+        UUID::new(get_random_locator(), get_random_user_id())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_disk_usage() {
+        let usage = disk_usage(&std::env::temp_dir()).unwrap();
+        assert!(usage <= 100);
+        assert_eq!(disk_usage(Path::new("/non/existing/path")), None);
+    }
+
+    #[test]
+    fn test_should_fire() {
+        let alerter = init_alerter(None, None);
+        let key = "key".to_owned();
+
+        assert!(alerter.should_fire(key.clone(), 1000));
+        // Repeated alerts are suppressed until the cooldown expires
+        assert!(!alerter.should_fire(key.clone(), 1000 + COOLDOWN - 1));
+        assert!(alerter.should_fire(key.clone(), 1000 + COOLDOWN));
+        // Other conditions are not affected
+        assert!(alerter.should_fire("another_key".to_owned(), 1000 + COOLDOWN));
+
+        // Resolved conditions are alerted straightaway
+        alerter.resolve(&key);
+        assert!(alerter.should_fire(key.clone(), 1001 + COOLDOWN));
+
+        // Conditions are forgotten once their cooldown expires
+        assert!(alerter.should_fire("yet_another_key".to_owned(), 1001 + 2 * COOLDOWN));
+        assert_eq!(
+            alerter
+                .last_fired
+                .lock()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["yet_another_key"]
+        );
+    }
+
+    #[test]
+    fn test_check_event_bitcoind_unreachable() {
+        let alerter = init_alerter(None, None);
+
+        let alert = alerter
+            .check_event(&FeedEvent::bitcoind_reachability(false))
+            .unwrap();
+        assert_eq!(alert.kind, AlertKind::BitcoindUnreachable);
+        assert!(alerter
+            .check_event(&FeedEvent::bitcoind_reachability(false))
+            .is_none());
+
+        // Once bitcoind is back, going down again is alerted
+        assert!(alerter
+            .check_event(&FeedEvent::bitcoind_reachability(true))
+            .is_none());
+        assert!(alerter
+            .check_event(&FeedEvent::bitcoind_reachability(false))
+            .is_some());
+    }
+
+    #[test]
+    fn test_check_event_missed_confirmations() {
+        let alerter = init_alerter(None, None);
+        let uuid = get_random_uuid();
+        let txid = get_random_tx().txid();
+
+        // Nothing is alerted below the threshold
+        for missed in 1..MISSED_CONFIRMATIONS {
+            assert!(alerter
+                .check_event(&FeedEvent::missed_confirmation(uuid, txid, missed))
+                .is_none());
+        }
+
+        let alert = alerter
+            .check_event(&FeedEvent::missed_confirmation(
+                uuid,
+                txid,
+                MISSED_CONFIRMATIONS,
+            ))
+            .unwrap();
+        assert_eq!(alert.kind, AlertKind::MissedConfirmations);
+        assert_eq!(alert.uuid, Some(uuid.to_string()));
+        assert_eq!(alert.txid, Some(txid.to_string()));
+
+        // The same tracker is only alerted once, but others are alerted independently
+        assert!(alerter
+            .check_event(&FeedEvent::missed_confirmation(
+                uuid,
+                txid,
+                MISSED_CONFIRMATIONS + 1
+            ))
+            .is_none());
+        assert!(alerter
+            .check_event(&FeedEvent::missed_confirmation(
+                get_random_uuid(),
+                txid,
+                MISSED_CONFIRMATIONS
+            ))
+            .is_some());
+    }
+
+    #[test]
+    fn test_check_event_reorged_tracker() {
+        let alerter = init_alerter(None, None);
+        let uuid = get_random_uuid();
+
+        let alert = alerter
+            .check_event(&FeedEvent::tracker_reorged(uuid, 100))
+            .unwrap();
+        assert_eq!(alert.kind, AlertKind::ReorgedTracker);
+        assert_eq!(alert.uuid, Some(uuid.to_string()));
+        assert_eq!(alert.height, Some(100));
+        assert!(alerter
+            .check_event(&FeedEvent::tracker_reorged(uuid, 100))
+            .is_none());
+    }
+
+    #[test]
+    fn test_check_event_ignored() {
+        let alerter = init_alerter(None, None);
+
+        for event in [
+            FeedEvent::new_user(get_random_user_id()),
+            FeedEvent::new_appointment(
+                get_random_uuid(),
+                get_random_user_id(),
+                get_random_locator(),
+            ),
+            FeedEvent::reorg(bitcoin::BlockHash::default(), 100),
+        ] {
+            assert!(alerter.check_event(&event).is_none());
+        }
+    }
+
+    #[test]
+    fn test_check_disk_usage() {
+        let alerter = init_alerter(None, None);
+
+        assert!(alerter.check_disk_usage(DISK_USAGE - 1).is_none());
+        let alert = alerter.check_disk_usage(DISK_USAGE).unwrap();
+        assert_eq!(alert.kind, AlertKind::DiskUsage);
+        assert!(alerter.check_disk_usage(DISK_USAGE + 1).is_none());
+
+        // Once usage drops, crossing the threshold again is alerted
+        assert!(alerter.check_disk_usage(DISK_USAGE - 1).is_none());
+        assert!(alerter.check_disk_usage(DISK_USAGE).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_webhook() {
+        let (addr, mut rx) = run_receiver().await;
+        let alerter = init_alerter(Some(format!("http://{addr}")), None);
+
+        let alert = Alert::new(AlertKind::DiskUsage, "Disk is full".to_owned());
+        alerter.deliver(&reqwest::Client::new(), &alert).await;
+        assert_eq!(rx.recv().await.unwrap(), alert);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_script() {
+        let tmp_dir = tempdir::TempDir::new("alerts").unwrap();
+        let output = tmp_dir.path().join("alert.json");
+        let script = tmp_dir.path().join("alert.sh");
+        std::fs::write(&script, format!("#!/bin/sh\ncat > {}\n", output.display())).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let alerter = init_alerter(None, Some(script));
+        let alert = Alert::new(AlertKind::DiskUsage, "Disk is full".to_owned());
+        alerter.deliver(&reqwest::Client::new(), &alert).await;
+
+        let delivered: Alert =
+            serde_json::from_str(&std::fs::read_to_string(output).unwrap()).unwrap();
+        assert_eq!(delivered, alert);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let (addr, mut rx) = run_receiver().await;
+        let feed = Arc::new(Feed::new());
+        let alerter = Alerter::new(
+            Some(format!("http://{addr}")),
+            None,
+            MISSED_CONFIRMATIONS,
+            DISK_USAGE,
+            COOLDOWN,
+            std::env::temp_dir(),
+            feed.clone(),
+        );
+
+        // Events published before the alerter starts running are not missed, and neither are events published
+        // in bulk, no matter how many non-critical events they are interleaved with
+        let uuids: Vec<UUID> = (0..10).map(|_| get_random_uuid()).collect();
+        for uuid in uuids.iter() {
+            for _ in 0..1000 {
+                feed.publish(FeedEvent::new_user(get_random_user_id()));
+            }
+            feed.publish(FeedEvent::tracker_reorged(*uuid, 42));
+        }
+
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let task = tokio::spawn(async move { alerter.run(shutdown_signal).await });
+
+        for uuid in uuids {
+            let alert = rx.recv().await.unwrap();
+            assert_eq!(alert.kind, AlertKind::ReorgedTracker);
+            assert_eq!(alert.uuid, Some(uuid.to_string()));
+        }
+
+        shutdown_trigger.trigger();
+        task.await.unwrap();
+    }
+}
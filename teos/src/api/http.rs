@@ -2,24 +2,33 @@ use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::time::Duration;
 use tonic::transport::Channel;
 use triggered::{Listener, Trigger};
 use warp::{http::StatusCode, reject, reply, Filter, Rejection, Reply};
 
 use teos_common::appointment::LOCATOR_LEN;
-use teos_common::net::http::Endpoint;
+use teos_common::net::http::{Endpoint, API_VERSION};
 use teos_common::protos as common_msgs;
 use teos_common::{errors, USER_ID_LEN};
 
+use crate::api::openapi;
+use crate::metrics::Metrics;
 use crate::protos::public_tower_services_client::PublicTowerServicesClient;
+use crate::tls::PublicTlsAcceptor;
 
 // TODO: Limit the body length for /add_appointment should not be needed, since slots are consumed proportionally to it.
 // Setting a limit for now just to prevent spam to some extend, but this is likely to be lifted.
 const REGISTER_BODY_LEN: u64 = 87;
 const ADD_APPOINTMENT_BODY_LEN: u64 = 2048;
+const ADD_APPOINTMENTS_BODY_LEN: u64 = 64 * ADD_APPOINTMENT_BODY_LEN;
 const GET_APPOINTMENT_BODY_LEN: u64 = 178;
 const GET_SUBSCRIPTION_INFO_BODY_LEN: u64 = 127;
+// Enough room for roughly a hundred locators per deletion request.
+const DELETE_APPOINTMENT_BODY_LEN: u64 = 4096;
+const REGISTER_WEBHOOK_BODY_LEN: u64 = 2048;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct ApiError {
@@ -58,13 +67,28 @@ impl ApiError {
     }
 }
 
+fn with_metrics(
+    metrics: Option<Arc<Metrics>>,
+) -> impl Filter<Extract = (Option<Arc<Metrics>>,), Error = Infallible> + Clone {
+    warp::any().map(move || metrics.clone())
+}
+
 fn with_grpc(
     grpc_endpoint: PublicTowerServicesClient<Channel>,
 ) -> impl Filter<Extract = (PublicTowerServicesClient<Channel>,), Error = Infallible> + Clone {
     warp::any().map(move || grpc_endpoint.clone())
 }
 
-fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
+/// Matches the path of a given endpoint, both under the API version prefix and at its legacy (unversioned) location.
+fn endpoint_path(endpoint: Endpoint) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let name = endpoint.to_string();
+    warp::path(API_VERSION)
+        .and(warp::path(name.clone()))
+        .or(warp::path(name))
+        .unify()
+}
+
+pub(crate) fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
     let mut status_code = StatusCode::BAD_REQUEST;
     let error_code = match s.code() {
         tonic::Code::InvalidArgument => errors::WRONG_FIELD_FORMAT,
@@ -83,7 +107,7 @@ fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
             errors::SERVICE_UNAVAILABLE
         }
         _ => {
-            log::debug!("Unexpected error ocurred: {}", s.message());
+            tracing::debug!("Unexpected error ocurred: {}", s.message());
             errors::UNEXPECTED_ERROR
         }
     };
@@ -91,20 +115,31 @@ fn match_status(s: &tonic::Status) -> (StatusCode, u8) {
     (status_code, error_code)
 }
 
+/// Records a request to the HTTP API, if metrics are enabled.
+fn record_request(metrics: &Option<Arc<Metrics>>, endpoint: Endpoint, error_code: Option<u8>) {
+    if let Some(metrics) = metrics {
+        metrics.record_http_request(endpoint, error_code);
+    }
+}
+
 fn parse_grpc_response<T: serde::Serialize>(
+    metrics: &Option<Arc<Metrics>>,
+    endpoint: Endpoint,
     result: Result<tonic::Response<T>, tonic::Status>,
 ) -> (reply::Json, StatusCode) {
     match result {
         Ok(r) => {
             let inner = r.into_inner();
-            log::debug!("Request succeeded");
-            log::debug!("Response: {}", serde_json::json!(inner));
+            tracing::debug!("Request succeeded");
+            record_request(metrics, endpoint, None);
+            tracing::debug!("Response: {}", serde_json::json!(inner));
             (reply::json(&inner), StatusCode::OK)
         }
         Err(s) => {
             let (status_code, error_code) = match_status(&s);
-            log::debug!("Request failed, error_code={error_code}");
-            log::debug!("Response: {}", serde_json::json!(s.message()));
+            tracing::debug!("Request failed, error_code={error_code}");
+            record_request(metrics, endpoint, Some(error_code));
+            tracing::debug!("Response: {}", serde_json::json!(s.message()));
             (
                 reply::json(&ApiError::new(s.message().into(), error_code)),
                 status_code,
@@ -117,8 +152,9 @@ async fn register(
     req: common_msgs::RegisterRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
 ) -> std::result::Result<impl Reply, Rejection> {
-    log::debug!(
+    tracing::debug!(
         "Received a register request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
@@ -135,7 +171,8 @@ async fn register(
         ));
     }
 
-    let (body, status) = parse_grpc_response(grpc_conn.register(req).await);
+    let (body, status) =
+        parse_grpc_response(&metrics, Endpoint::Register, grpc_conn.register(req).await);
     Ok(reply::with_status(body, status))
 }
 
@@ -143,8 +180,9 @@ async fn add_appointment(
     req: common_msgs::AddAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
 ) -> std::result::Result<impl Reply, Rejection> {
-    log::debug!(
+    tracing::debug!(
         "Received an add_appointment request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
@@ -167,7 +205,53 @@ async fn add_appointment(
         return Err(ApiError::empty_field("signature"));
     }
 
-    let (body, status) = parse_grpc_response(grpc_conn.add_appointment(req).await);
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::AddAppointment,
+        grpc_conn.add_appointment(req).await,
+    );
+    Ok(reply::with_status(body, status))
+}
+
+async fn add_appointments(
+    req: common_msgs::AddAppointmentsRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
+) -> std::result::Result<impl Reply, Rejection> {
+    tracing::debug!(
+        "Received an add_appointments request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.appointments.is_empty() {
+        return Err(ApiError::empty_field("appointments"));
+    }
+    for r in req.appointments.iter() {
+        if let Some(a) = &r.appointment {
+            if a.locator.is_empty() {
+                return Err(ApiError::empty_field("locator"));
+            }
+            if a.locator.len() != LOCATOR_LEN {
+                return Err(ApiError::wrong_field_length(
+                    "locator",
+                    a.locator.len(),
+                    LOCATOR_LEN,
+                ));
+            }
+        } else {
+            return Err(ApiError::missing_field("appointment"));
+        }
+        if r.signature.is_empty() {
+            return Err(ApiError::empty_field("signature"));
+        }
+    }
+
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::AddAppointments,
+        grpc_conn.add_appointments(req).await,
+    );
     Ok(reply::with_status(body, status))
 }
 
@@ -175,8 +259,9 @@ async fn get_appointment(
     req: common_msgs::GetAppointmentRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
 ) -> std::result::Result<impl Reply, Rejection> {
-    log::debug!(
+    tracing::debug!(
         "Received an get_appointment request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
@@ -195,7 +280,11 @@ async fn get_appointment(
         return Err(ApiError::empty_field("signature"));
     }
 
-    let (body, status) = parse_grpc_response(grpc_conn.get_appointment(req).await);
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::GetAppointment,
+        grpc_conn.get_appointment(req).await,
+    );
     Ok(reply::with_status(body, status))
 }
 
@@ -203,8 +292,9 @@ async fn get_subscription_info(
     req: common_msgs::GetSubscriptionInfoRequest,
     addr: Option<std::net::SocketAddr>,
     mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
 ) -> std::result::Result<impl Reply, Rejection> {
-    log::debug!(
+    tracing::debug!(
         "Received an get_subscription_info request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
@@ -213,66 +303,247 @@ async fn get_subscription_info(
         return Err(ApiError::empty_field("signature"));
     }
 
-    let (body, status) = parse_grpc_response(grpc_conn.get_subscription_info(req).await);
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::GetSubscriptionInfo,
+        grpc_conn.get_subscription_info(req).await,
+    );
+    Ok(reply::with_status(body, status))
+}
+
+async fn delete_appointment(
+    req: common_msgs::DeleteAppointmentRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
+) -> std::result::Result<impl Reply, Rejection> {
+    tracing::debug!(
+        "Received a delete_appointment request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.locators.is_empty() {
+        return Err(ApiError::empty_field("locators"));
+    }
+    for locator in req.locators.iter() {
+        if locator.len() != LOCATOR_LEN {
+            return Err(ApiError::wrong_field_length(
+                "locators",
+                locator.len(),
+                LOCATOR_LEN,
+            ));
+        }
+    }
+    if req.signature.is_empty() {
+        return Err(ApiError::empty_field("signature"));
+    }
+
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::DeleteAppointment,
+        grpc_conn.delete_appointment(req).await,
+    );
     Ok(reply::with_status(body, status))
 }
 
-async fn ping(addr: Option<SocketAddr>) -> Result<impl Reply, Rejection> {
-    log::debug!(
+async fn register_webhook(
+    req: common_msgs::RegisterWebhookRequest,
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
+) -> std::result::Result<impl Reply, Rejection> {
+    tracing::debug!(
+        "Received a register_webhook request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    if req.signature.is_empty() {
+        return Err(ApiError::empty_field("signature"));
+    }
+
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::RegisterWebhook,
+        grpc_conn.register_webhook(req).await,
+    );
+    Ok(reply::with_status(body, status))
+}
+
+async fn get_tower_info(
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
+) -> std::result::Result<impl Reply, Rejection> {
+    tracing::debug!(
+        "Received a get_tower_info request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::GetTowerInfo,
+        grpc_conn.get_tower_info(()).await,
+    );
+    Ok(reply::with_status(body, status))
+}
+
+async fn get_key_handoffs(
+    addr: Option<std::net::SocketAddr>,
+    mut grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
+) -> std::result::Result<impl Reply, Rejection> {
+    tracing::debug!(
+        "Received a get_key_handoffs request from {}",
+        addr.map_or("an unknown address".to_owned(), |a| a.to_string())
+    );
+
+    let (body, status) = parse_grpc_response(
+        &metrics,
+        Endpoint::GetKeyHandoffs,
+        grpc_conn.get_key_handoffs(()).await,
+    );
+    Ok(reply::with_status(body, status))
+}
+
+async fn ping(
+    addr: Option<SocketAddr>,
+    metrics: Option<Arc<Metrics>>,
+) -> Result<impl Reply, Rejection> {
+    tracing::debug!(
         "Received a ping request from {}",
         addr.map_or("an unknown address".to_owned(), |a| a.to_string())
     );
+    record_request(&metrics, Endpoint::Ping, None);
     Ok(reply::reply())
 }
 
 fn router(
     grpc_conn: PublicTowerServicesClient<Channel>,
+    metrics: Option<Arc<Metrics>>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let rejection_handler = |endpoint: Endpoint| {
+        let metrics = metrics.clone();
+        move |e| handle_rejection(endpoint, metrics.clone(), e)
+    };
+
     let register = warp::post()
-        .and(warp::path(Endpoint::Register.to_string()))
+        .and(endpoint_path(Endpoint::Register))
         .and(warp::body::content_length_limit(REGISTER_BODY_LEN).and(warp::body::json()))
         .and(warp::addr::remote())
         .and(with_grpc(grpc_conn.clone()))
-        .and_then(register);
+        .and(with_metrics(metrics.clone()))
+        .and_then(register)
+        .recover(rejection_handler(Endpoint::Register));
 
     let add_appointment = warp::post()
-        .and(warp::path(Endpoint::AddAppointment.to_string()))
+        .and(endpoint_path(Endpoint::AddAppointment))
         .and(warp::body::content_length_limit(ADD_APPOINTMENT_BODY_LEN).and(warp::body::json()))
         .and(warp::addr::remote())
         .and(with_grpc(grpc_conn.clone()))
-        .and_then(add_appointment);
+        .and(with_metrics(metrics.clone()))
+        .and_then(add_appointment)
+        .recover(rejection_handler(Endpoint::AddAppointment));
+
+    let add_appointments = warp::post()
+        .and(endpoint_path(Endpoint::AddAppointments))
+        .and(warp::body::content_length_limit(ADD_APPOINTMENTS_BODY_LEN).and(warp::body::json()))
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_metrics(metrics.clone()))
+        .and_then(add_appointments)
+        .recover(rejection_handler(Endpoint::AddAppointments));
 
     let get_appointment = warp::post()
-        .and(warp::path(Endpoint::GetAppointment.to_string()))
+        .and(endpoint_path(Endpoint::GetAppointment))
         .and(warp::body::content_length_limit(GET_APPOINTMENT_BODY_LEN).and(warp::body::json()))
         .and(warp::addr::remote())
         .and(with_grpc(grpc_conn.clone()))
-        .and_then(get_appointment);
+        .and(with_metrics(metrics.clone()))
+        .and_then(get_appointment)
+        .recover(rejection_handler(Endpoint::GetAppointment));
 
     let get_subscription_info = warp::post()
-        .and(warp::path(Endpoint::GetSubscriptionInfo.to_string()))
+        .and(endpoint_path(Endpoint::GetSubscriptionInfo))
         .and(
             warp::body::content_length_limit(GET_SUBSCRIPTION_INFO_BODY_LEN)
                 .and(warp::body::json()),
         )
         .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_metrics(metrics.clone()))
+        .and_then(get_subscription_info)
+        .recover(rejection_handler(Endpoint::GetSubscriptionInfo));
+
+    let delete_appointment = warp::post()
+        .and(endpoint_path(Endpoint::DeleteAppointment))
+        .and(warp::body::content_length_limit(DELETE_APPOINTMENT_BODY_LEN).and(warp::body::json()))
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_metrics(metrics.clone()))
+        .and_then(delete_appointment)
+        .recover(rejection_handler(Endpoint::DeleteAppointment));
+
+    let register_webhook = warp::post()
+        .and(endpoint_path(Endpoint::RegisterWebhook))
+        .and(warp::body::content_length_limit(REGISTER_WEBHOOK_BODY_LEN).and(warp::body::json()))
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_metrics(metrics.clone()))
+        .and_then(register_webhook)
+        .recover(rejection_handler(Endpoint::RegisterWebhook));
+
+    let get_tower_info = warp::get()
+        .and(endpoint_path(Endpoint::GetTowerInfo))
+        .and(warp::addr::remote())
+        .and(with_grpc(grpc_conn.clone()))
+        .and(with_metrics(metrics.clone()))
+        .and_then(get_tower_info)
+        .recover(rejection_handler(Endpoint::GetTowerInfo));
+
+    let get_key_handoffs = warp::get()
+        .and(endpoint_path(Endpoint::GetKeyHandoffs))
+        .and(warp::addr::remote())
         .and(with_grpc(grpc_conn))
-        .and_then(get_subscription_info);
+        .and(with_metrics(metrics.clone()))
+        .and_then(get_key_handoffs)
+        .recover(rejection_handler(Endpoint::GetKeyHandoffs));
 
     let ping = warp::get()
-        .and(warp::path(Endpoint::Ping.to_string()))
+        .and(endpoint_path(Endpoint::Ping))
         .and(warp::addr::remote())
+        .and(with_metrics(metrics.clone()))
         .and_then(ping);
 
+    let spec = openapi::spec();
+    let openapi = warp::get()
+        .and(warp::path(API_VERSION))
+        .and(warp::path(Endpoint::OpenApi.to_string()))
+        .and(warp::path::end())
+        .map(move || {
+            record_request(&metrics, Endpoint::OpenApi, None);
+            reply::json(&spec)
+        });
+
     register
         .or(add_appointment)
+        .or(add_appointments)
         .or(get_appointment)
         .or(get_subscription_info)
+        .or(delete_appointment)
+        .or(register_webhook)
+        .or(get_tower_info)
+        .or(get_key_handoffs)
         .or(ping)
-        .recover(handle_rejection)
+        .or(openapi)
+        .with(warp::trace::request())
 }
 
-async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+/// Replies to the rejections of a given endpoint caused by malformed requests. Any other rejection is passed through.
+async fn handle_rejection(
+    endpoint: Endpoint,
+    metrics: Option<Arc<Metrics>>,
+    err: Rejection,
+) -> Result<impl Reply, Rejection> {
     match err.find::<warp::body::BodyDeserializeError>() {
         Some(e) => {
             let mut error = e
@@ -290,37 +561,60 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
             } else {
                 errors::INVALID_REQUEST_FORMAT
             };
+            record_request(&metrics, endpoint, Some(error_code));
             Ok(reply::with_status(
                 reply::json(&ApiError { error, error_code }),
                 StatusCode::BAD_REQUEST,
             ))
         }
         None => match err.find::<ApiError>() {
-            Some(x) => Ok(reply::with_status(reply::json(x), StatusCode::BAD_REQUEST)),
+            Some(x) => {
+                record_request(&metrics, endpoint, Some(x.error_code));
+                Ok(reply::with_status(reply::json(x), StatusCode::BAD_REQUEST))
+            }
             None => Err(err),
         },
     }
 }
 
+/// Serves the HTTP API, over TLS if an acceptor is given. Requests are recorded in `metrics`, if given.
+///
+/// Returns an error if the API cannot be bound to `http_bind`.
 pub async fn serve(
     http_bind: SocketAddr,
     grpc_bind: SocketAddr,
+    tls: Option<Arc<PublicTlsAcceptor>>,
+    metrics: Option<Arc<Metrics>>,
     service_ready: Trigger,
     shutdown_signal: Listener,
-) {
+) -> Result<(), String> {
     let grpc_conn = loop {
         match PublicTowerServicesClient::connect(format!("http://{grpc_bind}")).await {
             Ok(conn) => break conn,
             Err(_) => {
-                log::error!("Cannot connect to the gRPC server. Retrying shortly");
+                tracing::error!("Cannot connect to the gRPC server. Retrying shortly");
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
         }
     };
-    let (_, server) =
-        warp::serve(router(grpc_conn)).bind_with_graceful_shutdown(http_bind, shutdown_signal);
-    service_ready.trigger();
-    server.await
+
+    if let Some(tls) = tls {
+        let listener = TcpListener::bind(http_bind)
+            .await
+            .map_err(|e| format!("Cannot bind the HTTP API to {http_bind}: {e}"))?;
+        let server = warp::serve(router(grpc_conn, metrics))
+            .serve_incoming_with_graceful_shutdown(tls.incoming(listener), shutdown_signal);
+        service_ready.trigger();
+        server.await
+    } else {
+        let (_, server) = warp::serve(router(grpc_conn, metrics))
+            .try_bind_with_graceful_shutdown(http_bind, shutdown_signal)
+            .map_err(|e| format!("Cannot bind the HTTP API to {http_bind}: {e}"))?;
+        service_ready.trigger();
+        server.await
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -329,8 +623,6 @@ mod test_helpers {
 
     use serde::de::DeserializeOwned;
     use serde_json::Value;
-    use std::sync::Arc;
-    use tokio::net::TcpListener;
     use tonic::transport::Server;
 
     use crate::api::internal::InternalAPI;
@@ -375,6 +667,15 @@ mod test_helpers {
         endpoint: Endpoint,
         body: RequestBody<'_>,
         server_addr: SocketAddr,
+    ) -> (ApiError, StatusCode) {
+        check_api_error_with_metrics(endpoint, body, server_addr, None).await
+    }
+
+    pub(crate) async fn check_api_error_with_metrics(
+        endpoint: Endpoint,
+        body: RequestBody<'_>,
+        server_addr: SocketAddr,
+        metrics: Option<Arc<Metrics>>,
     ) -> (ApiError, StatusCode) {
         let grpc_conn = PublicTowerServicesClient::connect(format!(
             "http://{}:{}",
@@ -403,7 +704,7 @@ mod test_helpers {
                 .body(b),
         };
 
-        let res = req.reply(&router(grpc_conn)).await;
+        let res = req.reply(&router(grpc_conn, metrics)).await;
         (
             serde_json::from_slice::<ApiError>(res.body()).unwrap(),
             res.status(),
@@ -429,9 +730,9 @@ mod test_helpers {
 
         let res = warp::test::request()
             .method("POST")
-            .path(&endpoint.path())
+            .path(&endpoint.versioned_path())
             .json(&serde_json::json!(body))
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, None))
             .await;
 
         serde_json::from_slice::<T>(res.body())
@@ -440,7 +741,9 @@ mod test_helpers {
 
 #[cfg(test)]
 mod tests_failures {
-    use super::test_helpers::{check_api_error, run_tower_in_background, RequestBody};
+    use super::test_helpers::{
+        check_api_error, check_api_error_with_metrics, run_tower_in_background, RequestBody,
+    };
     use super::*;
 
     use teos_common::test_utils::get_random_user_id;
@@ -483,6 +786,49 @@ mod tests_failures {
         assert_eq!(status, StatusCode::BAD_REQUEST);
     }
 
+    #[tokio::test]
+    async fn test_request_metrics() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let metrics = Arc::new(Metrics::new());
+        let requests = |endpoint: &str, error_code: u8| {
+            metrics
+                .http_requests
+                .with_label_values(&[endpoint, &error_code.to_string()])
+                .get()
+        };
+
+        // Both rejections raised by the handlers and by the body deserialization are accounted for
+        check_api_error_with_metrics(
+            Endpoint::GetSubscriptionInfo,
+            RequestBody::Jsonify(r#"{"signature": ""}"#),
+            server_addr,
+            Some(metrics.clone()),
+        )
+        .await;
+        for _ in 0..2 {
+            check_api_error_with_metrics(
+                Endpoint::RegisterWebhook,
+                RequestBody::Jsonify(r#"{}"#),
+                server_addr,
+                Some(metrics.clone()),
+            )
+            .await;
+        }
+
+        assert_eq!(requests("get_subscription_info", errors::EMPTY_FIELD), 1);
+        assert_eq!(requests("register_webhook", errors::MISSING_FIELD), 2);
+        assert_eq!(requests("register_webhook", errors::EMPTY_FIELD), 0);
+
+        // Nothing is recorded by routers with no metrics
+        check_api_error(
+            Endpoint::RegisterWebhook,
+            RequestBody::Jsonify(r#"{}"#),
+            server_addr,
+        )
+        .await;
+        assert_eq!(requests("register_webhook", errors::MISSING_FIELD), 2);
+    }
+
     #[tokio::test]
     async fn test_empty_field() {
         let (server_addr, _s) = run_tower_in_background().await;
@@ -585,7 +931,7 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .path(&Endpoint::Register.path())
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, None))
             .await;
 
         assert_eq!(res.status(), StatusCode::LENGTH_REQUIRED);
@@ -606,7 +952,7 @@ mod tests_failures {
             .method("POST")
             .path(&Endpoint::Register.path())
             .json(&format!("{}{}", get_random_user_id(), get_random_user_id()))
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, None))
             .await;
 
         assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
@@ -626,7 +972,7 @@ mod tests_failures {
         let res = warp::test::request()
             .method("POST")
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, None))
             .await;
 
         assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
@@ -645,7 +991,7 @@ mod tests_failures {
 
         let res = warp::test::request()
             .json(&"")
-            .reply(&router(grpc_conn))
+            .reply(&router(grpc_conn, None))
             .await;
 
         assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
@@ -664,8 +1010,12 @@ mod tests_methods {
     use crate::test_utils::{
         generate_dummy_appointment, get_random_tx, ApiConfig, DURATION, SLOTS,
     };
+    use crate::tls::test_utils::write_self_signed_certificate;
     use crate::watcher::Breach;
 
+    use std::convert::TryFrom;
+    use tempdir::TempDir;
+    use teos_common::announcement::TowerAnnouncement;
     use teos_common::test_utils::get_random_user_id;
     use teos_common::{cryptography, UserId};
 
@@ -897,6 +1247,88 @@ mod tests_methods {
         );
     }
 
+    #[tokio::test]
+    async fn test_add_appointments() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Then try to add a batch of appointments, one of them signed by a non-registered user
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        let (other_sk, _) = cryptography::get_random_keypair();
+        let other_appointment = generate_dummy_appointment(None).inner;
+        let other_signature = cryptography::sign(&other_appointment.to_vec(), &other_sk).unwrap();
+
+        let response = request_to_api::<
+            common_msgs::AddAppointmentsRequest,
+            common_msgs::AddAppointmentsResponse,
+        >(
+            Endpoint::AddAppointments,
+            common_msgs::AddAppointmentsRequest {
+                appointments: vec![
+                    common_msgs::AddAppointmentRequest {
+                        appointment: Some(appointment.into()),
+                        signature,
+                    },
+                    common_msgs::AddAppointmentRequest {
+                        appointment: Some(other_appointment.into()),
+                        signature: other_signature,
+                    },
+                ],
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            response.results[0].result,
+            Some(common_msgs::add_appointment_result::Result::Response(
+                common_msgs::AddAppointmentResponse { .. }
+            ))
+        ));
+        assert!(matches!(
+            response.results[1].result,
+            Some(common_msgs::add_appointment_result::Result::Error(
+                common_msgs::AddAppointmentError {
+                    error_code: x,
+                    ..
+                }
+            )) if x == errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR as u32
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_appointments_empty() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::AddAppointments,
+                RequestBody::Json(serde_json::json!(common_msgs::AddAppointmentsRequest {
+                    appointments: Vec::new(),
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new("`appointments` field is empty".into(), errors::EMPTY_FIELD),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_get_appointment() {
         let (server_addr, _s) = run_tower_in_background().await;
@@ -1153,4 +1585,380 @@ mod tests_methods {
             )
         );
     }
+
+    #[tokio::test]
+    async fn test_delete_appointment() {
+        let (server_addr, internal_api, _s) =
+            run_tower_in_background_with_config(ApiConfig::default()).await;
+        let tower_id = internal_api.get_watcher().tower_id;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        let registration =
+            request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+                Endpoint::Register,
+                common_msgs::RegisterRequest {
+                    user_id: user_pk.serialize().to_vec(),
+                },
+                server_addr,
+            )
+            .await
+            .unwrap();
+
+        // Add an appointment
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+
+        request_to_api::<common_msgs::AddAppointmentRequest, common_msgs::AddAppointmentResponse>(
+            Endpoint::AddAppointment,
+            common_msgs::AddAppointmentRequest {
+                appointment: Some(appointment.clone().into()),
+                signature,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Delete it
+        let response = request_to_api::<
+            common_msgs::DeleteAppointmentRequest,
+            common_msgs::DeleteAppointmentResponse,
+        >(
+            Endpoint::DeleteAppointment,
+            common_msgs::DeleteAppointmentRequest {
+                locators: vec![appointment.locator.to_vec()],
+                signature: cryptography::sign(
+                    format!("delete appointments {tower_id} 1 {}", appointment.locator).as_bytes(),
+                    &user_sk,
+                )
+                .unwrap(),
+                nonce: 1,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.locators, vec![appointment.locator.to_vec()]);
+        assert_eq!(response.available_slots, registration.available_slots);
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_empty_locators() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let (user_sk, _) = cryptography::get_random_keypair();
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::DeleteAppointment,
+                RequestBody::Json(serde_json::json!(common_msgs::DeleteAppointmentRequest {
+                    locators: Vec::new(),
+                    signature: cryptography::sign("delete appointments ".as_bytes(), &user_sk)
+                        .unwrap(),
+                    nonce: 1,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new("`locators` field is empty".into(), errors::EMPTY_FIELD),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_not_found() {
+        let (server_addr, internal_api, _s) =
+            run_tower_in_background_with_config(ApiConfig::default()).await;
+        let tower_id = internal_api.get_watcher().tower_id;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Appointment hasn't been added
+        let appointment = generate_dummy_appointment(None).inner;
+
+        assert_eq!(
+            check_api_error(
+                Endpoint::DeleteAppointment,
+                RequestBody::Json(serde_json::json!(common_msgs::DeleteAppointmentRequest {
+                    locators: vec![appointment.locator.to_vec()],
+                    signature: cryptography::sign(
+                        format!("delete appointments {tower_id} 1 {}", appointment.locator)
+                            .as_bytes(),
+                        &user_sk,
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    format!("Appointment not found: {}", appointment.locator),
+                    errors::APPOINTMENT_NOT_FOUND
+                ),
+                StatusCode::NOT_FOUND
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook() {
+        let (server_addr, internal_api, _s) =
+            run_tower_in_background_with_config(ApiConfig::default()).await;
+        let tower_id = internal_api.get_watcher().tower_id;
+
+        // Register first
+        let (user_sk, user_pk) = cryptography::get_random_keypair();
+        request_to_api::<common_msgs::RegisterRequest, common_msgs::RegisterResponse>(
+            Endpoint::Register,
+            common_msgs::RegisterRequest {
+                user_id: user_pk.serialize().to_vec(),
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        // Register the webhook
+        let url = "http://1.1.1.1:8080/hook";
+        let response = request_to_api::<
+            common_msgs::RegisterWebhookRequest,
+            common_msgs::RegisterWebhookResponse,
+        >(
+            Endpoint::RegisterWebhook,
+            common_msgs::RegisterWebhookRequest {
+                url: url.to_owned(),
+                signature: cryptography::sign(
+                    format!("register webhook {tower_id} 1 {url}").as_bytes(),
+                    &user_sk,
+                )
+                .unwrap(),
+                nonce: 1,
+            },
+            server_addr,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            common_msgs::RegisterWebhookResponse {
+                user_id: user_pk.serialize().to_vec(),
+                url: url.to_owned(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook_wrong_url() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        let (user_sk, _) = cryptography::get_random_keypair();
+        let url = "ftp://1.1.1.1/hook";
+        assert_eq!(
+            check_api_error(
+                Endpoint::RegisterWebhook,
+                RequestBody::Json(serde_json::json!(common_msgs::RegisterWebhookRequest {
+                    url: url.to_owned(),
+                    signature: cryptography::sign(
+                        format!("register webhook {url}").as_bytes(),
+                        &user_sk,
+                    )
+                    .unwrap(),
+                    nonce: 1,
+                })),
+                server_addr,
+            )
+            .await,
+            (
+                ApiError::new(
+                    "The provided url is not a valid http(s) url".into(),
+                    errors::WRONG_FIELD_FORMAT
+                ),
+                StatusCode::BAD_REQUEST
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_tower_info() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!("http://{server_addr}"))
+            .await
+            .unwrap();
+
+        let res = warp::test::request()
+            .method("GET")
+            .path(&Endpoint::GetTowerInfo.versioned_path())
+            .reply(&router(grpc_conn, None))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let announcement = TowerAnnouncement::try_from(
+            serde_json::from_slice::<common_msgs::TowerAnnouncement>(res.body()).unwrap(),
+        )
+        .unwrap();
+        assert!(announcement.verify());
+        assert_eq!(announcement.subscription_slots(), SLOTS);
+        assert_eq!(announcement.subscription_duration(), DURATION);
+    }
+
+    #[tokio::test]
+    async fn test_get_key_handoffs() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!("http://{server_addr}"))
+            .await
+            .unwrap();
+
+        let res = warp::test::request()
+            .method("GET")
+            .path(&Endpoint::GetKeyHandoffs.versioned_path())
+            .reply(&router(grpc_conn, None))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(
+            serde_json::from_slice::<common_msgs::GetKeyHandoffsResponse>(res.body())
+                .unwrap()
+                .handoffs
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_legacy_paths() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!("http://{server_addr}"))
+            .await
+            .unwrap();
+
+        // Endpoints are reachable both with and without the version prefix
+        for path in [
+            Endpoint::Register.path(),
+            Endpoint::Register.versioned_path(),
+        ] {
+            let res = warp::test::request()
+                .method("POST")
+                .path(&path)
+                .json(&common_msgs::RegisterRequest {
+                    user_id: get_random_user_id().to_vec(),
+                })
+                .reply(&router(grpc_conn.clone(), None))
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(serde_json::from_slice::<common_msgs::RegisterResponse>(res.body()).is_ok());
+        }
+
+        for path in [Endpoint::Ping.path(), Endpoint::Ping.versioned_path()] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(&path)
+                .reply(&router(grpc_conn.clone(), None))
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let (server_addr, _s) = run_tower_in_background().await;
+        let grpc_conn = PublicTowerServicesClient::connect(format!("http://{server_addr}"))
+            .await
+            .unwrap();
+
+        let res = warp::test::request()
+            .method("GET")
+            .path(&Endpoint::OpenApi.versioned_path())
+            .reply(&router(grpc_conn.clone(), None))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+            openapi::spec()
+        );
+
+        // The spec is only served under the versioned path
+        let res = warp::test::request()
+            .method("GET")
+            .path(&Endpoint::OpenApi.path())
+            .reply(&router(grpc_conn, None))
+            .await;
+        assert_ne!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls() {
+        let (server_addr, _s) = run_tower_in_background().await;
+
+        let tmp_dir = TempDir::new("http_tls").unwrap();
+        let cert_path = tmp_dir.path().join("cert.pem");
+        let key_path = tmp_dir.path().join("key.pem");
+        let cert = write_self_signed_certificate(&cert_path, &key_path);
+        let tls = Arc::new(
+            PublicTlsAcceptor::new(
+                cert_path.clone(),
+                key_path.clone(),
+                vec![b"http/1.1".to_vec()],
+            )
+            .unwrap(),
+        );
+
+        let http_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        tokio::spawn(serve(
+            http_addr,
+            server_addr,
+            Some(tls.clone()),
+            None,
+            service_ready,
+            shutdown_signal,
+        ));
+        ready_signal.await;
+
+        let get_tower_info = |scheme: &str, cert: &[u8]| {
+            reqwest::Client::builder()
+                .add_root_certificate(reqwest::Certificate::from_pem(cert).unwrap())
+                .resolve("localhost", http_addr)
+                .build()
+                .unwrap()
+                .get(format!(
+                    "{scheme}://localhost:{}{}",
+                    http_addr.port(),
+                    Endpoint::GetTowerInfo.versioned_path()
+                ))
+                .send()
+        };
+
+        // The API is served over TLS, and only over TLS
+        let res = get_tower_info("https", &cert).await.unwrap();
+        assert!(res.status().is_success());
+        assert!(get_tower_info("http", &cert).await.is_err());
+
+        // Renewed certificates are picked up once reloaded, without restarting the server
+        let new_cert = write_self_signed_certificate(&cert_path, &key_path);
+        tls.reload().await;
+        assert!(get_tower_info("https", &cert).await.is_err());
+        let res = get_tower_info("https", &new_cert).await.unwrap();
+        assert!(res.status().is_success());
+
+        shutdown_trigger.trigger();
+    }
 }
@@ -1,19 +1,40 @@
-use std::sync::{Arc, Condvar, Mutex};
+use futures::{future, stream, Stream, StreamExt};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
 use tonic::{Code, Request, Response, Status};
-use triggered::Trigger;
+use torut::onion::TorSecretKeyV3;
+use triggered::{Listener, Trigger};
 
+use bitcoin::secp256k1::PublicKey;
+
+use crate::api::tor::TorAPI;
+use crate::backup::{BackupEncryption, BackupError, BackupManager};
+use crate::config::ConfigError;
+use crate::dbm::{AppointmentRecord, AppointmentsFilter};
 use crate::extended_appointment::UUID;
+use crate::feed::{Feed, FeedEventKind};
+use crate::notifier::WebhookUrlError;
 use crate::protos as msgs;
 use crate::protos::private_tower_services_server::PrivateTowerServices;
 use crate::protos::public_tower_services_server::PublicTowerServices;
+use crate::reload::{ConfigReloader, ReloadReport};
 use crate::watcher::{
-    AddAppointmentFailure, AppointmentInfo, GetAppointmentFailure, GetSubscriptionInfoFailure,
-    Watcher,
+    AddAppointmentFailure, AppointmentInfo, DeleteAppointmentFailure, GetAppointmentFailure,
+    GetSubscriptionInfoFailure, RegisterWebhookFailure, Watcher,
 };
 
+use teos_common::announcement::TowerAnnouncement;
 use teos_common::appointment::{Appointment, AppointmentStatus, Locator};
+use teos_common::cryptography::KeyDecryptionError;
+use teos_common::net::AddressType;
 use teos_common::protos as common_msgs;
-use teos_common::UserId;
+use teos_common::{errors, UserId};
 
 /// Internal API of the tower.
 /// Holds the [Watcher] (which is the single entry point of the tower's core) and offers interfaces
@@ -23,31 +44,231 @@ pub struct InternalAPI {
     /// A [Watcher] instance.
     watcher: Arc<Watcher>,
     /// A list of public API endpoints.
-    addresses: Vec<msgs::NetworkAddress>,
+    addresses: RwLock<Vec<msgs::NetworkAddress>>,
+    /// The tower announcement, signed by the tower. Served to anyone asking for public information about the tower.
+    announcement: RwLock<TowerAnnouncement>,
+    /// The Tor endpoint of the public API, if enabled.
+    tor_api: Option<Arc<TorAPI>>,
     /// A flag that indicates wether bitcoind is reachable or not.
     bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
     /// A signal indicating the tower is shuting down.
     shutdown_trigger: Trigger,
+    /// A signal from the main thread indicating the tower is shuting down. Used to end the event feed streams.
+    shutdown_signal: Listener,
+    /// A [BackupManager] instance. Used to create tower backups.
+    backup_manager: Arc<BackupManager>,
+    /// A [ConfigReloader] instance. Used to apply config changes while the tower is running.
+    config_reloader: Arc<ConfigReloader>,
+    /// The live [Feed] of the tower. Streamed to the event subscribers.
+    feed: Arc<Feed>,
+}
+
+/// Number of appointments returned by `get_all_appointments` if no limit is requested.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Maximum number of appointments returned by a single `get_all_appointments` call.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Parses a `create_backup` request into the backup encryption and the (optional) path of the backup.
+fn parse_backup_request(
+    req_data: msgs::CreateBackupRequest,
+) -> Result<(BackupEncryption, Option<PathBuf>), String> {
+    let encryption = match (
+        req_data.passphrase.is_empty(),
+        req_data.public_key.is_empty(),
+    ) {
+        (false, true) => BackupEncryption::Passphrase(req_data.passphrase),
+        (true, false) => BackupEncryption::PublicKey(
+            PublicKey::from_slice(&req_data.public_key)
+                .map_err(|_| "Provided public key does not match expected format".to_owned())?,
+        ),
+        _ => return Err("Either a passphrase or a public key must be provided".to_owned()),
+    };
+    let path = (!req_data.path.is_empty()).then_some(PathBuf::from(req_data.path));
+
+    Ok((encryption, path))
+}
+
+/// Error returned by the RPCs that need Tor support when it is not enabled.
+fn tor_disabled() -> Status {
+    Status::new(Code::FailedPrecondition, "Tor support is not enabled")
+}
+
+/// Parses the cursor and filters of a [msgs::GetAllAppointmentsRequest]. Errors are meant to be returned as
+/// [Code::InvalidArgument].
+fn parse_appointments_request(
+    req_data: &msgs::GetAllAppointmentsRequest,
+) -> Result<(Option<UUID>, AppointmentsFilter), String> {
+    let cursor = if req_data.cursor.is_empty() {
+        None
+    } else {
+        Some(UUID::from_slice(&req_data.cursor).map_err(|_| {
+            "The provided cursor does not match the expected format (20-byte hexadecimal string)"
+                .to_owned()
+        })?)
+    };
+    let user_id = if req_data.user_id.is_empty() {
+        None
+    } else {
+        Some(UserId::from_slice(&req_data.user_id).map_err(|_| {
+            "The provided user_id does not match the expected format (33-byte compressed key)"
+                .to_owned()
+        })?)
+    };
+    let responded = match req_data.status.as_str() {
+        "" => None,
+        "watching" => Some(false),
+        "responded" => Some(true),
+        status => {
+            return Err(format!(
+                "Unknown status: {status}. Expected {{watching, responded}}"
+            ))
+        }
+    };
+    let confirmed = match req_data.confirmation_status.as_str() {
+        "" => None,
+        "in_mempool" => Some(false),
+        "confirmed" => Some(true),
+        status => {
+            return Err(format!(
+                "Unknown confirmation_status: {status}. Expected {{confirmed, in_mempool}}"
+            ))
+        }
+    };
+
+    Ok((
+        cursor,
+        AppointmentsFilter {
+            user_id,
+            start_block_from: (req_data.start_block_from != 0).then_some(req_data.start_block_from),
+            start_block_to: (req_data.start_block_to != 0).then_some(req_data.start_block_to),
+            responded,
+            confirmed,
+        },
+    ))
+}
+
+/// Builds the [common_msgs::AppointmentData] of an appointment or tracker held by the tower.
+fn appointment_data(record: AppointmentRecord) -> common_msgs::AppointmentData {
+    common_msgs::AppointmentData {
+        appointment_data: Some(match record {
+            AppointmentRecord::Appointment(appointment) => {
+                common_msgs::appointment_data::AppointmentData::Appointment(appointment.into())
+            }
+            AppointmentRecord::Tracker(tracker) => {
+                common_msgs::appointment_data::AppointmentData::Tracker(tracker.into())
+            }
+        }),
+    }
 }
 
 impl InternalAPI {
     /// Creates a new [InternalAPI] instance.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         watcher: Arc<Watcher>,
         addresses: Vec<msgs::NetworkAddress>,
+        announcement: TowerAnnouncement,
+        tor_api: Option<Arc<TorAPI>>,
         bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
         shutdown_trigger: Trigger,
+        shutdown_signal: Listener,
+        backup_manager: Arc<BackupManager>,
+        config_reloader: Arc<ConfigReloader>,
+        feed: Arc<Feed>,
     ) -> Self {
         Self {
             watcher,
-            addresses,
+            addresses: RwLock::new(addresses),
+            announcement: RwLock::new(announcement),
+            tor_api,
             bitcoind_reachable,
             shutdown_trigger,
+            shutdown_signal,
+            backup_manager,
+            config_reloader,
+            feed,
+        }
+    }
+
+    pub fn get_addresses(&self) -> Vec<msgs::NetworkAddress> {
+        self.addresses.read().unwrap().clone()
+    }
+
+    /// Gets the Tor endpoint of the public API, if Tor support is enabled.
+    fn get_tor_api(&self) -> Option<&Arc<TorAPI>> {
+        self.tor_api.as_ref()
+    }
+
+    /// Replaces the onion service key and updates the tower addresses (and announcement) accordingly.
+    async fn set_onion_key(
+        &self,
+        sk: TorSecretKeyV3,
+    ) -> Result<Response<msgs::OnionServiceResponse>, Status> {
+        let tor_api = self.get_tor_api().ok_or_else(tor_disabled)?;
+        tor_api.set_sk(sk).await.map_err(|e| {
+            Status::new(
+                Code::Internal,
+                format!("Cannot update the onion service key: {e}"),
+            )
+        })?;
+        let onion_address = tor_api.get_onion_address();
+
+        let mut addresses = self.addresses.write().unwrap();
+        for address in addresses
+            .iter_mut()
+            .filter(|a| a.address_type == AddressType::TorV3 as i32)
+        {
+            address.address = onion_address.clone();
         }
+
+        let mut announcement = self.announcement.write().unwrap();
+        let mut updated = TowerAnnouncement::new(
+            announcement.tower_id(),
+            addresses.iter().map(|a| a.to_string()).collect(),
+            announcement.features().to_vec(),
+            announcement.min_to_self_delay(),
+            announcement.subscription_slots(),
+            announcement.subscription_duration(),
+        );
+        self.watcher.sign_announcement(&mut updated);
+        *announcement = updated;
+
+        Ok(Response::new(msgs::OnionServiceResponse { onion_address }))
     }
 
-    pub fn get_addresses(&self) -> &Vec<msgs::NetworkAddress> {
-        &self.addresses
+    /// Reloads the tower configuration, applying the options that can be changed live. The announcement is updated
+    /// (and signed again) if any of the subscription parameters it advertises have changed.
+    pub fn reload_tower_config(&self) -> Result<ReloadReport, ConfigError> {
+        let report = self.config_reloader.reload()?;
+        let conf = self.config_reloader.get_config();
+
+        let mut announcement = self.announcement.write().unwrap();
+        if announcement.min_to_self_delay() != conf.min_to_self_delay as u32
+            || announcement.subscription_slots() != conf.subscription_slots
+            || announcement.subscription_duration() != conf.subscription_duration
+        {
+            let mut updated = TowerAnnouncement::new(
+                announcement.tower_id(),
+                announcement.addresses().to_vec(),
+                announcement.features().to_vec(),
+                conf.min_to_self_delay as u32,
+                conf.subscription_slots,
+                conf.subscription_duration,
+            );
+            self.watcher.sign_announcement(&mut updated);
+            *announcement = updated;
+        }
+
+        if !report.applied.is_empty() {
+            tracing::info!("Config reloaded. Applied: {}", report.applied.join(", "));
+        }
+        if !report.requires_restart.is_empty() {
+            tracing::warn!(
+                "Config reloaded. Changes to {} require a restart",
+                report.requires_restart.join(", ")
+            );
+        }
+        Ok(report)
     }
 
     /// Checks whether bitcoind is reachable.
@@ -55,7 +276,7 @@ impl InternalAPI {
         if *self.bitcoind_reachable.0.lock().unwrap() {
             Ok(())
         } else {
-            log::error!("Bitcoind not reachable");
+            tracing::error!("Bitcoind not reachable");
             Err(Status::new(
                 Code::Unavailable,
                 "Service currently unavailable",
@@ -104,14 +325,18 @@ impl PublicTowerServices for Arc<InternalAPI> {
     ) -> Result<Response<common_msgs::AddAppointmentResponse>, Status> {
         self.check_service_unavailable()?;
         let req_data = request.into_inner();
-        let app_data = req_data.appointment.unwrap();
+        let app_data = req_data
+            .appointment
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "Missing appointment"))?;
+        let locator = Locator::from_slice(&app_data.locator).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "The provided locator does not match the expected format (16-byte hexadecimal string)",
+            )
+        })?;
 
-        let appointment = Appointment::new(
-            Locator::from_slice(&app_data.locator).unwrap(),
-            app_data.encrypted_blob,
-            app_data.to_self_delay,
-        );
-        let locator = appointment.locator;
+        let appointment =
+            Appointment::new(locator, app_data.encrypted_blob, app_data.to_self_delay);
 
         match self
             .watcher
@@ -144,6 +369,97 @@ impl PublicTowerServices for Arc<InternalAPI> {
         }
     }
 
+    /// Add appointments endpoint. Part of the public API. Internally calls [Watcher::add_appointments].
+    ///
+    /// Returns one result per requested appointment. Failures of individual appointments are reported within the
+    /// response instead of failing the whole request.
+    async fn add_appointments(
+        &self,
+        request: Request<common_msgs::AddAppointmentsRequest>,
+    ) -> Result<Response<common_msgs::AddAppointmentsResponse>, Status> {
+        self.check_service_unavailable()?;
+        let req_data = request.into_inner();
+
+        if req_data.appointments.is_empty() {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "At least one appointment must be provided",
+            ));
+        }
+
+        let mut appointments = Vec::with_capacity(req_data.appointments.len());
+        for r in req_data.appointments {
+            let app_data = r.appointment.ok_or_else(|| {
+                Status::new(Code::InvalidArgument, "Missing appointment in the batch")
+            })?;
+            let locator = Locator::from_slice(&app_data.locator).map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "The provided locator does not match the expected format (16-byte hexadecimal string)",
+                )
+            })?;
+            appointments.push((
+                Appointment::new(locator, app_data.encrypted_blob, app_data.to_self_delay),
+                r.signature,
+            ));
+        }
+        let locators: Vec<Locator> = appointments.iter().map(|(a, _)| a.locator).collect();
+
+        let results = self
+            .watcher
+            .add_appointments(appointments)
+            .into_iter()
+            .zip(locators)
+            .map(|(r, locator)| {
+                let result = match r {
+                    Ok((receipt, available_slots, subscription_expiry)) => {
+                        common_msgs::add_appointment_result::Result::Response(
+                            common_msgs::AddAppointmentResponse {
+                                locator: locator.to_vec(),
+                                start_block: receipt.start_block(),
+                                signature: receipt.signature().unwrap(),
+                                available_slots,
+                                subscription_expiry,
+                            },
+                        )
+                    }
+                    Err(e) => {
+                        let (error, error_code) = match e {
+                            AddAppointmentFailure::AuthenticationFailure
+                            | AddAppointmentFailure::NotEnoughSlots => (
+                                "Invalid signature or user does not have enough slots available"
+                                    .to_owned(),
+                                errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR,
+                            ),
+                            AddAppointmentFailure::SubscriptionExpired(x) => (
+                                format!("Your subscription expired at {x}"),
+                                errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR,
+                            ),
+                            AddAppointmentFailure::AlreadyTriggered => (
+                                "The provided appointment has already been triggered".to_owned(),
+                                errors::APPOINTMENT_ALREADY_TRIGGERED,
+                            ),
+                        };
+                        common_msgs::add_appointment_result::Result::Error(
+                            common_msgs::AddAppointmentError {
+                                locator: locator.to_vec(),
+                                error,
+                                error_code: error_code as u32,
+                            },
+                        )
+                    }
+                };
+                common_msgs::AddAppointmentResult {
+                    result: Some(result),
+                }
+            })
+            .collect();
+
+        Ok(Response::new(common_msgs::AddAppointmentsResponse {
+            results,
+        }))
+    }
+
     /// Get appointment endpoint. Part of the public API. Internally calls [Watcher::get_appointment].
     async fn get_appointment(
         &self,
@@ -151,35 +467,46 @@ impl PublicTowerServices for Arc<InternalAPI> {
     ) -> Result<Response<common_msgs::GetAppointmentResponse>, Status> {
         self.check_service_unavailable()?;
         let req_data = request.into_inner();
-        let locator = Locator::from_slice(&req_data.locator).unwrap();
+        let locator = Locator::from_slice(&req_data.locator).map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "The provided locator does not match the expected format (16-byte hexadecimal string)",
+            )
+        })?;
 
         match self.watcher.get_appointment(locator, &req_data.signature) {
             Ok(info) => {
-                let (appointment_data, status) = match info {
+                let (appointment_data, status, summary) = match info {
                     AppointmentInfo::Appointment(appointment) => (
-                        common_msgs::AppointmentData {
+                        Some(common_msgs::AppointmentData {
                             appointment_data: Some(
                                 common_msgs::appointment_data::AppointmentData::Appointment(
                                     appointment.into(),
                                 ),
                             ),
-                        },
+                        }),
                         AppointmentStatus::BeingWatched,
+                        None,
                     ),
                     AppointmentInfo::Tracker(tracker) => (
-                        common_msgs::AppointmentData {
+                        Some(common_msgs::AppointmentData {
                             appointment_data: Some(
                                 common_msgs::appointment_data::AppointmentData::Tracker(
                                     tracker.into(),
                                 ),
                             ),
-                        },
+                        }),
                         AppointmentStatus::DisputeResponded,
+                        None,
                     ),
+                    AppointmentInfo::Summary(summary) => {
+                        (None, AppointmentStatus::Resolved, Some(summary.into()))
+                    }
                 };
                 Ok(Response::new(common_msgs::GetAppointmentResponse {
-                    appointment_data: Some(appointment_data),
+                    appointment_data,
                     status: status as i32,
+                    summary,
                 }))
             }
             Err(e) => match e {
@@ -224,56 +551,263 @@ impl PublicTowerServices for Arc<InternalAPI> {
             locators: locators.iter().map(|x| x.to_vec()).collect(),
         }))
     }
+
+    /// Delete appointment endpoint. Part of the public API. Internally calls [Watcher::delete_appointments].
+    async fn delete_appointment(
+        &self,
+        request: Request<common_msgs::DeleteAppointmentRequest>,
+    ) -> Result<Response<common_msgs::DeleteAppointmentResponse>, Status> {
+        self.check_service_unavailable()?;
+        let req_data = request.into_inner();
+
+        if req_data.locators.is_empty() {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "At least one locator must be provided",
+            ));
+        }
+
+        let locators = req_data
+            .locators
+            .iter()
+            .map(|l| Locator::from_slice(l))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "The provided locators do not match the expected format (16-byte hexadecimal string)",
+                )
+            })?;
+
+        match self
+            .watcher
+            .delete_appointments(locators, req_data.nonce, req_data.signature)
+        {
+            Ok(receipt) => Ok(Response::new(common_msgs::DeleteAppointmentResponse {
+                locators: receipt.locators().iter().map(|l| l.to_vec()).collect(),
+                available_slots: receipt.available_slots(),
+                signature: receipt.signature().unwrap(),
+            })),
+            Err(e) => match e {
+                DeleteAppointmentFailure::AuthenticationFailure => Err(Status::new(
+                    Code::Unauthenticated,
+                    "User cannot be authenticated",
+                )),
+                DeleteAppointmentFailure::InvalidNonce => Err(Status::new(
+                    Code::InvalidArgument,
+                    "The nonce must be greater than the last one used",
+                )),
+                DeleteAppointmentFailure::SubscriptionExpired(x) => Err(Status::new(
+                    Code::Unauthenticated,
+                    format!("Your subscription expired at {x}"),
+                )),
+                DeleteAppointmentFailure::NotFound(locator) => Err(Status::new(
+                    Code::NotFound,
+                    format!("Appointment not found: {locator}"),
+                )),
+                DeleteAppointmentFailure::AlreadyTriggered(locator) => Err(Status::new(
+                    Code::AlreadyExists,
+                    format!("Appointment {locator} has already been triggered"),
+                )),
+            },
+        }
+    }
+
+    /// Register webhook endpoint. Part of the public API. Internally calls [Watcher::register_webhook].
+    async fn register_webhook(
+        &self,
+        request: Request<common_msgs::RegisterWebhookRequest>,
+    ) -> Result<Response<common_msgs::RegisterWebhookResponse>, Status> {
+        self.check_service_unavailable()?;
+        let req_data = request.into_inner();
+
+        if !req_data.url.is_empty() {
+            match reqwest::Url::parse(&req_data.url) {
+                Ok(url) if ["http", "https"].contains(&url.scheme()) => (),
+                _ => {
+                    return Err(Status::new(
+                        Code::InvalidArgument,
+                        "The provided url is not a valid http(s) url",
+                    ))
+                }
+            }
+        }
+
+        match self
+            .watcher
+            .register_webhook(&req_data.url, req_data.nonce, &req_data.signature)
+            .await
+        {
+            Ok(user_id) => Ok(Response::new(common_msgs::RegisterWebhookResponse {
+                user_id: user_id.to_vec(),
+                url: req_data.url,
+            })),
+            Err(e) => match e {
+                RegisterWebhookFailure::AuthenticationFailure => Err(Status::new(
+                    Code::Unauthenticated,
+                    "User not found. Have you registered?",
+                )),
+                RegisterWebhookFailure::InvalidNonce => Err(Status::new(
+                    Code::InvalidArgument,
+                    "The nonce must be greater than the last one used",
+                )),
+                RegisterWebhookFailure::SubscriptionExpired(x) => Err(Status::new(
+                    Code::Unauthenticated,
+                    format!("Your subscription expired at {x}"),
+                )),
+                RegisterWebhookFailure::InvalidUrl(e) => Err(Status::new(
+                    Code::InvalidArgument,
+                    match e {
+                        WebhookUrlError::InvalidUrl => {
+                            "The provided url is not a valid http(s) url"
+                        }
+                        WebhookUrlError::Unresolvable => "The provided url cannot be resolved",
+                        WebhookUrlError::NonPublicAddress => {
+                            "The provided url does not point to a public address"
+                        }
+                    },
+                )),
+                RegisterWebhookFailure::StorageFailure => {
+                    Err(Status::new(Code::Internal, "The webhook cannot be stored"))
+                }
+            },
+        }
+    }
+
+    /// Get tower info endpoint. Gets the tower announcement, signed by the tower. Part of the public API.
+    /// Does not require bitcoind to be reachable, so users can learn about the tower even if it is temporarily unavailable.
+    async fn get_tower_info(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<common_msgs::TowerAnnouncement>, Status> {
+        tracing::debug!(
+            "Received a public get_tower_info request from {}",
+            request
+                .remote_addr()
+                .map_or("an unknown address".to_owned(), |a| a.to_string())
+        );
+
+        Ok(Response::new(
+            self.announcement.read().unwrap().clone().into(),
+        ))
+    }
+
+    /// Get key handoffs endpoint. Gets the handoffs signed by the tower when rotating its key, so users can follow the
+    /// tower to its new id. Part of the public API.
+    async fn get_key_handoffs(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<common_msgs::GetKeyHandoffsResponse>, Status> {
+        tracing::debug!(
+            "Received a get_key_handoffs request from {}",
+            request
+                .remote_addr()
+                .map_or("an unknown address".to_owned(), |a| a.to_string())
+        );
+
+        Ok(Response::new(common_msgs::GetKeyHandoffsResponse {
+            handoffs: self
+                .watcher
+                .get_key_handoffs()
+                .into_iter()
+                .map(|handoff| handoff.into())
+                .collect(),
+        }))
+    }
 }
 
 /// Private tower API. Only accessible by the tower admin via RPC.
 #[tonic::async_trait]
 impl PrivateTowerServices for Arc<InternalAPI> {
-    /// Get all appointments endpoint. Gets all appointments in the tower. Part of the private API.
-    /// Internally calls [Watcher::get_all_watcher_appointments] and [Watcher::get_all_responder_trackers].
+    /// Get all appointments endpoint. Gets a page of the appointments in the tower, optionally filtered.
+    /// Part of the private API. Internally calls [Watcher::get_appointments_page].
     async fn get_all_appointments(
         &self,
-        request: Request<()>,
+        request: Request<msgs::GetAllAppointmentsRequest>,
     ) -> Result<Response<msgs::GetAllAppointmentsResponse>, Status> {
-        log::debug!(
+        tracing::debug!(
             "Received a get_all_appointments request from {}",
             request
                 .remote_addr()
                 .map_or("an unknown address".to_owned(), |a| a.to_string())
         );
 
-        let mut all_appointments = Vec::new();
-
-        for (_, appointment) in self.watcher.get_all_watcher_appointments().into_iter() {
-            all_appointments.push(common_msgs::AppointmentData {
-                appointment_data: Some(
-                    common_msgs::appointment_data::AppointmentData::Appointment(
-                        appointment.inner.into(),
-                    ),
-                ),
-            })
-        }
-
-        for (_, tracker) in self.watcher.get_all_responder_trackers().into_iter() {
-            all_appointments.push(common_msgs::AppointmentData {
-                appointment_data: Some(common_msgs::appointment_data::AppointmentData::Tracker(
-                    tracker.into(),
-                )),
-            })
-        }
+        let req_data = request.into_inner();
+        let (cursor, filter) = parse_appointments_request(&req_data)
+            .map_err(|e| Status::new(Code::InvalidArgument, e))?;
+        let limit = match req_data.limit as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        };
+
+        // Ask for an extra appointment to know whether there is a next page.
+        let mut page = self
+            .watcher
+            .get_appointments_page(&filter, cursor, limit + 1);
+        let next_cursor = if page.len() > limit {
+            page.truncate(limit);
+            page.last().unwrap().0.to_vec()
+        } else {
+            Vec::new()
+        };
 
         Ok(Response::new(msgs::GetAllAppointmentsResponse {
-            appointments: all_appointments,
+            appointments: page
+                .into_iter()
+                .map(|(_, record)| appointment_data(record))
+                .collect(),
+            next_cursor,
         }))
     }
 
+    type export_appointmentsStream =
+        Pin<Box<dyn Stream<Item = Result<common_msgs::AppointmentData, Status>> + Send + 'static>>;
+
+    /// Export appointments endpoint. Streams all the appointments in the tower matching the given filters.
+    /// Appointments are loaded from the database in pages so the tower does not need to hold them all in memory.
+    /// Part of the private API. Internally calls [Watcher::get_appointments_page].
+    async fn export_appointments(
+        &self,
+        request: Request<msgs::GetAllAppointmentsRequest>,
+    ) -> Result<Response<Self::export_appointmentsStream>, Status> {
+        tracing::debug!(
+            "Received an export_appointments request from {}",
+            request
+                .remote_addr()
+                .map_or("an unknown address".to_owned(), |a| a.to_string())
+        );
+
+        let (cursor, filter) = parse_appointments_request(&request.into_inner())
+            .map_err(|e| Status::new(Code::InvalidArgument, e))?;
+        let watcher = self.watcher.clone();
+
+        // The state is the cursor of the next page to load, or None once the last page has been loaded.
+        let appointments = stream::unfold(Some(cursor), move |next| {
+            let watcher = watcher.clone();
+            let filter = filter.clone();
+            async move {
+                let cursor = next?;
+                let page = watcher.get_appointments_page(&filter, cursor, MAX_PAGE_SIZE);
+                let next =
+                    (page.len() == MAX_PAGE_SIZE).then(|| page.last().map(|(uuid, _)| *uuid));
+                let page = page.into_iter().map(|(_, record)| appointment_data(record));
+                Some((stream::iter(page), next))
+            }
+        })
+        .flatten()
+        .map(Ok)
+        .take_until(self.shutdown_signal.clone());
+
+        Ok(Response::new(Box::pin(appointments)))
+    }
+
     /// Get appointments endpoint. Gets the appointments with a specific locator. Part of the private API.
     /// Internally calls [Watcher::get_watcher_appointments_using_locator] and [Watcher::get_responder_trackers_using_locator].
     async fn get_appointments(
         &self,
         request: tonic::Request<msgs::GetAppointmentsRequest>,
     ) -> Result<tonic::Response<msgs::GetAppointmentsResponse>, Status> {
-        log::debug!(
+        tracing::debug!(
             "Received a get_appointments requests from {}",
             request
                 .remote_addr()
@@ -319,6 +853,92 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         }))
     }
 
+    /// Get events endpoint. Gets the audit history of the tower, optionally filtered by UUID and/or locator.
+    /// Part of the private API. Internally calls [Watcher::get_events].
+    async fn get_events(
+        &self,
+        request: Request<msgs::GetEventsRequest>,
+    ) -> Result<Response<msgs::GetEventsResponse>, Status> {
+        tracing::debug!(
+            "Received a get_events request from {}",
+            request
+                .remote_addr()
+                .map_or("an unknown address".to_owned(), |a| a.to_string())
+        );
+
+        let req_data = request.into_inner();
+        let uuid = if req_data.uuid.is_empty() {
+            None
+        } else {
+            Some(UUID::from_slice(&req_data.uuid).map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "The provided uuid does not match the expected format (20-byte hexadecimal string)",
+                )
+            })?)
+        };
+        let locator = if req_data.locator.is_empty() {
+            None
+        } else {
+            Some(Locator::from_slice(&req_data.locator).map_err(|_| {
+                Status::new(
+                    Code::InvalidArgument,
+                    "The provided locator does not match the expected format (16-byte hexadecimal string)",
+                )
+            })?)
+        };
+
+        Ok(Response::new(msgs::GetEventsResponse {
+            events: self
+                .watcher
+                .get_events(uuid, locator)
+                .into_iter()
+                .map(|event| event.into())
+                .collect(),
+        }))
+    }
+
+    type subscribe_eventsStream =
+        Pin<Box<dyn Stream<Item = Result<msgs::TowerEvent, Status>> + Send + 'static>>;
+
+    /// Subscribe events endpoint. Streams the events published to the live feed of the tower, optionally filtered by
+    /// event type, until the tower shuts down. Part of the private API.
+    async fn subscribe_events(
+        &self,
+        request: Request<msgs::SubscribeEventsRequest>,
+    ) -> Result<Response<Self::subscribe_eventsStream>, Status> {
+        tracing::debug!(
+            "Received a subscribe_events request from {}",
+            request
+                .remote_addr()
+                .map_or("an unknown address".to_owned(), |a| a.to_string())
+        );
+
+        let event_types = request
+            .into_inner()
+            .event_types
+            .iter()
+            .map(|event_type| FeedEventKind::from_str(event_type))
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(|e| Status::new(Code::InvalidArgument, e))?;
+
+        let events = BroadcastStream::new(self.feed.subscribe())
+            .filter_map(move |event| {
+                future::ready(match event {
+                    Ok(event) => (event_types.is_empty() || event_types.contains(&event.kind))
+                        .then(|| event.into()),
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "Event feed subscriber lagged behind");
+                        None
+                    }
+                })
+            })
+            .map(Ok)
+            .take_until(self.shutdown_signal.clone());
+
+        Ok(Response::new(Box::pin(events)))
+    }
+
     /// Get tower info endpoint. Gets information about the tower state. Part of the private API.
     /// Internally calls [Watcher::get_registered_users_count], [Watcher::get_appointments_count]
     /// and [Watcher::get_trackers_count].
@@ -326,7 +946,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         &self,
         request: Request<()>,
     ) -> Result<Response<msgs::GetTowerInfoResponse>, Status> {
-        log::debug!(
+        tracing::debug!(
             "Received a get_tower_info request from {}",
             request
                 .remote_addr()
@@ -335,7 +955,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
 
         Ok(Response::new(msgs::GetTowerInfoResponse {
             tower_id: self.watcher.tower_id.to_vec(),
-            addresses: self.get_addresses().clone(),
+            addresses: self.get_addresses(),
             n_registered_users: self.watcher.get_registered_users_count() as u32,
             n_watcher_appointments: self.watcher.get_appointments_count() as u32,
             n_responder_trackers: self.watcher.get_trackers_count() as u32,
@@ -349,7 +969,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         &self,
         request: Request<()>,
     ) -> Result<Response<msgs::GetUsersResponse>, Status> {
-        log::debug!(
+        tracing::debug!(
             "Received a get_users requests from {}",
             request
                 .remote_addr()
@@ -372,7 +992,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         &self,
         request: Request<msgs::GetUserRequest>,
     ) -> Result<Response<msgs::GetUserResponse>, Status> {
-        log::debug!(
+        tracing::debug!(
             "Received a get_user request from {}",
             request
                 .remote_addr()
@@ -404,7 +1024,7 @@ impl PrivateTowerServices for Arc<InternalAPI> {
     async fn stop(&self, request: Request<()>) -> Result<Response<()>, Status> {
         self.shutdown_trigger.trigger();
 
-        log::debug!(
+        tracing::debug!(
             "Received a shutting down request from {}, notifying components",
             request
                 .remote_addr()
@@ -412,44 +1032,167 @@ impl PrivateTowerServices for Arc<InternalAPI> {
         );
         Ok(Response::new(()))
     }
-}
 
-#[cfg(test)]
-mod tests_private_helpers {
-    use super::*;
+    /// Export onion key endpoint. Gets the secret key of the tower onion service, so it can be backed up.
+    /// Part of the private API.
+    async fn export_onion_key(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::ExportOnionKeyResponse>, Status> {
+        let tor_api = self.get_tor_api().ok_or_else(tor_disabled)?;
 
-    impl InternalAPI {
-        pub(crate) fn get_watcher(&self) -> &Watcher {
-            &self.watcher
-        }
+        Ok(Response::new(msgs::ExportOnionKeyResponse {
+            onion_address: tor_api.get_onion_address(),
+            secret_key: tor_api.get_sk().as_bytes().to_vec(),
+        }))
     }
-}
 
-#[cfg(test)]
-mod tests_private_api {
-    use super::*;
-    use std::collections::HashSet;
-    use std::iter::FromIterator;
-
-    use bitcoin::hashes::Hash;
-    use bitcoin::Txid;
+    /// Import onion key endpoint. Replaces the secret key of the tower onion service by a given one, for instance
+    /// to restore a backed up key. Part of the private API.
+    async fn import_onion_key(
+        &self,
+        request: Request<msgs::ImportOnionKeyRequest>,
+    ) -> Result<Response<msgs::OnionServiceResponse>, Status> {
+        self.get_tor_api().ok_or_else(tor_disabled)?;
+        let sk: [u8; 64] = request.into_inner().secret_key.try_into().map_err(|_| {
+            Status::new(
+                Code::InvalidArgument,
+                "Onion service keys must be 64-byte expanded ed25519 keys",
+            )
+        })?;
 
-    use crate::responder::{ConfirmationStatus, TransactionTracker};
-    use crate::test_utils::{
-        create_api, generate_dummy_appointment, generate_dummy_appointment_with_user,
-        get_random_tx, DURATION, SLOTS, START_HEIGHT,
-    };
-    use crate::watcher::Breach;
+        self.set_onion_key(TorSecretKeyV3::from(sk)).await
+    }
 
-    use teos_common::cryptography::{self, get_random_keypair};
-    use teos_common::test_utils::get_random_user_id;
+    /// Rotate onion key endpoint. Replaces the secret key of the tower onion service by a fresh one, which changes
+    /// the tower onion address. Part of the private API.
+    async fn rotate_onion_key(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::OnionServiceResponse>, Status> {
+        self.set_onion_key(TorSecretKeyV3::generate()).await
+    }
 
-    #[tokio::test]
-    async fn test_get_all_appointments() {
+    /// Create backup endpoint. Creates an encrypted backup of the tower (database snapshot, keys and certificates)
+    /// while it keeps running. Part of the private API.
+    async fn create_backup(
+        &self,
+        request: Request<msgs::CreateBackupRequest>,
+    ) -> Result<Response<msgs::CreateBackupResponse>, Status> {
+        let (encryption, path) = parse_backup_request(request.into_inner())
+            .map_err(|e| Status::new(Code::InvalidArgument, e))?;
+
+        let backup_manager = self.backup_manager.clone();
+        let (path, last_known_block) =
+            tokio::task::spawn_blocking(move || backup_manager.create_backup(&encryption, path))
+                .await
+                .unwrap()
+                .map_err(|e| match e {
+                    BackupError::AlreadyExists(_) => {
+                        Status::new(Code::AlreadyExists, e.to_string())
+                    }
+                    _ => Status::new(Code::Internal, format!("Cannot create backup: {e}")),
+                })?;
+
+        Ok(Response::new(msgs::CreateBackupResponse {
+            size: std::fs::metadata(&path).map_or(0, |m| m.len()),
+            path: path.display().to_string(),
+            last_known_block: last_known_block.map_or(Vec::new(), |b| b.to_vec()),
+        }))
+    }
+
+    /// Change key passphrase endpoint. Changes the passphrase the tower keys are encrypted with (encrypting them if
+    /// they were not). Part of the private API.
+    async fn change_key_passphrase(
+        &self,
+        request: Request<msgs::ChangeKeyPassphraseRequest>,
+    ) -> Result<Response<()>, Status> {
+        let req_data = request.into_inner();
+        if req_data.new_passphrase.is_empty() {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "The new passphrase cannot be empty",
+            ));
+        }
+
+        // Deriving the encryption keys is expensive by design, so it is done in a blocking task
+        let watcher = self.watcher.clone();
+        tokio::task::spawn_blocking(move || {
+            let old_passphrase =
+                (!req_data.old_passphrase.is_empty()).then_some(req_data.old_passphrase);
+            watcher.change_key_passphrase(old_passphrase.as_deref(), &req_data.new_passphrase)
+        })
+        .await
+        .unwrap()
+        .map_err(|e| match e {
+            KeyDecryptionError::InvalidKey => {
+                Status::new(Code::Internal, format!("Cannot change key passphrase: {e}"))
+            }
+            _ => Status::new(Code::PermissionDenied, e.to_string()),
+        })?;
+
+        tracing::info!("Tower key passphrase changed");
+        Ok(Response::new(()))
+    }
+
+    /// Reload config endpoint. Re-reads the tower configuration and applies the options that can be changed without
+    /// restarting the tower, reporting the ones that cannot. Part of the private API.
+    async fn reload_config(
+        &self,
+        _: Request<()>,
+    ) -> Result<Response<msgs::ReloadConfigResponse>, Status> {
+        let report = self
+            .reload_tower_config()
+            .map_err(|e| Status::new(Code::FailedPrecondition, e.to_string()))?;
+
+        Ok(Response::new(msgs::ReloadConfigResponse {
+            applied: report.applied,
+            requires_restart: report.requires_restart,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests_private_helpers {
+    use super::*;
+
+    impl InternalAPI {
+        pub(crate) fn get_watcher(&self) -> &Watcher {
+            &self.watcher
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_private_api {
+    use super::*;
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+
+    use tempdir::TempDir;
+
+    use crate::api::tor::TorControlAuth;
+    use crate::events::EventKind;
+    use crate::responder::{ConfirmationStatus, TransactionTracker};
+    use crate::test_utils::{
+        create_api, create_api_with_config, generate_dummy_appointment,
+        generate_dummy_appointment_with_user, generate_uuid, get_random_tx, ApiConfig,
+        BitcoindStopper, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+    };
+    use crate::watcher::Breach;
+
+    use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::test_utils::{get_random_locator, get_random_user_id};
+
+    #[tokio::test]
+    async fn test_get_all_appointments() {
         let (internal_api, _s) = create_api().await;
 
         let response = internal_api
-            .get_all_appointments(Request::new(()))
+            .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest::default()))
             .await
             .unwrap()
             .into_inner();
@@ -473,7 +1216,7 @@ mod tests_private_api {
             .unwrap();
 
         let response = internal_api
-            .get_all_appointments(Request::new(()))
+            .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest::default()))
             .await
             .unwrap()
             .into_inner();
@@ -493,7 +1236,7 @@ mod tests_private_api {
         internal_api.watcher.add_random_tracker_to_responder();
 
         let response = internal_api
-            .get_all_appointments(Request::new(()))
+            .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest::default()))
             .await
             .unwrap()
             .into_inner();
@@ -505,6 +1248,194 @@ mod tests_private_api {
         ));
     }
 
+    #[tokio::test]
+    async fn test_get_all_appointments_pagination() {
+        let (internal_api, _s) = create_api().await;
+
+        let mut cursors = HashSet::new();
+        for _ in 0..5 {
+            internal_api.watcher.add_random_tracker_to_responder();
+        }
+
+        // Pages are chained using the returned cursor until it comes back empty
+        let mut cursor = Vec::new();
+        let mut pages = 0;
+        loop {
+            let response = internal_api
+                .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest {
+                    cursor: cursor.clone(),
+                    limit: 2,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            pages += 1;
+
+            if response.next_cursor.is_empty() {
+                assert_eq!(response.appointments.len(), 1);
+                break;
+            }
+            assert_eq!(response.appointments.len(), 2);
+            assert!(cursors.insert(response.next_cursor.clone()));
+            cursor = response.next_cursor;
+        }
+        assert_eq!(pages, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_all_appointments_filters() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id).unwrap();
+        let appointment = generate_dummy_appointment(None).inner;
+        let user_signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        internal_api
+            .watcher
+            .add_appointment(appointment, user_signature)
+            .unwrap();
+        // The tracker is confirmed
+        internal_api.watcher.add_random_tracker_to_responder();
+
+        for (request, expected) in [
+            (msgs::GetAllAppointmentsRequest::default(), 2),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    user_id: user_id.to_vec(),
+                    ..Default::default()
+                },
+                1,
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    status: "watching".to_owned(),
+                    ..Default::default()
+                },
+                1,
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    status: "responded".to_owned(),
+                    ..Default::default()
+                },
+                1,
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    confirmation_status: "confirmed".to_owned(),
+                    ..Default::default()
+                },
+                1,
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    confirmation_status: "in_mempool".to_owned(),
+                    ..Default::default()
+                },
+                0,
+            ),
+        ] {
+            let response = internal_api
+                .get_all_appointments(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.appointments.len(), expected);
+        }
+
+        // The watching appointment is the one of the user, the responded one is the tracker
+        let response = internal_api
+            .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest {
+                user_id: user_id.to_vec(),
+                status: "watching".to_owned(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(matches!(
+            response.appointments[0].appointment_data,
+            Some(common_msgs::appointment_data::AppointmentData::Appointment { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_all_appointments_wrong_arguments() {
+        let (internal_api, _s) = create_api().await;
+
+        for (request, message) in [
+            (
+                msgs::GetAllAppointmentsRequest {
+                    cursor: vec![1; 10],
+                    ..Default::default()
+                },
+                "The provided cursor does not match the expected format",
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    user_id: vec![1; 10],
+                    ..Default::default()
+                },
+                "The provided user_id does not match the expected format",
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    status: "unknown".to_owned(),
+                    ..Default::default()
+                },
+                "Unknown status",
+            ),
+            (
+                msgs::GetAllAppointmentsRequest {
+                    confirmation_status: "unknown".to_owned(),
+                    ..Default::default()
+                },
+                "Unknown confirmation_status",
+            ),
+        ] {
+            match internal_api
+                .get_all_appointments(Request::new(request))
+                .await
+            {
+                Err(status) => {
+                    assert_eq!(status.code(), Code::InvalidArgument);
+                    assert!(status.message().starts_with(message));
+                }
+                _ => panic!("Test should have returned an error"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_appointments() {
+        let (internal_api, _s) = create_api().await;
+
+        for _ in 0..3 {
+            internal_api.watcher.add_random_tracker_to_responder();
+        }
+
+        let appointments = internal_api
+            .export_appointments(Request::new(msgs::GetAllAppointmentsRequest {
+                status: "responded".to_owned(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(appointments.len(), 3);
+        for appointment in appointments {
+            assert!(matches!(
+                appointment.unwrap().appointment_data,
+                Some(common_msgs::appointment_data::AppointmentData::Tracker { .. })
+            ));
+        }
+    }
+
     #[tokio::test]
     async fn test_get_appointments() {
         let (internal_api, _s) = create_api().await;
@@ -624,8 +1555,7 @@ mod tests_private_api {
     async fn test_get_tower_info_empty() {
         let (internal_api, _s) = create_api().await;
 
-        let response = internal_api
-            .get_tower_info(Request::new(()))
+        let response = PrivateTowerServices::get_tower_info(&internal_api, Request::new(()))
             .await
             .unwrap()
             .into_inner();
@@ -660,15 +1590,15 @@ mod tests_private_api {
             internal_api.watcher.add_random_tracker_to_responder();
         }
 
-        let response = internal_api
-            .get_tower_info(Request::new(()))
+        let response = PrivateTowerServices::get_tower_info(&internal_api, Request::new(()))
             .await
             .unwrap()
             .into_inner();
 
         // Given get_tower_info checks data in memory, the data added to the Responder in the test won't be added to the Watcher too.
         assert_eq!(response.tower_id, internal_api.watcher.tower_id.to_vec());
-        assert_eq!(response.n_registered_users, 1);
+        // Users are counted from the database, where every tracker added to the Responder comes with its own user.
+        assert_eq!(response.n_registered_users, 4);
         assert_eq!(response.n_watcher_appointments, 2);
         assert_eq!(response.n_responder_trackers, 3);
     }
@@ -772,154 +1702,550 @@ mod tests_private_api {
     }
 
     #[tokio::test]
-    async fn test_stop() {
-        let (internal_api, _s) = create_api().await;
-
-        assert!(!internal_api.shutdown_trigger.is_triggered());
-        internal_api.stop(Request::new(())).await.unwrap();
-        assert!(internal_api.shutdown_trigger.is_triggered());
-    }
-}
-
-#[cfg(test)]
-mod tests_public_api {
-    use super::*;
-
-    use crate::responder::{ConfirmationStatus, TransactionTracker};
-    use crate::test_utils::{
-        create_api, create_api_with_config, generate_dummy_appointment, get_random_tx, ApiConfig,
-        DURATION, SLOTS,
-    };
-    use crate::watcher::Breach;
-    use teos_common::cryptography::{self, get_random_keypair};
-
-    #[tokio::test]
-    async fn test_register() {
+    async fn test_get_events() {
         let (internal_api, _s) = create_api().await;
 
-        let (_, user_pk) = get_random_keypair();
+        let tracker = internal_api.watcher.add_random_tracker_to_responder();
+        let uuid = generate_uuid();
+        let events = [
+            tracker.get_event(uuid, EventKind::PenaltyBroadcast, START_HEIGHT as u32),
+            tracker.get_event(generate_uuid(), EventKind::Completed, START_HEIGHT as u32),
+        ];
+        for event in events.iter() {
+            internal_api.watcher.add_dummy_event(event);
+        }
 
-        // Registering (even multiple times) should work
-        for _ in 0..2 {
+        // Events can be filtered by uuid and/or locator
+        for (uuid, locator, expected) in [
+            (vec![], vec![], events.to_vec()),
+            (uuid.to_vec(), vec![], events[..1].to_vec()),
+            (vec![], events[0].locator.to_vec(), events.to_vec()),
+            (vec![], get_random_locator().to_vec(), vec![]),
+        ] {
             let response = internal_api
-                .register(Request::new(common_msgs::RegisterRequest {
-                    user_id: UserId(user_pk).to_vec(),
-                }))
+                .get_events(Request::new(msgs::GetEventsRequest { uuid, locator }))
                 .await
                 .unwrap()
                 .into_inner();
-
-            assert!(matches!(response, common_msgs::RegisterResponse { .. }))
+            assert_eq!(
+                response.events,
+                expected
+                    .into_iter()
+                    .map(msgs::Event::from)
+                    .collect::<Vec<_>>()
+            );
         }
     }
 
     #[tokio::test]
-    async fn test_register_wrong_user_id() {
+    async fn test_get_events_wrong_length() {
         let (internal_api, _s) = create_api().await;
 
-        let mut user_ids = Vec::new();
-
-        // Wrong user id size
-        let (_, user_pk) = get_random_keypair();
-        let mut user_id_vec = UserId(user_pk).to_vec();
-        user_id_vec.pop();
-        user_ids.push(user_id_vec);
-
-        // Wrong format (does not start with 2 nor 3)
-        user_id_vec = UserId(user_pk).to_vec();
-        user_id_vec[0] = 1;
-        user_ids.push(user_id_vec);
-
-        for user_id in user_ids {
+        for (uuid, locator) in [(vec![1; 19], vec![]), (vec![], vec![1; 15])] {
             match internal_api
-                .register(Request::new(common_msgs::RegisterRequest { user_id }))
+                .get_events(Request::new(msgs::GetEventsRequest { uuid, locator }))
                 .await
             {
-                Err(status) => {
-                    assert_eq!(status.code(), Code::InvalidArgument);
-                    assert_eq!(status.message(), "Provided public key does not match expected format (33-byte compressed key)")
-                }
+                Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
                 _ => panic!("Test should have returned Err"),
             }
         }
     }
 
+    async fn create_api_with_tor() -> (Arc<InternalAPI>, BitcoindStopper, TempDir) {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let tor_api = TorAPI::new(
+            "127.0.0.1:9814".parse().unwrap(),
+            9814,
+            9000,
+            TorControlAuth::Auto,
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+        let (internal_api, s) =
+            create_api_with_config(ApiConfig::default().with_tor_api(Arc::new(tor_api))).await;
+
+        (internal_api, s, tmp_path)
+    }
+
+    fn get_announcement(internal_api: &InternalAPI) -> TowerAnnouncement {
+        internal_api.announcement.read().unwrap().clone()
+    }
+
     #[tokio::test]
-    async fn test_register_max_slots() {
-        let (internal_api, _s) = create_api_with_config(ApiConfig::new(u32::MAX, DURATION)).await;
+    async fn test_onion_key_tor_disabled() {
+        let (internal_api, _s) = create_api().await;
 
-        let (_, user_pk) = get_random_keypair();
-        let user_id = UserId(user_pk).to_vec();
+        for status in [
+            internal_api
+                .export_onion_key(Request::new(()))
+                .await
+                .unwrap_err(),
+            internal_api
+                .import_onion_key(Request::new(msgs::ImportOnionKeyRequest {
+                    secret_key: TorSecretKeyV3::generate().as_bytes().to_vec(),
+                }))
+                .await
+                .unwrap_err(),
+            internal_api
+                .rotate_onion_key(Request::new(()))
+                .await
+                .unwrap_err(),
+        ] {
+            assert_eq!(status.code(), Code::FailedPrecondition);
+            assert_eq!(status.message(), "Tor support is not enabled");
+        }
+    }
 
-        // First registration should go trough
-        internal_api
-            .register(Request::new(common_msgs::RegisterRequest {
-                user_id: user_id.clone(),
-            }))
+    #[tokio::test]
+    async fn test_export_import_onion_key() {
+        let (internal_api, _s, _tmp_path) = create_api_with_tor().await;
+        let tor_api = internal_api.tor_api.clone().unwrap();
+
+        let response = internal_api
+            .export_onion_key(Request::new(()))
             .await
-            .unwrap();
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.onion_address, tor_api.get_onion_address());
+        assert_eq!(response.secret_key, tor_api.get_sk().as_bytes().to_vec());
 
-        // Trying to add more slots (re-register) must fail
-        match internal_api
-            .register(Request::new(common_msgs::RegisterRequest { user_id }))
+        // Importing a key updates both the tower addresses and the announcement
+        let sk = TorSecretKeyV3::generate();
+        let onion_address = sk.public().get_onion_address().to_string();
+        let response = internal_api
+            .import_onion_key(Request::new(msgs::ImportOnionKeyRequest {
+                secret_key: sk.as_bytes().to_vec(),
+            }))
             .await
-        {
-            Err(status) => {
-                assert_eq!(status.code(), Code::ResourceExhausted);
-                assert_eq!(status.message(), "Subscription maximum slots count reached")
-            }
-            _ => panic!("Test should have returned Err"),
-        }
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.onion_address, onion_address);
+        assert_eq!(tor_api.get_sk(), sk);
+
+        let addresses = internal_api.get_addresses();
+        let onion = addresses
+            .iter()
+            .find(|a| a.address_type == AddressType::TorV3 as i32)
+            .unwrap();
+        assert_eq!(onion.address, onion_address);
+
+        let announcement = get_announcement(&internal_api);
+        assert!(announcement.verify());
+        assert_eq!(
+            announcement.addresses(),
+            addresses
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .as_slice()
+        );
     }
 
     #[tokio::test]
-    async fn test_register_service_unavailable() {
-        let (internal_api, _s) =
-            create_api_with_config(ApiConfig::new(u32::MAX, DURATION).bitcoind_unreachable()).await;
-
-        let (_, user_pk) = get_random_keypair();
-        let user_id = UserId(user_pk).to_vec();
+    async fn test_import_onion_key_wrong_length() {
+        let (internal_api, _s, _tmp_path) = create_api_with_tor().await;
+        let sk = internal_api.tor_api.as_ref().unwrap().get_sk();
 
         match internal_api
-            .register(Request::new(common_msgs::RegisterRequest { user_id }))
+            .import_onion_key(Request::new(msgs::ImportOnionKeyRequest {
+                secret_key: vec![1; 32],
+            }))
             .await
         {
             Err(status) => {
-                assert_eq!(status.code(), Code::Unavailable);
-                assert_eq!(status.message(), "Service currently unavailable")
+                assert_eq!(status.code(), Code::InvalidArgument);
             }
             _ => panic!("Test should have returned Err"),
         }
+        assert_eq!(internal_api.tor_api.as_ref().unwrap().get_sk(), sk);
     }
 
     #[tokio::test]
-    async fn test_add_appointment() {
-        let (internal_api, _s) = create_api().await;
-
-        // User must be registered
-        let (user_sk, user_pk) = get_random_keypair();
-        internal_api.watcher.register(UserId(user_pk)).unwrap();
-
-        let appointment = generate_dummy_appointment(None).inner;
-        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+    async fn test_rotate_onion_key() {
+        let (internal_api, _s, _tmp_path) = create_api_with_tor().await;
+        let old_address = internal_api.tor_api.as_ref().unwrap().get_onion_address();
+        let old_announcement = get_announcement(&internal_api);
 
         let response = internal_api
-            .add_appointment(Request::new(common_msgs::AddAppointmentRequest {
-                appointment: Some(appointment.clone().into()),
-                signature,
-            }))
+            .rotate_onion_key(Request::new(()))
             .await
             .unwrap()
             .into_inner();
+        assert_ne!(response.onion_address, old_address);
 
-        assert!(matches!(
-            response,
-            common_msgs::AddAppointmentResponse { .. }
-        ));
+        let announcement = get_announcement(&internal_api);
+        assert!(announcement.verify());
+        assert_eq!(announcement.features(), old_announcement.features());
+        assert!(announcement
+            .addresses()
+            .iter()
+            .any(|a| a.starts_with(&response.onion_address)));
+        assert!(!announcement
+            .addresses()
+            .iter()
+            .any(|a| a.starts_with(&old_address)));
     }
 
     #[tokio::test]
-    async fn test_add_appointment_non_registered() {
+    async fn test_create_backup() {
+        let (internal_api, _s) = create_api().await;
+        let tmp_path = TempDir::new("backup").unwrap();
+        let path = tmp_path.path().join("tower.bak");
+
+        let response = internal_api
+            .create_backup(Request::new(msgs::CreateBackupRequest {
+                passphrase: "passphrase".to_owned(),
+                public_key: Vec::new(),
+                path: path.display().to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.path, path.display().to_string());
+        assert_eq!(response.size, std::fs::metadata(&path).unwrap().len());
+
+        // Backups are never overwritten
+        match internal_api
+            .create_backup(Request::new(msgs::CreateBackupRequest {
+                passphrase: String::new(),
+                public_key: get_random_keypair().1.serialize().to_vec(),
+                path: path.display().to_string(),
+            }))
+            .await
+        {
+            Ok(_) => panic!("Should have failed"),
+            Err(status) => assert_eq!(status.code(), Code::AlreadyExists),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_backup_wrong_arguments() {
+        let (internal_api, _s) = create_api().await;
+
+        for (passphrase, public_key) in [
+            (String::new(), Vec::new()),
+            (
+                "passphrase".to_owned(),
+                get_random_keypair().1.serialize().to_vec(),
+            ),
+            (String::new(), vec![2; 20]),
+        ] {
+            match internal_api
+                .create_backup(Request::new(msgs::CreateBackupRequest {
+                    passphrase,
+                    public_key,
+                    path: String::new(),
+                }))
+                .await
+            {
+                Ok(_) => panic!("Should have failed"),
+                Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_key_passphrase() {
+        let (internal_api, _s) = create_api().await;
+        internal_api
+            .watcher
+            .add_dummy_tower_key(&get_random_keypair().0);
+
+        // Plain keys are encrypted by leaving the old passphrase empty
+        internal_api
+            .change_key_passphrase(Request::new(msgs::ChangeKeyPassphraseRequest {
+                old_passphrase: String::new(),
+                new_passphrase: "passphrase".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        // From then on, the current passphrase is required
+        for old_passphrase in ["", "wrong passphrase"] {
+            match internal_api
+                .change_key_passphrase(Request::new(msgs::ChangeKeyPassphraseRequest {
+                    old_passphrase: old_passphrase.to_owned(),
+                    new_passphrase: "new passphrase".to_owned(),
+                }))
+                .await
+            {
+                Ok(_) => panic!("Should have failed"),
+                Err(status) => assert_eq!(status.code(), Code::PermissionDenied),
+            }
+        }
+        internal_api
+            .change_key_passphrase(Request::new(msgs::ChangeKeyPassphraseRequest {
+                old_passphrase: "passphrase".to_owned(),
+                new_passphrase: "new passphrase".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        // Keys cannot be left without passphrase
+        match internal_api
+            .change_key_passphrase(Request::new(msgs::ChangeKeyPassphraseRequest {
+                old_passphrase: "new passphrase".to_owned(),
+                new_passphrase: String::new(),
+            }))
+            .await
+        {
+            Ok(_) => panic!("Should have failed"),
+            Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let tmp_path = TempDir::new("reload").unwrap();
+        let conf_file_path = tmp_path.path().join("teos.toml");
+        // The config file matches the config the tower is running with (other than the subscription slots)
+        let base_conf = format!(
+            "btc_rpc_user = \"user\"\nbtc_rpc_password = \"passwd\"\nsubscription_duration = {DURATION}\nexpiry_delta = {EXPIRY_DELTA}\n"
+        );
+        std::fs::write(
+            &conf_file_path,
+            format!("{base_conf}subscription_slots = {SLOTS}\n"),
+        )
+        .unwrap();
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::default().with_conf_file(conf_file_path.clone()))
+                .await;
+        let old_announcement = get_announcement(&internal_api);
+
+        // Changing the subscription parameters updates the announcement
+        std::fs::write(
+            &conf_file_path,
+            format!(
+                "{base_conf}subscription_slots = {}\nrpc_port = 1234\n",
+                SLOTS * 2
+            ),
+        )
+        .unwrap();
+        let response = internal_api
+            .reload_config(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.applied, vec!["subscription_slots"]);
+        assert_eq!(response.requires_restart, vec!["rpc_port"]);
+
+        let announcement = get_announcement(&internal_api);
+        assert!(announcement.verify());
+        assert_eq!(announcement.subscription_slots(), SLOTS * 2);
+        assert_eq!(announcement.subscription_duration(), DURATION);
+        assert_eq!(announcement.addresses(), old_announcement.addresses());
+
+        // Invalid configs are rejected as a whole
+        std::fs::write(&conf_file_path, "subscription_slots = 1\n").unwrap();
+        match internal_api.reload_config(Request::new(())).await {
+            Ok(_) => panic!("Should have failed"),
+            Err(status) => assert_eq!(status.code(), Code::FailedPrecondition),
+        }
+        assert_eq!(get_announcement(&internal_api), announcement);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_events() {
+        let (internal_api, _s) = create_api().await;
+
+        let mut events = internal_api
+            .subscribe_events(Request::new(msgs::SubscribeEventsRequest {
+                event_types: vec!["new_user".to_owned()],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Only the events matching the filter are streamed
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id).unwrap();
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        internal_api
+            .watcher
+            .add_appointment(appointment, signature)
+            .unwrap();
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.event, "new_user");
+        assert_eq!(event.user_id, user_id.to_vec());
+
+        // The stream ends once the tower shuts down
+        internal_api.stop(Request::new(())).await.unwrap();
+        while events.next().await.is_some() {}
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_events_unknown_type() {
+        let (internal_api, _s) = create_api().await;
+
+        match internal_api
+            .subscribe_events(Request::new(msgs::SubscribeEventsRequest {
+                event_types: vec!["unknown".to_owned()],
+            }))
+            .await
+        {
+            Err(status) => assert_eq!(status.code(), Code::InvalidArgument),
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop() {
+        let (internal_api, _s) = create_api().await;
+
+        assert!(!internal_api.shutdown_trigger.is_triggered());
+        internal_api.stop(Request::new(())).await.unwrap();
+        assert!(internal_api.shutdown_trigger.is_triggered());
+    }
+}
+
+#[cfg(test)]
+mod tests_public_api {
+    use super::*;
+
+    use crate::responder::{ConfirmationStatus, TransactionTracker};
+    use crate::test_utils::{
+        create_api, create_api_with_config, generate_dummy_appointment, get_random_tx, ApiConfig,
+        DURATION, SLOTS, START_HEIGHT,
+    };
+    use crate::watcher::Breach;
+    use std::convert::TryFrom;
+    use teos_common::cryptography::{self, get_random_keypair};
+    use teos_common::handoff::KeyHandoff;
+    use teos_common::net::http::Endpoint;
+    use teos_common::test_utils::get_random_user_id;
+
+    #[tokio::test]
+    async fn test_register() {
+        let (internal_api, _s) = create_api().await;
+
+        let (_, user_pk) = get_random_keypair();
+
+        // Registering (even multiple times) should work
+        for _ in 0..2 {
+            let response = internal_api
+                .register(Request::new(common_msgs::RegisterRequest {
+                    user_id: UserId(user_pk).to_vec(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert!(matches!(response, common_msgs::RegisterResponse { .. }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_wrong_user_id() {
+        let (internal_api, _s) = create_api().await;
+
+        let mut user_ids = Vec::new();
+
+        // Wrong user id size
+        let (_, user_pk) = get_random_keypair();
+        let mut user_id_vec = UserId(user_pk).to_vec();
+        user_id_vec.pop();
+        user_ids.push(user_id_vec);
+
+        // Wrong format (does not start with 2 nor 3)
+        user_id_vec = UserId(user_pk).to_vec();
+        user_id_vec[0] = 1;
+        user_ids.push(user_id_vec);
+
+        for user_id in user_ids {
+            match internal_api
+                .register(Request::new(common_msgs::RegisterRequest { user_id }))
+                .await
+            {
+                Err(status) => {
+                    assert_eq!(status.code(), Code::InvalidArgument);
+                    assert_eq!(status.message(), "Provided public key does not match expected format (33-byte compressed key)")
+                }
+                _ => panic!("Test should have returned Err"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_max_slots() {
+        let (internal_api, _s) = create_api_with_config(ApiConfig::new(u32::MAX, DURATION)).await;
+
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk).to_vec();
+
+        // First registration should go trough
+        internal_api
+            .register(Request::new(common_msgs::RegisterRequest {
+                user_id: user_id.clone(),
+            }))
+            .await
+            .unwrap();
+
+        // Trying to add more slots (re-register) must fail
+        match internal_api
+            .register(Request::new(common_msgs::RegisterRequest { user_id }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::ResourceExhausted);
+                assert_eq!(status.message(), "Subscription maximum slots count reached")
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_service_unavailable() {
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::new(u32::MAX, DURATION).bitcoind_unreachable()).await;
+
+        let (_, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk).to_vec();
+
+        match internal_api
+            .register(Request::new(common_msgs::RegisterRequest { user_id }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::Unavailable);
+                assert_eq!(status.message(), "Service currently unavailable")
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment() {
+        let (internal_api, _s) = create_api().await;
+
+        // User must be registered
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+
+        let response = internal_api
+            .add_appointment(Request::new(common_msgs::AddAppointmentRequest {
+                appointment: Some(appointment.clone().into()),
+                signature,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(matches!(
+            response,
+            common_msgs::AddAppointmentResponse { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_non_registered() {
         let (internal_api, _s) = create_api().await;
 
         // User is not registered this time
@@ -1031,17 +2357,113 @@ mod tests_public_api {
             .await
         {
             Err(status) => {
-                assert_eq!(status.code(), Code::AlreadyExists);
-                assert!(status
-                    .message()
-                    .starts_with("The provided appointment has already been triggered"),)
+                assert_eq!(status.code(), Code::AlreadyExists);
+                assert!(status
+                    .message()
+                    .starts_with("The provided appointment has already been triggered"),)
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointment_service_unavailable() {
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::new(u32::MAX, DURATION).bitcoind_unreachable()).await;
+
+        let (user_sk, _) = get_random_keypair();
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+
+        match internal_api
+            .add_appointment(Request::new(common_msgs::AddAppointmentRequest {
+                appointment: Some(appointment.clone().into()),
+                signature,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::Unavailable);
+                assert_eq!(status.message(), "Service currently unavailable")
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointments() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        // One valid appointment and one the tower cannot authenticate
+        let appointment = generate_dummy_appointment(None).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        let (other_sk, _) = get_random_keypair();
+        let other_appointment = generate_dummy_appointment(None).inner;
+        let other_signature = cryptography::sign(&other_appointment.to_vec(), &other_sk).unwrap();
+
+        let response = internal_api
+            .add_appointments(Request::new(common_msgs::AddAppointmentsRequest {
+                appointments: vec![
+                    common_msgs::AddAppointmentRequest {
+                        appointment: Some(appointment.clone().into()),
+                        signature,
+                    },
+                    common_msgs::AddAppointmentRequest {
+                        appointment: Some(other_appointment.clone().into()),
+                        signature: other_signature,
+                    },
+                ],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.results.len(), 2);
+        match response.results[0].result.as_ref().unwrap() {
+            common_msgs::add_appointment_result::Result::Response(r) => {
+                assert_eq!(r.locator, appointment.locator.to_vec());
+                assert_eq!(r.available_slots, SLOTS - 1);
+            }
+            _ => panic!("The first appointment should have been accepted"),
+        }
+        match response.results[1].result.as_ref().unwrap() {
+            common_msgs::add_appointment_result::Result::Error(e) => {
+                assert_eq!(e.locator, other_appointment.locator.to_vec());
+                assert_eq!(
+                    e.error_code,
+                    errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR as u32
+                );
+            }
+            _ => panic!("The second appointment should have been rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_appointments_empty() {
+        let (internal_api, _s) = create_api().await;
+
+        match internal_api
+            .add_appointments(Request::new(common_msgs::AddAppointmentsRequest {
+                appointments: Vec::new(),
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::InvalidArgument);
+                assert_eq!(
+                    status.message(),
+                    "At least one appointment must be provided"
+                );
             }
             _ => panic!("Test should have returned Err"),
         }
     }
 
     #[tokio::test]
-    async fn test_add_appointment_service_unavailable() {
+    async fn test_add_appointments_service_unavailable() {
         let (internal_api, _s) =
             create_api_with_config(ApiConfig::new(u32::MAX, DURATION).bitcoind_unreachable()).await;
 
@@ -1050,9 +2472,11 @@ mod tests_public_api {
         let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
 
         match internal_api
-            .add_appointment(Request::new(common_msgs::AddAppointmentRequest {
-                appointment: Some(appointment.clone().into()),
-                signature,
+            .add_appointments(Request::new(common_msgs::AddAppointmentsRequest {
+                appointments: vec![common_msgs::AddAppointmentRequest {
+                    appointment: Some(appointment.into()),
+                    signature,
+                }],
             }))
             .await
         {
@@ -1294,4 +2718,405 @@ mod tests_public_api {
             _ => panic!("Test should have returned Err"),
         }
     }
+
+    #[tokio::test]
+    async fn test_delete_appointment() {
+        let (internal_api, _s) = create_api().await;
+
+        // The user must be registered
+        let (user_sk, user_pk) = get_random_keypair();
+        let registration_receipt = internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        // Add the appointment
+        let appointment = generate_dummy_appointment(None).inner;
+        let user_signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        internal_api
+            .watcher
+            .add_appointment(appointment.clone(), user_signature)
+            .unwrap();
+
+        // Delete it through the API
+        let message = format!(
+            "delete appointments {} 1 {}",
+            internal_api.watcher.tower_id, appointment.locator
+        );
+        let response = internal_api
+            .delete_appointment(Request::new(common_msgs::DeleteAppointmentRequest {
+                locators: vec![appointment.locator.to_vec()],
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.locators, vec![appointment.locator.to_vec()]);
+        // The slot is given back to the user
+        assert_eq!(
+            response.available_slots,
+            registration_receipt.available_slots()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_invalid_nonce() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        // The first request goes through, using up the nonce
+        let appointment = generate_dummy_appointment(None).inner;
+        internal_api
+            .watcher
+            .add_appointment(
+                appointment.clone(),
+                cryptography::sign(&appointment.to_vec(), &user_sk).unwrap(),
+            )
+            .unwrap();
+        let message = format!(
+            "delete appointments {} 1 {}",
+            internal_api.watcher.tower_id, appointment.locator
+        );
+        let request = common_msgs::DeleteAppointmentRequest {
+            locators: vec![appointment.locator.to_vec()],
+            signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+            nonce: 1,
+        };
+        internal_api
+            .delete_appointment(Request::new(request.clone()))
+            .await
+            .unwrap();
+
+        // So replaying it is rejected
+        match internal_api.delete_appointment(Request::new(request)).await {
+            Err(status) => {
+                assert_eq!(status.code(), Code::InvalidArgument);
+                assert_eq!(
+                    status.message(),
+                    "The nonce must be greater than the last one used"
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_no_locators() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        match internal_api
+            .delete_appointment(Request::new(common_msgs::DeleteAppointmentRequest {
+                locators: Vec::new(),
+                signature: cryptography::sign("delete appointments ".as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::InvalidArgument);
+                assert_eq!(status.message(), "At least one locator must be provided");
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_non_existent() {
+        let (internal_api, _s) = create_api().await;
+
+        // The user is registered but the appointment does not exist
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let message = format!(
+            "delete appointments {} 1 {}",
+            internal_api.watcher.tower_id, appointment.locator
+        );
+
+        match internal_api
+            .delete_appointment(Request::new(common_msgs::DeleteAppointmentRequest {
+                locators: vec![appointment.locator.to_vec()],
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::NotFound);
+                assert_eq!(
+                    status.message(),
+                    format!("Appointment not found: {}", appointment.locator)
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_already_triggered() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        internal_api.watcher.register(user_id).unwrap();
+
+        // Add a tracker to the responder to simulate it being triggered.
+        let dispute_tx = get_random_tx();
+        let tracker = TransactionTracker::new(
+            Breach::new(dispute_tx.clone(), get_random_tx()),
+            user_id,
+            ConfirmationStatus::ConfirmedIn(100),
+        );
+        internal_api
+            .get_watcher()
+            .add_dummy_tracker_to_responder(&tracker);
+
+        // Try to delete it using the API.
+        let locator = Locator::new(dispute_tx.txid());
+        let message = format!(
+            "delete appointments {} 1 {locator}",
+            internal_api.watcher.tower_id
+        );
+        match internal_api
+            .delete_appointment(Request::new(common_msgs::DeleteAppointmentRequest {
+                locators: vec![locator.to_vec()],
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::AlreadyExists);
+                assert_eq!(
+                    status.message(),
+                    format!("Appointment {locator} has already been triggered")
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointment_service_unavailable() {
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::new(SLOTS, DURATION).bitcoind_unreachable()).await;
+
+        let (user_sk, _) = get_random_keypair();
+        let appointment = generate_dummy_appointment(None).inner;
+        let message = format!(
+            "delete appointments {} 1 {}",
+            internal_api.watcher.tower_id, appointment.locator
+        );
+        match internal_api
+            .delete_appointment(Request::new(common_msgs::DeleteAppointmentRequest {
+                locators: vec![appointment.locator.to_vec()],
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::Unavailable);
+                assert_eq!(status.message(), "Service currently unavailable");
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook() {
+        let (internal_api, _s) = create_api().await;
+
+        // The user must be registered
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        // Both setting and removing the webhook should work
+        for (nonce, url) in [(1, "https://1.1.1.1/hook"), (2, "")] {
+            let message = format!(
+                "register webhook {} {nonce} {url}",
+                internal_api.watcher.tower_id
+            );
+            let response = internal_api
+                .register_webhook(Request::new(common_msgs::RegisterWebhookRequest {
+                    url: url.to_owned(),
+                    signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                    nonce,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+
+            assert_eq!(
+                response,
+                common_msgs::RegisterWebhookResponse {
+                    user_id: user_pk.serialize().to_vec(),
+                    url: url.to_owned(),
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook_invalid_url() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        for (nonce, (url, error)) in [
+            ("not a url", "The provided url is not a valid http(s) url"),
+            (
+                "ftp://1.1.1.1/hook",
+                "The provided url is not a valid http(s) url",
+            ),
+            (
+                "http://localhost/hook",
+                "The provided url does not point to a public address",
+            ),
+            (
+                "http://169.254.169.254/latest/meta-data",
+                "The provided url does not point to a public address",
+            ),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let nonce = nonce as u64 + 1;
+            let message = format!(
+                "register webhook {} {nonce} {url}",
+                internal_api.watcher.tower_id
+            );
+            match internal_api
+                .register_webhook(Request::new(common_msgs::RegisterWebhookRequest {
+                    url: url.to_string(),
+                    signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                    nonce,
+                }))
+                .await
+            {
+                Err(status) => {
+                    assert_eq!(status.code(), Code::InvalidArgument);
+                    assert_eq!(status.message(), *error);
+                }
+                _ => panic!("Test should have returned Err"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook_invalid_nonce() {
+        let (internal_api, _s) = create_api().await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        internal_api.watcher.register(UserId(user_pk)).unwrap();
+
+        let url = "https://1.1.1.1/hook";
+        let message = format!("register webhook {} 1 {url}", internal_api.watcher.tower_id);
+        let request = common_msgs::RegisterWebhookRequest {
+            url: url.to_owned(),
+            signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+            nonce: 1,
+        };
+        internal_api
+            .register_webhook(Request::new(request.clone()))
+            .await
+            .unwrap();
+
+        // Replaying the request is rejected
+        match internal_api.register_webhook(Request::new(request)).await {
+            Err(status) => {
+                assert_eq!(status.code(), Code::InvalidArgument);
+                assert_eq!(
+                    status.message(),
+                    "The nonce must be greater than the last one used"
+                );
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook_non_registered() {
+        let (internal_api, _s) = create_api().await;
+
+        // The user is not registered
+        let (user_sk, _) = get_random_keypair();
+
+        let url = "http://1.1.1.1/hook";
+        let message = format!("register webhook {} 1 {url}", internal_api.watcher.tower_id);
+        match internal_api
+            .register_webhook(Request::new(common_msgs::RegisterWebhookRequest {
+                url: url.to_owned(),
+                signature: cryptography::sign(message.as_bytes(), &user_sk).unwrap(),
+                nonce: 1,
+            }))
+            .await
+        {
+            Err(status) => {
+                assert_eq!(status.code(), Code::Unauthenticated);
+                assert_eq!(status.message(), "User not found. Have you registered?");
+            }
+            _ => panic!("Test should have returned Err"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_tower_info() {
+        // The tower announcement is served even if bitcoind is not reachable
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::new(SLOTS, DURATION).bitcoind_unreachable()).await;
+
+        let response = PublicTowerServices::get_tower_info(&internal_api, Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let announcement = TowerAnnouncement::try_from(response).unwrap();
+        assert!(announcement.verify());
+        assert!(announcement.is_compatible());
+        assert_eq!(announcement.tower_id(), internal_api.watcher.tower_id);
+        assert_eq!(announcement.subscription_slots(), SLOTS);
+        assert_eq!(announcement.subscription_duration(), DURATION);
+        assert!(announcement.supports(&Endpoint::GetTowerInfo.to_string()));
+        assert!(announcement.supports(&Endpoint::GetKeyHandoffs.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_get_key_handoffs() {
+        let (internal_api, _s) = create_api().await;
+
+        let response = PublicTowerServices::get_key_handoffs(&internal_api, Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.handoffs.is_empty());
+
+        // Handoffs are served once the tower has rotated its key
+        let mut handoff = KeyHandoff::new(
+            internal_api.watcher.tower_id,
+            get_random_user_id(),
+            START_HEIGHT as u32,
+        );
+        handoff.sign(&internal_api.watcher.get_signing_key());
+        internal_api.watcher.add_dummy_key_handoff(&handoff);
+
+        let response = PublicTowerServices::get_key_handoffs(&internal_api, Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response
+                .handoffs
+                .into_iter()
+                .map(|h| KeyHandoff::try_from(h).unwrap())
+                .collect::<Vec<_>>(),
+            vec![handoff]
+        );
+    }
 }
@@ -0,0 +1,680 @@
+//! Public API served over Lightning peer connections.
+//!
+//! Users connect to the tower as if it was a Lightning node (the tower key is used as node key) and talk to it using
+//! the custom messages defined in [teos_common::net::lightning]. Requests are served by the same handlers as the
+//! HTTP API.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedSender};
+use tonic::{Request, Status};
+use triggered::{Listener, Trigger};
+
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use lightning::ln::msgs::{DecodeError, ErrorAction, LightningError};
+use lightning::ln::peer_handler::{
+    CustomMessageHandler, ErroringMessageHandler, IgnoringMessageHandler, MessageHandler,
+};
+use lightning::ln::wire::{CustomMessageReader, Type};
+use lightning::util::logger::{Level, Logger, Record};
+use lightning_net_tokio::SocketDescriptor;
+
+use teos_common::cryptography::get_random_bytes;
+use teos_common::net::lightning::{TowerError, TowerMessage};
+
+use crate::api::http::match_status;
+use crate::api::internal::InternalAPI;
+use crate::protos::public_tower_services_server::PublicTowerServices;
+use crate::tls::{ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF};
+
+/// How often the peers are pinged (and disconnected if they do not respond).
+const TIMER_TICK_INTERVAL: u64 = 10;
+/// How many requests can be received (from all peers) before they are picked up to be served. Peers sending requests
+/// once the limit is reached are disconnected.
+const MAX_RECEIVED_REQUESTS: usize = 1024;
+/// How many requests from the same peer can be waiting to be served. Peers going over the limit are disconnected.
+const MAX_PEER_PENDING_REQUESTS: usize = 32;
+
+pub type PeerManager = lightning::ln::peer_handler::PeerManager<
+    SocketDescriptor,
+    Arc<ErroringMessageHandler>,
+    Arc<IgnoringMessageHandler>,
+    Arc<LightningLogger>,
+    Arc<TowerMessageHandler>,
+>;
+
+/// Forwards the logs of the Lightning peer handling to the tower logs.
+///
+/// Records are emitted as events of the current span, with the module they come from as a field given tracing
+/// targets must be known at compile time.
+pub struct LightningLogger;
+
+impl Logger for LightningLogger {
+    fn log(&self, record: &Record) {
+        macro_rules! forward {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    module_path = record.module_path,
+                    line = record.line,
+                    "{}",
+                    record.args
+                )
+            };
+        }
+
+        match record.level {
+            Level::Gossip | Level::Trace => forward!(tracing::Level::TRACE),
+            Level::Debug => forward!(tracing::Level::DEBUG),
+            Level::Info => forward!(tracing::Level::INFO),
+            Level::Warn => forward!(tracing::Level::WARN),
+            Level::Error => forward!(tracing::Level::ERROR),
+        }
+    }
+}
+
+/// Serves a request using the [InternalAPI]. Returns the message to reply with, if any.
+async fn handle_request(
+    internal_api: &Arc<InternalAPI>,
+    message: TowerMessage,
+) -> Option<TowerMessage> {
+    let result = match message {
+        TowerMessage::Register(r) => internal_api
+            .register(Request::new(r))
+            .await
+            .map(|r| TowerMessage::RegisterResponse(r.into_inner())),
+        TowerMessage::AddAppointment(r) => internal_api
+            .add_appointment(Request::new(r))
+            .await
+            .map(|r| TowerMessage::AddAppointmentResponse(r.into_inner())),
+        TowerMessage::GetAppointment(r) => internal_api
+            .get_appointment(Request::new(r))
+            .await
+            .map(|r| TowerMessage::GetAppointmentResponse(r.into_inner())),
+        TowerMessage::GetSubscriptionInfo(r) => internal_api
+            .get_subscription_info(Request::new(r))
+            .await
+            .map(|r| TowerMessage::GetSubscriptionInfoResponse(r.into_inner())),
+        // Responses are only sent by the tower.
+        _ => return None,
+    };
+
+    Some(result.unwrap_or_else(|s: Status| {
+        let (_, error_code) = match_status(&s);
+        TowerMessage::Error(TowerError::new(s.message().to_owned(), error_code))
+    }))
+}
+
+/// A peer has too many requests waiting to be served.
+#[derive(Debug, PartialEq)]
+struct TooManyRequests;
+
+/// Requests waiting to be served, by peer.
+///
+/// Tower messages carry no request id, so requests from the same peer are served one at a time for their responses to
+/// be sent back in the order the requests were received. A peer has a request being served as long as it has a queue.
+#[derive(Default)]
+struct PeerQueues(HashMap<PublicKey, VecDeque<TowerMessage>>);
+
+impl PeerQueues {
+    /// Queues a request received from `peer`. The request is returned back if it can be served right away (the peer has
+    /// no other request being served). If the peer has too many pending requests, they are all dropped.
+    fn push(
+        &mut self,
+        peer: PublicKey,
+        message: TowerMessage,
+    ) -> Result<Option<TowerMessage>, TooManyRequests> {
+        match self.0.entry(peer) {
+            Entry::Vacant(e) => {
+                e.insert(VecDeque::new());
+                Ok(Some(message))
+            }
+            Entry::Occupied(mut e) => {
+                let queue = e.get_mut();
+                if queue.len() >= MAX_PEER_PENDING_REQUESTS {
+                    // The queue is kept (empty), since a request from the peer may still be being served
+                    queue.clear();
+                    Err(TooManyRequests)
+                } else {
+                    queue.push_back(message);
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Gets the next request to serve from `peer` once the previous one has been served, if any.
+    fn pop(&mut self, peer: &PublicKey) -> Option<TowerMessage> {
+        let next = self.0.get_mut(peer)?.pop_front();
+        if next.is_none() {
+            self.0.remove(peer);
+        }
+        next
+    }
+}
+
+/// Serves `message` on the async runtime and sends its response through `served` once done.
+fn spawn_request(
+    internal_api: &Arc<InternalAPI>,
+    served: &UnboundedSender<(PublicKey, Option<TowerMessage>)>,
+    peer: PublicKey,
+    message: TowerMessage,
+) {
+    let internal_api = internal_api.clone();
+    let served = served.clone();
+    tokio::spawn(async move {
+        let response = handle_request(&internal_api, message).await;
+        // The receiver is only gone if the interface is shutting down
+        let _ = served.send((peer, response));
+    });
+}
+
+/// Handles the tower messages received from Lightning peers.
+///
+/// The peer handling is synchronous, so requests are forwarded to [serve], which runs them on the async runtime and
+/// queues their responses back here.
+pub struct TowerMessageHandler {
+    /// Requests waiting to be served, alongside the peer that sent them.
+    requests: Sender<(PublicKey, TowerMessage)>,
+    /// Responses waiting to be sent to their peers.
+    pending_messages: Mutex<Vec<(PublicKey, TowerMessage)>>,
+}
+
+impl TowerMessageHandler {
+    /// Creates a new [TowerMessageHandler] instance, alongside the receiving end of its requests.
+    pub fn new() -> (Self, Receiver<(PublicKey, TowerMessage)>) {
+        let (requests, receiver) = channel(MAX_RECEIVED_REQUESTS);
+        (
+            Self {
+                requests,
+                pending_messages: Mutex::new(Vec::new()),
+            },
+            receiver,
+        )
+    }
+}
+
+impl CustomMessageReader for TowerMessageHandler {
+    type CustomMessage = TowerMessage;
+
+    fn read<R: Read>(
+        &self,
+        message_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        TowerMessage::read(message_type, buffer)
+    }
+}
+
+impl CustomMessageHandler for TowerMessageHandler {
+    fn handle_custom_message(
+        &self,
+        msg: Self::CustomMessage,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        tracing::debug!(
+            "Received a message of type {} from {sender_node_id}",
+            msg.type_id()
+        );
+
+        match self.requests.try_send((*sender_node_id, msg)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(LightningError {
+                err: format!("Too many pending requests. Disconnecting {sender_node_id}"),
+                action: ErrorAction::DisconnectPeer { msg: None },
+            }),
+            Err(TrySendError::Closed(_)) => {
+                tracing::debug!(
+                    "The Lightning interface is shutting down. Ignoring message from {sender_node_id}"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+        std::mem::take(&mut *self.pending_messages.lock().unwrap())
+    }
+}
+
+/// Builds a [PeerManager] that serves tower messages, authenticated with the tower key.
+fn peer_manager(handler: Arc<TowerMessageHandler>, tower_sk: SecretKey) -> Arc<PeerManager> {
+    let ephemeral_random_data: [u8; 32] = get_random_bytes(32).try_into().unwrap();
+    Arc::new(PeerManager::new(
+        MessageHandler {
+            chan_handler: Arc::new(ErroringMessageHandler::new()),
+            route_handler: Arc::new(IgnoringMessageHandler {}),
+        },
+        tower_sk,
+        &ephemeral_random_data,
+        Arc::new(LightningLogger),
+        handler,
+    ))
+}
+
+/// Accepts Lightning peer connections on `listener` and serves their requests until the shutdown signal is received.
+///
+/// Peers are authenticated using the tower key (`tower_sk`). Requests from the same peer are served one at a time, in
+/// the order they are received (see [PeerQueues]).
+pub async fn serve(
+    listener: TcpListener,
+    internal_api: Arc<InternalAPI>,
+    tower_sk: SecretKey,
+    service_ready: Trigger,
+    shutdown_signal: Listener,
+) -> Result<(), String> {
+    let (handler, mut requests) = TowerMessageHandler::new();
+    let handler = Arc::new(handler);
+    let peer_manager = peer_manager(handler.clone(), tower_sk);
+    let mut queues = PeerQueues::default();
+    let (served_sender, mut served) = unbounded_channel();
+    let mut timer = tokio::time::interval(Duration::from_secs(TIMER_TICK_INTERVAL));
+    let mut backoff = ACCEPT_BACKOFF;
+    let accept_pause = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(accept_pause);
+    let mut accept_paused = false;
+    service_ready.trigger();
+
+    loop {
+        tokio::select! {
+            _ = &mut accept_pause, if accept_paused => accept_paused = false,
+            accepted = listener.accept(), if !accept_paused => match accepted {
+                Ok((stream, addr)) => {
+                    backoff = ACCEPT_BACKOFF;
+                    tracing::debug!("Received a Lightning connection from {addr}");
+                    let peer_manager = peer_manager.clone();
+                    match stream.into_std() {
+                        Ok(stream) => {
+                            tokio::spawn(lightning_net_tokio::setup_inbound(peer_manager, stream));
+                        }
+                        Err(e) => tracing::error!("Cannot set up connection with {addr}: {e}"),
+                    }
+                }
+                Err(e) => {
+                    // Stop accepting connections for a while, so persistent errors do not spin the loop (but keep
+                    // serving the connected peers in the meantime).
+                    tracing::error!("Cannot accept Lightning connection. Retrying in {backoff}ms: {e}");
+                    accept_pause
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + Duration::from_millis(backoff));
+                    accept_paused = true;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                }
+            },
+            Some((peer, message)) = requests.recv() => match queues.push(peer, message) {
+                Ok(Some(message)) => spawn_request(&internal_api, &served_sender, peer, message),
+                Ok(None) => (),
+                Err(TooManyRequests) => {
+                    tracing::info!("Too many pending requests from {peer}. Disconnecting");
+                    peer_manager.disconnect_by_node_id(peer, false);
+                }
+            },
+            Some((peer, response)) = served.recv() => {
+                match response {
+                    Some(response) => {
+                        handler.pending_messages.lock().unwrap().push((peer, response));
+                        peer_manager.process_events();
+                    }
+                    None => tracing::debug!("Unexpected message from {peer}. Ignoring it"),
+                }
+                if let Some(message) = queues.pop(&peer) {
+                    spawn_request(&internal_api, &served_sender, peer, message);
+                }
+            }
+            _ = timer.tick() => peer_manager.timer_tick_occurred(),
+            _ = shutdown_signal.clone() => break,
+        }
+    }
+
+    peer_manager.disconnect_all_peers();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpStream;
+
+    use teos_common::cryptography::get_random_keypair;
+    use teos_common::errors;
+    use teos_common::net::lightning::TowerMessageReader;
+    use teos_common::protos as common_msgs;
+
+    use crate::test_utils::{create_api, create_api_with_config, ApiConfig, DURATION, SLOTS};
+
+    type ClientPeerManager = lightning::ln::peer_handler::PeerManager<
+        SocketDescriptor,
+        Arc<ErroringMessageHandler>,
+        Arc<IgnoringMessageHandler>,
+        Arc<LightningLogger>,
+        Arc<ClientMessageHandler>,
+    >;
+
+    /// A minimal client that stores the messages received from the tower.
+    struct ClientMessageHandler {
+        outgoing: Mutex<Vec<(PublicKey, TowerMessage)>>,
+        received: tokio::sync::mpsc::UnboundedSender<TowerMessage>,
+    }
+
+    impl CustomMessageReader for ClientMessageHandler {
+        type CustomMessage = TowerMessage;
+
+        fn read<R: Read>(
+            &self,
+            message_type: u16,
+            buffer: &mut R,
+        ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+            TowerMessageReader.read(message_type, buffer)
+        }
+    }
+
+    impl CustomMessageHandler for ClientMessageHandler {
+        fn handle_custom_message(
+            &self,
+            msg: Self::CustomMessage,
+            _: &PublicKey,
+        ) -> Result<(), LightningError> {
+            self.received.send(msg).unwrap();
+            Ok(())
+        }
+
+        fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+            std::mem::take(&mut *self.outgoing.lock().unwrap())
+        }
+    }
+
+    /// Runs a Lightning interface for the given API and connects a client to it.
+    async fn connect(
+        internal_api: Arc<InternalAPI>,
+    ) -> (
+        Arc<ClientPeerManager>,
+        Arc<ClientMessageHandler>,
+        PublicKey,
+        tokio::sync::mpsc::UnboundedReceiver<TowerMessage>,
+    ) {
+        let tower_id = internal_api.get_watcher().tower_id.0;
+        let tower_sk = internal_api.get_watcher().get_signing_key();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (_, shutdown_signal) = triggered::trigger();
+        tokio::spawn(serve(
+            listener,
+            internal_api,
+            tower_sk,
+            service_ready,
+            shutdown_signal,
+        ));
+        ready_signal.await;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let handler = Arc::new(ClientMessageHandler {
+            outgoing: Mutex::new(Vec::new()),
+            received: tx,
+        });
+        let client = Arc::new(ClientPeerManager::new(
+            MessageHandler {
+                chan_handler: Arc::new(ErroringMessageHandler::new()),
+                route_handler: Arc::new(IgnoringMessageHandler {}),
+            },
+            get_random_keypair().0,
+            &[1; 32],
+            Arc::new(LightningLogger),
+            handler.clone(),
+        ));
+
+        let stream = TcpStream::connect(addr).await.unwrap().into_std().unwrap();
+        tokio::spawn(lightning_net_tokio::setup_outbound(
+            client.clone(),
+            tower_id,
+            stream,
+        ));
+
+        // Wait for the handshake to complete
+        while client.get_peer_node_ids().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        (client, handler, tower_id, rx)
+    }
+
+    /// Sends a message to the tower and waits for its response.
+    async fn request(
+        client: &ClientPeerManager,
+        handler: &ClientMessageHandler,
+        tower_id: PublicKey,
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<TowerMessage>,
+        message: TowerMessage,
+    ) -> TowerMessage {
+        handler.outgoing.lock().unwrap().push((tower_id, message));
+        client.process_events();
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn get_register_request() -> TowerMessage {
+        TowerMessage::Register(common_msgs::RegisterRequest {
+            user_id: get_random_keypair().1.serialize().to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_peer_queues() {
+        let mut queues = PeerQueues::default();
+        let peer = get_random_keypair().1;
+        let other_peer = get_random_keypair().1;
+        let requests: Vec<TowerMessage> = (0..3).map(|_| get_register_request()).collect();
+
+        // The first request from a peer is served right away, the rest wait for it in order
+        assert_eq!(
+            queues.push(peer, requests[0].clone()),
+            Ok(Some(requests[0].clone()))
+        );
+        assert_eq!(queues.push(peer, requests[1].clone()), Ok(None));
+        assert_eq!(queues.push(peer, requests[2].clone()), Ok(None));
+
+        // Peers do not wait for each other
+        assert_eq!(
+            queues.push(other_peer, requests[0].clone()),
+            Ok(Some(requests[0].clone()))
+        );
+
+        assert_eq!(queues.pop(&peer), Some(requests[1].clone()));
+        assert_eq!(queues.pop(&peer), Some(requests[2].clone()));
+        assert_eq!(queues.pop(&peer), None);
+
+        // Once everything has been served, the next request is served right away again
+        assert_eq!(
+            queues.push(peer, requests[0].clone()),
+            Ok(Some(requests[0].clone()))
+        );
+    }
+
+    #[test]
+    fn test_peer_queues_full() {
+        let mut queues = PeerQueues::default();
+        let peer = get_random_keypair().1;
+        let request = get_register_request();
+
+        assert!(matches!(queues.push(peer, request.clone()), Ok(Some(_))));
+        for _ in 0..MAX_PEER_PENDING_REQUESTS {
+            assert_eq!(queues.push(peer, request.clone()), Ok(None));
+        }
+
+        // Going over the limit drops whatever was pending, but the request being served is still accounted for
+        assert_eq!(queues.push(peer, request.clone()), Err(TooManyRequests));
+        assert_eq!(queues.push(peer, request.clone()), Ok(None));
+        assert_eq!(queues.pop(&peer), Some(request));
+        assert_eq!(queues.pop(&peer), None);
+    }
+
+    #[test]
+    fn test_handle_custom_message_too_many_requests() {
+        let (handler, _requests) = TowerMessageHandler::new();
+        let peer = get_random_keypair().1;
+
+        for _ in 0..MAX_RECEIVED_REQUESTS {
+            handler
+                .handle_custom_message(get_register_request(), &peer)
+                .unwrap();
+        }
+
+        // Requests that cannot be picked up get the peer disconnected
+        assert!(matches!(
+            handler.handle_custom_message(get_register_request(), &peer),
+            Err(LightningError {
+                action: ErrorAction::DisconnectPeer { msg: None },
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_responses_in_order() {
+        let (internal_api, _s) = create_api().await;
+        let (client, handler, tower_id, mut rx) = connect(internal_api).await;
+
+        // Requests sent at once are answered in the same order
+        let requests: Vec<TowerMessage> = (0..10)
+            .map(|i| {
+                if i % 2 == 0 {
+                    get_register_request()
+                } else {
+                    TowerMessage::GetAppointment(common_msgs::GetAppointmentRequest {
+                        locator: vec![0; 3],
+                        signature: "signature".to_owned(),
+                    })
+                }
+            })
+            .collect();
+        handler
+            .outgoing
+            .lock()
+            .unwrap()
+            .extend(requests.iter().map(|r| (tower_id, r.clone())));
+        client.process_events();
+
+        for request in requests {
+            let response = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            match (request, response) {
+                (TowerMessage::Register(req), TowerMessage::RegisterResponse(r)) => {
+                    assert_eq!(r.user_id, req.user_id)
+                }
+                (TowerMessage::GetAppointment(_), TowerMessage::Error(e)) => {
+                    assert_eq!(e.error_code, errors::WRONG_FIELD_FORMAT)
+                }
+                (_, m) => panic!("Unexpected response: {:?}", m),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        let (internal_api, _s) = create_api().await;
+        let (client, handler, tower_id, mut rx) = connect(internal_api).await;
+
+        let user_id = get_random_keypair().1.serialize().to_vec();
+        match request(
+            &client,
+            &handler,
+            tower_id,
+            &mut rx,
+            TowerMessage::Register(common_msgs::RegisterRequest {
+                user_id: user_id.clone(),
+            }),
+        )
+        .await
+        {
+            TowerMessage::RegisterResponse(r) => {
+                assert_eq!(r.user_id, user_id);
+                assert_eq!(r.available_slots, SLOTS);
+                assert_eq!(r.subscription_expiry - r.subscription_start, DURATION);
+            }
+            m => panic!("Unexpected response: {:?}", m),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let (internal_api, _s) = create_api().await;
+        let (client, handler, tower_id, mut rx) = connect(internal_api).await;
+
+        // Wrong data is reported back using the same error codes as the HTTP API
+        assert_eq!(
+            request(
+                &client,
+                &handler,
+                tower_id,
+                &mut rx,
+                TowerMessage::GetAppointment(common_msgs::GetAppointmentRequest {
+                    locator: vec![0; 3],
+                    signature: "signature".to_owned(),
+                }),
+            )
+            .await,
+            TowerMessage::Error(TowerError::new(
+                "The provided locator does not match the expected format (16-byte hexadecimal string)"
+                    .to_owned(),
+                errors::WRONG_FIELD_FORMAT
+            ))
+        );
+
+        // So are authentication errors
+        match request(
+            &client,
+            &handler,
+            tower_id,
+            &mut rx,
+            TowerMessage::GetSubscriptionInfo(common_msgs::GetSubscriptionInfoRequest {
+                signature: "signature".to_owned(),
+            }),
+        )
+        .await
+        {
+            TowerMessage::Error(e) => {
+                assert_eq!(
+                    e.error_code,
+                    errors::INVALID_SIGNATURE_OR_SUBSCRIPTION_ERROR
+                )
+            }
+            m => panic!("Unexpected response: {:?}", m),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_service_unavailable() {
+        let (internal_api, _s) =
+            create_api_with_config(ApiConfig::new(SLOTS, DURATION).bitcoind_unreachable()).await;
+        let (client, handler, tower_id, mut rx) = connect(internal_api).await;
+
+        match request(
+            &client,
+            &handler,
+            tower_id,
+            &mut rx,
+            TowerMessage::Register(common_msgs::RegisterRequest {
+                user_id: get_random_keypair().1.serialize().to_vec(),
+            }),
+        )
+        .await
+        {
+            TowerMessage::Error(e) => assert_eq!(e.error_code, errors::SERVICE_UNAVAILABLE),
+            m => panic!("Unexpected response: {:?}", m),
+        }
+    }
+}
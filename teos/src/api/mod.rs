@@ -1,4 +1,17 @@
 pub mod http;
 pub mod internal;
+pub mod lightning;
+pub mod openapi;
 pub mod serde;
 pub mod tor;
+
+use teos_common::net::http::Endpoint;
+
+/// Public endpoints offered by the tower on top of the base protocol. Announced so users can check what the tower supports.
+pub const FEATURES: [Endpoint; 5] = [
+    Endpoint::AddAppointments,
+    Endpoint::DeleteAppointment,
+    Endpoint::RegisterWebhook,
+    Endpoint::GetTowerInfo,
+    Endpoint::GetKeyHandoffs,
+];
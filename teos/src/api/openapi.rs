@@ -0,0 +1,372 @@
+//! Generation of the OpenAPI spec of the HTTP API.
+//!
+//! The spec is built from the protobuf descriptors of the [PublicTowerServices](crate::protos::public_tower_services_server::PublicTowerServices),
+//! so it is always in sync with the request and response types the HTTP API proxies to. Since the JSON representation of these types
+//! is given by the serde attributes set in `build.rs`, the same conventions are followed here:
+//! - `bytes` fields are hex encoded strings
+//! - enums are serialized as their lowercase value names
+//! - `oneof` fields are flattened (the message is represented by the selected variant)
+
+use std::collections::HashMap;
+
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+use serde_json::{json, Map, Value};
+
+use teos_common::net::http::{Endpoint, API_VERSION};
+
+const DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/teos_descriptor.bin"));
+const PUBLIC_SERVICE: &str = "PublicTowerServices";
+const OPENAPI_VERSION: &str = "3.0.3";
+const EMPTY_MESSAGE: &str = ".google.protobuf.Empty";
+
+/// Fields that are renamed when serialized (mirrors the `serde(rename)` attributes set in `build.rs`).
+const FIELD_RENAMES: [(&str, &str); 2] = [
+    ("appointment_data", "appointment"),
+    ("address_type", "type"),
+];
+
+/// Holds all the message and enum descriptors, indexed by their fully qualified name (e.g. `.common.teos.v2.Appointment`).
+struct Descriptors<'a> {
+    messages: HashMap<String, &'a DescriptorProto>,
+    enums: HashMap<String, &'a EnumDescriptorProto>,
+}
+
+impl<'a> Descriptors<'a> {
+    fn new(descriptor_set: &'a FileDescriptorSet) -> Self {
+        let mut descriptors = Descriptors {
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+        };
+
+        for file in descriptor_set.file.iter() {
+            let prefix = format!(".{}", file.package());
+            for e in file.enum_type.iter() {
+                descriptors
+                    .enums
+                    .insert(format!("{prefix}.{}", e.name()), e);
+            }
+            for m in file.message_type.iter() {
+                descriptors.add_message(&prefix, m);
+            }
+        }
+
+        descriptors
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &'a DescriptorProto) {
+        let name = format!("{prefix}.{}", message.name());
+        for e in message.enum_type.iter() {
+            self.enums.insert(format!("{name}.{}", e.name()), e);
+        }
+        for m in message.nested_type.iter() {
+            self.add_message(&name, m);
+        }
+        self.messages.insert(name, message);
+    }
+
+    /// Adds the schema of a given message (and of all the messages it depends on) to `schemas`.
+    /// Returns a reference to the added schema.
+    fn add_schema(&self, type_name: &str, schemas: &mut Map<String, Value>) -> Value {
+        let message = self.messages[type_name];
+        let schema_ref = json!({ "$ref": format!("#/components/schemas/{}", message.name()) });
+        if schemas.contains_key(message.name()) {
+            return schema_ref;
+        }
+        // Insert a placeholder first so recursive types do not loop forever.
+        schemas.insert(message.name().to_owned(), Value::Null);
+
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        let mut oneofs: Vec<Vec<Value>> = vec![Vec::new(); message.oneof_decl.len()];
+        for field in message.field.iter() {
+            let schema = self.field_schema(field, schemas);
+            match field.oneof_index {
+                Some(i) if !field.proto3_optional() => oneofs[i as usize].push(schema),
+                _ => {
+                    let name = FIELD_RENAMES
+                        .iter()
+                        .find(|(from, _)| *from == field.name())
+                        .map_or(field.name(), |(_, to)| to);
+                    // Message fields are optional. Everything else is always present.
+                    if field.r#type() != Type::Message || field.label() == Label::Repeated {
+                        required.push(Value::from(name));
+                    }
+                    properties.insert(name.to_owned(), schema);
+                }
+            }
+        }
+
+        let mut parts: Vec<Value> = oneofs
+            .into_iter()
+            .map(|variants| json!({ "oneOf": variants }))
+            .collect();
+        if !properties.is_empty() || parts.is_empty() {
+            let mut object = json!({ "type": "object", "properties": properties });
+            if !required.is_empty() {
+                object["required"] = Value::from(required);
+            }
+            parts.insert(0, object);
+        }
+        let schema = if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            json!({ "allOf": parts })
+        };
+
+        schemas.insert(message.name().to_owned(), schema);
+        schema_ref
+    }
+
+    /// Builds the schema of a given field.
+    fn field_schema(
+        &self,
+        field: &FieldDescriptorProto,
+        schemas: &mut Map<String, Value>,
+    ) -> Value {
+        let schema = match field.r#type() {
+            Type::Bytes => json!({ "type": "string", "format": "hex" }),
+            Type::String => json!({ "type": "string" }),
+            Type::Bool => json!({ "type": "boolean" }),
+            Type::Uint32 | Type::Fixed32 => {
+                json!({ "type": "integer", "format": "int64", "minimum": 0, "maximum": u32::MAX })
+            }
+            Type::Int32 | Type::Sint32 | Type::Sfixed32 => {
+                json!({ "type": "integer", "format": "int32" })
+            }
+            Type::Uint64 | Type::Fixed64 => json!({ "type": "integer", "minimum": 0 }),
+            Type::Int64 | Type::Sint64 | Type::Sfixed64 => {
+                json!({ "type": "integer", "format": "int64" })
+            }
+            Type::Float | Type::Double => json!({ "type": "number" }),
+            Type::Enum => {
+                let values: Vec<String> = self.enums[field.type_name()]
+                    .value
+                    .iter()
+                    .map(|v| v.name().to_lowercase())
+                    .collect();
+                json!({ "type": "string", "enum": values })
+            }
+            Type::Message | Type::Group => self.add_schema(field.type_name(), schemas),
+        };
+
+        if field.label() == Label::Repeated {
+            json!({ "type": "array", "items": schema })
+        } else {
+            schema
+        }
+    }
+}
+
+/// Wraps a schema into an `application/json` content object.
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// Generates the OpenAPI spec of the HTTP API.
+pub(crate) fn spec() -> Value {
+    let descriptor_set = FileDescriptorSet::decode(DESCRIPTOR_SET).unwrap();
+    let descriptors = Descriptors::new(&descriptor_set);
+    let service = descriptor_set
+        .file
+        .iter()
+        .flat_map(|f| f.service.iter())
+        .find(|s| s.name() == PUBLIC_SERVICE)
+        .unwrap();
+
+    let mut schemas = Map::new();
+    schemas.insert(
+        "ApiError".to_owned(),
+        json!({
+            "type": "object",
+            "properties": {
+                "error": { "type": "string" },
+                "error_code": { "type": "integer", "format": "int32", "minimum": 0, "maximum": u8::MAX },
+            },
+            "required": ["error", "error_code"],
+        }),
+    );
+
+    let mut paths = Map::new();
+    for method in service.method.iter() {
+        let response = descriptors.add_schema(method.output_type(), &mut schemas);
+        let mut operation = json!({
+            "operationId": method.name(),
+            "responses": {
+                "200": { "description": "Request succeeded", "content": json_content(response) },
+                "default": {
+                    "description": "Request failed",
+                    "content": json_content(json!({ "$ref": "#/components/schemas/ApiError" })),
+                },
+            },
+        });
+        // Methods that take no input are served as GET requests. The rest as POST requests with a JSON body.
+        let http_method = if method.input_type() == EMPTY_MESSAGE {
+            "get"
+        } else {
+            let request = descriptors.add_schema(method.input_type(), &mut schemas);
+            operation["requestBody"] =
+                json!({ "required": true, "content": json_content(request) });
+            "post"
+        };
+        paths.insert(
+            format!("/{API_VERSION}/{}", method.name()),
+            json!({ http_method: operation }),
+        );
+    }
+    paths.insert(
+        Endpoint::Ping.versioned_path(),
+        json!({
+            "get": {
+                "operationId": Endpoint::Ping.to_string(),
+                "responses": { "200": { "description": "The tower is reachable" } },
+            },
+        }),
+    );
+    paths.insert(
+        Endpoint::OpenApi.versioned_path(),
+        json!({
+            "get": {
+                "operationId": "openapi",
+                "responses": {
+                    "200": {
+                        "description": "This document",
+                        "content": json_content(json!({ "type": "object" })),
+                    },
+                },
+            },
+        }),
+    );
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "The Eye of Satoshi public API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::protos as common_msgs;
+
+    /// Checks that all the references in a given value point to an existing schema.
+    fn check_refs(value: &Value, schemas: &Map<String, Value>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(r)) = map.get("$ref") {
+                    let name = r.strip_prefix("#/components/schemas/").unwrap();
+                    assert!(schemas.contains_key(name), "missing schema {}", name);
+                }
+                map.values().for_each(|v| check_refs(v, schemas));
+            }
+            Value::Array(values) => values.iter().for_each(|v| check_refs(v, schemas)),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = spec();
+        let paths = spec["paths"].as_object().unwrap();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+
+        // All endpoints are there
+        for endpoint in [
+            Endpoint::Register,
+            Endpoint::AddAppointment,
+            Endpoint::AddAppointments,
+            Endpoint::GetAppointment,
+            Endpoint::GetSubscriptionInfo,
+            Endpoint::DeleteAppointment,
+            Endpoint::RegisterWebhook,
+            Endpoint::GetTowerInfo,
+            Endpoint::GetKeyHandoffs,
+            Endpoint::Ping,
+            Endpoint::OpenApi,
+        ] {
+            assert!(paths.contains_key(&endpoint.versioned_path()));
+        }
+
+        // Methods with no input are served as GET
+        let get_tower_info = &paths[&Endpoint::GetTowerInfo.versioned_path()];
+        assert!(get_tower_info["get"]["requestBody"].is_null());
+        assert!(get_tower_info.get("post").is_none());
+        assert!(paths[&Endpoint::Register.versioned_path()]["post"]["requestBody"].is_object());
+
+        // All references can be resolved and there are no placeholders left
+        check_refs(&spec, schemas);
+        assert!(schemas.values().all(|s| !s.is_null()));
+    }
+
+    #[test]
+    fn test_message_schema() {
+        let spec = spec();
+        let schemas = &spec["components"]["schemas"];
+
+        // Bytes are hex encoded, messages are optional and everything else is required
+        assert_eq!(
+            schemas["AddAppointmentRequest"],
+            json!({
+                "type": "object",
+                "properties": {
+                    "appointment": { "$ref": "#/components/schemas/Appointment" },
+                    "signature": { "type": "string" },
+                },
+                "required": ["signature"],
+            })
+        );
+        assert_eq!(
+            schemas["Appointment"]["properties"]["locator"],
+            json!({ "type": "string", "format": "hex" })
+        );
+        assert_eq!(
+            schemas["DeleteAppointmentRequest"]["properties"]["locators"],
+            json!({ "type": "array", "items": { "type": "string", "format": "hex" } })
+        );
+
+        // Renamed fields and enums match their serialized form
+        let get_appointment = &schemas["GetAppointmentResponse"]["properties"];
+        assert_eq!(
+            get_appointment["appointment"],
+            json!({ "$ref": "#/components/schemas/AppointmentData" })
+        );
+        assert_eq!(
+            get_appointment["status"],
+            json!({ "type": "string", "enum": ["not_found", "being_watched", "dispute_responded", "resolved"] })
+        );
+
+        // Oneofs are flattened
+        assert_eq!(
+            schemas["AppointmentData"],
+            json!({ "oneOf": [
+                { "$ref": "#/components/schemas/Appointment" },
+                { "$ref": "#/components/schemas/Tracker" },
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_schema_matches_serialization() {
+        let spec = spec();
+        let schemas = &spec["components"]["schemas"];
+
+        // The properties of the schema match the fields of the serialized message
+        let response = serde_json::to_value(common_msgs::RegisterResponse::default()).unwrap();
+        let mut fields: Vec<&String> = response.as_object().unwrap().keys().collect();
+        let mut properties: Vec<&String> = schemas["RegisterResponse"]["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        fields.sort();
+        properties.sort();
+        assert_eq!(fields, properties);
+    }
+}
@@ -1,6 +1,8 @@
+use std::fmt;
+
 use crate::protos as msgs;
 
-use teos_common::net::AddressType;
+use teos_common::net::{join_host_port, AddressType};
 
 impl msgs::NetworkAddress {
     pub fn from_ipv4(address: String, port: u16) -> Self {
@@ -18,6 +20,21 @@ impl msgs::NetworkAddress {
             port: port as u32,
         }
     }
+
+    /// Builds a network address for a given host, inferring its type (IPv4, IPv6, onion or DNS name).
+    pub fn from_host(address: String, port: u16) -> Self {
+        Self {
+            address_type: AddressType::get_type(&address) as i32,
+            address,
+            port: port as u32,
+        }
+    }
+}
+
+impl fmt::Display for msgs::NetworkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", join_host_port(&self.address, self.port as u16))
+    }
 }
 
 pub mod serde_address_type {
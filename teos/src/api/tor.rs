@@ -1,19 +1,62 @@
+use std::borrow::Cow;
 use std::convert::TryInto;
 use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Mutex;
 
+use bitcoin::base64;
 use tokio::fs;
 use tokio::net::TcpStream;
-use torut::control::UnauthenticatedConn;
+use tokio::sync::Mutex as AsyncMutex;
+use torut::control::{Conn, TorAuthData, TorAuthMethod, UnauthenticatedConn, COOKIE_LENGTH};
 use torut::onion::TorSecretKeyV3;
 use triggered::{Listener, Trigger};
 
+/// Length of a base32 encoded x25519 public key, as used by Tor v3 client authorization.
+const CLIENT_AUTH_KEY_LEN: usize = 52;
+
+/// How the tower authenticates with the Tor control port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TorControlAuth {
+    /// Uses any method offered by Tor that does not require input from the operator (NULL or cookie).
+    Auto,
+    /// SAFECOOKIE authentication. The cookie is read from the given file, or from the one reported by Tor if none.
+    SafeCookie(Option<PathBuf>),
+    /// HASHEDPASSWORD authentication using the given password.
+    HashedPassword(String),
+}
+
+/// Parses a Tor v3 client authorization key, either on its own or in Tor's `descriptor:x25519:<key>` format.
+///
+/// Returns the key, base32 encoded, as expected by the Tor control port.
+pub fn parse_client_auth_key(key: &str) -> Result<String, String> {
+    let key = key.strip_prefix("descriptor:x25519:").unwrap_or(key);
+    if key.len() == CLIENT_AUTH_KEY_LEN
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || ('2'..='7').contains(&c))
+    {
+        Ok(key.to_ascii_uppercase())
+    } else {
+        Err(format!(
+            "Invalid client authorization key: {key}. Expected a base32 encoded x25519 public key"
+        ))
+    }
+}
+
 pub struct TorAPI {
-    sk: TorSecretKeyV3,
+    sk: Mutex<TorSecretKeyV3>,
+    path: PathBuf,
     api_endpoint: SocketAddr,
     onion_port: u16,
     tor_control_port: u16,
+    auth: TorControlAuth,
+    /// Keys of the clients allowed to reach the onion service. Anyone can reach it if empty.
+    authorized_clients: Vec<String>,
+    /// Connection to the Tor control port. Kept open while the onion service is exposed, since the service is
+    /// bound to it.
+    control_conn: AsyncMutex<Option<TcpStream>>,
 }
 
 impl TorAPI {
@@ -21,49 +64,94 @@ impl TorAPI {
         api_endpoint: SocketAddr,
         onion_port: u16,
         tor_control_port: u16,
+        auth: TorControlAuth,
+        authorized_clients: Vec<String>,
         path: PathBuf,
     ) -> Self {
         let key = if let Some(key) = TorAPI::load_sk(path.clone()).await {
             key
         } else {
-            log::info!("Generating fresh Tor secret key");
+            tracing::info!("Generating fresh Tor secret key");
             let key = TorSecretKeyV3::generate();
-            TorAPI::store_sk(&key, path).await;
+            if let Err(e) = TorAPI::store_sk(&key, path.clone()).await {
+                tracing::error!("Cannot store Tor secret key. {e}");
+            }
             key
         };
 
         Self {
-            sk: key,
+            sk: Mutex::new(key),
+            path,
             api_endpoint,
             onion_port,
             tor_control_port,
+            auth,
+            authorized_clients,
+            control_conn: AsyncMutex::new(None),
         }
     }
 
     pub fn get_onion_address(&self) -> String {
-        self.sk.public().get_onion_address().to_string()
+        self.get_sk().public().get_onion_address().to_string()
+    }
+
+    /// Gets the secret key of the onion service.
+    pub fn get_sk(&self) -> TorSecretKeyV3 {
+        self.sk.lock().unwrap().clone()
+    }
+
+    /// Replaces the secret key of the onion service, which changes its onion address.
+    ///
+    /// If the onion service is exposed, the new one is published before the old one is removed, so the tower is
+    /// reachable all along. The new key is persisted to disk, so it is also used on restart.
+    pub async fn set_sk(&self, sk: TorSecretKeyV3) -> Result<(), Error> {
+        let mut control_conn = self.control_conn.lock().await;
+        let old_sk = self.get_sk();
+        if sk == old_sk {
+            return Ok(());
+        }
+
+        if let Some(stream) = control_conn.as_mut() {
+            self.add_onion(stream, &sk).await?;
+            if let Err(e) = TorAPI::store_sk(&sk, self.path.clone()).await {
+                if let Err(e) = TorAPI::del_onion(stream, &sk).await {
+                    tracing::error!("Cannot remove onion service: {e}");
+                }
+                return Err(e);
+            }
+            if let Err(e) = TorAPI::del_onion(stream, &old_sk).await {
+                tracing::error!("Cannot remove old onion service: {e}");
+            }
+        } else {
+            TorAPI::store_sk(&sk, self.path.clone()).await?;
+        }
+
+        *self.sk.lock().unwrap() = sk;
+        tracing::info!(
+            "Onion service key updated. New address: {}",
+            self.get_onion_address()
+        );
+        Ok(())
     }
 
     /// Loads a Tor key from disk (if found).
     async fn load_sk(path: PathBuf) -> Option<TorSecretKeyV3> {
-        log::info!("Loading Tor secret key from disk");
+        tracing::info!("Loading Tor secret key from disk");
         let key = fs::read(path.join("onion_v3_sk"))
             .await
-            .map_err(|e| log::warn!("Tor secret key cannot be loaded. {e}"))
+            .map_err(|e| tracing::warn!("Tor secret key cannot be loaded. {e}"))
             .ok()?;
         let key: [u8; 64] = key
             .try_into()
-            .map_err(|_| log::error!("Cannot convert loaded data into Tor secret key"))
+            .map_err(|_| tracing::error!("Cannot convert loaded data into Tor secret key"))
             .ok()?;
 
         Some(TorSecretKeyV3::from(key))
     }
 
     /// Stores a Tor key to disk.
-    async fn store_sk(key: &TorSecretKeyV3, path: PathBuf) {
-        if let Err(e) = fs::write(path.join("onion_v3_sk"), key.as_bytes()).await {
-            log::error!("Cannot store Tor secret key. {e}");
-        }
+    async fn store_sk(key: &TorSecretKeyV3, path: PathBuf) -> Result<(), Error> {
+        fs::write(path.join("onion_v3_sk"), key.as_bytes()).await
     }
 
     /// Tries to connect to the Tor control port
@@ -79,74 +167,159 @@ impl TorAPI {
         Ok(sock)
     }
 
-    /// Expose an onion service that re-directs to the public api.
-    pub async fn expose_onion_service(
-        &self,
-        service_ready: Trigger,
-        shutdown_signal_tor: Listener,
-    ) -> Result<(), Error> {
-        let stream = self
-            .connect_tor_cp()
-            .await
-            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
-
+    /// Authenticates with the Tor control port using the configured method.
+    async fn authenticate(&self, stream: &mut TcpStream) -> Result<(), Error> {
         let mut unauth_conn = UnauthenticatedConn::new(stream);
 
         let pre_auth = unauth_conn
             .load_protocol_info()
             .await
             .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
+        let check_method = |method: TorAuthMethod| {
+            if pre_auth.auth_methods.contains(&method) {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("{method:?} authentication is not enabled in Tor"),
+                ))
+            }
+        };
 
-        let auth_data = pre_auth
-            .make_auth_data()?
-            .expect("failed to make auth data");
+        let auth_data = match &self.auth {
+            TorControlAuth::Auto => pre_auth.make_auth_data()?.ok_or_else(|| {
+                Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Tor requires authentication. Set tor_auth_method",
+                )
+            })?,
+            TorControlAuth::HashedPassword(password) => {
+                check_method(TorAuthMethod::HashedPassword)?;
+                TorAuthData::HashedPassword(Cow::Owned(password.clone()))
+            }
+            TorControlAuth::SafeCookie(cookie_file) => {
+                check_method(TorAuthMethod::SafeCookie)?;
+                let cookie_file = cookie_file
+                    .clone()
+                    .or_else(|| {
+                        pre_auth
+                            .cookie_file
+                            .as_ref()
+                            .map(|f| PathBuf::from(f.as_ref()))
+                    })
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::NotFound, "Tor did not report any cookie file")
+                    })?;
+                let cookie = fs::read(&cookie_file).await?;
+                if cookie.len() != COOKIE_LENGTH {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid Tor cookie found at {cookie_file:?}"),
+                    ));
+                }
+                TorAuthData::SafeCookie(Cow::Owned(cookie))
+            }
+        };
 
         unauth_conn.authenticate(&auth_data).await.map_err(|_| {
             Error::new(
                 ErrorKind::PermissionDenied,
                 "failed to authenticate with Tor",
             )
-        })?;
+        })
+    }
+
+    /// Sends a command to the Tor control port, failing if it is not successful.
+    async fn send_command(stream: &mut TcpStream, command: &str) -> Result<(), Error> {
+        let mut conn = Conn::new(stream);
+        conn.write_data(format!("{command}\r\n").as_bytes())
+            .await
+            .map_err(Error::other)?;
+        let (code, lines) = conn.receive_data().await.map_err(Error::other)?;
+
+        if code == 250 {
+            Ok(())
+        } else {
+            Err(Error::other(format!(
+                "Tor replied with {code}: {}",
+                lines.join(" ")
+            )))
+        }
+    }
 
-        let mut auth_conn = unauth_conn.into_authenticated().await;
+    /// Publishes an onion service, using a given key, that re-directs to the public api.
+    ///
+    /// Only authorized clients are allowed to reach the service, if any.
+    async fn add_onion(&self, stream: &mut TcpStream, sk: &TorSecretKeyV3) -> Result<(), Error> {
+        let mut command = format!(
+            "ADD_ONION ED25519-V3:{} Flags=DiscardPK",
+            base64::encode(&sk.as_bytes())
+        );
+        if !self.authorized_clients.is_empty() {
+            command.push_str(",V3Auth");
+        }
+        command.push_str(&format!(" Port={},{}", self.onion_port, self.api_endpoint));
+        for client in self.authorized_clients.iter() {
+            command.push_str(&format!(" ClientAuthV3={client}"));
+        }
 
-        auth_conn.set_async_event_handler(Some(|_| async move { Ok(()) }));
+        TorAPI::send_command(stream, &command)
+            .await
+            .map_err(|e| Error::other(format!("failed to create onion hidden service: {e}")))
+    }
 
-        auth_conn
-            .add_onion_v3(
-                &self.sk,
-                false,
-                false,
-                false,
-                None,
-                &mut [(self.onion_port, self.api_endpoint)].iter(),
-            )
+    /// Removes the onion service of a given key.
+    async fn del_onion(stream: &mut TcpStream, sk: &TorSecretKeyV3) -> Result<(), Error> {
+        TorAPI::send_command(
+            stream,
+            &format!(
+                "DEL_ONION {}",
+                sk.public()
+                    .get_onion_address()
+                    .get_address_without_dot_onion()
+            ),
+        )
+        .await
+    }
+
+    /// Expose an onion service that re-directs to the public api.
+    pub async fn expose_onion_service(
+        &self,
+        service_ready: Trigger,
+        shutdown_signal_tor: Listener,
+    ) -> Result<(), Error> {
+        let mut stream = self
+            .connect_tor_cp()
             .await
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
-                    format!("failed to create onion hidden service: {e}"),
-                )
-            })?;
+            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
+        self.authenticate(&mut stream).await?;
 
-        log::info!(
+        {
+            // Hold the connection so the key cannot be replaced until the service is up.
+            let mut control_conn = self.control_conn.lock().await;
+            self.add_onion(&mut stream, &self.get_sk()).await?;
+            *control_conn = Some(stream);
+        }
+
+        tracing::info!(
             "Onion service: {}:{}",
             self.get_onion_address(),
             self.onion_port
         );
+        if !self.authorized_clients.is_empty() {
+            tracing::info!(
+                "Onion service restricted to {} authorized client(s)",
+                self.authorized_clients.len()
+            );
+        }
         service_ready.trigger();
         shutdown_signal_tor.await;
 
-        auth_conn
-            .del_onion(
-                &self
-                    .sk
-                    .public()
-                    .get_onion_address()
-                    .get_address_without_dot_onion(),
-            )
-            .await
-            .unwrap();
+        if let Some(mut stream) = self.control_conn.lock().await.take() {
+            if let Err(e) = TorAPI::del_onion(&mut stream, &self.get_sk()).await {
+                tracing::error!("Cannot remove onion service: {e}");
+            }
+        }
         Ok(())
     }
 }
@@ -154,16 +327,160 @@ impl TorAPI {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::Arc;
     use tempdir::TempDir;
 
+    use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
     use teos_common::test_utils::get_random_user_id;
 
+    const CLIENT_AUTH_KEY: &str = "QC5OMIN3BAWSJDZPF5ORSSCD7YJ6NVLGRKUUSGLT7IRJMBJYJWWQ";
+    const SERVER_NONCE: [u8; 32] = [7; 32];
+
+    fn safe_cookie_hash(key: &[u8], cookie: &[u8], client_nonce: &[u8]) -> String {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+        engine.input(cookie);
+        engine.input(client_nonce);
+        engine.input(&SERVER_NONCE);
+        hex::encode(hmac::Hmac::<sha256::Hash>::from_engine(engine).into_inner())
+    }
+
+    /// Mock Tor control port. Supports SAFECOOKIE and HASHEDPASSWORD authentication and records the onion service
+    /// commands it receives.
+    struct MockControlPort {
+        port: u16,
+        commands: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockControlPort {
+        async fn new(
+            auth_methods: &'static str,
+            cookie_file: PathBuf,
+            password: &'static str,
+        ) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let commands = Arc::new(Mutex::new(Vec::new()));
+
+            let commands_cloned = commands.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    let commands = commands_cloned.clone();
+                    let cookie_file = cookie_file.clone();
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = socket.into_split();
+                        let mut lines = BufReader::new(reader).lines();
+                        let mut client_nonce = Vec::new();
+
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            let reply = if line.starts_with("PROTOCOLINFO") {
+                                format!(
+                                    "250-PROTOCOLINFO 1\r\n250-AUTH METHODS={auth_methods} COOKIEFILE={:?}\r\n250-VERSION Tor=\"0.4.7.8\"\r\n250 OK\r\n",
+                                    cookie_file
+                                )
+                            } else if let Some(nonce) =
+                                line.strip_prefix("AUTHCHALLENGE SAFECOOKIE ")
+                            {
+                                client_nonce = hex::decode(nonce).unwrap();
+                                let cookie = std::fs::read(&cookie_file).unwrap();
+                                format!(
+                                    "250 AUTHCHALLENGE SERVERHASH={} SERVERNONCE={}\r\n",
+                                    safe_cookie_hash(
+                                        b"Tor safe cookie authentication server-to-controller hash",
+                                        &cookie,
+                                        &client_nonce
+                                    ),
+                                    hex::encode(SERVER_NONCE)
+                                )
+                            } else if let Some(data) = line.strip_prefix("AUTHENTICATE ") {
+                                let authenticated = if data.starts_with('"') {
+                                    data == format!("{password:?}")
+                                } else {
+                                    let cookie = std::fs::read(&cookie_file).unwrap();
+                                    data.eq_ignore_ascii_case(&safe_cookie_hash(
+                                        b"Tor safe cookie authentication controller-to-server hash",
+                                        &cookie,
+                                        &client_nonce,
+                                    ))
+                                };
+                                if authenticated {
+                                    "250 OK\r\n".to_owned()
+                                } else {
+                                    "515 Authentication failed\r\n".to_owned()
+                                }
+                            } else if line.starts_with("ADD_ONION") {
+                                commands.lock().unwrap().push(line);
+                                "250-ServiceID=service\r\n250 OK\r\n".to_owned()
+                            } else if line.starts_with("DEL_ONION") {
+                                commands.lock().unwrap().push(line);
+                                "250 OK\r\n".to_owned()
+                            } else {
+                                "510 Unrecognized command\r\n".to_owned()
+                            };
+
+                            if writer.write_all(reply.as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            });
+
+            MockControlPort { port, commands }
+        }
+
+        fn commands(&self) -> Vec<String> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    async fn get_tor_api(
+        tor_control_port: u16,
+        auth: TorControlAuth,
+        authorized_clients: Vec<String>,
+        path: PathBuf,
+    ) -> TorAPI {
+        TorAPI::new(
+            "127.0.0.1:9814".parse().unwrap(),
+            9814,
+            tor_control_port,
+            auth,
+            authorized_clients,
+            path,
+        )
+        .await
+    }
+
+    #[test]
+    fn test_parse_client_auth_key() {
+        assert_eq!(
+            parse_client_auth_key(CLIENT_AUTH_KEY),
+            Ok(CLIENT_AUTH_KEY.to_owned())
+        );
+        assert_eq!(
+            parse_client_auth_key(&format!(
+                "descriptor:x25519:{}",
+                CLIENT_AUTH_KEY.to_lowercase()
+            )),
+            Ok(CLIENT_AUTH_KEY.to_owned())
+        );
+
+        // Wrong length, not base32 and wrong key type
+        assert!(parse_client_auth_key(&CLIENT_AUTH_KEY[1..]).is_err());
+        assert!(parse_client_auth_key(&CLIENT_AUTH_KEY.replace('Q', "1")).is_err());
+        assert!(parse_client_auth_key(&format!("descriptor:ed25519:{CLIENT_AUTH_KEY}")).is_err());
+    }
+
     #[tokio::test]
     async fn test_store_load_sk() {
         let key = TorSecretKeyV3::generate();
         let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
 
-        TorAPI::store_sk(&key, tmp_path.path().into()).await;
+        TorAPI::store_sk(&key, tmp_path.path().into())
+            .await
+            .unwrap();
         let loaded_key = TorAPI::load_sk(tmp_path.path().into()).await;
 
         assert_eq!(key, loaded_key.unwrap())
@@ -188,14 +505,36 @@ mod tests {
         assert_eq!(loaded_key, None);
     }
 
+    #[tokio::test]
+    async fn test_set_sk() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let tor_api = get_tor_api(
+            9000,
+            TorControlAuth::Auto,
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+
+        // If the service is not exposed the key is simply replaced
+        let key = TorSecretKeyV3::generate();
+        tor_api.set_sk(key.clone()).await.unwrap();
+        assert_eq!(tor_api.get_sk(), key);
+        assert_eq!(
+            tor_api.get_onion_address(),
+            key.public().get_onion_address().to_string()
+        );
+        assert_eq!(TorAPI::load_sk(tmp_path.path().into()).await, Some(key));
+    }
+
     #[tokio::test]
     async fn test_connect_tor_cp_fail() {
         let wrong_cp = 9000;
         let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
-        let tor_api = TorAPI::new(
-            "127.0.1.1:9814".parse().unwrap(),
-            9814,
+        let tor_api = get_tor_api(
             wrong_cp,
+            TorControlAuth::Auto,
+            Vec::new(),
             tmp_path.path().into(),
         )
         .await;
@@ -207,4 +546,161 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_authenticate_safe_cookie() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let cookie_file = tmp_path.path().join("control_auth_cookie");
+        fs::write(&cookie_file, [1; COOKIE_LENGTH]).await.unwrap();
+        let mock = MockControlPort::new("SAFECOOKIE", cookie_file, "").await;
+
+        // The cookie file reported by Tor is used by default
+        let tor_api = get_tor_api(
+            mock.port,
+            TorControlAuth::SafeCookie(None),
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+        let mut stream = tor_api.connect_tor_cp().await.unwrap();
+        tor_api.authenticate(&mut stream).await.unwrap();
+
+        // Authentication fails if the cookie is wrong
+        let wrong_cookie_file = tmp_path.path().join("wrong_cookie");
+        fs::write(&wrong_cookie_file, [2; COOKIE_LENGTH])
+            .await
+            .unwrap();
+        let tor_api = get_tor_api(
+            mock.port,
+            TorControlAuth::SafeCookie(Some(wrong_cookie_file.clone())),
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+        let mut stream = tor_api.connect_tor_cp().await.unwrap();
+        assert_eq!(
+            tor_api.authenticate(&mut stream).await.unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+
+        // Or if it is not a cookie at all
+        fs::write(&wrong_cookie_file, "random stuff").await.unwrap();
+        let mut stream = tor_api.connect_tor_cp().await.unwrap();
+        assert_eq!(
+            tor_api.authenticate(&mut stream).await.unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_hashed_password() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let mock = MockControlPort::new("HASHEDPASSWORD", PathBuf::new(), "password").await;
+
+        let tor_api = get_tor_api(
+            mock.port,
+            TorControlAuth::HashedPassword("password".to_owned()),
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+        let mut stream = tor_api.connect_tor_cp().await.unwrap();
+        tor_api.authenticate(&mut stream).await.unwrap();
+
+        let tor_api = get_tor_api(
+            mock.port,
+            TorControlAuth::HashedPassword("wrong password".to_owned()),
+            Vec::new(),
+            tmp_path.path().into(),
+        )
+        .await;
+        let mut stream = tor_api.connect_tor_cp().await.unwrap();
+        assert_eq!(
+            tor_api.authenticate(&mut stream).await.unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+
+        // Methods not offered by Tor are rejected before trying them, and so is having no method at all
+        for auth in [TorControlAuth::SafeCookie(None), TorControlAuth::Auto] {
+            let tor_api = get_tor_api(mock.port, auth, Vec::new(), tmp_path.path().into()).await;
+            let mut stream = tor_api.connect_tor_cp().await.unwrap();
+            assert_eq!(
+                tor_api.authenticate(&mut stream).await.unwrap_err().kind(),
+                ErrorKind::PermissionDenied
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expose_onion_service() {
+        let tmp_path = TempDir::new(&format!("data_dir_{}", get_random_user_id())).unwrap();
+        let mock = MockControlPort::new("HASHEDPASSWORD", PathBuf::new(), "password").await;
+        let tor_api = Arc::new(
+            get_tor_api(
+                mock.port,
+                TorControlAuth::HashedPassword("password".to_owned()),
+                vec![CLIENT_AUTH_KEY.to_owned()],
+                tmp_path.path().into(),
+            )
+            .await,
+        );
+        let old_sk = tor_api.get_sk();
+
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let tor_api_cloned = tor_api.clone();
+        let task = tokio::spawn(async move {
+            tor_api_cloned
+                .expose_onion_service(service_ready, shutdown_signal)
+                .await
+        });
+        ready_signal.await;
+
+        // The service is only reachable by the authorized clients
+        assert_eq!(
+            mock.commands(),
+            vec![format!(
+                "ADD_ONION ED25519-V3:{} Flags=DiscardPK,V3Auth Port=9814,127.0.0.1:9814 ClientAuthV3={CLIENT_AUTH_KEY}",
+                base64::encode(&old_sk.as_bytes())
+            )]
+        );
+
+        // Replacing the key publishes the new service before removing the old one
+        let new_sk = TorSecretKeyV3::generate();
+        tor_api.set_sk(new_sk.clone()).await.unwrap();
+        let commands = mock.commands();
+        assert_eq!(commands.len(), 3);
+        assert!(commands[1].starts_with(&format!(
+            "ADD_ONION ED25519-V3:{} ",
+            base64::encode(&new_sk.as_bytes())
+        )));
+        assert_eq!(
+            commands[2],
+            format!(
+                "DEL_ONION {}",
+                old_sk
+                    .public()
+                    .get_onion_address()
+                    .get_address_without_dot_onion()
+            )
+        );
+        assert_eq!(
+            TorAPI::load_sk(tmp_path.path().into()).await,
+            Some(new_sk.clone())
+        );
+
+        // The current service is removed on shutdown
+        shutdown_trigger.trigger();
+        task.await.unwrap().unwrap();
+        assert_eq!(
+            mock.commands().last().unwrap(),
+            &format!(
+                "DEL_ONION {}",
+                new_sk
+                    .public()
+                    .get_onion_address()
+                    .get_address_without_dot_onion()
+            )
+        );
+    }
 }
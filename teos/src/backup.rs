@@ -0,0 +1,1164 @@
+//! Logic related to tower backups.
+//!
+//! A backup bundles a consistent snapshot of the tower database (which holds the tower keys) with the certificates of
+//! the private API and the onion service key. Backups are encrypted either with an operator passphrase or to an operator
+//! public key, so they can be safely stored off the tower host. They can be created while the tower is running and
+//! restored using `teosd --restore`. Backups are streamed from and to disk, so they are never held in memory as a whole.
+
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use serde::{Deserialize, Serialize};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::ecdh::SharedSecret;
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use bitcoin::BlockHash;
+
+use teos_common::cryptography::{self, get_random_bytes, get_random_keypair};
+use teos_common::dbm;
+
+use crate::dbm::DBM;
+use crate::notifier::now;
+
+/// Name of the tower database file, inside the network directory.
+pub const DB_FILE: &str = "teos_db.sql3";
+/// Magic bytes every backup starts with.
+const MAGIC: &[u8] = b"TEOSBKP";
+/// Version of the backup format.
+const BACKUP_VERSION: u8 = 1;
+/// Encryption scheme of backups encrypted with a passphrase.
+const PASSPHRASE_SCHEME: u8 = 0;
+/// Encryption scheme of backups encrypted to a public key.
+const PUBLIC_KEY_SCHEME: u8 = 1;
+/// Cost parameter (log2) of the scrypt key derivation used for passphrases. Stored in the backup header.
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 15;
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 4;
+/// Highest scrypt cost accepted when decrypting, so a crafted backup cannot make the key derivation take forever.
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const SALT_LEN: usize = 16;
+/// Length of the nonce prefix of the encryption stream. The rest of each chunk nonce is a counter and a last chunk flag.
+const NONCE_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+/// Size of the (plaintext) chunks backups are encrypted in.
+#[cfg(not(test))]
+const CHUNK_SIZE: usize = 64 * 1024;
+#[cfg(test)]
+const CHUNK_SIZE: usize = 64;
+/// Largest metadata accepted when restoring, so a crafted backup cannot make the tower allocate too much memory.
+const MAX_METADATA_LEN: u32 = 1024 * 1024;
+/// Largest file (other than the database) accepted when restoring. Certificates and keys are way smaller than this.
+const MAX_FILE_LEN: u64 = 1024 * 1024;
+/// Files backed up along with the database (relative to the data directory). Missing files are skipped.
+const DATA_DIR_FILES: [&str; 6] = [
+    "ca.pem",
+    "ca-key.pem",
+    "server.pem",
+    "server-key.pem",
+    "client.pem",
+    "client-key.pem",
+];
+/// Files backed up along with the database (relative to the network directory). Missing files are skipped.
+const NETWORK_DIR_FILES: [&str; 1] = ["onion_v3_sk"];
+
+/// Packs the reasons why creating or restoring a backup may fail.
+#[derive(Debug)]
+pub enum BackupError {
+    IoError(std::io::Error),
+    DatabaseError(String),
+    InvalidBackup(String),
+    DecryptionFailed,
+    NetworkMismatch(String),
+    AlreadyExists(PathBuf),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::IoError(e) => write!(f, "{e}"),
+            BackupError::DatabaseError(e) => write!(f, "Database error: {e}"),
+            BackupError::InvalidBackup(e) => write!(f, "Invalid backup: {e}"),
+            BackupError::DecryptionFailed => {
+                write!(f, "Cannot decrypt the backup. Wrong passphrase or key?")
+            }
+            BackupError::NetworkMismatch(network) => {
+                write!(f, "The backup belongs to a tower running on {network}")
+            }
+            BackupError::AlreadyExists(path) => write!(
+                f,
+                "{} already exists. Move it somewhere else before restoring",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self {
+        // Errors found while decrypting are bubbled up through the reader wrapped in an io::Error
+        if matches!(e.get_ref(), Some(inner) if inner.is::<BackupError>()) {
+            *e.into_inner().unwrap().downcast::<BackupError>().unwrap()
+        } else {
+            BackupError::IoError(e)
+        }
+    }
+}
+
+/// How a backup is encrypted.
+pub enum BackupEncryption {
+    Passphrase(String),
+    PublicKey(PublicKey),
+}
+
+/// How a backup is decrypted.
+pub enum BackupDecryption {
+    Passphrase(String),
+    SecretKey(SecretKey),
+}
+
+/// A file included in a backup. Its content follows the database snapshot in the backup payload.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct BackupFile {
+    /// Path of the file, relative to the data directory.
+    path: String,
+    len: u64,
+}
+
+/// What a backup holds. The (decrypted) backup payload is the length of the serialized metadata (4-byte big endian),
+/// followed by the metadata itself, the database snapshot and the content of each file.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct BackupMetadata {
+    network: String,
+    created_at: u64,
+    last_known_block: Option<String>,
+    database_len: u64,
+    files: Vec<BackupFile>,
+}
+
+/// Writes a backup payload (see [BackupMetadata]), streaming the database snapshot from `database`.
+fn write_payload<W: Write, R: Read>(
+    writer: &mut W,
+    metadata: &BackupMetadata,
+    database: &mut R,
+    files: &[Vec<u8>],
+) -> Result<(), BackupError> {
+    let metadata = serde_json::to_vec(metadata).unwrap();
+    writer.write_all(&(metadata.len() as u32).to_be_bytes())?;
+    writer.write_all(&metadata)?;
+    io::copy(database, writer)?;
+    for content in files {
+        writer.write_all(content)?;
+    }
+    Ok(())
+}
+
+/// Reads the metadata at the beginning of a backup payload.
+fn read_metadata<R: Read>(reader: &mut R) -> Result<BackupMetadata, BackupError> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).map_err(truncated)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_METADATA_LEN {
+        return Err(BackupError::InvalidBackup("metadata is too big".to_owned()));
+    }
+    let metadata = read_bytes(reader, len as usize)?;
+
+    serde_json::from_slice(&metadata).map_err(|e| BackupError::InvalidBackup(e.to_string()))
+}
+
+/// Derives an encryption key from a passphrase using scrypt.
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8) -> Result<[u8; 32], BackupError> {
+    cryptography::derive_key(passphrase, salt, log_n)
+        .map_err(|_| BackupError::InvalidBackup("wrong key derivation parameters".to_owned()))
+}
+
+/// Derives an encryption key from an ECDH shared secret.
+fn shared_key(pk: &PublicKey, sk: &SecretKey) -> [u8; 32] {
+    sha256::Hash::hash(&SharedSecret::new(pk, sk).secret_bytes()).into_inner()
+}
+
+/// Maps the errors of reading a truncated backup header.
+fn truncated(e: io::Error) -> BackupError {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        BackupError::InvalidBackup("truncated data".to_owned())
+    } else {
+        e.into()
+    }
+}
+
+/// Reads exactly `len` bytes from `reader`.
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, BackupError> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf).map_err(truncated)?;
+    Ok(buf)
+}
+
+/// Encrypts data using `chacha20poly1305` in the STREAM construction, so it can be written as it is produced.
+///
+/// The output is a header (magic bytes, version, scheme and the data needed to derive the key), followed by the nonce
+/// prefix and the encrypted chunks. The header is authenticated along with every chunk, and the last chunk is flagged
+/// as such so truncated backups are detected.
+struct Encryptor<W: Write> {
+    writer: W,
+    stream: EncryptorBE32<ChaCha20Poly1305>,
+    header: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Encryptor<W> {
+    /// Writes the backup header to `writer` and returns an [Encryptor] writing to it.
+    fn new(mut writer: W, encryption: &BackupEncryption) -> io::Result<Self> {
+        let mut header = MAGIC.to_vec();
+        header.push(BACKUP_VERSION);
+        let key = match encryption {
+            BackupEncryption::Passphrase(passphrase) => {
+                let salt = get_random_bytes(SALT_LEN);
+                header.push(PASSPHRASE_SCHEME);
+                header.push(SCRYPT_LOG_N);
+                header.extend(&salt);
+                derive_key(passphrase, &salt, SCRYPT_LOG_N).unwrap()
+            }
+            BackupEncryption::PublicKey(pk) => {
+                let (ephemeral_sk, ephemeral_pk) = get_random_keypair();
+                header.push(PUBLIC_KEY_SCHEME);
+                header.extend(ephemeral_pk.serialize());
+                shared_key(pk, &ephemeral_sk)
+            }
+        };
+
+        let nonce = get_random_bytes(NONCE_LEN);
+        writer.write_all(&header)?;
+        writer.write_all(&nonce)?;
+        Ok(Encryptor {
+            writer,
+            stream: EncryptorBE32::new(Key::from_slice(&key), nonce.as_slice().into()),
+            header,
+            buffer: Vec::with_capacity(CHUNK_SIZE + 1),
+        })
+    }
+
+    /// Encrypts the last chunk and returns the underlying writer.
+    fn finish(mut self) -> io::Result<W> {
+        let chunk = self
+            .stream
+            .encrypt_last(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })
+            .unwrap();
+        self.writer.write_all(&chunk)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for Encryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only encrypted once more data comes in, given the last chunk needs to be flagged
+        let len = buf.len().min(CHUNK_SIZE + 1 - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() > CHUNK_SIZE {
+            let chunk = self
+                .stream
+                .encrypt_next(Payload {
+                    msg: &self.buffer[..CHUNK_SIZE],
+                    aad: &self.header,
+                })
+                .unwrap();
+            self.writer.write_all(&chunk)?;
+            self.buffer.drain(..CHUNK_SIZE);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts data encrypted using an [Encryptor], one chunk at a time.
+///
+/// Decryption errors are returned by `read` wrapped in an [io::Error], and can be turned back into a [BackupError].
+struct Decryptor<R: Read> {
+    reader: R,
+    stream: Option<DecryptorBE32<ChaCha20Poly1305>>,
+    header: Vec<u8>,
+    /// The next encrypted chunk, read ahead to know whether the current one is the last.
+    next: Vec<u8>,
+    /// The current decrypted chunk, and how much of it has already been read.
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Reads the backup header from `reader` and returns a [Decryptor] reading from it.
+    fn new(mut reader: R, decryption: &BackupDecryption) -> Result<Self, BackupError> {
+        let mut header = read_bytes(&mut reader, MAGIC.len())?;
+        if header != MAGIC {
+            return Err(BackupError::InvalidBackup("not a tower backup".to_owned()));
+        }
+        header.extend(read_bytes(&mut reader, 2)?);
+        let (version, scheme) = (header[MAGIC.len()], header[MAGIC.len() + 1]);
+        if version != BACKUP_VERSION {
+            return Err(BackupError::InvalidBackup(format!(
+                "unsupported version {version}"
+            )));
+        }
+
+        let key = match (scheme, decryption) {
+            (PASSPHRASE_SCHEME, BackupDecryption::Passphrase(passphrase)) => {
+                let params = read_bytes(&mut reader, 1 + SALT_LEN)?;
+                header.extend(&params);
+                if params[0] > MAX_SCRYPT_LOG_N {
+                    return Err(BackupError::InvalidBackup(
+                        "wrong key derivation parameters".to_owned(),
+                    ));
+                }
+                derive_key(passphrase, &params[1..], params[0])?
+            }
+            (PUBLIC_KEY_SCHEME, BackupDecryption::SecretKey(sk)) => {
+                let params = read_bytes(&mut reader, 33)?;
+                header.extend(&params);
+                let ephemeral_pk = PublicKey::from_slice(&params)
+                    .map_err(|_| BackupError::InvalidBackup("wrong ephemeral key".to_owned()))?;
+                shared_key(&ephemeral_pk, sk)
+            }
+            (PASSPHRASE_SCHEME, _) => {
+                return Err(BackupError::InvalidBackup(
+                    "the backup is encrypted with a passphrase".to_owned(),
+                ))
+            }
+            (PUBLIC_KEY_SCHEME, _) => {
+                return Err(BackupError::InvalidBackup(
+                    "the backup is encrypted to a public key".to_owned(),
+                ))
+            }
+            _ => {
+                return Err(BackupError::InvalidBackup(format!(
+                    "unknown encryption scheme {scheme}"
+                )))
+            }
+        };
+
+        let nonce = read_bytes(&mut reader, NONCE_LEN)?;
+        let mut decryptor = Decryptor {
+            reader,
+            stream: Some(DecryptorBE32::new(
+                Key::from_slice(&key),
+                nonce.as_slice().into(),
+            )),
+            header,
+            next: Vec::new(),
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        decryptor.next = decryptor.read_chunk()?;
+        if decryptor.next.is_empty() {
+            return Err(BackupError::InvalidBackup("truncated data".to_owned()));
+        }
+        Ok(decryptor)
+    }
+
+    /// Reads an encrypted chunk. Only the last one can be shorter than the rest, and it is empty past the end.
+    fn read_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+        (&mut self.reader)
+            .take((CHUNK_SIZE + TAG_LEN) as u64)
+            .read_to_end(&mut chunk)?;
+        Ok(chunk)
+    }
+
+    /// Decrypts the next chunk. Returns false once the last chunk has been decrypted.
+    fn decrypt_chunk(&mut self) -> io::Result<bool> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return Ok(false),
+        };
+        let next = self.read_chunk()?;
+        let current = std::mem::replace(&mut self.next, next);
+        let payload = Payload {
+            msg: &current,
+            aad: &self.header,
+        };
+        let decrypted = if self.next.is_empty() {
+            stream.decrypt_last(payload)
+        } else {
+            let mut stream = stream;
+            let decrypted = stream.decrypt_next(payload);
+            self.stream = Some(stream);
+            decrypted
+        };
+
+        self.chunk = decrypted
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, BackupError::DecryptionFailed))?;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if !self.decrypt_chunk()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Component in charge of creating tower backups.
+pub struct BackupManager {
+    /// A [DBM] (database manager) instance. Used to snapshot the tower database.
+    dbm: Arc<Mutex<DBM>>,
+    /// The data directory of the tower.
+    data_dir: PathBuf,
+    /// The network the tower is running on.
+    network: String,
+}
+
+impl BackupManager {
+    /// Creates a new [BackupManager] instance.
+    pub fn new(dbm: Arc<Mutex<DBM>>, data_dir: PathBuf, network: String) -> Self {
+        BackupManager {
+            dbm,
+            data_dir,
+            network,
+        }
+    }
+
+    /// Creates an encrypted backup of the tower and writes it to `path` (relative to the data directory if not absolute).
+    ///
+    /// Defaults to `backups/teos-backup-<network>-<timestamp>.bak` if no path is given. Returns the path the backup was
+    /// written to along with the last block known by the tower at the time.
+    pub fn create_backup(
+        &self,
+        encryption: &BackupEncryption,
+        path: Option<PathBuf>,
+    ) -> Result<(PathBuf, Option<BlockHash>), BackupError> {
+        let created_at = now();
+        let path = self.data_dir.join(path.unwrap_or_else(|| {
+            PathBuf::from("backups").join(format!("teos-backup-{}-{created_at}.bak", self.network))
+        }));
+        if path.exists() {
+            return Err(BackupError::AlreadyExists(path));
+        }
+
+        let mut snapshot_path = path.clone().into_os_string();
+        snapshot_path.push(".snapshot");
+        let snapshot_path = PathBuf::from(snapshot_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // The database is snapshotted through a dedicated connection, so the tower is not blocked while it is copied.
+        // The last known block is read from the snapshot itself, so both are consistent.
+        let source = self.dbm.lock().unwrap().snapshot_source();
+        let snapshot = source
+            .and_then(|source| source.snapshot(&snapshot_path))
+            .map_err(|e| BackupError::DatabaseError(e.to_string()));
+        let result = snapshot.and_then(|last_known_block| {
+            self.write_backup(
+                encryption,
+                &path,
+                &snapshot_path,
+                created_at,
+                last_known_block,
+            )
+            .map(|_| last_known_block)
+        });
+        if snapshot_path.exists() {
+            fs::remove_file(&snapshot_path)?;
+        }
+        let last_known_block = result?;
+
+        tracing::info!("Backup written to {}", path.display());
+        Ok((path, last_known_block))
+    }
+
+    /// Writes a backup of the given database snapshot (along with the rest of the backed up files) to `path`.
+    /// Nothing is left behind if writing the backup fails.
+    fn write_backup(
+        &self,
+        encryption: &BackupEncryption,
+        path: &Path,
+        snapshot_path: &Path,
+        created_at: u64,
+        last_known_block: Option<BlockHash>,
+    ) -> Result<(), BackupError> {
+        let mut files = Vec::new();
+        let mut contents = Vec::new();
+        let network_files = NETWORK_DIR_FILES
+            .iter()
+            .map(|file| format!("{}/{file}", self.network));
+        for file in DATA_DIR_FILES
+            .iter()
+            .map(|file| file.to_string())
+            .chain(network_files)
+        {
+            match fs::read(self.data_dir.join(&file)) {
+                Ok(content) => {
+                    files.push(BackupFile {
+                        path: file,
+                        len: content.len() as u64,
+                    });
+                    contents.push(content);
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut database = File::open(snapshot_path)?;
+        let metadata = BackupMetadata {
+            network: self.network.clone(),
+            created_at,
+            last_known_block: last_known_block.map(|block_hash| block_hash.to_string()),
+            database_len: database.metadata()?.len(),
+            files,
+        };
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let backup = options.open(path)?;
+        let result = Encryptor::new(BufWriter::new(backup), encryption)
+            .map_err(BackupError::from)
+            .and_then(|mut writer| {
+                write_payload(&mut writer, &metadata, &mut database, &contents)?;
+                writer.finish()?;
+                Ok(())
+            });
+        if result.is_err() {
+            fs::remove_file(path)?;
+        }
+        result
+    }
+}
+
+/// Checks that a file included in a backup is one of the files backups are made of, so restoring it cannot write
+/// anywhere else in (or outside of) the data directory. Absolute paths and paths with anything but plain components
+/// (like `..`) are rejected upfront.
+fn check_file_path(file: &str, network: &str) -> Result<(), BackupError> {
+    let invalid = || BackupError::InvalidBackup(format!("unexpected file {file}"));
+    let path = Path::new(file);
+    if path.is_absolute()
+        || path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(invalid());
+    }
+
+    let known = DATA_DIR_FILES.contains(&file)
+        || NETWORK_DIR_FILES
+            .iter()
+            .any(|network_file| file == format!("{network}/{network_file}"));
+    if known {
+        Ok(())
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Reads the rest of a backup payload (past its metadata), streaming the database snapshot to `restore_path`.
+/// Returns the content of the backed up files.
+fn read_payload<R: Read>(
+    reader: &mut R,
+    metadata: &BackupMetadata,
+    restore_path: &Path,
+) -> Result<Vec<Vec<u8>>, BackupError> {
+    let mut database = BufWriter::new(File::create(restore_path)?);
+    let copied = io::copy(&mut reader.take(metadata.database_len), &mut database)?;
+    database.flush()?;
+    if copied != metadata.database_len {
+        return Err(BackupError::InvalidBackup("truncated data".to_owned()));
+    }
+
+    let mut contents = Vec::new();
+    for file in metadata.files.iter() {
+        if file.len > MAX_FILE_LEN {
+            return Err(BackupError::InvalidBackup(format!(
+                "{} is too big",
+                file.path
+            )));
+        }
+        let mut content = vec![0; usize::try_from(file.len).unwrap()];
+        reader.read_exact(&mut content).map_err(truncated)?;
+        contents.push(content);
+    }
+
+    // Reading past the end makes sure the whole backup has been authenticated
+    if reader.read(&mut [0])? != 0 {
+        return Err(BackupError::InvalidBackup("trailing data".to_owned()));
+    }
+    Ok(contents)
+}
+
+/// Gets the path a file being restored is written to before being moved to `path`.
+fn get_staging_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(".restore");
+    path.with_file_name(file_name)
+}
+
+/// Restores a tower backup into the given data directory, returning the last block known by the tower when the backup
+/// was created (if any).
+///
+/// The backup must belong to a tower running on the same `network` and the network directory must not contain a
+/// database already. The database snapshot is validated before being put in place, using `db_key` if it is encrypted.
+pub fn restore_backup(
+    backup: &Path,
+    decryption: &BackupDecryption,
+    data_dir: &Path,
+    network: &str,
+    db_key: Option<&str>,
+) -> Result<Option<BlockHash>, BackupError> {
+    let mut reader = Decryptor::new(BufReader::new(File::open(backup)?), decryption)?;
+    let metadata = read_metadata(&mut reader)?;
+    if metadata.network != network {
+        return Err(BackupError::NetworkMismatch(metadata.network));
+    }
+    let last_known_block = metadata
+        .last_known_block
+        .as_deref()
+        .map(BlockHash::from_str)
+        .transpose()
+        .map_err(|e| BackupError::InvalidBackup(e.to_string()))?;
+    for file in metadata.files.iter() {
+        check_file_path(&file.path, network)?;
+    }
+
+    let network_dir = data_dir.join(network);
+    let db_path = network_dir.join(DB_FILE);
+    if db_path.exists() {
+        return Err(BackupError::AlreadyExists(db_path));
+    }
+    fs::create_dir_all(&network_dir)?;
+
+    // Check the snapshot is a tower database matching the backup before putting it in place.
+    let restore_path = get_staging_path(&db_path);
+    let contents = match read_payload(&mut reader, &metadata, &restore_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            fs::remove_file(&restore_path)?;
+            return Err(e);
+        }
+    };
+    if db_key.is_none() && dbm::is_encrypted_database(&restore_path) {
+        fs::remove_file(&restore_path)?;
+        return Err(BackupError::InvalidBackup(
+            "the database snapshot is encrypted but no database key was given".to_owned(),
+        ));
+    }
+    let valid = match DBM::new(restore_path.clone(), db_key) {
+        Ok(dbm) => {
+            // The tower key may be encrypted, in which case it is there but cannot be loaded without a passphrase
+            dbm.load_tower_key(None) != Ok(None) && dbm.load_last_known_block() == last_known_block
+        }
+        Err(_) => false,
+    };
+    if !valid {
+        fs::remove_file(&restore_path)?;
+        return Err(BackupError::InvalidBackup(
+            "the database snapshot does not match the backup".to_owned(),
+        ));
+    }
+    // The rest of the files are staged next to their final paths and moved into place before the database, so a
+    // restore failing halfway through can be retried (it is only considered done once the database is there).
+    let mut staged = Vec::new();
+    let moved = metadata
+        .files
+        .iter()
+        .zip(contents)
+        .try_for_each(|(file, content)| {
+            let path = data_dir.join(&file.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let staging_path = get_staging_path(&path);
+            staged.push(staging_path.clone());
+            fs::write(&staging_path, content)?;
+            Ok(())
+        })
+        .and_then(|_| {
+            for (file, staging_path) in metadata.files.iter().zip(staged.iter()) {
+                fs::rename(staging_path, data_dir.join(&file.path))?;
+            }
+            fs::rename(&restore_path, &db_path)
+        });
+    if let Err(e) = moved {
+        for path in staged.iter().chain([&restore_path]) {
+            // Files that were already moved into place are overwritten on retry
+            let _ = fs::remove_file(path);
+        }
+        return Err(e.into());
+    }
+
+    tracing::info!(
+        "Restored backup created at {} (last known block: {})",
+        metadata.created_at,
+        metadata.last_known_block.as_deref().unwrap_or("none")
+    );
+    Ok(last_known_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    use bitcoin::hashes::Hash;
+
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::gatekeeper::UserInfo;
+
+    const NETWORK: &str = "regtest";
+
+    fn encrypt(data: &[u8], encryption: &BackupEncryption) -> Vec<u8> {
+        let mut encryptor = Encryptor::new(Vec::new(), encryption).unwrap();
+        encryptor.write_all(data).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    fn decrypt(backup: &[u8], decryption: &BackupDecryption) -> Result<Vec<u8>, BackupError> {
+        let mut data = Vec::new();
+        Decryptor::new(backup, decryption)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn get_last_known_block() -> BlockHash {
+        BlockHash::from_slice(&get_random_bytes(32)).unwrap()
+    }
+
+    /// Creates a tower data directory with a database (encrypted with `db_key`, if any) and some of the files included in
+    /// backups.
+    fn init_data_dir(db_key: Option<&str>) -> (TempDir, BackupManager) {
+        let data_dir = TempDir::new("backup").unwrap();
+        let network_dir = data_dir.path().join(NETWORK);
+        fs::create_dir_all(&network_dir).unwrap();
+
+        let dbm = DBM::new(network_dir.join(DB_FILE), db_key).unwrap();
+        dbm.store_tower_key(&get_random_keypair().0, None).unwrap();
+        fs::write(data_dir.path().join("ca.pem"), "ca certificate").unwrap();
+        fs::write(network_dir.join("onion_v3_sk"), "onion key").unwrap();
+
+        let manager = BackupManager::new(
+            Arc::new(Mutex::new(dbm)),
+            data_dir.path().to_path_buf(),
+            NETWORK.to_owned(),
+        );
+        (data_dir, manager)
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_passphrase() {
+        let data = get_random_bytes(100);
+        let encryption = BackupEncryption::Passphrase("passphrase".to_owned());
+        let backup = encrypt(&data, &encryption);
+
+        assert_eq!(
+            decrypt(
+                &backup,
+                &BackupDecryption::Passphrase("passphrase".to_owned())
+            )
+            .unwrap(),
+            data
+        );
+        assert!(matches!(
+            decrypt(&backup, &BackupDecryption::Passphrase("wrong".to_owned())),
+            Err(BackupError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            decrypt(
+                &backup,
+                &BackupDecryption::SecretKey(get_random_keypair().0)
+            ),
+            Err(BackupError::InvalidBackup(_))
+        ));
+
+        // Tampering with the header is detected too
+        let mut tampered = backup;
+        tampered[MAGIC.len() + 3] ^= 1;
+        assert!(matches!(
+            decrypt(
+                &tampered,
+                &BackupDecryption::Passphrase("passphrase".to_owned())
+            ),
+            Err(BackupError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_public_key() {
+        let data = get_random_bytes(100);
+        let (sk, pk) = get_random_keypair();
+        let backup = encrypt(&data, &BackupEncryption::PublicKey(pk));
+
+        assert_eq!(
+            decrypt(&backup, &BackupDecryption::SecretKey(sk)).unwrap(),
+            data
+        );
+        assert!(matches!(
+            decrypt(
+                &backup,
+                &BackupDecryption::SecretKey(get_random_keypair().0)
+            ),
+            Err(BackupError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            decrypt(
+                &backup,
+                &BackupDecryption::Passphrase("passphrase".to_owned())
+            ),
+            Err(BackupError::InvalidBackup(_))
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_chunks() {
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        let encryption = BackupEncryption::Passphrase("passphrase".to_owned());
+        let chunk_len = CHUNK_SIZE + TAG_LEN;
+
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+            3 * CHUNK_SIZE + 1,
+        ] {
+            let data = get_random_bytes(len);
+            let backup = encrypt(&data, &encryption);
+            assert_eq!(decrypt(&backup, &decryption).unwrap(), data);
+        }
+
+        let data = get_random_bytes(3 * CHUNK_SIZE + 1);
+        let backup = encrypt(&data, &encryption);
+        let header_len = backup.len() - 3 * chunk_len - (1 + TAG_LEN);
+
+        // Dropping the last chunks is detected
+        for truncated_len in [header_len + chunk_len, header_len + 3 * chunk_len] {
+            assert!(matches!(
+                decrypt(&backup[..truncated_len], &decryption),
+                Err(BackupError::DecryptionFailed)
+            ));
+        }
+        // And so is swapping chunks around
+        let mut swapped = backup[..header_len].to_vec();
+        swapped.extend(&backup[header_len + chunk_len..header_len + 2 * chunk_len]);
+        swapped.extend(&backup[header_len..header_len + chunk_len]);
+        swapped.extend(&backup[header_len + 2 * chunk_len..]);
+        assert!(matches!(
+            decrypt(&swapped, &decryption),
+            Err(BackupError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_invalid_backup() {
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        for backup in [
+            b"not a backup".to_vec(),
+            MAGIC.to_vec(),
+            [MAGIC, &[BACKUP_VERSION + 1]].concat(),
+            [MAGIC, &[BACKUP_VERSION, PASSPHRASE_SCHEME, SCRYPT_LOG_N]].concat(),
+            [MAGIC, &[BACKUP_VERSION, 42]].concat(),
+        ] {
+            assert!(matches!(
+                decrypt(&backup, &decryption),
+                Err(BackupError::InvalidBackup(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_create_restore_backup() {
+        let (data_dir, manager) = init_data_dir(None);
+        let last_known_block = get_last_known_block();
+        let user_id = get_random_user_id();
+        {
+            let dbm = manager.dbm.lock().unwrap();
+            dbm.store_last_known_block(&last_known_block).unwrap();
+            dbm.store_user(user_id, &UserInfo::new(21, 42, 420))
+                .unwrap();
+        }
+
+        let (path, backup_block) = manager
+            .create_backup(&BackupEncryption::Passphrase("passphrase".to_owned()), None)
+            .unwrap();
+        assert!(path.starts_with(data_dir.path().join("backups")));
+        assert_eq!(backup_block, Some(last_known_block));
+        // Nothing is left behind but the backup itself
+        assert_eq!(
+            fs::read_dir(data_dir.path().join("backups"))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // Restore it in a fresh data directory
+        let restore_dir = TempDir::new("restore").unwrap();
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        assert_eq!(
+            restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None).unwrap(),
+            Some(last_known_block)
+        );
+
+        let restored_dbm = DBM::new(restore_dir.path().join(NETWORK).join(DB_FILE), None).unwrap();
+        let original_dbm = manager.dbm.lock().unwrap();
+        assert_eq!(
+            restored_dbm.load_tower_key(None),
+            original_dbm.load_tower_key(None)
+        );
+        assert_eq!(restored_dbm.load_last_known_block(), Some(last_known_block));
+        assert_eq!(restored_dbm.load_all_users(), original_dbm.load_all_users());
+        assert_eq!(
+            fs::read(restore_dir.path().join("ca.pem")).unwrap(),
+            b"ca certificate"
+        );
+        assert_eq!(
+            fs::read(restore_dir.path().join(NETWORK).join("onion_v3_sk")).unwrap(),
+            b"onion key"
+        );
+
+        // Existing databases are never overwritten
+        assert!(matches!(
+            restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None),
+            Err(BackupError::AlreadyExists(_))
+        ));
+        // And backups can only be restored on the same network
+        assert!(matches!(
+            restore_backup(&path, &decryption, restore_dir.path(), "bitcoin", None),
+            Err(BackupError::NetworkMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_backup_retry() {
+        let (_data_dir, manager) = init_data_dir(None);
+        let (path, _) = manager
+            .create_backup(&BackupEncryption::Passphrase("passphrase".to_owned()), None)
+            .unwrap();
+
+        // If one of the files cannot be put in place the restore fails without leaving the database behind
+        let restore_dir = TempDir::new("restore").unwrap();
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        let cert_path = restore_dir.path().join("ca.pem");
+        fs::create_dir(&cert_path).unwrap();
+        assert!(matches!(
+            restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None),
+            Err(BackupError::IoError(_))
+        ));
+        assert_eq!(
+            fs::read_dir(restore_dir.path().join(NETWORK))
+                .unwrap()
+                .count(),
+            0
+        );
+        assert_eq!(fs::read_dir(restore_dir.path()).unwrap().count(), 2);
+
+        // So it can be retried once the issue is fixed
+        fs::remove_dir(&cert_path).unwrap();
+        restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None).unwrap();
+        assert!(restore_dir.path().join(NETWORK).join(DB_FILE).exists());
+        assert_eq!(fs::read(cert_path).unwrap(), b"ca certificate");
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_create_restore_encrypted_backup() {
+        let (_data_dir, manager) = init_data_dir(Some("db key"));
+        let (path, _) = manager
+            .create_backup(&BackupEncryption::Passphrase("passphrase".to_owned()), None)
+            .unwrap();
+
+        // The snapshot of an encrypted database is encrypted with the same key, so it is required to restore it
+        let restore_dir = TempDir::new("restore").unwrap();
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        assert!(matches!(
+            restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None),
+            Err(BackupError::InvalidBackup(_))
+        ));
+        assert!(matches!(
+            restore_backup(
+                &path,
+                &decryption,
+                restore_dir.path(),
+                NETWORK,
+                Some("wrong key")
+            ),
+            Err(BackupError::InvalidBackup(_))
+        ));
+        restore_backup(
+            &path,
+            &decryption,
+            restore_dir.path(),
+            NETWORK,
+            Some("db key"),
+        )
+        .unwrap();
+
+        let db_path = restore_dir.path().join(NETWORK).join(DB_FILE);
+        assert!(dbm::is_encrypted_database(&db_path));
+        assert_eq!(
+            DBM::new(db_path, Some("db key"))
+                .unwrap()
+                .load_tower_key(None),
+            manager.dbm.lock().unwrap().load_tower_key(None)
+        );
+    }
+
+    #[test]
+    fn test_create_backup_existing_path() {
+        let (data_dir, manager) = init_data_dir(None);
+        let (_, pk) = get_random_keypair();
+
+        let path = data_dir.path().join("backup.bak");
+        manager
+            .create_backup(&BackupEncryption::PublicKey(pk), Some(path.clone()))
+            .unwrap();
+        assert!(matches!(
+            manager.create_backup(&BackupEncryption::PublicKey(pk), Some(path)),
+            Err(BackupError::AlreadyExists(_))
+        ));
+    }
+
+    /// Writes a backup with the given database and files to `dir`, encrypted with a passphrase.
+    fn write_backup(dir: &Path, database: &[u8], files: Vec<(String, Vec<u8>)>) -> PathBuf {
+        let metadata = BackupMetadata {
+            network: NETWORK.to_owned(),
+            created_at: now(),
+            last_known_block: None,
+            database_len: database.len() as u64,
+            files: files
+                .iter()
+                .map(|(path, content)| BackupFile {
+                    path: path.clone(),
+                    len: content.len() as u64,
+                })
+                .collect(),
+        };
+        let contents: Vec<Vec<u8>> = files.into_iter().map(|(_, content)| content).collect();
+        let mut payload = Vec::new();
+        write_payload(&mut payload, &metadata, &mut &database[..], &contents).unwrap();
+
+        let path = dir.join("backup.bak");
+        fs::write(
+            &path,
+            encrypt(
+                &payload,
+                &BackupEncryption::Passphrase("passphrase".to_owned()),
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_restore_backup_invalid_database() {
+        let data_dir = TempDir::new("backup").unwrap();
+        let path = write_backup(data_dir.path(), b"not a database", Vec::new());
+
+        assert!(matches!(
+            restore_backup(
+                &path,
+                &BackupDecryption::Passphrase("passphrase".to_owned()),
+                data_dir.path(),
+                NETWORK,
+                None
+            ),
+            Err(BackupError::InvalidBackup(_))
+        ));
+        assert!(!data_dir.path().join(NETWORK).join(DB_FILE).exists());
+    }
+
+    #[test]
+    fn test_restore_backup_unexpected_files() {
+        let data_dir = TempDir::new("backup").unwrap();
+        let restore_dir = TempDir::new("restore").unwrap();
+        let decryption = BackupDecryption::Passphrase("passphrase".to_owned());
+        let outside = restore_dir.path().parent().unwrap().join("outside.pem");
+
+        for file_path in [
+            "../outside.pem".to_owned(),
+            format!("{NETWORK}/../../outside.pem"),
+            "./ca.pem".to_owned(),
+            outside.to_string_lossy().to_string(),
+            "unknown.pem".to_owned(),
+            format!("{NETWORK}/ca.pem"),
+            "bitcoin/onion_v3_sk".to_owned(),
+        ] {
+            let path = write_backup(
+                data_dir.path(),
+                b"database",
+                vec![(file_path.clone(), b"malicious".to_vec())],
+            );
+
+            assert!(
+                matches!(
+                    restore_backup(&path, &decryption, restore_dir.path(), NETWORK, None),
+                    Err(BackupError::InvalidBackup(_))
+                ),
+                "{}",
+                file_path
+            );
+            // Nothing is written to disk, not even the database
+            assert!(!outside.exists());
+            assert_eq!(fs::read_dir(restore_dir.path()).unwrap().count(), 0);
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_restore_backup_truncated() {
+        let (_data_dir, manager) = init_data_dir(None);
+        let (path, _) = manager
+            .create_backup(&BackupEncryption::Passphrase("passphrase".to_owned()), None)
+            .unwrap();
+        let backup = fs::read(&path).unwrap();
+        fs::write(&path, &backup[..backup.len() - 1]).unwrap();
+
+        // The database snapshot is streamed to disk before the end of the backup is reached, but it is not left behind
+        let restore_dir = TempDir::new("restore").unwrap();
+        assert!(matches!(
+            restore_backup(
+                &path,
+                &BackupDecryption::Passphrase("passphrase".to_owned()),
+                restore_dir.path(),
+                NETWORK,
+                None
+            ),
+            Err(BackupError::DecryptionFailed)
+        ));
+        assert_eq!(
+            fs::read_dir(restore_dir.path().join(NETWORK))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+}
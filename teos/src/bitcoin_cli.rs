@@ -24,20 +24,20 @@ use lightning_block_sync::rpc::RpcClient;
 use lightning_block_sync::{AsyncBlockSourceResult, BlockHeaderData, BlockSource};
 
 /// A simple implementation of a bitcoind client (`bitcoin-cli`) with the minimal functionality required by the tower.
-pub struct BitcoindClient<'a> {
+pub struct BitcoindClient {
     /// The underlying RPC client.
     bitcoind_rpc_client: Arc<Mutex<RpcClient>>,
     /// The hostname to connect to.
-    host: &'a str,
+    host: String,
     /// The port to connect to.
     port: u16,
     /// The RPC user `bitcoind` is configured with.
-    rpc_user: &'a str,
+    rpc_user: String,
     /// The RPC password for the given user.
-    rpc_password: &'a str,
+    rpc_password: String,
 }
 
-impl BlockSource for &BitcoindClient<'_> {
+impl BlockSource for BitcoindClient {
     /// Gets a block header given its hash.
     fn get_header<'a>(
         &'a self,
@@ -69,25 +69,25 @@ impl BlockSource for &BitcoindClient<'_> {
 
 // TODO: This is not being used atm since we're using bitcoincore-rpc.
 // Not deleting it since wd should need it once both get merged.
-impl<'a> BitcoindClient<'a> {
+impl BitcoindClient {
     /// Creates a new [BitcoindClient] instance.
     pub async fn new(
-        host: &'a str,
+        host: &str,
         port: u16,
-        rpc_user: &'a str,
-        rpc_password: &'a str,
-        teos_network: &'a str,
-    ) -> std::io::Result<BitcoindClient<'a>> {
+        rpc_user: &str,
+        rpc_password: &str,
+        teos_network: &str,
+    ) -> std::io::Result<BitcoindClient> {
         let http_endpoint = HttpEndpoint::for_host(host.to_owned()).with_port(port);
         let rpc_credentials = base64::encode(&format!("{rpc_user}:{rpc_password}"));
         let bitcoind_rpc_client = RpcClient::new(&rpc_credentials, http_endpoint)?;
 
         let client = Self {
             bitcoind_rpc_client: Arc::new(Mutex::new(bitcoind_rpc_client)),
-            host,
+            host: host.to_owned(),
             port,
-            rpc_user,
-            rpc_password,
+            rpc_user: rpc_user.to_owned(),
+            rpc_password: rpc_password.to_owned(),
         };
 
         // Test that bitcoind is reachable.
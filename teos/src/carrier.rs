@@ -1,22 +1,91 @@
 //! Logic related to the Carrier, the component in charge or sending/requesting transaction data from/to `bitcoind`.
 
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::{Arc, Condvar, Mutex};
 
 use crate::responder::ConfirmationStatus;
 use crate::{errors, rpc_errors};
 
-use bitcoin::{Transaction, Txid};
+use bitcoin::{BlockHash, Transaction, Txid};
 use bitcoincore_rpc::{
     jsonrpc::error::Error::Rpc as RpcError, jsonrpc::error::Error::Transport as TransportError,
     Client as BitcoindClient, Error::JsonRpc as JsonRpcError, RpcApi,
 };
 
+/// Errors a [Broadcaster] can report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastError {
+    /// The request was rejected. Codes are expected to follow `bitcoind`'s RPC error codes.
+    Rejected { code: i32, message: String },
+    /// The backend could not be reached. The request is retried once it is reachable again.
+    Unreachable(String),
+    /// Any other error.
+    Other(String),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BroadcastError::Rejected { code, message } => write!(f, "{message} (code: {code})"),
+            BroadcastError::Unreachable(e) => write!(f, "Backend unreachable: {e}"),
+            BroadcastError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+impl From<bitcoincore_rpc::Error> for BroadcastError {
+    fn from(e: bitcoincore_rpc::Error) -> Self {
+        match e {
+            JsonRpcError(RpcError(rpcerr)) => BroadcastError::Rejected {
+                code: rpcerr.code,
+                message: rpcerr.message,
+            },
+            JsonRpcError(TransportError(e)) => BroadcastError::Unreachable(e.to_string()),
+            e => BroadcastError::Other(e.to_string()),
+        }
+    }
+}
+
+/// Shorthand for the results returned by a [Broadcaster].
+pub type BroadcastResult<T> = Result<T, BroadcastError>;
+
+/// The data a [Broadcaster] reports about a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionInfo {
+    /// The block the transaction is confirmed in, if any.
+    pub blockhash: Option<BlockHash>,
+}
+
+/// Interface the [Carrier] uses to send transactions to the network and to query the mempool.
+pub trait Broadcaster: Send + Sync {
+    /// Sends a transaction to the network (`sendrawtransaction`).
+    fn send_raw_transaction(&self, tx: &Transaction) -> BroadcastResult<Txid>;
+
+    /// Gets the data of a transaction given its id (`getrawtransaction`). Unknown transactions are expected to be
+    /// reported as rejected with [rpc_errors::RPC_INVALID_ADDRESS_OR_KEY].
+    fn get_raw_transaction_info(&self, txid: &Txid) -> BroadcastResult<TransactionInfo>;
+}
+
+impl Broadcaster for BitcoindClient {
+    fn send_raw_transaction(&self, tx: &Transaction) -> BroadcastResult<Txid> {
+        Ok(RpcApi::send_raw_transaction(self, tx)?)
+    }
+
+    fn get_raw_transaction_info(&self, txid: &Txid) -> BroadcastResult<TransactionInfo> {
+        let info = RpcApi::get_raw_transaction_info(self, txid, None)?;
+        Ok(TransactionInfo {
+            blockhash: info.blockhash,
+        })
+    }
+}
+
 /// Component in charge of the interaction with Bitcoind by sending / querying transactions via RPC.
-#[derive(Debug)]
 pub struct Carrier {
     /// The underlying bitcoin client used by the [Carrier].
-    bitcoin_cli: Arc<BitcoindClient>,
+    bitcoin_cli: Arc<dyn Broadcaster>,
     /// A flag that indicates wether bitcoind is reachable or not.
     bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
     /// A map of receipts already issued by the [Carrier].
@@ -26,10 +95,20 @@ pub struct Carrier {
     block_height: u32,
 }
 
+impl fmt::Debug for Carrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Carrier")
+            .field("bitcoind_reachable", &self.bitcoind_reachable)
+            .field("issued_receipts", &self.issued_receipts)
+            .field("block_height", &self.block_height)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Carrier {
     /// Creates a new [Carrier] instance.
     pub fn new(
-        bitcoin_cli: Arc<BitcoindClient>,
+        bitcoin_cli: Arc<dyn Broadcaster>,
         bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
         last_known_block_height: u32,
     ) -> Self {
@@ -78,36 +157,34 @@ impl Carrier {
     ///
     /// Returns a [ConfirmationStatus] indicating whether the transaction was accepted by the node or not.
     pub(crate) fn send_transaction(&mut self, tx: &Transaction) -> ConfirmationStatus {
+        let _span = tracing::info_span!("transaction", txid = %tx.txid()).entered();
         self.hang_until_bitcoind_reachable();
 
         if let Some(receipt) = self.issued_receipts.get(&tx.txid()) {
-            log::info!("Transaction already sent: {}", tx.txid());
+            tracing::info!("Transaction already sent");
             return *receipt;
         }
 
-        log::info!("Pushing transaction to the network: {}", tx.txid());
+        tracing::info!("Pushing transaction to the network");
         let receipt = match self.bitcoin_cli.send_raw_transaction(tx) {
             Ok(_) => {
                 // Here the transaction could, potentially, have been in mempool before the current height.
                 // This shouldn't really matter though.
-                log::info!("Transaction successfully delivered: {}", tx.txid());
+                tracing::info!("Transaction successfully delivered");
                 ConfirmationStatus::InMempoolSince(self.block_height)
             }
-            Err(JsonRpcError(RpcError(rpcerr))) => match rpcerr.code {
+            Err(BroadcastError::Rejected { code, message }) => match code {
                 // Since we're pushing a raw transaction to the network we can face several rejections
                 rpc_errors::RPC_VERIFY_REJECTED => {
-                    log::error!("Transaction couldn't be broadcast. {rpcerr:?}");
+                    tracing::error!("Transaction couldn't be broadcast. {message}");
                     ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_REJECTED)
                 }
                 rpc_errors::RPC_VERIFY_ERROR => {
-                    log::error!("Transaction couldn't be broadcast. {rpcerr:?}");
+                    tracing::error!("Transaction couldn't be broadcast. {message}");
                     ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_ERROR)
                 }
                 rpc_errors::RPC_VERIFY_ALREADY_IN_CHAIN => {
-                    log::info!(
-                        "Transaction was confirmed long ago, not keeping track of it: {}",
-                        tx.txid()
-                    );
+                    tracing::info!("Transaction was confirmed long ago, not keeping track of it");
 
                     // Given we are not using txindex, if a transaction bounces we cannot get its confirmation count. However, [send_transaction] is guarded by
                     // checking whether the transaction id can be found in the [Responder]'s [TxIndex], meaning that if the transaction bounces it was confirmed long
@@ -117,24 +194,26 @@ impl Carrier {
                 rpc_errors::RPC_DESERIALIZATION_ERROR => {
                     // Adding this here just for completeness. We should never end up here. The Carrier only sends txs handed by the Responder,
                     // who receives them from the Watcher, who checks that the tx can be properly deserialized.
-                    log::info!("Transaction cannot be deserialized: {}", tx.txid());
+                    tracing::info!("Transaction cannot be deserialized");
                     ConfirmationStatus::Rejected(rpc_errors::RPC_DESERIALIZATION_ERROR)
                 }
                 _ => {
                     // If something else happens (unlikely but possible) log it so we can treat it in future releases.
-                    log::error!("Unexpected rpc error when calling sendrawtransaction: {rpcerr:?}");
+                    tracing::error!(
+                        "Unexpected rpc error when calling sendrawtransaction: {message} (code: {code})"
+                    );
                     ConfirmationStatus::Rejected(errors::UNKNOWN_JSON_RPC_EXCEPTION)
                 }
             },
-            Err(JsonRpcError(TransportError(_))) => {
+            Err(BroadcastError::Unreachable(_)) => {
                 // Connection refused, bitcoind is down.
-                log::error!("Connection lost with bitcoind, retrying request when possible");
+                tracing::error!("Connection lost with bitcoind, retrying request when possible");
                 self.flag_bitcoind_unreachable();
                 self.send_transaction(tx)
             }
-            Err(e) => {
+            Err(BroadcastError::Other(e)) => {
                 // TODO: This may need finer catching.
-                log::error!("Unexpected error when calling sendrawtransaction: {e:?}");
+                tracing::error!("Unexpected error when calling sendrawtransaction: {e}");
                 ConfirmationStatus::Rejected(errors::UNKNOWN_JSON_RPC_EXCEPTION)
             }
         };
@@ -152,29 +231,29 @@ impl Carrier {
     pub(crate) fn in_mempool(&self, txid: &Txid) -> bool {
         self.hang_until_bitcoind_reachable();
 
-        match self.bitcoin_cli.get_raw_transaction_info(txid, None) {
+        match self.bitcoin_cli.get_raw_transaction_info(txid) {
             Ok(tx) => tx.blockhash.is_none(),
-            Err(JsonRpcError(RpcError(rpcerr))) => match rpcerr.code {
+            Err(BroadcastError::Rejected { code, .. }) => match code {
                 rpc_errors::RPC_INVALID_ADDRESS_OR_KEY => {
-                    log::info!("Transaction not found in mempool: {txid}");
+                    tracing::info!(%txid, "Transaction not found in mempool");
                     false
                 }
                 e => {
                     // DISCUSS: This could result in a silent error with unknown consequences
-                    log::error!("Unexpected error code when calling getrawtransaction: {e}");
+                    tracing::error!("Unexpected error code when calling getrawtransaction: {e}");
                     false
                 }
             },
-            Err(JsonRpcError(TransportError(_))) => {
+            Err(BroadcastError::Unreachable(_)) => {
                 // Connection refused, bitcoind is down.
-                log::error!("Connection lost with bitcoind, retrying request when possible");
+                tracing::error!("Connection lost with bitcoind, retrying request when possible");
                 self.flag_bitcoind_unreachable();
                 self.in_mempool(txid)
             }
             // TODO: This may need finer catching.
-            Err(e) => {
+            Err(BroadcastError::Other(e)) => {
                 // DISCUSS: This could result in a silent error with unknown consequences
-                log::error!("Unexpected JSONRPCError when calling getrawtransaction: {e}");
+                tracing::error!("Unexpected JSONRPCError when calling getrawtransaction: {e}");
                 false
             }
         }
@@ -2,16 +2,68 @@
 //!
 
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time;
 use tokio::time::timeout;
 use triggered::Listener;
 
+use bitcoin::BlockHeader;
 use lightning::chain;
 use lightning_block_sync::poll::{ChainTip, Poll, ValidatedBlockHeader};
 use lightning_block_sync::{BlockSourceErrorKind, Cache, SpvClient};
 
 use crate::dbm::DBM;
+use crate::feed::{Feed, FeedEvent};
+use crate::systemd::SystemdNotifier;
+
+/// Updates the `bitcoind` reachability flag, publishing an event to the live feed if it flips.
+fn set_reachable(reachable: &Mutex<bool>, feed: &Feed, value: bool) {
+    let mut reachable = reachable.lock().unwrap();
+    if *reachable != value {
+        *reachable = value;
+        feed.publish(FeedEvent::bitcoind_reachability(value));
+    }
+}
+
+/// Persists the last block the tower has processed, so an interrupted sync (e.g. the tower being shut down while
+/// catching up with a backlog of blocks) resumes from it.
+///
+/// Must be the last listener, so blocks are only recorded once every other listener has processed them.
+pub struct LastKnownBlockListener {
+    /// A [DBM] (database manager) instance. Used to persist the last known block.
+    dbm: Arc<Mutex<DBM>>,
+}
+
+impl LastKnownBlockListener {
+    /// Creates a new [LastKnownBlockListener] instance.
+    pub fn new(dbm: Arc<Mutex<DBM>>) -> Self {
+        LastKnownBlockListener { dbm }
+    }
+}
+
+impl chain::Listen for LastKnownBlockListener {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        _: &chain::transaction::TransactionData,
+        _: u32,
+    ) {
+        self.dbm
+            .lock()
+            .unwrap()
+            .store_last_known_block(&header.block_hash())
+            .unwrap();
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, _: u32) {
+        self.dbm
+            .lock()
+            .unwrap()
+            .store_last_known_block(&header.prev_blockhash)
+            .unwrap();
+    }
+}
 
 /// Component in charge of monitoring the chain for new blocks.
 ///
@@ -30,12 +82,16 @@ where
     last_known_block_header: ValidatedBlockHeader,
     /// A [DBM] (database manager) instance. Used to persist block data into disk.
     dbm: Arc<Mutex<DBM>>,
-    /// The time between polls.
-    polling_delta: time::Duration,
+    /// The time between polls, in seconds. Shared so it can be changed while the tower is running.
+    polling_delta: Arc<AtomicU16>,
     /// A signal from the main thread indicating the tower is shuting down.
     shutdown_signal: Listener,
     /// A flag that indicates wether bitcoind is reachable or not.
     bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
+    /// A [SystemdNotifier] instance. The systemd watchdog is pinged on every successful poll.
+    systemd: SystemdNotifier,
+    /// The live [Feed] of the tower. Changes in the `bitcoind` reachability are published to it.
+    feed: Arc<Feed>,
 }
 
 impl<'a, P, C, L> ChainMonitor<'a, P, C, L>
@@ -46,34 +102,50 @@ where
     L::Target: chain::Listen,
 {
     /// Creates a new [ChainMonitor] instance.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         spv_client: SpvClient<'a, P, C, L>,
         last_known_block_header: ValidatedBlockHeader,
         dbm: Arc<Mutex<DBM>>,
-        polling_delta_sec: u16,
+        polling_delta: Arc<AtomicU16>,
         shutdown_signal: Listener,
         bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
+        systemd: SystemdNotifier,
+        feed: Arc<Feed>,
     ) -> ChainMonitor<'a, P, C, L> {
         ChainMonitor {
             spv_client,
             last_known_block_header,
             dbm,
-            polling_delta: time::Duration::from_secs(polling_delta_sec as u64),
+            polling_delta,
             shutdown_signal,
             bitcoind_reachable,
+            systemd,
+            feed,
         }
     }
 
     /// Polls the best chain tip from bitcoind. Serves the data to its listeners (through [chain::Listen]) and logs data about the polled tips.
+    ///
+    /// Polling is given up if the shutdown signal is received meanwhile (e.g. while catching up with a backlog of
+    /// blocks). Blocks are connected one by one, so listeners are left at the last block they processed.
     pub async fn poll_best_tip(&mut self) {
         let (reachable, notifier) = &*self.bitcoind_reachable;
-        match self.spv_client.poll_best_tip().await {
+        let result = tokio::select! {
+            biased;
+            _ = self.shutdown_signal.clone() => {
+                tracing::debug!("Received shutting down signal while polling. Stopping");
+                return;
+            }
+            result = self.spv_client.poll_best_tip() => result,
+        };
+        match result {
             Ok((chain_tip, _)) => {
                 match chain_tip {
-                    ChainTip::Common => log::debug!("No new best tip found"),
+                    ChainTip::Common => tracing::debug!("No new best tip found"),
 
                     ChainTip::Better(new_best) => {
-                        log::debug!("Updating best tip: {}", new_best.header.block_hash());
+                        tracing::debug!("Updating best tip: {}", new_best.header.block_hash());
                         self.last_known_block_header = new_best;
                         self.dbm
                             .lock()
@@ -85,27 +157,28 @@ where
                         // This would happen both if a block has less chainwork than the previous one, or if it has the same chainwork
                         // but it forks from the parent. In both cases, it'll be detected as a reorg once (if) the new chain grows past
                         // the current tip.
-                        log::warn!("Worse tip found: {:?}", worse.header.block_hash());
+                        tracing::warn!("Worse tip found: {:?}", worse.header.block_hash());
 
                         if worse.chainwork == self.last_known_block_header.chainwork {
-                            log::warn!("New tip has the same work as the previous one")
+                            tracing::warn!("New tip has the same work as the previous one")
                         } else {
-                            log::warn!("New tip has less work than the previous one")
+                            tracing::warn!("New tip has less work than the previous one")
                         }
                     }
                 }
-                *reachable.lock().unwrap() = true;
+                set_reachable(reachable, &self.feed, true);
                 notifier.notify_all();
+                self.systemd.watchdog();
             }
             Err(e) => match e.kind() {
                 BlockSourceErrorKind::Persistent => {
                     // FIXME: This may need finer catching
-                    log::error!("Unexpected persistent error: {e:?}");
+                    tracing::error!("Unexpected persistent error: {e:?}");
                 }
                 BlockSourceErrorKind::Transient => {
                     // Treating all transient as connection errors at least for now.
-                    log::error!("Connection lost with bitcoind");
-                    *reachable.lock().unwrap() = false;
+                    tracing::error!("Connection lost with bitcoind");
+                    set_reachable(reachable, &self.feed, false);
                 }
             },
         };
@@ -116,11 +189,13 @@ where
         loop {
             self.poll_best_tip().await;
             // Sleep for self.polling_delta seconds or shutdown if the signal is received.
-            if timeout(self.polling_delta, self.shutdown_signal.clone())
+            let polling_delta =
+                time::Duration::from_secs(self.polling_delta.load(Ordering::Acquire) as u64);
+            if timeout(polling_delta, self.shutdown_signal.clone())
                 .await
                 .is_ok()
             {
-                log::debug!("Received shutting down signal. Shutting down");
+                tracing::debug!("Received shutting down signal. Shutting down");
                 break;
             }
         }
@@ -139,6 +214,9 @@ mod tests {
     use bitcoin::BlockHash;
     use lightning_block_sync::{poll::ChainPoller, SpvClient, UnboundedCache};
 
+    #[cfg(unix)]
+    use crate::feed::FeedEventKind;
+    use crate::test_utils::NotifySocket;
     use crate::test_utils::{Blockchain, START_HEIGHT};
 
     pub(crate) struct DummyListener {
@@ -188,8 +266,17 @@ mod tests {
         let spv_client = SpvClient::new(tip, poller, cache, &listener);
         let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
 
-        let mut cm =
-            ChainMonitor::new(spv_client, tip, dbm, 1, shutdown_signal, bitcoind_reachable).await;
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            tip,
+            dbm,
+            Arc::new(AtomicU16::new(1)),
+            shutdown_signal,
+            bitcoind_reachable,
+            SystemdNotifier::default(),
+            Arc::new(Feed::new()),
+        )
+        .await;
 
         // If there's no new block nothing gets connected nor disconnected
         cm.poll_best_tip().await;
@@ -216,9 +303,11 @@ mod tests {
             spv_client,
             old_tip,
             dbm,
-            1,
+            Arc::new(AtomicU16::new(1)),
             shutdown_signal,
             bitcoind_reachable,
+            SystemdNotifier::default(),
+            Arc::new(Feed::new()),
         )
         .await;
 
@@ -255,9 +344,11 @@ mod tests {
             spv_client,
             best_tip,
             dbm,
-            1,
+            Arc::new(AtomicU16::new(1)),
             shutdown_signal,
             bitcoind_reachable,
+            SystemdNotifier::default(),
+            Arc::new(Feed::new()),
         )
         .await;
 
@@ -294,9 +385,11 @@ mod tests {
             spv_client,
             old_best,
             dbm,
-            1,
+            Arc::new(AtomicU16::new(1)),
             shutdown_signal,
             bitcoind_reachable,
+            SystemdNotifier::default(),
+            Arc::new(Feed::new()),
         )
         .await;
 
@@ -314,6 +407,61 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_poll_best_tip_shutdown() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let old_tip = chain.at_height(START_HEIGHT - 5);
+
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let listener = DummyListener::new();
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(old_tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            old_tip,
+            dbm,
+            Arc::new(AtomicU16::new(1)),
+            shutdown_signal,
+            bitcoind_reachable,
+            SystemdNotifier::default(),
+            Arc::new(Feed::new()),
+        )
+        .await;
+
+        // Once the tower is shutting down, the backlog of blocks is not processed anymore
+        shutdown_trigger.trigger();
+        cm.poll_best_tip().await;
+        assert_eq!(cm.last_known_block_header, old_tip);
+        assert!(listener.connected_blocks.borrow().is_empty());
+        assert!(listener.disconnected_blocks.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_last_known_block_listener() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let listener = LastKnownBlockListener::new(dbm.clone());
+
+        // Connected blocks become the last known block, and so do their parents once they are disconnected
+        let header = chain.tip().header;
+        chain::Listen::filtered_block_connected(&listener, &header, &[], START_HEIGHT as u32);
+        assert_eq!(
+            dbm.lock().unwrap().load_last_known_block(),
+            Some(header.block_hash())
+        );
+
+        chain::Listen::block_disconnected(&listener, &header, START_HEIGHT as u32);
+        assert_eq!(
+            dbm.lock().unwrap().load_last_known_block(),
+            Some(header.prev_blockhash)
+        );
+    }
+
     #[tokio::test]
     async fn test_poll_best_tip_bitcoind_unreachable() {
         let mut chain = Blockchain::default().unreachable();
@@ -328,14 +476,18 @@ mod tests {
         let cache = &mut UnboundedCache::new();
         let spv_client = SpvClient::new(tip, poller, cache, &listener);
         let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let feed = Arc::new(Feed::new());
+        let mut events = feed.subscribe();
 
         let mut cm = ChainMonitor::new(
             spv_client,
             tip,
             dbm,
-            1,
+            Arc::new(AtomicU16::new(1)),
             shutdown_signal,
             bitcoind_reachable.clone(),
+            SystemdNotifier::default(),
+            feed,
         )
         .await;
 
@@ -343,6 +495,14 @@ mod tests {
         cm.poll_best_tip().await;
         let (reachable, _) = &*bitcoind_reachable.clone();
         assert!(!*reachable.lock().unwrap());
+        assert_eq!(
+            events.try_recv().unwrap().kind,
+            FeedEventKind::BitcoindUnreachable
+        );
+
+        // Polling again while unreachable does not publish the change twice
+        cm.poll_best_tip().await;
+        assert!(events.try_recv().is_err());
 
         // Set a thread to block on bitcoind unreachable to check that it gets notified once bitcoind comes back online
         let t = thread::spawn(move || {
@@ -357,8 +517,52 @@ mod tests {
         *chain_offline.lock().unwrap() = false;
         cm.poll_best_tip().await;
         assert!(*reachable.lock().unwrap());
+        assert_eq!(
+            events.try_recv().unwrap().kind,
+            FeedEventKind::BitcoindReachable
+        );
+        assert!(events.try_recv().is_err());
 
         // This would hang if the cm didn't notify their subscribers about the bitcoind status, so it serves as out assert.
         t.join().unwrap();
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_poll_best_tip_watchdog() {
+        let mut chain = Blockchain::default().unreachable();
+        let chain_offline = chain.unreachable.clone();
+        let tip = chain.tip();
+
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let (_, shutdown_signal) = triggered::trigger();
+        let listener = DummyListener::new();
+
+        let poller = ChainPoller::new(&mut chain, Network::Bitcoin);
+        let cache = &mut UnboundedCache::new();
+        let spv_client = SpvClient::new(tip, poller, cache, &listener);
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+        let notify_socket = NotifySocket::new();
+
+        let mut cm = ChainMonitor::new(
+            spv_client,
+            tip,
+            dbm,
+            Arc::new(AtomicU16::new(1)),
+            shutdown_signal,
+            bitcoind_reachable,
+            notify_socket.notifier(Some(time::Duration::from_secs(30))),
+            Arc::new(Feed::new()),
+        )
+        .await;
+
+        // The watchdog is not pinged if bitcoind cannot be polled
+        cm.poll_best_tip().await;
+        assert_eq!(notify_socket.recv(), None);
+
+        // But it is once polling succeeds again
+        *chain_offline.lock().unwrap() = false;
+        cm.poll_best_tip().await;
+        assert_eq!(notify_socket.recv().unwrap(), "WATCHDOG=1");
+    }
 }
@@ -1,18 +1,39 @@
 use hex::FromHex;
-use serde_json::to_string_pretty as pretty_json;
+use serde_json::{to_string as to_json, to_string_pretty as pretty_json};
 use std::str::FromStr;
 use structopt::StructOpt;
 use tokio::fs;
 use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
 use tonic::Request;
 
-use teos::cli_config::{Command, Config, Opt};
+use teos::cli_config::{AppointmentsFilterData, Command, Config, Opt};
 use teos::config;
 use teos::protos as msgs;
 use teos::protos::private_tower_services_client::PrivateTowerServicesClient;
 use teos_common::appointment::Locator;
 use teos_common::UserId;
 
+/// Builds a [msgs::GetAllAppointmentsRequest] from the filters passed via the command line.
+fn appointments_request(
+    filter: AppointmentsFilterData,
+) -> Result<msgs::GetAllAppointmentsRequest, String> {
+    let user_id = match filter.user_id {
+        Some(user_id) => UserId::from_str(&user_id)
+            .map_err(|e| e.to_string())?
+            .to_vec(),
+        None => Vec::new(),
+    };
+
+    Ok(msgs::GetAllAppointmentsRequest {
+        user_id,
+        start_block_from: filter.start_block_from.unwrap_or_default(),
+        start_block_to: filter.start_block_to.unwrap_or_default(),
+        status: filter.status.unwrap_or_default(),
+        confirmation_status: filter.confirmation_status.unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
 /// Prints the cli error to standard error and exits the process
 fn handle_error<T: std::fmt::Display>(error: T) {
     eprintln!("{}", error);
@@ -70,10 +91,48 @@ async fn main() {
     let mut client = PrivateTowerServicesClient::new(channel);
 
     match command {
-        Command::GetAllAppointments => {
-            let appointments = client.get_all_appointments(Request::new(())).await.unwrap();
-            println!("{}", pretty_json(&appointments.into_inner()).unwrap());
+        Command::GetAllAppointments(appointments_data) => {
+            let cursor = appointments_data
+                .cursor
+                .map_or(Ok(Vec::new()), Vec::from_hex)
+                .map_err(|e| e.to_string());
+            match (cursor, appointments_request(appointments_data.filter)) {
+                (Ok(cursor), Ok(request)) => {
+                    match client
+                        .get_all_appointments(Request::new(msgs::GetAllAppointmentsRequest {
+                            cursor,
+                            limit: appointments_data.limit.unwrap_or_default(),
+                            ..request
+                        }))
+                        .await
+                    {
+                        Ok(appointments) => {
+                            println!("{}", pretty_json(&appointments.into_inner()).unwrap())
+                        }
+                        Err(status) => handle_error(status.message()),
+                    }
+                }
+                (Err(e), _) | (_, Err(e)) => handle_error(e),
+            };
         }
+        Command::ExportAppointments(filter) => match appointments_request(filter) {
+            Ok(request) => match client.export_appointments(Request::new(request)).await {
+                Ok(response) => {
+                    let mut appointments = response.into_inner();
+                    loop {
+                        match appointments.message().await {
+                            Ok(Some(appointment)) => {
+                                println!("{}", to_json(&appointment).unwrap())
+                            }
+                            Ok(None) => break,
+                            Err(status) => handle_error(status.message()),
+                        }
+                    }
+                }
+                Err(status) => handle_error(status.message()),
+            },
+            Err(e) => handle_error(e),
+        },
         Command::GetAppointments(appointments_data) => {
             match Locator::from_hex(&appointments_data.locator) {
                 Ok(locator) => {
@@ -92,6 +151,21 @@ async fn main() {
                 Err(e) => handle_error(e),
             };
         }
+        Command::GetEvents(events_data) => {
+            let decode = |data: Option<String>| data.map_or(Ok(Vec::new()), Vec::from_hex);
+            match (decode(events_data.uuid), decode(events_data.locator)) {
+                (Ok(uuid), Ok(locator)) => {
+                    match client
+                        .get_events(Request::new(msgs::GetEventsRequest { uuid, locator }))
+                        .await
+                    {
+                        Ok(events) => println!("{}", pretty_json(&events.into_inner()).unwrap()),
+                        Err(status) => handle_error(status.message()),
+                    }
+                }
+                (Err(e), _) | (_, Err(e)) => handle_error(e),
+            };
+        }
         Command::GetTowerInfo => {
             let info = client.get_tower_info(Request::new(())).await.unwrap();
             println!("{}", pretty_json(&info.into_inner()).unwrap())
@@ -118,9 +192,103 @@ async fn main() {
                 Err(e) => handle_error(e),
             };
         }
+        Command::ExportOnionKey => match client.export_onion_key(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::ImportOnionKey(key) => {
+            match Vec::from_hex(&key.secret_key) {
+                Ok(secret_key) => {
+                    match client
+                        .import_onion_key(Request::new(msgs::ImportOnionKeyRequest { secret_key }))
+                        .await
+                    {
+                        Ok(response) => {
+                            println!("{}", pretty_json(&response.into_inner()).unwrap())
+                        }
+                        Err(status) => handle_error(status.message()),
+                    }
+                }
+                Err(e) => handle_error(e),
+            };
+        }
+        Command::RotateOnionKey => match client.rotate_onion_key(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
+        Command::CreateBackup(data) => {
+            match data.public_key.map(Vec::from_hex).transpose() {
+                Ok(public_key) => {
+                    match client
+                        .create_backup(Request::new(msgs::CreateBackupRequest {
+                            passphrase: data.passphrase.unwrap_or_default(),
+                            public_key: public_key.unwrap_or_default(),
+                            path: data.path.unwrap_or_default(),
+                        }))
+                        .await
+                    {
+                        Ok(response) => {
+                            println!("{}", pretty_json(&response.into_inner()).unwrap())
+                        }
+                        Err(status) => handle_error(status.message()),
+                    }
+                }
+                Err(e) => handle_error(e),
+            };
+        }
+        Command::ChangeKeyPassphrase => {
+            let prompt = |prompt| {
+                rpassword::read_password_from_tty(Some(prompt)).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    std::process::exit(1)
+                })
+            };
+            let old_passphrase =
+                prompt("Current key passphrase (leave empty if the keys are not encrypted): ");
+            let new_passphrase = prompt("New key passphrase: ");
+            if prompt("Repeat the new key passphrase: ") != new_passphrase {
+                handle_error("Passphrases do not match");
+            }
+
+            match client
+                .change_key_passphrase(Request::new(msgs::ChangeKeyPassphraseRequest {
+                    old_passphrase,
+                    new_passphrase,
+                }))
+                .await
+            {
+                Ok(_) => println!("Key passphrase changed"),
+                Err(status) => handle_error(status.message()),
+            }
+        }
+        Command::ReloadConfig => match client.reload_config(Request::new(())).await {
+            Ok(response) => println!("{}", pretty_json(&response.into_inner()).unwrap()),
+            Err(status) => handle_error(status.message()),
+        },
         Command::Stop => {
             println!("Shutting down tower");
             client.stop(Request::new(())).await.unwrap();
         }
+        Command::Watch(watch_data) => {
+            match client
+                .subscribe_events(Request::new(msgs::SubscribeEventsRequest {
+                    event_types: watch_data.events,
+                }))
+                .await
+            {
+                Ok(response) => {
+                    let mut events = response.into_inner();
+                    loop {
+                        match events.message().await {
+                            Ok(Some(event)) => println!("{}", to_json(&event).unwrap()),
+                            // The stream is closed by the tower when shutting down
+                            Ok(None) => break,
+                            Err(status) => handle_error(status.message()),
+                        }
+                    }
+                }
+                Err(status) => handle_error(status.message()),
+            }
+        }
     };
 }
@@ -6,18 +6,56 @@ use structopt::StructOpt;
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(rename_all = "lower_case")]
 pub enum Command {
-    /// Gets information about all appointments stored in the tower
-    GetAllAppointments,
+    /// Gets a page of the appointments stored in the tower, optionally filtered. Use the returned next_cursor to get the next page
+    GetAllAppointments(GetAllAppointmentsData),
+    /// Exports all the appointments stored in the tower matching the given filters, one JSON appointment per line
+    ExportAppointments(AppointmentsFilterData),
     /// Gets information about specific appointments stored in the tower using a locator
     GetAppointments(GetAppointmentsData),
+    /// Gets the audit history of the tower (breaches, penalties and their outcome), optionally filtered by uuid and/or locator
+    GetEvents(GetEventsData),
     /// Gets generic information about the tower, like tower id and aggregate data on users and appointments
     GetTowerInfo,
     /// Gets an array with the user ids of all the users registered to the tower
     GetUsers,
     /// Gets information about a specific user
     GetUser(GetUserData),
+    /// Exports the onion service secret key of the tower (64-byte hexadecimal string)
+    ExportOnionKey,
+    /// Replaces the onion service secret key of the tower, changing its onion address
+    ImportOnionKey(ImportOnionKeyData),
+    /// Replaces the onion service secret key of the tower by a newly generated one
+    RotateOnionKey,
+    /// Creates an encrypted backup of the tower (database snapshot, keys and certificates) while it keeps running
+    CreateBackup(CreateBackupData),
+    /// Changes the passphrase the tower keys are encrypted with (encrypting them if they were not). Passphrases are prompted for
+    ChangeKeyPassphrase,
+    /// Reloads the tower config (file and TEOS_* environment variables), applying the options that can be changed live and reporting the ones that require a restart
+    ReloadConfig,
     /// Requests a graceful shutdown of the tower
     Stop,
+    /// Tails the live event feed of the tower (new users, appointments, breaches, penalties, reorgs, ...), one JSON event per line
+    Watch(WatchData),
+}
+
+#[derive(Debug, StructOpt, Clone)]
+#[structopt(rename_all = "snake_case")]
+pub struct ImportOnionKeyData {
+    /// The onion service secret key (64-byte hexadecimal string), as returned by exportonionkey.
+    pub secret_key: String,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct CreateBackupData {
+    /// Passphrase to encrypt the backup with. Requires public_key not to be set.
+    #[structopt(long)]
+    pub passphrase: Option<String>,
+    /// Public key to encrypt the backup to (33-byte compressed public key). Requires passphrase not to be set.
+    #[structopt(long)]
+    pub public_key: Option<String>,
+    /// Where to write the backup, relative to the tower data directory if not absolute [default: backups/].
+    #[structopt(long)]
+    pub path: Option<String>,
 }
 
 #[derive(Debug, StructOpt, Clone)]
@@ -27,12 +65,60 @@ pub struct GetUserData {
     pub user_id: String,
 }
 
+#[derive(Debug, StructOpt, Clone)]
+pub struct AppointmentsFilterData {
+    /// Only appointments of this user (33-byte compressed public key).
+    #[structopt(long)]
+    pub user_id: Option<String>,
+    /// Only appointments accepted at or after this block height.
+    #[structopt(long)]
+    pub start_block_from: Option<u32>,
+    /// Only appointments accepted at or before this block height.
+    #[structopt(long)]
+    pub start_block_to: Option<u32>,
+    /// Only appointments with this status (watching or responded).
+    #[structopt(long)]
+    pub status: Option<String>,
+    /// Only trackers whose penalty has this confirmation status (confirmed or in_mempool).
+    #[structopt(long)]
+    pub confirmation_status: Option<String>,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct GetAllAppointmentsData {
+    /// The next_cursor returned by the previous call (20-byte hexadecimal string). First page if not set.
+    #[structopt(long)]
+    pub cursor: Option<String>,
+    /// Maximum number of appointments to return (up to 1000) [default: 100].
+    #[structopt(long)]
+    pub limit: Option<u32>,
+    #[structopt(flatten)]
+    pub filter: AppointmentsFilterData,
+}
+
 #[derive(Debug, StructOpt, Clone)]
 pub struct GetAppointmentsData {
     /// The locator of the appointments (16-byte hexadecimal string).
     pub locator: String,
 }
 
+#[derive(Debug, StructOpt, Clone)]
+pub struct GetEventsData {
+    /// The uuid of the appointment (20-byte hexadecimal string).
+    #[structopt(long)]
+    pub uuid: Option<String>,
+    /// The locator of the appointment (16-byte hexadecimal string).
+    #[structopt(long)]
+    pub locator: Option<String>,
+}
+
+#[derive(Debug, StructOpt, Clone)]
+pub struct WatchData {
+    /// The types of the events to watch (e.g. new_user, breach_detected, penalty_broadcast, reorg). All of them if not set.
+    #[structopt(long = "event")]
+    pub events: Vec<String>,
+}
+
 /// Holds all the command line options and commands.
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "lowercase")]
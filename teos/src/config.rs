@@ -1,9 +1,17 @@
 //! Logic related to the tower configuration and command line parameter parsing.
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
+use teos_common::net::AddressType;
+
+use crate::api::tor::{parse_client_auth_key, TorControlAuth};
+
+/// The default tower data directory.
+pub const DEFAULT_DATA_DIR: &str = "~/.teos";
+
 pub fn data_dir_absolute_path(data_dir: String) -> PathBuf {
     if let Some(a) = data_dir.strip_prefix('~') {
         if let Some(b) = data_dir.strip_prefix("~/") {
@@ -16,19 +24,50 @@ pub fn data_dir_absolute_path(data_dir: String) -> PathBuf {
     }
 }
 
-pub fn from_file<T: Default + serde::de::DeserializeOwned>(path: &PathBuf) -> T {
+/// Gets the absolute path of a file set in the config, expanding `~`. Relative paths are relative to `data_dir`.
+pub fn config_file_path(data_dir: &Path, path: String) -> PathBuf {
+    let path = data_dir_absolute_path(path);
+    if path.is_absolute() {
+        path
+    } else {
+        data_dir.join(path)
+    }
+}
+
+pub fn from_file<T: Default + serde::de::DeserializeOwned>(path: &Path) -> T {
+    try_from_file(path).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        T::default()
+    })
+}
+
+/// Loads the config file at `path`, falling back to the defaults if there is no such file. Unlike [from_file], fails
+/// if the file cannot be parsed.
+pub fn try_from_file<T: Default + serde::de::DeserializeOwned>(
+    path: &Path,
+) -> Result<T, ConfigError> {
     match std::fs::read(path) {
-        Ok(file_content) => toml::from_slice::<T>(&file_content).map_or_else(
-            |e| {
-                eprintln!("Couldn't parse config file: {e}");
-                T::default()
-            },
-            |config| config,
-        ),
-        Err(_) => T::default(),
+        Ok(file_content) => toml::from_slice::<T>(&file_content)
+            .map_err(|e| ConfigError(format!("Couldn't parse config file: {e}"))),
+        Err(_) => Ok(T::default()),
     }
 }
 
+/// Prefix of the environment variables that override the config file options (e.g. `TEOS_BTC_RPC_USER`).
+pub const ENV_PREFIX: &str = "TEOS_";
+
+/// Options that can be changed while the tower is running, by reloading the config. Changing any other option
+/// requires a restart.
+pub const LIVE_OPTIONS: [&str; 7] = [
+    "subscription_slots",
+    "subscription_duration",
+    "expiry_delta",
+    "min_to_self_delay",
+    "polling_delta",
+    "debug",
+    "deps_debug",
+];
+
 /// Error raised if something is wrong with the configuration.
 #[derive(PartialEq, Eq, Debug)]
 pub struct ConfigError(String);
@@ -54,6 +93,19 @@ pub struct Opt {
     #[structopt(long)]
     pub api_port: Option<u16>,
 
+    /// Host (IP address or DNS name) advertised to users to reach the HTTP(s) API. Useful when the tower is behind
+    /// a NAT or a reverse proxy [default: api_bind]
+    #[structopt(long)]
+    pub api_public_host: Option<String>,
+
+    /// Path to the PEM encoded certificate used to serve the HTTP API over TLS (relative to the data dir). Requires api_tls_key
+    #[structopt(long)]
+    pub api_tls_cert: Option<String>,
+
+    /// Path to the PEM encoded private key used to serve the HTTP API over TLS (relative to the data dir). Requires api_tls_cert
+    #[structopt(long)]
+    pub api_tls_key: Option<String>,
+
     /// Address teos RPC server will bind to [default: localhost]
     #[structopt(long)]
     pub rpc_bind: Option<String>,
@@ -98,6 +150,10 @@ pub struct Opt {
     #[structopt(long)]
     pub overwrite_key: bool,
 
+    /// Rotates the tower secret key. The old key signs a handoff to the new one, so users can follow the tower to its new id
+    #[structopt(long)]
+    pub rotate_key: bool,
+
     /// If set, creates a Tor endpoint to serve API data. This endpoint is additional to the clearnet HTTP API
     #[structopt(long)]
     pub tor_support: bool,
@@ -107,6 +163,25 @@ pub struct Opt {
     #[structopt(long)]
     pub force_update: bool,
 
+    /// Restores the tower from an encrypted backup before starting. Decrypted with restorekey if set, or with a
+    /// passphrase otherwise
+    #[structopt(long)]
+    pub restore: Option<String>,
+
+    /// File descriptor to read the passphrase the backup to restore was encrypted with from. Falls back to the
+    /// TEOS_RESTORE_PASSPHRASE environment variable, and to an interactive prompt
+    #[structopt(long)]
+    pub restore_passphrase_fd: Option<i32>,
+
+    /// Path to a file with the (hex encoded) secret key matching the public key the backup to restore was encrypted to
+    #[structopt(long)]
+    pub restore_key: Option<String>,
+
+    /// File descriptor to read the passphrase the tower keys are encrypted with from. Falls back to the
+    /// TEOS_KEY_PASSPHRASE environment variable, and to an interactive prompt if the keys are encrypted
+    #[structopt(long)]
+    pub key_passphrase_fd: Option<i32>,
+
     /// Tor control port [default: 9051]
     #[structopt(long)]
     pub tor_control_port: Option<u16>,
@@ -114,6 +189,87 @@ pub struct Opt {
     /// Port for the onion hidden service to listen on [default: 9814]
     #[structopt(long)]
     pub onion_hidden_service_port: Option<u16>,
+
+    /// Tor control port authentication method {auto, safecookie, hashedpassword} [default: auto]
+    #[structopt(long)]
+    pub tor_auth_method: Option<String>,
+
+    /// Tor control port password. Required by hashedpassword authentication
+    #[structopt(long)]
+    pub tor_password: Option<String>,
+
+    /// Path to the Tor control port cookie used by safecookie authentication (relative to the data dir) [default: the one reported by Tor]
+    #[structopt(long)]
+    pub tor_cookie_file: Option<String>,
+
+    /// Public key (x25519, base32 encoded) of a client allowed to reach the onion service. Can be passed multiple
+    /// times. Anyone can reach the onion service if none is set
+    #[structopt(long)]
+    pub tor_client_auth: Vec<String>,
+
+    /// If set, accepts Lightning peer connections to serve API data. This endpoint is additional to the clearnet HTTP API
+    #[structopt(long)]
+    pub lightning_support: bool,
+
+    /// Port for the Lightning interface to listen on [default: 9815]
+    #[structopt(long)]
+    pub lightning_port: Option<u16>,
+
+    /// If set, also serves the public gRPC API over TLS, using the HTTP API certificate. Requires api_tls_cert and api_tls_key
+    #[structopt(long)]
+    pub public_grpc_support: bool,
+
+    /// Port for the public gRPC API to listen on [default: 9816]
+    #[structopt(long)]
+    pub public_grpc_port: Option<u16>,
+
+    /// If set, exports Prometheus metrics under /metrics
+    #[structopt(long)]
+    pub metrics_support: bool,
+
+    /// Address the metrics endpoint binds to [default: 127.0.0.1]
+    #[structopt(long)]
+    pub metrics_bind: Option<String>,
+
+    /// Port for the metrics endpoint to listen on [default: 9817]
+    #[structopt(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Format of the log output (text or json) [default: text]
+    #[structopt(long)]
+    pub log_format: Option<String>,
+
+    /// File to write logs to, relative to the network data directory if not absolute. Disabled if unset
+    #[structopt(long)]
+    pub log_file: Option<String>,
+
+    /// How often the log file is rotated (minutely, hourly, daily, weekly or never) [default: daily]
+    #[structopt(long)]
+    pub log_rotation: Option<String>,
+
+    /// Maximum number of rotated log files to keep, 0 for no limit [default: 7]
+    #[structopt(long)]
+    pub log_max_files: Option<usize>,
+
+    /// URL operator alerts are POSTed to. Disabled if unset
+    #[structopt(long)]
+    pub alert_webhook: Option<String>,
+
+    /// Executable operator alerts are piped to (as JSON on stdin, relative to the data dir). Disabled if unset
+    #[structopt(long)]
+    pub alert_script: Option<String>,
+
+    /// Missed confirmations after which a penalty in mempool is alerted [default: 6]
+    #[structopt(long)]
+    pub alert_missed_confirmations: Option<u32>,
+
+    /// Disk usage (in percent) after which an alert is raised [default: 90]
+    #[structopt(long)]
+    pub alert_disk_usage: Option<u8>,
+
+    /// Time (in seconds) repeated alerts for the same condition are suppressed for [default: 3600]
+    #[structopt(long)]
+    pub alert_cooldown: Option<u64>,
 }
 
 /// Holds all configuration options.
@@ -121,6 +277,7 @@ pub struct Opt {
 /// The overwrite policy goes, from less to more:
 /// - Defaults
 /// - Configuration file
+/// - Environment variables
 /// - Command line options
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(default)]
@@ -128,6 +285,9 @@ pub struct Config {
     // API
     pub api_bind: String,
     pub api_port: u16,
+    pub api_public_host: String,
+    pub api_tls_cert: String,
+    pub api_tls_key: String,
 
     // RPC
     pub rpc_bind: String,
@@ -144,6 +304,7 @@ pub struct Config {
     pub debug: bool,
     pub deps_debug: bool,
     pub overwrite_key: bool,
+    pub rotate_key: bool,
     pub force_update: bool,
 
     // General
@@ -152,6 +313,10 @@ pub struct Config {
     pub expiry_delta: u32,
     pub min_to_self_delay: u16,
     pub polling_delta: u16,
+    pub user_cache_size: usize,
+
+    // Database
+    pub db_key: String,
 
     // Internal API
     pub internal_api_bind: String,
@@ -161,9 +326,93 @@ pub struct Config {
     pub tor_support: bool,
     pub tor_control_port: u16,
     pub onion_hidden_service_port: u16,
+    pub tor_auth_method: String,
+    pub tor_password: String,
+    pub tor_cookie_file: String,
+    pub tor_client_auth: Vec<String>,
+
+    // Lightning
+    pub lightning_support: bool,
+    pub lightning_port: u16,
+
+    // Public gRPC
+    pub public_grpc_support: bool,
+    pub public_grpc_port: u16,
+
+    // Metrics
+    pub metrics_support: bool,
+    pub metrics_bind: String,
+    pub metrics_port: u16,
+
+    // Logging
+    pub log_format: String,
+    pub log_file: String,
+    pub log_rotation: String,
+    pub log_max_files: usize,
+
+    // Alerts
+    pub alert_webhook: String,
+    pub alert_script: String,
+    pub alert_missed_confirmations: u32,
+    pub alert_disk_usage: u8,
+    pub alert_cooldown: u64,
 }
 
 impl Config {
+    /// Patches the configuration options with the environment variables named after them, prefixed by [ENV_PREFIX]
+    /// (e.g. `TEOS_BTC_RPC_USER` for `btc_rpc_user`).
+    ///
+    /// List options (such as `tor_client_auth`) are comma separated. Variables not matching any option are ignored.
+    pub fn patch_with_env<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut json_config = serde_json::json!(&self);
+        for (name, value) in vars {
+            let option = match name
+                .strip_prefix(ENV_PREFIX)
+                .and_then(|key| json_config.get_mut(key.to_lowercase()))
+            {
+                Some(option) => option,
+                None => continue,
+            };
+            let invalid = || ConfigError(format!("{name} is not valid, received {value}"));
+
+            *option = match option {
+                serde_json::Value::Bool(_) => value.parse::<bool>().map_err(|_| invalid())?.into(),
+                serde_json::Value::Number(_) => value.parse::<u64>().map_err(|_| invalid())?.into(),
+                serde_json::Value::Array(_) => value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>()
+                    .into(),
+                _ => value.clone().into(),
+            };
+            // Numbers still need to fit the option they are set to
+            serde_json::from_value::<Config>(json_config.clone()).map_err(|_| invalid())?;
+        }
+        *self = serde_json::from_value(json_config).unwrap();
+
+        Ok(())
+    }
+
+    /// Gets the name of the options that differ between this config and `other`, sorted alphabetically.
+    pub fn changed_options(&self, other: &Config) -> Vec<String> {
+        let json_config = serde_json::json!(&self);
+        let json_other = serde_json::json!(other);
+
+        let mut changed: Vec<String> = json_config
+            .as_object()
+            .unwrap()
+            .iter()
+            .filter(|(key, value)| json_other[key.as_str()] != **value)
+            .map(|(key, _)| key.clone())
+            .collect();
+        changed.sort();
+        changed
+    }
+
     /// Patches the configuration options with the command line options.
     pub fn patch_with_options(&mut self, options: Opt) {
         if options.api_bind.is_some() {
@@ -199,11 +448,76 @@ impl Config {
         if options.onion_hidden_service_port.is_some() {
             self.onion_hidden_service_port = options.onion_hidden_service_port.unwrap();
         }
+        if let Some(tor_auth_method) = options.tor_auth_method {
+            self.tor_auth_method = tor_auth_method;
+        }
+        if let Some(tor_password) = options.tor_password {
+            self.tor_password = tor_password;
+        }
+        if let Some(tor_cookie_file) = options.tor_cookie_file {
+            self.tor_cookie_file = tor_cookie_file;
+        }
+        if !options.tor_client_auth.is_empty() {
+            self.tor_client_auth = options.tor_client_auth;
+        }
+        if let Some(api_public_host) = options.api_public_host {
+            self.api_public_host = api_public_host;
+        }
+        if let Some(api_tls_cert) = options.api_tls_cert {
+            self.api_tls_cert = api_tls_cert;
+        }
+        if let Some(api_tls_key) = options.api_tls_key {
+            self.api_tls_key = api_tls_key;
+        }
+        if let Some(public_grpc_port) = options.public_grpc_port {
+            self.public_grpc_port = public_grpc_port;
+        }
+        if let Some(lightning_port) = options.lightning_port {
+            self.lightning_port = lightning_port;
+        }
+        if let Some(metrics_bind) = options.metrics_bind {
+            self.metrics_bind = metrics_bind;
+        }
+        if let Some(metrics_port) = options.metrics_port {
+            self.metrics_port = metrics_port;
+        }
 
         self.tor_support |= options.tor_support;
+        if let Some(log_format) = options.log_format {
+            self.log_format = log_format;
+        }
+        if let Some(log_file) = options.log_file {
+            self.log_file = log_file;
+        }
+        if let Some(log_rotation) = options.log_rotation {
+            self.log_rotation = log_rotation;
+        }
+        if let Some(log_max_files) = options.log_max_files {
+            self.log_max_files = log_max_files;
+        }
+        if let Some(alert_webhook) = options.alert_webhook {
+            self.alert_webhook = alert_webhook;
+        }
+        if let Some(alert_script) = options.alert_script {
+            self.alert_script = alert_script;
+        }
+        if let Some(alert_missed_confirmations) = options.alert_missed_confirmations {
+            self.alert_missed_confirmations = alert_missed_confirmations;
+        }
+        if let Some(alert_disk_usage) = options.alert_disk_usage {
+            self.alert_disk_usage = alert_disk_usage;
+        }
+        if let Some(alert_cooldown) = options.alert_cooldown {
+            self.alert_cooldown = alert_cooldown;
+        }
+
+        self.lightning_support |= options.lightning_support;
+        self.public_grpc_support |= options.public_grpc_support;
+        self.metrics_support |= options.metrics_support;
         self.debug |= options.debug;
         self.deps_debug |= options.deps_debug;
         self.overwrite_key = options.overwrite_key;
+        self.rotate_key = options.rotate_key;
         self.force_update = options.force_update;
     }
 
@@ -212,6 +526,14 @@ impl Config {
     /// This includes:
     /// - `bitcoind` credentials have been set
     /// - The Bitcoin network has been properly set (to either bitcoin, testnet, signet or regtest)
+    /// - `api_bind` is an IP address (either v4 or v6) and `api_public_host`, if set, is a valid host
+    /// - The TLS certificate and key are either both set or both unset, and set if the public gRPC API is enabled
+    /// - The Tor authentication method is known (and has a password if needed) and the Tor client keys are valid
+    /// - `metrics_bind` is an IP address (either v4 or v6)
+    /// - `user_cache_size` is not zero
+    /// - The log format and log rotation are known
+    /// - `alert_webhook`, if set, is an HTTP(S) URL and `alert_disk_usage` is a percentage
+    /// - `db_key` is only set if the tower has been built with database encryption support (the `sqlcipher` feature)
     ///
     /// This will also assign the default `btc_rpc_port` depending on the network if it has not
     /// been overwritten at this point.
@@ -236,6 +558,96 @@ impl Config {
             _ => return Err(ConfigError(format!("btc_network not recognized. Expected {{mainnet, testnet, signet, regtest}}, received {}", self.btc_network)))
         };
 
+        if self.api_bind.parse::<IpAddr>().is_err() {
+            return Err(ConfigError(format!(
+                "api_bind must be an IP address, received {}",
+                self.api_bind
+            )));
+        }
+        if !self.api_public_host.is_empty() {
+            AddressType::from_host(&self.api_public_host)
+                .map_err(|e| ConfigError(format!("api_public_host is not valid. {e}")))?;
+        }
+
+        if self.api_tls_cert.is_empty() != self.api_tls_key.is_empty() {
+            return Err(ConfigError(
+                "api_tls_cert and api_tls_key must be set together".to_owned(),
+            ));
+        }
+        if self.public_grpc_support && !self.tls_enabled() {
+            return Err(ConfigError(
+                "public_grpc_support requires api_tls_cert and api_tls_key to be set".to_owned(),
+            ));
+        }
+
+        if self.overwrite_key && self.rotate_key {
+            return Err(ConfigError(
+                "overwrite_key and rotate_key cannot be set together".to_owned(),
+            ));
+        }
+
+        if self.metrics_bind.parse::<IpAddr>().is_err() {
+            return Err(ConfigError(format!(
+                "metrics_bind must be an IP address, received {}",
+                self.metrics_bind
+            )));
+        }
+
+        if self.user_cache_size == 0 {
+            return Err(ConfigError(
+                "user_cache_size must be greater than zero".to_owned(),
+            ));
+        }
+
+        if !["text", "json"].contains(&self.log_format.as_str()) {
+            return Err(ConfigError(format!(
+                "log_format not recognized. Expected {{text, json}}, received {}",
+                self.log_format
+            )));
+        }
+        if !["minutely", "hourly", "daily", "weekly", "never"].contains(&self.log_rotation.as_str())
+        {
+            return Err(ConfigError(format!("log_rotation not recognized. Expected {{minutely, hourly, daily, weekly, never}}, received {}", self.log_rotation)));
+        }
+
+        if !self.alert_webhook.is_empty()
+            && !["http://", "https://"]
+                .iter()
+                .any(|scheme| self.alert_webhook.starts_with(scheme))
+        {
+            return Err(ConfigError(format!(
+                "alert_webhook must be an HTTP(S) URL, received {}",
+                self.alert_webhook
+            )));
+        }
+        if !(1..=100).contains(&self.alert_disk_usage) {
+            return Err(ConfigError(format!(
+                "alert_disk_usage must be between 1 and 100, received {}",
+                self.alert_disk_usage
+            )));
+        }
+
+        if !cfg!(feature = "sqlcipher") && !self.db_key.is_empty() {
+            return Err(ConfigError(
+                "db_key requires teosd to be built with the sqlcipher feature".to_owned(),
+            ));
+        }
+
+        match self.tor_auth_method.as_str() {
+            "auto" | "safecookie" => (),
+            "hashedpassword" => {
+                if self.tor_password.is_empty() {
+                    return Err(ConfigError(
+                        "tor_password must be set for hashedpassword authentication".to_owned(),
+                    ));
+                }
+            }
+            _ => return Err(ConfigError(format!("tor_auth_method not recognized. Expected {{auto, safecookie, hashedpassword}}, received {}", self.tor_auth_method)))
+        }
+        for key in self.tor_client_auth.iter() {
+            parse_client_auth_key(key).map_err(ConfigError)?;
+        }
+
         // Set the port to it's default (depending on the network) if it has not been
         // overwritten at this point.
         if self.btc_rpc_port == 0 {
@@ -245,6 +657,32 @@ impl Config {
         Ok(())
     }
 
+    /// Gets the host advertised to users to reach the HTTP(s) API.
+    pub fn api_host(&self) -> &str {
+        if self.api_public_host.is_empty() {
+            &self.api_bind
+        } else {
+            &self.api_public_host
+        }
+    }
+
+    /// Gets how to authenticate with the Tor control port. A relative cookie file path is relative to `data_dir`.
+    pub fn tor_control_auth(&self, data_dir: &Path) -> TorControlAuth {
+        match self.tor_auth_method.as_str() {
+            "safecookie" => TorControlAuth::SafeCookie(
+                (!self.tor_cookie_file.is_empty())
+                    .then(|| config_file_path(data_dir, self.tor_cookie_file.clone())),
+            ),
+            "hashedpassword" => TorControlAuth::HashedPassword(self.tor_password.clone()),
+            _ => TorControlAuth::Auto,
+        }
+    }
+
+    /// Whether the public APIs are served over TLS.
+    pub fn tls_enabled(&self) -> bool {
+        !self.api_tls_cert.is_empty() && !self.api_tls_key.is_empty()
+    }
+
     /// Checks whether the config has been set with only with default values.
     pub fn is_default(&self) -> bool {
         self == &Config::default()
@@ -254,11 +692,11 @@ impl Config {
     pub fn log_non_default_options(&self) {
         let json_default_config = serde_json::json!(&Config::default());
         let json_config = serde_json::json!(&self);
-        let sensitive_args = ["btc_rpc_user", "btc_rpc_password"];
+        let sensitive_args = ["btc_rpc_user", "btc_rpc_password", "tor_password", "db_key"];
 
         for (key, value) in json_config.as_object().unwrap().iter() {
             if *value != json_default_config[key] {
-                log::info!(
+                tracing::info!(
                     "Custom config arg: {}: {}",
                     key,
                     if sensitive_args.contains(&key.as_str()) {
@@ -283,9 +721,32 @@ impl Default for Config {
         Self {
             api_bind: "127.0.0.1".into(),
             api_port: 9814,
+            api_public_host: String::new(),
+            api_tls_cert: String::new(),
+            api_tls_key: String::new(),
             tor_support: false,
             tor_control_port: 9051,
             onion_hidden_service_port: 9814,
+            tor_auth_method: "auto".into(),
+            tor_password: String::new(),
+            tor_cookie_file: String::new(),
+            tor_client_auth: Vec::new(),
+            lightning_support: false,
+            lightning_port: 9815,
+            public_grpc_support: false,
+            public_grpc_port: 9816,
+            metrics_support: false,
+            metrics_bind: "127.0.0.1".into(),
+            metrics_port: 9817,
+            log_format: "text".into(),
+            log_file: String::new(),
+            log_rotation: "daily".into(),
+            log_max_files: 7,
+            alert_webhook: String::new(),
+            alert_script: String::new(),
+            alert_missed_confirmations: 6,
+            alert_disk_usage: 90,
+            alert_cooldown: 3600,
             rpc_bind: "127.0.0.1".into(),
             rpc_port: 8814,
             btc_network: "mainnet".into(),
@@ -297,12 +758,15 @@ impl Default for Config {
             debug: false,
             deps_debug: false,
             overwrite_key: false,
+            rotate_key: false,
             force_update: false,
             subscription_slots: 10000,
             subscription_duration: 4320,
             expiry_delta: 6,
             min_to_self_delay: 20,
             polling_delta: 60,
+            user_cache_size: 100_000,
+            db_key: String::new(),
             internal_api_bind: "127.0.0.1".into(),
             internal_api_port: 50051,
         }
@@ -318,9 +782,32 @@ mod tests {
             Self {
                 api_bind: None,
                 api_port: None,
+                api_public_host: None,
+                api_tls_cert: None,
+                api_tls_key: None,
                 tor_support: false,
                 tor_control_port: None,
                 onion_hidden_service_port: None,
+                tor_auth_method: None,
+                tor_password: None,
+                tor_cookie_file: None,
+                tor_client_auth: Vec::new(),
+                lightning_support: false,
+                lightning_port: None,
+                public_grpc_support: false,
+                public_grpc_port: None,
+                metrics_support: false,
+                metrics_bind: None,
+                metrics_port: None,
+                log_format: None,
+                log_file: None,
+                log_rotation: None,
+                log_max_files: None,
+                alert_webhook: None,
+                alert_script: None,
+                alert_missed_confirmations: None,
+                alert_disk_usage: None,
+                alert_cooldown: None,
                 rpc_bind: None,
                 rpc_port: None,
                 btc_network: None,
@@ -333,7 +820,12 @@ mod tests {
                 debug: false,
                 deps_debug: false,
                 overwrite_key: false,
+                rotate_key: false,
                 force_update: false,
+                restore: None,
+                restore_passphrase_fd: None,
+                restore_key: None,
+                key_passphrase_fd: None,
             }
         }
     }
@@ -403,4 +895,328 @@ mod tests {
 
         config.verify().unwrap()
     }
+
+    #[test]
+    fn test_config_verify_api_bind() {
+        for api_bind in ["0.0.0.0", "::", "::1"] {
+            let mut config = Config {
+                btc_rpc_user: "user".to_owned(),
+                btc_rpc_password: "password".to_owned(),
+                api_bind: api_bind.to_owned(),
+                ..Default::default()
+            };
+            config.verify().unwrap();
+        }
+
+        // Hostnames cannot be bound to
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            api_bind: "localhost".to_owned(),
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("api_bind must be an IP address"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_api_public_host() {
+        for api_public_host in ["teos.talaia.watch", "2001:db8::1", "172.16.0.1"] {
+            let mut config = Config {
+                btc_rpc_user: "user".to_owned(),
+                btc_rpc_password: "password".to_owned(),
+                api_public_host: api_public_host.to_owned(),
+                ..Default::default()
+            };
+            config.verify().unwrap();
+            assert_eq!(config.api_host(), api_public_host);
+        }
+
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            api_public_host: "not a host".to_owned(),
+            ..Default::default()
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("api_public_host is not valid"))
+        );
+
+        // The bind address is advertised if no public host is set
+        assert_eq!(Config::default().api_host(), Config::default().api_bind);
+    }
+
+    #[test]
+    fn test_config_verify_tls() {
+        let config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            ..Default::default()
+        };
+
+        // Both or none of the certificate and key must be set
+        let mut tls_config = Config {
+            api_tls_cert: "cert.pem".to_owned(),
+            api_tls_key: "key.pem".to_owned(),
+            ..config.clone()
+        };
+        tls_config.verify().unwrap();
+        assert!(tls_config.tls_enabled());
+
+        for (api_tls_cert, api_tls_key) in [("cert.pem", ""), ("", "key.pem")] {
+            let mut config = Config {
+                api_tls_cert: api_tls_cert.to_owned(),
+                api_tls_key: api_tls_key.to_owned(),
+                ..config.clone()
+            };
+            assert!(
+                matches!(config.verify(), Err(ConfigError(e)) if e.contains("must be set together"))
+            );
+        }
+
+        // The public gRPC API can only be served over TLS
+        let mut grpc_config = Config {
+            public_grpc_support: true,
+            ..config
+        };
+        assert!(
+            matches!(grpc_config.verify(), Err(ConfigError(e)) if e.contains("public_grpc_support requires"))
+        );
+        tls_config.public_grpc_support = true;
+        tls_config.verify().unwrap();
+    }
+
+    #[test]
+    fn test_config_verify_tor_auth() {
+        let config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tor_support: true,
+            ..Default::default()
+        };
+        let data_dir = Path::new("/data_dir");
+        assert_eq!(config.tor_control_auth(data_dir), TorControlAuth::Auto);
+
+        let mut cookie_config = Config {
+            tor_auth_method: "safecookie".to_owned(),
+            tor_cookie_file: "/var/run/tor/control.authcookie".to_owned(),
+            ..config.clone()
+        };
+        cookie_config.verify().unwrap();
+        assert_eq!(
+            cookie_config.tor_control_auth(data_dir),
+            TorControlAuth::SafeCookie(Some("/var/run/tor/control.authcookie".into()))
+        );
+        // Relative cookie paths are relative to the data directory
+        cookie_config.tor_cookie_file = "control.authcookie".to_owned();
+        assert_eq!(
+            cookie_config.tor_control_auth(data_dir),
+            TorControlAuth::SafeCookie(Some("/data_dir/control.authcookie".into()))
+        );
+
+        // Passwords are required for hashedpassword authentication
+        let mut password_config = Config {
+            tor_auth_method: "hashedpassword".to_owned(),
+            ..config.clone()
+        };
+        assert!(
+            matches!(password_config.verify(), Err(ConfigError(e)) if e.contains("tor_password must be set"))
+        );
+        password_config.tor_password = "password".to_owned();
+        password_config.verify().unwrap();
+        assert_eq!(
+            password_config.tor_control_auth(data_dir),
+            TorControlAuth::HashedPassword("password".to_owned())
+        );
+
+        let mut config = Config {
+            tor_auth_method: "cookie".to_owned(),
+            ..config
+        };
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("tor_auth_method not recognized"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_tor_client_auth() {
+        let key = "QC5OMIN3BAWSJDZPF5ORSSCD7YJ6NVLGRKUUSGLT7IRJMBJYJWWQ";
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            tor_client_auth: vec![key.to_owned(), format!("descriptor:x25519:{key}")],
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.tor_client_auth.push(key[1..].to_owned());
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("Invalid client authorization key"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_rotate_key() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            rotate_key: true,
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.overwrite_key = true;
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("cannot be set together"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_user_cache_size() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            user_cache_size: 1,
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.user_cache_size = 0;
+        assert!(matches!(config.verify(), Err(ConfigError(e)) if e.contains("user_cache_size")));
+    }
+
+    #[test]
+    fn test_config_verify_metrics_bind() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            metrics_bind: "::1".to_owned(),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.metrics_bind = "localhost".to_owned();
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("metrics_bind must be an IP address"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_logging() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            log_format: "json".to_owned(),
+            log_rotation: "never".to_owned(),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.log_format = "yaml".to_owned();
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("log_format not recognized"))
+        );
+
+        config.log_format = "text".to_owned();
+        config.log_rotation = "yearly".to_owned();
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("log_rotation not recognized"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_alerts() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            alert_webhook: "http://localhost:8080/alerts".to_owned(),
+            ..Default::default()
+        };
+        config.verify().unwrap();
+
+        config.alert_webhook = "localhost:8080".to_owned();
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("alert_webhook must be an HTTP(S) URL"))
+        );
+
+        config.alert_webhook = String::new();
+        config.alert_disk_usage = 101;
+        assert!(
+            matches!(config.verify(), Err(ConfigError(e)) if e.contains("alert_disk_usage must be between 1 and 100"))
+        );
+    }
+
+    #[test]
+    fn test_config_verify_db_key() {
+        let mut config = Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "password".to_owned(),
+            db_key: "key".to_owned(),
+            ..Default::default()
+        };
+        if cfg!(feature = "sqlcipher") {
+            config.verify().unwrap();
+        } else {
+            assert!(
+                matches!(config.verify(), Err(ConfigError(e)) if e.contains("db_key requires teosd to be built with the sqlcipher feature"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_patch_with_env() {
+        let mut config = Config::default();
+        let vars = [
+            ("TEOS_BTC_RPC_USER", "user"),
+            ("TEOS_API_PORT", "1234"),
+            ("TEOS_TOR_SUPPORT", "true"),
+            ("TEOS_TOR_CLIENT_AUTH", "key1, key2"),
+            // Variables that do not match any option are ignored
+            ("TEOS_KEY_PASSPHRASE", "passphrase"),
+            ("BTC_RPC_PASSWORD", "password"),
+        ];
+        config
+            .patch_with_env(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                btc_rpc_user: "user".to_owned(),
+                api_port: 1234,
+                tor_support: true,
+                tor_client_auth: vec!["key1".to_owned(), "key2".to_owned()],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_patch_with_env_invalid_value() {
+        for (name, value) in [
+            ("TEOS_API_PORT", "port"),
+            ("TEOS_API_PORT", "70000"),
+            ("TEOS_DEBUG", "yes"),
+        ] {
+            let mut config = Config::default();
+            assert!(
+                matches!(config.patch_with_env([(name.to_owned(), value.to_owned())]), Err(ConfigError(e)) if e.contains(name))
+            );
+            assert_eq!(config, Config::default());
+        }
+    }
+
+    #[test]
+    fn test_changed_options() {
+        let config = Config::default();
+        assert!(config.changed_options(&Config::default()).is_empty());
+
+        let other = Config {
+            api_port: 1234,
+            debug: true,
+            ..Default::default()
+        };
+        assert_eq!(config.changed_options(&other), vec!["api_port", "debug"]);
+    }
 }
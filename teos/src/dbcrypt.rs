@@ -0,0 +1,79 @@
+//! Migrates tower (or watchtower client) databases between plain text and encrypted (SQLCipher) storage.
+//!
+//! The migrated database is written to a new file, the original one is left untouched. Make sure neither `teosd` nor
+//! `lightningd` are running while migrating their databases, and move the migrated database in place of the original
+//! one afterwards.
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use teos_common::dbm;
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "lowercase")]
+#[structopt(
+    version = env!("CARGO_PKG_VERSION"),
+    about = "Migrates The Eye of Satoshi databases to and from encrypted storage"
+)]
+enum Command {
+    /// Encrypts a plain text database. The key is prompted for
+    Encrypt(MigrationData),
+    /// Decrypts an encrypted database. The key is prompted for
+    Decrypt(MigrationData),
+}
+
+#[derive(Debug, StructOpt)]
+struct MigrationData {
+    /// Path to the database to migrate
+    #[structopt(parse(from_os_str))]
+    src: PathBuf,
+    /// Path to write the migrated database to. Must not exist
+    #[structopt(parse(from_os_str))]
+    dst: PathBuf,
+}
+
+/// Prints the error to standard error and exits the process.
+fn handle_error<T: std::fmt::Display>(error: T) -> ! {
+    eprintln!("{error}");
+    std::process::exit(1);
+}
+
+/// Prompts for a database key, which cannot be empty.
+fn prompt(prompt: &str) -> String {
+    match rpassword::read_password_from_tty(Some(prompt)) {
+        Ok(key) if key.is_empty() => handle_error("The database key cannot be empty"),
+        Ok(key) => key,
+        Err(e) => handle_error(format!("Cannot prompt for the database key: {e}")),
+    }
+}
+
+fn main() {
+    let (data, src_key, dst_key) = match Command::from_args() {
+        Command::Encrypt(data) => {
+            if dbm::is_encrypted_database(&data.src) {
+                handle_error(format!("{} is already encrypted", data.src.display()));
+            }
+            let key = prompt("Database key: ");
+            if prompt("Repeat the database key: ") != key {
+                handle_error("Keys do not match");
+            }
+            (data, None, Some(key))
+        }
+        Command::Decrypt(data) => {
+            if !dbm::is_encrypted_database(&data.src) {
+                handle_error(format!("{} is not encrypted", data.src.display()));
+            }
+            let key = prompt("Database key: ");
+            (data, Some(key), None)
+        }
+    };
+
+    match dbm::export_database(&data.src, src_key.as_deref(), &data.dst, dst_key.as_deref()) {
+        Ok(()) => println!(
+            "Database migrated to {}. Replace {} with it once you have checked it works",
+            data.dst.display(),
+            data.src.display()
+        ),
+        Err(e) => handle_error(format!("Cannot migrate {}: {e}", data.src.display())),
+    }
+}
@@ -1,28 +1,45 @@
 //! Logic related to the tower database manager (DBM), component in charge of persisting data on disk.
 //!
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
+use rusqlite::backup::Backup;
 use rusqlite::limits::Limit;
-use rusqlite::{params, params_from_iter, Connection, Error as SqliteError};
+use rusqlite::types::{ToSql, Type};
+use rusqlite::{params, params_from_iter, Connection, Error as SqliteError, Row};
 
 use bitcoin::consensus;
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::SecretKey;
-use bitcoin::BlockHash;
+use bitcoin::{BlockHash, Txid};
 
 use teos_common::appointment::{Appointment, Locator};
-use teos_common::dbm::{DatabaseConnection, DatabaseManager, Error};
-use teos_common::UserId;
+use teos_common::cryptography::{self, KeyDecryptionError};
+use teos_common::dbm::{self, DatabaseConnection, DatabaseManager, Error};
+use teos_common::handoff::KeyHandoff;
+use teos_common::{TowerId, UserId};
 
+use crate::events::{Event, EventKind};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
 use crate::gatekeeper::UserInfo;
+use crate::locator_filter::LocatorFilter;
+use crate::notifier::QueuedNotification;
 use crate::responder::{ConfirmationStatus, PenaltySummary, TransactionTracker};
 
-const TABLES: [&str; 6] = [
+/// Number of database pages copied at a time when snapshotting the database.
+const SNAPSHOT_STEP_PAGES: i32 = 256;
+/// Time (in milliseconds) to wait between two snapshot steps, so writers are not starved.
+const SNAPSHOT_STEP_PAUSE: u64 = 5;
+/// Number of appointments loaded at a time when rebuilding the [LocatorFilter].
+const LOCATOR_FILTER_REBUILD_STEP: u32 = 1024;
+
+const TABLES: [&str; 17] = [
     "CREATE TABLE IF NOT EXISTS users (
     user_id INT PRIMARY KEY,
     available_slots INT NOT NULL,
@@ -50,6 +67,13 @@ const TABLES: [&str; 6] = [
     FOREIGN KEY(UUID)
         REFERENCES appointments(UUID)
         ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS penalty_txids (
+    UUID INT PRIMARY KEY,
+    txid INT NOT NULL,
+    FOREIGN KEY(UUID)
+        REFERENCES trackers(UUID)
+        ON DELETE CASCADE
 )",
     "CREATE TABLE IF NOT EXISTS last_known_block (
     id INT PRIMARY KEY,
@@ -58,12 +82,100 @@ const TABLES: [&str; 6] = [
     "CREATE TABLE IF NOT EXISTS keys (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     key INT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS key_handoffs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    old_tower_id INT NOT NULL,
+    new_tower_id INT NOT NULL,
+    activation_height INT NOT NULL,
+    signature TEXT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS nonces (
+    user_id INT PRIMARY KEY,
+    nonce INT NOT NULL
+)",
+    "CREATE TABLE IF NOT EXISTS webhooks (
+    user_id INT PRIMARY KEY,
+    url TEXT NOT NULL,
+    FOREIGN KEY(user_id)
+        REFERENCES users(user_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS notifications (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INT NOT NULL,
+    next_attempt INT NOT NULL,
+    FOREIGN KEY(user_id)
+        REFERENCES webhooks(user_id)
+        ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    UUID INT NOT NULL,
+    user_id INT NOT NULL,
+    locator INT NOT NULL,
+    event TEXT NOT NULL,
+    height INT NOT NULL,
+    txid INT,
+    rejection_reason INT,
+    timestamp INT NOT NULL
 )",
     "CREATE INDEX IF NOT EXISTS locators_index ON appointments (
         locator
+)",
+    "CREATE INDEX IF NOT EXISTS appointments_user_id_index ON appointments (
+        user_id
+)",
+    "CREATE INDEX IF NOT EXISTS events_uuid_index ON events (
+        UUID
+)",
+    "CREATE INDEX IF NOT EXISTS penalty_txids_index ON penalty_txids (
+        txid
+)",
+    "CREATE INDEX IF NOT EXISTS trackers_status_index ON trackers (
+        confirmed, height
+)",
+    "CREATE INDEX IF NOT EXISTS users_expiry_index ON users (
+        subscription_expiry
 )",
 ];
 
+/// Filters used to query the appointments held by the tower. Unset filters match every appointment.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct AppointmentsFilter {
+    /// Only match appointments of this user.
+    pub user_id: Option<UserId>,
+    /// Only match appointments accepted at or after this block height.
+    pub start_block_from: Option<u32>,
+    /// Only match appointments accepted at or before this block height.
+    pub start_block_to: Option<u32>,
+    /// Only match appointments that have (or have not) been triggered and handed to the [Responder](crate::responder::Responder).
+    pub responded: Option<bool>,
+    /// Only match trackers whose penalty is (or is not) confirmed. Implies `responded`.
+    pub confirmed: Option<bool>,
+}
+
+/// An appointment held by the tower, as stored in the database.
+///
+/// Appointments that have been handed to the [Responder](crate::responder::Responder) are stored as trackers.
+#[derive(Debug)]
+pub(crate) enum AppointmentRecord {
+    Appointment(Appointment),
+    Tracker(TransactionTracker),
+}
+
+/// The key a database is encrypted with. Kept to open additional connections to the database, and never printed.
+#[derive(Clone)]
+struct DatabaseKey(String);
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DatabaseKey(..)")
+    }
+}
+
 /// Component in charge of interacting with the underlying database.
 ///
 /// Currently works for `SQLite`. `PostgreSQL` should also be added in the future.
@@ -71,6 +183,59 @@ const TABLES: [&str; 6] = [
 pub struct DBM {
     /// The underlying database connection.
     connection: Connection,
+    /// An in-memory filter over the locators of all stored appointments, so locator lookups only hit the database
+    /// for the (rare) candidates.
+    locator_filter: RefCell<LocatorFilter>,
+    /// The [LocatorFilter] replacing the current one, while it is being rebuilt.
+    locator_filter_rebuild: RefCell<Option<LocatorFilterRebuild>>,
+    /// Where the database lives. Used to open additional connections to it.
+    db_path: PathBuf,
+    /// The key the database is encrypted with, if any.
+    db_key: Option<DatabaseKey>,
+}
+
+/// A [LocatorFilter] being rebuilt from the stored appointments.
+///
+/// Appointments are loaded a few at a time, alongside the operations updating the filter, so rebuilding never stalls
+/// the tower. The current filter is used until the new one is complete.
+#[derive(Debug)]
+struct LocatorFilterRebuild {
+    /// The filter being built.
+    filter: LocatorFilter,
+    /// The rowid of the last appointment loaded into the filter.
+    last_rowid: i64,
+}
+
+/// A dedicated connection to the tower database, used to snapshot it without holding the [DBM].
+pub(crate) struct SnapshotSource {
+    connection: Connection,
+    db_key: Option<DatabaseKey>,
+}
+
+impl SnapshotSource {
+    /// Writes a consistent snapshot of the database to the given path, returning the last known block it holds.
+    ///
+    /// The database is copied a few pages at a time using the SQLite backup API, so the tower can keep reading and
+    /// writing meanwhile. The copy starts over if the database is modified along the way, so the snapshot always
+    /// matches a single point in time. Encrypted databases are snapshotted with the same key.
+    pub(crate) fn snapshot(&self, path: &Path) -> Result<Option<BlockHash>, SqliteError> {
+        let mut connection =
+            dbm::open_connection(path, self.db_key.as_ref().map(|key| key.0.as_str()))?;
+        Backup::new(&self.connection, &mut connection)?.run_to_completion(
+            SNAPSHOT_STEP_PAGES,
+            Duration::from_millis(SNAPSHOT_STEP_PAUSE),
+            None,
+        )?;
+
+        let snapshot = DBM {
+            connection,
+            locator_filter: RefCell::new(LocatorFilter::new(0)),
+            locator_filter_rebuild: RefCell::new(None),
+            db_path: path.to_path_buf(),
+            db_key: None,
+        };
+        Ok(snapshot.load_last_known_block())
+    }
 }
 
 impl DatabaseConnection for DBM {
@@ -84,12 +249,21 @@ impl DatabaseConnection for DBM {
 }
 
 impl DBM {
-    /// Creates a new [DBM] instance.
-    pub fn new(db_path: PathBuf) -> Result<Self, SqliteError> {
-        let connection = Connection::open(db_path)?;
+    /// Creates a new [DBM] instance. The database is encrypted with `db_key` (if any), which requires the `sqlcipher`
+    /// feature.
+    pub fn new(db_path: PathBuf, db_key: Option<&str>) -> Result<Self, SqliteError> {
+        let connection = dbm::open_connection(&db_path, db_key)?;
         connection.execute("PRAGMA foreign_keys=1;", [])?;
-        let mut dbm = Self { connection };
+        let mut dbm = Self {
+            connection,
+            locator_filter: RefCell::new(LocatorFilter::new(0)),
+            locator_filter_rebuild: RefCell::new(None),
+            db_path,
+            db_key: db_key.map(|key| DatabaseKey(key.to_owned())),
+        };
         dbm.create_tables(Vec::from_iter(TABLES))?;
+        dbm.index_penalty_txids()?;
+        dbm.rebuild_locator_filter();
 
         Ok(dbm)
     }
@@ -109,11 +283,11 @@ impl DBM {
             ],
         ) {
             Ok(x) => {
-                log::debug!("User successfully stored: {user_id}");
+                tracing::debug!(%user_id, "User successfully stored");
                 Ok(x)
             }
             Err(e) => {
-                log::error!("Couldn't store user: {user_id}. Error: {e:?}");
+                tracing::error!(%user_id, "Couldn't store user. Error: {e:?}");
                 Err(e)
             }
         }
@@ -133,14 +307,45 @@ impl DBM {
             ],
         ) {
             Ok(_) => {
-                log::debug!("User's info successfully updated: {user_id}");
+                tracing::debug!(%user_id, "User's info successfully updated");
             }
             Err(_) => {
-                log::error!("User not found, data cannot be updated: {user_id}");
+                tracing::error!(%user_id, "User not found, data cannot be updated");
+            }
+        }
+    }
+
+    /// Sets the last nonce used by a given (registered) user, provided it is greater than the current one.
+    ///
+    /// Nonces are kept when users are deleted, so requests signed during an old subscription cannot be replayed once
+    /// the user registers again. Returns whether the nonce has been updated.
+    pub(crate) fn update_user_nonce(&self, user_id: UserId, nonce: u64) -> bool {
+        let query = "INSERT INTO nonces (user_id, nonce)
+            SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM users WHERE user_id=?1)
+            ON CONFLICT (user_id) DO UPDATE SET nonce=excluded.nonce WHERE excluded.nonce>nonces.nonce";
+        match self
+            .connection
+            .execute(query, params![user_id.to_vec(), nonce])
+        {
+            Ok(updated) => updated == 1,
+            Err(e) => {
+                tracing::error!(%user_id, "Couldn't update user nonce. Error: {e:?}");
+                false
             }
         }
     }
 
+    /// Loads the last nonce used by a given user, if any.
+    pub(crate) fn load_user_nonce(&self, user_id: UserId) -> Option<u64> {
+        self.connection
+            .query_row(
+                "SELECT nonce FROM nonces WHERE user_id=(?)",
+                [user_id.to_vec()],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
     /// Loads the associated locators ([Locator]) of a given user ([UserId]).
     pub(crate) fn load_user_locators(&self, user_id: UserId) -> Vec<Locator> {
         let mut stmt = self
@@ -158,26 +363,65 @@ impl DBM {
         .collect()
     }
 
-    /// Loads all users from the database.
-    pub(crate) fn load_all_users(&self) -> HashMap<UserId, UserInfo> {
-        let mut users = HashMap::new();
+    /// Loads a given user ([UserInfo]) from the database.
+    pub(crate) fn load_user(&self, user_id: UserId) -> Option<UserInfo> {
         let mut stmt = self
             .connection
-            .prepare("SELECT user_id, available_slots, subscription_start, subscription_expiry FROM users")
+            .prepare(
+                "SELECT available_slots, subscription_start, subscription_expiry FROM users WHERE user_id=(?)",
+            )
             .unwrap();
-        let mut rows = stmt.query([]).unwrap();
+        stmt.query_row([user_id.to_vec()], |row| {
+            let slots = row.get(0).unwrap();
+            let start = row.get(1).unwrap();
+            let expiry = row.get(2).unwrap();
+            Ok(UserInfo::new(slots, start, expiry))
+        })
+        .ok()
+    }
 
-        while let Ok(Some(row)) = rows.next() {
+    /// Loads the ids of all the users registered within the tower.
+    pub(crate) fn load_user_ids(&self) -> Vec<UserId> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT user_id FROM users")
+            .unwrap();
+
+        stmt.query_map([], |row| {
             let raw_userid: Vec<u8> = row.get(0).unwrap();
-            let user_id = UserId::from_slice(&raw_userid).unwrap();
-            let slots = row.get(1).unwrap();
-            let start = row.get(2).unwrap();
-            let expiry = row.get(3).unwrap();
+            Ok(UserId::from_slice(&raw_userid).unwrap())
+        })
+        .unwrap()
+        .map(|res| res.unwrap())
+        .collect()
+    }
 
-            users.insert(user_id, UserInfo::new(slots, start, expiry));
-        }
+    /// Loads the ids of the users whose subscription expires at or before `max_expiry`.
+    ///
+    /// The query is backed by an index on the subscription expiry, so its cost depends on the number of matching
+    /// users rather than on the number of registered ones.
+    pub(crate) fn load_expired_users(&self, max_expiry: u32) -> Vec<UserId> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT user_id FROM users WHERE subscription_expiry<=(?)")
+            .unwrap();
+
+        stmt.query_map([max_expiry], |row| {
+            let raw_userid: Vec<u8> = row.get(0).unwrap();
+            Ok(UserId::from_slice(&raw_userid).unwrap())
+        })
+        .unwrap()
+        .map(|res| res.unwrap())
+        .collect()
+    }
 
-        users
+    /// Get the number of registered users.
+    pub(crate) fn get_users_count(&self) -> usize {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT COUNT(*) FROM users")
+            .unwrap();
+        stmt.query_row([], |row| row.get(0)).unwrap()
     }
 
     /// Removes some users from the database in batch.
@@ -189,19 +433,26 @@ impl DBM {
             .map(|uuid| uuid.to_vec())
             .collect::<Vec<Vec<u8>>>();
 
+        // Users' appointments are deleted on cascade, so their locators are loaded beforehand
+        let locators = DBM::load_appointment_locators(&tx, "user_id", &iter);
         for chunk in iter.chunks(limit) {
-            let query = "DELETE FROM users WHERE user_id IN ".to_owned();
             let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
-
+            let query = "DELETE FROM users WHERE user_id IN ".to_owned();
             match tx.execute(&format!("{query}{placeholders}"), params_from_iter(chunk)) {
-                Ok(_) => log::debug!("Users deletion added to db transaction"),
-                Err(e) => log::error!("Couldn't add deletion query to transaction. Error: {e:?}"),
+                Ok(_) => tracing::debug!("Users deletion added to db transaction"),
+                Err(e) => {
+                    tracing::error!("Couldn't add deletion query to transaction. Error: {e:?}")
+                }
             }
         }
+        let removed_locators = DBM::count_removed_locators(&tx, &locators);
 
         match tx.commit() {
-            Ok(_) => log::debug!("Users successfully deleted"),
-            Err(e) => log::error!("Couldn't delete users. Error: {e:?}"),
+            Ok(_) => {
+                tracing::debug!("Users successfully deleted");
+                self.remove_from_locator_filter(removed_locators);
+            }
+            Err(e) => tracing::error!("Couldn't delete users. Error: {e:?}"),
         }
 
         (users.len() as f64 / limit as f64).ceil() as usize
@@ -245,11 +496,12 @@ impl DBM {
             ],
         ) {
             Ok(x) => {
-                log::debug!("Appointment successfully stored: {uuid}");
+                tracing::debug!(%uuid, "Appointment successfully stored");
+                self.add_to_locator_filter(&[appointment.locator()]);
                 Ok(x)
             }
             Err(e) => {
-                log::error!("Couldn't store appointment: {uuid}. Error: {e:?}");
+                tracing::error!(%uuid, "Couldn't store appointment. Error: {e:?}");
                 Err(e)
             }
         }
@@ -275,16 +527,75 @@ impl DBM {
             ],
         ) {
             Ok(_) => {
-                log::debug!("Appointment successfully updated: {uuid}");
+                tracing::debug!(%uuid, "Appointment successfully updated");
                 Ok(())
             }
             Err(e) => {
-                log::error!("Appointment not found, data cannot be updated: {uuid}. Error: {e:?}");
+                tracing::error!(%uuid, "Appointment not found, data cannot be updated. Error: {e:?}");
                 Err(e)
             }
         }
     }
 
+    /// Stores some appointments into the database in batch (updating the ones that already exist) and updates the
+    /// associated users (consuming appointment slots) in one transaction so that the insertion and the update is atomic.
+    pub(crate) fn batch_store_appointments(
+        &mut self,
+        appointments: &[&ExtendedAppointment],
+        updated_users: &HashMap<UserId, UserInfo>,
+    ) -> Result<(), Error> {
+        let tx = self.connection.transaction().unwrap();
+
+        for appointment in appointments.iter() {
+            let uuid = appointment.uuid();
+            let query = "INSERT INTO appointments (UUID, locator, encrypted_blob, to_self_delay, user_signature, start_block, user_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT (UUID) DO UPDATE SET encrypted_blob=excluded.encrypted_blob, to_self_delay=excluded.to_self_delay, user_signature=excluded.user_signature, start_block=excluded.start_block";
+            match tx.execute(
+                query,
+                params![
+                    uuid.to_vec(),
+                    appointment.locator().to_vec(),
+                    appointment.encrypted_blob(),
+                    appointment.to_self_delay(),
+                    appointment.user_signature,
+                    appointment.start_block,
+                    appointment.user_id.to_vec(),
+                ],
+            ) {
+                Ok(_) => tracing::debug!(%uuid, "Appointment insertion added to db transaction"),
+                Err(e) => {
+                    tracing::error!(%uuid, "Couldn't add insertion query to transaction. Error: {e:?}");
+                    return Err(Error::Unknown(e));
+                }
+            }
+        }
+
+        for (id, info) in updated_users.iter() {
+            let query = "UPDATE users SET available_slots=(?1) WHERE user_id=(?2)";
+            match tx.execute(query, params![info.available_slots, id.to_vec(),]) {
+                Ok(_) => tracing::debug!("User update added to db transaction"),
+                Err(e) => {
+                    tracing::error!("Couldn't add update query to transaction. Error: {e:?}");
+                    return Err(Error::Unknown(e));
+                }
+            };
+        }
+
+        match tx.commit() {
+            Ok(_) => {
+                tracing::debug!("Appointments successfully stored");
+                self.add_to_locator_filter(
+                    &appointments.iter().map(|a| a.locator()).collect::<Vec<_>>(),
+                );
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Couldn't store appointments. Error: {e:?}");
+                Err(Error::Unknown(e))
+            }
+        }
+    }
+
     /// Loads an [Appointment] from the database.
     pub(crate) fn load_appointment(&self, uuid: UUID) -> Option<ExtendedAppointment> {
         let key = uuid.to_vec();
@@ -373,6 +684,81 @@ impl DBM {
         appointments
     }
 
+    /// Loads a page of appointments and trackers from the database, sorted by [UUID].
+    ///
+    /// Only entries matching `filter` and whose [UUID] is greater than `cursor` (if given) are loaded, up to `limit`.
+    /// Appointments that have been handed to the Responder are returned as trackers.
+    pub(crate) fn load_appointments_page(
+        &self,
+        filter: &AppointmentsFilter,
+        cursor: Option<UUID>,
+        limit: usize,
+    ) -> Vec<(UUID, AppointmentRecord)> {
+        let mut sql =
+            "SELECT a.UUID, a.locator, a.encrypted_blob, a.to_self_delay, a.user_id, t.dispute_tx, t.penalty_tx, t.height, t.confirmed
+                FROM appointments as a LEFT JOIN trackers as t ON a.UUID=t.UUID WHERE 1".to_string();
+        let mut filters: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(cursor) = cursor {
+            sql.push_str(" AND a.UUID>(?)");
+            filters.push(Box::new(cursor.to_vec()));
+        }
+        if let Some(user_id) = filter.user_id {
+            sql.push_str(" AND a.user_id=(?)");
+            filters.push(Box::new(user_id.to_vec()));
+        }
+        if let Some(start_block_from) = filter.start_block_from {
+            sql.push_str(" AND a.start_block>=(?)");
+            filters.push(Box::new(start_block_from));
+        }
+        if let Some(start_block_to) = filter.start_block_to {
+            sql.push_str(" AND a.start_block<=(?)");
+            filters.push(Box::new(start_block_to));
+        }
+        match filter.responded {
+            Some(true) => sql.push_str(" AND t.UUID IS NOT NULL"),
+            Some(false) => sql.push_str(" AND t.UUID IS NULL"),
+            None => (),
+        }
+        if let Some(confirmed) = filter.confirmed {
+            sql.push_str(" AND t.confirmed=(?)");
+            filters.push(Box::new(confirmed));
+        }
+        sql.push_str(" ORDER BY a.UUID LIMIT (?)");
+        filters.push(Box::new(limit as i64));
+        let mut stmt = self.connection.prepare(&sql).unwrap();
+
+        stmt.query_map(params_from_iter(filters), |row| {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            let raw_locator: Vec<u8> = row.get(1).unwrap();
+            let raw_userid: Vec<u8> = row.get(4).unwrap();
+            let raw_dispute_tx: Option<Vec<u8>> = row.get(5).unwrap();
+
+            let uuid = UUID::from_slice(&raw_uuid).unwrap();
+            let record = if let Some(raw_dispute_tx) = raw_dispute_tx {
+                let raw_penalty_tx: Vec<u8> = row.get(6).unwrap();
+                AppointmentRecord::Tracker(TransactionTracker {
+                    dispute_tx: consensus::deserialize(&raw_dispute_tx).unwrap(),
+                    penalty_tx: consensus::deserialize(&raw_penalty_tx).unwrap(),
+                    status: ConfirmationStatus::from_db_data(
+                        row.get(7).unwrap(),
+                        row.get(8).unwrap(),
+                    ),
+                    user_id: UserId::from_slice(&raw_userid).unwrap(),
+                })
+            } else {
+                AppointmentRecord::Appointment(Appointment::new(
+                    Locator::from_slice(&raw_locator).unwrap(),
+                    row.get(2).unwrap(),
+                    row.get(3).unwrap(),
+                ))
+            };
+            Ok((uuid, record))
+        })
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+    }
+
     /// Gets the length of an appointment (the length of `appointment.encrypted_blob`).
     pub(crate) fn get_appointment_length(&self, uuid: UUID) -> Option<usize> {
         let mut stmt = self
@@ -401,13 +787,18 @@ impl DBM {
 
     /// Removes an [Appointment] from the database.
     pub(crate) fn remove_appointment(&self, uuid: UUID) {
+        let locators = DBM::load_appointment_locators(&self.connection, "UUID", &[uuid.to_vec()]);
         let query = "DELETE FROM appointments WHERE UUID=(?)";
         match self.remove_data(query, params![uuid.to_vec()]) {
             Ok(_) => {
-                log::debug!("Appointment successfully removed: {uuid}");
+                tracing::debug!(%uuid, "Appointment successfully removed");
+                self.remove_from_locator_filter(DBM::count_removed_locators(
+                    &self.connection,
+                    &locators,
+                ));
             }
             Err(_) => {
-                log::error!("Appointment not found, data cannot be removed: {uuid}");
+                tracing::error!(%uuid, "Appointment not found, data cannot be removed");
             }
         }
     }
@@ -422,35 +813,101 @@ impl DBM {
     ) -> usize {
         let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
         let tx = self.connection.transaction().unwrap();
+        let removed_locators = DBM::remove_appointments(&tx, limit, appointments, updated_users);
+
+        match tx.commit() {
+            Ok(_) => {
+                tracing::debug!("Appointments successfully deleted");
+                self.remove_from_locator_filter(removed_locators);
+            }
+            Err(e) => tracing::error!("Couldn't delete appointments. Error: {e:?}"),
+        }
+
+        (appointments.len() as f64 / limit as f64).ceil() as usize
+    }
+
+    /// Removes some appointments of a given user on their behalf, updating the user (giving back freed appointment
+    /// slots) and the last nonce used by them in one transaction.
+    ///
+    /// Nothing is removed if the nonce is not greater than the last one used by the user. Returns whether the
+    /// appointments have been removed.
+    pub(crate) fn remove_user_appointments(
+        &mut self,
+        user_id: UserId,
+        nonce: u64,
+        appointments: &[UUID],
+        user_info: &UserInfo,
+    ) -> bool {
+        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let tx = self.connection.transaction().unwrap();
+
+        let query = "INSERT INTO nonces (user_id, nonce) VALUES (?1, ?2)
+            ON CONFLICT (user_id) DO UPDATE SET nonce=excluded.nonce WHERE excluded.nonce>nonces.nonce";
+        match tx.execute(query, params![user_id.to_vec(), nonce]) {
+            Ok(1) => tracing::debug!("Nonce update added to db transaction"),
+            Ok(_) => return false,
+            Err(e) => {
+                tracing::error!(%user_id, "Couldn't update user nonce. Error: {e:?}");
+                return false;
+            }
+        }
+
+        let removed_locators = DBM::remove_appointments(
+            &tx,
+            limit,
+            appointments,
+            &HashMap::from([(user_id, *user_info)]),
+        );
+
+        match tx.commit() {
+            Ok(_) => {
+                tracing::debug!("Appointments successfully deleted");
+                self.remove_from_locator_filter(removed_locators);
+                true
+            }
+            Err(e) => {
+                tracing::error!("Couldn't delete appointments. Error: {e:?}");
+                false
+            }
+        }
+    }
+
+    /// Adds the removal of some appointments and the update of the associated users to an ongoing transaction.
+    ///
+    /// Returns the number of locators that are not held by any appointment once the transaction is committed.
+    fn remove_appointments(
+        conn: &Connection,
+        limit: usize,
+        appointments: &[UUID],
+        updated_users: &HashMap<UserId, UserInfo>,
+    ) -> usize {
         let iter = appointments
             .iter()
             .map(|uuid| uuid.to_vec())
             .collect::<Vec<Vec<u8>>>();
 
+        let locators = DBM::load_appointment_locators(conn, "UUID", &iter);
         for chunk in iter.chunks(limit) {
             let query = "DELETE FROM appointments WHERE UUID IN ".to_owned();
             let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
 
-            match tx.execute(&format!("{query}{placeholders}"), params_from_iter(chunk)) {
-                Ok(_) => log::debug!("Appointments deletion added to db transaction"),
-                Err(e) => log::error!("Couldn't add deletion query to transaction. Error: {e:?}"),
+            match conn.execute(&format!("{query}{placeholders}"), params_from_iter(chunk)) {
+                Ok(_) => tracing::debug!("Appointments deletion added to db transaction"),
+                Err(e) => {
+                    tracing::error!("Couldn't add deletion query to transaction. Error: {e:?}")
+                }
             }
         }
 
         for (id, info) in updated_users.iter() {
             let query = "UPDATE users SET available_slots=(?1) WHERE user_id=(?2)";
-            match tx.execute(query, params![info.available_slots, id.to_vec(),]) {
-                Ok(_) => log::debug!("User update added to db transaction"),
-                Err(e) => log::error!("Couldn't add update query to transaction. Error: {e:?}"),
+            match conn.execute(query, params![info.available_slots, id.to_vec(),]) {
+                Ok(_) => tracing::debug!("User update added to db transaction"),
+                Err(e) => tracing::error!("Couldn't add update query to transaction. Error: {e:?}"),
             };
         }
 
-        match tx.commit() {
-            Ok(_) => log::debug!("Appointments successfully deleted"),
-            Err(e) => log::error!("Couldn't delete appointments. Error: {e:?}"),
-        }
-
-        (appointments.len() as f64 / limit as f64).ceil() as usize
+        DBM::count_removed_locators(conn, &locators)
     }
 
     /// Loads the [`UUID`]s of appointments triggered by `locator`.
@@ -471,9 +928,21 @@ impl DBM {
     }
 
     /// Filters the given set of [`Locator`]s by including only the ones which trigger any of our stored appointments.
+    ///
+    /// Locators are checked against the [LocatorFilter] first, so only the ones that may be known are looked up
+    /// in the database.
     pub(crate) fn batch_check_locators_exist(&self, locators: Vec<&Locator>) -> Vec<Locator> {
+        // This is called once per block, so it keeps the rebuild of the filter (if any) going when no appointments
+        // are being added or removed
+        self.rebuild_locator_filter_step();
+
         let mut registered_locators = Vec::new();
-        let locators: Vec<Vec<u8>> = locators.iter().map(|l| l.to_vec()).collect();
+        let locator_filter = self.locator_filter.borrow();
+        let locators: Vec<Vec<u8>> = locators
+            .iter()
+            .filter(|l| locator_filter.may_contain(l))
+            .map(|l| l.to_vec())
+            .collect();
         let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
 
         for chunk in locators.chunks(limit) {
@@ -498,6 +967,138 @@ impl DBM {
         registered_locators
     }
 
+    /// Builds the [LocatorFilter] from the locators of all the stored appointments at once. Used when the database is
+    /// opened, later rebuilds are done step by step (see [DBM::rebuild_locator_filter_step]).
+    fn rebuild_locator_filter(&self) {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT locator FROM appointments")
+            .unwrap();
+        let locators = stmt
+            .query_map([], |row| {
+                let raw_locator: Vec<u8> = row.get(0).unwrap();
+                Ok(Locator::from_slice(&raw_locator).unwrap())
+            })
+            .unwrap()
+            .map(|locator_res| locator_res.unwrap())
+            .collect();
+
+        let locator_filter = LocatorFilter::from_locators(locators);
+        tracing::debug!("Locator filter built: {locator_filter:?}");
+        *self.locator_filter.borrow_mut() = locator_filter;
+    }
+
+    /// Moves the rebuild of the [LocatorFilter] forward, starting it if the filter has got too full or too stale.
+    ///
+    /// Each call loads (at most) [LOCATOR_FILTER_REBUILD_STEP] appointments into the new filter, which replaces the
+    /// current one once all the stored appointments are loaded.
+    fn rebuild_locator_filter_step(&self) {
+        let mut rebuild = self.locator_filter_rebuild.borrow_mut();
+        if rebuild.is_none() {
+            let locator_filter = self.locator_filter.borrow();
+            if !locator_filter.needs_rebuild() {
+                return;
+            }
+            tracing::debug!("Rebuilding locator filter: {locator_filter:?}");
+            *rebuild = Some(LocatorFilterRebuild {
+                filter: LocatorFilter::new(locator_filter.estimated_len() * 2),
+                last_rowid: i64::MIN,
+            });
+        }
+
+        let step = rebuild.as_mut().unwrap();
+        let mut stmt = self
+            .connection
+            .prepare("SELECT rowid, locator FROM appointments WHERE rowid > (?1) ORDER BY rowid LIMIT (?2)")
+            .unwrap();
+        let mut rows = stmt
+            .query(params![step.last_rowid, LOCATOR_FILTER_REBUILD_STEP])
+            .unwrap();
+        let mut loaded = 0;
+        while let Ok(Some(row)) = rows.next() {
+            let raw_locator: Vec<u8> = row.get(1).unwrap();
+            step.filter
+                .insert(&Locator::from_slice(&raw_locator).unwrap());
+            step.last_rowid = row.get(0).unwrap();
+            loaded += 1;
+        }
+
+        if loaded < LOCATOR_FILTER_REBUILD_STEP {
+            let locator_filter = rebuild.take().unwrap().filter;
+            tracing::debug!("Locator filter built: {locator_filter:?}");
+            *self.locator_filter.borrow_mut() = locator_filter;
+        }
+    }
+
+    /// Adds the locators of some newly stored appointments to the [LocatorFilter] (and to the one being rebuilt, if
+    /// any, so they are not missed if they were stored after the rebuild went past them).
+    fn add_to_locator_filter(&self, locators: &[Locator]) {
+        let mut locator_filter = self.locator_filter.borrow_mut();
+        let mut rebuild = self.locator_filter_rebuild.borrow_mut();
+        for locator in locators {
+            locator_filter.insert(locator);
+            if let Some(rebuild) = rebuild.as_mut() {
+                rebuild.filter.insert(locator);
+            }
+        }
+        drop((locator_filter, rebuild));
+        self.rebuild_locator_filter_step();
+    }
+
+    /// Loads the distinct locators of the appointments whose `column` (either `UUID` or `user_id`) is one of `values`.
+    fn load_appointment_locators(
+        conn: &Connection,
+        column: &str,
+        values: &[Vec<u8>],
+    ) -> HashSet<Vec<u8>> {
+        let limit = conn.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let mut locators = HashSet::new();
+        for chunk in values.chunks(limit) {
+            let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT DISTINCT locator FROM appointments WHERE {column} IN {placeholders}"
+                ))
+                .unwrap();
+            locators.extend(
+                stmt.query_map(params_from_iter(chunk), |row| row.get(0))
+                    .unwrap()
+                    .map(|locator_res| locator_res.unwrap()),
+            );
+        }
+        locators
+    }
+
+    /// Counts how many of the given locators are not held by any appointment anymore.
+    fn count_removed_locators(conn: &Connection, locators: &HashSet<Vec<u8>>) -> usize {
+        let limit = conn.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let locators: Vec<&Vec<u8>> = locators.iter().collect();
+        let mut remaining = 0;
+        for chunk in locators.chunks(limit) {
+            let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+            remaining += conn
+                .query_row(
+                    &format!(
+                        "SELECT COUNT(DISTINCT locator) FROM appointments WHERE locator IN {placeholders}"
+                    ),
+                    params_from_iter(chunk),
+                    |row| row.get::<_, usize>(0),
+                )
+                .unwrap();
+        }
+        locators.len() - remaining
+    }
+
+    /// Accounts for `count` distinct locators no longer being held by any appointment in the [LocatorFilter] (and in
+    /// the one being rebuilt, if any, since it may have already loaded them).
+    fn remove_from_locator_filter(&self, count: usize) {
+        self.locator_filter.borrow_mut().remove(count);
+        if let Some(rebuild) = self.locator_filter_rebuild.borrow_mut().as_mut() {
+            rebuild.filter.remove(count);
+        }
+        self.rebuild_locator_filter_step();
+    }
+
     /// Stores a [TransactionTracker] into the database.
     pub(crate) fn store_tracker(
         &self,
@@ -506,24 +1107,38 @@ impl DBM {
     ) -> Result<(), Error> {
         let (height, confirmed) = tracker.status.to_db_data().ok_or(Error::MissingField)?;
 
+        // The tracker and its penalty txid are stored atomically. The transaction is rolled back if dropped.
+        let tx = self
+            .connection
+            .unchecked_transaction()
+            .map_err(Error::Unknown)?;
         let query =
             "INSERT INTO trackers (UUID, dispute_tx, penalty_tx, height, confirmed) VALUES (?1, ?2, ?3, ?4, ?5)";
-        match self.store_data(
-            query,
-            params![
-                uuid.to_vec(),
-                consensus::serialize(&tracker.dispute_tx),
-                consensus::serialize(&tracker.penalty_tx),
-                height,
-                confirmed,
-            ],
-        ) {
+        match self
+            .store_data(
+                query,
+                params![
+                    uuid.to_vec(),
+                    consensus::serialize(&tracker.dispute_tx),
+                    consensus::serialize(&tracker.penalty_tx),
+                    height,
+                    confirmed,
+                ],
+            )
+            .and_then(|_| {
+                self.store_data(
+                    "INSERT INTO penalty_txids (UUID, txid) VALUES (?1, ?2)",
+                    params![uuid.to_vec(), tracker.penalty_tx.txid().to_vec()],
+                )
+            })
+            .and_then(|_| tx.commit().map_err(Error::Unknown))
+        {
             Ok(x) => {
-                log::debug!("Tracker successfully stored: {uuid}");
+                tracing::debug!(%uuid, "Tracker successfully stored");
                 Ok(x)
             }
             Err(e) => {
-                log::error!("Couldn't store tracker: {uuid}. Error: {e:?}");
+                tracing::error!(%uuid, "Couldn't store tracker. Error: {e:?}");
                 Err(e)
             }
         }
@@ -542,11 +1157,11 @@ impl DBM {
         let query = "UPDATE trackers SET height=(?1), confirmed=(?2) WHERE UUID=(?3)";
         match self.update_data(query, params![height, confirmed, uuid.to_vec(),]) {
             Ok(x) => {
-                log::debug!("Tracker successfully updated: {uuid}");
+                tracing::debug!(%uuid, "Tracker successfully updated");
                 Ok(x)
             }
             Err(e) => {
-                log::error!("Couldn't update tracker: {uuid}. Error: {e:?}");
+                tracing::error!(%uuid, "Couldn't update tracker. Error: {e:?}");
                 Err(e)
             }
         }
@@ -616,31 +1231,69 @@ impl DBM {
         };
 
         while let Ok(Some(row)) = rows.next() {
-            let raw_uuid: Vec<u8> = row.get(0).unwrap();
-            let uuid = UUID::from_slice(&raw_uuid[0..20]).unwrap();
-            let raw_dispute_tx: Vec<u8> = row.get(1).unwrap();
-            let dispute_tx = consensus::deserialize(&raw_dispute_tx).unwrap();
-            let raw_penalty_tx: Vec<u8> = row.get(2).unwrap();
-            let penalty_tx = consensus::deserialize(&raw_penalty_tx).unwrap();
-            let height: u32 = row.get(3).unwrap();
-            let confirmed: bool = row.get(4).unwrap();
-            let raw_userid: Vec<u8> = row.get(5).unwrap();
-            let user_id = UserId::from_slice(&raw_userid).unwrap();
+            let (uuid, tracker) = DBM::tracker_from_row(row);
+            trackers.insert(uuid, tracker);
+        }
 
-            trackers.insert(
-                uuid,
-                TransactionTracker {
-                    dispute_tx,
-                    penalty_tx,
-                    status: ConfirmationStatus::from_db_data(height, confirmed),
-                    user_id,
-                },
-            );
+        trackers
+    }
+
+    /// Loads the trackers of the given users from the database.
+    pub(crate) fn load_trackers_by_user_ids(
+        &self,
+        user_ids: &[UserId],
+    ) -> HashMap<UUID, TransactionTracker> {
+        let mut trackers = HashMap::new();
+        let user_ids: Vec<Vec<u8>> = user_ids.iter().map(|user_id| user_id.to_vec()).collect();
+        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+
+        for chunk in user_ids.chunks(limit) {
+            let query =
+                "SELECT t.UUID, t.dispute_tx, t.penalty_tx, t.height, t.confirmed, a.user_id
+                FROM trackers as t INNER JOIN appointments as a ON t.UUID=a.UUID
+                WHERE a.user_id IN "
+                    .to_owned();
+            let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+
+            let mut stmt = self
+                .connection
+                .prepare(&format!("{query}{placeholders}"))
+                .unwrap();
+            let mut rows = stmt.query(params_from_iter(chunk)).unwrap();
+
+            while let Ok(Some(row)) = rows.next() {
+                let (uuid, tracker) = DBM::tracker_from_row(row);
+                trackers.insert(uuid, tracker);
+            }
         }
 
         trackers
     }
 
+    /// Builds a tracker out of a row holding `UUID, dispute_tx, penalty_tx, height, confirmed, user_id`.
+    fn tracker_from_row(row: &Row) -> (UUID, TransactionTracker) {
+        let raw_uuid: Vec<u8> = row.get(0).unwrap();
+        let uuid = UUID::from_slice(&raw_uuid[0..20]).unwrap();
+        let raw_dispute_tx: Vec<u8> = row.get(1).unwrap();
+        let dispute_tx = consensus::deserialize(&raw_dispute_tx).unwrap();
+        let raw_penalty_tx: Vec<u8> = row.get(2).unwrap();
+        let penalty_tx = consensus::deserialize(&raw_penalty_tx).unwrap();
+        let height: u32 = row.get(3).unwrap();
+        let confirmed: bool = row.get(4).unwrap();
+        let raw_userid: Vec<u8> = row.get(5).unwrap();
+        let user_id = UserId::from_slice(&raw_userid).unwrap();
+
+        (
+            uuid,
+            TransactionTracker {
+                dispute_tx,
+                penalty_tx,
+                status: ConfirmationStatus::from_db_data(height, confirmed),
+                user_id,
+            },
+        )
+    }
+
     /// Loads trackers with the given confirmation status.
     ///
     /// Note that for [`ConfirmationStatus::InMempoolSince(height)`] variant, this pulls trackers
@@ -667,40 +1320,300 @@ impl DBM {
             .collect())
     }
 
-    /// Loads the transaction IDs of all the penalties and their status from the database.
-    pub(crate) fn load_penalties_summaries(&self) -> HashMap<UUID, PenaltySummary> {
+    /// Loads the trackers that were confirmed at or before a given height.
+    ///
+    /// Unlike [`ConfirmationStatus::ConfirmedIn(height)`] in [DBM::load_trackers_with_confirmation_status], this also
+    /// pulls trackers confirmed at lower heights, so none is missed if some height is skipped.
+    pub(crate) fn load_trackers_confirmed_up_to(&self, height: u32) -> Vec<UUID> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT UUID FROM trackers WHERE confirmed=1 AND height<=(?)")
+            .unwrap();
+
+        stmt.query_map([height], |row| {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            Ok(UUID::from_slice(&raw_uuid).unwrap())
+        })
+        .unwrap()
+        .map(|uuid_res| uuid_res.unwrap())
+        .collect()
+    }
+
+    /// Loads the [`UUID`]s of the trackers which penalty transaction is any of the given ones.
+    pub(crate) fn load_uuids_by_penalty_txids(&self, txids: Vec<&Txid>) -> Vec<UUID> {
+        let mut uuids = Vec::new();
+        let txids: Vec<Vec<u8>> = txids.iter().map(|txid| txid.to_vec()).collect();
+        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+
+        for chunk in txids.chunks(limit) {
+            let query = "SELECT UUID FROM penalty_txids WHERE txid IN ".to_owned();
+            let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+
+            let mut stmt = self
+                .connection
+                .prepare(&format!("{query}{placeholders}"))
+                .unwrap();
+            let known_uuids = stmt
+                .query_map(params_from_iter(chunk), |row| {
+                    let raw_uuid: Vec<u8> = row.get(0).unwrap();
+                    let uuid = UUID::from_slice(&raw_uuid).unwrap();
+                    Ok(uuid)
+                })
+                .unwrap()
+                .map(|uuid_res| uuid_res.unwrap());
+            uuids.extend(known_uuids);
+        }
+
+        uuids
+    }
+
+    /// Counts the penalties that are not confirmed yet, leaving the ones of the `excluded` trackers out.
+    pub(crate) fn count_unconfirmed_penalties(&self, excluded: &HashSet<UUID>) -> usize {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT COUNT(*) FROM trackers WHERE confirmed=0")
+            .unwrap();
+        let count: usize = stmt.query_row([], |row| row.get(0)).unwrap();
+
+        let excluded: Vec<Vec<u8>> = excluded.iter().map(|uuid| uuid.to_vec()).collect();
+        let limit = self.connection.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
+        let mut excluded_count = 0;
+        for chunk in excluded.chunks(limit) {
+            let placeholders = format!("(?{})", (", ?").repeat(chunk.len() - 1));
+            let mut stmt = self
+                .connection
+                .prepare(&format!(
+                    "SELECT COUNT(*) FROM trackers WHERE confirmed=0 AND UUID IN {placeholders}"
+                ))
+                .unwrap();
+            let chunk_count: usize = stmt
+                .query_row(params_from_iter(chunk), |row| row.get(0))
+                .unwrap();
+            excluded_count += chunk_count;
+        }
+
+        count - excluded_count
+    }
+
+    /// Loads the transaction IDs and status of the penalties that are not confirmed yet from the database.
+    pub(crate) fn load_unconfirmed_penalties_summaries(&self) -> HashMap<UUID, PenaltySummary> {
         let mut summaries = HashMap::new();
 
         let mut stmt = self
             .connection
             .prepare(
-                "SELECT t.UUID, t.penalty_tx, t.height, t.confirmed
-                    FROM trackers as t INNER JOIN appointments as a ON t.UUID=a.UUID",
+                "SELECT t.UUID, p.txid, t.height
+                    FROM trackers as t INNER JOIN penalty_txids as p ON t.UUID=p.UUID WHERE t.confirmed=0",
             )
             .unwrap();
         let mut rows = stmt.query([]).unwrap();
 
         while let Ok(Some(row)) = rows.next() {
             let raw_uuid: Vec<u8> = row.get(0).unwrap();
-            let raw_penalty_tx: Vec<u8> = row.get(1).unwrap();
+            let raw_penalty_txid: Vec<u8> = row.get(1).unwrap();
             let height: u32 = row.get(2).unwrap();
-            let confirmed: bool = row.get(3).unwrap();
 
-            // DISCUSS: Should we store the txids to avoid pulling raw txs and deserializing then hashing them.
-            let penalty_txid = consensus::deserialize::<bitcoin::Transaction>(&raw_penalty_tx)
-                .unwrap()
-                .txid();
             summaries.insert(
                 UUID::from_slice(&raw_uuid).unwrap(),
                 PenaltySummary::new(
-                    penalty_txid,
-                    ConfirmationStatus::from_db_data(height, confirmed),
+                    Txid::from_slice(&raw_penalty_txid).unwrap(),
+                    ConfirmationStatus::InMempoolSince(height),
                 ),
             );
         }
         summaries
     }
 
+    /// Adds the penalty txids of the trackers that are missing from the index (e.g. the ones stored before it existed).
+    fn index_penalty_txids(&self) -> Result<(), SqliteError> {
+        let mut stmt = self.connection.prepare(
+            "SELECT t.UUID, t.penalty_tx FROM trackers as t LEFT JOIN penalty_txids as p ON t.UUID=p.UUID
+                WHERE p.UUID IS NULL",
+        )?;
+        let missing = stmt
+            .query_map([], |row| {
+                let raw_uuid: Vec<u8> = row.get(0)?;
+                let raw_penalty_tx: Vec<u8> = row.get(1)?;
+                let penalty_tx: bitcoin::Transaction = consensus::deserialize(&raw_penalty_tx)
+                    .map_err(|e| {
+                        SqliteError::FromSqlConversionFailure(1, Type::Blob, Box::new(e))
+                    })?;
+                Ok((raw_uuid, penalty_tx.txid()))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !missing.is_empty() {
+            tracing::info!("Indexing the penalty txids of {} tracker(s)", missing.len());
+            let tx = self.connection.unchecked_transaction()?;
+            for (raw_uuid, penalty_txid) in missing {
+                tx.execute(
+                    "INSERT INTO penalty_txids (UUID, txid) VALUES (?1, ?2)",
+                    params![raw_uuid, penalty_txid.to_vec()],
+                )?;
+            }
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores (or updates) the webhook of a given user into the database.
+    pub(crate) fn store_webhook(&self, user_id: UserId, url: &str) -> Result<(), Error> {
+        let query = "INSERT INTO webhooks (user_id, url) VALUES (?1, ?2)
+            ON CONFLICT (user_id) DO UPDATE SET url=excluded.url";
+        match self.store_data(query, params![user_id.to_vec(), url]) {
+            Ok(x) => {
+                tracing::debug!(%user_id, "Webhook successfully stored");
+                Ok(x)
+            }
+            Err(e) => {
+                tracing::error!(%user_id, "Couldn't store webhook. Error: {e:?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes the webhook of a given user from the database. Pending notifications are removed alongside it.
+    pub(crate) fn remove_webhook(&self, user_id: UserId) -> Result<(), Error> {
+        self.remove_data(
+            "DELETE FROM webhooks WHERE user_id=(?)",
+            params![user_id.to_vec()],
+        )
+    }
+
+    /// Stores a notification into the database, to be delivered at `next_attempt`.
+    ///
+    /// Returns [Error::MissingForeignKey] if the user has no webhook registered.
+    pub(crate) fn store_notification(
+        &self,
+        user_id: UserId,
+        payload: &str,
+        next_attempt: u64,
+    ) -> Result<(), Error> {
+        let query = "INSERT INTO notifications (user_id, payload, attempts, next_attempt) VALUES (?1, ?2, 0, ?3)";
+        self.store_data(query, params![user_id.to_vec(), payload, next_attempt])
+    }
+
+    /// Loads the notifications that are due at a given time, alongside the url they must be delivered to.
+    pub(crate) fn load_due_notifications(&self, now: u64) -> Vec<QueuedNotification> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT n.id, w.url, n.payload, n.attempts
+                    FROM notifications as n INNER JOIN webhooks as w ON n.user_id=w.user_id
+                    WHERE n.next_attempt<=(?) ORDER BY n.id",
+            )
+            .unwrap();
+
+        stmt.query_map([now], |row| {
+            Ok(QueuedNotification {
+                id: row.get(0).unwrap(),
+                url: row.get(1).unwrap(),
+                payload: row.get(2).unwrap(),
+                attempts: row.get(3).unwrap(),
+            })
+        })
+        .unwrap()
+        .map(|notification| notification.unwrap())
+        .collect()
+    }
+
+    /// Gets the time the next pending notification is due at, if there is any.
+    pub(crate) fn get_next_notification_time(&self) -> Option<u64> {
+        self.connection
+            .query_row("SELECT MIN(next_attempt) FROM notifications", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    /// Updates the number of delivery attempts of a notification and the time it must be retried at.
+    pub(crate) fn update_notification_attempts(&self, id: u64, attempts: u32, next_attempt: u64) {
+        let query = "UPDATE notifications SET attempts=(?1), next_attempt=(?2) WHERE id=(?3)";
+        if self
+            .update_data(query, params![attempts, next_attempt, id])
+            .is_err()
+        {
+            tracing::debug!("Notification not found, data cannot be updated: {id}");
+        }
+    }
+
+    /// Removes a notification from the database.
+    pub(crate) fn remove_notification(&self, id: u64) {
+        if self
+            .remove_data("DELETE FROM notifications WHERE id=(?)", params![id])
+            .is_err()
+        {
+            tracing::debug!("Notification not found, data cannot be removed: {id}");
+        }
+    }
+
+    /// Appends an [Event] to the audit history.
+    ///
+    /// Events are not linked to any other table, so they are kept after the data they refer to is deleted.
+    pub(crate) fn store_event(&self, event: &Event) {
+        let query = "INSERT INTO events (UUID, user_id, locator, event, height, txid, rejection_reason, timestamp)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+        if let Err(e) = self.store_data(
+            query,
+            params![
+                event.uuid.to_vec(),
+                event.user_id.to_vec(),
+                event.locator.to_vec(),
+                event.kind.to_string(),
+                event.height,
+                event.txid.map(|txid| txid.to_vec()),
+                event.rejection_reason,
+                event.timestamp,
+            ],
+        ) {
+            tracing::error!(uuid = %event.uuid, "Couldn't store event. Error: {e:?}");
+        }
+    }
+
+    /// Loads events from the audit history, in the order they were recorded.
+    ///
+    /// Events can be filtered by [UUID] and/or [Locator]. All events are returned if no filter is given.
+    pub(crate) fn load_events(&self, uuid: Option<UUID>, locator: Option<Locator>) -> Vec<Event> {
+        let mut sql =
+            "SELECT UUID, user_id, locator, event, height, txid, rejection_reason, timestamp
+            FROM events WHERE 1"
+                .to_string();
+        let mut filters = Vec::new();
+        if let Some(uuid) = uuid {
+            sql.push_str(" AND UUID=(?)");
+            filters.push(uuid.to_vec());
+        }
+        if let Some(locator) = locator {
+            sql.push_str(" AND locator=(?)");
+            filters.push(locator.to_vec());
+        }
+        sql.push_str(" ORDER BY id");
+        let mut stmt = self.connection.prepare(&sql).unwrap();
+
+        stmt.query_map(params_from_iter(filters), |row| {
+            let raw_uuid: Vec<u8> = row.get(0).unwrap();
+            let raw_userid: Vec<u8> = row.get(1).unwrap();
+            let raw_locator: Vec<u8> = row.get(2).unwrap();
+            let kind: String = row.get(3).unwrap();
+            let raw_txid: Option<Vec<u8>> = row.get(5).unwrap();
+
+            Ok(Event {
+                uuid: UUID::from_slice(&raw_uuid).unwrap(),
+                user_id: UserId::from_slice(&raw_userid).unwrap(),
+                locator: Locator::from_slice(&raw_locator).unwrap(),
+                kind: EventKind::from_str(&kind).unwrap(),
+                height: row.get(4).unwrap(),
+                txid: raw_txid.map(|txid| Txid::from_slice(&txid).unwrap()),
+                rejection_reason: row.get(6).unwrap(),
+                timestamp: row.get(7).unwrap(),
+            })
+        })
+        .unwrap()
+        .map(|event| event.unwrap())
+        .collect()
+    }
+
     /// Stores the last known block into the database.
     pub(crate) fn store_last_known_block(&self, block_hash: &BlockHash) -> Result<(), Error> {
         let query = "INSERT OR REPLACE INTO last_known_block (id, block_hash) VALUES (0, ?)";
@@ -721,19 +1634,19 @@ impl DBM {
         .ok()
     }
 
-    /// Stores the tower secret key into the database.
+    /// Stores the tower secret key into the database, encrypted with the given passphrase (if any).
     ///
     /// When a new key is generated, old keys are not overwritten but are not retrievable from the API either.
-    pub fn store_tower_key(&self, sk: &SecretKey) -> Result<(), Error> {
+    pub fn store_tower_key(&self, sk: &SecretKey, passphrase: Option<&str>) -> Result<(), Error> {
         let query = "INSERT INTO keys (key) VALUES (?)";
-        self.store_data(query, params![sk.display_secret().to_string()])
+        self.store_data(
+            query,
+            params![cryptography::encode_secret_key(sk, passphrase)],
+        )
     }
 
-    /// Loads the last known tower secret key from the database.
-    ///
-    /// Loads the key with higher id from the database. Old keys are not overwritten just in case a recovery is needed,
-    /// but they are not accessible from the API either.
-    pub fn load_tower_key(&self) -> Option<SecretKey> {
+    /// Loads the last known tower secret key (as stored) from the database.
+    fn load_stored_tower_key(&self) -> Option<String> {
         let mut stmt = self
             .connection
             .prepare(
@@ -741,19 +1654,183 @@ impl DBM {
             )
             .unwrap();
 
-        stmt.query_row(["keys"], |row| {
-            let sk: String = row.get(0).unwrap();
-            Ok(SecretKey::from_str(&sk).unwrap())
+        stmt.query_row(["keys"], |row| row.get(0)).ok()
+    }
+
+    /// Loads the last known tower secret key from the database, decrypting it with the given passphrase if needed.
+    ///
+    /// Loads the key with higher id from the database. Old keys are not overwritten just in case a recovery is needed,
+    /// but they are not accessible from the API either.
+    pub fn load_tower_key(
+        &self,
+        passphrase: Option<&str>,
+    ) -> Result<Option<SecretKey>, KeyDecryptionError> {
+        self.load_stored_tower_key()
+            .map(|sk| cryptography::decode_secret_key(&sk, passphrase))
+            .transpose()
+    }
+
+    /// Checks whether the tower secret key is encrypted. Returns false if there is no key.
+    pub fn is_tower_key_encrypted(&self) -> bool {
+        self.load_stored_tower_key()
+            .as_deref()
+            .map(cryptography::is_encrypted_secret_key)
+            == Some(true)
+    }
+
+    /// Changes the passphrase the tower secret keys are encrypted with. Keys that are not encrypted get encrypted.
+    ///
+    /// All the keys (including the old ones) are re-encrypted atomically. If any of them cannot be decrypted using
+    /// `old_passphrase`, none of them is changed.
+    ///
+    /// Deriving the encryption keys is expensive by design. When the [DBM] is shared, prefer loading the keys with
+    /// [DBM::load_encoded_tower_keys] and re-encrypting them ([reencrypt_tower_keys]) before locking it to store them.
+    pub fn change_key_passphrase(
+        &mut self,
+        old_passphrase: Option<&str>,
+        new_passphrase: &str,
+    ) -> Result<(), KeyDecryptionError> {
+        let keys = reencrypt_tower_keys(
+            self.load_encoded_tower_keys(),
+            old_passphrase,
+            new_passphrase,
+        )?;
+        self.replace_encoded_tower_keys(&keys);
+        Ok(())
+    }
+
+    /// Loads all the tower secret keys (including the old ones) as stored in the database, alongside their ids.
+    pub(crate) fn load_encoded_tower_keys(&self) -> Vec<(i64, String)> {
+        let mut stmt = self.connection.prepare("SELECT id, key FROM keys").unwrap();
+        stmt.query_map([], |row| Ok((row.get(0).unwrap(), row.get(1).unwrap())))
+            .unwrap()
+            .map(|key| key.unwrap())
+            .collect()
+    }
+
+    /// Replaces the stored tower secret keys with re-encoded ones, given as `(id, old, new)` encodings.
+    ///
+    /// Keys are replaced atomically, and only if the stored ones have not changed since they were loaded (so no key is
+    /// left behind, nor encrypted with a different passphrase). Returns whether the keys have been replaced.
+    pub(crate) fn replace_encoded_tower_keys(&mut self, keys: &[(i64, String, String)]) -> bool {
+        let tx = self.connection.transaction().unwrap();
+        let stored_keys: usize = tx
+            .query_row("SELECT COUNT(*) FROM keys", [], |row| row.get(0))
+            .unwrap();
+        if stored_keys != keys.len() {
+            return false;
+        }
+
+        for (id, old_key, new_key) in keys {
+            let updated = tx
+                .execute(
+                    "UPDATE keys SET key = ?1 WHERE id = ?2 AND key = ?3",
+                    params![new_key, id, old_key],
+                )
+                .unwrap();
+            if updated != 1 {
+                return false;
+            }
+        }
+
+        tx.commit().unwrap();
+        true
+    }
+
+    /// Rotates the tower secret key, storing the new key alongside the handoff signed by the old one.
+    ///
+    /// Both are stored atomically, so the tower never ends up with a new key users cannot follow.
+    pub fn rotate_tower_key(
+        &mut self,
+        sk: &SecretKey,
+        handoff: &KeyHandoff,
+        passphrase: Option<&str>,
+    ) -> Result<(), Error> {
+        let tx = self.connection.transaction().unwrap();
+        tx.execute(
+            "INSERT INTO key_handoffs (old_tower_id, new_tower_id, activation_height, signature) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                handoff.old_tower_id().to_vec(),
+                handoff.new_tower_id().to_vec(),
+                handoff.activation_height(),
+                handoff.signature().unwrap()
+            ],
+        )
+        .map_err(Error::Unknown)?;
+        tx.execute(
+            "INSERT INTO keys (key) VALUES (?)",
+            params![cryptography::encode_secret_key(sk, passphrase)],
+        )
+        .map_err(Error::Unknown)?;
+
+        tx.commit().map_err(Error::Unknown)
+    }
+
+    /// Loads all the key handoffs of the tower from the database, oldest first.
+    pub(crate) fn load_key_handoffs(&self) -> Vec<KeyHandoff> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT old_tower_id, new_tower_id, activation_height, signature FROM key_handoffs ORDER BY id")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            let old_tower_id: Vec<u8> = row.get(0).unwrap();
+            let new_tower_id: Vec<u8> = row.get(1).unwrap();
+            let activation_height: u32 = row.get(2).unwrap();
+            let signature: String = row.get(3).unwrap();
+
+            Ok(KeyHandoff::with_signature(
+                TowerId::from_slice(&old_tower_id).unwrap(),
+                TowerId::from_slice(&new_tower_id).unwrap(),
+                activation_height,
+                signature,
+            ))
+        })
+        .unwrap()
+        .map(|handoff| handoff.unwrap())
+        .collect()
+    }
+
+    /// Opens a dedicated connection to the database to snapshot it from (see [SnapshotSource::snapshot]).
+    pub(crate) fn snapshot_source(&self) -> Result<SnapshotSource, SqliteError> {
+        let db_key = self.db_key.clone();
+        Ok(SnapshotSource {
+            connection: dbm::open_connection(
+                &self.db_path,
+                db_key.as_ref().map(|key| key.0.as_str()),
+            )?,
+            db_key,
         })
-        .ok()
     }
 }
 
+/// Re-encrypts some encoded tower secret keys (as loaded by [DBM::load_encoded_tower_keys]) with a new passphrase.
+///
+/// Returns the `(id, old, new)` encodings of every key, or an error if any of them cannot be decrypted using
+/// `old_passphrase`.
+pub(crate) fn reencrypt_tower_keys(
+    keys: Vec<(i64, String)>,
+    old_passphrase: Option<&str>,
+    new_passphrase: &str,
+) -> Result<Vec<(i64, String, String)>, KeyDecryptionError> {
+    keys.into_iter()
+        .map(|(id, key)| {
+            let sk = cryptography::decode_secret_key(&key, old_passphrase)?;
+            Ok((
+                id,
+                key,
+                cryptography::encode_secret_key(&sk, Some(new_passphrase)),
+            ))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashSet;
     use std::iter::FromIterator;
+    use tempdir::TempDir;
 
     use teos_common::cryptography::{get_random_bytes, get_random_keypair};
     use teos_common::test_utils::{get_random_locator, get_random_user_id};
@@ -767,40 +1844,106 @@ mod tests {
 
     impl DBM {
         pub(crate) fn in_memory() -> Result<Self, SqliteError> {
-            let connection = Connection::open_in_memory()?;
+            // Named in-memory databases can be opened more than once (e.g. to snapshot them)
+            let db_path = PathBuf::from(format!(
+                "file:{}?mode=memory&cache=shared",
+                hex::encode(get_random_bytes(16))
+            ));
+            let connection = Connection::open(&db_path)?;
             connection.execute("PRAGMA foreign_keys=1;", [])?;
-            let mut dbm = Self { connection };
+            let mut dbm = Self {
+                connection,
+                locator_filter: RefCell::new(LocatorFilter::new(0)),
+                locator_filter_rebuild: RefCell::new(None),
+                db_path,
+                db_key: None,
+            };
             dbm.create_tables(Vec::from_iter(TABLES))?;
 
             Ok(dbm)
         }
 
-        pub(crate) fn load_user(&self, user_id: UserId) -> Option<UserInfo> {
-            let key = user_id.to_vec();
+        pub(crate) fn load_all_users(&self) -> HashMap<UserId, UserInfo> {
+            let mut users = HashMap::new();
             let mut stmt = self
                 .connection
-                .prepare(
-                    "SELECT available_slots, subscription_start, subscription_expiry
-                        FROM users WHERE user_id=(?)",
-                )
+                .prepare("SELECT user_id, available_slots, subscription_start, subscription_expiry FROM users")
                 .unwrap();
-            stmt.query_row([&key], |row| {
-                let slots = row.get(0).unwrap();
-                let start = row.get(1).unwrap();
-                let expiry = row.get(2).unwrap();
-                Ok(UserInfo::new(slots, start, expiry))
-            })
-            .ok()
+            let mut rows = stmt.query([]).unwrap();
+
+            while let Ok(Some(row)) = rows.next() {
+                let raw_userid: Vec<u8> = row.get(0).unwrap();
+                let user_id = UserId::from_slice(&raw_userid).unwrap();
+                let slots = row.get(1).unwrap();
+                let start = row.get(2).unwrap();
+                let expiry = row.get(3).unwrap();
+
+                users.insert(user_id, UserInfo::new(slots, start, expiry));
+            }
+
+            users
+        }
+
+        pub(crate) fn finish_locator_filter_rebuild(&self) {
+            while self.locator_filter_rebuild.borrow().is_some() {
+                self.rebuild_locator_filter_step();
+            }
+        }
+
+        pub(crate) fn load_webhook(&self, user_id: UserId) -> Option<String> {
+            let mut stmt = self
+                .connection
+                .prepare("SELECT url FROM webhooks WHERE user_id=(?)")
+                .unwrap();
+
+            stmt.query_row([user_id.to_vec()], |row| row.get(0)).ok()
         }
     }
 
     #[test]
     fn test_create_tables() {
         let connection = Connection::open_in_memory().unwrap();
-        let mut dbm = DBM { connection };
+        let mut dbm = DBM {
+            connection,
+            locator_filter: RefCell::new(LocatorFilter::new(0)),
+            locator_filter_rebuild: RefCell::new(None),
+            db_path: PathBuf::new(),
+            db_key: None,
+        };
         dbm.create_tables(Vec::from_iter(TABLES)).unwrap();
     }
 
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_new_encrypted() {
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("teos_db.sql3");
+        let sk = get_random_keypair().0;
+        DBM::new(db_path.clone(), Some("db key"))
+            .unwrap()
+            .store_tower_key(&sk, None)
+            .unwrap();
+        assert!(dbm::is_encrypted_database(&db_path));
+
+        // The database can only be opened with the right key
+        assert!(DBM::new(db_path.clone(), None).is_err());
+        assert!(DBM::new(db_path.clone(), Some("wrong key")).is_err());
+        let dbm = DBM::new(db_path, Some("db key")).unwrap();
+        assert_eq!(dbm.load_tower_key(None), Ok(Some(sk)));
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn test_new_encrypted_unsupported() {
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("teos_db.sql3");
+        assert!(DBM::new(db_path.clone(), Some("db key")).is_err());
+
+        // Plain text databases are not affected
+        DBM::new(db_path.clone(), None).unwrap();
+        assert!(!dbm::is_encrypted_database(&db_path));
+    }
+
     #[test]
     fn test_store_load_user() {
         let dbm = DBM::in_memory().unwrap();
@@ -885,6 +2028,50 @@ mod tests {
         assert_eq!(dbm.load_all_users(), users);
     }
 
+    #[test]
+    fn test_load_user_ids() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_user_ids().is_empty());
+        assert_eq!(dbm.get_users_count(), 0);
+
+        let mut user_ids = HashSet::new();
+        for _ in 0..10 {
+            let user_id = get_random_user_id();
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+            dbm.store_user(user_id, &user).unwrap();
+            user_ids.insert(user_id);
+        }
+
+        assert_eq!(HashSet::from_iter(dbm.load_user_ids()), user_ids);
+        assert_eq!(dbm.get_users_count(), user_ids.len());
+    }
+
+    #[test]
+    fn test_load_expired_users() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let mut user_ids = Vec::new();
+        for i in 0..10 {
+            let user_id = get_random_user_id();
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY + i);
+            dbm.store_user(user_id, &user).unwrap();
+            user_ids.push(user_id);
+        }
+
+        assert!(dbm.load_expired_users(SUBSCRIPTION_EXPIRY - 1).is_empty());
+        for i in 0..10 {
+            assert_eq!(
+                HashSet::<UserId>::from_iter(dbm.load_expired_users(SUBSCRIPTION_EXPIRY + i)),
+                HashSet::from_iter(user_ids[..=i as usize].iter().cloned())
+            );
+        }
+
+        // Renewed subscriptions are not expired anymore
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY * 2);
+        dbm.update_user(user_ids[0], &user);
+        assert!(dbm.load_expired_users(SUBSCRIPTION_EXPIRY).is_empty());
+    }
+
     #[test]
     fn test_batch_remove_users() {
         let mut dbm = DBM::in_memory().unwrap();
@@ -1179,17 +2366,123 @@ mod tests {
     }
 
     #[test]
-    fn test_get_appointment_length() {
+    fn test_load_appointments_page() {
         let dbm = DBM::in_memory().unwrap();
-
         let user_id = get_random_user_id();
         let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
-        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
-
         dbm.store_user(user_id, &user).unwrap();
-        dbm.store_appointment(uuid, &appointment).unwrap();
 
-        assert_eq!(
+        // Appointments accepted in blocks 1 to 10. The even ones are triggered, half of them with a confirmed penalty
+        let mut uuids = Vec::new();
+        for i in 1..11 {
+            let (uuid, mut appointment) = generate_dummy_appointment_with_user(user_id, None);
+            appointment.start_block = i;
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            if i % 2 == 0 {
+                let status = if i % 4 == 0 {
+                    ConfirmationStatus::ConfirmedIn(i)
+                } else {
+                    ConfirmationStatus::InMempoolSince(i)
+                };
+                dbm.store_tracker(uuid, &get_random_tracker(user_id, status))
+                    .unwrap();
+            }
+            uuids.push(uuid);
+        }
+        uuids.sort_by_key(|uuid| uuid.to_vec());
+
+        // Entries are sorted by UUID and start after the cursor
+        let page: Vec<UUID> = dbm
+            .load_appointments_page(&AppointmentsFilter::default(), None, 4)
+            .into_iter()
+            .map(|(uuid, _)| uuid)
+            .collect();
+        assert_eq!(page, uuids[..4]);
+        let page: Vec<UUID> = dbm
+            .load_appointments_page(&AppointmentsFilter::default(), Some(uuids[3]), 100)
+            .into_iter()
+            .map(|(uuid, _)| uuid)
+            .collect();
+        assert_eq!(page, uuids[4..]);
+
+        for (filter, expected) in [
+            (
+                AppointmentsFilter {
+                    user_id: Some(user_id),
+                    ..Default::default()
+                },
+                10,
+            ),
+            (
+                AppointmentsFilter {
+                    user_id: Some(get_random_user_id()),
+                    ..Default::default()
+                },
+                0,
+            ),
+            (
+                AppointmentsFilter {
+                    start_block_from: Some(3),
+                    start_block_to: Some(6),
+                    ..Default::default()
+                },
+                4,
+            ),
+            (
+                AppointmentsFilter {
+                    responded: Some(false),
+                    ..Default::default()
+                },
+                5,
+            ),
+            (
+                AppointmentsFilter {
+                    responded: Some(true),
+                    ..Default::default()
+                },
+                5,
+            ),
+            (
+                AppointmentsFilter {
+                    confirmed: Some(true),
+                    ..Default::default()
+                },
+                2,
+            ),
+            (
+                AppointmentsFilter {
+                    confirmed: Some(false),
+                    start_block_from: Some(5),
+                    ..Default::default()
+                },
+                2,
+            ),
+        ] {
+            let page = dbm.load_appointments_page(&filter, None, 100);
+            assert_eq!(page.len(), expected, "{:?}", filter);
+            for (_, info) in page {
+                match info {
+                    AppointmentRecord::Appointment(_) => {
+                        assert_ne!(filter.responded, Some(true))
+                    }
+                    AppointmentRecord::Tracker(_) => assert_ne!(filter.responded, Some(false)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_appointment_length() {
+        let dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+
+        dbm.store_user(user_id, &user).unwrap();
+        dbm.store_appointment(uuid, &appointment).unwrap();
+
+        assert_eq!(
             dbm.get_appointment_length(uuid).unwrap(),
             appointment.inner.encrypted_blob.len()
         );
@@ -1267,6 +2560,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_batch_store_appointments() {
+        let mut dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        let mut user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Store an appointment the regular way so we can check it gets updated by the batch
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let mut modified_appointment = appointment;
+        modified_appointment.inner.encrypted_blob.reverse();
+
+        let mut appointments = HashMap::from_iter([(uuid, modified_appointment)]);
+        for _ in 0..10 {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            appointments.insert(uuid, appointment);
+        }
+
+        // The number of slots can be made up here, we only want to make sure it matches.
+        user.available_slots = 42;
+        let updated_users = HashMap::from_iter([(user_id, user)]);
+        dbm.batch_store_appointments(&appointments.values().collect::<Vec<_>>(), &updated_users)
+            .unwrap();
+
+        assert_eq!(dbm.load_appointments(None), appointments);
+        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+    }
+
+    #[test]
+    fn test_batch_store_appointments_missing_user() {
+        let mut dbm = DBM::in_memory().unwrap();
+
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // If any of the appointments cannot be stored, nothing is
+        let (_, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        let unknown_user_appointment = generate_dummy_appointment(None);
+        let mut updated_user = user;
+        updated_user.available_slots -= 2;
+
+        assert!(matches!(
+            dbm.batch_store_appointments(
+                &[&appointment, &unknown_user_appointment],
+                &HashMap::from_iter([(user_id, updated_user)])
+            ),
+            Err(Error::Unknown(_))
+        ));
+        assert!(dbm.load_appointments(None).is_empty());
+        assert_eq!(dbm.load_user(user_id).unwrap(), user);
+    }
+
     #[test]
     fn test_batch_remove_appointments_cascade() {
         let mut dbm = DBM::in_memory().unwrap();
@@ -1307,6 +2655,33 @@ mod tests {
         assert!(dbm.load_tracker(uuid).is_none());
     }
 
+    #[test]
+    fn test_remove_user_appointments() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let mut info = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &info).unwrap();
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        info.available_slots += 1;
+
+        // The appointments are removed, and the user and nonce updated, all at once
+        assert!(dbm.remove_user_appointments(user_id, 5, &[uuid], &info));
+        assert!(!dbm.appointment_exists(uuid));
+        assert_eq!(dbm.load_user(user_id).unwrap(), info);
+        assert_eq!(dbm.load_user_nonce(user_id), Some(5));
+
+        // Nothing is updated if the nonce is not greater than the last one used by the user
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let updated_info = UserInfo::new(0, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        assert!(!dbm.remove_user_appointments(user_id, 5, &[uuid], &updated_info));
+        assert!(dbm.appointment_exists(uuid));
+        assert_eq!(dbm.load_user(user_id).unwrap(), info);
+        assert_eq!(dbm.load_user_nonce(user_id), Some(5));
+    }
+
     #[test]
     fn test_batch_remove_nonexistent_appointments() {
         let mut dbm = DBM::in_memory().unwrap();
@@ -1394,6 +2769,140 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_batch_check_locators_exist_after_restart() {
+        // The locator filter is rebuilt from the stored appointments when the database is opened
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("teos_db.sql3");
+        let dbm = DBM::new(db_path.clone(), None).unwrap();
+
+        let appointment = generate_dummy_appointment(None);
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &user).unwrap();
+        dbm.store_appointment(appointment.uuid(), &appointment)
+            .unwrap();
+        drop(dbm);
+
+        let dbm = DBM::new(db_path, None).unwrap();
+        assert_eq!(dbm.locator_filter.borrow().len(), 1);
+        assert_eq!(
+            dbm.batch_check_locators_exist(vec![&appointment.locator(), &get_random_locator()]),
+            vec![appointment.locator()]
+        );
+    }
+
+    #[test]
+    fn test_locator_filter_rebuild() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Store way more appointments than the filter is initially sized for. The filter is rebuilt as it gets full,
+        // so none of the locators is missed
+        let appointments: Vec<_> = (0..5000)
+            .map(|_| generate_dummy_appointment_with_user(user_id, None).1)
+            .collect();
+        dbm.batch_store_appointments(&appointments.iter().collect::<Vec<_>>(), &HashMap::new())
+            .unwrap();
+        let locators: Vec<_> = appointments.iter().map(|a| a.locator()).collect();
+        assert_eq!(
+            dbm.batch_check_locators_exist(locators.iter().collect())
+                .len(),
+            locators.len()
+        );
+
+        // Removing most of the appointments (here, on cascade) makes the filter be rebuilt with the ones left
+        dbm.finish_locator_filter_rebuild();
+        dbm.batch_remove_users(&vec![user_id]);
+        dbm.finish_locator_filter_rebuild();
+        assert!(dbm.locator_filter.borrow().is_empty());
+        assert!(dbm
+            .batch_check_locators_exist(locators.iter().collect())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_locator_filter_remove_shared_locators() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        let user_ids = [get_random_user_id(), get_random_user_id()];
+        for user_id in user_ids {
+            dbm.store_user(user_id, &user).unwrap();
+        }
+
+        // Both users send appointments for the same dispute (and therefore locator), and one of them another one
+        let dispute_txid = get_random_tx().txid();
+        let appointments: Vec<_> = user_ids
+            .iter()
+            .map(|user_id| generate_dummy_appointment_with_user(*user_id, Some(&dispute_txid)).1)
+            .chain([generate_dummy_appointment_with_user(user_ids[0], None).1])
+            .collect();
+        for appointment in appointments.iter() {
+            dbm.store_appointment(appointment.uuid(), appointment)
+                .unwrap();
+        }
+        assert_eq!(dbm.locator_filter.borrow().estimated_len(), 2);
+
+        // Locators are only accounted as removed once no appointment holds them anymore
+        dbm.remove_appointment(appointments[0].uuid());
+        assert_eq!(dbm.locator_filter.borrow().estimated_len(), 2);
+        dbm.batch_remove_appointments(&vec![appointments[1].uuid()], &HashMap::new());
+        assert_eq!(dbm.locator_filter.borrow().estimated_len(), 1);
+
+        // And they are counted once no matter how many appointments (or users) shared them
+        let appointment = generate_dummy_appointment_with_user(user_ids[1], None).1;
+        for user_id in user_ids {
+            let mut shared = appointment.clone();
+            shared.user_id = user_id;
+            dbm.store_appointment(shared.uuid(), &shared).unwrap();
+        }
+        assert_eq!(dbm.locator_filter.borrow().estimated_len(), 2);
+        dbm.batch_remove_users(&user_ids.to_vec());
+        assert_eq!(dbm.locator_filter.borrow().estimated_len(), 0);
+    }
+
+    #[test]
+    fn test_locator_filter_rebuild_step() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Overfill the filter so it needs to be rebuilt
+        let appointments: Vec<_> = (0..LOCATOR_FILTER_REBUILD_STEP * 3)
+            .map(|_| generate_dummy_appointment_with_user(user_id, None).1)
+            .collect();
+        dbm.batch_store_appointments(&appointments.iter().collect::<Vec<_>>(), &HashMap::new())
+            .unwrap();
+
+        // The rebuild is done a step at a time, so it is still going on
+        let last_rowid = dbm
+            .locator_filter_rebuild
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .last_rowid;
+        assert_eq!(last_rowid, LOCATOR_FILTER_REBUILD_STEP as i64);
+
+        // Appointments stored meanwhile are not missed by the rebuilt filter, even though they come after
+        let appointment = generate_dummy_appointment_with_user(user_id, None).1;
+        dbm.store_appointment(appointment.uuid(), &appointment)
+            .unwrap();
+        dbm.finish_locator_filter_rebuild();
+        let locators: Vec<_> = appointments
+            .iter()
+            .chain([&appointment])
+            .map(|a| a.locator())
+            .collect();
+        assert_eq!(
+            dbm.batch_check_locators_exist(locators.iter().collect())
+                .len(),
+            locators.len()
+        );
+        assert!(!dbm.locator_filter.borrow().needs_rebuild());
+    }
+
     #[test]
     fn test_store_load_tracker() {
         let dbm = DBM::in_memory().unwrap();
@@ -1553,6 +3062,39 @@ mod tests {
         assert_eq!(dbm.load_trackers(Some(locator)), trackers);
     }
 
+    #[test]
+    fn test_load_trackers_by_user_ids() {
+        let dbm = DBM::in_memory().unwrap();
+        let mut user_ids = Vec::new();
+        let mut trackers = HashMap::new();
+
+        for i in 0..10 {
+            let user_id = get_random_user_id();
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+            dbm.store_user(user_id, &user).unwrap();
+
+            // Give each user a couple of trackers and only ask for half of the users.
+            for _ in 0..2 {
+                let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+                let tracker = get_random_tracker(user_id, ConfirmationStatus::ConfirmedIn(42));
+                dbm.store_appointment(uuid, &appointment).unwrap();
+                dbm.store_tracker(uuid, &tracker).unwrap();
+                if i % 2 == 0 {
+                    trackers.insert(uuid, tracker);
+                }
+            }
+            if i % 2 == 0 {
+                user_ids.push(user_id);
+            }
+        }
+
+        assert_eq!(dbm.load_trackers_by_user_ids(&user_ids), trackers);
+        assert!(dbm.load_trackers_by_user_ids(&[]).is_empty());
+        assert!(dbm
+            .load_trackers_by_user_ids(&[get_random_user_id()])
+            .is_empty());
+    }
+
     #[test]
     fn test_load_trackers_with_confirmation_status_in_mempool() {
         let dbm = DBM::in_memory().unwrap();
@@ -1662,6 +3204,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_load_trackers_confirmed_up_to() {
+        let dbm = DBM::in_memory().unwrap();
+        let mut confirmed = Vec::new();
+
+        for status in [
+            ConfirmationStatus::ConfirmedIn(10),
+            ConfirmationStatus::ConfirmedIn(20),
+            ConfirmationStatus::InMempoolSince(10),
+        ] {
+            let user_id = get_random_user_id();
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+            dbm.store_user(user_id, &user).unwrap();
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            dbm.store_tracker(uuid, &get_random_tracker(user_id, status))
+                .unwrap();
+            if let ConfirmationStatus::ConfirmedIn(_) = status {
+                confirmed.push(uuid);
+            }
+        }
+
+        // Trackers in mempool are never pulled, no matter their height
+        assert!(dbm.load_trackers_confirmed_up_to(9).is_empty());
+        assert_eq!(dbm.load_trackers_confirmed_up_to(15), confirmed[..1]);
+        assert_eq!(
+            HashSet::<UUID>::from_iter(dbm.load_trackers_confirmed_up_to(20)),
+            HashSet::from_iter(confirmed)
+        );
+    }
+
     #[test]
     fn test_load_trackers_with_confirmation_status_bad_status() {
         let dbm = DBM::in_memory().unwrap();
@@ -1680,7 +3253,131 @@ mod tests {
     }
 
     #[test]
-    fn test_load_penalties_summaries() {
+    fn test_load_uuids_by_penalty_txids() {
+        let dbm = DBM::in_memory().unwrap();
+        let mut penalty_txids = HashMap::new();
+
+        for _ in 0..10 {
+            let user_id = get_random_user_id();
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+            dbm.store_user(user_id, &user).unwrap();
+
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            let tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(42));
+            dbm.store_tracker(uuid, &tracker).unwrap();
+            penalty_txids.insert(uuid, tracker.penalty_tx.txid());
+        }
+
+        // Only the trackers which penalty is in the given set are returned
+        let known_uuids: HashSet<UUID> = penalty_txids.keys().take(5).cloned().collect();
+        let unknown_txids: Vec<Txid> = (0..5).map(|_| get_random_tx().txid()).collect();
+        let txids = known_uuids
+            .iter()
+            .map(|uuid| &penalty_txids[uuid])
+            .chain(unknown_txids.iter())
+            .collect();
+        assert_eq!(
+            HashSet::from_iter(dbm.load_uuids_by_penalty_txids(txids)),
+            known_uuids
+        );
+        assert!(dbm
+            .load_uuids_by_penalty_txids(unknown_txids.iter().collect())
+            .is_empty());
+
+        // The index follows the trackers when they are removed
+        let mut dbm = dbm;
+        let removed_uuid = *known_uuids.iter().next().unwrap();
+        dbm.batch_remove_appointments(&vec![removed_uuid], &HashMap::new());
+        assert!(dbm
+            .load_uuids_by_penalty_txids(vec![&penalty_txids[&removed_uuid]])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_index_penalty_txids() {
+        // Trackers missing from the penalty txid index (e.g. stored by an older version) are indexed on load
+        let tmp_dir = TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("teos_db.sql3");
+        let dbm = DBM::new(db_path.clone(), None).unwrap();
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &user).unwrap();
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let tracker =
+            get_random_tracker(appointment.user_id, ConfirmationStatus::InMempoolSince(42));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+        dbm.connection
+            .execute("DELETE FROM penalty_txids", [])
+            .unwrap();
+        let penalty_txid = tracker.penalty_tx.txid();
+        assert!(dbm
+            .load_uuids_by_penalty_txids(vec![&penalty_txid])
+            .is_empty());
+        drop(dbm);
+
+        let dbm = DBM::new(db_path, None).unwrap();
+        assert_eq!(
+            dbm.load_uuids_by_penalty_txids(vec![&penalty_txid]),
+            vec![uuid]
+        );
+    }
+
+    #[test]
+    fn test_index_penalty_txids_invalid_penalty() {
+        // Trackers whose penalty cannot be deserialized make indexing fail instead of panicking
+        let dbm = DBM::in_memory().unwrap();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(appointment.user_id, &user).unwrap();
+        dbm.store_appointment(uuid, &appointment).unwrap();
+        let tracker =
+            get_random_tracker(appointment.user_id, ConfirmationStatus::InMempoolSince(42));
+        dbm.store_tracker(uuid, &tracker).unwrap();
+        dbm.connection
+            .execute("DELETE FROM penalty_txids", [])
+            .unwrap();
+        dbm.connection
+            .execute("UPDATE trackers SET penalty_tx=(?1)", [vec![0u8; 3]])
+            .unwrap();
+
+        assert!(matches!(
+            dbm.index_penalty_txids(),
+            Err(SqliteError::FromSqlConversionFailure(1, Type::Blob, _))
+        ));
+    }
+
+    #[test]
+    fn test_count_unconfirmed_penalties() {
+        let dbm = DBM::in_memory().unwrap();
+        let mut unconfirmed = Vec::new();
+
+        for i in 0..10 {
+            let (uuid, appointment) =
+                generate_dummy_appointment_with_user(get_random_user_id(), None);
+            let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+            dbm.store_user(appointment.user_id, &user).unwrap();
+            dbm.store_appointment(uuid, &appointment).unwrap();
+
+            let status = if i % 2 == 0 {
+                unconfirmed.push(uuid);
+                ConfirmationStatus::InMempoolSince(i)
+            } else {
+                ConfirmationStatus::ConfirmedIn(i)
+            };
+            dbm.store_tracker(uuid, &get_random_tracker(appointment.user_id, status))
+                .unwrap();
+        }
+
+        assert_eq!(dbm.count_unconfirmed_penalties(&HashSet::new()), 5);
+        // Excluded trackers are left out, whether their penalty is confirmed or not
+        let excluded = HashSet::from_iter([unconfirmed[0], unconfirmed[1], generate_uuid()]);
+        assert_eq!(dbm.count_unconfirmed_penalties(&excluded), 3);
+    }
+
+    #[test]
+    fn test_load_unconfirmed_penalties_summaries() {
         let dbm = DBM::in_memory().unwrap();
         let n_trackers = 100;
         let mut penalties_summaries = HashMap::new();
@@ -1706,11 +3403,135 @@ mod tests {
             let tracker = get_random_tracker(user_id, status);
             dbm.store_tracker(uuid, &tracker).unwrap();
 
+            // Only penalties that are not confirmed yet are loaded
+            if !matches!(status, ConfirmationStatus::ConfirmedIn(_)) {
+                penalties_summaries
+                    .insert(uuid, PenaltySummary::new(tracker.penalty_tx.txid(), status));
+            }
+        }
+
+        assert_eq!(
+            dbm.load_unconfirmed_penalties_summaries(),
             penalties_summaries
-                .insert(uuid, PenaltySummary::new(tracker.penalty_tx.txid(), status));
+        );
+    }
+
+    #[test]
+    fn test_store_load_remove_webhook() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+
+        // Webhooks can only be stored for existing users
+        assert!(matches!(
+            dbm.store_webhook(user_id, "http://localhost/hook"),
+            Err(Error::MissingForeignKey)
+        ));
+
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        for url in ["http://localhost/hook", "https://localhost/new_hook"] {
+            dbm.store_webhook(user_id, url).unwrap();
+            assert_eq!(dbm.load_webhook(user_id).unwrap(), url);
         }
 
-        assert_eq!(dbm.load_penalties_summaries(), penalties_summaries);
+        dbm.remove_webhook(user_id).unwrap();
+        assert!(dbm.load_webhook(user_id).is_none());
+        assert!(matches!(dbm.remove_webhook(user_id), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn test_store_load_notifications() {
+        let dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+
+        // Notifications can only be stored for users with a webhook
+        assert!(matches!(
+            dbm.store_notification(user_id, "{}", 0),
+            Err(Error::MissingForeignKey)
+        ));
+        assert!(dbm.get_next_notification_time().is_none());
+
+        let url = "http://localhost/hook";
+        dbm.store_webhook(user_id, url).unwrap();
+        dbm.store_notification(user_id, "first", 10).unwrap();
+        dbm.store_notification(user_id, "second", 20).unwrap();
+        assert_eq!(dbm.get_next_notification_time(), Some(10));
+
+        // Only the due ones are loaded
+        let due = dbm.load_due_notifications(15);
+        assert_eq!(due.len(), 1);
+        assert_eq!(
+            due[0],
+            QueuedNotification {
+                id: due[0].id,
+                url: url.to_owned(),
+                payload: "first".to_owned(),
+                attempts: 0
+            }
+        );
+
+        // Updating the attempts reschedules the notification
+        dbm.update_notification_attempts(due[0].id, 1, 30);
+        assert_eq!(dbm.get_next_notification_time(), Some(20));
+        let due = dbm.load_due_notifications(30);
+        assert_eq!(
+            due.iter()
+                .map(|n| (n.payload.as_str(), n.attempts))
+                .collect::<Vec<_>>(),
+            vec![("first", 1), ("second", 0)]
+        );
+
+        // Notifications can be removed
+        dbm.remove_notification(due[0].id);
+        assert_eq!(dbm.load_due_notifications(30).len(), 1);
+
+        // And they are also removed alongside the webhook
+        dbm.remove_webhook(user_id).unwrap();
+        assert!(dbm.load_due_notifications(u32::MAX as u64).is_empty());
+    }
+
+    #[test]
+    fn test_store_load_events() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let user_id = get_random_user_id();
+        let user = UserInfo::new(AVAILABLE_SLOTS, SUBSCRIPTION_START, SUBSCRIPTION_EXPIRY);
+        dbm.store_user(user_id, &user).unwrap();
+        assert!(dbm.load_events(None, None).is_empty());
+
+        // Store a couple of events for two different appointments
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            let tracker = get_random_tracker(user_id, ConfirmationStatus::InMempoolSince(42));
+            dbm.store_tracker(uuid, &tracker).unwrap();
+
+            for event in [
+                tracker.get_event(uuid, EventKind::PenaltyBroadcast, 42),
+                tracker
+                    .get_event(uuid, EventKind::PenaltyRejected, 43)
+                    .with_rejection_reason(rpc_errors::RPC_VERIFY_REJECTED),
+            ] {
+                dbm.store_event(&event);
+                events.push(event);
+            }
+        }
+
+        // Events are loaded in the order they were stored, and can be filtered
+        assert_eq!(dbm.load_events(None, None), events);
+        assert_eq!(dbm.load_events(Some(events[0].uuid), None), events[..2]);
+        assert_eq!(dbm.load_events(None, Some(events[2].locator)), events[2..]);
+        assert_eq!(
+            dbm.load_events(Some(events[0].uuid), Some(events[2].locator)),
+            Vec::new()
+        );
+
+        // Events outlive the data they refer to
+        dbm.batch_remove_users(&vec![user_id]);
+        assert!(dbm.load_trackers(None).is_empty());
+        assert_eq!(dbm.load_events(None, None), events);
     }
 
     #[test]
@@ -1738,11 +3559,131 @@ mod tests {
     fn test_store_load_tower_key() {
         let dbm = DBM::in_memory().unwrap();
 
-        assert!(dbm.load_tower_key().is_none());
+        assert_eq!(dbm.load_tower_key(None), Ok(None));
         for _ in 0..7 {
             let sk = get_random_keypair().0;
-            dbm.store_tower_key(&sk).unwrap();
-            assert_eq!(dbm.load_tower_key().unwrap(), sk);
+            dbm.store_tower_key(&sk, None).unwrap();
+            assert_eq!(dbm.load_tower_key(None), Ok(Some(sk)));
+            assert!(!dbm.is_tower_key_encrypted());
+        }
+    }
+
+    #[test]
+    fn test_store_load_encrypted_tower_key() {
+        let dbm = DBM::in_memory().unwrap();
+        assert!(!dbm.is_tower_key_encrypted());
+
+        let sk = get_random_keypair().0;
+        dbm.store_tower_key(&sk, Some("passphrase")).unwrap();
+        assert!(dbm.is_tower_key_encrypted());
+        assert_eq!(dbm.load_tower_key(Some("passphrase")), Ok(Some(sk)));
+
+        // The key cannot be loaded without the right passphrase
+        assert_eq!(
+            dbm.load_tower_key(None),
+            Err(KeyDecryptionError::MissingPassphrase)
+        );
+        assert_eq!(
+            dbm.load_tower_key(Some("wrong passphrase")),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+    }
+
+    #[test]
+    fn test_change_key_passphrase() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let old_sk = get_random_keypair().0;
+        let sk = get_random_keypair().0;
+        dbm.store_tower_key(&old_sk, None).unwrap();
+        dbm.store_tower_key(&sk, None).unwrap();
+
+        // Plain keys get encrypted
+        dbm.change_key_passphrase(None, "passphrase").unwrap();
+        assert!(dbm.is_tower_key_encrypted());
+        assert_eq!(dbm.load_tower_key(Some("passphrase")), Ok(Some(sk)));
+
+        // Changing the passphrase requires the current one, and nothing changes otherwise
+        assert_eq!(
+            dbm.change_key_passphrase(Some("wrong passphrase"), "new passphrase"),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+        assert_eq!(dbm.load_tower_key(Some("passphrase")), Ok(Some(sk)));
+        dbm.change_key_passphrase(Some("passphrase"), "new passphrase")
+            .unwrap();
+        assert_eq!(dbm.load_tower_key(Some("new passphrase")), Ok(Some(sk)));
+
+        // Old keys are re-encrypted too
+        let keys = dbm
+            .connection
+            .prepare("SELECT key FROM keys ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(|key| key.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cryptography::decode_secret_key(&keys[0], Some("new passphrase")),
+            Ok(old_sk)
+        );
+    }
+
+    #[test]
+    fn test_replace_encoded_tower_keys() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let sk = get_random_keypair().0;
+        dbm.store_tower_key(&sk, None).unwrap();
+
+        let keys = reencrypt_tower_keys(dbm.load_encoded_tower_keys(), None, "passphrase").unwrap();
+
+        // Keys are not replaced if they changed since they were loaded, be it because a new key was stored...
+        let new_sk = get_random_keypair().0;
+        dbm.store_tower_key(&new_sk, None).unwrap();
+        assert!(!dbm.replace_encoded_tower_keys(&keys));
+        assert!(!dbm.is_tower_key_encrypted());
+
+        // ... or because they were re-encrypted
+        let keys = reencrypt_tower_keys(dbm.load_encoded_tower_keys(), None, "passphrase").unwrap();
+        dbm.change_key_passphrase(None, "another passphrase")
+            .unwrap();
+        assert!(!dbm.replace_encoded_tower_keys(&keys));
+        assert_eq!(
+            dbm.load_tower_key(Some("another passphrase")),
+            Ok(Some(new_sk))
+        );
+
+        // Otherwise, they are
+        let keys = reencrypt_tower_keys(
+            dbm.load_encoded_tower_keys(),
+            Some("another passphrase"),
+            "passphrase",
+        )
+        .unwrap();
+        assert!(dbm.replace_encoded_tower_keys(&keys));
+        assert_eq!(dbm.load_tower_key(Some("passphrase")), Ok(Some(new_sk)));
+    }
+
+    #[test]
+    fn test_rotate_tower_key() {
+        let mut dbm = DBM::in_memory().unwrap();
+        assert!(dbm.load_key_handoffs().is_empty());
+
+        let (mut sk, pk) = get_random_keypair();
+        dbm.store_tower_key(&sk, None).unwrap();
+        let mut tower_id = TowerId(pk);
+        let mut handoffs = Vec::new();
+
+        for height in [100, 200, 300] {
+            let (new_sk, new_pk) = get_random_keypair();
+            let mut handoff = KeyHandoff::new(tower_id, TowerId(new_pk), height);
+            handoff.sign(&sk);
+            dbm.rotate_tower_key(&new_sk, &handoff, None).unwrap();
+            handoffs.push(handoff);
+
+            assert_eq!(dbm.load_tower_key(None), Ok(Some(new_sk)));
+            assert_eq!(dbm.load_key_handoffs(), handoffs);
+            assert!(dbm.load_key_handoffs().iter().all(|h| h.verify()));
+            sk = new_sk;
+            tower_id = TowerId(new_pk);
         }
     }
 }
@@ -0,0 +1,289 @@
+//! Logic related to the tower audit history.
+//!
+//! Appointment data is deleted once the tower is done with it (e.g. when a penalty gets irrevocably resolved or
+//! a subscription gets outdated), so the steps taken to respond to a breach are recorded in an append-only history
+//! that outlives it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::Txid;
+
+use teos_common::appointment::Locator;
+use teos_common::receipts::AppointmentSummary;
+use teos_common::UserId;
+
+use crate::dbm::DBM;
+use crate::extended_appointment::UUID;
+use crate::feed::Feed;
+use crate::notifier::now;
+use crate::protos as msgs;
+
+/// The events recorded in the audit history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// The dispute transaction of the appointment has been seen on chain.
+    BreachDetected,
+    /// The appointment could not be decrypted using the dispute transaction.
+    DecryptionFailed,
+    /// The penalty transaction has been accepted by the network.
+    PenaltyBroadcast,
+    /// The penalty transaction has been rejected by the network.
+    PenaltyRejected,
+    /// The penalty transaction has been confirmed.
+    PenaltyConfirmed,
+    /// The penalty transaction is [irrevocably resolved](teos_common::constants::IRREVOCABLY_RESOLVED).
+    Completed,
+    /// The owner subscription got outdated while the penalty was being tracked.
+    Outdated,
+}
+
+impl EventKind {
+    /// Whether this event closes the response to a breach (no further events are expected).
+    fn is_final(&self) -> bool {
+        matches!(
+            self,
+            EventKind::DecryptionFailed
+                | EventKind::PenaltyRejected
+                | EventKind::Completed
+                | EventKind::Outdated
+        )
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            EventKind::BreachDetected => "breach_detected",
+            EventKind::DecryptionFailed => "decryption_failed",
+            EventKind::PenaltyBroadcast => "penalty_broadcast",
+            EventKind::PenaltyRejected => "penalty_rejected",
+            EventKind::PenaltyConfirmed => "penalty_confirmed",
+            EventKind::Completed => "completed",
+            EventKind::Outdated => "outdated",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "breach_detected" => Ok(EventKind::BreachDetected),
+            "decryption_failed" => Ok(EventKind::DecryptionFailed),
+            "penalty_broadcast" => Ok(EventKind::PenaltyBroadcast),
+            "penalty_rejected" => Ok(EventKind::PenaltyRejected),
+            "penalty_confirmed" => Ok(EventKind::PenaltyConfirmed),
+            "completed" => Ok(EventKind::Completed),
+            "outdated" => Ok(EventKind::Outdated),
+            _ => Err(format!("Unknown event: {s}")),
+        }
+    }
+}
+
+/// An entry of the audit history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    /// The [UUID] of the appointment the event refers to.
+    pub uuid: UUID,
+    /// The user the appointment belongs to.
+    pub user_id: UserId,
+    /// The locator of the appointment.
+    pub locator: Locator,
+    /// What happened.
+    pub kind: EventKind,
+    /// The block height the event was recorded at (or the height of the block it refers to for confirmations).
+    pub height: u32,
+    /// The dispute transaction id for breaches, and the penalty transaction id otherwise (if known).
+    pub txid: Option<Txid>,
+    /// The `bitcoind` error code if the penalty transaction was rejected.
+    pub rejection_reason: Option<i32>,
+    /// The UNIX timestamp (in seconds) the event was recorded at.
+    pub timestamp: u64,
+}
+
+impl Event {
+    /// Creates a new [Event] instance, timestamped at the current time.
+    pub fn new(
+        uuid: UUID,
+        user_id: UserId,
+        locator: Locator,
+        kind: EventKind,
+        height: u32,
+        txid: Option<Txid>,
+    ) -> Self {
+        Event {
+            uuid,
+            user_id,
+            locator,
+            kind,
+            height,
+            txid,
+            rejection_reason: None,
+            timestamp: now(),
+        }
+    }
+
+    /// Sets the reason why the penalty transaction was rejected.
+    pub fn with_rejection_reason(self, reason: i32) -> Self {
+        Event {
+            rejection_reason: Some(reason),
+            ..self
+        }
+    }
+}
+
+impl From<Event> for msgs::Event {
+    fn from(e: Event) -> Self {
+        msgs::Event {
+            uuid: e.uuid.to_vec(),
+            user_id: e.user_id.to_vec(),
+            locator: e.locator.to_vec(),
+            event: e.kind.to_string(),
+            height: e.height,
+            txid: e.txid.map_or_else(Vec::new, |txid| txid.to_vec()),
+            rejection_reason: e.rejection_reason.unwrap_or_default(),
+            timestamp: e.timestamp,
+        }
+    }
+}
+
+/// Appends an [Event] to the audit history and publishes it to the live [Feed].
+pub(crate) fn record_event(dbm: &DBM, feed: &Feed, event: Event) {
+    dbm.store_event(&event);
+    feed.publish(event.into());
+}
+
+/// Builds an (unsigned) [AppointmentSummary] out of the events of a given appointment (in the order they were recorded).
+///
+/// Returns [None] if the appointment was never breached or the response to the breach has not finished yet.
+pub(crate) fn summarize(events: &[Event]) -> Option<AppointmentSummary> {
+    // Appointments can be sent again after being responded to, so only the last response is considered.
+    let start = events
+        .iter()
+        .rposition(|e| e.kind == EventKind::BreachDetected)?;
+    let response = &events[start..];
+    let breach = &response[0];
+    let last = response.last().filter(|e| e.kind.is_final())?;
+    let penalty_txid = response[1..].iter().rev().find_map(|e| e.txid);
+    let confirmation_height = response
+        .iter()
+        .rev()
+        .find(|e| e.kind == EventKind::PenaltyConfirmed)
+        .map(|e| e.height);
+
+    Some(AppointmentSummary::new(
+        breach.locator,
+        breach.txid?,
+        penalty_txid,
+        breach.height,
+        confirmation_height,
+        last.kind.to_string(),
+        last.height,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::test_utils::{generate_uuid, get_random_tx};
+
+    const BREACH_HEIGHT: u32 = 100;
+
+    fn get_event(kind: EventKind, height: u32, txid: Option<Txid>) -> Event {
+        Event::new(
+            generate_uuid(),
+            get_random_user_id(),
+            Locator::new(get_random_tx().txid()),
+            kind,
+            height,
+            txid,
+        )
+    }
+
+    #[test]
+    fn test_event_kind_from_str() {
+        for kind in [
+            EventKind::BreachDetected,
+            EventKind::DecryptionFailed,
+            EventKind::PenaltyBroadcast,
+            EventKind::PenaltyRejected,
+            EventKind::PenaltyConfirmed,
+            EventKind::Completed,
+            EventKind::Outdated,
+        ] {
+            assert_eq!(EventKind::from_str(&kind.to_string()), Ok(kind));
+        }
+        assert!(EventKind::from_str("unknown").is_err());
+    }
+
+    #[test]
+    fn test_summarize() {
+        let dispute_txid = get_random_tx().txid();
+        let penalty_txid = get_random_tx().txid();
+        let breach = get_event(EventKind::BreachDetected, BREACH_HEIGHT, Some(dispute_txid));
+        let mut events = vec![
+            breach.clone(),
+            get_event(
+                EventKind::PenaltyBroadcast,
+                BREACH_HEIGHT,
+                Some(penalty_txid),
+            ),
+        ];
+
+        // The response to the breach is not over yet
+        assert_eq!(summarize(&[]), None);
+        assert_eq!(summarize(&events), None);
+
+        events.push(get_event(
+            EventKind::PenaltyConfirmed,
+            BREACH_HEIGHT + 1,
+            Some(penalty_txid),
+        ));
+        events.push(get_event(
+            EventKind::Completed,
+            BREACH_HEIGHT + 101,
+            Some(penalty_txid),
+        ));
+        assert_eq!(
+            summarize(&events),
+            Some(AppointmentSummary::new(
+                breach.locator,
+                dispute_txid,
+                Some(penalty_txid),
+                BREACH_HEIGHT,
+                Some(BREACH_HEIGHT + 1),
+                "completed".to_owned(),
+                BREACH_HEIGHT + 101,
+            ))
+        );
+    }
+
+    #[test]
+    fn test_summarize_decryption_failed() {
+        let dispute_txid = get_random_tx().txid();
+        let breach = get_event(EventKind::BreachDetected, BREACH_HEIGHT, Some(dispute_txid));
+        let events = vec![
+            breach.clone(),
+            get_event(EventKind::DecryptionFailed, BREACH_HEIGHT, None),
+        ];
+
+        assert_eq!(
+            summarize(&events),
+            Some(AppointmentSummary::new(
+                breach.locator,
+                dispute_txid,
+                None,
+                BREACH_HEIGHT,
+                None,
+                "decryption_failed".to_owned(),
+                BREACH_HEIGHT,
+            ))
+        );
+    }
+}
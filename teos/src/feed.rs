@@ -0,0 +1,375 @@
+//! Logic related to the live event feed of the tower.
+//!
+//! Components publish what happens in the tower (users registering, appointments being accepted and triggered, penalties
+//! being broadcast and confirmed, reorgs, `bitcoind` going up and down, ...) to a broadcast channel that operators can
+//! subscribe to through the private API. Publishing never blocks: events are dropped if nobody is subscribed, and
+//! subscribers that fall too far behind miss the oldest events. Internal components that cannot afford to miss events
+//! (like the [Alerter](crate::alerts::Alerter)) subscribe losslessly to the kinds of events they care about instead.
+//! Each tower owns its own [Feed], which is handed to the components publishing to it.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tokio::sync::{broadcast, mpsc};
+
+use bitcoin::{BlockHash, Txid};
+
+use teos_common::appointment::Locator;
+use teos_common::UserId;
+
+use crate::events::{Event, EventKind};
+use crate::extended_appointment::UUID;
+use crate::notifier::now;
+use crate::protos as msgs;
+
+/// Number of events a subscriber can fall behind before starting to miss them.
+const FEED_CAPACITY: usize = 1024;
+
+/// The types of events published to the feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeedEventKind {
+    /// A new user has registered with the tower.
+    NewUser,
+    /// An appointment has been accepted by the tower.
+    NewAppointment,
+    /// An appointment has been triggered or responded to (see [EventKind]).
+    Appointment(EventKind),
+    /// A penalty transaction sitting in mempool has missed a confirmation.
+    MissedConfirmation,
+    /// A block has been disconnected from the chain.
+    Reorg,
+    /// The penalty transaction of a tracker has been reorged out of the chain.
+    TrackerReorged,
+    /// `bitcoind` is reachable again.
+    BitcoindReachable,
+    /// `bitcoind` is not reachable anymore.
+    BitcoindUnreachable,
+}
+
+impl fmt::Display for FeedEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeedEventKind::NewUser => write!(f, "new_user"),
+            FeedEventKind::NewAppointment => write!(f, "new_appointment"),
+            FeedEventKind::Appointment(kind) => write!(f, "{kind}"),
+            FeedEventKind::MissedConfirmation => write!(f, "missed_confirmation"),
+            FeedEventKind::Reorg => write!(f, "reorg"),
+            FeedEventKind::TrackerReorged => write!(f, "tracker_reorged"),
+            FeedEventKind::BitcoindReachable => write!(f, "bitcoind_reachable"),
+            FeedEventKind::BitcoindUnreachable => write!(f, "bitcoind_unreachable"),
+        }
+    }
+}
+
+impl FromStr for FeedEventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new_user" => Ok(FeedEventKind::NewUser),
+            "new_appointment" => Ok(FeedEventKind::NewAppointment),
+            "missed_confirmation" => Ok(FeedEventKind::MissedConfirmation),
+            "reorg" => Ok(FeedEventKind::Reorg),
+            "tracker_reorged" => Ok(FeedEventKind::TrackerReorged),
+            "bitcoind_reachable" => Ok(FeedEventKind::BitcoindReachable),
+            "bitcoind_unreachable" => Ok(FeedEventKind::BitcoindUnreachable),
+            _ => EventKind::from_str(s).map(FeedEventKind::Appointment),
+        }
+    }
+}
+
+/// An event published to the feed. Fields that do not apply to a given [FeedEventKind] are left empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FeedEvent {
+    /// What happened.
+    pub kind: FeedEventKind,
+    /// The UNIX timestamp (in seconds) the event was published at.
+    pub timestamp: u64,
+    /// The user the event refers to.
+    pub user_id: Option<UserId>,
+    /// The [UUID] of the appointment the event refers to.
+    pub uuid: Option<UUID>,
+    /// The locator of the appointment the event refers to.
+    pub locator: Option<Locator>,
+    /// The dispute transaction id for breaches, and the penalty transaction id otherwise.
+    pub txid: Option<Txid>,
+    /// The block height the event refers to.
+    pub height: Option<u32>,
+    /// The hash of the disconnected block for reorgs.
+    pub block_hash: Option<BlockHash>,
+    /// The `bitcoind` error code if a penalty transaction was rejected.
+    pub rejection_reason: Option<i32>,
+    /// The number of confirmations a penalty transaction has missed so far.
+    pub missed_confirmations: Option<u32>,
+}
+
+impl FeedEvent {
+    fn new(kind: FeedEventKind) -> Self {
+        FeedEvent {
+            kind,
+            timestamp: now(),
+            user_id: None,
+            uuid: None,
+            locator: None,
+            txid: None,
+            height: None,
+            block_hash: None,
+            rejection_reason: None,
+            missed_confirmations: None,
+        }
+    }
+
+    /// Creates a [FeedEventKind::NewUser] event.
+    pub fn new_user(user_id: UserId) -> Self {
+        FeedEvent {
+            user_id: Some(user_id),
+            ..FeedEvent::new(FeedEventKind::NewUser)
+        }
+    }
+
+    /// Creates a [FeedEventKind::NewAppointment] event.
+    pub fn new_appointment(uuid: UUID, user_id: UserId, locator: Locator) -> Self {
+        FeedEvent {
+            user_id: Some(user_id),
+            uuid: Some(uuid),
+            locator: Some(locator),
+            ..FeedEvent::new(FeedEventKind::NewAppointment)
+        }
+    }
+
+    /// Creates a [FeedEventKind::MissedConfirmation] event.
+    pub fn missed_confirmation(uuid: UUID, penalty_txid: Txid, missed_confirmations: u32) -> Self {
+        FeedEvent {
+            uuid: Some(uuid),
+            txid: Some(penalty_txid),
+            missed_confirmations: Some(missed_confirmations),
+            ..FeedEvent::new(FeedEventKind::MissedConfirmation)
+        }
+    }
+
+    /// Creates a [FeedEventKind::TrackerReorged] event.
+    pub fn tracker_reorged(uuid: UUID, height: u32) -> Self {
+        FeedEvent {
+            uuid: Some(uuid),
+            height: Some(height),
+            ..FeedEvent::new(FeedEventKind::TrackerReorged)
+        }
+    }
+
+    /// Creates a [FeedEventKind::Reorg] event.
+    pub fn reorg(block_hash: BlockHash, height: u32) -> Self {
+        FeedEvent {
+            block_hash: Some(block_hash),
+            height: Some(height),
+            ..FeedEvent::new(FeedEventKind::Reorg)
+        }
+    }
+
+    /// Creates either a [FeedEventKind::BitcoindReachable] or a [FeedEventKind::BitcoindUnreachable] event.
+    pub fn bitcoind_reachability(reachable: bool) -> Self {
+        FeedEvent::new(if reachable {
+            FeedEventKind::BitcoindReachable
+        } else {
+            FeedEventKind::BitcoindUnreachable
+        })
+    }
+}
+
+impl From<Event> for FeedEvent {
+    fn from(e: Event) -> Self {
+        FeedEvent {
+            kind: FeedEventKind::Appointment(e.kind),
+            timestamp: e.timestamp,
+            user_id: Some(e.user_id),
+            uuid: Some(e.uuid),
+            locator: Some(e.locator),
+            txid: e.txid,
+            height: Some(e.height),
+            block_hash: None,
+            rejection_reason: e.rejection_reason,
+            missed_confirmations: None,
+        }
+    }
+}
+
+impl From<FeedEvent> for msgs::TowerEvent {
+    fn from(e: FeedEvent) -> Self {
+        msgs::TowerEvent {
+            event: e.kind.to_string(),
+            timestamp: e.timestamp,
+            user_id: e.user_id.map_or_else(Vec::new, |user_id| user_id.to_vec()),
+            uuid: e.uuid.map_or_else(Vec::new, |uuid| uuid.to_vec()),
+            locator: e.locator.map_or_else(Vec::new, |locator| locator.to_vec()),
+            txid: e.txid.map_or_else(Vec::new, |txid| txid.to_vec()),
+            height: e.height.unwrap_or_default(),
+            block_hash: e
+                .block_hash
+                .map_or_else(Vec::new, |block_hash| block_hash.to_vec()),
+            rejection_reason: e.rejection_reason.unwrap_or_default(),
+            missed_confirmations: e.missed_confirmations.unwrap_or_default(),
+        }
+    }
+}
+
+/// A subscriber that must not miss any of the events it is interested in.
+#[derive(Debug)]
+struct LosslessSubscriber {
+    /// Whether the subscriber is interested in a given kind of event.
+    filter: fn(FeedEventKind) -> bool,
+    sender: mpsc::UnboundedSender<FeedEvent>,
+}
+
+/// The live event feed of the tower.
+#[derive(Debug)]
+pub struct Feed {
+    sender: broadcast::Sender<FeedEvent>,
+    lossless_subscribers: Mutex<Vec<LosslessSubscriber>>,
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Feed {
+    /// Creates a new [Feed] instance.
+    pub fn new() -> Self {
+        Feed {
+            sender: broadcast::channel(FEED_CAPACITY).0,
+            lossless_subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Publishes an event to all the current subscribers.
+    pub(crate) fn publish(&self, event: FeedEvent) {
+        // Lossless subscribers whose receiving end has been dropped are removed.
+        self.lossless_subscribers
+            .lock()
+            .unwrap()
+            .retain(|s| !(s.filter)(event.kind) || s.sender.send(event.clone()).is_ok());
+        // Sending only fails if there are no subscribers, in which case the event can be dropped.
+        self.sender.send(event).ok();
+    }
+
+    /// Whether anyone would receive an event of the given kind if it was published, so events that are expensive to
+    /// build can be skipped otherwise.
+    pub(crate) fn has_subscribers(&self, kind: FeedEventKind) -> bool {
+        self.sender.receiver_count() > 0
+            || self
+                .lossless_subscribers
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|s| (s.filter)(kind) && !s.sender.is_closed())
+    }
+
+    /// Subscribes to the feed. Only events published after subscribing are received.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<FeedEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribes to the events accepted by `filter` without ever missing any of them. Only events published after
+    /// subscribing are received. Events are buffered until received, so the filter should be kept as narrow as possible.
+    pub(crate) fn subscribe_lossless(
+        &self,
+        filter: fn(FeedEventKind) -> bool,
+    ) -> mpsc::UnboundedReceiver<FeedEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.lossless_subscribers
+            .lock()
+            .unwrap()
+            .push(LosslessSubscriber { filter, sender });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::test_utils::get_random_user_id;
+
+    #[test]
+    fn test_feed_event_kind_from_str() {
+        for kind in [
+            FeedEventKind::NewUser,
+            FeedEventKind::NewAppointment,
+            FeedEventKind::Appointment(EventKind::PenaltyConfirmed),
+            FeedEventKind::MissedConfirmation,
+            FeedEventKind::Reorg,
+            FeedEventKind::TrackerReorged,
+            FeedEventKind::BitcoindReachable,
+            FeedEventKind::BitcoindUnreachable,
+        ] {
+            assert_eq!(FeedEventKind::from_str(&kind.to_string()), Ok(kind));
+        }
+        assert!(FeedEventKind::from_str("unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_publish_subscribe() {
+        let feed = Feed::new();
+
+        // Events published while nobody is listening are dropped
+        feed.publish(FeedEvent::new_user(get_random_user_id()));
+
+        let mut receiver = feed.subscribe();
+        let events = vec![
+            FeedEvent::new_user(get_random_user_id()),
+            FeedEvent::reorg(BlockHash::default(), 42),
+            FeedEvent::bitcoind_reachability(false),
+        ];
+        for event in events.iter() {
+            feed.publish(event.clone());
+        }
+
+        for event in events {
+            assert_eq!(receiver.recv().await.unwrap(), event);
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_subscribe_lossless() {
+        let feed = Feed::new();
+        let mut receiver = feed.subscribe_lossless(|kind| kind == FeedEventKind::Reorg);
+
+        // Lossless subscribers never lag behind, and only receive the events they are interested in
+        let mut events = Vec::new();
+        for height in 0..2 * FEED_CAPACITY as u32 {
+            let event = FeedEvent::reorg(BlockHash::default(), height);
+            feed.publish(FeedEvent::new_user(get_random_user_id()));
+            feed.publish(event.clone());
+            events.push(event);
+        }
+
+        for event in events {
+            assert_eq!(receiver.try_recv().unwrap(), event);
+        }
+        assert!(receiver.try_recv().is_err());
+
+        // Subscribers are removed once their receiver is dropped
+        drop(receiver);
+        feed.publish(FeedEvent::reorg(BlockHash::default(), 0));
+        assert!(feed.lossless_subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_has_subscribers() {
+        let feed = Feed::new();
+        assert!(!feed.has_subscribers(FeedEventKind::Reorg));
+
+        // Lossless subscribers only count for the events they are interested in
+        let receiver = feed.subscribe_lossless(|kind| kind == FeedEventKind::Reorg);
+        assert!(feed.has_subscribers(FeedEventKind::Reorg));
+        assert!(!feed.has_subscribers(FeedEventKind::NewUser));
+        drop(receiver);
+        assert!(!feed.has_subscribers(FeedEventKind::Reorg));
+
+        // While regular subscribers receive every event
+        let _receiver = feed.subscribe();
+        assert!(feed.has_subscribers(FeedEventKind::NewUser));
+    }
+}
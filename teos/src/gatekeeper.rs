@@ -1,9 +1,12 @@
 //! Logic related to the Gatekeeper, the component in charge of managing access to the tower resources.
 
 use lightning::chain;
+use lru::LruCache;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
+use tracing::instrument;
 
 use teos_common::appointment::{compute_appointment_slots, Locator};
 use teos_common::constants::ENCRYPTED_BLOB_MAX_SIZE;
@@ -12,7 +15,9 @@ use teos_common::receipts::RegistrationReceipt;
 use teos_common::UserId;
 
 use crate::dbm::DBM;
+use crate::events::{record_event, EventKind};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
+use crate::feed::{Feed, FeedEvent};
 
 /// Data regarding a user subscription with the tower.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,9 +45,28 @@ impl UserInfo {
 #[derive(Debug, PartialEq)]
 pub(crate) struct AuthenticationFailure<'a>(&'a str);
 
-/// Error raised if the user subscription has not enough slots to fit a new appointment.
+/// Packs the reasons why an appointment may not be accounted for in a user subscription.
 #[derive(Debug, PartialEq)]
-pub(crate) struct NotEnoughSlots;
+pub(crate) enum SlotsFailure {
+    /// The user is not registered (e.g. it was outdated after being authenticated).
+    UserNotFound,
+    /// The user subscription has not enough slots to fit the appointment.
+    NotEnoughSlots,
+}
+
+/// Error raised if the nonce of a user request is not greater than the last one used by the user.
+#[derive(Debug, PartialEq)]
+pub(crate) struct InvalidNonce;
+
+/// Packs the reasons why a user may not be allowed to delete some of their appointments.
+#[derive(Debug, PartialEq)]
+pub(crate) enum DeletionFailure {
+    UserNotFound,
+    SubscriptionExpired(u32),
+    InvalidNonce,
+    NotFound(UUID),
+    AlreadyTriggered(UUID),
+}
 
 /// Error raised if the user subscription slots limit has been reached.
 ///
@@ -62,15 +86,18 @@ pub struct Gatekeeper {
     /// last known block header by the [Gatekeeper].
     last_known_block_height: AtomicU32,
     /// Number of slots new subscriptions get by default.
-    subscription_slots: u32,
+    subscription_slots: AtomicU32,
     /// Expiry time new subscription get by default, in blocks (starting from the block the subscription is requested).
-    subscription_duration: u32,
+    subscription_duration: AtomicU32,
     /// Grace period given to renew subscriptions, in blocks.
-    expiry_delta: u32,
-    /// Map of users registered within the tower.
-    registered_users: Mutex<HashMap<UserId, UserInfo>>,
+    expiry_delta: AtomicU32,
+    /// Cache of the most recently used users registered within the tower. All of them are in the database, which is
+    /// written through every time a user is updated.
+    registered_users: Mutex<LruCache<UserId, UserInfo>>,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
     dbm: Arc<Mutex<DBM>>,
+    /// The live [Feed] of the tower. New users are published to it.
+    feed: Arc<Feed>,
 }
 
 impl Gatekeeper {
@@ -80,42 +107,59 @@ impl Gatekeeper {
         subscription_slots: u32,
         subscription_duration: u32,
         expiry_delta: u32,
+        user_cache_size: usize,
         dbm: Arc<Mutex<DBM>>,
+        feed: Arc<Feed>,
     ) -> Self {
-        let registered_users = dbm.lock().unwrap().load_all_users();
         Gatekeeper {
             last_known_block_height: AtomicU32::new(last_known_block_height),
-            subscription_slots,
-            subscription_duration,
-            expiry_delta,
-            registered_users: Mutex::new(registered_users),
+            subscription_slots: AtomicU32::new(subscription_slots),
+            subscription_duration: AtomicU32::new(subscription_duration),
+            expiry_delta: AtomicU32::new(expiry_delta),
+            registered_users: Mutex::new(LruCache::new(user_cache_size)),
             dbm,
+            feed,
         }
     }
 
+    /// Updates the subscription parameters. Existing subscriptions are not affected until they are renewed.
+    pub fn set_subscription_params(
+        &self,
+        subscription_slots: u32,
+        subscription_duration: u32,
+        expiry_delta: u32,
+    ) {
+        self.subscription_slots
+            .store(subscription_slots, Ordering::Release);
+        self.subscription_duration
+            .store(subscription_duration, Ordering::Release);
+        self.expiry_delta.store(expiry_delta, Ordering::Release);
+    }
+
     /// Returns whether the [Gatekeeper] has been created from scratch (fresh) or from backed-up data.
     pub fn is_fresh(&self) -> bool {
-        self.registered_users.lock().unwrap().is_empty()
+        self.get_registered_users_count() == 0
     }
 
     /// Ges the number of users currently registered to the tower.
     pub(crate) fn get_registered_users_count(&self) -> usize {
-        self.registered_users.lock().unwrap().len()
+        self.dbm.lock().unwrap().get_users_count()
     }
 
     /// Gets the list of all registered user ids.
     pub(crate) fn get_user_ids(&self) -> Vec<UserId> {
-        self.registered_users
-            .lock()
-            .unwrap()
-            .keys()
-            .cloned()
-            .collect()
+        self.dbm.lock().unwrap().load_user_ids()
+    }
+
+    /// Gets a registered user, either from the cache or from the database.
+    fn get_user(&self, user_id: UserId) -> Option<UserInfo> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        get_or_load_user(&mut registered_users, &self.dbm.lock().unwrap(), user_id)
     }
 
     /// Gets the data held by the tower about a given user.
     pub(crate) fn get_user_info(&self, user_id: UserId) -> Option<(UserInfo, Vec<Locator>)> {
-        let info = self.registered_users.lock().unwrap().get(&user_id).cloned();
+        let info = self.get_user(user_id);
         info.map(|info| (info, self.dbm.lock().unwrap().load_user_locators(user_id)))
     }
 
@@ -133,54 +177,65 @@ impl Gatekeeper {
                 .map_err(|_| AuthenticationFailure("Wrong message or signature."))?,
         );
 
-        if self.registered_users.lock().unwrap().contains_key(&user_id) {
+        if self.get_user(user_id).is_some() {
             Ok(user_id)
         } else {
             Err(AuthenticationFailure("User not found."))
         }
     }
 
+    /// Checks that the nonce of a user request is greater than the last one used by the user, and records it.
+    ///
+    /// Nonces are shared by all the commands that require one, so a signed request cannot be replayed.
+    pub(crate) fn check_nonce(&self, user_id: UserId, nonce: u64) -> Result<(), InvalidNonce> {
+        if self.dbm.lock().unwrap().update_user_nonce(user_id, nonce) {
+            Ok(())
+        } else {
+            Err(InvalidNonce)
+        }
+    }
+
     /// Adds a new user to the tower (or updates its subscription if already registered).
     pub(crate) fn add_update_user(
         &self,
         user_id: UserId,
     ) -> Result<RegistrationReceipt, MaxSlotsReached> {
         let block_count = self.last_known_block_height.load(Ordering::Acquire);
+        let subscription_slots = self.subscription_slots.load(Ordering::Acquire);
+        let subscription_duration = self.subscription_duration.load(Ordering::Acquire);
 
         // TODO: For now, new calls to `add_update_user` add subscription_slots to the current count and reset the expiry time
         let mut registered_users = self.registered_users.lock().unwrap();
-        let user_info = match registered_users.get_mut(&user_id) {
+        let dbm = self.dbm.lock().unwrap();
+        let user_info = match get_or_load_user(&mut registered_users, &dbm, user_id) {
             // User already exists, updating the info
-            Some(user_info) => {
+            Some(mut user_info) => {
                 user_info.available_slots = user_info
                     .available_slots
-                    .checked_add(self.subscription_slots)
+                    .checked_add(subscription_slots)
                     .ok_or(MaxSlotsReached)?;
                 user_info.subscription_expiry = user_info
                     .subscription_expiry
-                    .checked_add(self.subscription_duration)
+                    .checked_add(subscription_duration)
                     .unwrap_or(u32::MAX);
-                self.dbm.lock().unwrap().update_user(user_id, user_info);
+                dbm.update_user(user_id, &user_info);
 
                 user_info
             }
             // New user
             None => {
                 let user_info = UserInfo::new(
-                    self.subscription_slots,
+                    subscription_slots,
                     block_count,
-                    block_count + self.subscription_duration,
+                    block_count + subscription_duration,
                 );
-                self.dbm
-                    .lock()
-                    .unwrap()
-                    .store_user(user_id, &user_info)
-                    .unwrap();
+                dbm.store_user(user_id, &user_info).unwrap();
+                self.feed.publish(FeedEvent::new_user(user_id));
 
-                registered_users.insert(user_id, user_info);
-                registered_users.get_mut(&user_id).unwrap()
+                user_info
             }
         };
+        registered_users.put(user_id, user_info);
 
         Ok(RegistrationReceipt::new(
             user_id,
@@ -196,16 +251,13 @@ impl Gatekeeper {
         user_id: UserId,
         uuid: UUID,
         appointment: &ExtendedAppointment,
-    ) -> Result<u32, NotEnoughSlots> {
+    ) -> Result<u32, SlotsFailure> {
         // For updates, the difference between the existing appointment size and the update is computed.
         let mut registered_users = self.registered_users.lock().unwrap();
-        let user_info = registered_users.get_mut(&user_id).unwrap();
-        let used_blob_size = self
-            .dbm
-            .lock()
-            .unwrap()
-            .get_appointment_length(uuid)
-            .unwrap_or(0);
+        let dbm = self.dbm.lock().unwrap();
+        let mut user_info = get_or_load_user(&mut registered_users, &dbm, user_id)
+            .ok_or(SlotsFailure::UserNotFound)?;
+        let used_blob_size = dbm.get_appointment_length(uuid).unwrap_or(0);
         let used_slots = compute_appointment_slots(used_blob_size, ENCRYPTED_BLOB_MAX_SIZE);
 
         let required_slots =
@@ -217,12 +269,77 @@ impl Gatekeeper {
             // than the old appointment
             user_info.available_slots = (user_info.available_slots as i64 - diff) as u32;
 
-            self.dbm.lock().unwrap().update_user(user_id, user_info);
+            dbm.update_user(user_id, &user_info);
+            registered_users.put(user_id, user_info);
 
             Ok(user_info.available_slots)
         } else {
-            Err(NotEnoughSlots)
+            Err(SlotsFailure::NotEnoughSlots)
+        }
+    }
+
+    /// Adds a batch of appointments to their users, or updates them if already present in the system.
+    ///
+    /// Slots are accounted for in order, so an appointment that does not fit in the slots its user has left (or which user
+    /// is no longer registered) is rejected without affecting the rest of the batch. The accepted appointments and the updated slot count of their users are
+    /// persisted in a single database transaction.
+    pub(crate) fn add_update_appointments(
+        &self,
+        appointments: &[&ExtendedAppointment],
+    ) -> Vec<Result<u32, SlotsFailure>> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        let mut dbm = self.dbm.lock().unwrap();
+
+        let mut results = Vec::with_capacity(appointments.len());
+        let mut accepted = Vec::new();
+        let mut updated_users = HashMap::new();
+        // Sizes of the appointments accepted so far, so updates within the same batch are accounted for properly.
+        let mut used_blob_sizes = HashMap::new();
+
+        for appointment in appointments.iter() {
+            let uuid = appointment.uuid();
+            let user_info = match updated_users.entry(appointment.user_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    match get_or_load_user(&mut registered_users, &dbm, appointment.user_id) {
+                        Some(user_info) => entry.insert(user_info),
+                        None => {
+                            results.push(Err(SlotsFailure::UserNotFound));
+                            continue;
+                        }
+                    }
+                }
+            };
+            let used_blob_size = used_blob_sizes
+                .get(&uuid)
+                .copied()
+                .or_else(|| dbm.get_appointment_length(uuid))
+                .unwrap_or(0);
+            let used_slots = compute_appointment_slots(used_blob_size, ENCRYPTED_BLOB_MAX_SIZE);
+
+            let required_slots = compute_appointment_slots(
+                appointment.encrypted_blob().len(),
+                ENCRYPTED_BLOB_MAX_SIZE,
+            );
+
+            let diff = required_slots as i64 - used_slots as i64;
+            if diff <= user_info.available_slots as i64 {
+                user_info.available_slots = (user_info.available_slots as i64 - diff) as u32;
+                used_blob_sizes.insert(uuid, appointment.encrypted_blob().len());
+                accepted.push(*appointment);
+                results.push(Ok(user_info.available_slots));
+            } else {
+                results.push(Err(SlotsFailure::NotEnoughSlots));
+            }
         }
+
+        dbm.batch_store_appointments(&accepted, &updated_users)
+            .unwrap();
+        for (user_id, user_info) in updated_users {
+            registered_users.put(user_id, user_info);
+        }
+
+        results
     }
 
     /// Checks whether a subscription has expired.
@@ -230,30 +347,27 @@ impl Gatekeeper {
         &self,
         user_id: UserId,
     ) -> Result<(bool, u32), AuthenticationFailure<'_>> {
-        self.registered_users.lock().unwrap().get(&user_id).map_or(
-            Err(AuthenticationFailure("User not found.")),
-            |user_info| {
+        self.get_user(user_id)
+            .map_or(Err(AuthenticationFailure("User not found.")), |user_info| {
                 Ok((
                     self.last_known_block_height.load(Ordering::Acquire)
                         >= user_info.subscription_expiry,
                     user_info.subscription_expiry,
                 ))
-            },
-        )
+            })
     }
 
     /// Gets a map of outdated users. Outdated users are those whose subscription has expired and the renewal grace period
     /// has already passed ([expiry_delta](Self::expiry_delta)).
     pub(crate) fn get_outdated_users(&self, block_height: u32) -> Vec<UserId> {
-        self.registered_users
-            .lock()
-            .unwrap()
-            .iter()
-            // NOTE: Ideally there won't be a user with `block_height > subscription_expiry + expiry_delta`, but
-            // this might happen if we skip a couple of block connections due to a force update.
-            .filter(|(_, info)| block_height >= info.subscription_expiry + self.expiry_delta)
-            .map(|(user_id, _)| *user_id)
-            .collect()
+        let expiry_delta = self.expiry_delta.load(Ordering::Acquire);
+        // NOTE: Ideally there won't be a user with `block_height > subscription_expiry + expiry_delta`, but
+        // this might happen if we skip a couple of block connections due to a force update.
+        block_height
+            .checked_sub(expiry_delta)
+            .map_or_else(Vec::new, |max_expiry| {
+                self.dbm.lock().unwrap().load_expired_users(max_expiry)
+            })
     }
 
     /// Deletes these appointments from the database and updates the user's information.
@@ -263,31 +377,122 @@ impl Gatekeeper {
     /// DISCUSS: When `refund` is `false` we don't give back the slots to the user for the deleted appointments.
     /// This is to discourage misbehavior (sending bad appointments, either non-decryptable or rejected by the network).
     pub(crate) fn delete_appointments(&self, appointments: Vec<UUID>, refund: bool) {
+        // The cache is locked before the database, like everywhere else, so the two locks are always acquired in the same order.
+        let mut registered_users = self.registered_users.lock().unwrap();
         let mut dbm = self.dbm.lock().unwrap();
+        delete_appointments(&mut registered_users, &mut dbm, appointments, refund);
+    }
 
-        let updated_users = if refund {
-            let mut updated_users = HashMap::new();
-            let mut registered_users = self.registered_users.lock().unwrap();
-            // Give back the consumed slots to each user.
-            for uuid in appointments.iter() {
-                let (user_id, blob_size) = dbm.get_appointment_user_and_length(*uuid).unwrap();
-                registered_users.get_mut(&user_id).unwrap().available_slots +=
-                    compute_appointment_slots(blob_size, ENCRYPTED_BLOB_MAX_SIZE);
-                updated_users.insert(user_id, registered_users[&user_id]);
+    /// Deletes a batch of appointments on behalf of their owner, refunding the slots they consumed.
+    ///
+    /// The request is checked and the appointments deleted while holding the database lock, so none of them can be
+    /// triggered (nor deleted) in between, and the user cannot be outdated either. The batch is either deleted as a whole
+    /// or not deleted at all, and the request nonce is only recorded if it is. Returns the slots the user has available
+    /// after the deletion.
+    pub(crate) fn delete_user_appointments(
+        &self,
+        user_id: UserId,
+        uuids: Vec<UUID>,
+        nonce: u64,
+    ) -> Result<u32, DeletionFailure> {
+        let mut registered_users = self.registered_users.lock().unwrap();
+        let mut dbm = self.dbm.lock().unwrap();
+
+        // The nonce is checked upfront so replayed requests are reported as such, but it is only recorded alongside
+        // the deletion.
+        if dbm
+            .load_user_nonce(user_id)
+            .is_some_and(|last_nonce| nonce <= last_nonce)
+        {
+            return Err(DeletionFailure::InvalidNonce);
+        }
+
+        let mut freed_slots = 0;
+        for uuid in uuids.iter() {
+            // Trackers are checked first given the appointment data is kept in the database while the Responder
+            // is handling it. Removing the appointment would remove the tracker as well.
+            if dbm.tracker_exists(*uuid) {
+                return Err(DeletionFailure::AlreadyTriggered(*uuid));
             }
-            updated_users
-        } else {
-            // No updated users.
-            HashMap::new()
-        };
+            // UUIDs are derived from the user id, so the appointment can only belong to the user. Appointments of users
+            // that are not registered (anymore) cannot be found either.
+            let (_, blob_size) = dbm
+                .get_appointment_user_and_length(*uuid)
+                .ok_or(DeletionFailure::NotFound(*uuid))?;
+            freed_slots += compute_appointment_slots(blob_size, ENCRYPTED_BLOB_MAX_SIZE);
+        }
 
-        // An optimization for the case when only one appointment is being deleted without refunding.
-        // This avoids creating a DB transaction for a single query.
-        if appointments.len() == 1 && updated_users.is_empty() {
-            dbm.remove_appointment(appointments[0])
-        } else {
-            dbm.batch_remove_appointments(&appointments, &updated_users);
+        let mut user_info = get_or_load_user(&mut registered_users, &dbm, user_id)
+            .ok_or(DeletionFailure::UserNotFound)?;
+        if self.last_known_block_height.load(Ordering::Acquire) >= user_info.subscription_expiry {
+            return Err(DeletionFailure::SubscriptionExpired(
+                user_info.subscription_expiry,
+            ));
+        }
+        user_info.available_slots += freed_slots;
+
+        if !dbm.remove_user_appointments(user_id, nonce, &uuids, &user_info) {
+            return Err(DeletionFailure::InvalidNonce);
+        }
+        registered_users.put(user_id, user_info);
+
+        Ok(user_info.available_slots)
+    }
+}
+
+/// Gets a user from the cache, loading it from the database (and caching it) if it is not there.
+fn get_or_load_user(
+    registered_users: &mut LruCache<UserId, UserInfo>,
+    dbm: &DBM,
+    user_id: UserId,
+) -> Option<UserInfo> {
+    if let Some(user_info) = registered_users.get(&user_id) {
+        return Some(*user_info);
+    }
+
+    let user_info = dbm.load_user(user_id)?;
+    registered_users.put(user_id, user_info);
+    Some(user_info)
+}
+
+/// Deletes some appointments from the database, refunding their owners if `refund` is set.
+///
+/// Appointments that cannot be found (e.g. because they have already been deleted) are skipped.
+fn delete_appointments(
+    registered_users: &mut LruCache<UserId, UserInfo>,
+    dbm: &mut DBM,
+    appointments: Vec<UUID>,
+    refund: bool,
+) {
+    let mut updated_users = HashMap::new();
+    let mut appointments_to_delete = Vec::with_capacity(appointments.len());
+    for uuid in appointments {
+        let (user_id, blob_size) = match dbm.get_appointment_user_and_length(uuid) {
+            Some(x) => x,
+            None => {
+                tracing::debug!(%uuid, "Appointment not found. Skipping deletion");
+                continue;
+            }
+        };
+        // Give back the consumed slots to each user.
+        if refund {
+            updated_users
+                .entry(user_id)
+                .or_insert_with(|| get_or_load_user(registered_users, dbm, user_id).unwrap())
+                .available_slots += compute_appointment_slots(blob_size, ENCRYPTED_BLOB_MAX_SIZE);
         }
+        appointments_to_delete.push(uuid);
+    }
+    for (user_id, user_info) in updated_users.iter() {
+        registered_users.put(*user_id, *user_info);
+    }
+
+    // An optimization for the case when only one appointment is being deleted without refunding.
+    // This avoids creating a DB transaction for a single query.
+    if appointments_to_delete.len() == 1 && updated_users.is_empty() {
+        dbm.remove_appointment(appointments_to_delete[0])
+    } else if !appointments_to_delete.is_empty() {
+        dbm.batch_remove_appointments(&appointments_to_delete, &updated_users);
     }
 }
 
@@ -295,27 +500,37 @@ impl chain::Listen for Gatekeeper {
     /// Handles the monitoring process by the [Gatekeeper].
     ///
     /// This is mainly used to keep track of time and expire / outdate subscriptions when needed.
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn filtered_block_connected(
         &self,
         header: &bitcoin::BlockHeader,
         _: &chain::transaction::TransactionData,
         height: u32,
     ) {
-        log::info!("New block received: {}", header.block_hash());
+        tracing::info!("New block received");
 
         // Expired user deletion is delayed. Users are deleted when their subscription is outdated, not expired.
+        // The cache is held locked until the outdated users are gone from the database, otherwise a cache miss in
+        // between could load them back right before they are deleted.
+        let mut registered_users = self.registered_users.lock().unwrap();
         let outdated_users = self.get_outdated_users(height);
         if !outdated_users.is_empty() {
-            // Remove the outdated users from memory first.
-            {
-                let mut registered_users = self.registered_users.lock().unwrap();
-                // Removing each outdated user in a loop is more efficient than retaining non-outdated users
-                // because retaining would loop over all the available users which is always more than the outdated ones.
-                for outdated_user in outdated_users.iter() {
-                    registered_users.remove(outdated_user);
-                }
+            // Removing each outdated user in a loop is more efficient than retaining non-outdated users
+            // because retaining would loop over all the available users which is always more than the outdated ones.
+            for outdated_user in outdated_users.iter() {
+                registered_users.pop(outdated_user);
+            }
+            let mut dbm = self.dbm.lock().unwrap();
+            // The response to the breaches of outdated users is over, so record it in the audit history
+            // before their trackers are gone.
+            for (uuid, tracker) in dbm.load_trackers_by_user_ids(&outdated_users) {
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker.get_event(uuid, EventKind::Outdated, height),
+                );
             }
-            self.dbm.lock().unwrap().batch_remove_users(&outdated_users);
+            dbm.batch_remove_users(&outdated_users);
         }
 
         // Update last known block height
@@ -324,8 +539,9 @@ impl chain::Listen for Gatekeeper {
     }
 
     /// Handles reorgs in the [Gatekeeper]. Simply updates the last_known_block_height.
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn block_disconnected(&self, header: &bitcoin::BlockHeader, height: u32) {
-        log::warn!("Block disconnected: {}", header.block_hash());
+        tracing::warn!("Block disconnected");
         // There's nothing to be done here but updating the last known block
         self.last_known_block_height
             .store(height - 1, Ordering::Release);
@@ -346,14 +562,20 @@ mod tests {
     const SLOTS: u32 = 21;
     const DURATION: u32 = 500;
     const EXPIRY_DELTA: u32 = 42;
+    const USER_CACHE_SIZE: usize = 100;
     const START_HEIGHT: usize = 100;
 
     impl PartialEq for Gatekeeper {
         fn eq(&self, other: &Self) -> bool {
-            self.subscription_slots == other.subscription_slots
-                && self.subscription_duration == other.subscription_duration
-                && self.expiry_delta == other.expiry_delta
-                && *self.registered_users.lock().unwrap() == *other.registered_users.lock().unwrap()
+            // Both Gatekeepers may share the same database, so it cannot be locked twice within the same expression.
+            let registered_users = self.dbm.lock().unwrap().load_all_users();
+            self.subscription_slots.load(Ordering::Relaxed)
+                == other.subscription_slots.load(Ordering::Relaxed)
+                && self.subscription_duration.load(Ordering::Relaxed)
+                    == other.subscription_duration.load(Ordering::Relaxed)
+                && self.expiry_delta.load(Ordering::Relaxed)
+                    == other.expiry_delta.load(Ordering::Relaxed)
+                && registered_users == other.dbm.lock().unwrap().load_all_users()
                 && self.last_known_block_height.load(Ordering::Relaxed)
                     == other.last_known_block_height.load(Ordering::Relaxed)
         }
@@ -361,21 +583,33 @@ mod tests {
     impl Eq for Gatekeeper {}
 
     impl Gatekeeper {
-        pub(crate) fn get_registered_users(&self) -> &Mutex<HashMap<UserId, UserInfo>> {
+        pub(crate) fn get_registered_users(&self) -> &Mutex<LruCache<UserId, UserInfo>> {
             &self.registered_users
         }
 
         pub(crate) fn add_outdated_user(&self, user_id: UserId, outdates_at: u32) {
             self.add_update_user(user_id).unwrap();
             let mut registered_users = self.registered_users.lock().unwrap();
-            let user = registered_users.get_mut(&user_id).unwrap();
-            user.subscription_expiry = outdates_at - self.expiry_delta;
+            let dbm = self.dbm.lock().unwrap();
+            // The user may have already been evicted from the cache
+            let mut user = get_or_load_user(&mut registered_users, &dbm, user_id).unwrap();
+            user.subscription_expiry = outdates_at - self.expiry_delta.load(Ordering::Relaxed);
+            registered_users.put(user_id, user);
+            dbm.update_user(user_id, &user);
         }
     }
 
     fn init_gatekeeper(chain: &Blockchain) -> Gatekeeper {
         let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
-        Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm)
+        Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            USER_CACHE_SIZE,
+            dbm,
+            Arc::new(Feed::new()),
+        )
     }
 
     #[test]
@@ -389,7 +623,9 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            USER_CACHE_SIZE,
             dbm.clone(),
+            Arc::new(Feed::new()),
         );
         assert!(gatekeeper.is_fresh());
 
@@ -413,12 +649,88 @@ mod tests {
         }
 
         // Create a new GK reusing the same DB and check that the data is loaded
-        let another_gk =
-            Gatekeeper::new(chain.get_block_count(), SLOTS, DURATION, EXPIRY_DELTA, dbm);
+        let another_gk = Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            USER_CACHE_SIZE,
+            dbm,
+            Arc::new(Feed::new()),
+        );
         assert!(!another_gk.is_fresh());
         assert_eq!(gatekeeper, another_gk);
     }
 
+    #[test]
+    fn test_user_cache() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        // Shrink the cache so it is easy to overflow
+        let cache_size = 5;
+        gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .resize(cache_size);
+
+        let user_ids: Vec<UserId> = (0..cache_size * 2).map(|_| get_random_user_id()).collect();
+        for user_id in user_ids.iter() {
+            gatekeeper.add_update_user(*user_id).unwrap();
+        }
+
+        // Only the most recently used users are kept in memory, but all of them are registered
+        assert_eq!(
+            gatekeeper.registered_users.lock().unwrap().len(),
+            cache_size
+        );
+        assert_eq!(gatekeeper.get_registered_users_count(), user_ids.len());
+        assert!(!gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .contains(&user_ids[0]));
+
+        // Evicted users are loaded back from the database when needed
+        let (user_info, _) = gatekeeper.get_user_info(user_ids[0]).unwrap();
+        assert_eq!(
+            user_info,
+            gatekeeper
+                .dbm
+                .lock()
+                .unwrap()
+                .load_user(user_ids[0])
+                .unwrap()
+        );
+        assert!(gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .contains(&user_ids[0]));
+        assert_eq!(
+            gatekeeper.registered_users.lock().unwrap().len(),
+            cache_size
+        );
+
+        // Updates on evicted users build on top of the data in the database
+        assert!(!gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .contains(&user_ids[1]));
+        let receipt = gatekeeper.add_update_user(user_ids[1]).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS * 2);
+        assert_eq!(
+            gatekeeper
+                .dbm
+                .lock()
+                .unwrap()
+                .load_user(user_ids[1])
+                .unwrap()
+                .available_slots,
+            SLOTS * 2
+        );
+    }
+
     #[test]
     fn test_authenticate_user() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -519,6 +831,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_subscription_params() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let gatekeeper = init_gatekeeper(&chain);
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id).unwrap();
+
+        // New subscriptions (and renewals) get the updated parameters
+        gatekeeper.set_subscription_params(SLOTS * 2, DURATION * 2, EXPIRY_DELTA * 2);
+        let new_user_id = get_random_user_id();
+        let receipt = gatekeeper.add_update_user(new_user_id).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS * 2);
+        assert_eq!(
+            receipt.subscription_expiry(),
+            START_HEIGHT as u32 + DURATION * 2
+        );
+
+        let receipt = gatekeeper.add_update_user(user_id).unwrap();
+        assert_eq!(receipt.available_slots(), SLOTS * 3);
+        assert_eq!(
+            receipt.subscription_expiry(),
+            START_HEIGHT as u32 + DURATION * 3
+        );
+
+        // And users are outdated using the updated grace period
+        let outdated_at = START_HEIGHT as u32 + DURATION * 2 + EXPIRY_DELTA * 2;
+        assert!(gatekeeper.get_outdated_users(outdated_at - 1).is_empty());
+        assert_eq!(
+            gatekeeper.get_outdated_users(outdated_at),
+            vec![new_user_id]
+        );
+    }
+
     #[test]
     fn test_add_update_appointment() {
         let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
@@ -642,12 +987,85 @@ mod tests {
             .available_slots = 0;
         assert!(matches!(
             gatekeeper.add_update_appointment(user_id, uuid, &appointment),
-            Err(NotEnoughSlots)
+            Err(SlotsFailure::NotEnoughSlots)
         ));
 
         // The entry in the database should remain unchanged in this case
         loaded_user = gatekeeper.dbm.lock().unwrap().load_user(user_id).unwrap();
         assert_eq!(loaded_user.available_slots, updated_slot_count);
+
+        // Adding an appointment for a user that is not registered (anymore) should fail too
+        let user2_id = get_random_user_id();
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user2_id, None);
+        assert_eq!(
+            gatekeeper.add_update_appointment(user2_id, uuid, &appointment),
+            Err(SlotsFailure::UserNotFound)
+        );
+    }
+
+    #[test]
+    fn test_add_update_appointments() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id).unwrap();
+        // Leave the user with just a few slots so we can check slots are accounted for across the batch
+        gatekeeper
+            .registered_users
+            .lock()
+            .unwrap()
+            .get_mut(&user_id)
+            .unwrap()
+            .available_slots = 3;
+
+        // An appointment, an update of it with a bigger blob (taking an additional slot), and two more appointments.
+        // The last one should not fit.
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        let mut bigger_appointment = appointment.clone();
+        bigger_appointment.inner.encrypted_blob = get_random_bytes(ENCRYPTED_BLOB_MAX_SIZE + 1);
+        let (uuid2, appointment2) = generate_dummy_appointment_with_user(user_id, None);
+        let (uuid3, appointment3) = generate_dummy_appointment_with_user(user_id, None);
+
+        let results = gatekeeper.add_update_appointments(&[
+            &appointment,
+            &bigger_appointment,
+            &appointment2,
+            &appointment3,
+        ]);
+        assert_eq!(
+            results,
+            vec![Ok(2), Ok(1), Ok(0), Err(SlotsFailure::NotEnoughSlots)]
+        );
+
+        // Appointments of users that are not registered are rejected without affecting the rest of the batch
+        let user2_id = get_random_user_id();
+        gatekeeper.add_update_user(user2_id).unwrap();
+        let (_, appointment4) = generate_dummy_appointment_with_user(get_random_user_id(), None);
+        let (uuid5, appointment5) = generate_dummy_appointment_with_user(user2_id, None);
+        assert_eq!(
+            gatekeeper.add_update_appointments(&[&appointment4, &appointment5]),
+            vec![Err(SlotsFailure::UserNotFound), Ok(SLOTS - 1)]
+        );
+
+        // The accepted appointments are in the database (the update replacing the original one), the rejected one is not
+        let dbm = gatekeeper.dbm.lock().unwrap();
+        assert_eq!(dbm.load_appointment(uuid).unwrap(), bigger_appointment);
+        assert_eq!(dbm.load_appointment(uuid2).unwrap(), appointment2);
+        assert!(!dbm.appointment_exists(uuid3));
+        assert!(dbm.appointment_exists(uuid5));
+
+        // Slots are updated both in memory and in the database
+        assert_eq!(dbm.load_user(user_id).unwrap().available_slots, 0);
+        assert_eq!(
+            gatekeeper
+                .registered_users
+                .lock()
+                .unwrap()
+                .peek(&user_id)
+                .unwrap()
+                .available_slots,
+            0
+        );
     }
 
     #[test]
@@ -703,6 +1121,13 @@ mod tests {
         // Add an outdated user and check again
         gatekeeper.add_outdated_user(user_id, start_height);
         assert_eq!(gatekeeper.get_outdated_users(start_height), vec![user_id]);
+
+        // Outdated users are found even if they are not cached
+        gatekeeper.registered_users.lock().unwrap().clear();
+        assert_eq!(gatekeeper.get_outdated_users(start_height), vec![user_id]);
+
+        // Heights below the expiry delta cannot have outdated users
+        assert_eq!(gatekeeper.get_outdated_users(EXPIRY_DELTA - 1), vec![]);
     }
 
     #[test]
@@ -855,12 +1280,168 @@ mod tests {
                 gatekeeper.get_user_info(user_id).unwrap().0.available_slots;
             assert_eq!(remaining_slots_from_db, correct_remaining_slots);
             assert_eq!(
-                gatekeeper.registered_users.lock().unwrap()[&user_id].available_slots,
+                gatekeeper
+                    .registered_users
+                    .lock()
+                    .unwrap()
+                    .peek(&user_id)
+                    .unwrap()
+                    .available_slots,
                 correct_remaining_slots
             );
         }
     }
 
+    #[test]
+    fn test_delete_appointments_not_found() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id).unwrap();
+
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        gatekeeper
+            .add_update_appointment(user_id, uuid, &appointment)
+            .unwrap();
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_appointment(uuid, &appointment)
+            .unwrap();
+
+        // Appointments that cannot be found are skipped, the rest are deleted and refunded
+        let (missing_uuid, _) = generate_dummy_appointment_with_user(user_id, None);
+        gatekeeper.delete_appointments(vec![missing_uuid, uuid], true);
+        assert!(!gatekeeper.dbm.lock().unwrap().appointment_exists(uuid));
+        assert_eq!(
+            gatekeeper.get_user_info(user_id).unwrap().0.available_slots,
+            SLOTS
+        );
+    }
+
+    #[test]
+    fn test_delete_user_appointments() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id).unwrap();
+
+        let mut uuids = Vec::new();
+        for _ in 0..3 {
+            let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+            gatekeeper
+                .add_update_appointment(user_id, uuid, &appointment)
+                .unwrap();
+            gatekeeper
+                .dbm
+                .lock()
+                .unwrap()
+                .store_appointment(uuid, &appointment)
+                .unwrap();
+            uuids.push(uuid);
+        }
+
+        // If any of the appointments cannot be found, nothing is deleted
+        let (missing_uuid, _) = generate_dummy_appointment_with_user(user_id, None);
+        assert_eq!(
+            gatekeeper.delete_user_appointments(user_id, vec![uuids[0], missing_uuid], 1),
+            Err(DeletionFailure::NotFound(missing_uuid))
+        );
+        assert!(gatekeeper.dbm.lock().unwrap().appointment_exists(uuids[0]));
+
+        // The same applies if any of them has been triggered
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_tracker(
+                uuids[2],
+                &get_random_tracker(
+                    user_id,
+                    ConfirmationStatus::InMempoolSince(START_HEIGHT as u32),
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            gatekeeper.delete_user_appointments(user_id, uuids.clone(), 1),
+            Err(DeletionFailure::AlreadyTriggered(uuids[2]))
+        );
+        assert!(gatekeeper.dbm.lock().unwrap().appointment_exists(uuids[0]));
+
+        // Otherwise, the appointments are deleted and their slots refunded. Failed requests do not use up their nonce
+        assert_eq!(
+            gatekeeper.delete_user_appointments(user_id, uuids[..2].to_vec(), 1),
+            Ok(SLOTS - 1)
+        );
+        for uuid in uuids[..2].iter() {
+            assert!(!gatekeeper.dbm.lock().unwrap().appointment_exists(*uuid));
+        }
+        assert_eq!(
+            gatekeeper.get_user_info(user_id).unwrap().0.available_slots,
+            SLOTS - 1
+        );
+        assert_eq!(
+            gatekeeper
+                .dbm
+                .lock()
+                .unwrap()
+                .load_user(user_id)
+                .unwrap()
+                .available_slots,
+            SLOTS - 1
+        );
+
+        // Once used, the nonce cannot be used again
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        gatekeeper
+            .dbm
+            .lock()
+            .unwrap()
+            .store_appointment(uuid, &appointment)
+            .unwrap();
+        assert_eq!(
+            gatekeeper.delete_user_appointments(user_id, vec![uuid], 1),
+            Err(DeletionFailure::InvalidNonce)
+        );
+        assert!(gatekeeper.dbm.lock().unwrap().appointment_exists(uuid));
+
+        // Users whose subscription has expired cannot delete their appointments
+        gatekeeper.add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            gatekeeper.delete_user_appointments(user_id, vec![uuid], 2),
+            Err(DeletionFailure::SubscriptionExpired(_))
+        ));
+
+        // Nor can users that are not registered
+        assert_eq!(
+            gatekeeper.delete_user_appointments(get_random_user_id(), Vec::new(), 1),
+            Err(DeletionFailure::UserNotFound)
+        );
+    }
+
+    #[test]
+    fn test_check_nonce() {
+        let gatekeeper = init_gatekeeper(&Blockchain::default().with_height(START_HEIGHT));
+        let user_id = get_random_user_id();
+        gatekeeper.add_update_user(user_id).unwrap();
+
+        assert_eq!(gatekeeper.check_nonce(user_id, 5), Ok(()));
+        // Nonces cannot be reused, nor go backwards
+        assert_eq!(gatekeeper.check_nonce(user_id, 5), Err(InvalidNonce));
+        assert_eq!(gatekeeper.check_nonce(user_id, 4), Err(InvalidNonce));
+        assert_eq!(gatekeeper.check_nonce(user_id, 6), Ok(()));
+
+        // Nonces are per user
+        let another_user_id = get_random_user_id();
+        gatekeeper.add_update_user(another_user_id).unwrap();
+        assert_eq!(gatekeeper.check_nonce(another_user_id, 1), Ok(()));
+
+        // And only registered users can use them
+        assert_eq!(
+            gatekeeper.check_nonce(get_random_user_id(), 1),
+            Err(InvalidNonce)
+        );
+    }
+
     #[test]
     fn test_filtered_block_connected() {
         // block_connected in the Gatekeeper is used to keep track of time in order to manage the users' subscription expiry.
@@ -877,8 +1458,30 @@ mod tests {
             gatekeeper.add_outdated_user(*user_id, chain.tip().height + 1)
         }
 
+        // Give one of them a tracker, so we can check the outcome is recorded in the audit history
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user1_id, None);
+        let tracker = get_random_tracker(user1_id, ConfirmationStatus::ConfirmedIn(42));
+        {
+            let dbm = gatekeeper.dbm.lock().unwrap();
+            dbm.store_appointment(uuid, &appointment).unwrap();
+            dbm.store_tracker(uuid, &tracker).unwrap();
+        }
+
         // Connect a new block. Outdated users are deleted
         gatekeeper.block_connected(&chain.generate(None), chain.get_block_count());
+        let events = gatekeeper.dbm.lock().unwrap().load_events(None, None);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.uuid, e.kind, e.height, e.txid))
+                .collect::<Vec<_>>(),
+            vec![(
+                uuid,
+                EventKind::Outdated,
+                chain.get_block_count(),
+                Some(tracker.penalty_tx.txid())
+            )]
+        );
 
         // Check that users have been removed from registered_users and the database
         for user_id in &[user1_id, user2_id, user3_id] {
@@ -886,7 +1489,7 @@ mod tests {
                 .registered_users
                 .lock()
                 .unwrap()
-                .contains_key(user_id));
+                .contains(user_id));
             assert!(gatekeeper.dbm.lock().unwrap().load_user(*user_id).is_none());
         }
 
@@ -4,10 +4,13 @@
 
 // FIXME: This is a temporary fix. See https://github.com/tokio-rs/prost/issues/661
 #[allow(clippy::derive_partial_eq_without_eq)]
+#[allow(non_camel_case_types)]
 pub mod protos {
     tonic::include_proto!("teos.v2");
 }
+pub mod alerts;
 pub mod api;
+pub mod backup;
 pub mod bitcoin_cli;
 pub mod carrier;
 pub mod chain_monitor;
@@ -16,14 +19,25 @@ pub mod config;
 pub mod dbm;
 #[doc(hidden)]
 mod errors;
+pub mod events;
 mod extended_appointment;
+pub mod feed;
 pub mod gatekeeper;
+pub mod locator_filter;
+pub mod logging;
+pub mod metrics;
+pub mod notifier;
+pub mod reload;
 pub mod responder;
 #[doc(hidden)]
 mod rpc_errors;
+pub mod systemd;
 pub mod tls;
+pub mod tower;
 mod tx_index;
 pub mod watcher;
 
+pub use tower::{TowerBuilder, TowerError, TowerHandle};
+
 #[cfg(test)]
 mod test_utils;
@@ -0,0 +1,211 @@
+//! Logic related to the LocatorFilter, an in-memory probabilistic filter over the locators of all stored appointments.
+//!
+//! The filter is a Bloom filter, so it can be updated as appointments are added. Deleting from a Bloom filter is not
+//! possible, so removed locators are only accounted for and the filter is rebuilt once too many of them are stale
+//! (or once it gets too full to keep its false positive rate low).
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+
+use teos_common::appointment::Locator;
+
+/// The minimum number of locators a filter is sized for.
+const MIN_CAPACITY: usize = 1024;
+/// The number of bits per locator. Alongside [NUM_HASHES] this gives a false positive rate of roughly 1% at capacity.
+const BITS_PER_LOCATOR: usize = 10;
+/// The number of bits set (and checked) per locator.
+const NUM_HASHES: u64 = 7;
+
+/// Probabilistic set of [Locator]s.
+///
+/// [may_contain](Self::may_contain) never returns false negatives, so only the locators it lets through need to be
+/// looked up in the database. False positives are just filtered out by the database lookup.
+pub struct LocatorFilter {
+    /// The bit array, packed in words.
+    bits: Vec<u64>,
+    /// Randomly seeded hasher, so the filter bits cannot be predicted (and targeted) by third parties.
+    hash_builder: RandomState,
+    /// The number of locators the filter is sized for.
+    capacity: usize,
+    /// The number of distinct locators added to the filter since it was built.
+    len: usize,
+    /// The number of distinct locators removed from the set since the filter was built (they are still set in the
+    /// filter).
+    removed: usize,
+}
+
+impl LocatorFilter {
+    /// Creates a new, empty, [LocatorFilter] sized for `capacity` locators.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        LocatorFilter {
+            bits: vec![0; (capacity * BITS_PER_LOCATOR).div_ceil(64)],
+            hash_builder: RandomState::new(),
+            capacity,
+            len: 0,
+            removed: 0,
+        }
+    }
+
+    /// Creates a new [LocatorFilter] holding the given locators.
+    ///
+    /// The filter is sized for twice as many locators, so there is room for the set to grow before needing a rebuild.
+    pub fn from_locators(locators: Vec<Locator>) -> Self {
+        let mut filter = LocatorFilter::new(locators.len() * 2);
+        for locator in locators.iter() {
+            filter.insert(locator);
+        }
+        filter
+    }
+
+    /// Adds a locator to the filter.
+    ///
+    /// Locators the filter may already contain are not counted again, so the same locator being added more than once
+    /// (e.g. by different users) does not make the filter look fuller than it is.
+    pub fn insert(&mut self, locator: &Locator) {
+        if self.may_contain(locator) {
+            return;
+        }
+        for bit in self.bit_indexes(locator) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Checks whether a locator may be part of the set. A negative answer is always right.
+    pub fn may_contain(&self, locator: &Locator) -> bool {
+        self.bit_indexes(locator)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Accounts for `count` distinct locators being removed from the set.
+    ///
+    /// Locators are only removed from the set once no appointment holds them anymore, so they are counted once no
+    /// matter how many appointments (or users) shared them.
+    pub fn remove(&mut self, count: usize) {
+        self.removed += count;
+    }
+
+    /// The number of distinct locators added to the filter since it was built.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// An estimate of the number of locators in the set (the ones added minus the ones removed).
+    pub fn estimated_len(&self) -> usize {
+        self.len.saturating_sub(self.removed)
+    }
+
+    /// Whether no locator has been added to the filter since it was built.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the filter should be rebuilt from the current set of locators, either because it is over capacity or
+    /// because too many of the locators it holds have already been removed.
+    pub fn needs_rebuild(&self) -> bool {
+        self.len > self.capacity || self.removed >= self.capacity / 2
+    }
+
+    /// Computes the indexes of the bits for a given locator (using double hashing).
+    fn bit_indexes(&self, locator: &Locator) -> impl Iterator<Item = u64> {
+        let h1 = self.hash_builder.hash_one(locator);
+        let h2 = h1.rotate_left(32) | 1;
+        let num_bits = (self.bits.len() * 64) as u64;
+
+        (0..NUM_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+impl fmt::Debug for LocatorFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocatorFilter")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len)
+            .field("removed", &self.removed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teos_common::test_utils::get_random_locator;
+
+    #[test]
+    fn test_new() {
+        let filter = LocatorFilter::new(10);
+        assert_eq!(filter.capacity, MIN_CAPACITY);
+        assert!(filter.is_empty());
+        assert!(!filter.needs_rebuild());
+
+        let filter = LocatorFilter::new(MIN_CAPACITY * 4);
+        assert_eq!(filter.capacity, MIN_CAPACITY * 4);
+        assert_eq!(filter.bits.len() * 64, MIN_CAPACITY * 4 * BITS_PER_LOCATOR);
+    }
+
+    #[test]
+    fn test_may_contain() {
+        let locators: Vec<Locator> = (0..MIN_CAPACITY).map(|_| get_random_locator()).collect();
+        let filter = LocatorFilter::from_locators(locators.clone());
+        // Locators hitting a false positive when added are not counted
+        assert!(filter.len() <= locators.len() && filter.len() >= locators.len() * 99 / 100);
+
+        // No false negatives
+        for locator in locators.iter() {
+            assert!(filter.may_contain(locator));
+        }
+
+        // And only a few false positives
+        let false_positives = (0..10000)
+            .filter(|_| filter.may_contain(&get_random_locator()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut filter = LocatorFilter::new(0);
+        let locator = get_random_locator();
+        assert!(!filter.may_contain(&locator));
+
+        filter.insert(&locator);
+        assert!(filter.may_contain(&locator));
+        assert_eq!(filter.len(), 1);
+
+        // Adding the same locator again does not count it twice
+        filter.insert(&locator);
+        assert_eq!(filter.len(), 1);
+    }
+
+    #[test]
+    fn test_needs_rebuild_over_capacity() {
+        let mut filter = LocatorFilter::new(0);
+        while filter.len() < MIN_CAPACITY {
+            filter.insert(&get_random_locator());
+        }
+        assert!(!filter.needs_rebuild());
+
+        while filter.len() == MIN_CAPACITY {
+            filter.insert(&get_random_locator());
+        }
+        assert!(filter.needs_rebuild());
+    }
+
+    #[test]
+    fn test_needs_rebuild_removed() {
+        let mut filter = LocatorFilter::new(0);
+        while filter.len() < MIN_CAPACITY {
+            filter.insert(&get_random_locator());
+        }
+
+        filter.remove(MIN_CAPACITY / 2 - 1);
+        assert!(!filter.needs_rebuild());
+        assert_eq!(filter.estimated_len(), MIN_CAPACITY / 2 + 1);
+        filter.remove(1);
+        assert!(filter.needs_rebuild());
+        assert_eq!(filter.estimated_len(), MIN_CAPACITY / 2);
+    }
+}
@@ -0,0 +1,188 @@
+//! Logic related to the tower logging.
+//!
+//! Logs are emitted using [tracing], so components can attach context to them (e.g. the user, locator and UUID an
+//! appointment is being processed for, or the hash of the block being processed) by entering spans. Records emitted
+//! by third party libs through the `log` crate are forwarded to the same subscriber.
+
+use std::path::Path;
+
+use tonic::codegen::http::Request;
+use tracing::Span;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+use crate::config::Config;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Handle to change the log levels of the running tower.
+pub struct LogFilterHandle(reload::Handle<Targets, Layered<Vec<BoxedLayer>, Registry>>);
+
+impl LogFilterHandle {
+    /// Sets the log levels according to the logging options in [Config].
+    pub fn reload(&self, conf: &Config) -> Result<(), String> {
+        self.0
+            .reload(filter(conf))
+            .map_err(|e| format!("Cannot update the log levels: {e}"))
+    }
+}
+
+/// Builds the filter that sets the log level of the tower and its dependencies.
+fn filter(conf: &Config) -> Targets {
+    Targets::new()
+        .with_default(if conf.deps_debug {
+            LevelFilter::DEBUG
+        } else {
+            LevelFilter::WARN
+        })
+        .with_target(
+            "teos",
+            if conf.debug {
+                LevelFilter::DEBUG
+            } else {
+                LevelFilter::INFO
+            },
+        )
+}
+
+/// Builds a formatting layer that writes to `writer` using the given format (either text or json).
+fn fmt_layer<W>(format: &str, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    if format == "json" {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+/// Builds the appender for the log file. Relative paths are resolved against the network data directory.
+fn file_appender(conf: &Config, path_network: &Path) -> Result<RollingFileAppender, String> {
+    let path = path_network.join(&conf.log_file);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("log_file is not a valid file path: {}", conf.log_file))?;
+    let rotation = match conf.log_rotation.as_str() {
+        "minutely" => Rotation::MINUTELY,
+        "hourly" => Rotation::HOURLY,
+        "daily" => Rotation::DAILY,
+        "weekly" => Rotation::WEEKLY,
+        _ => Rotation::NEVER,
+    };
+
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(file_name.to_string_lossy())
+        .max_log_files(conf.log_max_files)
+        .build(path.parent().unwrap_or(path_network))
+        .map_err(|e| format!("Cannot open log file: {e}"))
+}
+
+/// Creates the span a gRPC request is served under.
+pub fn grpc_request_span(request: &Request<()>) -> Span {
+    tracing::info_span!("grpc_request", path = %request.uri().path())
+}
+
+/// Sets the global logger according to the logging options in [Config].
+///
+/// Logs are always written to stdout and, if `log_file` is set, to a rotated log file as well. The returned guard
+/// (if any) flushes the log file when dropped, so it must be kept alive for as long as the tower runs. The returned
+/// handle can be used to change the log levels later on.
+pub fn init(
+    conf: &Config,
+    path_network: &Path,
+) -> Result<(Option<WorkerGuard>, LogFilterHandle), String> {
+    let mut layers = vec![fmt_layer(&conf.log_format, std::io::stdout, true)];
+
+    let guard = if conf.log_file.is_empty() {
+        None
+    } else {
+        let (writer, guard) = tracing_appender::non_blocking(file_appender(conf, path_network)?);
+        layers.push(fmt_layer(&conf.log_format, writer, false));
+        Some(guard)
+    };
+
+    let (filter, handle) = reload::Layer::new(filter(conf));
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .map_err(|e| format!("Cannot set the logger: {e}"))?;
+
+    Ok((guard, LogFilterHandle(handle)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+    use tracing::Level;
+
+    #[test]
+    fn test_filter() {
+        let mut conf = Config::default();
+        let filter = filter(&conf);
+        assert!(filter.would_enable("teos::watcher", &Level::INFO));
+        assert!(!filter.would_enable("teos::watcher", &Level::DEBUG));
+        assert!(filter.would_enable("lightning", &Level::WARN));
+        assert!(!filter.would_enable("lightning", &Level::INFO));
+
+        conf.debug = true;
+        conf.deps_debug = true;
+        let filter = super::filter(&conf);
+        assert!(filter.would_enable("teos::watcher", &Level::DEBUG));
+        assert!(filter.would_enable("lightning", &Level::DEBUG));
+    }
+
+    #[test]
+    fn test_file_appender() {
+        let tmp_path = TempDir::new("logs").unwrap();
+        let conf = Config {
+            log_file: "logs/teosd.log".to_owned(),
+            log_rotation: "never".to_owned(),
+            ..Default::default()
+        };
+
+        // Relative paths are created inside the network directory
+        let mut appender = file_appender(&conf, tmp_path.path()).unwrap();
+        std::io::Write::write_all(&mut appender, b"test").unwrap();
+        assert_eq!(
+            std::fs::read(tmp_path.path().join("logs/teosd.log")).unwrap(),
+            b"test"
+        );
+
+        // Absolute paths are used as is
+        let other_path = TempDir::new("other_logs").unwrap();
+        let conf = Config {
+            log_file: other_path
+                .path()
+                .join("teosd.log")
+                .to_str()
+                .unwrap()
+                .to_owned(),
+            ..conf
+        };
+        file_appender(&conf, tmp_path.path()).unwrap();
+        assert!(other_path.path().join("teosd.log").exists());
+    }
+
+    #[test]
+    fn test_file_appender_invalid_path() {
+        let tmp_path = TempDir::new("logs").unwrap();
+        let conf = Config {
+            log_file: "..".to_owned(),
+            ..Default::default()
+        };
+        assert!(file_appender(&conf, tmp_path.path()).is_err());
+    }
+}
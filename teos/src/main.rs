@@ -1,66 +1,165 @@
-use log::LevelFilter;
-use simple_logger::SimpleLogger;
+use std::env;
 use std::fs;
 use std::io::ErrorKind;
-use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
 use tokio::task;
-use tonic::transport::{Certificate, Server, ServerTlsConfig};
 
-use bitcoin::network::constants::Network;
-use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::secp256k1::SecretKey;
 use bitcoincore_rpc::{Auth, Client, RpcApi};
-use lightning_block_sync::init::validate_best_block_header;
-use lightning_block_sync::poll::{
-    ChainPoller, Poll, Validate, ValidatedBlock, ValidatedBlockHeader,
-};
-use lightning_block_sync::{BlockSource, BlockSourceError, SpvClient, UnboundedCache};
-
-use teos::api::internal::InternalAPI;
-use teos::api::{http, tor::TorAPI};
+use lightning_block_sync::poll::Validate;
+use lightning_block_sync::BlockSource;
+
+use teos::backup::{restore_backup, BackupDecryption, DB_FILE};
 use teos::bitcoin_cli::BitcoindClient;
-use teos::carrier::Carrier;
-use teos::chain_monitor::ChainMonitor;
 use teos::config::{self, Config, Opt};
 use teos::dbm::DBM;
-use teos::gatekeeper::Gatekeeper;
-use teos::protos as msgs;
-use teos::protos::private_tower_services_server::PrivateTowerServicesServer;
-use teos::protos::public_tower_services_server::PublicTowerServicesServer;
-use teos::responder::Responder;
-use teos::tls::tls_init;
-use teos::watcher::Watcher;
+use teos::logging;
+use teos::systemd::SystemdNotifier;
+use teos::TowerBuilder;
 
 use teos_common::constants::IRREVOCABLY_RESOLVED;
-use teos_common::cryptography::get_random_keypair;
-use teos_common::TowerId;
-
-async fn get_last_n_blocks<B, T>(
-    poller: &mut ChainPoller<B, T>,
-    mut last_known_block: ValidatedBlockHeader,
-    n: usize,
-) -> Result<Vec<ValidatedBlock>, BlockSourceError>
-where
-    B: DerefMut<Target = T> + Sized + Send + Sync,
-    T: BlockSource,
-{
-    let mut last_n_blocks = Vec::with_capacity(n);
-    for _ in 0..n {
-        log::debug!("Fetching block #{}", last_known_block.height);
-        let block = poller.fetch_block(&last_known_block).await?;
-        last_known_block = poller.look_up_previous_header(&last_known_block).await?;
-        last_n_blocks.push(block);
+use teos_common::dbm;
+
+/// Listens for the given unix signal. Exits if the signal handler cannot be set.
+#[cfg(unix)]
+fn listen_signal(kind: SignalKind, name: &str) -> Signal {
+    signal(kind).unwrap_or_else(|e| {
+        tracing::error!("Cannot listen for {name}: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// The signals the tower is shut down on.
+struct TerminationSignals {
+    #[cfg(unix)]
+    sigterm: Signal,
+    #[cfg(unix)]
+    sigint: Signal,
+}
+
+impl TerminationSignals {
+    /// Starts listening for termination signals. Exits if the signal handlers cannot be set.
+    fn new() -> Self {
+        TerminationSignals {
+            #[cfg(unix)]
+            sigterm: listen_signal(SignalKind::terminate(), "SIGTERM"),
+            #[cfg(unix)]
+            sigint: listen_signal(SignalKind::interrupt(), "SIGINT"),
+        }
+    }
+
+    /// Waits for a termination signal, returning its name.
+    #[cfg(unix)]
+    async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.sigterm.recv() => "SIGTERM",
+            _ = self.sigint.recv() => "SIGINT",
+        }
+    }
+
+    /// Waits for a termination signal, returning its name.
+    #[cfg(not(unix))]
+    async fn recv(&mut self) -> &'static str {
+        tokio::signal::ctrl_c().await.ok();
+        "Ctrl-C"
+    }
+}
+
+/// Environment variable the passphrase the tower keys are encrypted with can be read from.
+const KEY_PASSPHRASE_ENV: &str = "TEOS_KEY_PASSPHRASE";
+
+/// Environment variable the passphrase the backup to restore is encrypted with can be read from.
+const RESTORE_PASSPHRASE_ENV: &str = "TEOS_RESTORE_PASSPHRASE";
+
+/// Gets a passphrase (described by `name`) from the given file descriptor if set, from the `env_var` environment
+/// variable otherwise, or prompts for it using `prompt` if set.
+///
+/// Passphrases are never taken as command line arguments so they do not leak through the process list.
+fn get_passphrase(
+    name: &str,
+    fd: Option<i32>,
+    env_var: &str,
+    prompt: Option<&str>,
+) -> Result<Option<String>, String> {
+    let passphrase = if let Some(fd) = fd {
+        #[cfg(unix)]
+        {
+            use std::io::Read;
+            use std::os::unix::io::FromRawFd;
+
+            let mut passphrase = String::new();
+            // SAFETY: the file descriptor is handed to us by the operator for this sole purpose, and it is not used anywhere else
+            unsafe { fs::File::from_raw_fd(fd) }
+                .read_to_string(&mut passphrase)
+                .map_err(|e| format!("Cannot read the {name} from fd {fd}: {e}"))?;
+            passphrase.trim_end_matches(&['\r', '\n'][..]).to_owned()
+        }
+        #[cfg(not(unix))]
+        return Err(format!(
+            "Reading the {name} from fd {fd} is only supported on unix"
+        ));
+    } else if let Ok(passphrase) = env::var(env_var) {
+        passphrase
+    } else if let Some(prompt) = prompt {
+        rpassword::read_password_from_tty(Some(prompt))
+            .map_err(|e| format!("Cannot prompt for the {name}: {e}"))?
+    } else {
+        return Ok(None);
+    };
+
+    if passphrase.is_empty() {
+        Err(format!("The {name} cannot be empty"))
+    } else {
+        Ok(Some(passphrase))
     }
+}
 
-    Ok(last_n_blocks)
+/// Gets the passphrase the tower keys are (or will be) encrypted with, if any.
+///
+/// The passphrase is read from the given file descriptor if set, or from [KEY_PASSPHRASE_ENV] otherwise. If none of
+/// them are set but the keys are encrypted, the passphrase is prompted for.
+fn get_key_passphrase(fd: Option<i32>, keys_encrypted: bool) -> Result<Option<String>, String> {
+    get_passphrase(
+        "key passphrase",
+        fd,
+        KEY_PASSPHRASE_ENV,
+        keys_encrypted.then_some("Tower key passphrase: "),
+    )
 }
 
-fn create_new_tower_keypair(db: &DBM) -> (SecretKey, PublicKey) {
-    let (sk, pk) = get_random_keypair();
-    db.store_tower_key(&sk).unwrap();
-    (sk, pk)
+/// Gets the passphrase the backup to restore is encrypted with.
+///
+/// The passphrase is read from the given file descriptor if set, or from [RESTORE_PASSPHRASE_ENV] otherwise. If none of
+/// them are set, the passphrase is prompted for.
+fn get_restore_passphrase(fd: Option<i32>) -> Result<String, String> {
+    get_passphrase(
+        "backup passphrase",
+        fd,
+        RESTORE_PASSPHRASE_ENV,
+        Some("Backup passphrase: "),
+    )
+    .map(|passphrase| passphrase.expect("the passphrase is prompted for if not provided"))
+}
+
+/// Gets the key the tower database is encrypted with, if any.
+///
+/// The key is taken from the config if set. Otherwise, it is prompted for if the database is encrypted.
+fn get_db_key(conf_key: &str, db_path: &Path) -> Result<Option<String>, String> {
+    if !conf_key.is_empty() {
+        Ok(Some(conf_key.to_owned()))
+    } else if dbm::is_encrypted_database(db_path) {
+        rpassword::read_password_from_tty(Some("Database key: "))
+            .map(Some)
+            .map_err(|e| format!("Cannot prompt for the database key: {e}"))
+    } else {
+        Ok(None)
+    }
 }
 
 #[tokio::main]
@@ -74,33 +173,27 @@ async fn main() {
         std::process::exit(1);
     });
 
-    // Load conf (from file or defaults) and patch it with the command line parameters received (if any)
+    // Load conf (from file or defaults) and patch it with the environment variables and command line parameters
+    // received (if any)
     let mut conf = config::from_file::<Config>(&conf_file_path);
     let is_default = conf.is_default();
+    conf.patch_with_env(env::vars()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let key_passphrase_fd = opt.key_passphrase_fd;
+    let restore = opt
+        .restore
+        .clone()
+        .map(|backup| (backup, opt.restore_passphrase_fd, opt.restore_key.clone()));
+    // The command line options keep overriding the config file when it is reloaded
+    let options = opt.clone();
     conf.patch_with_options(opt);
     conf.verify().unwrap_or_else(|e| {
         eprintln!("{e}");
         std::process::exit(1);
     });
 
-    // Set log level
-    SimpleLogger::new()
-        .with_level(if conf.deps_debug {
-            LevelFilter::Debug
-        } else {
-            LevelFilter::Warn
-        })
-        .with_module_level(
-            "teos",
-            if conf.debug {
-                LevelFilter::Debug
-            } else {
-                LevelFilter::Info
-            },
-        )
-        .init()
-        .unwrap();
-
     // Create network dir
     let path_network = path.join(conf.btc_network.clone());
     fs::create_dir_all(&path_network).unwrap_or_else(|e| {
@@ -108,42 +201,103 @@ async fn main() {
         std::process::exit(1);
     });
 
+    // Set the logger. The guard needs to be kept around so the log file is flushed on shutdown.
+    let (_log_guard, log_filter) = logging::init(&conf, &path_network).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    // Notify systemd about the tower state if run by it
+    let systemd = SystemdNotifier::from_env();
+    if let Some(watchdog_interval) = systemd.watchdog_interval() {
+        if watchdog_interval <= Duration::from_secs(conf.polling_delta as u64) {
+            tracing::warn!(
+                "The systemd watchdog interval ({}s) is not greater than polling_delta ({}s). The tower may be restarted even if healthy",
+                watchdog_interval.as_secs(),
+                conf.polling_delta
+            );
+        }
+    }
+
     // Log default data dir
-    log::info!("Default data directory: {:?}", &path);
+    tracing::info!("Default data directory: {:?}", &path);
 
     // Log datadir path
-    log::info!("Using data directory: {:?}", &path_network);
+    tracing::info!("Using data directory: {:?}", &path_network);
 
     // Log config file path based on whether the config file is found or not
     if is_default {
-        log::info!("Config file: {:?} (not found, skipping)", &conf_file_path);
+        tracing::info!("Config file: {:?} (not found, skipping)", &conf_file_path);
     } else {
-        log::info!("Config file: {:?}", &conf_file_path);
+        tracing::info!("Config file: {:?}", &conf_file_path);
         conf.log_non_default_options();
     }
 
-    let dbm = Arc::new(Mutex::new(
-        DBM::new(path_network.join("teos_db.sql3")).unwrap(),
-    ));
-
-    // Load tower secret key or create a fresh one if none is found. If overwrite key is set, create a new
-    // key straightaway
-    let (tower_sk, tower_pk) = {
-        let locked_db = dbm.lock().unwrap();
-        if conf.overwrite_key {
-            log::info!("Overwriting tower keys");
-            create_new_tower_keypair(&locked_db)
-        } else if let Some(sk) = locked_db.load_tower_key() {
-            (sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))
-        } else {
-            log::info!("Tower keys not found. Creating a fresh set");
-            create_new_tower_keypair(&locked_db)
+    // Restore the tower from a backup if requested. The tower will resume from the last block known by the backup
+    if let Some((backup, passphrase_fd, key_file)) = restore {
+        let decryption = match (passphrase_fd, key_file) {
+            (_, None) => get_restore_passphrase(passphrase_fd)
+                .map(BackupDecryption::Passphrase)
+                .unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }),
+            (None, Some(key_file)) => fs::read_to_string(&key_file)
+                .ok()
+                .and_then(|sk| SecretKey::from_str(sk.trim()).ok())
+                .map(BackupDecryption::SecretKey)
+                .unwrap_or_else(|| {
+                    eprintln!("Cannot load a secret key from {key_file}");
+                    std::process::exit(1);
+                }),
+            (Some(_), Some(_)) => {
+                eprintln!("Only one of restorepassphrasefd and restorekey can be provided to restore a backup");
+                std::process::exit(1);
+            }
+        };
+
+        let last_known_block = restore_backup(
+            &config::data_dir_absolute_path(backup.clone()),
+            &decryption,
+            &path,
+            &conf.btc_network,
+            (!conf.db_key.is_empty()).then_some(conf.db_key.as_str()),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Cannot restore backup {backup}: {e}");
+            std::process::exit(1);
+        });
+        match last_known_block {
+            Some(block_hash) => tracing::info!("Backup restored. Resuming from block {block_hash}"),
+            None => tracing::info!("Backup restored"),
         }
-    };
-    log::info!("tower_id: {tower_pk}");
+    }
+
+    let db_path = path_network.join(DB_FILE);
+    let db_key = get_db_key(&conf.db_key, &db_path).unwrap_or_else(|e| {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    });
+    let dbm = DBM::new(db_path, db_key.as_deref()).unwrap_or_else(|e| {
+        tracing::error!("Cannot open the tower database: {e}");
+        std::process::exit(1);
+    });
+
+    // Get the passphrase the tower keys are encrypted with
+    let key_passphrase = get_key_passphrase(key_passphrase_fd, dbm.is_tower_key_encrypted())
+        .unwrap_or_else(|e| {
+            tracing::error!("{e}");
+            std::process::exit(1);
+        });
+
+    // Listen for termination signals before bootstrapping, so the tower can be stopped gracefully while starting up
+    // (e.g. while catching up with the chain). Signals received meanwhile are handled once the tower is started.
+    let mut termination = TerminationSignals::new();
+    #[cfg(unix)]
+    let mut sighup = listen_signal(SignalKind::hangup(), "SIGHUP");
 
     // Initialize our bitcoind client
-    let (bitcoin_cli, bitcoind_reachable) = match BitcoindClient::new(
+    let bitcoin_cli = BitcoindClient::new(
         &conf.btc_rpc_connect,
         conf.btc_rpc_port,
         &conf.btc_rpc_user,
@@ -151,20 +305,14 @@ async fn main() {
         &conf.btc_network,
     )
     .await
-    {
-        Ok(client) => (
-            Arc::new(client),
-            Arc::new((Mutex::new(true), Condvar::new())),
-        ),
-        Err(e) => {
-            let e_msg = match e.kind() {
-                ErrorKind::InvalidData => "invalid btcrpcuser or btcrpcpassword".into(),
-                _ => e.to_string(),
-            };
-            log::error!("Failed to connect to bitcoind. Error: {e_msg}");
-            std::process::exit(1);
-        }
-    };
+    .unwrap_or_else(|e| {
+        let e_msg = match e.kind() {
+            ErrorKind::InvalidData => "invalid btcrpcuser or btcrpcpassword".into(),
+            _ => e.to_string(),
+        };
+        tracing::error!("Failed to connect to bitcoind. Error: {e_msg}");
+        std::process::exit(1);
+    });
 
     // FIXME: Temporary. We're using bitcoin_core_rpc and rust-lightning's rpc until they both get merged
     // https://github.com/rust-bitcoin/rust-bitcoincore-rpc/issues/166
@@ -180,265 +328,116 @@ async fn main() {
         )
         .unwrap(),
     );
-    let mut derefed = bitcoin_cli.deref();
-    // Load last known block from DB if found. Poll it from Bitcoind otherwise.
-    let last_known_block = dbm.lock().unwrap().load_last_known_block();
-    let tip = if let Some(block_hash) = last_known_block {
-        let mut last_known_header = derefed
+
+    // If we are running in pruned mode some data may be missing (if we happen to have been offline for a while)
+    let mut resume_from = None;
+    if let (Some(block_hash), Some(prune_height)) = (
+        dbm.load_last_known_block(),
+        rpc.get_blockchain_info().unwrap().prune_height,
+    ) {
+        let last_known_header = bitcoin_cli
             .get_header(&block_hash, None)
             .await
             .unwrap()
             .validate(block_hash)
             .unwrap();
 
-        log::info!(
-            "Last known block: {} (height: {})",
-            last_known_header.header.block_hash(),
-            last_known_header.height
-        );
-
-        // If we are running in pruned mode some data may be missing (if we happen to have been offline for a while)
-        if let Some(prune_height) = rpc.get_blockchain_info().unwrap().prune_height {
-            if last_known_header.height - IRREVOCABLY_RESOLVED + 1 < prune_height as u32 {
-                log::warn!(
-                    "Cannot load blocks in the range {}-{}. Chain has gone too far out of sync",
-                    last_known_header.height - IRREVOCABLY_RESOLVED + 1,
-                    last_known_header.height
-                );
-                if conf.force_update {
-                    log::info!("Forcing a backend update");
-                    // We want to grab the first IRREVOCABLY_RESOLVED we know about for the initial cache
-                    // So we can perform transitions from there onwards.
-                    let target_height = prune_height + IRREVOCABLY_RESOLVED as u64;
-                    let target_hash = rpc.get_block_hash(target_height).unwrap();
-                    last_known_header = derefed
-                        .get_header(
-                            &rpc.get_block_hash(target_height).unwrap(),
-                            Some(target_height as u32),
-                        )
+        if last_known_header.height - IRREVOCABLY_RESOLVED + 1 < prune_height as u32 {
+            tracing::warn!(
+                "Cannot load blocks in the range {}-{}. Chain has gone too far out of sync",
+                last_known_header.height - IRREVOCABLY_RESOLVED + 1,
+                last_known_header.height
+            );
+            if conf.force_update {
+                tracing::info!("Forcing a backend update");
+                // We want to grab the first IRREVOCABLY_RESOLVED we know about for the initial cache
+                // So we can perform transitions from there onwards.
+                let target_height = prune_height + IRREVOCABLY_RESOLVED as u64;
+                let target_hash = rpc.get_block_hash(target_height).unwrap();
+                resume_from = Some(
+                    bitcoin_cli
+                        .get_header(&target_hash, Some(target_height as u32))
                         .await
                         .unwrap()
                         .validate(target_hash)
-                        .unwrap();
-                } else {
-                    log::error!(
-                        "The underlying chain has gone too far out of sync. The tower block cache cannot be initialized. Run with --forceupdate to force update. THIS WILL, POTENTIALLY, MAKE THE TOWER MISS SOME OF ITS APPOINTMENTS"
-                    );
-                    std::process::exit(1);
-                }
+                        .unwrap(),
+                );
+            } else {
+                tracing::error!(
+                    "The underlying chain has gone too far out of sync. The tower block cache cannot be initialized. Run with --forceupdate to force update. THIS WILL, POTENTIALLY, MAKE THE TOWER MISS SOME OF ITS APPOINTMENTS"
+                );
+                std::process::exit(1);
             }
         }
-        last_known_header
-    } else {
-        validate_best_block_header(&derefed).await.unwrap()
-    };
-
-    // DISCUSS: This is not really required (and only triggered in regtest). This is only in place so the caches can be
-    // populated with enough blocks mainly because the size of the cache is based on the amount of blocks passed when initializing.
-    // However, we could add an additional parameter to specify the size of the cache, and initialize with however may blocks we
-    // could pull from the backend. Adding this functionality just for regtest seemed unnecessary though, hence the check.
-    if tip.height < IRREVOCABLY_RESOLVED {
-        log::error!(
-            "Not enough blocks to start teosd (required: {IRREVOCABLY_RESOLVED}). Mine at least {} more",
-            IRREVOCABLY_RESOLVED - tip.height
-        );
-        std::process::exit(1);
     }
 
-    log::info!(
-        "Current chain tip: {} (height: {})",
-        tip.header.block_hash(),
-        tip.height
-    );
-
-    // This is how chain poller names bitcoin networks.
-    let btc_network = match conf.btc_network.as_str() {
-        "main" => "bitcoin",
-        "test" => "testnet",
-        any => any,
-    };
-
-    // Build components
-    let gatekeeper = Arc::new(Gatekeeper::new(
-        tip.height,
-        conf.subscription_slots,
-        conf.subscription_duration,
-        conf.expiry_delta,
-        dbm.clone(),
-    ));
-
-    let mut poller = ChainPoller::new(&mut derefed, Network::from_str(btc_network).unwrap());
-    let (responder, watcher) = {
-        let last_n_blocks = get_last_n_blocks(&mut poller, tip, IRREVOCABLY_RESOLVED as usize)
-            .await.unwrap_or_else(|e| {
-                // I'm pretty sure this can only happen if we are pulling blocks from the target to the prune height, and by the time we get to
-                // the end at least one has been pruned.
-                log::error!("Couldn't load the latest {IRREVOCABLY_RESOLVED} blocks. Please try again (Error: {})", e.into_inner());
-                std::process::exit(1);
-            }
-        );
-
-        let responder = Arc::new(Responder::new(
-            &last_n_blocks,
-            tip.height,
-            Carrier::new(rpc, bitcoind_reachable.clone(), tip.height),
-            gatekeeper.clone(),
-            dbm.clone(),
-        ));
-        let watcher = Arc::new(Watcher::new(
-            gatekeeper.clone(),
-            responder.clone(),
-            &last_n_blocks[0..6],
-            tip.height,
-            tower_sk,
-            TowerId(tower_pk),
-            dbm.clone(),
-        ));
-        (responder, watcher)
-    };
-
-    if watcher.is_fresh() & responder.is_fresh() & gatekeeper.is_fresh() {
-        log::info!("Fresh bootstrap");
-    } else {
-        log::info!("Bootstrapping from backed up data");
+    let mut builder = TowerBuilder::new(conf, bitcoin_cli, rpc, dbm)
+        .with_data_dir(path)
+        .with_config_file(conf_file_path, options)
+        .with_log_filter(log_filter)
+        .with_systemd(systemd);
+    if let Some(passphrase) = key_passphrase {
+        builder = builder.with_key_passphrase(passphrase);
     }
-
-    let (shutdown_trigger, shutdown_signal_rpc_api) = triggered::trigger();
-    let shutdown_signal_internal_api = shutdown_signal_rpc_api.clone();
-    let shutdown_signal_http = shutdown_signal_rpc_api.clone();
-    let shutdown_signal_cm = shutdown_signal_rpc_api.clone();
-    let shutdown_signal_tor = shutdown_signal_rpc_api.clone();
-
-    // The ordering here actually matters. Listeners are called by order, and we want the gatekeeper to be called
-    // first so it updates the users' states and both the Watcher and the Responder operate only on registered users.
-    let listener = &(gatekeeper, &(watcher.clone(), responder));
-    let cache = &mut UnboundedCache::new();
-    let spv_client = SpvClient::new(tip, poller, cache, listener);
-    let mut chain_monitor = ChainMonitor::new(
-        spv_client,
-        tip,
-        dbm,
-        conf.polling_delta,
-        shutdown_signal_cm,
-        bitcoind_reachable.clone(),
-    )
-    .await;
-
-    // Get all the components up to date if there's a backlog of blocks
-    chain_monitor.poll_best_tip().await;
-    log::info!("Bootstrap completed. Turning on interfaces");
-
-    // Build interfaces
-    let http_api_addr = format!("{}:{}", conf.api_bind, conf.api_port)
-        .parse()
-        .unwrap();
-    let mut addresses = vec![msgs::NetworkAddress::from_ipv4(
-        conf.api_bind.clone(),
-        conf.api_port,
-    )];
-
-    // Create Tor endpoint if required
-    let tor_api = if conf.tor_support {
-        let tor_api = TorAPI::new(
-            http_api_addr,
-            conf.onion_hidden_service_port,
-            conf.tor_control_port,
-            path_network,
-        )
-        .await;
-        addresses.push(msgs::NetworkAddress::from_torv3(
-            tor_api.get_onion_address(),
-            conf.onion_hidden_service_port,
-        ));
-
-        Some(tor_api)
-    } else {
-        None
+    if let Some(header) = resume_from {
+        builder = builder.with_resume_from(header);
+    }
+    let mut tower = builder.build();
+    let shutdown_trigger = tower.shutdown_trigger();
+    let shutdown_signal = tower.shutdown_signal();
+
+    // Starting up can take a while, so stop straightaway if asked to meanwhile
+    let started = tokio::select! {
+        result = tower.start() => result,
+        signal_name = termination.recv() => {
+            tracing::info!("{signal_name} received while starting up. Shutting down");
+            shutdown_trigger.trigger();
+            if let Err(e) = tower.stopped().await {
+                tracing::error!("{e}");
+            }
+            return;
+        }
     };
-
-    let internal_api = Arc::new(InternalAPI::new(
-        watcher,
-        addresses,
-        bitcoind_reachable.clone(),
-        shutdown_trigger,
-    ));
-    let internal_api_cloned = internal_api.clone();
-
-    let rpc_api_addr = format!("{}:{}", conf.rpc_bind, conf.rpc_port)
-        .parse()
-        .unwrap();
-    let internal_api_addr = format!("{}:{}", conf.internal_api_bind, conf.internal_api_port)
-        .parse()
-        .unwrap();
-
-    // Generate mtls certificates to data directory so the admin can securely connect
-    // to the server to perform administrative tasks.
-    let (identity, ca_cert) = tls_init(&path).unwrap_or_else(|e| {
-        eprintln!("Couldn't generate tls certificates: {e:?}");
+    if let Err(e) = started {
+        tracing::error!("Cannot start the tower. {e}");
         std::process::exit(1);
-    });
-
-    let tls = ServerTlsConfig::new()
-        .identity(identity)
-        .client_ca_root(Certificate::from_pem(ca_cert));
-
-    // Start tasks
-    let private_api_task = task::spawn(async move {
-        Server::builder()
-            .tls_config(tls)
-            .expect("couldn't configure tls")
-            .add_service(PrivateTowerServicesServer::new(internal_api))
-            .serve_with_shutdown(rpc_api_addr, shutdown_signal_rpc_api)
-            .await
-            .unwrap();
-    });
-
-    let public_api_task = task::spawn(async move {
-        Server::builder()
-            .add_service(PublicTowerServicesServer::new(internal_api_cloned))
-            .serve_with_shutdown(internal_api_addr, shutdown_signal_internal_api)
-            .await
-            .unwrap();
-    });
-
-    let (http_service_ready, ready_signal_http) = triggered::trigger();
-    let http_api_task = task::spawn(http::serve(
-        http_api_addr,
-        internal_api_addr,
-        http_service_ready,
-        shutdown_signal_http,
-    ));
-    ready_signal_http.await;
-
-    // Add Tor Onion Service for public API
-    let mut tor_task = Option::None;
-    let (tor_service_ready, ready_signal_tor) = triggered::trigger();
-    if let Some(tor_api) = tor_api {
-        log::info!("Starting up Tor hidden service");
-
-        tor_task = Some(task::spawn(async move {
-            if let Err(e) = tor_api
-                .expose_onion_service(tor_service_ready, shutdown_signal_tor)
-                .await
-            {
-                eprintln!("Cannot connect to the Tor backend: {e}");
-                std::process::exit(1);
-            }
-        }));
-
-        ready_signal_tor.await
     }
 
-    log::info!("Tower ready");
-    chain_monitor.monitor_chain().await;
+    // Shut down gracefully on SIGTERM and SIGINT (Ctrl-C if not on unix), and reload the config on SIGHUP
+    #[cfg(unix)]
+    let internal_api = tower.internal_api().unwrap();
+    let signals_task = task::spawn(async move {
+        loop {
+            #[cfg(unix)]
+            let signal_name = tokio::select! {
+                _ = sighup.recv() => {
+                    tracing::info!("SIGHUP received. Reloading config");
+                    if let Err(e) = internal_api.reload_tower_config() {
+                        tracing::error!("Cannot reload config. {e}");
+                    }
+                    continue;
+                }
+                signal_name = termination.recv() => signal_name,
+                _ = shutdown_signal.clone() => break,
+            };
+            #[cfg(not(unix))]
+            let signal_name = tokio::select! {
+                signal_name = termination.recv() => signal_name,
+                _ = shutdown_signal.clone() => break,
+            };
+            tracing::info!("{signal_name} received. Shutting down");
+            shutdown_trigger.trigger();
+            break;
+        }
+    });
 
     // Wait until shutdown
-    http_api_task.await.unwrap();
-    private_api_task.await.unwrap();
-    public_api_task.await.unwrap();
-    if let Some(tor_task) = tor_task {
-        tor_task.await.unwrap();
-    }
+    let stopped = tower.stopped().await;
+    signals_task.await.unwrap();
 
-    log::info!("Shutting down tower");
+    tracing::info!("Shutting down tower");
+    if let Err(e) = stopped {
+        tracing::error!("{e}");
+        std::process::exit(1);
+    }
 }
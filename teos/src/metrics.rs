@@ -0,0 +1,427 @@
+//! Logic related to the tower metrics, exported in the Prometheus text format.
+//!
+//! Counters are updated by the components as events happen, while gauges are refreshed from the [Watcher] every
+//! time metrics are requested. Each tower owns its own [Metrics] instance, which is only created (and handed to the
+//! components) if metrics are enabled.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use triggered::{Listener, Trigger};
+use warp::{http::header, Filter};
+
+use bitcoin::BlockHeader;
+use lightning::chain;
+
+use teos_common::net::http::Endpoint;
+
+use crate::watcher::Watcher;
+
+/// Label value used for requests that did not fail.
+const NO_ERROR: &str = "none";
+
+/// Metrics exported by the tower.
+pub struct Metrics {
+    registry: Registry,
+    pub registered_users: IntGauge,
+    pub watcher_appointments: IntGauge,
+    pub responder_trackers: IntGauge,
+    pub breaches: IntCounter,
+    pub penalties_broadcast: IntCounter,
+    pub penalties_accepted: IntCounter,
+    pub penalties_rejected: IntCounter,
+    pub missed_confirmations: IntCounter,
+    pub reorged_trackers: IntCounter,
+    pub bitcoind_reachable: IntGauge,
+    pub block_processing_seconds: HistogramVec,
+    pub http_requests: IntCounterVec,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates a new [Metrics] instance, with its own registry.
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let int_gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let int_counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+
+        let block_processing_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "teos_block_processing_seconds",
+                "Time taken by each component to process a connected block",
+            ),
+            &["component"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(block_processing_seconds.clone()))
+            .unwrap();
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "teos_http_requests_total",
+                "Requests served by the HTTP API, by endpoint and error code",
+            ),
+            &["endpoint", "error_code"],
+        )
+        .unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+
+        Metrics {
+            registered_users: int_gauge("teos_registered_users", "Users registered to the tower"),
+            watcher_appointments: int_gauge(
+                "teos_watcher_appointments",
+                "Appointments being watched by the Watcher",
+            ),
+            responder_trackers: int_gauge(
+                "teos_responder_trackers",
+                "Trackers being monitored by the Responder",
+            ),
+            breaches: int_counter("teos_breaches_total", "Breaches detected on chain"),
+            penalties_broadcast: int_counter(
+                "teos_penalties_broadcast_total",
+                "Penalty transactions broadcast to the network",
+            ),
+            penalties_accepted: int_counter(
+                "teos_penalties_accepted_total",
+                "Penalty transactions accepted by bitcoind",
+            ),
+            penalties_rejected: int_counter(
+                "teos_penalties_rejected_total",
+                "Penalty transactions rejected by bitcoind",
+            ),
+            missed_confirmations: int_counter(
+                "teos_missed_confirmations_total",
+                "Confirmations missed by penalty transactions in mempool",
+            ),
+            reorged_trackers: int_counter(
+                "teos_reorged_trackers_total",
+                "Trackers whose penalty transaction was reorged out",
+            ),
+            bitcoind_reachable: int_gauge(
+                "teos_bitcoind_reachable",
+                "Whether bitcoind is reachable (1) or not (0)",
+            ),
+            block_processing_seconds,
+            http_requests,
+            registry,
+        }
+    }
+
+    /// Records the result of a penalty transaction broadcast.
+    pub fn record_penalty(&self, accepted: bool) {
+        self.penalties_broadcast.inc();
+        if accepted {
+            self.penalties_accepted.inc();
+        } else {
+            self.penalties_rejected.inc();
+        }
+    }
+
+    /// Records a request to the HTTP API, alongside its error code if the request failed.
+    pub fn record_http_request(&self, endpoint: Endpoint, error_code: Option<u8>) {
+        let error_code = error_code.map_or_else(|| NO_ERROR.to_owned(), |c| c.to_string());
+        self.http_requests
+            .with_label_values(&[&endpoint.to_string(), &error_code])
+            .inc();
+    }
+
+    /// Refreshes the gauges that reflect the state of the tower.
+    fn update(&self, watcher: &Watcher, bitcoind_reachable: &(Mutex<bool>, Condvar)) {
+        self.registered_users
+            .set(watcher.get_registered_users_count() as i64);
+        self.watcher_appointments
+            .set(watcher.get_appointments_count() as i64);
+        self.responder_trackers
+            .set(watcher.get_trackers_count() as i64);
+        self.bitcoind_reachable
+            .set(*bitcoind_reachable.0.lock().unwrap() as i64);
+    }
+
+    /// Encodes all the metrics in the Prometheus text format.
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        buffer
+    }
+}
+
+/// Wrapper around a [chain::Listen] component that records how long it takes to process connected blocks.
+pub struct TimedListener<L: Deref>
+where
+    L::Target: chain::Listen,
+{
+    component: &'static str,
+    listener: L,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<L: Deref> TimedListener<L>
+where
+    L::Target: chain::Listen,
+{
+    /// Creates a new [TimedListener]. Nothing is recorded if no [Metrics] are given.
+    pub fn new(component: &'static str, listener: L, metrics: Option<Arc<Metrics>>) -> Self {
+        TimedListener {
+            component,
+            listener,
+            metrics,
+        }
+    }
+}
+
+impl<L: Deref> chain::Listen for TimedListener<L>
+where
+    L::Target: chain::Listen,
+{
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &chain::transaction::TransactionData,
+        height: u32,
+    ) {
+        let timer = self.metrics.as_ref().map(|metrics| {
+            metrics
+                .block_processing_seconds
+                .with_label_values(&[self.component])
+                .start_timer()
+        });
+        self.listener
+            .filtered_block_connected(header, txdata, height);
+        if let Some(timer) = timer {
+            timer.observe_duration();
+        }
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+        self.listener.block_disconnected(header, height)
+    }
+}
+
+/// Serves the tower metrics under `/metrics`.
+///
+/// Returns an error if the interface cannot be bound to `metrics_bind`.
+pub async fn serve(
+    metrics_bind: SocketAddr,
+    metrics: Arc<Metrics>,
+    watcher: Arc<Watcher>,
+    bitcoind_reachable: Arc<(Mutex<bool>, Condvar)>,
+    service_ready: Trigger,
+    shutdown_signal: Listener,
+) -> Result<(), String> {
+    let route = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .map(move || {
+            metrics.update(&watcher, &bitcoind_reachable);
+            warp::reply::with_header(
+                metrics.encode(),
+                header::CONTENT_TYPE,
+                TextEncoder::new().format_type(),
+            )
+        });
+
+    let (_, server) = warp::serve(route)
+        .try_bind_with_graceful_shutdown(metrics_bind, shutdown_signal)
+        .map_err(|e| format!("Cannot bind the metrics interface to {metrics_bind}: {e}"))?;
+    service_ready.trigger();
+    server.await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use lightning::chain::Listen;
+
+    use crate::dbm::DBM;
+    use crate::feed::Feed;
+    use crate::gatekeeper::Gatekeeper;
+    use crate::test_utils::{
+        create_responder, create_watcher, BitcoindMock, Blockchain, MockOptions, DURATION,
+        EXPIRY_DELTA, SLOTS, START_HEIGHT, USER_CACHE_SIZE,
+    };
+
+    /// Listener that counts the blocks it gets notified about.
+    #[derive(Default)]
+    struct CountingListener {
+        connected: AtomicU32,
+        disconnected: AtomicU32,
+    }
+
+    impl chain::Listen for CountingListener {
+        fn filtered_block_connected(
+            &self,
+            _: &BlockHeader,
+            _: &chain::transaction::TransactionData,
+            _: u32,
+        ) {
+            self.connected.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn block_disconnected(&self, _: &BlockHeader, _: u32) {
+            self.disconnected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_record_penalty() {
+        let metrics = Metrics::new();
+
+        metrics.record_penalty(true);
+        metrics.record_penalty(false);
+        metrics.record_penalty(true);
+
+        assert_eq!(metrics.penalties_broadcast.get(), 3);
+        assert_eq!(metrics.penalties_accepted.get(), 2);
+        assert_eq!(metrics.penalties_rejected.get(), 1);
+    }
+
+    #[test]
+    fn test_record_http_request() {
+        let metrics = Metrics::new();
+        metrics.record_http_request(Endpoint::Ping, None);
+        metrics.record_http_request(Endpoint::Register, Some(1));
+        metrics.record_http_request(Endpoint::Register, Some(1));
+
+        let encoded = String::from_utf8(metrics.encode()).unwrap();
+        assert!(
+            encoded.contains("teos_http_requests_total{endpoint=\"ping\",error_code=\"none\"} 1")
+        );
+        assert!(
+            encoded.contains("teos_http_requests_total{endpoint=\"register\",error_code=\"1\"} 2")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timed_listener() {
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let header = chain.tip().header;
+        let listener = CountingListener::default();
+        let metrics = Arc::new(Metrics::new());
+        let timed_listener = TimedListener::new("watcher", &listener, Some(metrics.clone()));
+        let samples = || {
+            metrics
+                .block_processing_seconds
+                .with_label_values(&["watcher"])
+                .get_sample_count()
+        };
+
+        timed_listener.filtered_block_connected(&header, &[], 1);
+        assert_eq!(listener.connected.load(Ordering::Relaxed), 1);
+        assert_eq!(samples(), 1);
+
+        // Disconnections are forwarded but not timed
+        timed_listener.block_disconnected(&header, 1);
+        assert_eq!(listener.disconnected.load(Ordering::Relaxed), 1);
+        assert_eq!(samples(), 1);
+
+        // Nothing is recorded if metrics are disabled
+        let untimed_listener = TimedListener::new("watcher", &listener, None);
+        untimed_listener.filtered_block_connected(&header, &[], 2);
+        assert_eq!(listener.connected.load(Ordering::Relaxed), 2);
+        assert_eq!(samples(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let bitcoind_mock = BitcoindMock::new(MockOptions::default());
+        let feed = Arc::new(Feed::new());
+        let gatekeeper = Arc::new(Gatekeeper::new(
+            chain.get_block_count(),
+            SLOTS,
+            DURATION,
+            EXPIRY_DELTA,
+            USER_CACHE_SIZE,
+            dbm.clone(),
+            feed.clone(),
+        ));
+        let responder = create_responder(
+            &mut chain,
+            gatekeeper.clone(),
+            dbm.clone(),
+            bitcoind_mock.url(),
+            feed.clone(),
+        )
+        .await;
+        let (watcher, _s) = create_watcher(
+            &mut chain,
+            Arc::new(responder),
+            gatekeeper,
+            bitcoind_mock,
+            dbm,
+            feed,
+        )
+        .await;
+        let bitcoind_reachable = Arc::new((Mutex::new(false), Condvar::new()));
+
+        let metrics_bind = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (service_ready, ready_signal) = triggered::trigger();
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let server = tokio::spawn(serve(
+            metrics_bind,
+            Arc::new(Metrics::new()),
+            Arc::new(watcher),
+            bitcoind_reachable,
+            service_ready,
+            shutdown_signal,
+        ));
+        ready_signal.await;
+
+        let response = reqwest::get(format!("http://{metrics_bind}/metrics"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            TextEncoder::new().format_type()
+        );
+        let body = response.text().await.unwrap();
+        for metric in [
+            "teos_registered_users 0",
+            "teos_watcher_appointments 0",
+            "teos_responder_trackers 0",
+            "teos_bitcoind_reachable 0",
+            "teos_breaches_total 0",
+            "teos_missed_confirmations_total 0",
+            "teos_reorged_trackers_total 0",
+        ] {
+            assert!(body.contains(metric), "{} not found", metric);
+        }
+
+        shutdown_trigger.trigger();
+        server.await.unwrap().unwrap();
+    }
+}
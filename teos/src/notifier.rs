@@ -0,0 +1,748 @@
+//! Logic related to the Notifier, the component in charge of pushing breach related events to users via webhooks.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::Txid;
+use futures::{stream, StreamExt};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use triggered::Listener;
+
+use teos_common::appointment::Locator;
+use teos_common::cryptography;
+use teos_common::dbm::Error as DBError;
+use teos_common::UserId;
+
+use crate::dbm::DBM;
+
+/// HTTP header where the tower signature of the notification body is sent.
+pub const SIGNATURE_HEADER: &str = "X-Tower-Signature";
+/// Number of times the delivery of a notification is attempted before giving up on it.
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 10;
+/// Time (in seconds) to wait before retrying a failed delivery. Doubles with every failed attempt.
+const RETRY_BASE_DELAY: u64 = 5;
+/// Maximum time (in seconds) between two delivery attempts of the same notification.
+const MAX_RETRY_DELAY: u64 = 3600;
+/// Time (in seconds) to wait for the user's endpoint to reply to a delivery.
+const DELIVERY_TIMEOUT: u64 = 10;
+/// Time (in seconds) the delivery task sleeps for if there is nothing pending.
+const IDLE_DELAY: u64 = 60;
+/// Maximum number of notifications being delivered at the same time.
+const MAX_CONCURRENT_DELIVERIES: usize = 16;
+
+/// Returns the current UNIX timestamp (in seconds).
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Reasons why a webhook url may be rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum WebhookUrlError {
+    /// The url cannot be parsed or it is not an http(s) url.
+    InvalidUrl,
+    /// The url host cannot be resolved.
+    Unresolvable,
+    /// The url host resolves to an address that is not publicly routable.
+    NonPublicAddress,
+}
+
+/// Checks whether an IP address is publicly routable.
+///
+/// Webhooks are not allowed to point anywhere else, so they cannot be used to reach the tower's local network
+/// (or the cloud metadata endpoints).
+fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "This" network (0.0.0.0/8)
+                || a == 0
+                // Shared address space (100.64.0.0/10)
+                || (a == 100 && (b & 0xc0) == 64)
+                // IETF protocol assignments (192.0.0.0/24)
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking (198.18.0.0/15)
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved (240.0.0.0/4)
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if ip.is_unspecified() || ip.is_loopback() || ip.is_multicast() {
+                return false;
+            }
+            // IPv4-mapped and IPv4-compatible addresses are checked as IPv4
+            if let Some(ipv4) = ip.to_ipv4() {
+                return is_global(IpAddr::V4(ipv4));
+            }
+            let segments = ip.segments();
+            // Unique local (fc00::/7), link-local (fe80::/10) and documentation (2001:db8::/32)
+            !((segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+        }
+    }
+}
+
+/// Resolves the address a webhook must be reached at, checking the url is a valid http(s) url that only points to
+/// public addresses (unless `allow_local` is set).
+///
+/// This is done both when the webhook is registered and every time a notification is delivered, so a host cannot
+/// be pointed to a local address once it has been registered (DNS rebinding).
+pub(crate) async fn resolve_webhook(
+    url: &str,
+    allow_local: bool,
+) -> Result<SocketAddr, WebhookUrlError> {
+    let url = reqwest::Url::parse(url).map_err(|_| WebhookUrlError::InvalidUrl)?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(WebhookUrlError::InvalidUrl);
+    }
+    let host = url.host_str().ok_or(WebhookUrlError::InvalidUrl)?;
+    let port = url
+        .port_or_known_default()
+        .ok_or(WebhookUrlError::InvalidUrl)?;
+
+    let addresses: Vec<SocketAddr> = match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| WebhookUrlError::Unresolvable)?
+            .collect(),
+    };
+
+    // All the addresses need to be public, otherwise which one is reached would be up to the resolver.
+    if !allow_local && addresses.iter().any(|address| !is_global(address.ip())) {
+        return Err(WebhookUrlError::NonPublicAddress);
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or(WebhookUrlError::Unresolvable)
+}
+
+/// Computes how long to wait (in seconds) before the next delivery attempt given the number of failed attempts.
+fn retry_delay(attempts: u32) -> u64 {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// The events a user can be notified about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// One of the user's locators has been seen on chain.
+    Triggered,
+    /// The penalty transaction has been accepted by the network.
+    PenaltyBroadcast,
+    /// The penalty transaction has received its first confirmation.
+    PenaltyConfirmed,
+    /// The penalty transaction is [irrevocably resolved](teos_common::constants::IRREVOCABLY_RESOLVED).
+    IrrevocablyResolved,
+}
+
+/// The body of the requests sent to the users' webhooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    /// The event being notified.
+    pub event: WebhookEvent,
+    /// The user the notification is addressed to.
+    pub user_id: UserId,
+    /// The locator that was triggered.
+    #[serde(with = "hex::serde")]
+    pub locator: Locator,
+    /// The transaction that triggered the locator.
+    pub dispute_txid: Txid,
+    /// The penalty transaction, if the appointment could be decrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub penalty_txid: Option<Txid>,
+    /// The height the penalty transaction was confirmed at, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation_height: Option<u32>,
+}
+
+impl Notification {
+    /// Creates a new [Notification] instance.
+    pub fn new(
+        event: WebhookEvent,
+        user_id: UserId,
+        dispute_txid: Txid,
+        penalty_txid: Option<Txid>,
+        confirmation_height: Option<u32>,
+    ) -> Self {
+        Notification {
+            event,
+            user_id,
+            locator: Locator::new(dispute_txid),
+            dispute_txid,
+            penalty_txid,
+            confirmation_height,
+        }
+    }
+}
+
+/// A [Notification] waiting to be delivered, as stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct QueuedNotification {
+    pub id: u64,
+    pub url: String,
+    pub payload: String,
+    pub attempts: u32,
+}
+
+/// Component in charge of delivering [Notification]s to the users that have registered a webhook.
+///
+/// Notifications are queued in the database as soon as they are created, so they survive restarts, and are
+/// pushed by a background task (see [Notifier::deliver_notifications]), retrying failed deliveries with an
+/// exponential backoff. The body of every request is signed by the tower and the signature is sent in the
+/// [SIGNATURE_HEADER] header.
+#[derive(Debug)]
+pub struct Notifier {
+    /// The tower signing key. Used to sign the notifications.
+    signing_key: SecretKey,
+    /// A [DBM] (database manager) instance. Used to persist the notification queue.
+    dbm: Arc<Mutex<DBM>>,
+    /// Used to wake up the delivery task when new notifications are queued.
+    new_notifications: Notify,
+    /// Whether webhooks are allowed to point to non-public addresses.
+    allow_local_webhooks: bool,
+}
+
+impl Notifier {
+    /// Creates a new [Notifier] instance.
+    pub fn new(signing_key: SecretKey, dbm: Arc<Mutex<DBM>>) -> Self {
+        Notifier {
+            signing_key,
+            dbm,
+            new_notifications: Notify::new(),
+            allow_local_webhooks: false,
+        }
+    }
+
+    /// Checks that a webhook url can be registered (see [resolve_webhook]).
+    pub(crate) async fn check_webhook(&self, url: &str) -> Result<(), WebhookUrlError> {
+        resolve_webhook(url, self.allow_local_webhooks)
+            .await
+            .map(|_| ())
+    }
+
+    /// Queues a [Notification] for delivery.
+    ///
+    /// Notifications addressed to users that have not registered a webhook are silently dropped.
+    pub(crate) fn notify(&self, notification: Notification) {
+        let payload = serde_json::to_string(&notification).unwrap();
+        match self
+            .dbm
+            .lock()
+            .unwrap()
+            .store_notification(notification.user_id, &payload, now())
+        {
+            Ok(()) => {
+                tracing::debug!(
+                    user_id = %notification.user_id,
+                    event = ?notification.event,
+                    "Notification queued"
+                );
+                self.new_notifications.notify_one();
+            }
+            Err(DBError::MissingForeignKey) => (),
+            Err(e) => tracing::error!("Couldn't queue notification. Error: {e:?}"),
+        }
+    }
+
+    /// Tries to deliver all the notifications that are due. Successful deliveries are removed from the queue,
+    /// failed ones are rescheduled until they reach [MAX_DELIVERY_ATTEMPTS].
+    ///
+    /// Notifications are delivered concurrently (up to [MAX_CONCURRENT_DELIVERIES] at a time), so a slow endpoint
+    /// does not hold back the notifications of other users.
+    async fn process_queue(&self) {
+        let pending = self.dbm.lock().unwrap().load_due_notifications(now());
+
+        stream::iter(pending)
+            .for_each_concurrent(MAX_CONCURRENT_DELIVERIES, |notification| async move {
+                let delivered = self.deliver(&notification).await;
+
+                let dbm = self.dbm.lock().unwrap();
+                let attempts = notification.attempts + 1;
+                if delivered {
+                    tracing::debug!("Notification delivered to {}", notification.url);
+                    dbm.remove_notification(notification.id);
+                } else if attempts >= MAX_DELIVERY_ATTEMPTS {
+                    tracing::warn!(
+                        "Giving up on notification after {attempts} attempts (url={})",
+                        notification.url
+                    );
+                    dbm.remove_notification(notification.id);
+                } else {
+                    dbm.update_notification_attempts(
+                        notification.id,
+                        attempts,
+                        now() + retry_delay(attempts),
+                    );
+                }
+            })
+            .await;
+    }
+
+    /// Delivers a single notification. Returns whether the endpoint acknowledged it.
+    ///
+    /// The endpoint is resolved (and checked) right before the delivery, and the request is pinned to the resolved
+    /// address. Redirects are not followed.
+    async fn deliver(&self, notification: &QueuedNotification) -> bool {
+        let address = match resolve_webhook(&notification.url, self.allow_local_webhooks).await {
+            Ok(address) => address,
+            Err(e) => {
+                tracing::info!(
+                    "Cannot deliver to webhook {}. Error: {e:?}",
+                    notification.url
+                );
+                return false;
+            }
+        };
+        // The url has just been parsed successfully by resolve_webhook
+        let host = reqwest::Url::parse(&notification.url)
+            .unwrap()
+            .host_str()
+            .unwrap()
+            .to_owned();
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(DELIVERY_TIMEOUT))
+            .redirect(Policy::none())
+            .no_proxy()
+            .resolve(&host, address)
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("Cannot build the webhook client. Error: {e}");
+                return false;
+            }
+        };
+
+        let signature =
+            cryptography::sign(notification.payload.as_bytes(), &self.signing_key).unwrap();
+        match client
+            .post(&notification.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .body(notification.payload.clone())
+            .send()
+            .await
+        {
+            Ok(r) if r.status().is_success() => true,
+            Ok(r) => {
+                tracing::info!(
+                    "Webhook {} replied with status {}",
+                    notification.url,
+                    r.status()
+                );
+                false
+            }
+            Err(e) => {
+                tracing::info!("Cannot reach webhook {}. Error: {e}", notification.url);
+                false
+            }
+        }
+    }
+
+    /// Delivers queued notifications until the shutdown signal is received.
+    ///
+    /// The task wakes up whenever a new notification is queued or a failed delivery is due to be retried.
+    pub async fn deliver_notifications(&self, shutdown_signal: Listener) {
+        loop {
+            self.process_queue().await;
+
+            let delay = self
+                .dbm
+                .lock()
+                .unwrap()
+                .get_next_notification_time()
+                .map_or(IDLE_DELAY, |t| t.saturating_sub(now()).min(IDLE_DELAY));
+
+            tokio::select! {
+                _ = self.new_notifications.notified() => (),
+                _ = tokio::time::sleep(Duration::from_secs(delay)) => (),
+                _ = shutdown_signal.clone() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::PublicKey;
+
+    use teos_common::cryptography::get_random_keypair;
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::gatekeeper::UserInfo;
+
+    impl Notifier {
+        pub(crate) fn allow_local_webhooks(mut self) -> Self {
+            self.allow_local_webhooks = true;
+            self
+        }
+    }
+
+    /// Runs a local HTTP server that forwards every (signature, body) pair it receives through a channel,
+    /// replying with the given status.
+    async fn run_receiver(
+        status: StatusCode,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<(String, Notification)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let route = warp::post()
+            .and(warp::header::<String>(SIGNATURE_HEADER))
+            .and(warp::body::json())
+            .map(move |signature: String, notification: Notification| {
+                tx.send((signature, notification)).unwrap();
+                warp::reply::with_status(warp::reply(), status)
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, rx)
+    }
+
+    fn init_notifier() -> (Notifier, PublicKey, UserId) {
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let user_id = get_random_user_id();
+        dbm.lock()
+            .unwrap()
+            .store_user(user_id, &UserInfo::new(21, 42, 420))
+            .unwrap();
+
+        let (sk, pk) = get_random_keypair();
+        // The test receivers are run locally
+        (Notifier::new(sk, dbm).allow_local_webhooks(), pk, user_id)
+    }
+
+    fn get_random_notification(user_id: UserId) -> Notification {
+        Notification::new(
+            WebhookEvent::Triggered,
+            user_id,
+            Txid::from_slice(&cryptography::get_random_bytes(32)).unwrap(),
+            None,
+            None,
+        )
+    }
+
+    fn get_queue(notifier: &Notifier) -> Vec<QueuedNotification> {
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .load_due_notifications(u32::MAX as u64)
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), 2 * RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(3), 4 * RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS * 10), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_is_global() {
+        for ip in ["1.1.1.1", "8.8.8.8", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_global(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "192.0.0.1",
+            "198.18.0.1",
+            "192.0.2.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "fc00::1",
+            "fd00:ec2::254",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+        ] {
+            assert!(!is_global(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_webhook() {
+        assert_eq!(
+            resolve_webhook("https://1.1.1.1/hook", false).await,
+            Ok("1.1.1.1:443".parse().unwrap())
+        );
+        assert_eq!(
+            resolve_webhook("http://[2606:4700::1111]:8080/hook", false).await,
+            Ok("[2606:4700::1111]:8080".parse().unwrap())
+        );
+
+        for url in ["", "not a url", "ftp://1.1.1.1/hook", "unix:/run/hook"] {
+            assert_eq!(
+                resolve_webhook(url, false).await,
+                Err(WebhookUrlError::InvalidUrl)
+            );
+        }
+
+        // Local addresses are rejected unless allowed, including the ones behind a domain name
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://localhost/hook",
+        ] {
+            assert_eq!(
+                resolve_webhook(url, false).await,
+                Err(WebhookUrlError::NonPublicAddress)
+            );
+            assert!(resolve_webhook(url, true).await.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_notification_serde() {
+        let notification = get_random_notification(get_random_user_id());
+        let json = serde_json::to_value(&notification).unwrap();
+
+        assert_eq!(json["event"], "triggered");
+        assert_eq!(json["locator"], notification.locator.to_string());
+        assert!(json.get("penalty_txid").is_none());
+        assert_eq!(
+            serde_json::from_value::<Notification>(json).unwrap(),
+            notification
+        );
+    }
+
+    #[test]
+    fn test_notify() {
+        let (notifier, _, user_id) = init_notifier();
+
+        // Notifications for users with no webhook are dropped
+        notifier.notify(get_random_notification(user_id));
+        assert!(get_queue(&notifier).is_empty());
+
+        // Once a webhook is registered they are queued
+        let url = "http://localhost:1234/hook";
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, url)
+            .unwrap();
+        let notification = get_random_notification(user_id);
+        notifier.notify(notification.clone());
+
+        let queue = get_queue(&notifier);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].url, url);
+        assert_eq!(queue[0].attempts, 0);
+        assert_eq!(
+            serde_json::from_str::<Notification>(&queue[0].payload).unwrap(),
+            notification
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_queue() {
+        let (notifier, tower_pk, user_id) = init_notifier();
+        let (addr, mut rx) = run_receiver(StatusCode::OK).await;
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, &format!("http://{addr}"))
+            .unwrap();
+
+        let notification = get_random_notification(user_id);
+        notifier.notify(notification.clone());
+        notifier.process_queue().await;
+
+        // The notification has been received, and it is signed by the tower
+        let (signature, received) = rx.recv().await.unwrap();
+        assert_eq!(received, notification);
+        assert_eq!(
+            cryptography::recover_pk(
+                serde_json::to_string(&received).unwrap().as_bytes(),
+                &signature
+            )
+            .unwrap(),
+            tower_pk
+        );
+
+        // And it is not in the queue anymore
+        assert!(get_queue(&notifier).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_queue_failed_delivery() {
+        let (notifier, _, user_id) = init_notifier();
+        let (addr, mut rx) = run_receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, &format!("http://{addr}"))
+            .unwrap();
+
+        notifier.notify(get_random_notification(user_id));
+        notifier.process_queue().await;
+        rx.recv().await.unwrap();
+
+        // The notification is kept and rescheduled for later
+        let queue = get_queue(&notifier);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].attempts, 1);
+        assert!(notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .load_due_notifications(now())
+            .is_empty());
+        assert!(notifier.dbm.lock().unwrap().get_next_notification_time() > Some(now()));
+
+        // Once the last attempt fails, the notification is dropped
+        notifier.dbm.lock().unwrap().update_notification_attempts(
+            queue[0].id,
+            MAX_DELIVERY_ATTEMPTS - 1,
+            0,
+        );
+        notifier.process_queue().await;
+        rx.recv().await.unwrap();
+        assert!(get_queue(&notifier).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_process_queue_non_public_address() {
+        let (notifier, _, user_id) = init_notifier();
+        let notifier = Notifier {
+            allow_local_webhooks: false,
+            ..notifier
+        };
+        let (addr, mut rx) = run_receiver(StatusCode::OK).await;
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, &format!("http://{addr}"))
+            .unwrap();
+
+        // The delivery fails without reaching the endpoint
+        notifier.notify(get_random_notification(user_id));
+        notifier.process_queue().await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(get_queue(&notifier)[0].attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_queue_slow_endpoint() {
+        let (notifier, _, user_id) = init_notifier();
+        let notifier = Arc::new(notifier);
+
+        // An endpoint that accepts connections but never replies
+        let blackhole = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(
+                user_id,
+                &format!("http://{}", blackhole.local_addr().unwrap()),
+            )
+            .unwrap();
+        notifier.notify(get_random_notification(user_id));
+
+        // And another user with a responsive one
+        let another_user_id = get_random_user_id();
+        let (addr, mut rx) = run_receiver(StatusCode::OK).await;
+        {
+            let dbm = notifier.dbm.lock().unwrap();
+            dbm.store_user(another_user_id, &UserInfo::new(21, 42, 420))
+                .unwrap();
+            dbm.store_webhook(another_user_id, &format!("http://{addr}"))
+                .unwrap();
+        }
+        let notification = get_random_notification(another_user_id);
+        notifier.notify(notification.clone());
+
+        // The second notification is delivered without waiting for the first one to time out
+        let notifier_clone = notifier.clone();
+        let task = tokio::spawn(async move { notifier_clone.process_queue().await });
+        let (_, received) =
+            tokio::time::timeout(Duration::from_secs(DELIVERY_TIMEOUT / 2), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(received, notification);
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_deliver_notifications() {
+        let (notifier, _, user_id) = init_notifier();
+        let notifier = Arc::new(notifier);
+        let (addr, mut rx) = run_receiver(StatusCode::OK).await;
+        notifier
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, &format!("http://{addr}"))
+            .unwrap();
+
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        let notifier_clone = notifier.clone();
+        let task =
+            tokio::spawn(
+                async move { notifier_clone.deliver_notifications(shutdown_signal).await },
+            );
+
+        // Notifications queued while the task is running are delivered straightaway
+        for event in [
+            WebhookEvent::Triggered,
+            WebhookEvent::PenaltyBroadcast,
+            WebhookEvent::PenaltyConfirmed,
+            WebhookEvent::IrrevocablyResolved,
+        ] {
+            let mut notification = get_random_notification(user_id);
+            notification.event = event;
+            notifier.notify(notification.clone());
+            assert_eq!(rx.recv().await.unwrap().1, notification);
+        }
+
+        shutdown_trigger.trigger();
+        task.await.unwrap();
+    }
+}
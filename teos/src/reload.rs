@@ -0,0 +1,229 @@
+//! Logic related to reloading the tower configuration while the tower is running.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{self, Config, ConfigError, Opt, LIVE_OPTIONS};
+use crate::gatekeeper::Gatekeeper;
+use crate::logging::LogFilterHandle;
+
+/// The outcome of reloading the configuration.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Options that have changed and have been applied.
+    pub applied: Vec<String>,
+    /// Options that have changed but will only be applied once the tower is restarted.
+    pub requires_restart: Vec<String>,
+}
+
+/// Component in charge of re-reading the tower configuration and applying the options that can be changed live
+/// (see [LIVE_OPTIONS]) to the running tower.
+pub struct ConfigReloader {
+    /// Path to the config file.
+    conf_file_path: PathBuf,
+    /// The command line options the tower was started with. They keep overriding the config file on reload.
+    options: Opt,
+    /// The configuration the tower is running with.
+    conf: Mutex<Config>,
+    /// A [Gatekeeper] instance. Its subscription parameters are updated on reload.
+    gatekeeper: Arc<Gatekeeper>,
+    /// The time between `bitcoind` polls, in seconds (shared with the [ChainMonitor](crate::chain_monitor::ChainMonitor)).
+    polling_delta: Arc<AtomicU16>,
+    /// A handle to change the log levels, if the tower logger has been set.
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl ConfigReloader {
+    /// Creates a new [ConfigReloader] instance.
+    pub fn new(
+        conf_file_path: PathBuf,
+        options: Opt,
+        conf: Config,
+        gatekeeper: Arc<Gatekeeper>,
+        polling_delta: Arc<AtomicU16>,
+        log_filter: Option<LogFilterHandle>,
+    ) -> Self {
+        ConfigReloader {
+            conf_file_path,
+            options,
+            conf: Mutex::new(conf),
+            gatekeeper,
+            polling_delta,
+            log_filter,
+        }
+    }
+
+    /// Gets the configuration the tower is running with.
+    pub fn get_config(&self) -> Config {
+        self.conf.lock().unwrap().clone()
+    }
+
+    /// Re-reads the configuration (config file, environment variables and command line options, in that order) and
+    /// applies the options that can be changed live.
+    ///
+    /// Nothing is applied if the new configuration is not valid. Changes to options that cannot be changed live are
+    /// reported so the operator knows a restart is required.
+    pub fn reload(&self) -> Result<ReloadReport, ConfigError> {
+        let mut new_conf = config::try_from_file::<Config>(&self.conf_file_path)?;
+        new_conf.patch_with_env(std::env::vars())?;
+        new_conf.patch_with_options(self.options.clone());
+        new_conf.verify()?;
+
+        let mut conf = self.conf.lock().unwrap();
+        let (applied, requires_restart): (Vec<String>, Vec<String>) = conf
+            .changed_options(&new_conf)
+            .into_iter()
+            .partition(|option| LIVE_OPTIONS.contains(&option.as_str()));
+
+        if !applied.is_empty() {
+            if let Some(Err(e)) = self.log_filter.as_ref().map(|f| f.reload(&new_conf)) {
+                tracing::error!("{e}");
+            }
+            self.gatekeeper.set_subscription_params(
+                new_conf.subscription_slots,
+                new_conf.subscription_duration,
+                new_conf.expiry_delta,
+            );
+            self.polling_delta
+                .store(new_conf.polling_delta, Ordering::Release);
+
+            conf.subscription_slots = new_conf.subscription_slots;
+            conf.subscription_duration = new_conf.subscription_duration;
+            conf.expiry_delta = new_conf.expiry_delta;
+            conf.min_to_self_delay = new_conf.min_to_self_delay;
+            conf.polling_delta = new_conf.polling_delta;
+            conf.debug = new_conf.debug;
+            conf.deps_debug = new_conf.deps_debug;
+        }
+
+        Ok(ReloadReport {
+            applied,
+            requires_restart,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+    use structopt::StructOpt;
+    use tempdir::TempDir;
+
+    use teos_common::test_utils::get_random_user_id;
+
+    use crate::dbm::DBM;
+    use crate::feed::Feed;
+
+    fn write_config(path: &Path, extra: &str) {
+        fs::write(
+            path,
+            format!("btc_rpc_user = \"user\"\nbtc_rpc_password = \"passwd\"\n{extra}"),
+        )
+        .unwrap();
+    }
+
+    fn init_reloader(conf_file_path: PathBuf, options: Opt) -> ConfigReloader {
+        let mut conf = config::try_from_file::<Config>(&conf_file_path).unwrap();
+        conf.patch_with_options(options.clone());
+        conf.verify().unwrap();
+
+        let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+        let gatekeeper = Arc::new(Gatekeeper::new(
+            0,
+            conf.subscription_slots,
+            conf.subscription_duration,
+            conf.expiry_delta,
+            conf.user_cache_size,
+            dbm,
+            Arc::new(Feed::new()),
+        ));
+        let polling_delta = Arc::new(AtomicU16::new(conf.polling_delta));
+        ConfigReloader::new(
+            conf_file_path,
+            options,
+            conf,
+            gatekeeper,
+            polling_delta,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_reload() {
+        let tmp_dir = TempDir::new("reload").unwrap();
+        let conf_file_path = tmp_dir.path().join("teos.toml");
+        write_config(&conf_file_path, "");
+        let reloader = init_reloader(conf_file_path.clone(), Opt::from_iter(["teosd"]));
+
+        // Nothing changes if the file has not changed
+        assert_eq!(reloader.reload().unwrap(), ReloadReport::default());
+
+        // Live options are applied, while the rest are only reported
+        write_config(
+            &conf_file_path,
+            "subscription_slots = 42\npolling_delta = 5\napi_port = 1234\n",
+        );
+        let report = reloader.reload().unwrap();
+        assert_eq!(report.applied, vec!["polling_delta", "subscription_slots"]);
+        assert_eq!(report.requires_restart, vec!["api_port"]);
+        assert_eq!(reloader.polling_delta.load(Ordering::Relaxed), 5);
+        assert_eq!(
+            reloader
+                .gatekeeper
+                .add_update_user(get_random_user_id())
+                .unwrap()
+                .available_slots(),
+            42
+        );
+        let conf = reloader.get_config();
+        assert_eq!(conf.subscription_slots, 42);
+        assert_eq!(conf.api_port, Config::default().api_port);
+
+        // Options that require a restart keep being reported until the tower is restarted
+        assert_eq!(
+            reloader.reload().unwrap(),
+            ReloadReport {
+                applied: Vec::new(),
+                requires_restart: vec!["api_port".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reload_command_line_options() {
+        let tmp_dir = TempDir::new("reload").unwrap();
+        let conf_file_path = tmp_dir.path().join("teos.toml");
+        write_config(&conf_file_path, "");
+        let reloader = init_reloader(conf_file_path.clone(), Opt::from_iter(["teosd", "--debug"]));
+
+        // Command line options keep overriding the config file
+        write_config(&conf_file_path, "debug = false\n");
+        assert_eq!(reloader.reload().unwrap(), ReloadReport::default());
+        assert!(reloader.get_config().debug);
+    }
+
+    #[test]
+    fn test_reload_invalid_config() {
+        let tmp_dir = TempDir::new("reload").unwrap();
+        let conf_file_path = tmp_dir.path().join("teos.toml");
+        write_config(&conf_file_path, "");
+        let reloader = init_reloader(conf_file_path.clone(), Opt::from_iter(["teosd"]));
+
+        // Nothing is applied if the config file cannot be parsed or is not valid
+        write_config(&conf_file_path, "subscription_slots = 42\npolling_delta = ");
+        assert!(reloader.reload().is_err());
+        write_config(
+            &conf_file_path,
+            "subscription_slots = 42\nlog_format = \"xml\"\n",
+        );
+        assert!(reloader.reload().is_err());
+        assert_eq!(
+            reloader.get_config().subscription_slots,
+            Config::default().subscription_slots
+        );
+    }
+}
@@ -7,15 +7,21 @@ use bitcoin::{consensus, BlockHash};
 use bitcoin::{BlockHeader, Transaction, Txid};
 use lightning::chain;
 use lightning_block_sync::poll::ValidatedBlock;
+use tracing::instrument;
 
+use teos_common::appointment::Locator;
 use teos_common::constants;
 use teos_common::protos as common_msgs;
 use teos_common::UserId;
 
 use crate::carrier::Carrier;
 use crate::dbm::DBM;
+use crate::events::{record_event, Event, EventKind};
 use crate::extended_appointment::UUID;
+use crate::feed::{Feed, FeedEvent, FeedEventKind};
 use crate::gatekeeper::Gatekeeper;
+use crate::metrics::Metrics;
+use crate::notifier::{Notification, Notifier, WebhookEvent};
 use crate::tx_index::TxIndex;
 use crate::watcher::Breach;
 
@@ -88,6 +94,33 @@ impl TransactionTracker {
             user_id,
         }
     }
+
+    /// Builds a [Notification] about this tracker for its owner.
+    pub(crate) fn get_notification(
+        &self,
+        event: WebhookEvent,
+        confirmation_height: Option<u32>,
+    ) -> Notification {
+        Notification::new(
+            event,
+            self.user_id,
+            self.dispute_tx.txid(),
+            Some(self.penalty_tx.txid()),
+            confirmation_height,
+        )
+    }
+
+    /// Builds an [Event] about this tracker for the audit history.
+    pub(crate) fn get_event(&self, uuid: UUID, kind: EventKind, height: u32) -> Event {
+        Event::new(
+            uuid,
+            self.user_id,
+            Locator::new(self.dispute_tx.txid()),
+            kind,
+            height,
+            Some(self.penalty_tx.txid()),
+        )
+    }
 }
 
 impl From<TransactionTracker> for common_msgs::Tracker {
@@ -133,16 +166,26 @@ pub struct Responder {
     dbm: Arc<Mutex<DBM>>,
     /// A list of all the reorged trackers that might need to be republished after reorg resolution.
     reorged_trackers: Mutex<HashSet<UUID>>,
+    /// A [Notifier] instance. Used to let users know about the progress of their penalties.
+    notifier: Arc<Notifier>,
+    /// The live [Feed] of the tower. Missed confirmations and reorged trackers are published to it.
+    feed: Arc<Feed>,
+    /// The tower [Metrics], if enabled.
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl Responder {
     /// Creates a new [Responder] instance.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         last_n_blocs: &[ValidatedBlock],
         last_known_block_height: u32,
         carrier: Carrier,
         gatekeeper: Arc<Gatekeeper>,
         dbm: Arc<Mutex<DBM>>,
+        notifier: Arc<Notifier>,
+        feed: Arc<Feed>,
+        metrics: Option<Arc<Metrics>>,
     ) -> Self {
         Responder {
             carrier: Mutex::new(carrier),
@@ -150,6 +193,16 @@ impl Responder {
             dbm,
             gatekeeper,
             reorged_trackers: Mutex::new(HashSet::new()),
+            notifier,
+            feed,
+            metrics,
+        }
+    }
+
+    /// Records the result of a penalty transaction broadcast, if metrics are enabled.
+    fn record_penalty(&self, accepted: bool) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_penalty(accepted);
         }
     }
 
@@ -188,11 +241,18 @@ impl Responder {
             // If it's in mempool we assume it was just included
             ConfirmationStatus::InMempoolSince(carrier.block_height())
         } else {
-            carrier.send_transaction(&breach.penalty_tx)
+            let status = carrier.send_transaction(&breach.penalty_tx);
+            self.record_penalty(status.accepted());
+            status
         };
 
         if status.accepted() {
             self.add_tracker(uuid, breach, user_id, status);
+        } else if let ConfirmationStatus::Rejected(reason) = status {
+            let event = TransactionTracker::new(breach, user_id, status)
+                .get_event(uuid, EventKind::PenaltyRejected, carrier.block_height())
+                .with_rejection_reason(reason);
+            record_event(&self.dbm.lock().unwrap(), &self.feed, event);
         }
 
         status
@@ -213,17 +273,36 @@ impl Responder {
         user_id: UserId,
         status: ConfirmationStatus,
     ) {
-        if self
-            .dbm
-            .lock()
-            .unwrap()
-            .store_tracker(uuid, &TransactionTracker::new(breach, user_id, status))
-            .is_ok()
-        {
-            log::info!("New tracker added (uuid={uuid})");
+        let tracker = TransactionTracker::new(breach, user_id, status);
+        let dbm = self.dbm.lock().unwrap();
+        if dbm.store_tracker(uuid, &tracker).is_ok() {
+            tracing::info!(%uuid, "New tracker added");
+            let (h, confirmed) = tracker.status.to_db_data().unwrap();
+            record_event(
+                &dbm,
+                &self.feed,
+                tracker.get_event(uuid, EventKind::PenaltyBroadcast, h),
+            );
+            if confirmed {
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker.get_event(uuid, EventKind::PenaltyConfirmed, h),
+                );
+            }
+
+            // WARNING(deadlock): The notifier uses the database as well, so the lock needs to be released first.
+            drop(dbm);
+            self.notifier
+                .notify(tracker.get_notification(WebhookEvent::PenaltyBroadcast, None));
+            if confirmed {
+                self.notifier
+                    .notify(tracker.get_notification(WebhookEvent::PenaltyConfirmed, Some(h)));
+            }
         } else {
-            log::error!(
-                "Failed to store tracker in database (uuid={uuid}). It might be already stored."
+            tracing::error!(
+                %uuid,
+                "Failed to store tracker in database. It might be already stored"
             );
         }
     }
@@ -238,41 +317,93 @@ impl Responder {
     /// For unconfirmed transactions, it checks whether they have been confirmed or keep missing confirmations.
     /// For confirmed transactions, nothing is done until they are completed (confirmation count reaches [IRREVOCABLY_RESOLVED](constants::IRREVOCABLY_RESOLVED))
     /// Returns the set of completed trackers or [None] if none were completed.
+    ///
+    /// Trackers are looked up by penalty txid and status, so only the ones affected by this block are loaded.
     fn check_confirmations(&self, txids: HashSet<Txid>, current_height: u32) -> Option<Vec<UUID>> {
         let mut completed_trackers = Vec::new();
+        let mut notifications = Vec::new();
         let mut reorged_trackers = self.reorged_trackers.lock().unwrap();
         let dbm = self.dbm.lock().unwrap();
 
-        for (uuid, penalty_summary) in dbm.load_penalties_summaries() {
-            if txids.contains(&penalty_summary.penalty_txid) {
-                // First confirmation was received
-                dbm.update_tracker_status(uuid, &ConfirmationStatus::ConfirmedIn(current_height))
-                    .unwrap();
-                // Remove that uuid from reorged trackers if it was confirmed.
-                reorged_trackers.remove(&uuid);
-            // TODO: We won't need this check when we persist the correct tracker status
-            // in the DB after migrations are supported.
-            } else if reorged_trackers.contains(&uuid) {
-                // Don't consider reorged trackers since they have wrong DB status.
-                continue;
-            } else if let ConfirmationStatus::ConfirmedIn(h) = penalty_summary.status {
-                let confirmations = current_height - h;
-                if confirmations == constants::IRREVOCABLY_RESOLVED {
-                    // Tracker is deep enough in the chain, it can be deleted
-                    completed_trackers.push(uuid);
-                } else {
-                    log::info!("{uuid} received a confirmation (count={confirmations})");
+        for uuid in dbm.load_uuids_by_penalty_txids(txids.iter().collect()) {
+            // First confirmation was received
+            dbm.update_tracker_status(uuid, &ConfirmationStatus::ConfirmedIn(current_height))
+                .unwrap();
+            // Remove that uuid from reorged trackers if it was confirmed.
+            reorged_trackers.remove(&uuid);
+            let tracker = dbm.load_tracker(uuid).unwrap();
+            record_event(
+                &dbm,
+                &self.feed,
+                tracker.get_event(uuid, EventKind::PenaltyConfirmed, current_height),
+            );
+            notifications.push(
+                tracker.get_notification(WebhookEvent::PenaltyConfirmed, Some(current_height)),
+            );
+        }
+
+        // Trackers that are deep enough in the chain can be deleted. Trackers confirmed at lower heights are also
+        // pulled, so they are not missed if some height is skipped.
+        if let Some(resolved_height) = current_height.checked_sub(constants::IRREVOCABLY_RESOLVED) {
+            for uuid in dbm.load_trackers_confirmed_up_to(resolved_height) {
+                // TODO: We won't need this check when we persist the correct tracker status
+                // in the DB after migrations are supported.
+                if reorged_trackers.contains(&uuid) {
+                    // Don't consider reorged trackers since they have wrong DB status.
+                    continue;
                 }
-            } else if let ConfirmationStatus::InMempoolSince(h) = penalty_summary.status {
-                // Log all transactions that have missed confirmations
-                log::info!(
-                    "Transaction missed a confirmation: {} (missed conf count: {})",
-                    penalty_summary.penalty_txid,
-                    current_height - h
+                completed_trackers.push(uuid);
+                let tracker = dbm.load_tracker(uuid).unwrap();
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker.get_event(uuid, EventKind::Completed, current_height),
                 );
+                if let ConfirmationStatus::ConfirmedIn(h) = tracker.status {
+                    notifications
+                        .push(tracker.get_notification(WebhookEvent::IrrevocablyResolved, Some(h)));
+                }
+            }
+        }
+
+        // All the penalties that are still unconfirmed have missed a confirmation. Counting them is cheap, while
+        // loading them is only worth it if someone is listening for the details
+        let missed = dbm.count_unconfirmed_penalties(&reorged_trackers);
+        if missed > 0 {
+            tracing::info!("{missed} transaction(s) missed a confirmation");
+            if let Some(metrics) = &self.metrics {
+                metrics.missed_confirmations.inc_by(missed as u64);
+            }
+        }
+        if missed > 0 && self.feed.has_subscribers(FeedEventKind::MissedConfirmation) {
+            for (uuid, penalty_summary) in dbm.load_unconfirmed_penalties_summaries() {
+                if reorged_trackers.contains(&uuid) {
+                    continue;
+                }
+                if let ConfirmationStatus::InMempoolSince(h) = penalty_summary.status {
+                    let missed_confirmations = current_height - h;
+                    self.feed.publish(FeedEvent::missed_confirmation(
+                        uuid,
+                        penalty_summary.penalty_txid,
+                        missed_confirmations,
+                    ));
+                    tracing::debug!(
+                        %uuid,
+                        txid = %penalty_summary.penalty_txid,
+                        missed_confirmations,
+                        "Transaction missed a confirmation"
+                    );
+                }
             }
         }
 
+        // WARNING(deadlock): The notifier uses the database as well, so locks need to be released first.
+        drop(dbm);
+        drop(reorged_trackers);
+        for notification in notifications {
+            self.notifier.notify(notification);
+        }
+
         (!completed_trackers.is_empty()).then_some(completed_trackers)
     }
 
@@ -292,28 +423,28 @@ impl Responder {
         // Republish all the dispute transactions of the reorged trackers.
         for uuid in reorged_trackers {
             let tracker = dbm.load_tracker(uuid).unwrap();
-            let dispute_txid = tracker.dispute_tx.txid();
+            let _span = tracing::info_span!("reorged_tracker", %uuid).entered();
             // Try to publish the dispute transaction.
             let should_publish_penalty = match carrier.send_transaction(&tracker.dispute_tx) {
                 ConfirmationStatus::InMempoolSince(_) => {
-                    log::info!(
-                        "Reorged dispute tx (txid={}) is in the mempool now",
-                        dispute_txid
-                    );
+                    tracing::info!("Reorged dispute tx is in the mempool now");
                     true
                 }
                 // NOTE: We aren't fully synced with the bitcoind backend so can't check if the dispute tx is in our txindex.
                 ConfirmationStatus::IrrevocablyResolved => {
-                    log::info!(
-                        "Reorged dispute tx (txid={}) is already on the strong chain",
-                        dispute_txid
-                    );
+                    tracing::info!("Reorged dispute tx is already on the strong chain");
                     true
                 }
                 ConfirmationStatus::Rejected(e) => {
-                    log::error!(
-                        "Reorged dispute tx (txid={}) rejected during rebroadcast (reason: {e:?})",
-                        dispute_txid
+                    tracing::error!(
+                        "Reorged dispute tx rejected during rebroadcast (reason: {e:?})"
+                    );
+                    record_event(
+                        &dbm,
+                        &self.feed,
+                        tracker
+                            .get_event(uuid, EventKind::PenaltyRejected, height)
+                            .with_rejection_reason(e),
                     );
                     false
                 }
@@ -325,11 +456,23 @@ impl Responder {
 
             if should_publish_penalty {
                 // Try to rebroadcast the penalty tx.
-                if let ConfirmationStatus::Rejected(_) =
-                    carrier.send_transaction(&tracker.penalty_tx)
-                {
+                let status = carrier.send_transaction(&tracker.penalty_tx);
+                self.record_penalty(!matches!(status, ConfirmationStatus::Rejected(_)));
+                if let ConfirmationStatus::Rejected(reason) = status {
+                    record_event(
+                        &dbm,
+                        &self.feed,
+                        tracker
+                            .get_event(uuid, EventKind::PenaltyRejected, height)
+                            .with_rejection_reason(reason),
+                    );
                     rejected.push(uuid)
                 } else {
+                    record_event(
+                        &dbm,
+                        &self.feed,
+                        tracker.get_event(uuid, EventKind::PenaltyBroadcast, height),
+                    );
                     // The penalty might actually be confirmed (ConfirmationStatus::IrrevocablyResolved) since bitcoind
                     // is fully synced with the stronger chain already, but we won't know which block was it confirmed in.
                     // We should see the tracker appear in the blockchain in the next couple of connected blocks.
@@ -349,41 +492,80 @@ impl Responder {
     /// This covers the case where a transaction is not getting confirmations (most likely due to low
     /// fess and needs to be bumped, but there is not much we can do until anchors).
     ///
-    /// Returns a vector of rejected trackers during rebroadcast if any were rejected, [None] otherwise.
-    fn rebroadcast_stale_txs(&self, height: u32) -> Option<Vec<UUID>> {
+    /// Penalties found to be already buried deep enough in the chain (e.g. because they got confirmed while the tower
+    /// was down and it was later force updated) are not rebroadcast, their trackers are completed instead.
+    ///
+    /// Returns the trackers rejected during rebroadcast and the ones completed, in that order.
+    fn rebroadcast_stale_txs(&self, height: u32) -> (Vec<UUID>, Vec<UUID>) {
+        let stale_height = match height.checked_sub(CONFIRMATIONS_BEFORE_RETRY as u32) {
+            Some(stale_height) => stale_height,
+            None => return (Vec::new(), Vec::new()),
+        };
         let dbm = self.dbm.lock().unwrap();
         let mut carrier = self.carrier.lock().unwrap();
         let mut rejected = Vec::new();
+        let mut completed = Vec::new();
+        let mut notifications = Vec::new();
 
-        // Retry sending trackers which have been in the mempool since more than `CONFIRMATIONS_BEFORE_RETRY` blocks.
-        let stale_confirmation_status =
-            ConfirmationStatus::InMempoolSince(height - CONFIRMATIONS_BEFORE_RETRY as u32);
-        // NOTE: Ideally this will only pull UUIDs which have been in mempool since `CONFIRMATIONS_BEFORE_RETRY`, but
-        // might also return ones which have been there for a longer period. This can only happen if the tower missed
-        // a couple of block connections due to a force update.
+        // Retry sending trackers which have been in the mempool for `CONFIRMATIONS_BEFORE_RETRY` blocks or more.
+        // NOTE: This pulls every tracker that has been in mempool for at least that long, not only the ones that reached
+        // `CONFIRMATIONS_BEFORE_RETRY` in this block, so none is missed if the tower skips some block connections (e.g.
+        // due to a force update). Rebroadcast trackers are in mempool since `height` afterwards.
         for uuid in dbm
-            .load_trackers_with_confirmation_status(stale_confirmation_status)
+            .load_trackers_with_confirmation_status(ConfirmationStatus::InMempoolSince(
+                stale_height,
+            ))
             .unwrap()
         {
             let tracker = dbm.load_tracker(uuid).unwrap();
-            log::warn!(
-                "Penalty transaction has missed many confirmations: {}",
-                tracker.penalty_tx.txid()
+            tracing::warn!(
+                %uuid,
+                txid = %tracker.penalty_tx.txid(),
+                "Penalty transaction has missed many confirmations"
             );
             // Rebroadcast the penalty transaction.
             let status = carrier.send_transaction(&tracker.penalty_tx);
-            if let ConfirmationStatus::Rejected(_) = status {
+            if status == ConfirmationStatus::IrrevocablyResolved {
+                // The confirmation height is unknown, but it is deep enough for the tracker to be done with.
+                tracing::info!(%uuid, "Penalty transaction already irrevocably resolved");
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker.get_event(uuid, EventKind::Completed, height),
+                );
+                notifications
+                    .push(tracker.get_notification(WebhookEvent::IrrevocablyResolved, None));
+                completed.push(uuid);
+                continue;
+            }
+            self.record_penalty(!matches!(status, ConfirmationStatus::Rejected(_)));
+            if let ConfirmationStatus::Rejected(reason) = status {
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker
+                        .get_event(uuid, EventKind::PenaltyRejected, height)
+                        .with_rejection_reason(reason),
+                );
                 rejected.push(uuid);
             } else {
-                // DISCUSS: What if the tower was down for some time and was later force updated while this penalty got on-chain?
-                // Sending it will yield `ConfirmationStatus::IrrevocablyResolved` which would panic here.
-                // We might want to replace `ConfirmationStatus::IrrevocablyResolved` variant with
-                // `ConfirmationStatus::ConfirmedIn(height - IRREVOCABLY_RESOLVED)
+                record_event(
+                    &dbm,
+                    &self.feed,
+                    tracker.get_event(uuid, EventKind::PenaltyBroadcast, height),
+                );
                 dbm.update_tracker_status(uuid, &status).unwrap();
             }
         }
 
-        (!rejected.is_empty()).then_some(rejected)
+        // WARNING(deadlock): The notifier uses the database as well, so locks need to be released first.
+        drop(dbm);
+        drop(carrier);
+        for notification in notifications {
+            self.notifier.notify(notification);
+        }
+
+        (rejected, completed)
     }
 }
 
@@ -399,13 +581,14 @@ impl chain::Listen for Responder {
     /// Every time a block is received the tracking conditions are checked against the monitored [TransactionTracker]s and
     /// data deletion is performed accordingly. Moreover, lack of confirmations is check for the tracked transactions and
     /// rebroadcasting is performed for those that have missed too many.
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn filtered_block_connected(
         &self,
         header: &BlockHeader,
         txdata: &chain::transaction::TransactionData,
         height: u32,
     ) {
-        log::info!("New block received: {}", header.block_hash());
+        tracing::info!("New block received");
         self.carrier.lock().unwrap().update_height(height);
 
         let txs = txdata
@@ -430,8 +613,10 @@ impl chain::Listen for Responder {
         }
 
         // Rebroadcast those transactions that need to
-        if let Some(trackers) = self.rebroadcast_stale_txs(height) {
-            trackers_to_delete.extend(trackers);
+        let (rejected, completed) = self.rebroadcast_stale_txs(height);
+        trackers_to_delete.extend(rejected);
+        if !completed.is_empty() {
+            self.gatekeeper.delete_appointments(completed, true);
         }
 
         if !trackers_to_delete.is_empty() {
@@ -444,8 +629,9 @@ impl chain::Listen for Responder {
     }
 
     /// Handles reorgs in the [Responder].
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn block_disconnected(&self, header: &BlockHeader, height: u32) {
-        log::warn!("Block disconnected: {}", header.block_hash());
+        tracing::warn!("Block disconnected");
         // Update the carrier and our tx_index.
         self.carrier.lock().unwrap().update_height(height);
         self.tx_index
@@ -456,13 +642,24 @@ impl chain::Listen for Responder {
         // TODO: Not only confirmed trackers need to be marked as reorged, but trackers that hasn't confirmed but their
         // dispute did confirm in the reorged block. We can pull dispute txids of non confirmed penalties and get their
         // confirmation block from our tx_index.
-        self.reorged_trackers.lock().unwrap().extend(
-            self.dbm
-                .lock()
-                .unwrap()
-                .load_trackers_with_confirmation_status(ConfirmationStatus::ConfirmedIn(height))
-                .unwrap(),
-        );
+        let reorged_trackers = self
+            .dbm
+            .lock()
+            .unwrap()
+            .load_trackers_with_confirmation_status(ConfirmationStatus::ConfirmedIn(height))
+            .unwrap();
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .reorged_trackers
+                .inc_by(reorged_trackers.len() as u64);
+        }
+        for uuid in reorged_trackers.iter() {
+            self.feed.publish(FeedEvent::tracker_reorged(*uuid, height));
+        }
+        self.reorged_trackers
+            .lock()
+            .unwrap()
+            .extend(reorged_trackers);
     }
 }
 
@@ -482,10 +679,11 @@ mod tests {
         create_carrier, generate_dummy_appointment, generate_dummy_appointment_with_user,
         generate_uuid, get_last_n_blocks, get_random_breach, get_random_tracker, get_random_tx,
         store_appointment_and_its_user, BitcoindStopper, Blockchain, MockedServerQuery, DURATION,
-        EXPIRY_DELTA, SLOTS, START_HEIGHT,
+        EXPIRY_DELTA, SLOTS, START_HEIGHT, USER_CACHE_SIZE,
     };
 
     use teos_common::constants::IRREVOCABLY_RESOLVED;
+    use teos_common::cryptography::get_random_keypair;
     use teos_common::test_utils::get_random_user_id;
 
     impl TransactionTracker {
@@ -564,7 +762,16 @@ mod tests {
 
         let (carrier, bitcoind_stopper) = create_carrier(query, chain.tip().height);
         (
-            Responder::new(&last_n_blocks, chain.tip().height, carrier, gatekeeper, dbm),
+            Responder::new(
+                &last_n_blocks,
+                chain.tip().height,
+                carrier,
+                gatekeeper,
+                dbm.clone(),
+                Arc::new(Notifier::new(get_random_keypair().0, dbm)),
+                Arc::new(Feed::new()),
+                None,
+            ),
             bitcoind_stopper,
         )
     }
@@ -579,7 +786,9 @@ mod tests {
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            USER_CACHE_SIZE,
             dbm.clone(),
+            Arc::new(Feed::new()),
         );
         create_responder(chain, Arc::new(gk), dbm, mocked_query).await
     }
@@ -756,6 +965,15 @@ mod tests {
             ConfirmationStatus::Rejected(rpc_errors::RPC_VERIFY_ERROR)
         );
         assert!(!responder.has_tracker(uuid));
+
+        // The rejection is recorded in the audit history
+        let events = responder.dbm.lock().unwrap().load_events(Some(uuid), None);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EventKind::PenaltyRejected);
+        assert_eq!(
+            events[0].rejection_reason,
+            Some(rpc_errors::RPC_VERIFY_ERROR)
+        );
     }
 
     #[tokio::test]
@@ -883,6 +1101,7 @@ mod tests {
     async fn test_check_confirmations() {
         let (responder, _s) = init_responder(MockedServerQuery::Regular).await;
         let target_height = (START_HEIGHT * 2) as u32;
+        let not_resolved_height = target_height - constants::IRREVOCABLY_RESOLVED + 1;
 
         // Unconfirmed transactions that miss a confirmation will be added to missed_confirmations (if not there) or their missed confirmation count till be increased
         let mut in_mempool = HashSet::new();
@@ -920,7 +1139,7 @@ mod tests {
                         uuid,
                         breach.clone(),
                         user_id,
-                        ConfirmationStatus::ConfirmedIn(42),
+                        ConfirmationStatus::ConfirmedIn(not_resolved_height),
                     );
                     confirmed.insert(uuid);
                 }
@@ -982,11 +1201,118 @@ mod tests {
                     .load_tracker(uuid)
                     .unwrap()
                     .status,
-                ConfirmationStatus::ConfirmedIn(42)
+                ConfirmationStatus::ConfirmedIn(not_resolved_height)
             );
         }
     }
 
+    #[tokio::test]
+    async fn test_check_confirmations_skipped_height() {
+        let (responder, _s) = init_responder(MockedServerQuery::Regular).await;
+        let (user_id, uuid) = responder.store_dummy_appointment_to_db();
+        let height = START_HEIGHT as u32;
+        responder.add_tracker(
+            uuid,
+            get_random_breach(),
+            user_id,
+            ConfirmationStatus::ConfirmedIn(height),
+        );
+
+        // The height at which the tracker is irrevocably resolved is skipped, but it is completed nonetheless
+        assert_eq!(
+            responder
+                .check_confirmations(HashSet::new(), height + constants::IRREVOCABLY_RESOLVED + 2),
+            Some(vec![uuid])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_confirmations_events() {
+        let (responder, _s) = init_responder(MockedServerQuery::Regular).await;
+        let (user_id, uuid) = responder.store_dummy_appointment_to_db();
+        let breach = get_random_breach();
+        let penalty_txid = breach.penalty_tx.txid();
+        let height = START_HEIGHT as u32;
+        responder.add_tracker(
+            uuid,
+            breach,
+            user_id,
+            ConfirmationStatus::InMempoolSince(height),
+        );
+
+        responder.check_confirmations(HashSet::from_iter([penalty_txid]), height + 1);
+        assert!(responder
+            .check_confirmations(HashSet::new(), height + 1 + constants::IRREVOCABLY_RESOLVED)
+            .is_some());
+
+        let events = responder.dbm.lock().unwrap().load_events(Some(uuid), None);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.kind, e.height, e.txid))
+                .collect::<Vec<_>>(),
+            vec![
+                (EventKind::PenaltyBroadcast, height, Some(penalty_txid)),
+                (EventKind::PenaltyConfirmed, height + 1, Some(penalty_txid)),
+                (
+                    EventKind::Completed,
+                    height + 1 + constants::IRREVOCABLY_RESOLVED,
+                    Some(penalty_txid)
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_confirmations_notifications() {
+        let (responder, _s) = init_responder(MockedServerQuery::Regular).await;
+        let (user_id, uuid) = responder.store_dummy_appointment_to_db();
+        responder
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, "http://localhost/hook")
+            .unwrap();
+        let get_events = || {
+            responder
+                .dbm
+                .lock()
+                .unwrap()
+                .load_due_notifications(u32::MAX as u64)
+                .iter()
+                .map(|n| {
+                    let notification: Notification = serde_json::from_str(&n.payload).unwrap();
+                    (notification.event, notification.confirmation_height)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The user is notified when the penalty is broadcast
+        let breach = get_random_breach();
+        let height = START_HEIGHT as u32;
+        responder.add_tracker(
+            uuid,
+            breach.clone(),
+            user_id,
+            ConfirmationStatus::InMempoolSince(height),
+        );
+        assert_eq!(get_events(), vec![(WebhookEvent::PenaltyBroadcast, None)]);
+
+        // When it confirms
+        responder.check_confirmations(HashSet::from_iter([breach.penalty_tx.txid()]), height + 1);
+        assert_eq!(
+            get_events()[1..],
+            [(WebhookEvent::PenaltyConfirmed, Some(height + 1))]
+        );
+
+        // And when it is irrevocably resolved
+        responder.check_confirmations(HashSet::new(), height + 1 + IRREVOCABLY_RESOLVED);
+        assert_eq!(
+            get_events()[2..],
+            [(WebhookEvent::IrrevocablyResolved, Some(height + 1))]
+        );
+    }
+
     #[tokio::test]
     async fn test_handle_reorged_txs() {
         let (responder, _s) = init_responder(MockedServerQuery::InMempoool).await;
@@ -1076,8 +1402,8 @@ mod tests {
             statues.insert(uuid, status);
         }
 
-        // There should be no rejected tx.
-        assert!(responder.rebroadcast_stale_txs(height).is_none());
+        // There should be no rejected (nor completed) tx.
+        assert_eq!(responder.rebroadcast_stale_txs(height), (vec![], vec![]));
 
         for (uuid, former_status) in statues {
             let status = responder
@@ -1124,7 +1450,9 @@ mod tests {
 
         // `rebroadcast_stale_txs` will broadcast txs which has been in mempool since `CONFIRMATIONS_BEFORE_RETRY` or more
         // blocks. Since our backend rejects all the txs, all these broadcasted txs should be returned from this method (rejected).
-        let rejected = HashSet::from_iter(responder.rebroadcast_stale_txs(height).unwrap());
+        let (rejected, completed) = responder.rebroadcast_stale_txs(height);
+        assert!(completed.is_empty());
+        let rejected = HashSet::from_iter(rejected);
         let should_reject: HashSet<_> = statues
             .iter()
             .filter_map(|(&uuid, &status)| {
@@ -1150,6 +1478,46 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_rebroadcast_stale_txs_irrevocably_resolved() {
+        // The penalties got buried in the chain while the tower was not looking (e.g. it was force updated)
+        let (responder, _s) = init_responder(MockedServerQuery::Error(
+            rpc_errors::RPC_VERIFY_ALREADY_IN_CHAIN as i64,
+        ))
+        .await;
+        let height = 100;
+        let stale_uuid = responder
+            .add_random_tracker(ConfirmationStatus::InMempoolSince(
+                height - CONFIRMATIONS_BEFORE_RETRY as u32,
+            ))
+            .uuid();
+        let fresh_uuid = responder
+            .add_random_tracker(ConfirmationStatus::InMempoolSince(height - 1))
+            .uuid();
+
+        // Stale trackers are completed instead of being rebroadcast (and the call does not panic)
+        assert_eq!(
+            responder.rebroadcast_stale_txs(height),
+            (vec![], vec![stale_uuid])
+        );
+        let dbm = responder.dbm.lock().unwrap();
+        assert_eq!(
+            dbm.load_tracker(stale_uuid).unwrap().status,
+            ConfirmationStatus::InMempoolSince(height - CONFIRMATIONS_BEFORE_RETRY as u32)
+        );
+        assert_eq!(
+            dbm.load_tracker(fresh_uuid).unwrap().status,
+            ConfirmationStatus::InMempoolSince(height - 1)
+        );
+        assert_eq!(
+            dbm.load_events(Some(stale_uuid), None)
+                .iter()
+                .map(|e| e.kind)
+                .collect::<Vec<_>>(),
+            vec![EventKind::Completed]
+        );
+    }
+
     #[tokio::test]
     async fn test_filtered_block_connected() {
         let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
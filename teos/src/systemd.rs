@@ -0,0 +1,227 @@
+//! Logic related to notifying the service manager (systemd) about the state of the tower.
+//!
+//! Notifications follow the `sd_notify(3)` protocol. They are only sent if the tower has been started by systemd
+//! (that is, if `NOTIFY_SOCKET` is set), otherwise they are ignored.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::process;
+use std::time::Duration;
+
+/// Environment variable systemd sets with the path of the socket notifications are sent to.
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+/// Environment variable systemd sets with the watchdog interval, in microseconds, if the watchdog is enabled.
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+/// Environment variable systemd sets with the pid of the process the watchdog is meant for.
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/// Component in charge of sending state notifications to systemd.
+#[derive(Clone, Debug, Default)]
+pub struct SystemdNotifier {
+    /// The socket notifications are sent to, if any. Abstract sockets are prefixed by `@`.
+    socket: Option<OsString>,
+    /// The time after which systemd considers the tower as hung if no watchdog ping has been received.
+    watchdog_interval: Option<Duration>,
+}
+
+impl SystemdNotifier {
+    /// Creates a new [SystemdNotifier] instance.
+    pub fn new(socket: Option<OsString>, watchdog_interval: Option<Duration>) -> Self {
+        SystemdNotifier {
+            socket,
+            watchdog_interval,
+        }
+    }
+
+    /// Creates a new [SystemdNotifier] instance from the environment systemd sets for the services it runs.
+    pub fn from_env() -> Self {
+        SystemdNotifier::from_vars(
+            env::var_os(NOTIFY_SOCKET_ENV),
+            env::var(WATCHDOG_USEC_ENV).ok(),
+            env::var(WATCHDOG_PID_ENV).ok(),
+        )
+    }
+
+    /// Creates a new [SystemdNotifier] instance from the values of the systemd environment variables.
+    ///
+    /// The watchdog is only enabled if it is meant for this process.
+    fn from_vars(
+        socket: Option<OsString>,
+        watchdog_usec: Option<String>,
+        watchdog_pid: Option<String>,
+    ) -> Self {
+        let watchdog_for_us = match watchdog_pid {
+            Some(pid) => pid == process::id().to_string(),
+            None => true,
+        };
+        let watchdog_interval = watchdog_usec
+            .filter(|_| watchdog_for_us)
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros);
+
+        SystemdNotifier::new(socket.filter(|s| !s.is_empty()), watchdog_interval)
+    }
+
+    /// Whether the tower has been started by systemd.
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Gets the watchdog interval, if the watchdog is enabled.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Notifies systemd that the tower has finished starting up.
+    pub fn ready(&self) {
+        self.notify("READY=1\nSTATUS=Tower ready");
+    }
+
+    /// Sends a free-form status message to systemd.
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={status}"));
+    }
+
+    /// Pings the systemd watchdog, if enabled.
+    pub fn watchdog(&self) {
+        if self.watchdog_interval.is_some() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+
+    /// Notifies systemd that the tower is shutting down.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=Shutting down");
+    }
+
+    /// Sends a notification to systemd. Failing to do so is logged but otherwise ignored.
+    fn notify(&self, state: &str) {
+        if let Some(socket) = self.socket.as_ref() {
+            if let Err(e) = send(socket, state) {
+                tracing::warn!("Cannot notify systemd: {e}");
+            }
+        }
+    }
+}
+
+/// Sends the given state to the notification socket.
+#[cfg(unix)]
+fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            datagram.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+        }
+        _ => datagram.send_to(state.as_bytes(), socket),
+    }
+    .map(|_| ())
+}
+
+/// Sends the given state to the notification socket.
+#[cfg(not(unix))]
+fn send(_: &OsStr, _: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "systemd notifications are only supported on unix",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixDatagram;
+
+    use crate::test_utils::NotifySocket;
+
+    #[test]
+    fn test_from_vars() {
+        let socket = Some(OsString::from("/run/systemd/notify"));
+        let pid = process::id().to_string();
+
+        let notifier = SystemdNotifier::from_vars(None, None, None);
+        assert!(!notifier.is_enabled());
+        assert_eq!(notifier.watchdog_interval(), None);
+
+        let notifier = SystemdNotifier::from_vars(Some(OsString::new()), None, None);
+        assert!(!notifier.is_enabled());
+
+        let notifier =
+            SystemdNotifier::from_vars(socket.clone(), Some("30000000".to_owned()), Some(pid));
+        assert!(notifier.is_enabled());
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(30)));
+
+        let notifier =
+            SystemdNotifier::from_vars(socket.clone(), Some("30000000".to_owned()), None);
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(30)));
+
+        // The watchdog is disabled if it is meant for some other process or the interval is not valid
+        let notifier = SystemdNotifier::from_vars(
+            socket.clone(),
+            Some("30000000".to_owned()),
+            Some((process::id() + 1).to_string()),
+        );
+        assert_eq!(notifier.watchdog_interval(), None);
+        for usec in ["0", "thirty"] {
+            let notifier = SystemdNotifier::from_vars(socket.clone(), Some(usec.to_owned()), None);
+            assert_eq!(notifier.watchdog_interval(), None);
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let socket = NotifySocket::new();
+        let notifier = socket.notifier(Some(Duration::from_secs(30)));
+
+        notifier.status("Fetching blocks");
+        assert_eq!(socket.recv().unwrap(), "STATUS=Fetching blocks");
+        notifier.ready();
+        assert_eq!(socket.recv().unwrap(), "READY=1\nSTATUS=Tower ready");
+        notifier.watchdog();
+        assert_eq!(socket.recv().unwrap(), "WATCHDOG=1");
+        notifier.stopping();
+        assert_eq!(socket.recv().unwrap(), "STOPPING=1\nSTATUS=Shutting down");
+        assert_eq!(socket.recv(), None);
+    }
+
+    #[test]
+    fn test_watchdog_disabled() {
+        let socket = NotifySocket::new();
+        socket.notifier(None).watchdog();
+        assert_eq!(socket.recv(), None);
+    }
+
+    #[test]
+    fn test_notify_not_enabled() {
+        // Nothing is sent (nor fails) if the tower is not run by systemd
+        let notifier = SystemdNotifier::default();
+        notifier.ready();
+        notifier.watchdog();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_notify_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("teos-test-{}", process::id());
+        let socket =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes()).unwrap())
+                .unwrap();
+        SystemdNotifier::new(Some(OsString::from(format!("@{name}"))), None).ready();
+
+        let mut buf = [0; 256];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=Tower ready");
+    }
+}
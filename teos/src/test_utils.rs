@@ -8,8 +8,19 @@
 */
 
 use rand::Rng;
+#[cfg(unix)]
+use std::ffi::OsString;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU16;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+use structopt::StructOpt;
+#[cfg(unix)]
+use tempdir::TempDir;
 
 use jsonrpc_http_server::jsonrpc_core::error::ErrorCode as JsonRpcErrorCode;
 use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, IoHandler, Params, Value};
@@ -35,24 +46,36 @@ use lightning_block_sync::{
     AsyncBlockSourceResult, BlockHeaderData, BlockSource, BlockSourceError, UnboundedCache,
 };
 
+use teos_common::announcement::TowerAnnouncement;
 use teos_common::constants::IRREVOCABLY_RESOLVED;
 use teos_common::cryptography::{get_random_bytes, get_random_keypair};
 use teos_common::test_utils::{generate_random_appointment, get_random_user_id, TXID_HEX, TX_HEX};
 use teos_common::UserId;
 
 use crate::api::internal::InternalAPI;
+use crate::api::tor::TorAPI;
+use crate::api::FEATURES;
+use crate::backup::BackupManager;
 use crate::carrier::Carrier;
+use crate::config::{Config, Opt};
 use crate::dbm::DBM;
 use crate::extended_appointment::{ExtendedAppointment, UUID};
+use crate::feed::Feed;
 use crate::gatekeeper::{Gatekeeper, UserInfo};
+use crate::notifier::Notifier;
 use crate::protos as msgs;
+use crate::reload::ConfigReloader;
 use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
 use crate::rpc_errors;
+#[cfg(unix)]
+use crate::systemd::SystemdNotifier;
 use crate::watcher::{Breach, Watcher};
 
 pub(crate) const SLOTS: u32 = 21;
 pub(crate) const DURATION: u32 = 500;
 pub(crate) const EXPIRY_DELTA: u32 = 42;
+pub(crate) const USER_CACHE_SIZE: usize = 100;
+pub(crate) const MIN_TO_SELF_DELAY: u32 = 20;
 pub(crate) const START_HEIGHT: usize = 100;
 
 pub(crate) const AVAILABLE_SLOTS: u32 = 21;
@@ -65,6 +88,7 @@ pub(crate) struct Blockchain {
     without_blocks: Option<std::ops::RangeFrom<usize>>,
     without_headers: bool,
     malformed_headers: bool,
+    block_delay: Option<Duration>,
     pub unreachable: Arc<Mutex<bool>>,
 }
 
@@ -120,6 +144,13 @@ impl Blockchain {
         }
     }
 
+    pub fn with_block_delay(self, delay: Duration) -> Self {
+        Self {
+            block_delay: Some(delay),
+            ..self
+        }
+    }
+
     pub fn unreachable(self) -> Self {
         Self {
             unreachable: Arc::new(Mutex::new(true)),
@@ -247,6 +278,10 @@ impl BlockSource for Blockchain {
 
     fn get_block<'a>(&'a self, header_hash: &'a BlockHash) -> AsyncBlockSourceResult<'a, Block> {
         Box::pin(async move {
+            if let Some(delay) = self.block_delay {
+                tokio::time::sleep(delay).await;
+            }
+
             for (height, block) in self.blocks.iter().enumerate() {
                 if block.header.block_hash() == *header_hash {
                     if let Some(without_blocks) = &self.without_blocks {
@@ -394,6 +429,7 @@ pub(crate) async fn create_responder(
     gatekeeper: Arc<Gatekeeper>,
     dbm: Arc<Mutex<DBM>>,
     server_url: &str,
+    feed: Arc<Feed>,
 ) -> Responder {
     let height = chain.tip().height;
     // For the local TxIndex logic to be sound, our index needs to have, at least, IRREVOCABLY_RESOLVED blocks
@@ -405,7 +441,17 @@ pub(crate) async fn create_responder(
     let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
     let carrier = Carrier::new(bitcoin_cli, bitcoind_reachable, height);
 
-    Responder::new(&last_n_blocks, height, carrier, gatekeeper, dbm)
+    let notifier = Arc::new(Notifier::new(get_random_keypair().0, dbm.clone()));
+    Responder::new(
+        &last_n_blocks,
+        height,
+        carrier,
+        gatekeeper,
+        dbm,
+        notifier,
+        feed,
+        None,
+    )
 }
 
 pub(crate) async fn create_watcher(
@@ -414,6 +460,7 @@ pub(crate) async fn create_watcher(
     gatekeeper: Arc<Gatekeeper>,
     bitcoind_mock: BitcoindMock,
     dbm: Arc<Mutex<DBM>>,
+    feed: Arc<Feed>,
 ) -> (Watcher, BitcoindStopper) {
     let last_n_blocks = get_last_n_blocks(chain, 6).await;
 
@@ -428,7 +475,10 @@ pub(crate) async fn create_watcher(
             chain.get_block_count(),
             tower_sk,
             tower_id,
-            dbm,
+            dbm.clone(),
+            Arc::new(Notifier::new(tower_sk, dbm)),
+            feed,
+            None,
         ),
         bitcoind_mock.stopper,
     )
@@ -438,6 +488,8 @@ pub(crate) struct ApiConfig {
     slots: u32,
     duration: u32,
     bitcoind_reachable: bool,
+    tor_api: Option<Arc<TorAPI>>,
+    conf_file_path: PathBuf,
 }
 
 impl ApiConfig {
@@ -445,7 +497,7 @@ impl ApiConfig {
         Self {
             slots,
             duration,
-            bitcoind_reachable: true,
+            ..Default::default()
         }
     }
 
@@ -453,6 +505,16 @@ impl ApiConfig {
         self.bitcoind_reachable = false;
         self.clone()
     }
+
+    pub fn with_tor_api(&mut self, tor_api: Arc<TorAPI>) -> Self {
+        self.tor_api = Some(tor_api);
+        self.clone()
+    }
+
+    pub fn with_conf_file(&mut self, conf_file_path: PathBuf) -> Self {
+        self.conf_file_path = conf_file_path;
+        self.clone()
+    }
 }
 
 impl Default for ApiConfig {
@@ -461,6 +523,8 @@ impl Default for ApiConfig {
             slots: SLOTS,
             duration: DURATION,
             bitcoind_reachable: true,
+            tor_api: None,
+            conf_file_path: std::env::temp_dir().join("teos.toml"),
         }
     }
 }
@@ -472,32 +536,90 @@ pub(crate) async fn create_api_with_config(
     let mut chain = Blockchain::default().with_height(START_HEIGHT);
 
     let dbm = Arc::new(Mutex::new(DBM::in_memory().unwrap()));
+    let feed = Arc::new(Feed::new());
     let gk = Arc::new(Gatekeeper::new(
         chain.get_block_count(),
         api_config.slots,
         api_config.duration,
         EXPIRY_DELTA,
+        USER_CACHE_SIZE,
         dbm.clone(),
+        feed.clone(),
     ));
-    let responder =
-        create_responder(&mut chain, gk.clone(), dbm.clone(), bitcoind_mock.url()).await;
+    let responder = create_responder(
+        &mut chain,
+        gk.clone(),
+        dbm.clone(),
+        bitcoind_mock.url(),
+        feed.clone(),
+    )
+    .await;
     let (watcher, stopper) = create_watcher(
         &mut chain,
         Arc::new(responder),
         gk.clone(),
         bitcoind_mock,
         dbm.clone(),
+        feed.clone(),
     )
     .await;
 
+    let mut addresses = vec![msgs::NetworkAddress::from_ipv4("address".to_string(), 21)];
+    if let Some(tor_api) = api_config.tor_api.as_ref() {
+        addresses.push(msgs::NetworkAddress::from_torv3(
+            tor_api.get_onion_address(),
+            21,
+        ));
+    }
+
+    let mut announcement = TowerAnnouncement::new(
+        watcher.tower_id,
+        addresses.iter().map(|a| a.to_string()).collect(),
+        FEATURES.iter().map(|f| f.to_string()).collect(),
+        MIN_TO_SELF_DELAY,
+        api_config.slots,
+        api_config.duration,
+    );
+    announcement.sign(&watcher.get_signing_key());
+
+    // The tower config, as if it was read from a config file setting only the bitcoind credentials
+    let mut conf = Config {
+        btc_rpc_user: "user".to_owned(),
+        btc_rpc_password: "passwd".to_owned(),
+        subscription_slots: api_config.slots,
+        subscription_duration: api_config.duration,
+        expiry_delta: EXPIRY_DELTA,
+        min_to_self_delay: MIN_TO_SELF_DELAY as u16,
+        ..Default::default()
+    };
+    conf.verify().unwrap();
+    let config_reloader = Arc::new(ConfigReloader::new(
+        api_config.conf_file_path,
+        Opt::from_iter(["teosd"]),
+        conf,
+        gk,
+        Arc::new(AtomicU16::new(1)),
+        None,
+    ));
+
     let bitcoind_reachable = Arc::new((Mutex::new(api_config.bitcoind_reachable), Condvar::new()));
-    let (shutdown_trigger, _) = triggered::trigger();
+    let (shutdown_trigger, shutdown_signal) = triggered::trigger();
     (
         Arc::new(InternalAPI::new(
             Arc::new(watcher),
-            vec![msgs::NetworkAddress::from_ipv4("address".to_string(), 21)],
+            addresses,
+            announcement,
+            api_config.tor_api,
             bitcoind_reachable,
             shutdown_trigger,
+            shutdown_signal,
+            Arc::new(BackupManager::new(
+                dbm,
+                std::env::temp_dir(),
+                "regtest".to_owned(),
+            )),
+            config_reloader,
+            feed,
         )),
         stopper,
     )
@@ -622,3 +744,40 @@ pub(crate) fn start_server(server: Server) {
         server.wait();
     });
 }
+
+/// A fake systemd notification socket.
+#[cfg(unix)]
+pub(crate) struct NotifySocket {
+    socket: UnixDatagram,
+    _tmp_dir: TempDir,
+    path: OsString,
+}
+
+#[cfg(unix)]
+impl NotifySocket {
+    pub(crate) fn new() -> Self {
+        let tmp_dir = TempDir::new("systemd").unwrap();
+        let path = tmp_dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        NotifySocket {
+            socket,
+            _tmp_dir: tmp_dir,
+            path: path.into_os_string(),
+        }
+    }
+
+    pub(crate) fn notifier(&self, watchdog_interval: Option<Duration>) -> SystemdNotifier {
+        SystemdNotifier::new(Some(self.path.clone()), watchdog_interval)
+    }
+
+    /// Gets the next received notification, if any.
+    pub(crate) fn recv(&self) -> Option<String> {
+        let mut buf = [0; 256];
+        match self.socket.recv(&mut buf) {
+            Ok(n) => Some(String::from_utf8(buf[..n].to_vec()).unwrap()),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+            Err(e) => panic!("Cannot read from the notify socket: {}", e),
+        }
+    }
+}
@@ -5,9 +5,36 @@
  * https://github.com/ElementsProject/lightning/blob/master/LICENSE
 */
 
+use futures::Stream;
 use rcgen::{Certificate, KeyPair, RcgenError};
 use std::convert::TryFrom;
-use std::path::Path;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Time given to clients to complete the TLS handshake with the public APIs.
+const HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// Time to wait (in milliseconds) before accepting connections again after an error (e.g. running out of file
+/// descriptors). Doubled on consecutive errors up to [MAX_ACCEPT_BACKOFF].
+pub(crate) const ACCEPT_BACKOFF: u64 = 10;
+
+/// Maximum time to wait (in milliseconds) before accepting connections again after an error.
+pub(crate) const MAX_ACCEPT_BACKOFF: u64 = 1000;
+
+/// How often (in seconds) the public TLS certificate and key are checked for changes on disk.
+const CERT_RELOAD_INTERVAL: u64 = 60;
+
+/// The PEM encoded certificate chain and private key, as read from disk.
+type PemFiles = (Vec<u8>, Vec<u8>);
 
 /// Packs the reasons why generating mtls certificates may fail.
 #[derive(Debug)]
@@ -71,10 +98,10 @@ fn generate_or_load_identity(
     let key_path = directory.join(format!("{filename}-key.pem"));
     // Did we have to generate a new key? In that case we also need to regenerate the certificate.
     if !key_path.exists() || !cert_path.exists() {
-        log::debug!("Generating a new keypair in {key_path:?}, it didn't exist",);
+        tracing::debug!("Generating a new keypair in {key_path:?}, it didn't exist",);
         let keypair = KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
         std::fs::write(&key_path, keypair.serialize_pem())?;
-        log::debug!("Generating a new certificate for key {key_path:?} at {cert_path:?}",);
+        tracing::debug!("Generating a new certificate for key {key_path:?} at {cert_path:?}",);
 
         // Configure the certificate we want.
         let subject_alt_names = vec!["cln".to_string(), "localhost".to_string()];
@@ -104,3 +131,310 @@ fn generate_or_load_identity(
     let certificate = std::fs::read(cert_path)?;
     Ok(Identity { certificate, key })
 }
+
+/// Packs the reasons why loading the certificate used to serve the public APIs over TLS may fail.
+#[derive(Debug)]
+pub enum LoadCertificateFailure {
+    IoError(std::io::Error),
+    InvalidCertificate(String),
+    InvalidKey(String),
+}
+
+impl fmt::Display for LoadCertificateFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadCertificateFailure::IoError(e) => write!(f, "{e}"),
+            LoadCertificateFailure::InvalidCertificate(e) => write!(f, "{e}"),
+            LoadCertificateFailure::InvalidKey(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadCertificateFailure {
+    fn from(e: std::io::Error) -> Self {
+        LoadCertificateFailure::IoError(e)
+    }
+}
+
+/// Reads the certificate chain and private key files.
+fn read_pem_files(cert_path: &Path, key_path: &Path) -> Result<PemFiles, io::Error> {
+    Ok((std::fs::read(cert_path)?, std::fs::read(key_path)?))
+}
+
+/// Loads a TLS server configuration from a PEM encoded certificate chain and private key (either PKCS8 or RSA).
+///
+/// Returns the configuration alongside the contents of the files it was loaded from.
+fn load_server_config(
+    cert_path: &Path,
+    key_path: &Path,
+    alpn_protocols: &[Vec<u8>],
+) -> Result<(ServerConfig, PemFiles), LoadCertificateFailure> {
+    let files = read_pem_files(cert_path, key_path)?;
+    let config = server_config(&files, alpn_protocols).map_err(|e| match e {
+        LoadCertificateFailure::InvalidCertificate(e) => {
+            LoadCertificateFailure::InvalidCertificate(format!("{e} at {cert_path:?}"))
+        }
+        LoadCertificateFailure::InvalidKey(e) => {
+            LoadCertificateFailure::InvalidKey(format!("{e} at {key_path:?}"))
+        }
+        e => e,
+    })?;
+
+    Ok((config, files))
+}
+
+/// Builds a TLS server configuration from the contents of the certificate chain and private key files.
+fn server_config(
+    (cert_pem, key_pem): &PemFiles,
+    alpn_protocols: &[Vec<u8>],
+) -> Result<ServerConfig, LoadCertificateFailure> {
+    let certs = pemfile::certs(&mut BufReader::new(cert_pem.as_slice())).map_err(|_| {
+        LoadCertificateFailure::InvalidCertificate("Cannot parse certificate".to_owned())
+    })?;
+    if certs.is_empty() {
+        return Err(LoadCertificateFailure::InvalidCertificate(
+            "No certificate found".to_owned(),
+        ));
+    }
+
+    let key = pemfile::pkcs8_private_keys(&mut key_pem.as_slice())
+        .ok()
+        .filter(|keys| !keys.is_empty())
+        .or_else(|| pemfile::rsa_private_keys(&mut key_pem.as_slice()).ok())
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| LoadCertificateFailure::InvalidKey("No private key found".to_owned()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, key)
+        .map_err(|e| LoadCertificateFailure::InvalidKey(e.to_string()))?;
+    config.set_protocols(alpn_protocols);
+
+    Ok(config)
+}
+
+/// TLS acceptor for the public APIs using an operator supplied certificate and key.
+///
+/// The certificate and key are checked for changes every [CERT_RELOAD_INTERVAL] seconds while accepting connections,
+/// so they can be renewed without restarting the tower. If the new files cannot be loaded the previous certificate
+/// keeps being served.
+pub struct PublicTlsAcceptor {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    alpn_protocols: Vec<Vec<u8>>,
+    current: Mutex<(TlsAcceptor, Option<PemFiles>)>,
+}
+
+impl PublicTlsAcceptor {
+    /// Creates a new acceptor. Fails if the given certificate and key cannot be loaded.
+    pub fn new(
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> Result<Self, LoadCertificateFailure> {
+        let (config, files) = load_server_config(&cert_path, &key_path, &alpn_protocols)?;
+
+        Ok(PublicTlsAcceptor {
+            cert_path,
+            key_path,
+            alpn_protocols,
+            current: Mutex::new((TlsAcceptor::from(Arc::new(config)), Some(files))),
+        })
+    }
+
+    /// Gets an acceptor for the current certificate.
+    pub fn acceptor(&self) -> TlsAcceptor {
+        self.current.lock().unwrap().0.clone()
+    }
+
+    /// Reloads the certificate if the files have changed on disk. The files are read off the async runtime.
+    ///
+    /// Changes are detected by comparing the contents of the files, so replacements are picked up even if they
+    /// happen within the resolution of the file modification times.
+    pub async fn reload(&self) {
+        let (cert_path, key_path) = (self.cert_path.clone(), self.key_path.clone());
+        let files = tokio::task::spawn_blocking(move || read_pem_files(&cert_path, &key_path).ok())
+            .await
+            .unwrap_or(None);
+
+        let mut current = self.current.lock().unwrap();
+        if files != current.1 {
+            match files
+                .as_ref()
+                .map(|f| server_config(f, &self.alpn_protocols))
+            {
+                Some(Ok(config)) => {
+                    tracing::info!("TLS certificate reloaded from {:?}", self.cert_path);
+                    current.0 = TlsAcceptor::from(Arc::new(config));
+                }
+                Some(Err(e)) => tracing::error!(
+                    "Cannot reload TLS certificate from {:?}. Keeping the previous one: {e}",
+                    self.cert_path
+                ),
+                None => tracing::error!(
+                    "Cannot read TLS certificate from {:?}. Keeping the previous one",
+                    self.cert_path
+                ),
+            }
+            // Files are only reloaded again once they change, so broken files are not retried over and over.
+            current.1 = files;
+        }
+    }
+
+    /// Accepts TLS connections on a given listener.
+    ///
+    /// Handshakes are performed in the background so slow clients cannot hold back the rest. Connections
+    /// failing the handshake are dropped. Accept errors are retried with an exponential backoff, so running out of
+    /// resources does not turn into a busy loop. The certificate is reloaded periodically (see [Self::reload]).
+    /// Stops accepting connections once the returned stream is dropped.
+    pub fn incoming(
+        self: Arc<Self>,
+        listener: TcpListener,
+    ) -> impl Stream<Item = Result<TlsStream<TcpStream>, io::Error>> {
+        let (tx, rx) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            let mut backoff = ACCEPT_BACKOFF;
+            let reload_interval = Duration::from_secs(CERT_RELOAD_INTERVAL);
+            let mut reload_timer = tokio::time::interval_at(
+                tokio::time::Instant::now() + reload_interval,
+                reload_interval,
+            );
+            loop {
+                let stream = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            backoff = ACCEPT_BACKOFF;
+                            stream
+                        }
+                        Err(e) => {
+                            tracing::error!("Cannot accept TLS connection. Retrying in {backoff}ms: {e}");
+                            tokio::time::sleep(Duration::from_millis(backoff)).await;
+                            backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                            continue;
+                        }
+                    },
+                    _ = reload_timer.tick() => {
+                        self.reload().await;
+                        continue;
+                    }
+                    _ = tx.closed() => break,
+                };
+
+                let acceptor = self.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(
+                        Duration::from_secs(HANDSHAKE_TIMEOUT),
+                        acceptor.accept(stream),
+                    )
+                    .await
+                    {
+                        Ok(Ok(stream)) => {
+                            // The receiver may be gone if the server is shutting down.
+                            let _ = tx.send(stream).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake failed: {e}"),
+                        Err(_) => tracing::debug!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|stream| (Ok(stream), rx))
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::*;
+
+    /// Writes a new self-signed certificate for localhost to the given paths and returns it (PEM encoded).
+    pub(crate) fn write_self_signed_certificate(cert_path: &Path, key_path: &Path) -> Vec<u8> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        std::fs::write(key_path, cert.serialize_private_key_pem()).unwrap();
+        std::fs::write(cert_path, &cert_pem).unwrap();
+
+        cert_pem.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::write_self_signed_certificate;
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn get_paths(tmp_dir: &TempDir) -> (PathBuf, PathBuf) {
+        (
+            tmp_dir.path().join("cert.pem"),
+            tmp_dir.path().join("key.pem"),
+        )
+    }
+
+    #[test]
+    fn test_load_server_config() {
+        let tmp_dir = TempDir::new("tls").unwrap();
+        let (cert_path, key_path) = get_paths(&tmp_dir);
+
+        // Missing files cannot be loaded
+        assert!(matches!(
+            load_server_config(&cert_path, &key_path, &[]),
+            Err(LoadCertificateFailure::IoError(..))
+        ));
+
+        write_self_signed_certificate(&cert_path, &key_path);
+        assert!(load_server_config(&cert_path, &key_path, &[]).is_ok());
+
+        // Files with no PEM data in them are rejected
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(matches!(
+            load_server_config(&cert_path, &key_path, &[]),
+            Err(LoadCertificateFailure::InvalidKey(..))
+        ));
+        std::fs::write(&cert_path, "not a certificate").unwrap();
+        assert!(matches!(
+            load_server_config(&cert_path, &key_path, &[]),
+            Err(LoadCertificateFailure::InvalidCertificate(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_acceptor_reload() {
+        let tmp_dir = TempDir::new("tls").unwrap();
+        let (cert_path, key_path) = get_paths(&tmp_dir);
+        write_self_signed_certificate(&cert_path, &key_path);
+
+        let tls = PublicTlsAcceptor::new(cert_path.clone(), key_path.clone(), vec![]).unwrap();
+        let loaded = tls.current.lock().unwrap().1.clone();
+        assert!(loaded.is_some());
+
+        // Nothing changes if the files have not been modified
+        tls.reload().await;
+        assert_eq!(tls.current.lock().unwrap().1, loaded);
+
+        // Getting an acceptor does not touch the files
+        let new_cert = write_self_signed_certificate(&cert_path, &key_path);
+        tls.acceptor();
+        assert_eq!(tls.current.lock().unwrap().1, loaded);
+
+        // New files are picked up on reload, even if they are written within the same mtime tick
+        tls.reload().await;
+        let reloaded = tls.current.lock().unwrap().1.clone();
+        assert_ne!(reloaded, loaded);
+        assert_eq!(reloaded.unwrap().0, new_cert);
+
+        // Broken files are not, but they do not make the acceptor fail either
+        std::fs::write(&key_path, "not a key").unwrap();
+        tls.reload().await;
+        tls.acceptor();
+        assert_eq!(
+            tls.current.lock().unwrap().1.as_ref().unwrap().1,
+            b"not a key".to_vec()
+        );
+    }
+}
@@ -0,0 +1,1181 @@
+//! Logic related to the Tower, the component that wires all the others together so a tower can be run as part of any
+//! Rust service (`teosd` being one of them).
+
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU16;
+use std::sync::{Arc, Condvar, Mutex};
+use structopt::StructOpt;
+use tokio::net::TcpListener;
+use tokio::task::{self, JoinHandle};
+use tonic::transport::{Certificate, Server, ServerTlsConfig};
+use triggered::{Listener, Trigger};
+
+use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::BlockHeader;
+use lightning::chain;
+use lightning_block_sync::init::validate_best_block_header;
+use lightning_block_sync::poll::{
+    ChainPoller, Poll, Validate, ValidatedBlock, ValidatedBlockHeader,
+};
+use lightning_block_sync::{BlockSource, BlockSourceError, SpvClient, UnboundedCache};
+
+use teos_common::announcement::TowerAnnouncement;
+use teos_common::constants::IRREVOCABLY_RESOLVED;
+use teos_common::cryptography::get_random_keypair;
+use teos_common::handoff::KeyHandoff;
+use teos_common::net::join_host_port;
+use teos_common::TowerId;
+
+use crate::alerts::Alerter;
+use crate::api::internal::InternalAPI;
+use crate::api::lightning as lightning_api;
+use crate::api::tor::{parse_client_auth_key, TorAPI};
+use crate::api::{http, FEATURES};
+use crate::backup::BackupManager;
+use crate::carrier::{Broadcaster, Carrier};
+use crate::chain_monitor::{ChainMonitor, LastKnownBlockListener};
+use crate::config::{self, Config, Opt};
+use crate::dbm::DBM;
+use crate::feed::Feed;
+use crate::gatekeeper::Gatekeeper;
+use crate::logging::{self, LogFilterHandle};
+use crate::metrics::{self, Metrics, TimedListener};
+use crate::notifier::Notifier;
+use crate::protos as msgs;
+use crate::protos::private_tower_services_server::PrivateTowerServicesServer;
+use crate::protos::public_tower_services_server::PublicTowerServicesServer;
+use crate::reload::ConfigReloader;
+use crate::responder::Responder;
+use crate::systemd::SystemdNotifier;
+use crate::tls::{tls_init, PublicTlsAcceptor};
+use crate::watcher::Watcher;
+
+/// Errors that can prevent the tower from starting (or make it stop).
+#[derive(Debug)]
+pub enum TowerError {
+    AlreadyStarted,
+    InvalidConfig(String),
+    Keys(String),
+    BlockSource(String),
+    NotEnoughBlocks(u32),
+    Interface(String),
+}
+
+impl fmt::Display for TowerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TowerError::AlreadyStarted => write!(f, "The tower has already been started"),
+            TowerError::InvalidConfig(e) => write!(f, "{e}"),
+            TowerError::Keys(e) => write!(f, "Cannot load the tower keys. {e}"),
+            TowerError::BlockSource(e) => write!(f, "Cannot load data from the block source. {e}"),
+            TowerError::NotEnoughBlocks(height) => write!(
+                f,
+                "Not enough blocks to start the tower (required: {IRREVOCABLY_RESOLVED}). Mine at least {} more",
+                IRREVOCABLY_RESOLVED - height
+            ),
+            TowerError::Interface(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for TowerError {}
+
+impl From<BlockSourceError> for TowerError {
+    fn from(e: BlockSourceError) -> Self {
+        TowerError::BlockSource(e.into_inner().to_string())
+    }
+}
+
+/// Chain listeners added to the tower on top of its own components.
+struct ExtraListeners(Vec<Arc<dyn chain::Listen + Send + Sync>>);
+
+impl chain::Listen for ExtraListeners {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &chain::transaction::TransactionData,
+        height: u32,
+    ) {
+        for listener in self.0.iter() {
+            listener.filtered_block_connected(header, txdata, height);
+        }
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+        for listener in self.0.iter() {
+            listener.block_disconnected(header, height);
+        }
+    }
+}
+
+/// Builds a [TowerHandle], which can be used to run a tower.
+///
+/// The tower pulls blocks from a [BlockSource] and sends transactions through a [Broadcaster], so it is not tied to
+/// `bitcoind`.
+pub struct TowerBuilder<B: BlockSource + 'static> {
+    /// The tower configuration.
+    conf: Config,
+    /// The source the tower pulls blocks from.
+    block_source: Arc<B>,
+    /// The interface used to send transactions to the network.
+    broadcaster: Arc<dyn Broadcaster>,
+    /// The tower database.
+    dbm: DBM,
+    /// Where tls certificates and backups are stored. Relative paths in the config are relative to it.
+    data_dir: PathBuf,
+    /// The passphrase the tower keys are (or will be) encrypted with, if any.
+    key_passphrase: Option<String>,
+    /// The block to resume from, if it must be other than the last block known by the tower.
+    resume_from: Option<ValidatedBlockHeader>,
+    /// Additional components to be notified about connected and disconnected blocks.
+    listeners: Vec<Arc<dyn chain::Listen + Send + Sync>>,
+    /// The config file re-read when the configuration is reloaded.
+    conf_file_path: PathBuf,
+    /// The command line options overriding the config file on reload.
+    options: Opt,
+    /// A handle to change the log levels on reload, if the logger has been set.
+    log_filter: Option<LogFilterHandle>,
+    /// A [SystemdNotifier] instance, used to report the tower state.
+    systemd: SystemdNotifier,
+    /// The live event feed of the tower, if it must be other than a fresh one.
+    feed: Option<Arc<Feed>>,
+    /// The metrics of the tower, if they must be collected into other than a fresh registry.
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl<B: BlockSource + 'static> TowerBuilder<B> {
+    /// Creates a new [TowerBuilder] instance.
+    pub fn new(conf: Config, block_source: B, broadcaster: Arc<dyn Broadcaster>, dbm: DBM) -> Self {
+        let data_dir = config::data_dir_absolute_path(config::DEFAULT_DATA_DIR.to_owned());
+        TowerBuilder {
+            conf,
+            block_source: Arc::new(block_source),
+            broadcaster,
+            dbm,
+            conf_file_path: data_dir.join("teos.toml"),
+            data_dir,
+            key_passphrase: None,
+            resume_from: None,
+            listeners: Vec::new(),
+            options: Opt::from_iter(["teosd"]),
+            log_filter: None,
+            systemd: SystemdNotifier::default(),
+            feed: None,
+            metrics: None,
+        }
+    }
+
+    /// Sets the tower data directory. Defaults to [config::DEFAULT_DATA_DIR].
+    pub fn with_data_dir(mut self, data_dir: PathBuf) -> Self {
+        self.conf_file_path = data_dir.join("teos.toml");
+        self.data_dir = data_dir;
+        self
+    }
+
+    /// Sets the passphrase the tower keys are encrypted with. Keys stored in plain text are encrypted with it.
+    pub fn with_key_passphrase(mut self, passphrase: String) -> Self {
+        self.key_passphrase = Some(passphrase);
+        self
+    }
+
+    /// Makes the tower resume from the given block instead of from the last block it knows about.
+    pub fn with_resume_from(mut self, header: ValidatedBlockHeader) -> Self {
+        self.resume_from = Some(header);
+        self
+    }
+
+    /// Adds a component to be notified about connected and disconnected blocks. Listeners are notified in the order
+    /// they are added, after the tower components.
+    pub fn with_listener(mut self, listener: Arc<dyn chain::Listen + Send + Sync>) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Sets the config file (and command line options) the configuration is reloaded from. Defaults to `teos.toml`
+    /// in the data directory (and no options).
+    pub fn with_config_file(mut self, conf_file_path: PathBuf, options: Opt) -> Self {
+        self.conf_file_path = conf_file_path;
+        self.options = options;
+        self
+    }
+
+    /// Sets a handle to change the log levels when the configuration is reloaded.
+    pub fn with_log_filter(mut self, log_filter: LogFilterHandle) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Sets a [SystemdNotifier] so the tower reports its state to systemd.
+    pub fn with_systemd(mut self, systemd: SystemdNotifier) -> Self {
+        self.systemd = systemd;
+        self
+    }
+
+    /// Sets the live event feed the tower publishes to. Defaults to a feed of its own.
+    pub fn with_feed(mut self, feed: Arc<Feed>) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Sets the metrics the tower collects. Defaults to metrics of its own, only collected if they are exported.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Builds a [TowerHandle]. The tower does not run until [TowerHandle::start] is called.
+    pub fn build(self) -> TowerHandle<B> {
+        let (shutdown_trigger, shutdown_signal) = triggered::trigger();
+        TowerHandle {
+            builder: Some(self),
+            shutdown_trigger,
+            shutdown_signal,
+            tower_id: None,
+            internal_api: None,
+            tasks: Vec::new(),
+        }
+    }
+}
+
+/// A handle to a tower, used to start and stop it.
+pub struct TowerHandle<B: BlockSource + 'static> {
+    /// The data the tower is built from, until it is started.
+    builder: Option<TowerBuilder<B>>,
+    /// A trigger to shut the tower down.
+    shutdown_trigger: Trigger,
+    /// A signal indicating the tower is shutting down.
+    shutdown_signal: Listener,
+    /// The tower id, once started.
+    tower_id: Option<TowerId>,
+    /// The [InternalAPI] instance, once started.
+    internal_api: Option<Arc<InternalAPI>>,
+    /// The tasks run by the tower.
+    tasks: Vec<JoinHandle<Result<(), String>>>,
+}
+
+impl<B: BlockSource + 'static> TowerHandle<B> {
+    /// Gets the tower id, if the tower has been started.
+    pub fn tower_id(&self) -> Option<TowerId> {
+        self.tower_id
+    }
+
+    /// Gets the [InternalAPI] instance, if the tower has been started.
+    pub fn internal_api(&self) -> Option<Arc<InternalAPI>> {
+        self.internal_api.clone()
+    }
+
+    /// Gets a trigger that shuts the tower down.
+    pub fn shutdown_trigger(&self) -> Trigger {
+        self.shutdown_trigger.clone()
+    }
+
+    /// Gets a signal indicating the tower is shutting down.
+    pub fn shutdown_signal(&self) -> Listener {
+        self.shutdown_signal.clone()
+    }
+
+    /// Starts the tower. Returns once the tower is in sync with the block source and all its interfaces are up.
+    ///
+    /// If the tower fails to start, everything that has already been started is stopped before returning.
+    pub async fn start(&mut self) -> Result<(), TowerError> {
+        let builder = self.builder.take().ok_or(TowerError::AlreadyStarted)?;
+        let result = self.bootstrap(builder).await;
+        if result.is_err() {
+            self.shutdown_trigger.trigger();
+            // The error that prevented the tower from starting is the one worth reporting
+            let _ = self.stopped().await;
+        }
+        result
+    }
+
+    /// Stops the tower, waiting until all its tasks are done.
+    pub async fn stop(&mut self) -> Result<(), TowerError> {
+        self.shutdown_trigger.trigger();
+        self.stopped().await
+    }
+
+    /// Waits until the tower has stopped, either because [TowerHandle::stop] has been called, the tower has been
+    /// requested to stop through its API or one of its interfaces has failed.
+    pub async fn stopped(&mut self) -> Result<(), TowerError> {
+        let mut result = Ok(());
+        for task in self.tasks.drain(..) {
+            let error = match task.await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(e) => format!("Tower task failed: {e}"),
+            };
+            if result.is_ok() {
+                result = Err(TowerError::Interface(error));
+            }
+        }
+        result
+    }
+
+    /// Spawns a task run by the tower. If the task fails, the tower is shut down.
+    fn spawn<F>(&self, future: F) -> JoinHandle<Result<(), String>>
+    where
+        F: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let shutdown_trigger = self.shutdown_trigger.clone();
+        task::spawn(async move {
+            let result = future.await;
+            if let Err(e) = result.as_ref() {
+                tracing::error!("{e}. Shutting down");
+                shutdown_trigger.trigger();
+            }
+            result
+        })
+    }
+
+    /// Spawns a task run by the tower and waits until it signals it is ready.
+    ///
+    /// The task is tracked right away, so it is waited for when stopping even if the tower is stopped (or starting it
+    /// is given up) before it is ready.
+    async fn spawn_and_wait<F>(&mut self, future: F, ready: Listener) -> Result<(), TowerError>
+    where
+        F: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let task = self.spawn(future);
+        self.tasks.push(task);
+        let result = tokio::select! {
+            biased;
+            _ = ready => return Ok(()),
+            result = self.tasks.last_mut().unwrap() => result,
+        };
+
+        // The task is already done, so it must not be waited for again
+        self.tasks.pop();
+        Err(TowerError::Interface(match result {
+            Ok(Err(e)) => e,
+            Ok(Ok(())) => "Tower task stopped before being ready".to_owned(),
+            Err(e) => format!("Tower task failed: {e}"),
+        }))
+    }
+
+    /// Bootstraps the tower components and turns on its interfaces.
+    async fn bootstrap(&mut self, b: TowerBuilder<B>) -> Result<(), TowerError> {
+        let mut conf = b.conf;
+        conf.verify()
+            .map_err(|e| TowerError::InvalidConfig(e.to_string()))?;
+        let systemd = b.systemd;
+        let path_network = b.data_dir.join(conf.btc_network.clone());
+        let dbm = Arc::new(Mutex::new(b.dbm));
+
+        let (tower_sk, tower_pk) = load_tower_keys(
+            &mut dbm.lock().unwrap(),
+            conf.overwrite_key,
+            b.key_passphrase.as_deref(),
+        )?;
+        tracing::info!("tower_id: {tower_pk}");
+
+        // Resume from the last known block if found. Start from the tip of the block source otherwise.
+        let block_source = b.block_source;
+        let last_known_block = dbm.lock().unwrap().load_last_known_block();
+        let tip = if let Some(header) = b.resume_from {
+            tracing::info!(
+                "Resuming from block: {} (height: {})",
+                header.header.block_hash(),
+                header.height
+            );
+            header
+        } else if let Some(block_hash) = last_known_block {
+            let header = block_source
+                .get_header(&block_hash, None)
+                .await?
+                .validate(block_hash)?;
+            tracing::info!(
+                "Last known block: {} (height: {})",
+                header.header.block_hash(),
+                header.height
+            );
+            header
+        } else {
+            validate_best_block_header(&*block_source).await?
+        };
+
+        // DISCUSS: This is not really required (and only triggered in regtest). This is only in place so the caches can be
+        // populated with enough blocks mainly because the size of the cache is based on the amount of blocks passed when initializing.
+        // However, we could add an additional parameter to specify the size of the cache, and initialize with however may blocks we
+        // could pull from the backend. Adding this functionality just for regtest seemed unnecessary though, hence the check.
+        if tip.height < IRREVOCABLY_RESOLVED {
+            return Err(TowerError::NotEnoughBlocks(tip.height));
+        }
+
+        tracing::info!(
+            "Current chain tip: {} (height: {})",
+            tip.header.block_hash(),
+            tip.height
+        );
+
+        // Rotate the tower key if requested. The old key hands the tower identity over to the new one from the current
+        // height onwards, so users can follow the tower to its new id and old receipts remain valid under the old key
+        let (tower_sk, tower_pk) = if conf.rotate_key {
+            let (new_sk, new_pk) = get_random_keypair();
+            let mut handoff = KeyHandoff::new(TowerId(tower_pk), TowerId(new_pk), tip.height);
+            handoff.sign(&tower_sk);
+            dbm.lock()
+                .unwrap()
+                .rotate_tower_key(&new_sk, &handoff, b.key_passphrase.as_deref())
+                .map_err(|e| TowerError::Keys(format!("{e:?}")))?;
+            tracing::info!(
+                "Tower key rotated. New tower_id: {new_pk} (active from height {})",
+                tip.height
+            );
+            (new_sk, new_pk)
+        } else {
+            (tower_sk, tower_pk)
+        };
+        self.tower_id = Some(TowerId(tower_pk));
+
+        // This is how chain poller names bitcoin networks.
+        let btc_network = match conf.btc_network.as_str() {
+            "main" => "bitcoin",
+            "test" => "testnet",
+            any => any,
+        };
+        let network = Network::from_str(btc_network).unwrap();
+        let bitcoind_reachable = Arc::new((Mutex::new(true), Condvar::new()));
+
+        // The live event feed of the tower, shared by the components publishing to it and its subscribers
+        let feed = b.feed.unwrap_or_else(|| Arc::new(Feed::new()));
+
+        let data_dir = &b.data_dir;
+        let alerter =
+            (!conf.alert_webhook.is_empty() || !conf.alert_script.is_empty()).then(|| {
+                Alerter::new(
+                    (!conf.alert_webhook.is_empty()).then(|| conf.alert_webhook.clone()),
+                    (!conf.alert_script.is_empty())
+                        .then(|| config::config_file_path(data_dir, conf.alert_script.clone())),
+                    conf.alert_missed_confirmations,
+                    conf.alert_disk_usage,
+                    conf.alert_cooldown,
+                    data_dir.clone(),
+                    feed.clone(),
+                )
+            });
+
+        // Metrics are only collected if they are exported, unless they have been handed to the builder
+        let metrics = b
+            .metrics
+            .or_else(|| conf.metrics_support.then(|| Arc::new(Metrics::new())));
+
+        // Build components
+        let gatekeeper = Arc::new(Gatekeeper::new(
+            tip.height,
+            conf.subscription_slots,
+            conf.subscription_duration,
+            conf.expiry_delta,
+            conf.user_cache_size,
+            dbm.clone(),
+            feed.clone(),
+        ));
+
+        let notifier = Arc::new(Notifier::new(tower_sk, dbm.clone()));
+
+        let (responder, watcher) = {
+            let mut poller = ChainPoller::new(block_source.clone(), network);
+            let last_n_blocks =
+                get_last_n_blocks(&mut poller, tip, IRREVOCABLY_RESOLVED as usize, &systemd)
+                    .await
+                    .map_err(|e| {
+                        // I'm pretty sure this can only happen if we are pulling blocks from the target to the prune height, and by the time we get to
+                        // the end at least one has been pruned.
+                        TowerError::BlockSource(format!(
+                            "Couldn't load the latest {IRREVOCABLY_RESOLVED} blocks. Please try again (Error: {})",
+                            e.into_inner()
+                        ))
+                    })?;
+
+            let responder = Arc::new(Responder::new(
+                &last_n_blocks,
+                tip.height,
+                Carrier::new(b.broadcaster, bitcoind_reachable.clone(), tip.height),
+                gatekeeper.clone(),
+                dbm.clone(),
+                notifier.clone(),
+                feed.clone(),
+                metrics.clone(),
+            ));
+            let watcher = Arc::new(Watcher::new(
+                gatekeeper.clone(),
+                responder.clone(),
+                &last_n_blocks[0..6],
+                tip.height,
+                tower_sk,
+                TowerId(tower_pk),
+                dbm.clone(),
+                notifier.clone(),
+                feed.clone(),
+                metrics.clone(),
+            ));
+            (responder, watcher)
+        };
+
+        if watcher.is_fresh() & responder.is_fresh() & gatekeeper.is_fresh() {
+            tracing::info!("Fresh bootstrap");
+        } else {
+            tracing::info!("Bootstrapping from backed up data");
+        }
+
+        // Settings that can be changed live are shared between the components using them and the config reloader
+        let polling_delta = Arc::new(AtomicU16::new(conf.polling_delta));
+        let config_reloader = Arc::new(ConfigReloader::new(
+            b.conf_file_path,
+            b.options,
+            conf.clone(),
+            gatekeeper.clone(),
+            polling_delta.clone(),
+            b.log_filter,
+        ));
+
+        // The chain monitor owns the listeners, so it is built and run within its own task. It signals back once all
+        // the components are up to date, if there's a backlog of blocks.
+        let (synced_trigger, synced) = triggered::trigger();
+        let chain_monitor_task = {
+            let gatekeeper = gatekeeper.clone();
+            let watcher = watcher.clone();
+            let extra_listeners = ExtraListeners(b.listeners);
+            let last_known_block = LastKnownBlockListener::new(dbm.clone());
+            let dbm = dbm.clone();
+            let shutdown_signal = self.shutdown_signal.clone();
+            let shutdown_signal_sync = self.shutdown_signal.clone();
+            let bitcoind_reachable = bitcoind_reachable.clone();
+            let systemd = systemd.clone();
+            let metrics = metrics.clone();
+            let feed = feed.clone();
+            async move {
+                // The ordering here actually matters. Listeners are called by order, and we want the gatekeeper to be called
+                // first so it updates the users' states and both the Watcher and the Responder operate only on registered users.
+                // Each component is timed separately so block processing latency can be exported per component.
+                let timed_gatekeeper =
+                    TimedListener::new("gatekeeper", gatekeeper, metrics.clone());
+                let timed_watcher = TimedListener::new("watcher", watcher, metrics.clone());
+                let timed_responder = TimedListener::new("responder", responder, metrics);
+                // The last known block is recorded once every other listener is done with it.
+                let listener = &(
+                    &timed_gatekeeper,
+                    &(
+                        &timed_watcher,
+                        &(&timed_responder, &(&extra_listeners, &last_known_block)),
+                    ),
+                );
+                let cache = &mut UnboundedCache::new();
+                let poller = ChainPoller::new(block_source, network);
+                let spv_client = SpvClient::new(tip, poller, cache, listener);
+                let mut chain_monitor = ChainMonitor::new(
+                    spv_client,
+                    tip,
+                    dbm,
+                    polling_delta,
+                    shutdown_signal,
+                    bitcoind_reachable,
+                    systemd.clone(),
+                    feed,
+                )
+                .await;
+
+                // Get all the components up to date if there's a backlog of blocks
+                systemd.status("Syncing with bitcoind");
+                chain_monitor.poll_best_tip().await;
+                if shutdown_signal_sync.is_triggered() {
+                    // Stopped while syncing. The last processed block has already been recorded
+                    systemd.stopping();
+                    return Ok(());
+                }
+                synced_trigger.trigger();
+
+                chain_monitor.monitor_chain().await;
+                systemd.stopping();
+                Ok(())
+            }
+        };
+        self.spawn_and_wait(chain_monitor_task, synced).await?;
+        tracing::info!("Bootstrap completed. Turning on interfaces");
+        systemd.status("Turning on interfaces");
+
+        // Build interfaces
+        // api_bind has already been checked to be an IP address by Config::verify.
+        let api_bind = conf.api_bind.parse::<IpAddr>().unwrap();
+        let http_api_addr = SocketAddr::new(api_bind, conf.api_port);
+        let mut addresses = vec![msgs::NetworkAddress::from_host(
+            conf.api_host().to_owned(),
+            conf.api_port,
+        )];
+
+        // Create Tor endpoint if required
+        let tor_api = if conf.tor_support {
+            let client_auth_keys = conf
+                .tor_client_auth
+                .iter()
+                .map(|key| parse_client_auth_key(key))
+                .collect::<Result<_, _>>()
+                .map_err(TowerError::InvalidConfig)?;
+            let tor_api = TorAPI::new(
+                http_api_addr,
+                conf.onion_hidden_service_port,
+                conf.tor_control_port,
+                conf.tor_control_auth(&b.data_dir),
+                client_auth_keys,
+                path_network,
+            )
+            .await;
+            addresses.push(msgs::NetworkAddress::from_torv3(
+                tor_api.get_onion_address(),
+                conf.onion_hidden_service_port,
+            ));
+
+            Some(Arc::new(tor_api))
+        } else {
+            None
+        };
+
+        let mut announcement = TowerAnnouncement::new(
+            watcher.tower_id,
+            addresses
+                .iter()
+                .map(|a| join_host_port(&a.address, a.port as u16))
+                .collect(),
+            FEATURES.iter().map(|f| f.to_string()).collect(),
+            conf.min_to_self_delay as u32,
+            conf.subscription_slots,
+            conf.subscription_duration,
+        );
+        announcement.sign(&tower_sk);
+
+        let internal_api = Arc::new(InternalAPI::new(
+            watcher.clone(),
+            addresses,
+            announcement,
+            tor_api.clone(),
+            bitcoind_reachable.clone(),
+            self.shutdown_trigger.clone(),
+            self.shutdown_signal.clone(),
+            Arc::new(BackupManager::new(
+                dbm.clone(),
+                b.data_dir.clone(),
+                conf.btc_network.clone(),
+            )),
+            config_reloader,
+            feed.clone(),
+        ));
+        self.internal_api = Some(internal_api.clone());
+
+        let rpc_api_addr = format!("{}:{}", conf.rpc_bind, conf.rpc_port)
+            .parse()
+            .map_err(|e| TowerError::InvalidConfig(format!("Invalid rpc_bind: {e}")))?;
+        let internal_api_addr = format!("{}:{}", conf.internal_api_bind, conf.internal_api_port)
+            .parse()
+            .map_err(|e| TowerError::InvalidConfig(format!("Invalid internal_api_bind: {e}")))?;
+
+        // Generate mtls certificates to data directory so the admin can securely connect
+        // to the server to perform administrative tasks.
+        let (identity, ca_cert) = tls_init(&b.data_dir).map_err(|e| {
+            TowerError::Interface(format!("Couldn't generate tls certificates: {e:?}"))
+        })?;
+
+        // Load the operator supplied certificate if the public APIs are to be served over TLS.
+        let load_public_tls = |alpn_protocols: Vec<Vec<u8>>| {
+            PublicTlsAcceptor::new(
+                config::config_file_path(data_dir, conf.api_tls_cert.clone()),
+                config::config_file_path(data_dir, conf.api_tls_key.clone()),
+                alpn_protocols,
+            )
+            .map(Arc::new)
+            .map_err(|e| {
+                TowerError::Interface(format!("Couldn't load the API tls certificate: {e}"))
+            })
+        };
+        let http_tls = conf
+            .tls_enabled()
+            .then(|| load_public_tls(vec![b"h2".to_vec(), b"http/1.1".to_vec()]))
+            .transpose()?;
+        let public_grpc_tls = conf
+            .public_grpc_support
+            .then(|| load_public_tls(vec![b"h2".to_vec()]))
+            .transpose()?;
+
+        let tls = ServerTlsConfig::new()
+            .identity(identity)
+            .client_ca_root(Certificate::from_pem(ca_cert));
+
+        // Start tasks
+        let private_api = Server::builder()
+            .trace_fn(logging::grpc_request_span)
+            .tls_config(tls)
+            .map_err(|e| TowerError::Interface(format!("Couldn't configure tls: {e}")))?
+            .add_service(PrivateTowerServicesServer::new(internal_api.clone()))
+            .serve_with_shutdown(rpc_api_addr, self.shutdown_signal.clone());
+        let task = self.spawn(async move {
+            private_api
+                .await
+                .map_err(|e| format!("Cannot serve the private API: {e}"))
+        });
+        self.tasks.push(task);
+
+        // Serve the public gRPC API over TLS if required. The plain text one is only meant to be reached by the HTTP API.
+        if let Some(tls) = public_grpc_tls {
+            let public_grpc_addr = SocketAddr::new(api_bind, conf.public_grpc_port);
+            tracing::info!("Starting up public gRPC interface on {public_grpc_addr}");
+
+            let listener = TcpListener::bind(public_grpc_addr).await.map_err(|e| {
+                TowerError::Interface(format!(
+                    "Cannot bind the public gRPC API to {public_grpc_addr}: {e}"
+                ))
+            })?;
+            let public_grpc = Server::builder()
+                .trace_fn(logging::grpc_request_span)
+                .add_service(PublicTowerServicesServer::new(internal_api.clone()))
+                .serve_with_incoming_shutdown(tls.incoming(listener), self.shutdown_signal.clone());
+            let task = self.spawn(async move {
+                public_grpc
+                    .await
+                    .map_err(|e| format!("Cannot serve the public gRPC API: {e}"))
+            });
+            self.tasks.push(task);
+        }
+
+        let public_api = Server::builder()
+            .trace_fn(logging::grpc_request_span)
+            .add_service(PublicTowerServicesServer::new(internal_api.clone()))
+            .serve_with_shutdown(internal_api_addr, self.shutdown_signal.clone());
+        let task = self.spawn(async move {
+            public_api
+                .await
+                .map_err(|e| format!("Cannot serve the public API: {e}"))
+        });
+        self.tasks.push(task);
+
+        let shutdown_signal = self.shutdown_signal.clone();
+        let task = self.spawn(async move {
+            notifier.deliver_notifications(shutdown_signal).await;
+            Ok(())
+        });
+        self.tasks.push(task);
+
+        let (http_service_ready, ready_signal_http) = triggered::trigger();
+        let http_api = http::serve(
+            http_api_addr,
+            internal_api_addr,
+            http_tls,
+            metrics.clone(),
+            http_service_ready,
+            self.shutdown_signal.clone(),
+        );
+        self.spawn_and_wait(http_api, ready_signal_http).await?;
+
+        // Add Tor Onion Service for public API
+        if let Some(tor_api) = tor_api {
+            tracing::info!("Starting up Tor hidden service");
+
+            let (tor_service_ready, ready_signal_tor) = triggered::trigger();
+            let shutdown_signal = self.shutdown_signal.clone();
+            let tor_service = async move {
+                tor_api
+                    .expose_onion_service(tor_service_ready, shutdown_signal)
+                    .await
+                    .map_err(|e| format!("Cannot connect to the Tor backend: {e}"))
+            };
+            self.spawn_and_wait(tor_service, ready_signal_tor).await?;
+        }
+
+        // Accept Lightning peer connections for the public API if required
+        if conf.lightning_support {
+            let lightning_api_addr = SocketAddr::new(api_bind, conf.lightning_port);
+            tracing::info!("Starting up Lightning interface on {lightning_api_addr}");
+
+            let listener = TcpListener::bind(lightning_api_addr).await.map_err(|e| {
+                TowerError::Interface(format!(
+                    "Cannot bind the Lightning interface to {lightning_api_addr}: {e}"
+                ))
+            })?;
+            let (lightning_service_ready, ready_signal_lightning) = triggered::trigger();
+            let lightning_service = lightning_api::serve(
+                listener,
+                internal_api,
+                tower_sk,
+                lightning_service_ready,
+                self.shutdown_signal.clone(),
+            );
+            self.spawn_and_wait(lightning_service, ready_signal_lightning)
+                .await?;
+        }
+
+        // Export metrics if required
+        if let Some(metrics) = metrics.filter(|_| conf.metrics_support) {
+            // metrics_bind has already been checked to be an IP address by Config::verify.
+            let metrics_addr =
+                SocketAddr::new(conf.metrics_bind.parse().unwrap(), conf.metrics_port);
+            tracing::info!("Starting up metrics interface on {metrics_addr}");
+
+            let (metrics_service_ready, ready_signal_metrics) = triggered::trigger();
+            let metrics_service = metrics::serve(
+                metrics_addr,
+                metrics,
+                watcher,
+                bitcoind_reachable,
+                metrics_service_ready,
+                self.shutdown_signal.clone(),
+            );
+            self.spawn_and_wait(metrics_service, ready_signal_metrics)
+                .await?;
+        }
+
+        // Watch for conditions the operator needs to be alerted about if required
+        if let Some(alerter) = alerter {
+            tracing::info!("Starting up operator alerts");
+
+            let shutdown_signal = self.shutdown_signal.clone();
+            let task = self.spawn(async move {
+                alerter.run(shutdown_signal).await;
+                Ok(())
+            });
+            self.tasks.push(task);
+        }
+
+        tracing::info!("Tower ready");
+        systemd.ready();
+        Ok(())
+    }
+}
+
+/// Loads the tower keys from the database, or creates a fresh set if none is found (or if they are to be overwritten).
+///
+/// Keys stored in plain text are encrypted as soon as a passphrase is provided.
+fn load_tower_keys(
+    db: &mut DBM,
+    overwrite: bool,
+    passphrase: Option<&str>,
+) -> Result<(SecretKey, PublicKey), TowerError> {
+    if let Some(passphrase) = passphrase {
+        if !db.is_tower_key_encrypted() && db.load_tower_key(None) != Ok(None) {
+            tracing::info!("Encrypting tower keys");
+            db.change_key_passphrase(None, passphrase)
+                .map_err(|e| TowerError::Keys(e.to_string()))?;
+        }
+    }
+
+    let sk = if overwrite {
+        tracing::info!("Overwriting tower keys");
+        None
+    } else {
+        let sk = db
+            .load_tower_key(passphrase)
+            .map_err(|e| TowerError::Keys(e.to_string()))?;
+        if sk.is_none() {
+            tracing::info!("Tower keys not found. Creating a fresh set");
+        }
+        sk
+    };
+
+    match sk {
+        Some(sk) => Ok((sk, PublicKey::from_secret_key(&Secp256k1::new(), &sk))),
+        None => {
+            let (sk, pk) = get_random_keypair();
+            db.store_tower_key(&sk, passphrase)
+                .map_err(|e| TowerError::Keys(format!("{e:?}")))?;
+            Ok((sk, pk))
+        }
+    }
+}
+
+/// Fetches the last `n` blocks, starting from `last_known_block` backwards.
+async fn get_last_n_blocks<B, T>(
+    poller: &mut ChainPoller<B, T>,
+    mut last_known_block: ValidatedBlockHeader,
+    n: usize,
+    systemd: &SystemdNotifier,
+) -> Result<Vec<ValidatedBlock>, BlockSourceError>
+where
+    B: std::ops::Deref<Target = T> + Sized + Send + Sync,
+    T: BlockSource,
+{
+    let mut last_n_blocks = Vec::with_capacity(n);
+    for i in 0..n {
+        tracing::debug!("Fetching block #{}", last_known_block.height);
+        systemd.status(&format!("Fetching the last {n} blocks ({i}/{n})"));
+        let block = poller.fetch_block(&last_known_block).await?;
+        last_known_block = poller.look_up_previous_header(&last_known_block).await?;
+        last_n_blocks.push(block);
+    }
+
+    Ok(last_n_blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoincore_rpc::{Auth, Client as BitcoindClient};
+    use tempdir::TempDir;
+    use tonic::Request;
+
+    use crate::feed::FeedEventKind;
+    use crate::protos::private_tower_services_server::PrivateTowerServices;
+
+    use crate::test_utils::{Blockchain, START_HEIGHT};
+    use teos_common::protos as common_msgs;
+    use teos_common::test_utils::get_random_user_id;
+
+    /// A chain listener that keeps track of the heights of the blocks it gets notified about.
+    struct HeightsListener(Mutex<Vec<u32>>);
+
+    impl chain::Listen for HeightsListener {
+        fn filtered_block_connected(
+            &self,
+            _: &BlockHeader,
+            _: &chain::transaction::TransactionData,
+            height: u32,
+        ) {
+            self.0.lock().unwrap().push(height);
+        }
+
+        fn block_disconnected(&self, _: &BlockHeader, _: u32) {}
+    }
+
+    /// A chain listener that shuts the tower down once it gets notified about a block.
+    struct ShutdownListener(Mutex<Option<Trigger>>);
+
+    impl chain::Listen for ShutdownListener {
+        fn filtered_block_connected(
+            &self,
+            _: &BlockHeader,
+            _: &chain::transaction::TransactionData,
+            _: u32,
+        ) {
+            if let Some(trigger) = &*self.0.lock().unwrap() {
+                trigger.trigger();
+            }
+        }
+
+        fn block_disconnected(&self, _: &BlockHeader, _: u32) {}
+    }
+
+    fn get_free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn get_test_config() -> Config {
+        Config {
+            btc_rpc_user: "user".to_owned(),
+            btc_rpc_password: "passwd".to_owned(),
+            btc_network: "regtest".to_owned(),
+            api_port: get_free_port(),
+            rpc_port: get_free_port(),
+            internal_api_port: get_free_port() as u32,
+            ..Default::default()
+        }
+    }
+
+    fn get_builder(
+        conf: Config,
+        chain: Blockchain,
+        data_dir: &TempDir,
+    ) -> TowerBuilder<Blockchain> {
+        // Nothing is broadcast in these tests, so the broadcaster does not need to be reachable
+        let broadcaster = Arc::new(BitcoindClient::new("http://localhost:1", Auth::None).unwrap());
+        TowerBuilder::new(conf, chain, broadcaster, DBM::in_memory().unwrap())
+            .with_data_dir(data_dir.path().to_path_buf())
+    }
+
+    #[tokio::test]
+    async fn test_start_stop() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let mut tower = get_builder(get_test_config(), chain, &data_dir).build();
+        assert!(tower.tower_id().is_none());
+        assert!(tower.internal_api().is_none());
+
+        tower.start().await.unwrap();
+        let tower_info = tower
+            .internal_api()
+            .unwrap()
+            .get_tower_info(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(tower_info.tower_id, tower.tower_id().unwrap().to_vec());
+        assert!(matches!(
+            tower.start().await,
+            Err(TowerError::AlreadyStarted)
+        ));
+
+        tower.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_through_the_api() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let mut tower = get_builder(get_test_config(), chain, &data_dir).build();
+        tower.start().await.unwrap();
+
+        // Shutting the tower down from the outside makes it stop
+        tower.shutdown_trigger().trigger();
+        tower.stopped().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_start_with_listener() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT + 2);
+        let resume_from = chain.at_height(START_HEIGHT);
+        let listener = Arc::new(HeightsListener(Mutex::new(Vec::new())));
+        let mut tower = get_builder(get_test_config(), chain, &data_dir)
+            .with_resume_from(resume_from)
+            .with_listener(listener.clone())
+            .build();
+
+        // The backlog of blocks is processed before the tower is ready, so the listener already knows about it
+        tower.start().await.unwrap();
+        assert_eq!(
+            *listener.0.lock().unwrap(),
+            vec![START_HEIGHT as u32 + 1, START_HEIGHT as u32 + 2]
+        );
+
+        tower.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_while_syncing() {
+        let data_dir = TempDir::new("tower").unwrap();
+        // Blocks take a while to be served, so there is a chance to stop the tower while it is catching up
+        let chain = Blockchain::default()
+            .with_height(START_HEIGHT + 10)
+            .with_block_delay(std::time::Duration::from_millis(10));
+        let resume_from = chain.at_height(START_HEIGHT);
+        let listener = Arc::new(HeightsListener(Mutex::new(Vec::new())));
+        let shutdown_listener = Arc::new(ShutdownListener(Mutex::new(None)));
+        let mut tower = get_builder(get_test_config(), chain, &data_dir)
+            .with_resume_from(resume_from)
+            .with_listener(listener.clone())
+            .with_listener(shutdown_listener.clone())
+            .build();
+        *shutdown_listener.0.lock().unwrap() = Some(tower.shutdown_trigger());
+
+        // The tower is shut down right after processing the first block of the backlog, so it never gets to be ready
+        let shutdown_signal = tower.shutdown_signal();
+        tokio::select! {
+            biased;
+            _ = shutdown_signal => (),
+            _ = tower.start() => panic!("The tower should not have been able to start"),
+        }
+        tower.stopped().await.unwrap();
+
+        // The chain monitor has been waited for (and dropped its listeners) and it has stopped catching up
+        assert_eq!(Arc::strong_count(&shutdown_listener), 1);
+        assert_eq!(*listener.0.lock().unwrap(), vec![START_HEIGHT as u32 + 1]);
+    }
+
+    #[tokio::test]
+    async fn test_start_with_feed_and_metrics() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT + 2);
+        let resume_from = chain.at_height(START_HEIGHT);
+        let feed = Arc::new(Feed::new());
+        let metrics = Arc::new(Metrics::new());
+        let mut feed_subscriber = feed.subscribe();
+        let mut tower = get_builder(get_test_config(), chain, &data_dir)
+            .with_resume_from(resume_from)
+            .with_feed(feed)
+            .with_metrics(metrics.clone())
+            .build();
+
+        // Metrics handed to the builder are collected even if they are not exported
+        tower.start().await.unwrap();
+        assert_eq!(
+            metrics
+                .block_processing_seconds
+                .with_label_values(&["watcher"])
+                .get_sample_count(),
+            2
+        );
+
+        // And the tower publishes to the given feed
+        let internal_api = tower.internal_api().unwrap();
+        crate::protos::public_tower_services_server::PublicTowerServices::register(
+            &internal_api,
+            Request::new(common_msgs::RegisterRequest {
+                user_id: get_random_user_id().to_vec(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            feed_subscriber.recv().await.unwrap().kind,
+            FeedEventKind::NewUser
+        );
+
+        tower.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_start_not_enough_blocks() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(IRREVOCABLY_RESOLVED as usize - 1);
+        let mut tower = get_builder(get_test_config(), chain, &data_dir).build();
+
+        assert!(matches!(
+            tower.start().await,
+            Err(TowerError::NotEnoughBlocks(height)) if height == IRREVOCABLY_RESOLVED - 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_start_invalid_config() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        let conf = Config {
+            btc_rpc_user: String::new(),
+            ..get_test_config()
+        };
+        let mut tower = get_builder(conf, chain.clone(), &data_dir).build();
+
+        assert!(matches!(
+            tower.start().await,
+            Err(TowerError::InvalidConfig(_))
+        ));
+
+        // Malformed Tor client authorization keys are reported the same way
+        let conf = Config {
+            tor_support: true,
+            tor_client_auth: vec!["not a key".to_owned()],
+            ..get_test_config()
+        };
+        let mut tower = get_builder(conf, chain, &data_dir).build();
+
+        assert!(matches!(
+            tower.start().await,
+            Err(TowerError::InvalidConfig(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_start_interface_error() {
+        let data_dir = TempDir::new("tower").unwrap();
+        let chain = Blockchain::default().with_height(START_HEIGHT);
+        // Keep the metrics port busy so the interface cannot be bound
+        let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let conf = Config {
+            metrics_support: true,
+            metrics_port: busy.local_addr().unwrap().port(),
+            ..get_test_config()
+        };
+        let mut tower = get_builder(conf, chain, &data_dir).build();
+
+        // The error is returned instead of exiting, and whatever was started is stopped
+        match tower.start().await {
+            Err(TowerError::Interface(e)) => assert!(e.contains("metrics")),
+            _ => panic!("Interface error expected"),
+        }
+        assert!(tower.shutdown_signal().is_triggered());
+    }
+}
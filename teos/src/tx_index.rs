@@ -170,7 +170,7 @@ where
 
         if self.is_full() {
             // Avoid logging during bootstrap
-            log::debug!("New block added to index: {}", block_header.block_hash());
+            tracing::debug!("New block added to index: {}", block_header.block_hash());
             self.tip += 1;
             self.remove_oldest_block();
         }
@@ -184,11 +184,11 @@ where
             // Blocks should be disconnected from last backwards. Log if that's not the case so we can revisit this and fix it.
             if let Some(ref h) = self.blocks.pop_back() {
                 if h != block_hash {
-                    log::error!("Disconnected block does not match the oldest block stored in the TxIndex ({block_hash} != {h})");
+                    tracing::error!("Disconnected block does not match the oldest block stored in the TxIndex ({block_hash} != {h})");
                 }
             }
         } else {
-            log::warn!("The index is already empty");
+            tracing::warn!("The index is already empty");
         }
     }
 
@@ -199,7 +199,7 @@ where
         let ks = self.tx_in_block.remove(&h).unwrap();
         self.index.retain(|k, _| !ks.contains(k));
 
-        log::debug!("Oldest block removed from index: {h}");
+        tracing::debug!("Oldest block removed from index: {h}");
     }
 }
 
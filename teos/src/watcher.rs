@@ -1,6 +1,6 @@
 //! Logic related to the Watcher, the components in charge of watching for breaches on chain.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -8,15 +8,24 @@ use bitcoin::secp256k1::SecretKey;
 use bitcoin::{BlockHeader, Transaction};
 use lightning::chain;
 use lightning_block_sync::poll::ValidatedBlock;
+use tracing::{field, instrument, Span};
 
+use teos_common::announcement::TowerAnnouncement;
 use teos_common::appointment::{Appointment, Locator};
-use teos_common::cryptography;
-use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
+use teos_common::cryptography::{self, KeyDecryptionError};
+use teos_common::handoff::KeyHandoff;
+use teos_common::receipts::{
+    AppointmentReceipt, AppointmentSummary, DeletionReceipt, RegistrationReceipt,
+};
 use teos_common::{TowerId, UserId};
 
-use crate::dbm::DBM;
+use crate::dbm::{self, AppointmentRecord, AppointmentsFilter, DBM};
+use crate::events::{self, record_event, Event, EventKind};
 use crate::extended_appointment::{ExtendedAppointment, UUID};
-use crate::gatekeeper::{Gatekeeper, MaxSlotsReached, UserInfo};
+use crate::feed::{Feed, FeedEvent};
+use crate::gatekeeper::{DeletionFailure, Gatekeeper, MaxSlotsReached, SlotsFailure, UserInfo};
+use crate::metrics::Metrics;
+use crate::notifier::{Notification, Notifier, WebhookEvent, WebhookUrlError};
 use crate::responder::{ConfirmationStatus, Responder, TransactionTracker};
 use crate::tx_index::TxIndex;
 
@@ -53,6 +62,16 @@ pub(crate) enum AddAppointmentFailure {
     AlreadyTriggered,
 }
 
+impl From<SlotsFailure> for AddAppointmentFailure {
+    fn from(e: SlotsFailure) -> Self {
+        match e {
+            // The user has been outdated since it was authenticated.
+            SlotsFailure::UserNotFound => AddAppointmentFailure::AuthenticationFailure,
+            SlotsFailure::NotEnoughSlots => AddAppointmentFailure::NotEnoughSlots,
+        }
+    }
+}
+
 /// Packs the reasons why trying to query an appointment may fail.
 #[derive(Debug)]
 pub(crate) enum GetAppointmentFailure {
@@ -68,14 +87,36 @@ pub(crate) enum GetSubscriptionInfoFailure {
     SubscriptionExpired(u32),
 }
 
+/// Packs the reasons why trying to delete a batch of appointments may fail.
+#[derive(Debug)]
+pub(crate) enum DeleteAppointmentFailure {
+    AuthenticationFailure,
+    InvalidNonce,
+    SubscriptionExpired(u32),
+    NotFound(Locator),
+    AlreadyTriggered(Locator),
+}
+
+/// Packs the reasons why trying to register a webhook may fail.
+#[derive(Debug)]
+pub(crate) enum RegisterWebhookFailure {
+    AuthenticationFailure,
+    InvalidNonce,
+    SubscriptionExpired(u32),
+    InvalidUrl(WebhookUrlError),
+    StorageFailure,
+}
+
 /// Wraps the returning information regarding a queried appointment.
 ///
 /// Either an [Appointment] or a [TransactionTracker] can be
 /// returned depending on whether the appointment can be found in the [Watcher] or in the [Responder].
+/// Once the tower is done with a triggered appointment, a signed [AppointmentSummary] of the response is returned instead.
 #[derive(Debug)]
 pub(crate) enum AppointmentInfo {
     Appointment(Appointment),
     Tracker(TransactionTracker),
+    Summary(AppointmentSummary),
 }
 
 /// Types of new appointments stored in the [Watcher].
@@ -93,6 +134,18 @@ enum TriggeredAppointment {
     Invalid,
 }
 
+/// Creates the span an appointment is processed under.
+///
+/// The user id and UUID of the appointment are not known until the user is authenticated, so they are recorded later on.
+fn appointment_span(locator: &Locator) -> Span {
+    tracing::info_span!(
+        "appointment",
+        %locator,
+        user_id = field::Empty,
+        uuid = field::Empty
+    )
+}
+
 /// Component in charge of watching for triggers in the chain (aka channel breaches for lightning).
 #[derive(Debug)]
 pub struct Watcher {
@@ -110,10 +163,17 @@ pub struct Watcher {
     pub tower_id: TowerId,
     /// A [DBM] (database manager) instance. Used to persist appointment data into disk.
     dbm: Arc<Mutex<DBM>>,
+    /// A [Notifier] instance. Used to let users know their appointments have been triggered.
+    notifier: Arc<Notifier>,
+    /// The live [Feed] of the tower. New appointments and reorgs are published to it.
+    feed: Arc<Feed>,
+    /// The tower [Metrics], if enabled.
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl Watcher {
     /// Creates a new [Watcher] instance.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         gatekeeper: Arc<Gatekeeper>,
         responder: Arc<Responder>,
@@ -122,6 +182,9 @@ impl Watcher {
         signing_key: SecretKey,
         tower_id: TowerId,
         dbm: Arc<Mutex<DBM>>,
+        notifier: Arc<Notifier>,
+        feed: Arc<Feed>,
+        metrics: Option<Arc<Metrics>>,
     ) -> Self {
         Watcher {
             locator_cache: Mutex::new(TxIndex::new(last_n_blocks, last_known_block_height)),
@@ -131,6 +194,9 @@ impl Watcher {
             signing_key,
             tower_id,
             dbm,
+            notifier,
+            feed,
+            metrics,
         }
     }
 
@@ -164,38 +230,22 @@ impl Watcher {
         appointment: Appointment,
         user_signature: String,
     ) -> Result<(AppointmentReceipt, u32, u32), AddAppointmentFailure> {
-        let user_id = self
-            .gatekeeper
-            .authenticate_user(&appointment.to_vec(), &user_signature)
-            .map_err(|_| AddAppointmentFailure::AuthenticationFailure)?;
-
-        let (has_subscription_expired, expiry) =
-            self.gatekeeper.has_subscription_expired(user_id).unwrap();
-
-        if has_subscription_expired {
-            return Err(AddAppointmentFailure::SubscriptionExpired(expiry));
-        }
-
-        let extended_appointment = ExtendedAppointment::new(
-            appointment,
-            user_id,
-            user_signature,
-            self.last_known_block_height.load(Ordering::Acquire),
-        );
-
+        let _span = appointment_span(&appointment.locator).entered();
+        let (extended_appointment, expiry) = self.check_appointment(appointment, user_signature)?;
+        let user_id = extended_appointment.user_id;
         let uuid = extended_appointment.uuid();
 
-        if self.responder.has_tracker(uuid) {
-            log::info!("Tracker for {uuid} already found in Responder");
-            return Err(AddAppointmentFailure::AlreadyTriggered);
-        }
-
         // TODO: This is not atomic, we update the users slots and THEN add their appointment
         // this means it can happen that we update the slots but some failure happens before we insert their appointment.
         let available_slots = self
             .gatekeeper
             .add_update_appointment(user_id, uuid, &extended_appointment)
-            .map_err(|_| AddAppointmentFailure::NotEnoughSlots)?;
+            .map_err(AddAppointmentFailure::from)?;
+        self.feed.publish(FeedEvent::new_appointment(
+            uuid,
+            user_id,
+            extended_appointment.locator(),
+        ));
 
         // FIXME: There's an edge case here if store_triggered_appointment is called and bitcoind is unreachable.
         // This will hang, the request will timeout but be accepted. However, the user will not be handed the receipt.
@@ -226,6 +276,113 @@ impl Watcher {
         Ok((receipt, available_slots, expiry))
     }
 
+    /// Adds a batch of [Appointment]s to the tower.
+    ///
+    /// Every appointment in the batch is checked in the same way as in [add_appointment](Self::add_appointment), and
+    /// a result is returned for each of them (in the same order they were provided). The accepted appointments and
+    /// the slots they consume are persisted at once, so either all of them make it to the database or none does.
+    pub(crate) fn add_appointments(
+        &self,
+        appointments: Vec<(Appointment, String)>,
+    ) -> Vec<Result<(AppointmentReceipt, u32, u32), AddAppointmentFailure>> {
+        let checked: Vec<_> = appointments
+            .into_iter()
+            .map(|(appointment, user_signature)| {
+                let span = appointment_span(&appointment.locator);
+                let result = span.in_scope(|| self.check_appointment(appointment, user_signature));
+                (span, result)
+            })
+            .collect();
+
+        let mut available_slots = self
+            .gatekeeper
+            .add_update_appointments(
+                &checked
+                    .iter()
+                    .filter_map(|(_, r)| r.as_ref().ok().map(|(a, _)| a))
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter();
+
+        checked
+            .into_iter()
+            .map(|(span, r)| {
+                let _span = span.entered();
+                let (extended_appointment, expiry) = r?;
+                let available_slots = available_slots
+                    .next()
+                    .unwrap()
+                    .map_err(AddAppointmentFailure::from)?;
+                self.feed.publish(FeedEvent::new_appointment(
+                    extended_appointment.uuid(),
+                    extended_appointment.user_id,
+                    extended_appointment.locator(),
+                ));
+
+                // Appointments that were triggered in blocks held in the cache are already in the database,
+                // so they only need to be handed to the Responder.
+                let dispute_tx = self
+                    .locator_cache
+                    .lock()
+                    .unwrap()
+                    .get(&extended_appointment.locator())
+                    .cloned();
+                if let Some(dispute_tx) = dispute_tx {
+                    self.handle_stored_triggered_appointment(&extended_appointment, &dispute_tx);
+                }
+
+                let mut receipt = AppointmentReceipt::new(
+                    extended_appointment.user_signature,
+                    extended_appointment.start_block,
+                );
+                receipt.sign(&self.signing_key);
+
+                Ok((receipt, available_slots, expiry))
+            })
+            .collect()
+    }
+
+    /// Checks whether an [Appointment] can be accepted by the tower.
+    ///
+    /// Returns the [ExtendedAppointment] built from it alongside the subscription expiry of its owner if so.
+    /// The owner and UUID of the appointment are recorded in the current [appointment span](appointment_span).
+    fn check_appointment(
+        &self,
+        appointment: Appointment,
+        user_signature: String,
+    ) -> Result<(ExtendedAppointment, u32), AddAppointmentFailure> {
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(&appointment.to_vec(), &user_signature)
+            .map_err(|_| AddAppointmentFailure::AuthenticationFailure)?;
+
+        let (has_subscription_expired, expiry) =
+            self.gatekeeper.has_subscription_expired(user_id).unwrap();
+
+        if has_subscription_expired {
+            return Err(AddAppointmentFailure::SubscriptionExpired(expiry));
+        }
+
+        let extended_appointment = ExtendedAppointment::new(
+            appointment,
+            user_id,
+            user_signature,
+            self.last_known_block_height.load(Ordering::Acquire),
+        );
+
+        let uuid = extended_appointment.uuid();
+        let span = Span::current();
+        span.record("user_id", &field::display(user_id));
+        span.record("uuid", &field::display(uuid));
+
+        if self.responder.has_tracker(uuid) {
+            tracing::info!("Tracker already found in Responder");
+            return Err(AddAppointmentFailure::AlreadyTriggered);
+        }
+
+        Ok((extended_appointment, expiry))
+    }
+
     /// Stores an appointment in the database (or updates it if it already exists).
     fn store_appointment(
         &self,
@@ -234,10 +391,7 @@ impl Watcher {
     ) -> StoredAppointment {
         let dbm = self.dbm.lock().unwrap();
         if dbm.appointment_exists(uuid) {
-            log::debug!(
-                "User {} is updating appointment {uuid}",
-                appointment.user_id
-            );
+            tracing::debug!("User is updating the appointment");
             dbm.update_appointment(uuid, appointment).unwrap();
             StoredAppointment::Update
         } else {
@@ -257,11 +411,17 @@ impl Watcher {
         user_id: UserId,
         dispute_tx: &Transaction,
     ) -> TriggeredAppointment {
-        log::info!(
-            "Trigger for locator {} found in cache",
-            appointment.locator()
+        tracing::info!("Trigger found in cache");
+        let penalty_tx = cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid());
+        self.notify_trigger(user_id, dispute_tx, penalty_tx.as_ref().ok());
+        self.store_breach_events(
+            uuid,
+            user_id,
+            dispute_tx,
+            penalty_tx.is_ok(),
+            self.last_known_block_height.load(Ordering::Acquire),
         );
-        match cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid()) {
+        match penalty_tx {
             Ok(penalty_tx) => {
                 // Data needs to be added the database straightaway since appointments are
                 // FKs to trackers. If handle breach fails, data will be deleted later.
@@ -279,11 +439,11 @@ impl Watcher {
                     Breach::new(dispute_tx.clone(), penalty_tx),
                     user_id,
                 ) {
-                    log::warn!("Appointment bounced in the Responder. Reason: {reason:?}");
+                    tracing::warn!("Appointment bounced in the Responder. Reason: {reason:?}");
                     self.gatekeeper.delete_appointments(vec![uuid], false);
                     TriggeredAppointment::Rejected
                 } else {
-                    log::info!("Appointment went straight to the Responder");
+                    tracing::info!("Appointment went straight to the Responder");
                     TriggeredAppointment::Accepted
                 }
             }
@@ -293,10 +453,50 @@ impl Watcher {
             // (same as with data that bounces in the Responder). This reduces the appointment slot count so it
             // could be used to discourage user misbehavior.
             Err(_) => {
-                log::info!(
-                    "The appointment contained invalid data {}",
-                    appointment.locator()
-                );
+                tracing::info!("The appointment contained invalid data");
+                TriggeredAppointment::Invalid
+            }
+        }
+    }
+
+    /// Hands an already triggered appointment, that has already been stored in the database, to the [Responder].
+    ///
+    /// If the appointment is rejected by the [Responder], or it contains invalid data, it is wiped from the database
+    /// but the slot is not freed.
+    fn handle_stored_triggered_appointment(
+        &self,
+        appointment: &ExtendedAppointment,
+        dispute_tx: &Transaction,
+    ) -> TriggeredAppointment {
+        tracing::info!("Trigger found in cache");
+        let uuid = appointment.uuid();
+        let penalty_tx = cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid());
+        self.notify_trigger(appointment.user_id, dispute_tx, penalty_tx.as_ref().ok());
+        self.store_breach_events(
+            uuid,
+            appointment.user_id,
+            dispute_tx,
+            penalty_tx.is_ok(),
+            self.last_known_block_height.load(Ordering::Acquire),
+        );
+        match penalty_tx {
+            Ok(penalty_tx) => {
+                if let ConfirmationStatus::Rejected(reason) = self.responder.handle_breach(
+                    uuid,
+                    Breach::new(dispute_tx.clone(), penalty_tx),
+                    appointment.user_id,
+                ) {
+                    tracing::warn!("Appointment bounced in the Responder. Reason: {reason:?}");
+                    self.gatekeeper.delete_appointments(vec![uuid], false);
+                    TriggeredAppointment::Rejected
+                } else {
+                    tracing::info!("Appointment went straight to the Responder");
+                    TriggeredAppointment::Accepted
+                }
+            }
+            Err(_) => {
+                tracing::info!("The appointment contained invalid data");
+                self.gatekeeper.delete_appointments(vec![uuid], false);
                 TriggeredAppointment::Invalid
             }
         }
@@ -336,12 +536,92 @@ impl Watcher {
                 dbm.load_appointment(uuid)
                     .map(|ext_app| AppointmentInfo::Appointment(ext_app.inner))
             })
+            .or_else(|| {
+                events::summarize(&dbm.load_events(Some(uuid), None)).map(|mut summary| {
+                    summary.sign(&self.signing_key);
+                    AppointmentInfo::Summary(summary)
+                })
+            })
             .ok_or_else(|| {
-                log::info!("Cannot find {locator}");
+                tracing::info!(%locator, "Cannot find locator");
                 GetAppointmentFailure::NotFound
             })
     }
 
+    /// Deletes a batch of appointments from the tower on behalf of their owner, refunding the slots they consumed.
+    ///
+    /// Appointments can only be deleted provided:
+    /// - The user is registered into the system
+    /// - The request nonce has not been used before (it is greater than the last one used by the user)
+    /// - All the appointments belong to the user and can be found in the [Watcher]
+    /// - None of the appointments has been responded to yet (data cannot be found in the [Responder])
+    /// - The user subscription has not expired
+    ///
+    /// The signed message commits to the tower id and the nonce, so it cannot be replayed (to this or any other tower).
+    /// The batch is either deleted as a whole or not deleted at all, and the nonce is only used up if it is, so
+    /// failed requests can be retried.
+    pub(crate) fn delete_appointments(
+        &self,
+        locators: Vec<Locator>,
+        nonce: u64,
+        user_signature: String,
+    ) -> Result<DeletionReceipt, DeleteAppointmentFailure> {
+        let message = format!(
+            "delete appointments {} {nonce} {}",
+            self.tower_id,
+            locators
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(message.as_bytes(), &user_signature)
+            .map_err(|_| DeleteAppointmentFailure::AuthenticationFailure)?;
+
+        // Repeated locators are only deleted (and refunded) once.
+        let mut seen = HashSet::new();
+        let locators: Vec<Locator> = locators.into_iter().filter(|l| seen.insert(*l)).collect();
+
+        let uuids: Vec<UUID> = locators
+            .iter()
+            .map(|locator| UUID::new(*locator, user_id))
+            .collect();
+        let locator_of = |uuid| locators[uuids.iter().position(|u| *u == uuid).unwrap()];
+
+        let available_slots = self
+            .gatekeeper
+            .delete_user_appointments(user_id, uuids.clone(), nonce)
+            .map_err(|e| match e {
+                // The user has been outdated since it was authenticated (and none of the locators was provided,
+                // otherwise the appointments of the user would have been reported as not found).
+                DeletionFailure::UserNotFound => DeleteAppointmentFailure::AuthenticationFailure,
+                DeletionFailure::SubscriptionExpired(expiry) => {
+                    DeleteAppointmentFailure::SubscriptionExpired(expiry)
+                }
+                DeletionFailure::InvalidNonce => DeleteAppointmentFailure::InvalidNonce,
+                DeletionFailure::AlreadyTriggered(uuid) => {
+                    tracing::info!(%uuid, "Tracker already found in Responder. Refusing to delete it");
+                    DeleteAppointmentFailure::AlreadyTriggered(locator_of(uuid))
+                }
+                DeletionFailure::NotFound(uuid) => {
+                    tracing::info!(locator = %locator_of(uuid), "Cannot find locator");
+                    DeleteAppointmentFailure::NotFound(locator_of(uuid))
+                }
+            })?;
+        tracing::info!(
+            "User {user_id} deleted {} appointment(s) from the tower",
+            locators.len()
+        );
+
+        let mut receipt = DeletionReceipt::new(user_signature, locators, available_slots);
+        receipt.sign(&self.signing_key);
+
+        Ok(receipt)
+    }
+
     /// Gets a map of breaches provided a map between locators and transactions.
     ///
     /// The provided map if intersected with the map of all locators monitored by [Watcher] and the result
@@ -361,9 +641,12 @@ impl Watcher {
             .collect();
 
         if breaches.is_empty() {
-            log::info!("No breaches found")
+            tracing::info!("No breaches found")
         } else {
-            log::debug!("List of breaches: {:?}", breaches.keys());
+            tracing::debug!("List of breaches: {:?}", breaches.keys());
+            if let Some(metrics) = &self.metrics {
+                metrics.breaches.inc_by(breaches.len() as u64);
+            }
         }
 
         breaches
@@ -375,7 +658,11 @@ impl Watcher {
     /// If the decryption fails for some appointments or if it succeeds but they get rejected when sent to the network,
     /// they are marked as an invalid breaches and returned.
     /// [None] is returned if none of these breaches are invalid.
-    fn handle_breaches(&self, breaches: HashMap<Locator, Transaction>) -> Option<Vec<UUID>> {
+    fn handle_breaches(
+        &self,
+        breaches: HashMap<Locator, Transaction>,
+        height: u32,
+    ) -> Option<Vec<UUID>> {
         let mut invalid_breaches = Vec::new();
 
         for (locator, dispute_tx) in breaches.into_iter() {
@@ -383,7 +670,24 @@ impl Watcher {
             let uuids = self.dbm.lock().unwrap().load_uuids(locator);
             for uuid in uuids {
                 let appointment = self.dbm.lock().unwrap().load_appointment(uuid).unwrap();
-                match cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid()) {
+                let _span = tracing::info_span!(
+                    "breach",
+                    %locator,
+                    %uuid,
+                    user_id = %appointment.user_id
+                )
+                .entered();
+                let penalty_tx =
+                    cryptography::decrypt(appointment.encrypted_blob(), &dispute_tx.txid());
+                self.notify_trigger(appointment.user_id, &dispute_tx, penalty_tx.as_ref().ok());
+                self.store_breach_events(
+                    uuid,
+                    appointment.user_id,
+                    &dispute_tx,
+                    penalty_tx.is_ok(),
+                    height,
+                );
+                match penalty_tx {
                     Ok(penalty_tx) => {
                         if let ConfirmationStatus::Rejected(_) = self.responder.handle_breach(
                             uuid,
@@ -403,6 +707,63 @@ impl Watcher {
         (!invalid_breaches.is_empty()).then_some(invalid_breaches)
     }
 
+    /// Lets a user know that one of their appointments has been triggered by `dispute_tx`.
+    fn notify_trigger(
+        &self,
+        user_id: UserId,
+        dispute_tx: &Transaction,
+        penalty_tx: Option<&Transaction>,
+    ) {
+        self.notifier.notify(Notification::new(
+            WebhookEvent::Triggered,
+            user_id,
+            dispute_tx.txid(),
+            penalty_tx.map(|tx| tx.txid()),
+            None,
+        ));
+    }
+
+    /// Records in the audit history (and publishes to the live feed) that an appointment has been triggered by `dispute_tx`,
+    /// and whether it could be decrypted.
+    fn store_breach_events(
+        &self,
+        uuid: UUID,
+        user_id: UserId,
+        dispute_tx: &Transaction,
+        decrypted: bool,
+        height: u32,
+    ) {
+        let dispute_txid = dispute_tx.txid();
+        let locator = Locator::new(dispute_txid);
+        let dbm = self.dbm.lock().unwrap();
+        record_event(
+            &dbm,
+            &self.feed,
+            Event::new(
+                uuid,
+                user_id,
+                locator,
+                EventKind::BreachDetected,
+                height,
+                Some(dispute_txid),
+            ),
+        );
+        if !decrypted {
+            record_event(
+                &dbm,
+                &self.feed,
+                Event::new(
+                    uuid,
+                    user_id,
+                    locator,
+                    EventKind::DecryptionFailed,
+                    height,
+                    None,
+                ),
+            );
+        }
+    }
+
     /// Ges the number of users currently registered with the tower.
     pub(crate) fn get_registered_users_count(&self) -> usize {
         self.gatekeeper.get_registered_users_count()
@@ -418,11 +779,6 @@ impl Watcher {
         self.responder.get_trackers_count()
     }
 
-    /// Gets all the appointments stored in the [Watcher] (from the database).
-    pub(crate) fn get_all_watcher_appointments(&self) -> HashMap<UUID, ExtendedAppointment> {
-        self.dbm.lock().unwrap().load_appointments(None)
-    }
-
     /// Gets all the appointments matching a specific locator from the [Watcher] (from the database).
     pub(crate) fn get_watcher_appointments_with_locator(
         &self,
@@ -431,11 +787,6 @@ impl Watcher {
         self.dbm.lock().unwrap().load_appointments(Some(locator))
     }
 
-    /// Gets all the trackers stored in the [Responder] (from the database).
-    pub(crate) fn get_all_responder_trackers(&self) -> HashMap<UUID, TransactionTracker> {
-        self.dbm.lock().unwrap().load_trackers(None)
-    }
-
     /// Gets all the trackers matching s specific locator from the [Responder] (from the database).
     pub(crate) fn get_responder_trackers_with_locator(
         &self,
@@ -444,6 +795,26 @@ impl Watcher {
         self.dbm.lock().unwrap().load_trackers(Some(locator))
     }
 
+    /// Gets a page of the appointments and trackers held by the tower (from the database) that match a given filter.
+    ///
+    /// Results are sorted by [UUID] and start right after `cursor` (from the beginning if unset).
+    pub(crate) fn get_appointments_page(
+        &self,
+        filter: &AppointmentsFilter,
+        cursor: Option<UUID>,
+        limit: usize,
+    ) -> Vec<(UUID, AppointmentRecord)> {
+        self.dbm
+            .lock()
+            .unwrap()
+            .load_appointments_page(filter, cursor, limit)
+    }
+
+    /// Gets the audit history of the tower (from the database), optionally filtered by [UUID] and/or [Locator].
+    pub(crate) fn get_events(&self, uuid: Option<UUID>, locator: Option<Locator>) -> Vec<Event> {
+        self.dbm.lock().unwrap().load_events(uuid, locator)
+    }
+
     /// Gets the list of all registered user ids.
     pub(crate) fn get_user_ids(&self) -> Vec<UserId> {
         self.gatekeeper.get_user_ids()
@@ -476,6 +847,82 @@ impl Watcher {
         let (subscription_info, locators) = self.gatekeeper.get_user_info(user_id).unwrap();
         Ok((subscription_info, locators))
     }
+
+    /// Sets the webhook a user wants to be notified at when their appointments are triggered.
+    ///
+    /// An empty `url` removes the user's webhook (and any notification pending to be delivered to it). Otherwise, the
+    /// url must only point to public addresses.
+    ///
+    /// The signed message commits to the tower id and a nonce, so it cannot be replayed (to this or any other tower).
+    pub(crate) async fn register_webhook(
+        &self,
+        url: &str,
+        nonce: u64,
+        signature: &str,
+    ) -> Result<UserId, RegisterWebhookFailure> {
+        let message = format!("register webhook {} {nonce} {url}", self.tower_id);
+
+        let user_id = self
+            .gatekeeper
+            .authenticate_user(message.as_bytes(), signature)
+            .map_err(|_| RegisterWebhookFailure::AuthenticationFailure)?;
+
+        self.gatekeeper
+            .check_nonce(user_id, nonce)
+            .map_err(|_| RegisterWebhookFailure::InvalidNonce)?;
+
+        let (has_subscription_expired, expiry) =
+            self.gatekeeper.has_subscription_expired(user_id).unwrap();
+
+        if has_subscription_expired {
+            return Err(RegisterWebhookFailure::SubscriptionExpired(expiry));
+        }
+
+        if url.is_empty() {
+            // Removing a non-existing webhook is not an error.
+            self.dbm.lock().unwrap().remove_webhook(user_id).ok();
+        } else {
+            self.notifier
+                .check_webhook(url)
+                .await
+                .map_err(RegisterWebhookFailure::InvalidUrl)?;
+            self.dbm
+                .lock()
+                .unwrap()
+                .store_webhook(user_id, url)
+                .map_err(|_| RegisterWebhookFailure::StorageFailure)?;
+        }
+
+        Ok(user_id)
+    }
+
+    /// Signs a tower announcement with the tower key.
+    pub(crate) fn sign_announcement(&self, announcement: &mut TowerAnnouncement) {
+        announcement.sign(&self.signing_key);
+    }
+
+    /// Gets all the key handoffs of the tower, oldest first.
+    pub(crate) fn get_key_handoffs(&self) -> Vec<KeyHandoff> {
+        self.dbm.lock().unwrap().load_key_handoffs()
+    }
+
+    /// Changes the passphrase the tower keys are encrypted with.
+    ///
+    /// Deriving the encryption keys is expensive by design, so the keys are re-encrypted without holding the database
+    /// lock. They are only replaced if they have not changed in the meantime, starting over otherwise.
+    pub(crate) fn change_key_passphrase(
+        &self,
+        old_passphrase: Option<&str>,
+        new_passphrase: &str,
+    ) -> Result<(), KeyDecryptionError> {
+        loop {
+            let keys = self.dbm.lock().unwrap().load_encoded_tower_keys();
+            let keys = dbm::reencrypt_tower_keys(keys, old_passphrase, new_passphrase)?;
+            if self.dbm.lock().unwrap().replace_encoded_tower_keys(&keys) {
+                return Ok(());
+            }
+        }
+    }
 }
 
 /// Listen implementation by the [Watcher]. Handles monitoring and reorgs.
@@ -491,13 +938,14 @@ impl chain::Listen for Watcher {
     ///
     /// This also takes care of updating the [LocatorCache] and removing outdated data from the [Watcher] when
     /// told by the [Gatekeeper].
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn filtered_block_connected(
         &self,
         header: &BlockHeader,
         txdata: &chain::transaction::TransactionData,
         height: u32,
     ) {
-        log::info!("New block received: {}", header.block_hash());
+        tracing::info!("New block received");
 
         let locator_tx_map = txdata
             .iter()
@@ -510,7 +958,9 @@ impl chain::Listen for Watcher {
             .update(*header, &locator_tx_map);
 
         // Get the breaches found in this block, handle them, and delete invalid ones.
-        if let Some(invalid_breaches) = self.handle_breaches(self.get_breaches(locator_tx_map)) {
+        if let Some(invalid_breaches) =
+            self.handle_breaches(self.get_breaches(locator_tx_map), height)
+        {
             self.gatekeeper.delete_appointments(invalid_breaches, false);
         }
 
@@ -522,8 +972,11 @@ impl chain::Listen for Watcher {
     /// Handle reorgs in the [Watcher].
     ///
     /// Fixes the [LocatorCache] by removing the disconnected data and updates the last_known_block_height.
+    #[instrument(name = "block", skip_all, fields(block_hash = %header.block_hash(), height))]
     fn block_disconnected(&self, header: &BlockHeader, height: u32) {
-        log::warn!("Block disconnected: {}", header.block_hash());
+        tracing::warn!("Block disconnected");
+        self.feed
+            .publish(FeedEvent::reorg(header.block_hash(), height));
         self.locator_cache
             .lock()
             .unwrap()
@@ -536,7 +989,6 @@ impl chain::Listen for Watcher {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
     use std::iter::FromIterator;
     use std::ops::Deref;
     use std::sync::{Arc, Mutex};
@@ -548,8 +1000,10 @@ mod tests {
         create_carrier, create_responder, create_watcher, generate_dummy_appointment,
         generate_dummy_appointment_with_user, get_random_tx, BitcoindMock, BitcoindStopper,
         Blockchain, MockOptions, MockedServerQuery, DURATION, EXPIRY_DELTA, SLOTS, START_HEIGHT,
+        USER_CACHE_SIZE,
     };
-    use teos_common::cryptography::get_random_keypair;
+    use teos_common::cryptography::{get_random_bytes, get_random_keypair};
+    use teos_common::test_utils::get_random_locator;
 
     use bitcoin::secp256k1::{PublicKey, Secp256k1};
 
@@ -567,10 +1021,34 @@ mod tests {
     impl Eq for Watcher {}
 
     impl Watcher {
+        pub(crate) fn get_signing_key(&self) -> SecretKey {
+            self.signing_key
+        }
+
+        pub(crate) fn get_all_watcher_appointments(&self) -> HashMap<UUID, ExtendedAppointment> {
+            self.dbm.lock().unwrap().load_appointments(None)
+        }
+
         pub(crate) fn add_dummy_tracker_to_responder(&self, tracker: &TransactionTracker) {
             self.responder.add_dummy_tracker(tracker)
         }
 
+        pub(crate) fn add_dummy_event(&self, event: &Event) {
+            self.dbm.lock().unwrap().store_event(event)
+        }
+
+        pub(crate) fn add_dummy_key_handoff(&self, handoff: &KeyHandoff) {
+            self.dbm
+                .lock()
+                .unwrap()
+                .rotate_tower_key(&get_random_keypair().0, handoff, None)
+                .unwrap()
+        }
+
+        pub(crate) fn add_dummy_tower_key(&self, sk: &SecretKey) {
+            self.dbm.lock().unwrap().store_tower_key(sk, None).unwrap()
+        }
+
         pub(crate) fn add_random_tracker_to_responder(&self) -> TransactionTracker {
             // The confirmation status can be whatever here. Using the most common.
             self.responder
@@ -589,20 +1067,31 @@ mod tests {
     ) -> (Watcher, BitcoindStopper) {
         let bitcoind_mock = BitcoindMock::new(MockOptions::default());
 
+        let feed = Arc::new(Feed::new());
         let gk = Arc::new(Gatekeeper::new(
             chain.get_block_count(),
             SLOTS,
             DURATION,
             EXPIRY_DELTA,
+            USER_CACHE_SIZE,
             dbm.clone(),
+            feed.clone(),
         ));
-        let responder = create_responder(chain, gk.clone(), dbm.clone(), bitcoind_mock.url()).await;
+        let responder = create_responder(
+            chain,
+            gk.clone(),
+            dbm.clone(),
+            bitcoind_mock.url(),
+            feed.clone(),
+        )
+        .await;
         create_watcher(
             chain,
             Arc::new(responder),
             gk.clone(),
             bitcoind_mock,
             dbm.clone(),
+            feed,
         )
         .await
     }
@@ -866,6 +1355,151 @@ mod tests {
         assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid));
     }
 
+    #[tokio::test]
+    async fn test_add_appointments() {
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let tip_txs = chain.blocks.last().unwrap().txdata.clone();
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        // add_appointments follows the same logic as add_appointment, but a result is returned for every
+        // appointment in the batch (in order) and the failure of one of them does not affect the rest.
+        let tower_id = TowerId(PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &watcher.signing_key,
+        ));
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id).unwrap();
+        let (user2_sk, user2_pk) = get_random_keypair();
+        let user2_id = UserId(user2_pk);
+        watcher.register(user2_id).unwrap();
+
+        // Get an appointment to the Responder beforehand so it can be used to check triggered appointments bounce
+        let dispute_tx = get_random_tx();
+        let (uuid, triggered_appointment) =
+            generate_dummy_appointment_with_user(user_id, Some(&dispute_tx.txid()));
+        let triggered_sig =
+            cryptography::sign(&triggered_appointment.inner.to_vec(), &user_sk).unwrap();
+        watcher
+            .add_appointment(triggered_appointment.inner.clone(), triggered_sig.clone())
+            .unwrap();
+        watcher.responder.add_tracker(
+            uuid,
+            Breach::new(dispute_tx, get_random_tx()),
+            user_id,
+            ConfirmationStatus::InMempoolSince(chain.get_block_count()),
+        );
+
+        // A regular appointment (sent twice, so the second counts as an update), an appointment by another user,
+        // one that cannot be authenticated, one that has already been triggered, one whose trigger can be found in the
+        // cache and one whose trigger can be found in the cache but contains invalid data.
+        let appointment = generate_dummy_appointment(None).inner;
+        let user_sig = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        let user2_sig = cryptography::sign(&appointment.to_vec(), &user2_sk).unwrap();
+        let wrong_sig = String::from_utf8((0..65).collect()).unwrap();
+
+        let (uuid_in_cache, appointment_in_cache) =
+            generate_dummy_appointment_with_user(user_id, Some(&tip_txs.last().unwrap().txid()));
+        let in_cache_sig =
+            cryptography::sign(&appointment_in_cache.inner.to_vec(), &user_sk).unwrap();
+
+        let (uuid_invalid, mut invalid_appointment) =
+            generate_dummy_appointment_with_user(user_id, Some(&tip_txs[tip_txs.len() - 2].txid()));
+        invalid_appointment.inner.encrypted_blob.reverse();
+        let invalid_sig =
+            cryptography::sign(&invalid_appointment.inner.to_vec(), &user_sk).unwrap();
+
+        let mut results = watcher
+            .add_appointments(vec![
+                (appointment.clone(), user_sig.clone()),
+                (appointment.clone(), user_sig.clone()),
+                (appointment.clone(), user2_sig.clone()),
+                (appointment.clone(), wrong_sig),
+                (triggered_appointment.inner, triggered_sig),
+                (appointment_in_cache.inner, in_cache_sig.clone()),
+                (invalid_appointment.inner, invalid_sig.clone()),
+            ])
+            .into_iter();
+        assert_eq!(results.len(), 7);
+
+        for expected_slots in [SLOTS - 2, SLOTS - 2] {
+            let (receipt, slots, expiry) = results.next().unwrap().unwrap();
+            assert_appointment_added(slots, expected_slots, expiry, receipt, &user_sig, tower_id);
+        }
+        let (receipt, slots, expiry) = results.next().unwrap().unwrap();
+        assert_appointment_added(slots, SLOTS - 1, expiry, receipt, &user2_sig, tower_id);
+        assert!(matches!(
+            results.next().unwrap(),
+            Err(AddAppointmentFailure::AuthenticationFailure)
+        ));
+        assert!(matches!(
+            results.next().unwrap(),
+            Err(AddAppointmentFailure::AlreadyTriggered)
+        ));
+        let (receipt, slots, expiry) = results.next().unwrap().unwrap();
+        assert_appointment_added(slots, SLOTS - 3, expiry, receipt, &in_cache_sig, tower_id);
+        let (receipt, slots, expiry) = results.next().unwrap().unwrap();
+        assert_appointment_added(slots, SLOTS - 4, expiry, receipt, &invalid_sig, tower_id);
+
+        // The appointment of each user is in the Watcher, the one found in the cache went straight to the Responder
+        // and the invalid one is nowhere to be found (but the slot is not freed)
+        assert_eq!(watcher.get_appointments_count(), 2);
+        assert_eq!(watcher.responder.get_trackers_count(), 2);
+        assert!(watcher.responder.has_tracker(uuid_in_cache));
+        assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid_invalid));
+        assert_eq!(
+            watcher
+                .dbm
+                .lock()
+                .unwrap()
+                .load_user(user_id)
+                .unwrap()
+                .available_slots,
+            SLOTS - 4
+        );
+
+        // Slots are accounted for across the batch, so only the appointments that fit are accepted
+        watcher
+            .gatekeeper
+            .get_registered_users()
+            .lock()
+            .unwrap()
+            .get_mut(&user_id)
+            .unwrap()
+            .available_slots = 1;
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user_id, None);
+        let (uuid2, appointment2) = generate_dummy_appointment_with_user(user_id, None);
+        let results = watcher.add_appointments(vec![
+            (
+                appointment.inner.clone(),
+                cryptography::sign(&appointment.inner.to_vec(), &user_sk).unwrap(),
+            ),
+            (
+                appointment2.inner.clone(),
+                cryptography::sign(&appointment2.inner.to_vec(), &user_sk).unwrap(),
+            ),
+        ]);
+        assert!(matches!(results[0], Ok((_, 0, _))));
+        assert!(matches!(
+            results[1],
+            Err(AddAppointmentFailure::NotEnoughSlots)
+        ));
+        assert!(watcher.dbm.lock().unwrap().appointment_exists(uuid));
+        assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid2));
+
+        // If the user subscription has expired, the appointment should be rejected.
+        watcher
+            .gatekeeper
+            .add_outdated_user(user2_id, START_HEIGHT as u32);
+        let (uuid, appointment) = generate_dummy_appointment_with_user(user2_id, None);
+        let signature = cryptography::sign(&appointment.inner.to_vec(), &user2_sk).unwrap();
+        assert!(matches!(
+            watcher.add_appointments(vec![(appointment.inner, signature)])[0],
+            Err(AddAppointmentFailure::SubscriptionExpired { .. })
+        ));
+        assert!(!watcher.dbm.lock().unwrap().appointment_exists(uuid));
+    }
+
     #[tokio::test]
     async fn test_store_appointment() {
         let mut chain = Blockchain::default().with_height(START_HEIGHT);
@@ -993,8 +1627,8 @@ mod tests {
 
         match info {
             AppointmentInfo::Appointment(a) => assert_eq!(a, appointment),
-            AppointmentInfo::Tracker { .. } => {
-                panic!("Should have received an appointment, not a tracker")
+            AppointmentInfo::Tracker { .. } | AppointmentInfo::Summary { .. } => {
+                panic!("Should have received an appointment")
             }
         }
 
@@ -1018,8 +1652,8 @@ mod tests {
             .unwrap();
 
         match info {
-            AppointmentInfo::Appointment { .. } => {
-                panic!("Should have received an tracker, not an appointment")
+            AppointmentInfo::Appointment { .. } | AppointmentInfo::Summary { .. } => {
+                panic!("Should have received a tracker")
             }
             AppointmentInfo::Tracker(t) => assert_eq!(t, tracker),
         }
@@ -1047,6 +1681,351 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_get_appointment_summary() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+        let height = START_HEIGHT as u32;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id).unwrap();
+
+        // Add an appointment that cannot be decrypted and trigger it
+        let dispute_tx = get_random_tx();
+        let (uuid, appointment) =
+            generate_dummy_appointment_with_user(user_id, Some(&dispute_tx.txid()));
+        let mut appointment = appointment.inner;
+        appointment.encrypted_blob.reverse();
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), signature)
+            .unwrap();
+
+        let breaches = HashMap::from_iter([(appointment.locator, dispute_tx.clone())]);
+        let invalid_breaches = watcher.handle_breaches(breaches, height).unwrap();
+        watcher
+            .gatekeeper
+            .delete_appointments(invalid_breaches, false);
+
+        // The appointment data is gone, but the events are kept
+        assert_eq!(
+            watcher
+                .get_events(Some(uuid), None)
+                .iter()
+                .map(|e| e.kind)
+                .collect::<Vec<_>>(),
+            vec![EventKind::BreachDetected, EventKind::DecryptionFailed]
+        );
+
+        // So a signed summary of the response is returned instead
+        let message = format!("get appointment {}", appointment.locator);
+        let signature = cryptography::sign(message.as_bytes(), &user_sk).unwrap();
+        match watcher
+            .get_appointment(appointment.locator, &signature)
+            .unwrap()
+        {
+            AppointmentInfo::Summary(summary) => {
+                assert_eq!(summary.locator(), appointment.locator);
+                assert_eq!(summary.dispute_txid(), dispute_tx.txid());
+                assert_eq!(summary.penalty_txid(), None);
+                assert_eq!(summary.breach_height(), height);
+                assert_eq!(summary.outcome(), "decryption_failed");
+                let recovered_pk =
+                    cryptography::recover_pk(&summary.to_vec(), &summary.signature().unwrap())
+                        .unwrap();
+                assert_eq!(TowerId(recovered_pk), watcher.tower_id);
+            }
+            _ => panic!("Should have received a summary"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointments() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let available_slots = watcher.register(user_id).unwrap().available_slots();
+
+        let mut locators = Vec::new();
+        for _ in 0..3 {
+            let appointment = generate_dummy_appointment(None).inner;
+            watcher
+                .add_appointment(
+                    appointment.clone(),
+                    cryptography::sign(&appointment.to_vec(), &user_sk).unwrap(),
+                )
+                .unwrap();
+            locators.push(appointment.locator);
+        }
+        let tower_id = watcher.tower_id;
+        let sign_deletion = |locators: &[Locator], nonce: u64, sk| {
+            let message = format!(
+                "delete appointments {tower_id} {nonce} {}",
+                locators
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            cryptography::sign(message.as_bytes(), sk).unwrap()
+        };
+
+        // If the user cannot be properly identified, the request will fail. This can be simulated by providing a wrong signature
+        let wrong_sig = String::from_utf8((0..65).collect()).unwrap();
+        assert!(matches!(
+            watcher.delete_appointments(locators.clone(), 1, wrong_sig),
+            Err(DeleteAppointmentFailure::AuthenticationFailure)
+        ));
+
+        // If any of the appointments cannot be found, nothing is deleted
+        let unknown_locator = get_random_locator();
+        let mut batch = locators[..2].to_vec();
+        batch.push(unknown_locator);
+        assert!(matches!(
+            watcher.delete_appointments(batch.clone(), 1, sign_deletion(&batch, 1, &user_sk)),
+            Err(DeleteAppointmentFailure::NotFound(l)) if l == unknown_locator
+        ));
+        for locator in locators.iter() {
+            assert!(watcher
+                .dbm
+                .lock()
+                .unwrap()
+                .appointment_exists(UUID::new(*locator, user_id)));
+        }
+
+        // Appointments that have been triggered (and are therefore being handled by the Responder) cannot be deleted
+        let dispute_tx = get_random_tx();
+        let triggered = generate_dummy_appointment(Some(&dispute_tx.txid())).inner;
+        watcher
+            .add_appointment(
+                triggered.clone(),
+                cryptography::sign(&triggered.to_vec(), &user_sk).unwrap(),
+            )
+            .unwrap();
+        let uuid = UUID::new(triggered.locator, user_id);
+        watcher.responder.add_tracker(
+            uuid,
+            Breach::new(dispute_tx, get_random_tx()),
+            user_id,
+            ConfirmationStatus::ConfirmedIn(chain.get_block_count()),
+        );
+        let batch = vec![locators[0], triggered.locator];
+        assert!(matches!(
+            watcher.delete_appointments(batch.clone(), 2, sign_deletion(&batch, 2, &user_sk)),
+            Err(DeleteAppointmentFailure::AlreadyTriggered(l)) if l == triggered.locator
+        ));
+        assert!(watcher.responder.has_tracker(uuid));
+
+        // Otherwise the appointments are deleted and their slots refunded. Repeated locators are only refunded once.
+        // Failed requests do not use up their nonce, so it can be used here again.
+        let mut batch = locators.clone();
+        batch.push(locators[0]);
+        let receipt = watcher
+            .delete_appointments(batch.clone(), 2, sign_deletion(&batch, 2, &user_sk))
+            .unwrap();
+        assert_eq!(receipt.locators(), locators.as_slice());
+        assert!(receipt.verify(&watcher.tower_id));
+        for locator in locators.iter() {
+            assert!(!watcher
+                .dbm
+                .lock()
+                .unwrap()
+                .appointment_exists(UUID::new(*locator, user_id)));
+        }
+        // Only the slot of the triggered appointment is still in use
+        assert_eq!(receipt.available_slots(), available_slots - 1);
+        assert_eq!(
+            watcher.get_user_info(user_id).unwrap().0.available_slots,
+            available_slots - 1
+        );
+
+        // Replaying the request fails, even if the appointments are sent to the tower again
+        for locator in locators.iter() {
+            let appointment = Appointment::new(*locator, get_random_bytes(32), 42);
+            watcher
+                .add_appointment(
+                    appointment.clone(),
+                    cryptography::sign(&appointment.to_vec(), &user_sk).unwrap(),
+                )
+                .unwrap();
+        }
+        assert!(matches!(
+            watcher.delete_appointments(batch.clone(), 2, sign_deletion(&batch, 2, &user_sk)),
+            Err(DeleteAppointmentFailure::InvalidNonce)
+        ));
+        assert!(matches!(
+            watcher.delete_appointments(batch.clone(), 1, sign_deletion(&batch, 1, &user_sk)),
+            Err(DeleteAppointmentFailure::InvalidNonce)
+        ));
+
+        // Requests signed for a different tower cannot be used either
+        let message = format!(
+            "delete appointments {} 4 {}",
+            TowerId(get_random_keypair().1),
+            locators[0]
+        );
+        assert!(matches!(
+            watcher.delete_appointments(
+                vec![locators[0]],
+                4,
+                cryptography::sign(message.as_bytes(), &user_sk).unwrap()
+            ),
+            Err(DeleteAppointmentFailure::AuthenticationFailure)
+        ));
+
+        // Deleting the same appointments twice fails, since they cannot be found anymore
+        watcher
+            .delete_appointments(locators.clone(), 5, sign_deletion(&locators, 5, &user_sk))
+            .unwrap();
+        assert!(matches!(
+            watcher.delete_appointments(locators.clone(), 6, sign_deletion(&locators, 6, &user_sk)),
+            Err(DeleteAppointmentFailure::NotFound(_))
+        ));
+
+        // If the user subscription has expired, the request will fail
+        for locator in locators.iter() {
+            let appointment = Appointment::new(*locator, get_random_bytes(32), 42);
+            watcher
+                .add_appointment(
+                    appointment.clone(),
+                    cryptography::sign(&appointment.to_vec(), &user_sk).unwrap(),
+                )
+                .unwrap();
+        }
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            watcher.delete_appointments(locators.clone(), 7, sign_deletion(&locators, 7, &user_sk)),
+            Err(DeleteAppointmentFailure::SubscriptionExpired { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_appointments_replay_after_reregistration() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id).unwrap();
+
+        let appointment = generate_dummy_appointment(None).inner;
+        let user_signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), user_signature.clone())
+            .unwrap();
+        let message = format!(
+            "delete appointments {} 1 {}",
+            watcher.tower_id, appointment.locator
+        );
+        let deletion_signature = cryptography::sign(message.as_bytes(), &user_sk).unwrap();
+        watcher
+            .delete_appointments(vec![appointment.locator], 1, deletion_signature.clone())
+            .unwrap();
+
+        // Get the user outdated (and therefore deleted) and register it again
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, chain.get_block_count() + 1);
+        watcher
+            .gatekeeper
+            .block_connected(&chain.generate(None), chain.get_block_count());
+        assert!(watcher.get_user_info(user_id).is_none());
+        watcher.register(user_id).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), user_signature)
+            .unwrap();
+
+        // The old deletion request cannot be replayed, given the nonce outlives the user
+        assert!(matches!(
+            watcher.delete_appointments(vec![appointment.locator], 1, deletion_signature),
+            Err(DeleteAppointmentFailure::InvalidNonce)
+        ));
+        assert!(watcher
+            .dbm
+            .lock()
+            .unwrap()
+            .appointment_exists(UUID::new(appointment.locator, user_id)));
+    }
+
+    #[tokio::test]
+    async fn test_register_webhook() {
+        let mut chain = Blockchain::default().with_height(START_HEIGHT);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        let tower_id = watcher.tower_id;
+        let sign_url = |url: &str, nonce: u64| {
+            cryptography::sign(
+                format!("register webhook {tower_id} {nonce} {url}").as_bytes(),
+                &user_sk,
+            )
+            .unwrap()
+        };
+
+        // Non-registered users cannot set a webhook
+        let url = "http://1.1.1.1/hook";
+        assert!(matches!(
+            watcher.register_webhook(url, 1, &sign_url(url, 1)).await,
+            Err(RegisterWebhookFailure::AuthenticationFailure)
+        ));
+
+        // Registered ones can, and they can also update and remove it
+        watcher.register(user_id).unwrap();
+        let new_url = "https://[2606:4700::1111]/new_hook";
+        for (nonce, (url, expected)) in [(url, Some(url)), (new_url, Some(new_url)), ("", None)]
+            .iter()
+            .enumerate()
+        {
+            let nonce = nonce as u64 + 1;
+            assert_eq!(
+                watcher
+                    .register_webhook(url, nonce, &sign_url(url, nonce))
+                    .await
+                    .unwrap(),
+                user_id
+            );
+            assert_eq!(
+                watcher.dbm.lock().unwrap().load_webhook(user_id).as_deref(),
+                *expected
+            );
+        }
+
+        // Requests cannot be replayed
+        assert!(matches!(
+            watcher.register_webhook(url, 1, &sign_url(url, 1)).await,
+            Err(RegisterWebhookFailure::InvalidNonce)
+        ));
+        assert_eq!(watcher.dbm.lock().unwrap().load_webhook(user_id), None);
+
+        // Webhooks cannot point to local addresses
+        for (nonce, url) in [(4, "http://localhost/hook"), (5, "http://10.0.0.1/hook")] {
+            assert!(matches!(
+                watcher
+                    .register_webhook(url, nonce, &sign_url(url, nonce))
+                    .await,
+                Err(RegisterWebhookFailure::InvalidUrl(
+                    WebhookUrlError::NonPublicAddress
+                ))
+            ));
+        }
+        assert_eq!(watcher.dbm.lock().unwrap().load_webhook(user_id), None);
+
+        // If the user subscription has expired, the request will fail
+        watcher
+            .gatekeeper
+            .add_outdated_user(user_id, START_HEIGHT as u32);
+        assert!(matches!(
+            watcher.register_webhook(url, 6, &sign_url(url, 6)).await,
+            Err(RegisterWebhookFailure::SubscriptionExpired { .. })
+        ));
+    }
+
     #[tokio::test]
     async fn test_get_breaches() {
         let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
@@ -1100,7 +2079,60 @@ mod tests {
             watcher.add_appointment(appointment, signature).unwrap();
         }
 
-        assert!(watcher.handle_breaches(breaches).is_none())
+        assert!(watcher
+            .handle_breaches(breaches, START_HEIGHT as u32)
+            .is_none())
+    }
+
+    #[tokio::test]
+    async fn test_handle_breaches_notifications() {
+        let mut chain = Blockchain::default().with_height_and_txs(START_HEIGHT, 10);
+        let (watcher, _s) = init_watcher(&mut chain).await;
+
+        let (user_sk, user_pk) = get_random_keypair();
+        let user_id = UserId(user_pk);
+        watcher.register(user_id).unwrap();
+        watcher
+            .dbm
+            .lock()
+            .unwrap()
+            .store_webhook(user_id, "http://localhost/hook")
+            .unwrap();
+
+        let dispute_tx = get_random_tx();
+        let appointment = generate_dummy_appointment(Some(&dispute_tx.txid())).inner;
+        let signature = cryptography::sign(&appointment.to_vec(), &user_sk).unwrap();
+        watcher
+            .add_appointment(appointment.clone(), signature)
+            .unwrap();
+
+        let breaches = HashMap::from_iter([(appointment.locator, dispute_tx.clone())]);
+        assert!(watcher
+            .handle_breaches(breaches, START_HEIGHT as u32)
+            .is_none());
+
+        // The user is notified both about the trigger and the penalty being broadcast
+        let notifications: Vec<Notification> = watcher
+            .dbm
+            .lock()
+            .unwrap()
+            .load_due_notifications(u32::MAX as u64)
+            .iter()
+            .map(|n| serde_json::from_str(&n.payload).unwrap())
+            .collect();
+        assert_eq!(
+            notifications
+                .iter()
+                .map(|n| n.event)
+                .collect::<Vec<WebhookEvent>>(),
+            vec![WebhookEvent::Triggered, WebhookEvent::PenaltyBroadcast]
+        );
+        for notification in notifications {
+            assert_eq!(notification.user_id, user_id);
+            assert_eq!(notification.locator, appointment.locator);
+            assert_eq!(notification.dispute_txid, dispute_tx.txid());
+            assert!(notification.penalty_txid.is_some());
+        }
     }
 
     #[tokio::test]
@@ -1135,7 +2167,11 @@ mod tests {
 
         assert_eq!(
             rejected,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
         );
     }
 
@@ -1174,7 +2210,11 @@ mod tests {
 
         assert_eq!(
             uuids,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
         );
     }
 
@@ -1210,7 +2250,11 @@ mod tests {
 
         assert_eq!(
             rejected_breaches,
-            HashSet::from_iter(watcher.handle_breaches(breaches).unwrap())
+            HashSet::from_iter(
+                watcher
+                    .handle_breaches(breaches, START_HEIGHT as u32)
+                    .unwrap()
+            )
         );
     }
 
@@ -1286,14 +2330,14 @@ mod tests {
             .get_registered_users()
             .lock()
             .unwrap()
-            .contains_key(&user_id));
+            .contains(&user_id));
         assert!(watcher.dbm.lock().unwrap().appointment_exists(uuid2));
         assert!(watcher
             .gatekeeper
             .get_registered_users()
             .lock()
             .unwrap()
-            .contains_key(&user2_id));
+            .contains(&user2_id));
 
         // Check triggers. Add a new appointment and trigger it with valid data.
         let dispute_tx = get_random_tx();
@@ -1,6 +1,8 @@
 // Collection of ENV variable names and values
 pub const TOWERS_DATA_DIR: &str = "TOWERS_DATA_DIR";
 pub const DEFAULT_TOWERS_DATA_DIR: &str = ".watchtower";
+pub const WT_KEY_PASSPHRASE: &str = "WT_KEY_PASSPHRASE";
+pub const WT_DB_KEY: &str = "WT_DB_KEY";
 
 /// Collections of plugin option names, default values and descriptions
 
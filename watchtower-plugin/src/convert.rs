@@ -8,6 +8,7 @@ use serde_json::json;
 use bitcoin::{Transaction, Txid};
 
 use teos_common::appointment::Locator;
+use teos_common::net::AddressType;
 use teos_common::TowerId;
 
 /// Errors related to the `registertower` command.
@@ -36,6 +37,7 @@ pub struct RegisterParams {
     pub tower_id: TowerId,
     pub host: Option<String>,
     pub port: Option<u16>,
+    pub https: bool,
 }
 
 impl RegisterParams {
@@ -59,9 +61,14 @@ impl RegisterParams {
                 .map_err(|_| RegisterError::InvalidId("Invalid tower id".to_owned()))?,
             host: None,
             port: None,
+            https: false,
         })
     }
 
+    /// Sets the host of the tower. Hosts can be IP addresses (either v4 or v6), onion addresses or DNS names.
+    ///
+    /// The scheme (either `http://` or `https://`) and the brackets around IPv6 addresses are dropped, if present.
+    /// Towers are reached over plain HTTP unless `https://` is specified.
     fn with_host(self, host: &str) -> Result<Self, RegisterError> {
         if host.is_empty() {
             Err(RegisterError::InvalidHost("hostname is empty".to_owned()))
@@ -70,8 +77,19 @@ impl RegisterParams {
                 "hostname contains white spaces".to_owned(),
             ))
         } else {
+            let (https, host) = match host.strip_prefix("https://") {
+                Some(h) => (true, h),
+                None => (false, host.strip_prefix("http://").unwrap_or(host)),
+            };
+            AddressType::from_host(host).map_err(RegisterError::InvalidHost)?;
+
             Ok(Self {
-                host: Some(String::from(host)),
+                host: Some(
+                    host.trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_owned(),
+                ),
+                https,
                 ..self
             })
         }
@@ -103,9 +121,27 @@ impl TryFrom<serde_json::Value> for RegisterParams {
 
                 match v.next() {
                     Some(x) => {
-                        let mut v = x.split(':');
-                        let host = v.next();
-                        let port = if let Some(p) = v.next() {
+                        // The scheme, if any, is kept along with the host.
+                        let (scheme, x) = match x.find("://") {
+                            Some(i) => x.split_at(i + 3),
+                            None => ("", x),
+                        };
+                        // IPv6 hosts need to be enclosed in brackets to be followed by a port. Unbracketed hosts
+                        // with more than one colon are considered IPv6 hosts with no port.
+                        let (host, port) = if let Some(h) = x.strip_prefix('[') {
+                            let (host, rest) = h.split_once(']').ok_or_else(|| RegisterError::InvalidHost(format!("Unclosed bracket in host: {x}")))?;
+                            match rest.strip_prefix(':') {
+                                Some(p) => (Some(host), Some(p)),
+                                None if rest.is_empty() => (Some(host), None),
+                                None => return Err(RegisterError::InvalidHost(format!("Unexpected data after host: {rest}"))),
+                            }
+                        } else if x.matches(':').count() > 1 {
+                            (Some(x), None)
+                        } else {
+                            let mut v = x.split(':');
+                            (v.next(), v.next())
+                        };
+                        let port = if let Some(p) = port {
                             p.parse()
                                 .map(Some)
                                 .map_err(|_| RegisterError::InvalidPort(format!("Port is not a number: {p}")))?
@@ -113,7 +149,8 @@ impl TryFrom<serde_json::Value> for RegisterParams {
                             None
                         };
 
-                        RegisterParams::new(tower_id, host, port)
+                        let host = host.map(|h| format!("{scheme}{h}"));
+                        RegisterParams::new(tower_id, host.as_deref(), port)
                     }
                     None => RegisterParams::from_id(tower_id),
                 }
@@ -301,6 +338,41 @@ mod tests {
             let host = "myhost";
             assert_eq!(params.with_host(host).unwrap().host, Some(host.to_owned()));
 
+            // IP addresses, DNS names and onion addresses are all valid. The scheme and brackets are dropped
+            for (host, expected) in [
+                ("172.16.0.1", "172.16.0.1"),
+                ("2001:db8::1", "2001:db8::1"),
+                ("[2001:db8::1]", "2001:db8::1"),
+                ("teos.talaia.watch", "teos.talaia.watch"),
+                ("http://teos.talaia.watch", "teos.talaia.watch"),
+                ("https://teos.talaia.watch", "teos.talaia.watch"),
+                ("https://[2001:db8::1]", "2001:db8::1"),
+                (
+                    "recnedb7xfhzjdrcgxongzli3a6qyrv5jwgowoho3v5g3rwk7kkglrid.onion",
+                    "recnedb7xfhzjdrcgxongzli3a6qyrv5jwgowoho3v5g3rwk7kkglrid.onion",
+                ),
+            ] {
+                let params = RegisterParams::from_id(VALID_ID).unwrap();
+                assert_eq!(
+                    params.with_host(host).unwrap().host,
+                    Some(expected.to_owned())
+                );
+            }
+
+            // Only https hosts are reached over TLS
+            let params = RegisterParams::from_id(VALID_ID).unwrap();
+            assert!(!params.with_host("http://myhost").unwrap().https);
+            let params = RegisterParams::from_id(VALID_ID).unwrap();
+            assert!(params.with_host("https://myhost").unwrap().https);
+
+            // Anything else is not
+            for host in ["my_host", "-myhost", "my..host", "[myhost]", "ftp://myhost"] {
+                assert!(matches!(
+                    RegisterParams::from_id(VALID_ID).unwrap().with_host(host),
+                    Err(RegisterError::InvalidHost(..))
+                ));
+            }
+
             // Host must not be empty not have spaces
             assert!(matches!(
                 RegisterParams::from_id(VALID_ID).unwrap().with_host(""),
@@ -336,6 +408,13 @@ mod tests {
             let ok = [
                 format!("{VALID_ID}@host:80"),
                 format!("{VALID_ID}@host"),
+                format!("{VALID_ID}@172.16.0.1:80"),
+                format!("{VALID_ID}@[2001:db8::1]:80"),
+                format!("{VALID_ID}@[2001:db8::1]"),
+                format!("{VALID_ID}@2001:db8::1"),
+                format!("{VALID_ID}@http://host:80"),
+                format!("{VALID_ID}@https://host:443"),
+                format!("{VALID_ID}@https://[2001:db8::1]:443"),
                 VALID_ID.to_string(),
             ];
             let wrong_id = ["", "id@host:80", "@host:80", "@:80"];
@@ -344,8 +423,18 @@ mod tests {
                 format!("{VALID_ID}@ "),
                 format!("{VALID_ID}@ host"),
                 format!("{VALID_ID}@:80"),
+                format!("{VALID_ID}@host_name:80"),
+                format!("{VALID_ID}@[2001:db8::1:80"),
+                format!("{VALID_ID}@[2001:db8::1]80"),
+                format!("{VALID_ID}@[]:80"),
+                format!("{VALID_ID}@https://:443"),
+                format!("{VALID_ID}@ftp://host:80"),
+            ];
+            let wrong_port = [
+                format!("{VALID_ID}@host:"),
+                format!("{VALID_ID}@host:port"),
+                format!("{VALID_ID}@[2001:db8::1]:port"),
             ];
-            let wrong_port = [format!("{VALID_ID}@host:"), format!("{VALID_ID}@host:port")];
 
             for s in ok {
                 let v = serde_json::Value::Array(vec![serde_json::Value::String(s.to_string())]);
@@ -353,6 +442,14 @@ mod tests {
                 assert!(matches!(p, Ok(..)));
             }
 
+            // The scheme is kept apart from the host and port
+            let p =
+                RegisterParams::try_from(json!(format!("{VALID_ID}@https://[2001:db8::1]:443")))
+                    .unwrap();
+            assert_eq!(p.host, Some("2001:db8::1".to_owned()));
+            assert_eq!(p.port, Some(443));
+            assert!(p.https);
+
             for s in wrong_id {
                 let v = serde_json::Value::Array(vec![serde_json::Value::String(s.to_string())]);
                 let p = RegisterParams::try_from(v);
@@ -1,20 +1,21 @@
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::Path;
 
 use rusqlite::{params, Connection, Error as SqliteError};
 
 use bitcoin::secp256k1::SecretKey;
 
 use teos_common::appointment::{Appointment, Locator};
-use teos_common::dbm::{DatabaseConnection, DatabaseManager, Error};
+use teos_common::cryptography::{self, KeyDecryptionError};
+use teos_common::dbm::{self, DatabaseConnection, DatabaseManager, Error};
+use teos_common::handoff::KeyHandoff;
 use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
 use teos_common::{TowerId, UserId};
 
 use crate::{AppointmentStatus, MisbehaviorProof, TowerInfo, TowerStatus, TowerSummary};
 
-const TABLES: [&str; 8] = [
+const TABLES: [&str; 9] = [
     "CREATE TABLE IF NOT EXISTS towers (
     tower_id INT PRIMARY KEY,
     net_addr TEXT NOT NULL,
@@ -76,6 +77,16 @@ const TABLES: [&str; 8] = [
     FOREIGN KEY(locator, tower_id)
         REFERENCES appointment_receipts(locator, tower_id)
         ON DELETE CASCADE
+)",
+    "CREATE TABLE IF NOT EXISTS key_handoffs (
+    old_tower_id INT PRIMARY KEY,
+    new_tower_id INT NOT NULL,
+    activation_height INT NOT NULL,
+    signature TEXT NOT NULL,
+    tower_id INT NOT NULL,
+    FOREIGN KEY(tower_id)
+        REFERENCES towers(tower_id)
+        ON DELETE CASCADE
 )",
     "CREATE TABLE IF NOT EXISTS keys (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -103,9 +114,10 @@ impl DatabaseConnection for DBM {
 }
 
 impl DBM {
-    /// Creates a new [DBM] instance.
-    pub fn new(db_path: &PathBuf) -> Result<Self, SqliteError> {
-        let connection = Connection::open(db_path)?;
+    /// Creates a new [DBM] instance. The database is encrypted with `db_key` (if any), which requires the `sqlcipher`
+    /// feature.
+    pub fn new(db_path: &Path, db_key: Option<&str>) -> Result<Self, SqliteError> {
+        let connection = dbm::open_connection(db_path, db_key)?;
         connection.execute("PRAGMA foreign_keys=1;", [])?;
         let mut dbm = Self { connection };
         dbm.create_tables(Vec::from_iter(TABLES))?;
@@ -113,19 +125,19 @@ impl DBM {
         Ok(dbm)
     }
 
-    /// Stores the client secret key into the database.
+    /// Stores the client secret key into the database, encrypted with the given passphrase (if any).
     ///
     /// When a new key is generated, old keys are not overwritten but are not retrievable from the API either.
-    pub fn store_client_key(&self, sk: &SecretKey) -> Result<(), Error> {
+    pub fn store_client_key(&self, sk: &SecretKey, passphrase: Option<&str>) -> Result<(), Error> {
         let query = "INSERT INTO keys (key) VALUES (?)";
-        self.store_data(query, params![sk.display_secret().to_string()])
+        self.store_data(
+            query,
+            params![cryptography::encode_secret_key(sk, passphrase)],
+        )
     }
 
-    /// Loads the last known client secret key from the database.
-    ///
-    /// Loads the key with higher id from the database. Old keys are not overwritten just in case a recovery is needed,
-    /// but they are not accessible from the API either.
-    pub fn load_client_key(&self) -> Option<SecretKey> {
+    /// Loads the last known client secret key (as stored) from the database.
+    fn load_stored_client_key(&self) -> Option<String> {
         let mut stmt = self
             .connection
             .prepare(
@@ -133,11 +145,69 @@ impl DBM {
             )
             .unwrap();
 
-        stmt.query_row(["keys"], |row| {
-            let sk: String = row.get(0).unwrap();
-            Ok(SecretKey::from_str(&sk).unwrap())
-        })
-        .ok()
+        stmt.query_row(["keys"], |row| row.get(0)).ok()
+    }
+
+    /// Loads the last known client secret key from the database, decrypting it with the given passphrase if needed.
+    ///
+    /// Loads the key with higher id from the database. Old keys are not overwritten just in case a recovery is needed,
+    /// but they are not accessible from the API either.
+    pub fn load_client_key(
+        &self,
+        passphrase: Option<&str>,
+    ) -> Result<Option<SecretKey>, KeyDecryptionError> {
+        self.load_stored_client_key()
+            .map(|sk| cryptography::decode_secret_key(&sk, passphrase))
+            .transpose()
+    }
+
+    /// Checks whether the client secret key is encrypted. Returns false if there is no key.
+    pub fn is_client_key_encrypted(&self) -> bool {
+        self.load_stored_client_key()
+            .as_deref()
+            .map(cryptography::is_encrypted_secret_key)
+            == Some(true)
+    }
+
+    /// Changes the passphrase the client secret keys are encrypted with. Keys that are not encrypted get encrypted.
+    ///
+    /// All the keys (including the old ones) are re-encrypted atomically. If any of them cannot be decrypted using
+    /// `old_passphrase`, none of them is changed.
+    pub fn change_key_passphrase(
+        &mut self,
+        old_passphrase: Option<&str>,
+        new_passphrase: &str,
+    ) -> Result<(), KeyDecryptionError> {
+        let tx = self.connection.transaction().unwrap();
+        let keys = {
+            let mut stmt = tx.prepare("SELECT id, key FROM keys").unwrap();
+            let keys = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0).unwrap(),
+                        row.get::<_, String>(1).unwrap(),
+                    ))
+                })
+                .unwrap()
+                .map(|key| key.unwrap())
+                .collect::<Vec<_>>();
+            keys
+        };
+
+        for (id, sk) in keys {
+            let sk = cryptography::decode_secret_key(&sk, old_passphrase)?;
+            tx.execute(
+                "UPDATE keys SET key = ?1 WHERE id = ?2",
+                params![
+                    cryptography::encode_secret_key(&sk, Some(new_passphrase)),
+                    id
+                ],
+            )
+            .unwrap();
+        }
+
+        tx.commit().unwrap();
+        Ok(())
     }
 
     /// Stores a tower record into the database alongside the corresponding registration receipt.
@@ -205,6 +275,7 @@ impl DBM {
         } else if !tower.pending_appointments.is_empty() {
             tower.status = TowerStatus::TemporaryUnreachable;
         }
+        tower.set_key_handoffs(self.load_key_handoffs(tower_id));
 
         Some(tower)
     }
@@ -633,6 +704,88 @@ impl DBM {
             .ok()
     }
 
+    /// Moves all the data of a tower to the id it handed its identity over to, storing the handoffs that lead to it.
+    ///
+    /// The whole record (registration receipts, appointment receipts, pending and invalid appointments, ...) is kept
+    /// as is, just under the new id. `chain` must be the (verified) handoffs from `tower_id` to the new id, oldest first.
+    pub fn rotate_tower_key(
+        &mut self,
+        tower_id: TowerId,
+        chain: &[KeyHandoff],
+    ) -> Result<(), Error> {
+        let new_tower_id = chain.last().ok_or(Error::NotFound)?.new_tower_id();
+        let tx = self.get_mut_connection().transaction().unwrap();
+        // Foreign keys are checked on commit, so the tower and its data can be updated one table at a time.
+        tx.execute("PRAGMA defer_foreign_keys=1;", [])
+            .map_err(Error::Unknown)?;
+        for table in [
+            "towers",
+            "registration_receipts",
+            "appointment_receipts",
+            "pending_appointments",
+            "invalid_appointments",
+            "misbehaving_proofs",
+            "key_handoffs",
+        ] {
+            tx.execute(
+                &format!("UPDATE {table} SET tower_id = ?1 WHERE tower_id = ?2"),
+                params![new_tower_id.to_vec(), tower_id.to_vec()],
+            )
+            .map_err(|e| match e {
+                SqliteError::SqliteFailure(..) => Error::AlreadyExists,
+                _ => Error::Unknown(e),
+            })?;
+        }
+        for handoff in chain {
+            tx.execute(
+                "INSERT INTO key_handoffs (old_tower_id, new_tower_id, activation_height, signature, tower_id) 
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    handoff.old_tower_id().to_vec(),
+                    handoff.new_tower_id().to_vec(),
+                    handoff.activation_height(),
+                    handoff.signature().unwrap(),
+                    new_tower_id.to_vec()
+                ],
+            )
+            .map_err(Error::Unknown)?;
+        }
+
+        tx.commit().map_err(Error::Unknown)
+    }
+
+    /// Loads the handoffs that lead to the current id of a given tower, oldest first.
+    ///
+    /// Receipts issued by the tower before rotating its key can be checked against the previous ids using them.
+    pub fn load_key_handoffs(&self, tower_id: TowerId) -> Vec<KeyHandoff> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT old_tower_id, new_tower_id, activation_height, signature 
+                    FROM key_handoffs 
+                    WHERE tower_id = ? 
+                    ORDER BY activation_height, rowid",
+            )
+            .unwrap();
+
+        stmt.query_map([tower_id.to_vec()], |row| {
+            let old_tower_id: Vec<u8> = row.get(0).unwrap();
+            let new_tower_id: Vec<u8> = row.get(1).unwrap();
+            let activation_height: u32 = row.get(2).unwrap();
+            let signature: String = row.get(3).unwrap();
+
+            Ok(KeyHandoff::with_signature(
+                TowerId::from_slice(&old_tower_id).unwrap(),
+                TowerId::from_slice(&new_tower_id).unwrap(),
+                activation_height,
+                signature,
+            ))
+        })
+        .unwrap()
+        .map(|handoff| handoff.unwrap())
+        .collect()
+    }
+
     /// Checks whether a misbehaving proof exists for a given tower.
     fn exists_misbehaving_proof(&self, tower_id: TowerId) -> bool {
         let mut misbehaving_stmt = self
@@ -648,8 +801,10 @@ mod tests {
     use super::*;
 
     use teos_common::cryptography::get_random_keypair;
+    use teos_common::handoff::is_valid_signer;
     use teos_common::test_utils::{
-        generate_random_appointment, get_random_registration_receipt, get_random_user_id,
+        generate_random_appointment, get_random_appointment_receipt,
+        get_random_registration_receipt, get_random_user_id,
         get_registration_receipt_from_previous,
     };
 
@@ -692,6 +847,25 @@ mod tests {
         dbm.create_tables(Vec::from_iter(TABLES)).unwrap();
     }
 
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_new_encrypted() {
+        let tmp_dir = tempdir::TempDir::new("dbm").unwrap();
+        let db_path = tmp_dir.path().join("watchtowers_db.sql3");
+        let sk = get_random_keypair().0;
+        DBM::new(&db_path, Some("db key"))
+            .unwrap()
+            .store_client_key(&sk, None)
+            .unwrap();
+        assert!(dbm::is_encrypted_database(&db_path));
+
+        // The database can only be opened with the right key
+        assert!(DBM::new(&db_path, None).is_err());
+        assert!(DBM::new(&db_path, Some("wrong key")).is_err());
+        let dbm = DBM::new(&db_path, Some("db key")).unwrap();
+        assert_eq!(dbm.load_client_key(None), Ok(Some(sk)));
+    }
+
     #[test]
     fn test_store_load_tower_record() {
         let mut dbm = DBM::in_memory().unwrap();
@@ -1273,6 +1447,77 @@ mod tests {
         assert_eq!(dbm.load_misbehaving_proof(tower_id).unwrap(), proof);
     }
 
+    #[test]
+    fn test_rotate_tower_key() {
+        let mut dbm = DBM::in_memory().unwrap();
+
+        let (tower_sk, tower_pk) = get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let net_addr = "talaia.watch";
+        let receipt = get_random_registration_receipt();
+        dbm.store_tower_record(tower_id, net_addr, &receipt)
+            .unwrap();
+
+        // Add some data signed with the old key
+        let appointment = generate_random_appointment(None);
+        let appointment_receipt = get_random_appointment_receipt(tower_sk);
+        dbm.store_appointment_receipt(tower_id, appointment.locator, 21, &appointment_receipt)
+            .unwrap();
+        let pending_appointment = generate_random_appointment(None);
+        dbm.store_pending_appointment(tower_id, &pending_appointment)
+            .unwrap();
+
+        // Rotate the key
+        let new_tower_id = get_random_user_id();
+        let mut handoff = KeyHandoff::new(tower_id, new_tower_id, 100);
+        handoff.sign(&tower_sk);
+        dbm.rotate_tower_key(tower_id, &[handoff.clone()]).unwrap();
+
+        // The tower can only be found under its new id, and all its data has been moved along
+        assert!(dbm.load_tower_record(tower_id).is_none());
+        let tower = dbm.load_tower_record(new_tower_id).unwrap();
+        assert_eq!(tower.net_addr, net_addr);
+        assert_eq!(
+            tower.appointments,
+            HashMap::from([(
+                appointment.locator,
+                appointment_receipt.signature().unwrap()
+            )])
+        );
+        assert_eq!(tower.pending_appointments, vec![pending_appointment]);
+        assert_eq!(tower.key_handoffs, vec![handoff]);
+        assert_eq!(
+            dbm.load_registration_receipt(new_tower_id, receipt.user_id())
+                .unwrap(),
+            receipt
+        );
+
+        // Old receipts can still be checked against the key that signed them
+        let stored_receipt = dbm
+            .load_appointment_receipt(new_tower_id, appointment.locator)
+            .unwrap();
+        assert!(stored_receipt.verify(&tower_id));
+        assert!(is_valid_signer(
+            new_tower_id,
+            &dbm.load_key_handoffs(new_tower_id),
+            stored_receipt.start_block(),
+            tower_id
+        ));
+
+        // Rotating into an already known tower fails
+        let other_tower_id = get_random_user_id();
+        dbm.store_tower_record(other_tower_id, net_addr, &get_random_registration_receipt())
+            .unwrap();
+        let (new_sk, _) = get_random_keypair();
+        let mut handoff = KeyHandoff::new(new_tower_id, other_tower_id, 200);
+        handoff.sign(&new_sk);
+        assert!(matches!(
+            dbm.rotate_tower_key(new_tower_id, &[handoff]),
+            Err(Error::AlreadyExists)
+        ));
+        assert!(dbm.load_tower_record(new_tower_id).is_some());
+    }
+
     #[test]
     fn test_store_load_non_existing_misbehaving_proof() {
         let dbm = DBM::in_memory().unwrap();
@@ -1329,11 +1574,45 @@ mod tests {
     fn test_store_load_client_key() {
         let dbm = DBM::in_memory().unwrap();
 
-        assert!(dbm.load_client_key().is_none());
+        assert_eq!(dbm.load_client_key(None), Ok(None));
         for _ in 0..7 {
             let sk = get_random_keypair().0;
-            dbm.store_client_key(&sk).unwrap();
-            assert_eq!(dbm.load_client_key().unwrap(), sk);
+            dbm.store_client_key(&sk, None).unwrap();
+            assert_eq!(dbm.load_client_key(None), Ok(Some(sk)));
+            assert!(!dbm.is_client_key_encrypted());
         }
     }
+
+    #[test]
+    fn test_store_load_encrypted_client_key() {
+        let mut dbm = DBM::in_memory().unwrap();
+        let old_sk = get_random_keypair().0;
+        dbm.store_client_key(&old_sk, None).unwrap();
+
+        // Keys stored in plain text can be encrypted later on
+        dbm.change_key_passphrase(None, "passphrase").unwrap();
+        assert!(dbm.is_client_key_encrypted());
+        assert_eq!(dbm.load_client_key(Some("passphrase")), Ok(Some(old_sk)));
+
+        let sk = get_random_keypair().0;
+        dbm.store_client_key(&sk, Some("passphrase")).unwrap();
+        assert_eq!(dbm.load_client_key(Some("passphrase")), Ok(Some(sk)));
+        assert_eq!(
+            dbm.load_client_key(None),
+            Err(KeyDecryptionError::MissingPassphrase)
+        );
+        assert_eq!(
+            dbm.load_client_key(Some("wrong passphrase")),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+
+        // The passphrase can only be changed knowing the current one
+        assert_eq!(
+            dbm.change_key_passphrase(Some("wrong passphrase"), "new passphrase"),
+            Err(KeyDecryptionError::WrongPassphrase)
+        );
+        dbm.change_key_passphrase(Some("passphrase"), "new passphrase")
+            .unwrap();
+        assert_eq!(dbm.load_client_key(Some("new passphrase")), Ok(Some(sk)));
+    }
 }
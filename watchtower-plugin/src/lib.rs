@@ -4,6 +4,7 @@ use std::fmt;
 use serde::Serialize;
 
 use teos_common::appointment::{Appointment, Locator};
+use teos_common::handoff::KeyHandoff;
 use teos_common::net::NetAddr;
 use teos_common::receipts::AppointmentReceipt;
 use teos_common::TowerId;
@@ -210,6 +211,8 @@ pub struct TowerInfo {
     pub invalid_appointments: Vec<Appointment>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub misbehaving_proof: Option<MisbehaviorProof>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub key_handoffs: Vec<KeyHandoff>,
 }
 
 impl TowerInfo {
@@ -233,6 +236,7 @@ impl TowerInfo {
             pending_appointments,
             invalid_appointments,
             misbehaving_proof: None,
+            key_handoffs: Vec::new(),
         }
     }
 
@@ -246,6 +250,11 @@ impl TowerInfo {
     pub fn set_misbehaving_proof(&mut self, proof: MisbehaviorProof) {
         self.misbehaving_proof = Some(proof);
     }
+
+    /// Sets the key handoffs that lead to the current id of a tower.
+    pub fn set_key_handoffs(&mut self, key_handoffs: Vec<KeyHandoff>) {
+        self.key_handoffs = key_handoffs;
+    }
 }
 
 /// A misbehaving proof. Contains proof of a tower replying with a public key different from the advertised one.
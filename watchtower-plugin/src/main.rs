@@ -12,8 +12,9 @@ use cln_plugin::options::{ConfigOption, Value};
 use cln_plugin::{anyhow, Builder, Error, Plugin};
 
 use teos_common::appointment::{Appointment, Locator};
+use teos_common::constants::PROTOCOL_VERSION;
 use teos_common::net::http::Endpoint;
-use teos_common::net::NetAddr;
+use teos_common::net::{join_host_port, NetAddr};
 use teos_common::protos as common_msgs;
 use teos_common::TowerId;
 use teos_common::{cryptography, errors};
@@ -25,7 +26,7 @@ use watchtower_plugin::net::http::{
 };
 use watchtower_plugin::net::ProxyInfo;
 use watchtower_plugin::retrier::RetryManager;
-use watchtower_plugin::wt_client::{RevocationData, WTClient};
+use watchtower_plugin::wt_client::{follow_key_handoffs, RevocationData, WTClient};
 use watchtower_plugin::{constants, TowerStatus};
 
 fn to_cln_error(e: RequestError) -> Error {
@@ -62,12 +63,15 @@ fn send_to_retrier(state: &MutexGuard<WTClient>, tower_id: TowerId, locator: Loc
 ///     - tower_id host port
 ///     - tower_id@host (will default port to DEFAULT_PORT)
 ///     - tower_id host (will default port to DEFAULT_PORT)
+///
+/// Hosts can be IPv4 or IPv6 addresses, DNS names or onion addresses. IPv6 hosts followed by a port need to be
+/// enclosed in brackets (e.g. tower_id@[::1]:port). Hosts prefixed by `https://` are reached over TLS.
 async fn register(
     plugin: Plugin<Arc<Mutex<WTClient>>>,
     v: serde_json::Value,
 ) -> Result<serde_json::Value, Error> {
     let params = RegisterParams::try_from(v).map_err(|x| anyhow!(x))?;
-    let mut host = params.host.unwrap_or_else(|| "localhost".to_owned());
+    let host = params.host.unwrap_or_else(|| "localhost".to_owned());
     let tower_id = params.tower_id;
     let user_id = plugin.state().lock().unwrap().user_id;
 
@@ -80,15 +84,46 @@ async fn register(
             .map_err(|_| anyhow!("{} out of range", constants::WT_PORT))?,
     );
 
-    let tower_net_addr = {
-        if !host.starts_with("http://") {
-            host = format!("http://{host}")
-        }
-        NetAddr::new(format!("{host}:{port}"))
-    };
+    let scheme = if params.https { "https" } else { "http" };
+    let tower_net_addr = NetAddr::new(format!("{scheme}://{}", join_host_port(&host, port)));
 
     let proxy = plugin.state().lock().unwrap().proxy.clone();
 
+    // Check the tower is compatible with us before registering. Towers running older versions do not serve their
+    // announcement, so not being able to get it is not a reason not to register.
+    match http::get_tower_info(&tower_net_addr, &proxy).await {
+        Ok(announcement) => {
+            if announcement.tower_id() != tower_id || !announcement.verify() {
+                return Err(anyhow!(
+                    "Tower announcement contains bad signature. Are you using the right tower_id?"
+                ));
+            }
+            if !announcement.is_compatible() {
+                return Err(anyhow!(
+                    "Tower runs protocol version {}, but version {PROTOCOL_VERSION} is required",
+                    announcement.protocol_version()
+                ));
+            }
+            log::info!(
+                "Tower announcement verified. Subscription terms: {} slots for {} blocks. Min to_self_delay: {}. Features: {:?}",
+                announcement.subscription_slots(),
+                announcement.subscription_duration(),
+                announcement.min_to_self_delay(),
+                announcement.features()
+            );
+        }
+        Err(RequestError::DeserializeError(e)) => {
+            log::info!("Cannot get tower announcement (the tower may be outdated): {e}");
+        }
+        Err(e) => {
+            let mut state = plugin.state().lock().unwrap();
+            if e.is_connection() && state.towers.contains_key(&tower_id) {
+                state.set_tower_status(tower_id, TowerStatus::TemporaryUnreachable);
+            }
+            return Err(to_cln_error(e));
+        }
+    }
+
     let receipt = http::register(tower_id, user_id, &tower_net_addr, &proxy)
         .await
         .map_err(|e| {
@@ -426,21 +461,36 @@ async fn on_commitment_revocation(
     .unwrap();
 
     // Looks like we cannot iterate through towers given a locked state is not Send (due to the async call),
-    // so we need to clone the bare minimum.
-    let towers = plugin
-        .state()
-        .lock()
-        .unwrap()
-        .towers
-        .iter()
-        .map(|(id, info)| (*id, info.net_addr.clone(), info.status))
-        .collect::<Vec<_>>();
+    // so we need to clone the bare minimum. The key handoffs are needed to check who signed the receipts.
+    let towers = {
+        let state = plugin.state().lock().unwrap();
+        state
+            .towers
+            .iter()
+            .map(|(id, info)| {
+                (
+                    *id,
+                    info.net_addr.clone(),
+                    info.status,
+                    state.dbm.load_key_handoffs(*id),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
 
     let proxy = plugin.state().lock().unwrap().proxy.clone();
 
-    for (tower_id, net_addr, status) in towers {
+    for (tower_id, net_addr, status, key_handoffs) in towers {
         if status.is_reachable() {
-            match http::add_appointment(tower_id, &net_addr, &proxy, &appointment, &signature).await
+            match http::add_appointment(
+                tower_id,
+                &key_handoffs,
+                &net_addr,
+                &proxy,
+                &appointment,
+                &signature,
+            )
+            .await
             {
                 Ok((slots, receipt)) => {
                     plugin
@@ -489,12 +539,22 @@ async fn on_commitment_revocation(
                         }
                     },
                     AddAppointmentError::SignatureError(proof) => {
-                        log::warn!("Cannot recover known tower_id from the appointment receipt. Flagging tower as misbehaving");
-                        plugin
-                            .state()
-                            .lock()
-                            .unwrap()
-                            .flag_misbehaving_tower(tower_id, proof)
+                        // The tower may have rotated its key, in which case the appointment is retried with the new id
+                        if let Some(new_tower_id) =
+                            follow_key_handoffs(plugin.state(), tower_id, &proof).await
+                        {
+                            let mut state = plugin.state().lock().unwrap();
+                            state.set_tower_status(new_tower_id, TowerStatus::TemporaryUnreachable);
+                            state.add_pending_appointment(new_tower_id, &appointment);
+                            send_to_retrier(&state, new_tower_id, appointment.locator);
+                        } else {
+                            log::warn!("Cannot recover known tower_id from the appointment receipt. Flagging tower as misbehaving");
+                            plugin
+                                .state()
+                                .lock()
+                                .unwrap()
+                                .flag_misbehaving_tower(tower_id, proof)
+                        }
                     }
                 },
             };
@@ -625,6 +685,14 @@ async fn main() -> Result<(), Error> {
                     midstate.configuration().always_use_proxy.unwrap_or(false),
                 )
             }),
+            env::var(constants::WT_KEY_PASSPHRASE)
+                .ok()
+                .filter(|passphrase| !passphrase.is_empty())
+                .as_deref(),
+            env::var(constants::WT_DB_KEY)
+                .ok()
+                .filter(|db_key| !db_key.is_empty())
+                .as_deref(),
         )
         .await,
     ));
@@ -1,8 +1,11 @@
 use reqwest::{Method, Response};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryFrom;
 
+use teos_common::announcement::TowerAnnouncement;
 use teos_common::appointment::Appointment;
 use teos_common::cryptography;
+use teos_common::handoff::{self, KeyHandoff};
 use teos_common::net::http::Endpoint;
 use teos_common::net::NetAddr;
 use teos_common::protos as common_msgs;
@@ -86,9 +89,42 @@ pub async fn register(
     })
 }
 
+/// Handles the logic of interacting with the `get_tower_info` endpoint of the tower.
+///
+/// The announcement is returned as is. Checking it was signed by the expected tower is up to the caller.
+pub async fn get_tower_info(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<TowerAnnouncement, RequestError> {
+    process_post_response(get_request(tower_net_addr, Endpoint::GetTowerInfo, proxy).await)
+        .await
+        .and_then(|r: common_msgs::TowerAnnouncement| {
+            TowerAnnouncement::try_from(r).map_err(RequestError::DeserializeError)
+        })
+}
+
+/// Handles the logic of interacting with the `get_key_handoffs` endpoint of the tower.
+///
+/// Handoffs are returned as they are. Checking their signatures (and following them) is up to the caller.
+pub async fn get_key_handoffs(
+    tower_net_addr: &NetAddr,
+    proxy: &Option<ProxyInfo>,
+) -> Result<Vec<KeyHandoff>, RequestError> {
+    process_post_response(get_request(tower_net_addr, Endpoint::GetKeyHandoffs, proxy).await)
+        .await
+        .and_then(|r: common_msgs::GetKeyHandoffsResponse| {
+            r.handoffs
+                .into_iter()
+                .map(KeyHandoff::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(RequestError::DeserializeError)
+        })
+}
+
 /// Encapsulates the logging and response parsing of sending and appointment to the tower.
 pub async fn add_appointment(
     tower_id: TowerId,
+    key_handoffs: &[KeyHandoff],
     tower_net_addr: &NetAddr,
     proxy: &Option<ProxyInfo>,
     appointment: &Appointment,
@@ -98,8 +134,15 @@ pub async fn add_appointment(
         "Sending appointment {} to tower {tower_id}",
         appointment.locator
     );
-    let (response, receipt) =
-        send_appointment(tower_id, tower_net_addr, proxy, appointment, signature).await?;
+    let (response, receipt) = send_appointment(
+        tower_id,
+        key_handoffs,
+        tower_net_addr,
+        proxy,
+        appointment,
+        signature,
+    )
+    .await?;
     log::debug!("Appointment accepted and signed by {tower_id}");
     log::debug!("Remaining slots: {}", response.available_slots);
     log::debug!("Start block: {}", response.start_block);
@@ -108,8 +151,12 @@ pub async fn add_appointment(
 }
 
 /// Handles the logic of interacting with the `add_appointment` endpoint of the tower.
+///
+/// `key_handoffs` are the handoffs leading to `tower_id` (oldest first). The receipt must be signed by a key that was
+/// valid at its `start_block`, otherwise a [MisbehaviorProof] is returned.
 pub async fn send_appointment(
     tower_id: TowerId,
+    key_handoffs: &[KeyHandoff],
     tower_net_addr: &NetAddr,
     proxy: &Option<ProxyInfo>,
     appointment: &Appointment,
@@ -140,7 +187,8 @@ pub async fn send_appointment(
             let recovered_id = TowerId(
                 cryptography::recover_pk(&receipt.to_vec(), &receipt.signature().unwrap()).unwrap(),
             );
-            if recovered_id == tower_id {
+            if handoff::is_valid_signer(tower_id, key_handoffs, receipt.start_block(), recovered_id)
+            {
                 Ok((r, receipt))
             } else {
                 Err(AddAppointmentError::SignatureError(MisbehaviorProof::new(
@@ -332,6 +380,84 @@ mod tests {
         assert!(matches!(error, RequestError::DeserializeError { .. }))
     }
 
+    #[tokio::test]
+    async fn test_get_tower_info() {
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let mut announcement = TowerAnnouncement::new(
+            TowerId(tower_pk),
+            vec!["localhost:9814".to_owned()],
+            vec![Endpoint::GetTowerInfo.to_string()],
+            20,
+            10000,
+            4320,
+        );
+        announcement.sign(&tower_sk);
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("GET", Endpoint::GetTowerInfo.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::TowerAnnouncement::from(announcement.clone())).to_string(),
+            )
+            .create_async()
+            .await;
+
+        let received = get_tower_info(&NetAddr::new(server.url()), &None)
+            .await
+            .unwrap();
+
+        api_mock.assert_async().await;
+        assert_eq!(received, announcement);
+    }
+
+    #[tokio::test]
+    async fn test_get_tower_info_deserialize_error() {
+        // Towers that do not know about the endpoint reply with an empty 404
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("GET", Endpoint::GetTowerInfo.path().as_str())
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let error = get_tower_info(&NetAddr::new(server.url()), &None)
+            .await
+            .unwrap_err();
+
+        api_mock.assert_async().await;
+        assert!(matches!(error, RequestError::DeserializeError { .. }))
+    }
+
+    #[tokio::test]
+    async fn test_get_key_handoffs() {
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let mut handoff = KeyHandoff::new(TowerId(tower_pk), get_random_user_id(), 100);
+        handoff.sign(&tower_sk);
+
+        let mut server = mockito::Server::new_async().await;
+        let api_mock = server
+            .mock("GET", Endpoint::GetKeyHandoffs.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(common_msgs::GetKeyHandoffsResponse {
+                    handoffs: vec![handoff.clone().into()]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let received = get_key_handoffs(&NetAddr::new(server.url()), &None)
+            .await
+            .unwrap();
+
+        api_mock.assert_async().await;
+        assert_eq!(received, vec![handoff]);
+    }
+
     #[tokio::test]
     async fn test_add_appointment() {
         // `add_appointment` is basically a pass trough function for `send_appointment` with some logging and a parse of the outputs
@@ -354,6 +480,7 @@ mod tests {
 
         let (response, receipt) = add_appointment(
             TowerId(tower_pk),
+            &[],
             &NetAddr::new(server.url()),
             &None,
             &appointment,
@@ -387,6 +514,7 @@ mod tests {
 
         let (response, receipt) = send_appointment(
             TowerId(tower_pk),
+            &[],
             &NetAddr::new(server.url()),
             &None,
             &appointment,
@@ -421,6 +549,7 @@ mod tests {
         let tower_id = get_random_user_id();
         let error = send_appointment(
             tower_id,
+            &[],
             &NetAddr::new(server.url()),
             &None,
             &appointment,
@@ -444,10 +573,66 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_send_appointment_rotated_key() {
+        let (old_tower_sk, old_tower_pk) = cryptography::get_random_keypair();
+        let (new_tower_sk, new_tower_pk) = cryptography::get_random_keypair();
+        let appointment = generate_random_appointment(None);
+
+        // Random receipts are issued at height 42, so the old key is no longer valid for them, but the new one is
+        let mut handoff = KeyHandoff::new(TowerId(old_tower_pk), TowerId(new_tower_pk), 40);
+        handoff.sign(&old_tower_sk);
+
+        for (tower_sk, tower_pk, valid) in [
+            (new_tower_sk, new_tower_pk, true),
+            (old_tower_sk, old_tower_pk, false),
+        ] {
+            let appointment_receipt = get_random_appointment_receipt(tower_sk);
+            let add_appointment_response =
+                get_dummy_add_appointment_response(appointment.locator, &appointment_receipt);
+
+            let mut server = mockito::Server::new_async().await;
+            let api_mock = server
+                .mock("POST", Endpoint::AddAppointment.path().as_str())
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(json!(add_appointment_response).to_string())
+                .create_async()
+                .await;
+
+            let result = send_appointment(
+                TowerId(new_tower_pk),
+                &[handoff.clone()],
+                &NetAddr::new(server.url()),
+                &None,
+                &appointment,
+                appointment_receipt.user_signature(),
+            )
+            .await;
+
+            api_mock.assert_async().await;
+            if valid {
+                assert_eq!(result.unwrap().1, appointment_receipt);
+            } else if let Err(AddAppointmentError::SignatureError(proof)) = result {
+                assert_eq!(
+                    MisbehaviorProof::new(
+                        appointment.locator,
+                        appointment_receipt,
+                        TowerId(tower_pk)
+                    ),
+                    proof
+                )
+            } else {
+                panic!("SignatureError was expected")
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_send_appointment_connection_error() {
         let error = send_appointment(
             get_random_user_id(),
+            &[],
             &NetAddr::new("http://server_addr".to_owned()),
             &None,
             &generate_random_appointment(None),
@@ -476,6 +661,7 @@ mod tests {
 
         let error = send_appointment(
             get_random_user_id(),
+            &[],
             &NetAddr::new(server.url()),
             &None,
             &generate_random_appointment(None),
@@ -510,6 +696,7 @@ mod tests {
 
         let error = send_appointment(
             get_random_user_id(),
+            &[],
             &NetAddr::new(server.url()),
             &None,
             &generate_random_appointment(None),
@@ -13,7 +13,7 @@ use teos_common::errors;
 use teos_common::UserId as TowerId;
 
 use crate::net::http::{self, AddAppointmentError};
-use crate::wt_client::{RevocationData, WTClient};
+use crate::wt_client::{follow_key_handoffs, RevocationData, WTClient};
 use crate::{MisbehaviorProof, TowerStatus};
 
 const POLLING_TIME: u64 = 1;
@@ -402,11 +402,29 @@ impl Retrier {
                                 .set_tower_status(self.tower_id, TowerStatus::SubscriptionError)
                         }
                         RetryError::Misbehaving(p) => {
-                            log::warn!("Cannot recover known tower_id from the appointment receipt. Flagging tower as misbehaving");
-                            self.wt_client
-                                .lock()
-                                .unwrap()
-                                .flag_misbehaving_tower(self.tower_id, p);
+                            // The tower may have rotated its key, in which case the pending appointments are retried
+                            // with the new id
+                            if let Some(new_tower_id) =
+                                follow_key_handoffs(&self.wt_client, self.tower_id, &p).await
+                            {
+                                let mut wt_client = self.wt_client.lock().unwrap();
+                                wt_client.set_tower_status(
+                                    new_tower_id,
+                                    TowerStatus::TemporaryUnreachable,
+                                );
+                                let locators =
+                                    wt_client.towers[&new_tower_id].pending_appointments.clone();
+                                wt_client
+                                    .unreachable_towers
+                                    .send((new_tower_id, RevocationData::Stale(locators)))
+                                    .unwrap();
+                            } else {
+                                log::warn!("Cannot recover known tower_id from the appointment receipt. Flagging tower as misbehaving");
+                                self.wt_client
+                                    .lock()
+                                    .unwrap()
+                                    .flag_misbehaving_tower(self.tower_id, p);
+                            }
                         }
                         RetryError::Abandoned => {
                             log::info!("Skipping retrying abandoned tower {}", self.tower_id)
@@ -430,7 +448,7 @@ impl Retrier {
 
     async fn run(&self) -> Result<(), Error<RetryError>> {
         // Create a new scope so we can get all the data only locking the WTClient once.
-        let (tower_id, status, net_addr, user_id, user_sk, proxy) = {
+        let (tower_id, status, net_addr, key_handoffs, user_id, user_sk, proxy) = {
             let wt_client = self.wt_client.lock().unwrap();
             if wt_client.towers.get(&self.tower_id).is_none() {
                 return Err(Error::permanent(RetryError::Abandoned));
@@ -441,6 +459,7 @@ impl Retrier {
                 self.tower_id,
                 tower.status,
                 tower.net_addr.clone(),
+                wt_client.dbm.load_key_handoffs(self.tower_id),
                 wt_client.user_id,
                 wt_client.user_sk,
                 wt_client.proxy.clone(),
@@ -488,6 +507,7 @@ impl Retrier {
 
                 match http::add_appointment(
                     tower_id,
+                    &key_handoffs,
                     &net_addr,
                     &proxy,
                     &appointment,
@@ -581,8 +601,9 @@ mod tests {
     use tokio::sync::mpsc::unbounded_channel;
 
     use teos_common::errors;
+    use teos_common::handoff::KeyHandoff;
     use teos_common::net::http::Endpoint;
-    use teos_common::protos::AddAppointmentRequest;
+    use teos_common::protos::{AddAppointmentRequest, GetKeyHandoffsResponse};
     use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
     use teos_common::test_utils::{
         generate_random_appointment, get_random_registration_receipt, get_random_user_id,
@@ -1037,6 +1058,219 @@ mod tests {
         task.abort();
     }
 
+    #[tokio::test]
+    async fn test_manage_retry_key_rotated() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let (tx, rx) = unbounded_channel();
+        let wt_client = Arc::new(Mutex::new(
+            WTClient::new(tmp_path.path().to_path_buf(), tx.clone()).await,
+        ));
+        let mut server = mockito::Server::new_async().await;
+
+        // Add a tower with pending appointments
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let receipt = get_random_registration_receipt();
+        wt_client
+            .lock()
+            .unwrap()
+            .add_update_tower(tower_id, &server.url(), &receipt)
+            .unwrap();
+
+        // Add appointment to pending
+        let appointment = generate_random_appointment(None);
+        wt_client
+            .lock()
+            .unwrap()
+            .add_pending_appointment(tower_id, &appointment);
+
+        // The tower has rotated its key, so it signs with the new one and serves the handoff to it
+        let (new_tower_sk, new_tower_pk) = cryptography::get_random_keypair();
+        let new_tower_id = TowerId(new_tower_pk);
+        let mut handoff = KeyHandoff::new(tower_id, new_tower_id, 21);
+        handoff.sign(&tower_sk);
+        let handoffs_mock = server
+            .mock("GET", Endpoint::GetKeyHandoffs.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(GetKeyHandoffsResponse {
+                    handoffs: vec![handoff.clone().into()]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut add_appointment_receipt = AppointmentReceipt::new(
+            cryptography::sign(&appointment.to_vec(), &wt_client.lock().unwrap().user_sk).unwrap(),
+            42,
+        );
+        add_appointment_receipt.sign(&new_tower_sk);
+        let add_appointment_response =
+            get_dummy_add_appointment_response(appointment.locator, &add_appointment_receipt);
+        let api_mock = server
+            .mock("POST", Endpoint::AddAppointment.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!(add_appointment_response).to_string())
+            .expect(2)
+            .create_async()
+            .await;
+
+        // Start the task and send the tower to the channel for retry
+        tx.send((tower_id, RevocationData::Fresh(appointment.locator)))
+            .unwrap();
+
+        let wt_client_clone = wt_client.clone();
+        let task = tokio::spawn(async move {
+            RetryManager::new(
+                wt_client_clone,
+                rx,
+                MAX_ELAPSED_TIME,
+                LONG_AUTO_RETRY_DELAY,
+                MAX_INTERVAL_TIME,
+            )
+            .manage_retry()
+            .await
+        });
+
+        // Wait until the tower is followed to its new id and the appointment is accepted there
+        wait_until!(
+            wt_client.lock().unwrap().get_tower_status(&new_tower_id)
+                == Some(TowerStatus::Reachable)
+        );
+
+        {
+            let state = wt_client.lock().unwrap();
+            assert!(!state.towers.contains_key(&tower_id));
+            assert!(state.towers[&new_tower_id].pending_appointments.is_empty());
+            assert!(state
+                .get_tower_status(&new_tower_id)
+                .unwrap()
+                .is_reachable());
+            assert_eq!(
+                state
+                    .get_appointment_receipt(new_tower_id, appointment.locator)
+                    .unwrap(),
+                add_appointment_receipt
+            );
+            assert_eq!(
+                state.load_tower_info(new_tower_id).unwrap().key_handoffs,
+                vec![handoff]
+            );
+        }
+        handoffs_mock.assert_async().await;
+        api_mock.assert_async().await;
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_manage_retry_key_rotated_stale_signature() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let (tx, rx) = unbounded_channel();
+        let wt_client = Arc::new(Mutex::new(
+            WTClient::new(tmp_path.path().to_path_buf(), tx.clone()).await,
+        ));
+        let mut server = mockito::Server::new_async().await;
+
+        // Add a tower that has already been followed to its new key
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let new_tower_id = TowerId(cryptography::get_random_keypair().1);
+        let mut handoff = KeyHandoff::new(tower_id, new_tower_id, 21);
+        handoff.sign(&tower_sk);
+        {
+            let mut state = wt_client.lock().unwrap();
+            state
+                .add_update_tower(tower_id, &server.url(), &get_random_registration_receipt())
+                .unwrap();
+            state
+                .rotate_tower_key(tower_id, new_tower_id, &[handoff.clone()])
+                .unwrap();
+        }
+
+        // Add appointment to pending
+        let appointment = generate_random_appointment(None);
+        wt_client
+            .lock()
+            .unwrap()
+            .add_pending_appointment(new_tower_id, &appointment);
+
+        // The old key signs a receipt past the activation height of the new one
+        let handoffs_mock = server
+            .mock("GET", Endpoint::GetKeyHandoffs.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!(GetKeyHandoffsResponse {
+                    handoffs: vec![handoff.into()]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut add_appointment_receipt = AppointmentReceipt::new(
+            cryptography::sign(&appointment.to_vec(), &wt_client.lock().unwrap().user_sk).unwrap(),
+            42,
+        );
+        add_appointment_receipt.sign(&tower_sk);
+        let add_appointment_response =
+            get_dummy_add_appointment_response(appointment.locator, &add_appointment_receipt);
+        let api_mock = server
+            .mock("POST", Endpoint::AddAppointment.path().as_str())
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!(add_appointment_response).to_string())
+            .create_async()
+            .await;
+
+        // Start the task and send the tower to the channel for retry
+        tx.send((new_tower_id, RevocationData::Fresh(appointment.locator)))
+            .unwrap();
+
+        let wt_client_clone = wt_client.clone();
+        let task = tokio::spawn(async move {
+            RetryManager::new(
+                wt_client_clone,
+                rx,
+                MAX_ELAPSED_TIME,
+                LONG_AUTO_RETRY_DELAY,
+                MAX_INTERVAL_TIME,
+            )
+            .manage_retry()
+            .await
+        });
+
+        // The tower is flagged instead of being followed back to its old key
+        wait_until!(wt_client
+            .lock()
+            .unwrap()
+            .get_tower_status(&new_tower_id)
+            .unwrap()
+            .is_misbehaving());
+
+        {
+            let state = wt_client.lock().unwrap();
+            assert!(!state.towers.contains_key(&tower_id));
+            assert_eq!(
+                state
+                    .load_tower_info(new_tower_id)
+                    .unwrap()
+                    .misbehaving_proof
+                    .unwrap()
+                    .recovered_id,
+                tower_id
+            );
+        }
+        handoffs_mock.assert_async().await;
+        api_mock.assert_async().await;
+
+        task.abort();
+    }
+
     #[tokio::test]
     async fn test_manage_retry_abandoned() {
         let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
@@ -1205,7 +1439,11 @@ mod tests {
         let (tower_sk, tower_pk) = cryptography::get_random_keypair();
         let tower_id = TowerId(tower_pk);
 
-        let mut dbm = DBM::new(&tmp_path.path().to_path_buf().join("watchtowers_db.sql3")).unwrap();
+        let mut dbm = DBM::new(
+            &tmp_path.path().to_path_buf().join("watchtowers_db.sql3"),
+            None,
+        )
+        .unwrap();
         let receipt = get_random_registration_receipt();
         dbm.store_tower_record(tower_id, "http://unreachable.tower", &receipt)
             .unwrap();
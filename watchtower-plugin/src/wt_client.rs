@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use tokio::fs;
 use tokio::sync::mpsc::UnboundedSender;
 
@@ -9,10 +10,12 @@ use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use teos_common::appointment::{Appointment, Locator};
 use teos_common::cryptography;
 use teos_common::dbm::Error as DBError;
+use teos_common::handoff::{self, KeyHandoff};
 use teos_common::receipts::{AppointmentReceipt, RegistrationReceipt};
 use teos_common::{TowerId, UserId};
 
 use crate::dbm::DBM;
+use crate::net::http;
 use crate::net::ProxyInfo;
 use crate::retrier::RetrierStatus;
 use crate::{MisbehaviorProof, SubscriptionError, TowerInfo, TowerStatus, TowerSummary};
@@ -80,13 +83,20 @@ impl WTClient {
         data_dir: PathBuf,
         unreachable_towers: UnboundedSender<(TowerId, RevocationData)>,
     ) -> Self {
-        Self::with_proxy(data_dir, unreachable_towers, None).await
+        Self::with_proxy(data_dir, unreachable_towers, None, None, None).await
     }
 
+    /// Creates a new [WTClient] instance using the given proxy (if any).
+    ///
+    /// If a `key_passphrase` is given, the client keys are encrypted with it at rest (the ones stored in plain text
+    /// included). It is required to load the keys from then on. Likewise, the whole database is encrypted with `db_key`
+    /// if given (which requires the `sqlcipher` feature).
     pub async fn with_proxy(
         data_dir: PathBuf,
         unreachable_towers: UnboundedSender<(TowerId, RevocationData)>,
         proxy: Option<ProxyInfo>,
+        key_passphrase: Option<&str>,
+        db_key: Option<&str>,
     ) -> Self {
         // Create data dir if it does not exist
         fs::create_dir_all(&data_dir).await.unwrap_or_else(|e| {
@@ -94,19 +104,33 @@ impl WTClient {
             std::process::exit(1);
         });
 
-        let dbm = DBM::new(&data_dir.join("watchtowers_db.sql3")).unwrap();
+        let mut dbm = DBM::new(&data_dir.join("watchtowers_db.sql3"), db_key).unwrap_or_else(|e| {
+            log::error!("Cannot open the watchtower client database: {e}");
+            std::process::exit(1);
+        });
 
-        let (user_sk, user_id) = if let Some(sk) = dbm.load_client_key() {
-            (
+        let (user_sk, user_id) = match dbm.load_client_key(key_passphrase) {
+            Ok(Some(sk)) => (
                 sk,
                 UserId(PublicKey::from_secret_key(&Secp256k1::new(), &sk)),
-            )
-        } else {
-            log::info!("Watchtower client keys not found. Creating a fresh set");
-            let (sk, pk) = cryptography::get_random_keypair();
-            dbm.store_client_key(&sk).unwrap();
-            (sk, UserId(pk))
+            ),
+            Ok(None) => {
+                log::info!("Watchtower client keys not found. Creating a fresh set");
+                let (sk, pk) = cryptography::get_random_keypair();
+                dbm.store_client_key(&sk, key_passphrase).unwrap();
+                (sk, UserId(pk))
+            }
+            Err(e) => {
+                log::error!("Cannot load the watchtower client keys. {e}");
+                std::process::exit(1);
+            }
         };
+        if let Some(passphrase) = key_passphrase {
+            if !dbm.is_client_key_encrypted() {
+                log::info!("Encrypting watchtower client keys");
+                dbm.change_key_passphrase(None, passphrase).unwrap();
+            }
+        }
 
         let towers = dbm.load_towers();
         for (tower_id, tower) in towers.iter() {
@@ -290,6 +314,38 @@ impl WTClient {
         }
     }
 
+    /// Moves a tower over to a new id, given the tower has handed its identity over to it.
+    ///
+    /// Only handoffs properly signed (and chained) from `tower_id` to `new_tower_id` are accepted. The tower record is
+    /// kept as is, just under the new id, alongside the handoffs so old receipts can still be checked.
+    pub fn rotate_tower_key(
+        &mut self,
+        tower_id: TowerId,
+        new_tower_id: TowerId,
+        handoffs: &[KeyHandoff],
+    ) -> Result<(), DBError> {
+        if !self.towers.contains_key(&tower_id) {
+            return Err(DBError::NotFound);
+        }
+        if self.towers.contains_key(&new_tower_id) {
+            return Err(DBError::AlreadyExists);
+        }
+
+        let mut chain = handoff::follow(tower_id, handoffs);
+        let len = chain
+            .iter()
+            .position(|h| h.new_tower_id() == new_tower_id)
+            .ok_or(DBError::NotFound)?
+            + 1;
+        chain.truncate(len);
+
+        self.dbm.rotate_tower_key(tower_id, &chain)?;
+        let tower = self.towers.remove(&tower_id).unwrap();
+        self.towers.insert(new_tower_id, tower);
+
+        Ok(())
+    }
+
     /// Removes a tower from the client (both memory and database).
     ///
     /// Any data associated to the tower will be deleted (i.e. links to appointments)
@@ -303,6 +359,58 @@ impl WTClient {
     }
 }
 
+/// Tries to follow a tower that has signed a receipt with an unknown key (`proof.recovered_id`) to its new id.
+///
+/// The handoffs are fetched from the tower. If they hand the tower identity over to the recovered id, and the receipt
+/// was issued once the new key was already valid, the tower is moved to it and the new id is returned. Otherwise,
+/// `None` is returned and the tower is to be considered misbehaving.
+pub async fn follow_key_handoffs(
+    wt_client: &Arc<Mutex<WTClient>>,
+    tower_id: TowerId,
+    proof: &MisbehaviorProof,
+) -> Option<TowerId> {
+    let recovered_id = proof.recovered_id;
+    let (net_addr, proxy) = {
+        let state = wt_client.lock().unwrap();
+        (
+            state.towers.get(&tower_id)?.net_addr.clone(),
+            state.proxy.clone(),
+        )
+    };
+
+    let handoffs = http::get_key_handoffs(&net_addr, &proxy)
+        .await
+        .map_err(|e| log::debug!("Cannot get key handoffs from {tower_id}. Error: {e:?}"))
+        .ok()?;
+
+    let chain = handoff::follow(tower_id, &handoffs);
+    let len = chain
+        .iter()
+        .position(|h| h.new_tower_id() == recovered_id)
+        .map_or(0, |i| i + 1);
+    let start_block = proof.appointment_receipt.start_block();
+    if len > 0 && !handoff::is_valid_signer(recovered_id, &chain[..len], start_block, recovered_id)
+    {
+        log::debug!("{recovered_id} was not a valid key of {tower_id} at height {start_block}");
+        return None;
+    }
+
+    match wt_client
+        .lock()
+        .unwrap()
+        .rotate_tower_key(tower_id, recovered_id, &handoffs)
+    {
+        Ok(()) => {
+            log::info!("{tower_id} handed its key over to {recovered_id}. Following it");
+            Some(recovered_id)
+        }
+        Err(e) => {
+            log::debug!("Cannot follow {tower_id} to {recovered_id}. Error: {e:?}");
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -316,6 +424,30 @@ mod tests {
         get_registration_receipt_from_previous,
     };
 
+    #[tokio::test]
+    async fn test_encrypt_client_keys() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let user_id = WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0)
+            .await
+            .user_id;
+
+        // Keys stored in plain text are encrypted once a passphrase is provided, keeping the same user id
+        let wt_client = WTClient::with_proxy(
+            tmp_path.path().to_path_buf(),
+            unbounded_channel().0,
+            None,
+            Some("passphrase"),
+            None,
+        )
+        .await;
+        assert_eq!(wt_client.user_id, user_id);
+        assert!(wt_client.dbm.is_client_key_encrypted());
+        assert_eq!(
+            wt_client.dbm.load_client_key(Some("passphrase")),
+            Ok(Some(wt_client.user_sk))
+        );
+    }
+
     #[tokio::test]
     async fn test_add_update_load_tower() {
         let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
@@ -779,6 +911,66 @@ mod tests {
         assert!(loaded_info.appointments.contains_key(&appointment.locator));
     }
 
+    #[tokio::test]
+    async fn test_rotate_tower_key() {
+        let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();
+        let mut wt_client =
+            WTClient::new(tmp_path.path().to_path_buf(), unbounded_channel().0).await;
+
+        let (tower_sk, tower_pk) = cryptography::get_random_keypair();
+        let tower_id = TowerId(tower_pk);
+        let (new_tower_sk, new_tower_pk) = cryptography::get_random_keypair();
+        let new_tower_id = TowerId(new_tower_pk);
+        let mut handoff = KeyHandoff::new(tower_id, new_tower_id, 100);
+        handoff.sign(&tower_sk);
+
+        // Unknown towers cannot be rotated
+        assert!(matches!(
+            wt_client.rotate_tower_key(tower_id, new_tower_id, &[handoff.clone()]),
+            Err(DBError::NotFound)
+        ));
+
+        let receipt = get_random_registration_receipt();
+        wt_client
+            .add_update_tower(tower_id, "talaia.watch", &receipt)
+            .unwrap();
+        let appointment = generate_random_appointment(None);
+        wt_client.add_pending_appointment(tower_id, &appointment);
+
+        // Handoffs not signed by the tower (or to a different id) are not followed
+        let mut forged = KeyHandoff::new(tower_id, new_tower_id, 100);
+        forged.sign(&new_tower_sk);
+        assert!(matches!(
+            wt_client.rotate_tower_key(tower_id, new_tower_id, &[forged]),
+            Err(DBError::NotFound)
+        ));
+        assert!(matches!(
+            wt_client.rotate_tower_key(tower_id, get_random_user_id(), &[handoff.clone()]),
+            Err(DBError::NotFound)
+        ));
+        assert!(wt_client.towers.contains_key(&tower_id));
+
+        // Properly signed ones are
+        wt_client
+            .rotate_tower_key(tower_id, new_tower_id, &[handoff.clone()])
+            .unwrap();
+        assert!(!wt_client.towers.contains_key(&tower_id));
+        assert!(wt_client.towers[&new_tower_id]
+            .pending_appointments
+            .contains(&appointment.locator));
+
+        let loaded_info = wt_client.load_tower_info(new_tower_id).unwrap();
+        assert_eq!(loaded_info.key_handoffs, vec![handoff]);
+        assert_eq!(loaded_info.pending_appointments, vec![appointment]);
+        assert_eq!(
+            wt_client
+                .get_registration_receipt(new_tower_id)
+                .unwrap()
+                .signature(),
+            receipt.signature()
+        );
+    }
+
     #[tokio::test]
     async fn test_remove_tower() {
         let tmp_path = TempDir::new(&format!("watchtower_{}", get_random_user_id())).unwrap();